@@ -45,6 +45,8 @@ where PROVIDER_NAME can be one of:
     - all
     - coverage
     - on-disk-kim
+    - minimal
+    - cross-provider
 "
 }
 
@@ -198,11 +200,22 @@ while [ "$#" -gt 0 ]; do
             else
                 FEATURES="--features=$1-provider,direct-authenticator"
                 TEST_FEATURES="--features=$1-provider"
+                # XChaCha20-Poly1305 is tested on top of the ChaCha20-Poly1305 of Mbed Crypto.
+                if [ "$1" = "mbed-crypto" ]; then
+                    FEATURES="$FEATURES,xchacha20-poly1305"
+                fi
             fi
         ;;
         coverage )
             PROVIDER_NAME=$1
         ;;
+        minimal )
+            PROVIDER_NAME=$1
+        ;;
+        cross-provider )
+            PROVIDER_NAME=$1
+            FEATURES="--features=mbed-crypto-provider,tpm-provider,pkcs11-provider,direct-authenticator"
+        ;;
         mismatcher )
             PROVIDER_NAME=$1
         ;;
@@ -369,13 +382,83 @@ if [ "$PROVIDER_NAME" = "cargo-check" ]; then
     RUST_BACKTRACE=1 cargo check --features="pkcs11-provider"
     RUST_BACKTRACE=1 cargo check --features="tpm-provider"
     RUST_BACKTRACE=1 cargo check --features="cryptoauthlib-provider"
+    RUST_BACKTRACE=1 cargo check --features="piv-provider"
+    RUST_BACKTRACE=1 cargo check --features="forwarding-provider"
+    RUST_BACKTRACE=1 cargo check --features="external-provider"
     RUST_BACKTRACE=1 cargo check --features="trusted-service-provider"
     RUST_BACKTRACE=1 cargo check --features="all-providers"
+    RUST_BACKTRACE=1 cargo check --features="test-provider"
+    RUST_BACKTRACE=1 cargo test --features="test-provider" test_provider
+    RUST_BACKTRACE=1 cargo check --features="fuzz"
+    RUST_BACKTRACE=1 cargo check --features="fault-injection"
+    RUST_BACKTRACE=1 cargo test --features="fault-injection" fault_injection
+    RUST_BACKTRACE=1 cargo check --features="key-import-formats"
+    RUST_BACKTRACE=1 cargo check --features="key-export-formats"
+    RUST_BACKTRACE=1 cargo check --features="cbor-bodies"
+    RUST_BACKTRACE=1 cargo check --features="wrapping-key-export"
+    RUST_BACKTRACE=1 cargo check --features="aes-key-wrap"
+    RUST_BACKTRACE=1 cargo check --features="aead-detached-tag"
+    RUST_BACKTRACE=1 cargo check --features="xchacha20-poly1305"
+    RUST_BACKTRACE=1 cargo check --features="jws-signing"
+    RUST_BACKTRACE=1 cargo check --features="cose-signing"
+    RUST_BACKTRACE=1 cargo check --features="tls13-signing"
+    RUST_BACKTRACE=1 cargo check --features="key-disabling"
+    RUST_BACKTRACE=1 cargo test --features="key-disabling" key_disabling
+    RUST_BACKTRACE=1 cargo check --features="kim-integrity"
+    RUST_BACKTRACE=1 cargo check --features="software-verifier"
+    RUST_BACKTRACE=1 cargo check --features="dbus-interface"
+    RUST_BACKTRACE=1 cargo test --features="dbus-interface" dbus
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,sqlite-kim"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,approvals"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,import-checks"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,signing-policies"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,key-import-formats"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,key-export-formats"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,journald"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="unix-peer-credentials-authenticator,compression"
 
     RUST_BACKTRACE=1 cargo check --features="direct-authenticator"
     RUST_BACKTRACE=1 cargo check --features="unix-peer-credentials-authenticator"
     RUST_BACKTRACE=1 cargo check --features="jwt-svid-authenticator"
+    RUST_BACKTRACE=1 cargo check --features="attestation-token-authenticator"
+    RUST_BACKTRACE=1 cargo test --features="attestation-token-authenticator" attestation_token
+    RUST_BACKTRACE=1 cargo check --features="external-authenticator"
     RUST_BACKTRACE=1 cargo check --features="all-authenticators"
+    RUST_BACKTRACE=1 cargo check --no-default-features --features="minimal"
+
+    exit 0
+fi
+
+if [ "$PROVIDER_NAME" = "minimal" ]; then
+    # Size regression test of the build for constrained gateways: its stripped binary must stay
+    # below MINIMAL_MAX_SIZE bytes, 2 MiB by default.
+    MINIMAL_MAX_SIZE=${MINIMAL_MAX_SIZE:-2097152}
+    RUST_BACKTRACE=1 cargo build --profile minimal --no-default-features --features=minimal
+    MINIMAL_SIZE=$(stat -c %s target/minimal/parsec)
+    echo "Size of the minimal build: $MINIMAL_SIZE bytes (limit: $MINIMAL_MAX_SIZE bytes)"
+    if [ "$MINIMAL_SIZE" -gt "$MINIMAL_MAX_SIZE" ]; then
+        echo "Error: the minimal build is larger than $MINIMAL_MAX_SIZE bytes"
+        exit 1
+    fi
+
+    # The minimal set must not grow dependencies back. Prost and num-bigint stay, used by
+    # parsec-interface.
+    for crate in tss-esapi cryptoki rust-cryptoauthlib spiffe ring serde_json libloading rusqlite libsystemd lz4_flex \
+        picky-asn1-der picky-asn1-x509 sha2; do
+        if cargo tree --no-default-features --features=minimal --edges normal --prefix none | grep -q "^$crate "; then
+            echo "Error: the minimal build depends on $crate"
+            exit 1
+        fi
+    done
+
+    exit 0
+fi
+
+if [ "$PROVIDER_NAME" = "cross-provider" ]; then
+    # The tests start swtpm, SoftHSM2 and the service built here on their own, then compare the
+    # behaviour of the providers.
+    RUST_BACKTRACE=1 cargo build $FEATURES
+    RUST_BACKTRACE=1 cargo test --features=cross-provider-tests --manifest-path ./e2e_tests/Cargo.toml cross_provider -- --nocapture
 
     exit 0
 fi
@@ -400,6 +483,9 @@ RUST_BACKTRACE=1 cargo test $FEATURES
 rm -rf mappings/
 rm -f *.psa_its
 
+echo "Check the configuration used for end-to-end tests"
+RUST_LOG=info RUST_BACKTRACE=1 cargo run --release $FEATURES -- --config $CONFIG_PATH --check-config
+
 echo "Start Parsec for end-to-end tests"
 RUST_LOG=info RUST_BACKTRACE=1 cargo run --release $FEATURES -- --config $CONFIG_PATH &
 # Sleep time needed to make sure Parsec is ready before launching the tests.
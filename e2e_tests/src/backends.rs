@@ -0,0 +1,185 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Software backends for the cross-provider tests
+//!
+//! Starts a software TPM (swtpm) and a SoftHSM2 token in a scratch directory, then a Parsec
+//! service offering the Mbed Crypto, TPM and PKCS 11 providers on top of them. The service has to
+//! be built beforehand with those providers and the Direct authenticator. Everything is stopped
+//! and removed when the `Backends` is dropped.
+//!
+//! The following environment variables change the defaults:
+//! * `PARSEC_SERVICE_BINARY`: the service to start, `../target/debug/parsec` from this crate
+//! * `SOFTHSM2_LIBRARY`: the PKCS 11 library of SoftHSM2
+//! * `SWTPM_PORT`: the TCP port of the software TPM, its control port being the next one
+use log::{error, info};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_SOFTHSM2_LIBRARY: &str = "/usr/local/lib/softhsm/libsofthsm2.so";
+const DEFAULT_SWTPM_PORT: u16 = 2321;
+const TOKEN_LABEL: &str = "parsec-cross-provider";
+const USER_PIN: &str = "123456";
+const SERVICE_START_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Software TPM, SoftHSM2 token and Parsec service running on top of them
+#[derive(Debug)]
+pub struct Backends {
+    directory: PathBuf,
+    swtpm: Child,
+    service: Child,
+}
+
+impl Backends {
+    /// Start the backends and the service, then point the clients created afterwards to it.
+    ///
+    /// Panics if one of them can not be started.
+    pub fn start() -> Backends {
+        let directory = env::temp_dir().join(format!("parsec-cross-provider-{}", process::id()));
+        let tpm_state = directory.join("tpm");
+        let tokens = directory.join("tokens");
+        fs::create_dir_all(&tpm_state).unwrap();
+        fs::create_dir_all(&tokens).unwrap();
+
+        let swtpm_port = env::var("SWTPM_PORT")
+            .map(|port| port.parse().expect("SWTPM_PORT is not a port number"))
+            .unwrap_or(DEFAULT_SWTPM_PORT);
+        let swtpm = Command::new("swtpm")
+            .args(["socket", "--tpm2"])
+            .arg("--server")
+            .arg(format!("type=tcp,port={}", swtpm_port))
+            .arg("--ctrl")
+            .arg(format!("type=tcp,port={}", swtpm_port + 1))
+            .arg("--tpmstate")
+            .arg(format!("dir={}", tpm_state.display()))
+            .args(["--flags", "not-need-init,startup-clear"])
+            .spawn()
+            .expect("Failed to start swtpm");
+
+        let softhsm2_conf = directory.join("softhsm2.conf");
+        fs::write(
+            &softhsm2_conf,
+            format!(
+                "directories.tokendir = {}\nobjectstore.backend = file\n",
+                tokens.display()
+            ),
+        )
+        .unwrap();
+        let status = Command::new("softhsm2-util")
+            .args(["--init-token", "--free", "--label", TOKEN_LABEL])
+            .args(["--pin", USER_PIN, "--so-pin", USER_PIN])
+            .env("SOFTHSM2_CONF", &softhsm2_conf)
+            .stdout(Stdio::null())
+            .status()
+            .expect("Failed to run softhsm2-util");
+        assert!(status.success(), "Failed to initialise the SoftHSM2 token");
+
+        let socket_path = directory.join("parsec.sock");
+        let config_path = directory.join("config.toml");
+        fs::write(&config_path, config(&directory, &socket_path, swtpm_port)).unwrap();
+        let binary = env::var("PARSEC_SERVICE_BINARY")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/debug/parsec")
+            });
+        let service = Command::new(&binary)
+            .arg("--config")
+            .arg(&config_path)
+            // The Mbed Crypto provider keeps its keys in the working directory.
+            .current_dir(&directory)
+            .env("SOFTHSM2_CONF", &softhsm2_conf)
+            .spawn()
+            .unwrap_or_else(|e| panic!("Failed to start {}: {}", binary.display(), e));
+
+        let mut backends = Backends {
+            directory,
+            swtpm,
+            service,
+        };
+        backends.wait_for_service(&socket_path);
+        env::set_var(
+            "PARSEC_SERVICE_ENDPOINT",
+            format!("unix:{}", socket_path.display()),
+        );
+        backends
+    }
+
+    fn wait_for_service(&mut self, socket_path: &Path) {
+        let start = Instant::now();
+        while !socket_path.exists() {
+            if let Some(status) = self.service.try_wait().unwrap() {
+                panic!("The Parsec service stopped on start: {}", status);
+            }
+            if start.elapsed() > SERVICE_START_TIMEOUT {
+                panic!("The Parsec service did not create its socket in time");
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        info!("Parsec service started on {}", socket_path.display());
+    }
+}
+
+fn config(directory: &Path, socket_path: &Path, swtpm_port: u16) -> String {
+    let softhsm2_library =
+        env::var("SOFTHSM2_LIBRARY").unwrap_or_else(|_| String::from(DEFAULT_SOFTHSM2_LIBRARY));
+    format!(
+        r#"
+[core_settings]
+log_error_details = true
+allow_root = true
+
+[listener]
+listener_type = "DomainSocket"
+timeout = 200
+socket_path = "{socket_path}"
+
+[authenticator]
+auth_type = "Direct"
+
+[[key_manager]]
+name = "on-disk-manager"
+manager_type = "OnDisk"
+store_path = "{mappings}"
+
+[[provider]]
+provider_type = "MbedCrypto"
+key_info_manager = "on-disk-manager"
+
+[[provider]]
+provider_type = "Tpm"
+key_info_manager = "on-disk-manager"
+tcti = "swtpm:host=localhost,port={swtpm_port}"
+owner_hierarchy_auth = ""
+
+[[provider]]
+provider_type = "Pkcs11"
+key_info_manager = "on-disk-manager"
+library_path = "{softhsm2_library}"
+token_label = "{token_label}"
+user_pin = "{user_pin}"
+"#,
+        socket_path = socket_path.display(),
+        mappings = directory.join("mappings").display(),
+        token_label = TOKEN_LABEL,
+        user_pin = USER_PIN,
+    )
+}
+
+impl Drop for Backends {
+    fn drop(&mut self) {
+        for (name, child) in [
+            ("Parsec service", &mut self.service),
+            ("swtpm", &mut self.swtpm),
+        ] {
+            if child.kill().and_then(|_| child.wait()).is_err() {
+                error!("Failed to stop the {}", name);
+            }
+        }
+        if fs::remove_dir_all(&self.directory).is_err() {
+            error!("Failed to remove {}", self.directory.display());
+        }
+    }
+}
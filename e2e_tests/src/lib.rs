@@ -27,6 +27,8 @@
 )]
 // This one is hard to avoid.
 #![allow(clippy::multiple_crate_versions)]
+#[cfg(feature = "cross-provider-tests")]
+pub mod backends;
 pub mod raw_request;
 pub mod stress;
 
@@ -400,6 +402,33 @@ impl TestClient {
         self.import_key(key_name, attributes, data)
     }
 
+    /// Import a ChaCha20 key for ChaCha20-Poly1305, that can be exported if asked.
+    pub fn import_chacha20_key(
+        &mut self,
+        key_name: String,
+        data: Vec<u8>,
+        exportable: bool,
+    ) -> Result<()> {
+        let mut usage_flags: UsageFlags = Default::default();
+        let _ = usage_flags.set_encrypt().set_decrypt();
+        if exportable {
+            let _ = usage_flags.set_export();
+        }
+        let attributes = Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::Chacha20,
+            bits: 0,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms: Aead::AeadWithDefaultLengthTag(
+                    AeadWithDefaultLengthTag::Chacha20Poly1305,
+                )
+                .into(),
+            },
+        };
+        self.import_key(key_name, attributes, data)
+    }
+
     /// Import an AES key.
     pub fn import_aes_key_cipher(
         &mut self,
@@ -0,0 +1,338 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+use e2e_tests::TestClient;
+use parsec_client::core::interface::operations::psa_algorithm::{
+    Aead, AeadWithDefaultLengthTag, Hash, RawKeyAgreement,
+};
+use parsec_client::core::interface::requests::{Opcode, Result};
+
+pub const HASH: [u8; 32] = [
+    0x69, 0x3E, 0xDB, 0x1B, 0x22, 0x79, 0x03, 0xF4, 0xC0, 0xBF, 0xD6, 0x91, 0x76, 0x37, 0x84, 0xA2,
+    0x94, 0x8E, 0x92, 0x50, 0x35, 0xC2, 0x8C, 0x5C, 0x3C, 0xCA, 0xFE, 0x18, 0xE8, 0x81, 0x37, 0x78,
+];
+
+const PLAINTEXT: [u8; 24] = [
+    0x45, 0x35, 0xd1, 0x2b, 0x43, 0x77, 0x92, 0x8a, 0x7c, 0x0a, 0x61, 0xc9, 0xf8, 0x25, 0xa4, 0x86,
+    0x71, 0xea, 0x05, 0x91, 0x07, 0x48, 0xc8, 0xef,
+];
+
+const AES_KEY: [u8; 16] = [
+    0x41, 0x89, 0x35, 0x1B, 0x5C, 0xAE, 0xA3, 0x75, 0xA0, 0x29, 0x9E, 0x81, 0xC6, 0x21, 0xBF, 0x43,
+];
+
+const NONCE: [u8; 13] = [
+    0x48, 0xc0, 0x90, 0x69, 0x30, 0x56, 0x1e, 0x0a, 0xb0, 0xef, 0x4c, 0xd9, 0x72,
+];
+
+const ADDITIONAL_DATA: [u8; 32] = [
+    0x40, 0xa2, 0x7c, 0x1d, 0x1e, 0x23, 0xea, 0x3d, 0xbe, 0x80, 0x56, 0xb2, 0x77, 0x48, 0x61, 0xa4,
+    0xa2, 0x01, 0xcc, 0xe4, 0x9f, 0x19, 0x99, 0x7d, 0x19, 0x20, 0x6d, 0x8c, 0x8a, 0x34, 0x39, 0x51,
+];
+
+const ECC_PRIVATE_KEY: [u8; 32] = [
+    0x26, 0xc8, 0x82, 0x9e, 0x22, 0xe3, 0x0c, 0xa6, 0x3d, 0x29, 0xf5, 0xf7, 0x27, 0x39, 0x58, 0x47,
+    0x41, 0x81, 0xf6, 0x57, 0x4f, 0xdb, 0xcb, 0x4d, 0xbb, 0xdd, 0x52, 0xff, 0x3a, 0xc0, 0xf6, 0x0d,
+];
+
+const PEER_PUBLIC_KEY: [u8; 65] = [
+    0x04, 0xd1, 0x2d, 0xfb, 0x52, 0x89, 0xc8, 0xd4, 0xf8, 0x12, 0x08, 0xb7, 0x02, 0x70, 0x39, 0x8c,
+    0x34, 0x22, 0x96, 0x97, 0x0a, 0x0b, 0xcc, 0xb7, 0x4c, 0x73, 0x6f, 0xc7, 0x55, 0x44, 0x94, 0xbf,
+    0x63, 0x56, 0xfb, 0xf3, 0xca, 0x36, 0x6c, 0xc2, 0x3e, 0x81, 0x57, 0x85, 0x4c, 0x13, 0xc5, 0x8d,
+    0x6a, 0xac, 0x23, 0xf0, 0x46, 0xad, 0xa3, 0x0f, 0x83, 0x53, 0xe7, 0x4f, 0x33, 0x03, 0x98, 0x72,
+    0xab,
+];
+
+const ECDH_PRIVATE_KEY: [u8; 32] = [
+    0xc8, 0x8f, 0x01, 0xf5, 0x10, 0xd9, 0xac, 0x3f, 0x70, 0xa2, 0x92, 0xda, 0xa2, 0x31, 0x6d, 0xe5,
+    0x44, 0xe9, 0xaa, 0xb8, 0xaf, 0xe8, 0x40, 0x49, 0xc6, 0x2a, 0x9c, 0x57, 0x86, 0x2d, 0x14, 0x33,
+];
+
+/// What is compared between the providers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Observation {
+    /// Output of a deterministic operation, the same for all providers
+    Bytes(Vec<u8>),
+    /// Description of an output that varies between runs, like a signature with random nonces
+    Shape(String),
+    /// Success of an operation without output
+    Done,
+}
+
+pub type Outcome = Result<Observation>;
+
+/// Operation run on each provider offering all its opcodes, with the name of the keys to use
+pub struct Case {
+    pub name: &'static str,
+    pub opcodes: &'static [Opcode],
+    pub run: fn(&mut TestClient, String) -> Outcome,
+}
+
+fn shape(output: &[u8]) -> Observation {
+    Observation::Shape(format!(
+        "{} bytes starting with {:#04x}",
+        output.len(),
+        output.first().copied().unwrap_or_default()
+    ))
+}
+
+fn generate_random(client: &mut TestClient, _: String) -> Outcome {
+    Ok(shape(&client.generate_bytes(32)?))
+}
+
+fn hash_compute(client: &mut TestClient, _: String) -> Outcome {
+    Ok(Observation::Bytes(
+        client.hash_compute(Hash::Sha256, &PLAINTEXT)?,
+    ))
+}
+
+fn hash_compare_mismatch(client: &mut TestClient, _: String) -> Outcome {
+    client.hash_compare(Hash::Sha256, &PLAINTEXT, &HASH)?;
+    Ok(Observation::Done)
+}
+
+fn rsa_sign_verify(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    let signature = client.sign_with_rsa_sha256(key_name.clone(), HASH.to_vec())?;
+    client.verify_with_rsa_sha256(key_name, HASH.to_vec(), signature.clone())?;
+    Ok(shape(&signature))
+}
+
+fn ecdsa_sign_verify(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_ecc_key_pair_secpr1_ecdsa_sha256(key_name.clone())?;
+    let signature = client.sign_with_ecdsa_sha256(key_name.clone(), HASH.to_vec())?;
+    client.verify_with_ecdsa_sha256(key_name, HASH.to_vec(), signature.clone())?;
+    Ok(shape(&signature))
+}
+
+fn ecdsa_sign_verify_message(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_ecc_key_pair_secpr1_ecdsa_sha256(key_name.clone())?;
+    let signature = client.sign_msg_with_ecdsa_sha256(key_name.clone(), PLAINTEXT.to_vec())?;
+    client.verify_msg_with_ecdsa_sha256(key_name, PLAINTEXT.to_vec(), signature.clone())?;
+    Ok(shape(&signature))
+}
+
+fn rsa_export_public_key(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    Ok(shape(&client.export_public_key(key_name)?))
+}
+
+fn ecc_export_public_key(client: &mut TestClient, key_name: String) -> Outcome {
+    client.import_ecc_key_pair_secpr1_ecdsa_sha256(key_name.clone(), ECC_PRIVATE_KEY.to_vec())?;
+    Ok(Observation::Bytes(client.export_public_key(key_name)?))
+}
+
+fn rsa_encrypt_decrypt(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_encryption_keys_rsapkcs1v15crypt(key_name.clone())?;
+    let ciphertext =
+        client.asymmetric_encrypt_message_with_rsapkcs1v15(key_name.clone(), PLAINTEXT.to_vec())?;
+    Ok(Observation::Bytes(
+        client.asymmetric_decrypt_message_with_rsapkcs1v15(key_name, ciphertext)?,
+    ))
+}
+
+fn aead_ccm_encrypt(client: &mut TestClient, key_name: String) -> Outcome {
+    let alg = Aead::AeadWithDefaultLengthTag(AeadWithDefaultLengthTag::Ccm);
+    client.import_aes_key(key_name.clone(), AES_KEY.to_vec(), alg)?;
+    Ok(Observation::Bytes(client.aead_encrypt_message(
+        key_name,
+        alg,
+        &NONCE,
+        &ADDITIONAL_DATA,
+        &PLAINTEXT,
+    )?))
+}
+
+fn raw_ecdh(client: &mut TestClient, key_name: String) -> Outcome {
+    client.import_ecc_pair_secp_r1_key(key_name.clone(), ECDH_PRIVATE_KEY.to_vec())?;
+    Ok(Observation::Bytes(client.raw_key_agreement(
+        RawKeyAgreement::Ecdh,
+        key_name,
+        &PEER_PUBLIC_KEY,
+    )?))
+}
+
+fn generate_existing_key(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    client.generate_rsa_sign_key(key_name)?;
+    Ok(Observation::Done)
+}
+
+fn sign_with_destroyed_key(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    client.destroy_key(key_name.clone())?;
+    Ok(shape(
+        &client.sign_with_rsa_sha256(key_name, HASH.to_vec())?,
+    ))
+}
+
+fn sign_with_other_algorithm(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    Ok(shape(
+        &client.sign_with_ecdsa_sha256(key_name, HASH.to_vec())?,
+    ))
+}
+
+fn verify_tampered_signature(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    let mut signature = client.sign_with_rsa_sha256(key_name.clone(), HASH.to_vec())?;
+    signature[0] ^= 0x01;
+    client.verify_with_rsa_sha256(key_name, HASH.to_vec(), signature)?;
+    Ok(Observation::Done)
+}
+
+fn export_non_exportable_key(client: &mut TestClient, key_name: String) -> Outcome {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    Ok(shape(&client.export_key(key_name)?))
+}
+
+pub const CASES: &[Case] = &[
+    Case {
+        name: "generate_random",
+        opcodes: &[Opcode::PsaGenerateRandom],
+        run: generate_random,
+    },
+    Case {
+        name: "hash_compute",
+        opcodes: &[Opcode::PsaHashCompute],
+        run: hash_compute,
+    },
+    Case {
+        name: "hash_compare_mismatch",
+        opcodes: &[Opcode::PsaHashCompare],
+        run: hash_compare_mismatch,
+    },
+    Case {
+        name: "rsa_sign_verify",
+        opcodes: &[
+            Opcode::PsaGenerateKey,
+            Opcode::PsaSignHash,
+            Opcode::PsaVerifyHash,
+        ],
+        run: rsa_sign_verify,
+    },
+    Case {
+        name: "ecdsa_sign_verify",
+        opcodes: &[
+            Opcode::PsaGenerateKey,
+            Opcode::PsaSignHash,
+            Opcode::PsaVerifyHash,
+        ],
+        run: ecdsa_sign_verify,
+    },
+    Case {
+        name: "ecdsa_sign_verify_message",
+        opcodes: &[
+            Opcode::PsaGenerateKey,
+            Opcode::PsaSignMessage,
+            Opcode::PsaVerifyMessage,
+        ],
+        run: ecdsa_sign_verify_message,
+    },
+    Case {
+        name: "rsa_export_public_key",
+        opcodes: &[Opcode::PsaGenerateKey, Opcode::PsaExportPublicKey],
+        run: rsa_export_public_key,
+    },
+    Case {
+        name: "ecc_export_public_key",
+        opcodes: &[Opcode::PsaImportKey, Opcode::PsaExportPublicKey],
+        run: ecc_export_public_key,
+    },
+    Case {
+        name: "rsa_encrypt_decrypt",
+        opcodes: &[
+            Opcode::PsaGenerateKey,
+            Opcode::PsaAsymmetricEncrypt,
+            Opcode::PsaAsymmetricDecrypt,
+        ],
+        run: rsa_encrypt_decrypt,
+    },
+    Case {
+        name: "aead_ccm_encrypt",
+        opcodes: &[Opcode::PsaImportKey, Opcode::PsaAeadEncrypt],
+        run: aead_ccm_encrypt,
+    },
+    Case {
+        name: "raw_ecdh",
+        opcodes: &[Opcode::PsaImportKey, Opcode::PsaRawKeyAgreement],
+        run: raw_ecdh,
+    },
+    Case {
+        name: "generate_existing_key",
+        opcodes: &[Opcode::PsaGenerateKey],
+        run: generate_existing_key,
+    },
+    Case {
+        name: "sign_with_destroyed_key",
+        opcodes: &[
+            Opcode::PsaGenerateKey,
+            Opcode::PsaDestroyKey,
+            Opcode::PsaSignHash,
+        ],
+        run: sign_with_destroyed_key,
+    },
+    Case {
+        name: "sign_with_other_algorithm",
+        opcodes: &[Opcode::PsaGenerateKey, Opcode::PsaSignHash],
+        run: sign_with_other_algorithm,
+    },
+    Case {
+        name: "verify_tampered_signature",
+        opcodes: &[
+            Opcode::PsaGenerateKey,
+            Opcode::PsaSignHash,
+            Opcode::PsaVerifyHash,
+        ],
+        run: verify_tampered_signature,
+    },
+    Case {
+        name: "export_non_exportable_key",
+        opcodes: &[Opcode::PsaGenerateKey, Opcode::PsaExportKey],
+        run: export_non_exportable_key,
+    },
+];
+
+/// Creates a key, then signs `HASH` with it, returning its public key and the signature
+pub type Sign = fn(&mut TestClient, String) -> Result<(Vec<u8>, Vec<u8>)>;
+
+/// Imports a public key, then verifies a signature of `HASH` with it
+pub type Verify = fn(&mut TestClient, String, Vec<u8>, Vec<u8>) -> Outcome;
+
+fn rsa_sign(client: &mut TestClient, key_name: String) -> Result<(Vec<u8>, Vec<u8>)> {
+    client.generate_rsa_sign_key(key_name.clone())?;
+    let signature = client.sign_with_rsa_sha256(key_name.clone(), HASH.to_vec())?;
+    Ok((client.export_public_key(key_name)?, signature))
+}
+
+fn rsa_verify(
+    client: &mut TestClient,
+    key_name: String,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+) -> Outcome {
+    client.import_rsa_public_key(key_name.clone(), public_key)?;
+    client.verify_with_rsa_sha256(key_name, HASH.to_vec(), signature)?;
+    Ok(Observation::Done)
+}
+
+fn ecdsa_sign(client: &mut TestClient, key_name: String) -> Result<(Vec<u8>, Vec<u8>)> {
+    client.generate_ecc_key_pair_secpr1_ecdsa_sha256(key_name.clone())?;
+    let signature = client.sign_with_ecdsa_sha256(key_name.clone(), HASH.to_vec())?;
+    Ok((client.export_public_key(key_name)?, signature))
+}
+
+fn ecdsa_verify(
+    client: &mut TestClient,
+    key_name: String,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+) -> Outcome {
+    client.import_ecc_public_secp_r1_ecdsa_sha256_key(key_name.clone(), public_key)?;
+    client.verify_with_ecdsa_sha256(key_name, HASH.to_vec(), signature)?;
+    Ok(Observation::Done)
+}
+
+pub const SIGNATURES: &[(&str, Sign, Verify)] = &[
+    ("RSA PKCS 1v15", rsa_sign, rsa_verify),
+    ("ECDSA", ecdsa_sign, ecdsa_verify),
+];
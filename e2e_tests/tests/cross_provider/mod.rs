@@ -0,0 +1,110 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Runs the same operations on each provider of a service started on software backends, and
+//! reports the ones where the providers behave differently: different outputs for deterministic
+//! operations, differently shaped outputs (like DER instead of raw ECDSA signatures), different
+//! errors for the same misuse or signatures that the other providers do not verify.
+mod matrix;
+
+use e2e_tests::backends::Backends;
+use e2e_tests::TestClient;
+use matrix::{Case, Outcome, CASES};
+use parsec_client::core::interface::requests::{Opcode, ProviderId};
+use std::collections::HashSet;
+
+const PROVIDERS: [ProviderId; 3] = [ProviderId::MbedCrypto, ProviderId::Tpm, ProviderId::Pkcs11];
+
+fn supported(opcodes: &HashSet<Opcode>, case: &Case) -> bool {
+    case.opcodes.iter().all(|opcode| opcodes.contains(opcode))
+}
+
+// The outcomes of one check, described if they are not all the same
+fn divergence(check: &str, outcomes: &[(ProviderId, Outcome)]) -> Option<String> {
+    let first = &outcomes.first()?.1;
+    if outcomes.iter().all(|(_, outcome)| outcome == first) {
+        return None;
+    }
+    let outcomes: Vec<String> = outcomes
+        .iter()
+        .map(|(provider, outcome)| format!("  {}: {:?}", provider, outcome))
+        .collect();
+    Some(format!("{}\n{}", check, outcomes.join("\n")))
+}
+
+#[test]
+fn providers_behave_the_same() {
+    let _backends = Backends::start();
+    let mut client = TestClient::new();
+
+    let mut opcodes = Vec::new();
+    for provider in PROVIDERS {
+        opcodes.push((provider, client.list_opcodes(provider).unwrap()));
+    }
+
+    let mut divergences = Vec::new();
+    for case in CASES {
+        let mut outcomes = Vec::new();
+        for (provider, opcodes) in &opcodes {
+            if !supported(opcodes, case) {
+                println!("{}: skipped on the {}", case.name, provider);
+                continue;
+            }
+            client.set_provider(*provider);
+            outcomes.push((*provider, (case.run)(&mut client, case.name.to_string())));
+        }
+        divergences.extend(divergence(case.name, &outcomes));
+    }
+
+    // The signatures of each provider, verified by all of them
+    let signing = [
+        Opcode::PsaGenerateKey,
+        Opcode::PsaSignHash,
+        Opcode::PsaExportPublicKey,
+    ];
+    let verifying = [Opcode::PsaImportKey, Opcode::PsaVerifyHash];
+    for (name, sign, verify) in matrix::SIGNATURES {
+        for (signer, signer_opcodes) in &opcodes {
+            if !signing.iter().all(|opcode| signer_opcodes.contains(opcode)) {
+                continue;
+            }
+            client.set_provider(*signer);
+            let key_name = format!("{}-{}", name, signer);
+            let (public_key, signature) = match sign(&mut client, key_name.clone()) {
+                Ok(signed) => signed,
+                // Already reported by the cases above
+                Err(status) => {
+                    println!("{} signature: failed on the {}: {}", name, signer, status);
+                    continue;
+                }
+            };
+            let mut outcomes = Vec::new();
+            for (verifier, verifier_opcodes) in &opcodes {
+                if !verifying
+                    .iter()
+                    .all(|opcode| verifier_opcodes.contains(opcode))
+                {
+                    continue;
+                }
+                client.set_provider(*verifier);
+                let outcome = verify(
+                    &mut client,
+                    format!("{}-verifier", key_name),
+                    public_key.clone(),
+                    signature.clone(),
+                );
+                outcomes.push((*verifier, outcome));
+            }
+            divergences.extend(divergence(
+                &format!("{} signature of the {}", name, signer),
+                &outcomes,
+            ));
+        }
+    }
+
+    assert!(
+        divergences.is_empty(),
+        "The providers diverge on {} checks:\n{}",
+        divergences.len(),
+        divergences.join("\n")
+    );
+}
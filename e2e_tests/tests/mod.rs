@@ -31,5 +31,7 @@
 
 #[cfg(feature = "all-providers")]
 mod all_providers;
+#[cfg(feature = "cross-provider-tests")]
+mod cross_provider;
 #[cfg(not(feature = "all-providers"))]
 mod per_provider;
@@ -754,3 +754,121 @@ fn aead_encrypt_gcm_encrypt_tag_length_too_long() {
         ResponseStatus::PsaErrorInvalidArgument
     );
 }
+
+// Section A.3.1 of draft-irtf-cfrg-xchacha-03
+#[cfg(feature = "mbed-crypto-provider")]
+const XCHACHA20_POLY1305_PLAINTEXT: &[u8] =
+    b"Ladies and Gentlemen of the class of '99: If I could \
+offer you only one tip for the future, sunscreen would be it.";
+
+#[cfg(feature = "mbed-crypto-provider")]
+const XCHACHA20_POLY1305_ADDITIONAL_DATA: [u8; 12] = [
+    0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+];
+
+#[cfg(feature = "mbed-crypto-provider")]
+const XCHACHA20_POLY1305_CIPHERTEXT: [u8; 130] = [
+    0xbd, 0x6d, 0x17, 0x9d, 0x3e, 0x83, 0xd4, 0x3b, 0x95, 0x76, 0x57, 0x94, 0x93, 0xc0, 0xe9, 0x39,
+    0x57, 0x2a, 0x17, 0x00, 0x25, 0x2b, 0xfa, 0xcc, 0xbe, 0xd2, 0x90, 0x2c, 0x21, 0x39, 0x6c, 0xbb,
+    0x73, 0x1c, 0x7f, 0x1b, 0x0b, 0x4a, 0xa6, 0x44, 0x0b, 0xf3, 0xa8, 0x2f, 0x4e, 0xda, 0x7e, 0x39,
+    0xae, 0x64, 0xc6, 0x70, 0x8c, 0x54, 0xc2, 0x16, 0xcb, 0x96, 0xb7, 0x2e, 0x12, 0x13, 0xb4, 0x52,
+    0x2f, 0x8c, 0x9b, 0xa4, 0x0d, 0xb5, 0xd9, 0x45, 0xb1, 0x1b, 0x69, 0xb9, 0x82, 0xc1, 0xbb, 0x9e,
+    0x3f, 0x3f, 0xac, 0x2b, 0xc3, 0x69, 0x48, 0x8f, 0x76, 0xb2, 0x38, 0x35, 0x65, 0xd3, 0xff, 0xf9,
+    0x21, 0xf9, 0x66, 0x4c, 0x97, 0x63, 0x7d, 0xa9, 0x76, 0x88, 0x12, 0xf6, 0x15, 0xc6, 0x8b, 0x13,
+    0xb5, 0x2e, 0xc0, 0x87, 0x59, 0x24, 0xc1, 0xc7, 0x98, 0x79, 0x47, 0xde, 0xaf, 0xd8, 0x78, 0x0a,
+    0xcf, 0x49,
+];
+
+#[cfg(feature = "mbed-crypto-provider")]
+fn xchacha20_poly1305_key_and_nonce() -> (Vec<u8>, Vec<u8>) {
+    ((0x80..0xa0).collect(), (0x40..0x58).collect())
+}
+
+#[cfg(feature = "mbed-crypto-provider")]
+#[test]
+fn aead_xchacha20_poly1305_encrypt_decrypt() {
+    let key_name = auto_test_keyname!();
+    let mut client = TestClient::new();
+    if !client.is_operation_supported(Opcode::PsaAeadEncrypt) {
+        return;
+    }
+    let (key, nonce) = xchacha20_poly1305_key_and_nonce();
+    client
+        .import_chacha20_key(key_name.clone(), key, true)
+        .unwrap();
+    let alg = Aead::AeadWithDefaultLengthTag(AeadWithDefaultLengthTag::Chacha20Poly1305);
+
+    let ciphertext = client
+        .aead_encrypt_message(
+            format!("{}#xchacha20", key_name),
+            alg,
+            &nonce,
+            &XCHACHA20_POLY1305_ADDITIONAL_DATA,
+            XCHACHA20_POLY1305_PLAINTEXT,
+        )
+        .unwrap();
+    assert_eq!(ciphertext, XCHACHA20_POLY1305_CIPHERTEXT.to_vec());
+
+    let plaintext = client
+        .aead_decrypt_message(
+            format!("{}#xchacha20", key_name),
+            alg,
+            &nonce,
+            &XCHACHA20_POLY1305_ADDITIONAL_DATA,
+            &XCHACHA20_POLY1305_CIPHERTEXT,
+        )
+        .unwrap();
+    assert_eq!(plaintext, XCHACHA20_POLY1305_PLAINTEXT.to_vec());
+}
+
+#[cfg(feature = "mbed-crypto-provider")]
+#[test]
+fn aead_xchacha20_poly1305_needs_the_suffix() {
+    let key_name = auto_test_keyname!();
+    let mut client = TestClient::new();
+    if !client.is_operation_supported(Opcode::PsaAeadEncrypt) {
+        return;
+    }
+    let (key, nonce) = xchacha20_poly1305_key_and_nonce();
+    client
+        .import_chacha20_key(key_name.clone(), key, true)
+        .unwrap();
+    assert_eq!(
+        client
+            .aead_encrypt_message(
+                key_name,
+                Aead::AeadWithDefaultLengthTag(AeadWithDefaultLengthTag::Chacha20Poly1305),
+                &nonce,
+                &XCHACHA20_POLY1305_ADDITIONAL_DATA,
+                XCHACHA20_POLY1305_PLAINTEXT,
+            )
+            .unwrap_err(),
+        ResponseStatus::PsaErrorNotSupported
+    );
+}
+
+#[cfg(feature = "mbed-crypto-provider")]
+#[test]
+fn aead_xchacha20_poly1305_needs_exportable_keys() {
+    let key_name = auto_test_keyname!();
+    let mut client = TestClient::new();
+    if !client.is_operation_supported(Opcode::PsaAeadEncrypt) {
+        return;
+    }
+    let (key, nonce) = xchacha20_poly1305_key_and_nonce();
+    client
+        .import_chacha20_key(key_name.clone(), key, false)
+        .unwrap();
+    assert_eq!(
+        client
+            .aead_encrypt_message(
+                format!("{}#xchacha20", key_name),
+                Aead::AeadWithDefaultLengthTag(AeadWithDefaultLengthTag::Chacha20Poly1305),
+                &nonce,
+                &XCHACHA20_POLY1305_ADDITIONAL_DATA,
+                XCHACHA20_POLY1305_PLAINTEXT,
+            )
+            .unwrap_err(),
+        ResponseStatus::PsaErrorNotPermitted
+    );
+}
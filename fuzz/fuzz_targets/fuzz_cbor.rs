@@ -0,0 +1,15 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use parsec_service::utils::cbor;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything decoded is in the preferred serialization once encoded again, which decodes to the
+    // same value.
+    if let Ok(value) = cbor::decode(data) {
+        let encoded = cbor::encode(&value);
+        assert_eq!(cbor::decode(&encoded), Ok(value));
+    }
+});
@@ -0,0 +1,33 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use parsec_interface::operations::psa_key_attributes::EccFamily;
+use parsec_service::providers::utils::key_validation;
+use picky_asn1_x509::RsaPublicKey;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything accepted by the strict parser must be understood in the same way by the DER
+    // library used by the providers.
+    if let Ok(key) = key_validation::parse_rsa_public_key(data) {
+        let picky_key: RsaPublicKey =
+            picky_asn1_der::from_bytes(data).expect("Key rejected by picky-asn1-der");
+        assert_eq!(picky_key.modulus.as_unsigned_bytes_be(), key.modulus);
+        assert_eq!(
+            picky_key.public_exponent.as_unsigned_bytes_be(),
+            key.public_exponent
+        );
+    }
+
+    let _ = key_validation::parse_rsa_private_key(data);
+
+    for curve_family in &[EccFamily::SecpR1, EccFamily::Montgomery] {
+        if let Ok(bits) = key_validation::validate_ecc_public_key(data, *curve_family, 0) {
+            assert_eq!(
+                key_validation::validate_ecc_public_key(data, *curve_family, bits),
+                Ok(bits)
+            );
+        }
+    }
+});
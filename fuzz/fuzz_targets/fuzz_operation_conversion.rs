@@ -0,0 +1,25 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+#![no_main]
+
+use lazy_static::lazy_static;
+use libfuzzer_sys::fuzz_target;
+use parsec_interface::operations::Convert;
+use parsec_interface::operations_protobuf::ProtobufConverter;
+use parsec_interface::requests::request::RequestBody;
+use parsec_interface::requests::Opcode;
+
+lazy_static! {
+    static ref CONVERTER: ProtobufConverter = ProtobufConverter {};
+}
+
+// Deserializes arbitrary bodies for all opcodes. Operations that are successfully deserialized
+// must be serializable again.
+fuzz_target!(|input: (Opcode, RequestBody)| {
+    let (opcode, body) = input;
+    if let Ok(operation) = CONVERTER.body_to_operation(body, opcode) {
+        let _ = CONVERTER
+            .operation_to_body(operation)
+            .expect("Failed to serialize a deserialized operation");
+    }
+});
@@ -0,0 +1,25 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+#![no_main]
+
+use lazy_static::lazy_static;
+use libfuzzer_sys::fuzz_target;
+use parsec_interface::operations::Convert;
+use parsec_interface::operations_protobuf::ProtobufConverter;
+use parsec_interface::requests::Request;
+
+// Same as the default body length limit of the service.
+const BODY_LEN_LIMIT: usize = 1 << 20;
+
+lazy_static! {
+    static ref CONVERTER: ProtobufConverter = ProtobufConverter {};
+}
+
+// Parses the input as a full request, the way the front end does it, and converts the body of
+// the requests that made it through to operations.
+fuzz_target!(|data: &[u8]| {
+    let mut stream = data;
+    if let Ok(request) = Request::read_from_stream(&mut stream, BODY_LEN_LIMIT) {
+        let _ = CONVERTER.body_to_operation(request.body, request.header.opcode);
+    }
+});
@@ -53,6 +53,7 @@ fuzz_target!(|stream: MockStream| {
     FRONT_END_HANDLER.handle_request(Connection {
         stream: Box::from(stream),
         metadata: None,
+        auth_type: None,
     });
 });
 
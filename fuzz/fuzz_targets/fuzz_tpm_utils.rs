@@ -0,0 +1,83 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use parsec_interface::operations::psa_algorithm::{
+    Algorithm, AsymmetricSignature, Hash, SignHash,
+};
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, EccFamily, Lifetime, Policy, Type, UsageFlags,
+};
+use parsec_service::providers::tpm::fuzz::*;
+use zeroize::Zeroizing;
+
+fn attributes(key_type: Type, bits: usize, alg: AsymmetricSignature) -> Attributes {
+    Attributes {
+        lifetime: Lifetime::Persistent,
+        key_type,
+        bits,
+        policy: Policy {
+            usage_flags: UsageFlags::default(),
+            permitted_algorithms: Algorithm::AsymmetricSignature(alg),
+        },
+    }
+}
+
+// Goes through the same conversions as a key import and a signature verification, with both RSA
+// and ECC attributes. Data accepted on the way in must come out unchanged.
+fuzz_target!(|data: &[u8]| {
+    let rsa_alg = AsymmetricSignature::RsaPkcs1v15Sign {
+        hash_alg: SignHash::Specific(Hash::Sha256),
+    };
+    let ecc_alg = AsymmetricSignature::Ecdsa {
+        hash_alg: SignHash::Specific(Hash::Sha256),
+    };
+    let import_attributes = [
+        attributes(Type::RsaPublicKey, 0, rsa_alg),
+        attributes(
+            Type::EccPublicKey {
+                curve_family: EccFamily::SecpR1,
+            },
+            0,
+            ecc_alg,
+        ),
+    ];
+
+    for key_attributes in import_attributes.iter() {
+        let key_attributes = match adjust_attributes_key_bits(*key_attributes, data) {
+            Ok(key_attributes) => key_attributes,
+            Err(_) => continue,
+        };
+        if parsec_to_tpm_params(key_attributes).is_err() {
+            continue;
+        }
+        if let Ok(pub_key) = bytes_to_pub_key(data.to_vec(), &key_attributes) {
+            let exported = pub_key_to_bytes(pub_key, key_attributes)
+                .expect("Failed to convert an imported key back");
+            assert_eq!(exported, data);
+        }
+    }
+
+    for (key_attributes, alg) in &[
+        (attributes(Type::RsaKeyPair, 2048, rsa_alg), rsa_alg),
+        (
+            attributes(
+                Type::EccKeyPair {
+                    curve_family: EccFamily::SecpR1,
+                },
+                256,
+                ecc_alg,
+            ),
+            ecc_alg,
+        ),
+    ] {
+        if let Ok(signature) =
+            parsec_to_tpm_signature(Zeroizing::new(data.to_vec()), *key_attributes, *alg)
+        {
+            let converted = signature_data_to_bytes(signature, *key_attributes)
+                .expect("Failed to convert a signature back");
+            assert_eq!(converted, data);
+        }
+    }
+});
@@ -43,10 +43,40 @@ SLOT_NUMBER=`softhsm2-util --show-slots | head -n2 | tail -n1 | cut -d " " -f 2`
 # Find all TOML files in the directory (except Cargo.toml) and replace the commented slot number with the valid one
 sed -i "s/^# slot_number.*$/slot_number = $SLOT_NUMBER/" $CONFIG_PATH
 
+# Target to fuzz, one of the binaries defined in Cargo.toml
+FUZZ_TARGET=${FUZZ_TARGET:-fuzz_service}
+
 # Create corpus if it doesn't exist
 cargo build --features="mbed-crypto-provider,tpm-provider,pkcs11-provider"
-mkdir -p corpus/fuzz_service
+mkdir -p corpus/fuzz_service corpus/fuzz_request_header
 cp init_corpus/* corpus/fuzz_service
+cp init_corpus/* corpus/fuzz_request_header
+
+# The key material targets are seeded with valid keys, in the formats expected by PsaImportKey
+mkdir -p corpus/fuzz_key_validation corpus/fuzz_tpm_utils
+for bits in 1024 2048; do
+    openssl genrsa -out rsa-$bits.pem $bits
+    openssl rsa -in rsa-$bits.pem -RSAPublicKey_out -outform DER -out corpus/fuzz_key_validation/rsa-public-$bits
+    openssl rsa -in rsa-$bits.pem -traditional -outform DER -out corpus/fuzz_key_validation/rsa-private-$bits
+    rm rsa-$bits.pem
+done
+for curve in prime256v1 secp384r1 secp521r1; do
+    # The last bytes of the SubjectPublicKeyInfo structure hold the uncompressed point
+    openssl ecparam -name $curve -genkey -noout | openssl ec -pubout -outform DER -out ecc-$curve.der
+    case $curve in
+        prime256v1) POINT_LEN=65;;
+        secp384r1) POINT_LEN=97;;
+        secp521r1) POINT_LEN=133;;
+    esac
+    tail -c $POINT_LEN ecc-$curve.der > corpus/fuzz_key_validation/ecc-public-$curve
+    rm ecc-$curve.der
+done
+cp corpus/fuzz_key_validation/* corpus/fuzz_tpm_utils
+
+# The CBOR target is seeded with a COSE_Key and an untagged COSE_Sign1 structure
+mkdir -p corpus/fuzz_cbor
+printf '\xa4\x01\x02\x20\x01\x21\x42\x01\x02\x22\x42\x03\x04' > corpus/fuzz_cbor/cose-key
+printf '\x84\x43\xa1\x01\x26\xa0\x44test\x40' > corpus/fuzz_cbor/cose-sign1
 
 
 if [[ "$1" == "test" ]]
@@ -64,7 +94,7 @@ set +e
 while [ true ]
 do
     # Run fuzzer
-    cargo +nightly fuzz run --features="mbed-crypto-provider,tpm-provider,pkcs11-provider" fuzz_service
+    cargo +nightly fuzz run --features="mbed-crypto-provider,tpm-provider,pkcs11-provider" $FUZZ_TARGET
 
     cleanup
     setup_tpm
@@ -0,0 +1,626 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Attestation token authenticator
+//!
+//! Clients authenticate with a short-lived token signed by a key which was previously attested,
+//! for example a TPM key attested with the `AttestKey` operation of Parsec itself. Once the
+//! attestation has been checked, the administrator registers the public part of the key in the
+//! configuration, together with the application name it stands for. Tokens then bind the requests
+//! to that key, without the need of an external identity infrastructure.
+//!
+//! Tokens are JSON Web Signatures in compact serialization, with a header naming the key in its
+//! `kid` field and the signature algorithm in its `alg` field. Each key only verifies tokens of one
+//! algorithm, so that a token can not have its signature checked in a way the key was not meant
+//! for: `ES256` for P-256 keys, `ES384` for P-384 keys and `RS256` for RSA keys, unless `PS256` is
+//! configured for them. The claims must contain an `aud` of `"parsec"` and an `exp` expiration time, at most
+//! the configured token lifetime in the future. If a `sub` claim is present, it must be the name of
+//! the application of the key. The signatures have the format of the `PsaSignHash` operation, so
+//! clients can create them with the attested key stored in Parsec.
+
+use super::{Admin, AdminList, Application, ApplicationIdentity, Authenticate};
+use crate::front::listener::ConnectionMetadata;
+use crate::utils::config::AttestedKeyConfig;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use log::error;
+use parsec_interface::operations::list_authenticators;
+use parsec_interface::requests::request::RequestAuth;
+use parsec_interface::requests::{AuthType, ResponseStatus, Result};
+use parsec_interface::secrecy::ExposeSecret;
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Audience that the tokens must be issued for
+const AUDIENCE: &str = "parsec";
+
+/// Key trusted to sign tokens for an application
+#[derive(Debug)]
+struct AttestedKey {
+    application: String,
+    public_key: Vec<u8>,
+    algorithm: TokenAlgorithm,
+}
+
+/// Signature algorithm of the tokens
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TokenAlgorithm {
+    Es256,
+    Es384,
+    Rs256,
+    Ps256,
+}
+
+impl TokenAlgorithm {
+    /// Algorithm of the given JWS name
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ES256" => Some(TokenAlgorithm::Es256),
+            "ES384" => Some(TokenAlgorithm::Es384),
+            "RS256" => Some(TokenAlgorithm::Rs256),
+            "PS256" => Some(TokenAlgorithm::Ps256),
+            _ => None,
+        }
+    }
+
+    /// JWS name of the algorithm
+    fn name(self) -> &'static str {
+        match self {
+            TokenAlgorithm::Es256 => "ES256",
+            TokenAlgorithm::Es384 => "ES384",
+            TokenAlgorithm::Rs256 => "RS256",
+            TokenAlgorithm::Ps256 => "PS256",
+        }
+    }
+
+    /// Default algorithm of a public key in the format of PsaExportPublicKey: the uncompressed
+    /// point of a P-256 or P-384 key, or the DER encoding of an RSA key.
+    fn of_key(public_key: &[u8]) -> Option<Self> {
+        match public_key {
+            [0x04, point @ ..] if point.len() == 64 => Some(TokenAlgorithm::Es256),
+            [0x04, point @ ..] if point.len() == 96 => Some(TokenAlgorithm::Es384),
+            [0x30, ..] => Some(TokenAlgorithm::Rs256),
+            _ => None,
+        }
+    }
+
+    /// Whether the algorithm can be used with the keys of a default algorithm
+    fn fits(self, default: TokenAlgorithm) -> bool {
+        self == default || (self == TokenAlgorithm::Ps256 && default == TokenAlgorithm::Rs256)
+    }
+
+    fn verification(self) -> &'static dyn VerificationAlgorithm {
+        match self {
+            TokenAlgorithm::Es256 => &signature::ECDSA_P256_SHA256_FIXED,
+            TokenAlgorithm::Es384 => &signature::ECDSA_P384_SHA384_FIXED,
+            TokenAlgorithm::Rs256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+            TokenAlgorithm::Ps256 => &signature::RSA_PSS_2048_8192_SHA256,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    aud: String,
+    exp: u64,
+    sub: Option<String>,
+}
+
+/// Attestation token authenticator
+#[derive(Debug)]
+pub struct AttestationTokenAuthenticator {
+    admins: AdminList,
+    keys: HashMap<String, AttestedKey>,
+    max_token_lifetime: Duration,
+}
+
+impl AttestationTokenAuthenticator {
+    /// Create a new attestation token authenticator trusting the given keys, and accepting tokens
+    /// expiring at most `max_token_lifetime` in the future.
+    pub fn new(
+        keys: &[AttestedKeyConfig],
+        max_token_lifetime: Duration,
+        admins: Vec<Admin>,
+    ) -> std::io::Result<Self> {
+        let mut attested_keys = HashMap::new();
+        for key in keys {
+            let public_key = STANDARD.decode(key.public_key()).map_err(|e| {
+                error!(
+                    "The public key of attested key \"{}\" is not valid base64 ({}).",
+                    key.key_id(),
+                    e
+                );
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid attested key")
+            })?;
+            let default_algorithm = TokenAlgorithm::of_key(&public_key).ok_or_else(|| {
+                error!(
+                    "The public key of attested key \"{}\" is neither a P-256, P-384 nor RSA key.",
+                    key.key_id()
+                );
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid attested key")
+            })?;
+            let algorithm = match key.algorithm() {
+                None => default_algorithm,
+                Some(name) => match TokenAlgorithm::from_name(name) {
+                    Some(algorithm) if algorithm.fits(default_algorithm) => algorithm,
+                    _ => {
+                        error!(
+                            "The algorithm {} can not be used with attested key \"{}\".",
+                            name,
+                            key.key_id()
+                        );
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "invalid attested key algorithm",
+                        ));
+                    }
+                },
+            };
+            let previous = attested_keys.insert(
+                key.key_id().to_string(),
+                AttestedKey {
+                    application: key.application().to_string(),
+                    public_key,
+                    algorithm,
+                },
+            );
+            if previous.is_some() {
+                error!("The attested key \"{}\" is defined twice.", key.key_id());
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "duplicate attested key",
+                ));
+            }
+        }
+
+        Ok(AttestationTokenAuthenticator {
+            admins: admins.into(),
+            keys: attested_keys,
+            max_token_lifetime,
+        })
+    }
+
+    fn verify(&self, token: &str) -> Result<String> {
+        let mut parts = token.split('.');
+        let (header_part, claims_part, token_signature) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(header), Some(claims), Some(signature), None) => (header, claims, signature),
+                _ => {
+                    error!("The attestation token is not a JWS in compact serialization.");
+                    return Err(ResponseStatus::AuthenticationError);
+                }
+            };
+        let header: Header = decode_part(header_part)?;
+        let key = self.keys.get(&header.kid).ok_or_else(|| {
+            if crate::utils::GlobalConfig::log_error_details() {
+                error!(
+                    "The attestation token is signed by an unknown key: {}.",
+                    header.kid
+                );
+            } else {
+                error!("The attestation token is signed by an unknown key.");
+            }
+            ResponseStatus::AuthenticationError
+        })?;
+
+        // Only the algorithm of the key is accepted, whatever the token says.
+        if header.alg != key.algorithm.name() {
+            error!("The attestation token is not signed with the algorithm of its key.");
+            return Err(ResponseStatus::AuthenticationError);
+        }
+        let token_signature = URL_SAFE_NO_PAD.decode(token_signature).map_err(|_| {
+            error!("The signature of the attestation token is not valid base64url.");
+            ResponseStatus::AuthenticationError
+        })?;
+        // The signing input is the token without its signature.
+        let signing_input = &token[..header_part.len() + 1 + claims_part.len()];
+        UnparsedPublicKey::new(key.algorithm.verification(), &key.public_key)
+            .verify(signing_input.as_bytes(), &token_signature)
+            .map_err(|_| {
+                error!("The signature of the attestation token is not valid.");
+                ResponseStatus::AuthenticationError
+            })?;
+
+        let claims: Claims = decode_part(claims_part)?;
+        if claims.aud != AUDIENCE {
+            error!("The attestation token is not issued for Parsec.");
+            return Err(ResponseStatus::AuthenticationError);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| ResponseStatus::AuthenticationError)?
+            .as_secs();
+        if claims.exp <= now {
+            error!("The attestation token has expired.");
+            return Err(ResponseStatus::AuthenticationError);
+        }
+        if claims.exp - now > self.max_token_lifetime.as_secs() {
+            error!("The attestation token expires too far in the future.");
+            return Err(ResponseStatus::AuthenticationError);
+        }
+        if claims.sub.map_or(false, |sub| sub != key.application) {
+            error!("The subject of the attestation token is not the application of its key.");
+            return Err(ResponseStatus::AuthenticationError);
+        }
+
+        Ok(key.application.clone())
+    }
+}
+
+/// Decode a base64url JSON part of a token.
+fn decode_part<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T> {
+    let json = URL_SAFE_NO_PAD.decode(part).map_err(|_| {
+        error!("A part of the attestation token is not valid base64url.");
+        ResponseStatus::AuthenticationError
+    })?;
+    serde_json::from_slice(&json).map_err(|e| {
+        error!("A part of the attestation token can not be parsed ({}).", e);
+        ResponseStatus::AuthenticationError
+    })
+}
+
+impl Authenticate for AttestationTokenAuthenticator {
+    fn describe(&self) -> Result<list_authenticators::AuthenticatorInfo> {
+        Ok(list_authenticators::AuthenticatorInfo {
+            description: String::from(
+                "Authenticator validating JSON Web Signatures made with previously attested keys",
+            ),
+            version_maj: 0,
+            version_min: 1,
+            version_rev: 0,
+            id: AuthType::Jwt,
+        })
+    }
+
+    fn authenticate(
+        &self,
+        auth: &RequestAuth,
+        _: Option<ConnectionMetadata>,
+    ) -> Result<Application> {
+        let token = str::from_utf8(auth.buffer.expose_secret()).map_err(|_| {
+            error!("The authentication buffer can not be parsed into a UTF-8 string.");
+            ResponseStatus::AuthenticationError
+        })?;
+        let app_name = self.verify(token)?;
+        let is_admin = self.admins.is_admin(&app_name);
+        Ok(Application {
+            identity: ApplicationIdentity {
+                name: app_name,
+                authenticator_id: AuthType::Jwt,
+            },
+            is_admin,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    const MAX_TOKEN_LIFETIME: Duration = Duration::from_secs(300);
+
+    // Public part of a 2048 bits RSA key, in the format of PsaExportPublicKey.
+    const RSA_PUBLIC_KEY: &str = "MIIBCgKCAQEArURiCFGHxVrORc26aHaRSeOA4IbEbF7fzOXbyiAATVGgfCP3CR+2h1vUgshpMXwskgILwOfrM07CDtzDFtBno/ZedKvvKo7WD6OoaYm1lQzDxi0dLb/QvkPEyYNkvJI4PyRptEoKrJuwvQhCiuW5jCPr9vNdz6niUxmv24TRXDZAmaor0Bh1eyZezxzQAZc569naDhMaxVrAuW0qWc6ueBYNrGnLbeQnEYerEKkOr8gopxCSNgle1gE4D/23qHSXlfz0kL1OiImG85bXXLEoZcTk7+GTX1JFAJQHWqt7g091pHo5QFUsDOBmGIAuOc0udgOqhAKZ134AAGJR+2GyNwIDAQAB";
+
+    fn key_pair() -> EcdsaKeyPair {
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+                .unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap()
+    }
+
+    fn key_config(key_id: &str, public_key: &str) -> AttestedKeyConfig {
+        toml::from_str(&format!(
+            "key_id = '{}'\napplication = 'workload'\npublic_key = '{}'",
+            key_id, public_key
+        ))
+        .unwrap()
+    }
+
+    fn authenticator(key_pair: &EcdsaKeyPair, admins: &[&str]) -> AttestationTokenAuthenticator {
+        let public_key = STANDARD.encode(key_pair.public_key());
+        let admins = admins
+            .iter()
+            .map(|name| toml::from_str(&format!("name = '{}'", name)).unwrap())
+            .collect();
+        AttestationTokenAuthenticator::new(
+            &[key_config("tpm-key", &public_key)],
+            MAX_TOKEN_LIFETIME,
+            admins,
+        )
+        .unwrap()
+    }
+
+    fn header(kid: &str) -> String {
+        format!(r#"{{"alg":"ES256","kid":"{}"}}"#, kid)
+    }
+
+    fn claims(exp_in: u64, sub: Option<&str>) -> String {
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + exp_in;
+        match sub {
+            Some(sub) => format!(r#"{{"aud":"parsec","exp":{},"sub":"{}"}}"#, exp, sub),
+            None => format!(r#"{{"aud":"parsec","exp":{}}}"#, exp),
+        }
+    }
+
+    fn token(key_pair: &EcdsaKeyPair, header: &str, claims: &str) -> String {
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header),
+            URL_SAFE_NO_PAD.encode(claims)
+        );
+        let signature = key_pair
+            .sign(&SystemRandom::new(), signing_input.as_bytes())
+            .unwrap();
+        format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn authenticate(
+        authenticator: &AttestationTokenAuthenticator,
+        token: impl Into<Vec<u8>>,
+    ) -> Result<Application> {
+        authenticator.authenticate(&RequestAuth::new(token.into()), None)
+    }
+
+    #[test]
+    fn token_authenticates_application_of_key() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let valid = token(&key_pair, &header("tpm-key"), &claims(60, Some("workload")));
+        let application = authenticate(&authenticator, valid).unwrap();
+        assert_eq!(application.identity.name, "workload");
+        assert_eq!(application.identity.authenticator_id, AuthType::Jwt);
+        assert!(!application.is_admin);
+    }
+
+    #[test]
+    fn subject_is_optional() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let valid = token(&key_pair, &header("tpm-key"), &claims(60, None));
+        let application = authenticate(&authenticator, valid).unwrap();
+        assert_eq!(application.identity.name, "workload");
+    }
+
+    #[test]
+    fn admin_application() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &["workload"]);
+        let valid = token(&key_pair, &header("tpm-key"), &claims(60, None));
+        assert!(authenticate(&authenticator, valid).unwrap().is_admin);
+    }
+
+    #[test]
+    fn tampered_claims() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let valid = token(&key_pair, &header("tpm-key"), &claims(60, Some("workload")));
+        // Claims of another application, with the signature of the valid token.
+        let parts: Vec<&str> = valid.split('.').collect();
+        let tampered = format!(
+            "{}.{}.{}",
+            parts[0],
+            URL_SAFE_NO_PAD.encode(claims(60, Some("admin"))),
+            parts[2]
+        );
+        assert_eq!(
+            authenticate(&authenticator, tampered).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn signature_of_another_key() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let forged = token(&self::key_pair(), &header("tpm-key"), &claims(60, None));
+        assert_eq!(
+            authenticate(&authenticator, forged).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn unknown_key() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let unknown_key = token(&key_pair, &header("other-key"), &claims(60, None));
+        assert_eq!(
+            authenticate(&authenticator, unknown_key).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn unsupported_algorithm() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let hmac = token(
+            &key_pair,
+            r#"{"alg":"HS256","kid":"tpm-key"}"#,
+            &claims(60, None),
+        );
+        assert_eq!(
+            authenticate(&authenticator, hmac).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn algorithm_of_the_key_is_pinned() {
+        let key_pair = key_pair();
+        let authenticator = AttestationTokenAuthenticator::new(
+            &[key_config("rsa-key", RSA_PUBLIC_KEY)],
+            MAX_TOKEN_LIFETIME,
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            authenticator.keys["rsa-key"].algorithm,
+            TokenAlgorithm::Rs256
+        );
+        // Tokens naming the RSA key with another algorithm are refused, whatever their signature.
+        for alg in ["HS256", "ES256", "PS256"] {
+            let other_algorithm = token(
+                &key_pair,
+                &format!(r#"{{"alg":"{}","kid":"rsa-key"}}"#, alg),
+                &claims(60, None),
+            );
+            assert_eq!(
+                authenticate(&authenticator, other_algorithm).unwrap_err(),
+                ResponseStatus::AuthenticationError
+            );
+        }
+    }
+
+    #[test]
+    fn configured_algorithm_must_fit_the_key() {
+        let config = |public_key: &str, algorithm: &str| -> AttestedKeyConfig {
+            toml::from_str(&format!(
+                "key_id = 'key'\napplication = 'workload'\npublic_key = '{}'\nalgorithm = '{}'",
+                public_key, algorithm
+            ))
+            .unwrap()
+        };
+        let authenticator = AttestationTokenAuthenticator::new(
+            &[config(RSA_PUBLIC_KEY, "PS256")],
+            MAX_TOKEN_LIFETIME,
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(authenticator.keys["key"].algorithm, TokenAlgorithm::Ps256);
+
+        let ecc_public_key = STANDARD.encode(key_pair().public_key());
+        for (public_key, algorithm) in [
+            (ecc_public_key.as_str(), "ES384"),
+            (ecc_public_key.as_str(), "RS256"),
+            (RSA_PUBLIC_KEY, "ES256"),
+            (RSA_PUBLIC_KEY, "HS256"),
+        ] {
+            let error = AttestationTokenAuthenticator::new(
+                &[config(public_key, algorithm)],
+                MAX_TOKEN_LIFETIME,
+                Vec::new(),
+            )
+            .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn other_audience() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let claims = claims(60, None).replace("parsec", "other-service");
+        let other_audience = token(&key_pair, &header("tpm-key"), &claims);
+        assert_eq!(
+            authenticate(&authenticator, other_audience).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn expired_token() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let expired = token(
+            &key_pair,
+            &header("tpm-key"),
+            &format!(r#"{{"aud":"parsec","exp":{}}}"#, exp),
+        );
+        assert_eq!(
+            authenticate(&authenticator, expired).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn lifetime_too_long() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let too_long = token(
+            &key_pair,
+            &header("tpm-key"),
+            &claims(MAX_TOKEN_LIFETIME.as_secs() + 60, None),
+        );
+        assert_eq!(
+            authenticate(&authenticator, too_long).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn subject_of_another_application() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &["admin"]);
+        let other_subject = token(&key_pair, &header("tpm-key"), &claims(60, Some("admin")));
+        assert_eq!(
+            authenticate(&authenticator, other_subject).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn malformed_tokens() {
+        let key_pair = key_pair();
+        let authenticator = authenticator(&key_pair, &[]);
+        let valid = token(&key_pair, &header("tpm-key"), &claims(60, None));
+        let malformed: Vec<Vec<u8>> = vec![
+            valid.rsplit_once('.').unwrap().0.into(),
+            format!("{}.", valid).into(),
+            valid.replacen('.', ".!", 1).into(),
+            format!("{}!", valid).into(),
+            vec![0xff; 16],
+        ];
+        for token in malformed {
+            assert_eq!(
+                authenticate(&authenticator, token).unwrap_err(),
+                ResponseStatus::AuthenticationError
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_public_key_encoding() {
+        let error = AttestationTokenAuthenticator::new(
+            &[key_config("tpm-key", "not base64!")],
+            MAX_TOKEN_LIFETIME,
+            Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn duplicate_key() {
+        let public_key = STANDARD.encode(key_pair().public_key());
+        let error = AttestationTokenAuthenticator::new(
+            &[
+                key_config("tpm-key", &public_key),
+                key_config("tpm-key", &public_key),
+            ],
+            MAX_TOKEN_LIFETIME,
+            Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Authentication cache
+//!
+//! Bounded cache remembering the result of an authentication for a limited time, for
+//! authenticators where checking a credential is expensive. Entries are looked up by a key derived
+//! from the credential (typically its hash), never by the credential itself.
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    inserted: Instant,
+}
+
+/// Bounded cache with a time-to-live on every entry
+#[derive(Debug)]
+pub struct AuthCache<K, V> {
+    entries: Mutex<HashMap<K, Entry<V>>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> AuthCache<K, V> {
+    /// Create a cache holding at most `capacity` entries, each valid for `ttl`.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        AuthCache {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Return the value cached for `key`, if it has not expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self
+            .entries
+            .lock()
+            .expect("Authentication cache lock poisoned");
+        match entries.get(key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => Some(entry.value.clone()),
+            Some(_) => {
+                let _ = entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache `value` for `key`. If the cache is full, expired entries are dropped first and then,
+    /// if needed, the oldest one.
+    pub fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self
+            .entries
+            .lock()
+            .expect("Authentication cache lock poisoned");
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.inserted.elapsed() < ttl);
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.inserted)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    let _ = entries.remove(&oldest);
+                }
+            }
+        }
+        let _ = entries.insert(
+            key,
+            Entry {
+                value,
+                inserted: Instant::now(),
+            },
+        );
+    }
+
+    /// Drop all the entries of the cache.
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .expect("Authentication cache lock poisoned")
+            .clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn cached_values_are_returned() {
+        let cache = AuthCache::new(2, Duration::from_secs(60));
+        assert_eq!(cache.get(&1), None);
+        cache.insert(1, "one");
+        assert_eq!(cache.get(&1), Some("one"));
+        cache.insert(1, "uno");
+        assert_eq!(cache.get(&1), Some("uno"));
+    }
+
+    #[test]
+    fn entries_expire() {
+        let cache = AuthCache::new(2, Duration::from_millis(50));
+        cache.insert(1, "one");
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn oldest_entry_is_evicted() {
+        let cache = AuthCache::new(2, Duration::from_secs(60));
+        cache.insert(1, "one");
+        thread::sleep(Duration::from_millis(5));
+        cache.insert(2, "two");
+        cache.insert(3, "three");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some("two"));
+        assert_eq!(cache.get(&3), Some("three"));
+    }
+
+    #[test]
+    fn expired_entries_are_evicted_first() {
+        let cache = AuthCache::new(2, Duration::from_millis(50));
+        cache.insert(1, "one");
+        thread::sleep(Duration::from_millis(60));
+        cache.insert(2, "two");
+        cache.insert(3, "three");
+        // The expired entry made room: the valid one is kept.
+        assert_eq!(cache.get(&2), Some("two"));
+        assert_eq!(cache.get(&3), Some("three"));
+    }
+
+    #[test]
+    fn updating_a_full_cache_evicts_nothing() {
+        let cache = AuthCache::new(2, Duration::from_secs(60));
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        cache.insert(1, "uno");
+        assert_eq!(cache.get(&1), Some("uno"));
+        assert_eq!(cache.get(&2), Some("two"));
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let cache = AuthCache::new(0, Duration::from_secs(60));
+        cache.insert(1, "one");
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn cleared_cache_is_empty() {
+        let cache = AuthCache::new(2, Duration::from_secs(60));
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        cache.clear();
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), None);
+    }
+}
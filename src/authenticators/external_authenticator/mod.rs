@@ -0,0 +1,390 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! External authenticator
+//!
+//! The authentication of the requests is delegated to a plugin, a separate process listening on a
+//! Unix domain socket, so that identity systems not known of Parsec can be used without changing
+//! the service. For each request, the authenticator connects to the socket and sends an
+//! authentication request, to which the plugin answers with its decision. Both are JSON objects on
+//! a single line, ended by a newline:
+//!
+//! * the request: `{"version":1,"auth_type":3,"auth":"<base64>","peer":<peer>}`, with the
+//!   authentication type of the request header, the authentication field of the request in
+//!   base64 and, if the connection has some, the Unix peer credentials of the client as
+//!   `{"uid":1000,"gid":1000,"pid":42,"security_context":null}`, `null` otherwise. The `pid` and
+//!   `security_context` members can also be `null`.
+//! * the decision: `{"allow":true,"application":"<name>"}` to authenticate the request as coming
+//!   from the named application, or `{"allow":false,"reason":"<why>"}` to reject it.
+//!
+//! The authenticator fails closed: the request is rejected if the plugin can not be reached,
+//! takes longer than the timeout to answer, or gives an answer which is not a valid decision.
+use super::{Admin, AdminList, Application, ApplicationIdentity, Authenticate};
+use crate::front::listener::ConnectionMetadata;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::error;
+use parsec_interface::operations::list_authenticators;
+use parsec_interface::requests::request::RequestAuth;
+use parsec_interface::requests::{AuthType, ResponseStatus, Result};
+use parsec_interface::secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Version of the plugin protocol
+const PROTOCOL_VERSION: u8 = 1;
+
+/// Maximum size of a decision, in bytes
+const MAX_DECISION_SIZE: u64 = 64 * 1024;
+
+/// Authentication request sent to the plugin
+#[derive(Serialize)]
+struct AuthnRequest {
+    version: u8,
+    auth_type: u8,
+    auth: String,
+    peer: Option<Peer>,
+}
+
+/// Unix peer credentials of the client
+#[derive(Serialize)]
+struct Peer {
+    uid: u32,
+    gid: u32,
+    pid: Option<i32>,
+    security_context: Option<String>,
+}
+
+/// Decision of the plugin on an authentication request
+#[derive(Deserialize)]
+struct AuthnDecision {
+    allow: bool,
+    application: Option<String>,
+    reason: Option<String>,
+}
+
+/// External authenticator
+#[derive(Debug)]
+pub struct ExternalAuthenticator {
+    admins: AdminList,
+    socket_path: PathBuf,
+    auth_type: AuthType,
+    timeout: Duration,
+}
+
+impl ExternalAuthenticator {
+    /// Create an authenticator asking the plugin listening on `socket_path` for the requests of
+    /// the given authentication type, and waiting at most `timeout` for each of its decisions.
+    pub fn new(
+        socket_path: PathBuf,
+        auth_type: AuthType,
+        timeout: Duration,
+        admins: Vec<Admin>,
+    ) -> Self {
+        ExternalAuthenticator {
+            admins: admins.into(),
+            socket_path,
+            auth_type,
+            timeout,
+        }
+    }
+
+    fn decide(&self, request: &AuthnRequest) -> std::io::Result<AuthnDecision> {
+        let mut stream = UnixStream::connect(&self.socket_path)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        stream.write_all(&line)?;
+
+        let mut decision = String::new();
+        let _ = BufReader::new(stream.take(MAX_DECISION_SIZE)).read_line(&mut decision)?;
+        Ok(serde_json::from_str(&decision)?)
+    }
+}
+
+impl Authenticate for ExternalAuthenticator {
+    fn describe(&self) -> Result<list_authenticators::AuthenticatorInfo> {
+        Ok(list_authenticators::AuthenticatorInfo {
+            description: String::from(
+                "Authenticator delegating the decisions to an external plugin",
+            ),
+            version_maj: 0,
+            version_min: 1,
+            version_rev: 0,
+            id: self.auth_type,
+        })
+    }
+
+    fn authenticate(
+        &self,
+        auth: &RequestAuth,
+        meta: Option<ConnectionMetadata>,
+    ) -> Result<Application> {
+        let peer = meta.map(|meta| match meta {
+            ConnectionMetadata::UnixPeerCredentials {
+                uid,
+                gid,
+                pid,
+                security_context,
+            } => Peer {
+                uid,
+                gid,
+                pid,
+                security_context,
+            },
+        });
+        let request = AuthnRequest {
+            version: PROTOCOL_VERSION,
+            auth_type: self.auth_type as u8,
+            auth: STANDARD.encode(auth.buffer.expose_secret()),
+            peer,
+        };
+        let decision = self.decide(&request).map_err(|e| {
+            format_error!("Failed to get a decision from the authentication plugin", e);
+            ResponseStatus::AuthenticationError
+        })?;
+
+        let app_name = match decision {
+            AuthnDecision {
+                allow: true,
+                application: Some(application),
+                ..
+            } if !application.is_empty() => application,
+            AuthnDecision { allow: true, .. } => {
+                error!(
+                    "The authentication plugin allowed a request without naming its application."
+                );
+                return Err(ResponseStatus::AuthenticationError);
+            }
+            AuthnDecision { reason, .. } => {
+                error!(
+                    "The authentication plugin rejected the request ({}).",
+                    reason.as_deref().unwrap_or("no reason given")
+                );
+                return Err(ResponseStatus::AuthenticationError);
+            }
+        };
+        let is_admin = self.admins.is_admin(&app_name);
+        Ok(Application {
+            identity: ApplicationIdentity {
+                name: app_name,
+                authenticator_id: self.auth_type,
+            },
+            is_admin,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::config::AuthenticatorConfig;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread::{self, JoinHandle};
+
+    // Plugin answering each request with the next decision, returning the requests it received
+    fn plugin(dir: &Path, decisions: &[&str]) -> (PathBuf, JoinHandle<Vec<serde_json::Value>>) {
+        let socket_path = dir.join("plugin.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let decisions: Vec<String> = decisions
+            .iter()
+            .map(|decision| decision.to_string())
+            .collect();
+        let plugin = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for decision in decisions {
+                let (stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let _ = BufReader::new(&stream).read_line(&mut request).unwrap();
+                requests.push(serde_json::from_str(&request).unwrap());
+                // The authenticator stops reading oversized decisions.
+                let _ = writeln!(&stream, "{}", decision);
+            }
+            requests
+        });
+        (socket_path, plugin)
+    }
+
+    fn authenticator(socket_path: &Path, admins: Vec<Admin>) -> ExternalAuthenticator {
+        ExternalAuthenticator::new(
+            socket_path.to_path_buf(),
+            AuthType::Jwt,
+            Duration::from_secs(5),
+            admins,
+        )
+    }
+
+    fn peer() -> Option<ConnectionMetadata> {
+        Some(ConnectionMetadata::UnixPeerCredentials {
+            uid: 1000,
+            gid: 100,
+            pid: Some(42),
+            security_context: Some(String::from("unconfined")),
+        })
+    }
+
+    fn authenticate(authenticator: &ExternalAuthenticator) -> Result<Application> {
+        authenticator.authenticate(&RequestAuth::new(b"token".to_vec()), peer())
+    }
+
+    // Whether the single decision is refused
+    fn refused(decision: &str) -> bool {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = plugin(dir.path(), &[decision]);
+        let result = authenticate(&authenticator(&socket_path, Vec::new()));
+        let _ = plugin.join().unwrap();
+        result.map(|_| ()) == Err(ResponseStatus::AuthenticationError)
+    }
+
+    #[test]
+    fn allowed_requests_come_from_the_named_application() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) =
+            plugin(dir.path(), &[r#"{"allow":true,"application":"payroll"}"#]);
+        let application = authenticate(&authenticator(&socket_path, Vec::new())).unwrap();
+        let _ = plugin.join().unwrap();
+        assert_eq!(application.identity().name(), "payroll");
+        assert_eq!(*application.identity().authenticator_id(), AuthType::Jwt);
+        assert!(!application.is_admin());
+    }
+
+    #[test]
+    fn admins_are_recognised() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) =
+            plugin(dir.path(), &[r#"{"allow":true,"application":"payroll"}"#]);
+        let admins = vec![toml::from_str("name = 'payroll'").unwrap()];
+        let application = authenticate(&authenticator(&socket_path, admins)).unwrap();
+        let _ = plugin.join().unwrap();
+        assert!(application.is_admin());
+    }
+
+    #[test]
+    fn requests_carry_the_authentication_and_the_peer() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) =
+            plugin(dir.path(), &[r#"{"allow":true,"application":"payroll"}"#]);
+        let _ = authenticate(&authenticator(&socket_path, Vec::new())).unwrap();
+        let requests = plugin.join().unwrap();
+        assert_eq!(
+            requests[0],
+            serde_json::json!({
+                "version": 1,
+                "auth_type": AuthType::Jwt as u8,
+                "auth": STANDARD.encode("token"),
+                "peer": {
+                    "uid": 1000,
+                    "gid": 100,
+                    "pid": 42,
+                    "security_context": "unconfined",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn requests_without_credentials_have_no_peer() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) =
+            plugin(dir.path(), &[r#"{"allow":true,"application":"payroll"}"#]);
+        let _ = authenticator(&socket_path, Vec::new())
+            .authenticate(&RequestAuth::new(b"token".to_vec()), None)
+            .unwrap();
+        let requests = plugin.join().unwrap();
+        assert!(requests[0]["peer"].is_null());
+    }
+
+    #[test]
+    fn rejected_requests_are_refused() {
+        assert!(refused(r#"{"allow":false,"reason":"unknown token"}"#));
+        assert!(refused(r#"{"allow":false}"#));
+        // The application is ignored when the request is rejected.
+        assert!(refused(r#"{"allow":false,"application":"payroll"}"#));
+    }
+
+    #[test]
+    fn allowed_requests_without_application_are_refused() {
+        assert!(refused(r#"{"allow":true}"#));
+        assert!(refused(r#"{"allow":true,"application":""}"#));
+    }
+
+    #[test]
+    fn invalid_decisions_are_refused() {
+        assert!(refused("not a decision"));
+        assert!(refused(r#"{"application":"payroll"}"#));
+        assert!(refused(r#"{"allow":"yes","application":"payroll"}"#));
+        assert!(refused(""));
+    }
+
+    #[test]
+    fn oversized_decisions_are_refused() {
+        let application = "a".repeat(MAX_DECISION_SIZE as usize);
+        let decision = format!(r#"{{"allow":true,"application":"{}"}}"#, application);
+        assert!(refused(&decision));
+    }
+
+    #[test]
+    fn unreachable_plugins_refuse_every_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let authenticator = authenticator(&dir.path().join("missing.sock"), Vec::new());
+        assert_eq!(
+            authenticate(&authenticator).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn slow_plugins_refuse_the_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("plugin.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let (done, wait) = mpsc::channel::<()>();
+        let plugin = thread::spawn(move || {
+            // Keep the connection open without answering until the request is refused.
+            let (_stream, _) = listener.accept().unwrap();
+            let _ = wait.recv();
+        });
+        let authenticator = ExternalAuthenticator::new(
+            socket_path,
+            AuthType::Jwt,
+            Duration::from_millis(100),
+            Vec::new(),
+        );
+        assert_eq!(
+            authenticate(&authenticator).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+        drop(done);
+        plugin.join().unwrap();
+    }
+
+    #[test]
+    fn authenticator_is_described_with_its_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let authenticator = ExternalAuthenticator::new(
+            dir.path().join("plugin.sock"),
+            AuthType::JwtSvid,
+            Duration::from_secs(1),
+            Vec::new(),
+        );
+        assert_eq!(authenticator.describe().unwrap().id, AuthType::JwtSvid);
+    }
+
+    #[test]
+    fn client_auth_type_defaults_to_jwt() {
+        let config: AuthenticatorConfig =
+            toml::from_str("auth_type = 'External'\nsocket_path = '/run/plugin.sock'").unwrap();
+        assert_eq!(config.auth_type(), AuthType::Jwt);
+        let config: AuthenticatorConfig = toml::from_str(
+            "auth_type = 'External'\nsocket_path = '/run/plugin.sock'\n\
+             client_auth_type = 'UnixPeerCredentials'",
+        )
+        .unwrap();
+        assert_eq!(config.auth_type(), AuthType::UnixPeerCredentials);
+    }
+}
@@ -2,22 +2,34 @@
 // SPDX-License-Identifier: Apache-2.0
 //! JWT SVID authenticator
 
+use super::cache::AuthCache;
 use super::{Admin, AdminList, Application, ApplicationIdentity, Authenticate};
 use crate::front::listener::ConnectionMetadata;
-use log::error;
+use log::{error, info};
 use parsec_interface::operations::list_authenticators;
 use parsec_interface::requests::request::RequestAuth;
 use parsec_interface::requests::Result;
 use parsec_interface::requests::{AuthType, ResponseStatus};
 use parsec_interface::secrecy::ExposeSecret;
+use ring::digest::{digest, SHA256};
+use spiffe::bundle::jwt::JwtBundleSet;
 use spiffe::workload_api::client::WorkloadApiClient;
 use std::str;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two checks of the JWT trust bundles, when the cache is enabled
+const BUNDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 /// JWT SVID authenticator
 #[allow(missing_debug_implementations)]
 pub struct JwtSvidAuthenticator {
     client: WorkloadApiClient,
     admins: AdminList,
+    // Maps the SHA-256 of validated tokens to their SPIFFE ID.
+    cache: Option<AuthCache<Vec<u8>, String>>,
+    // Trust bundles seen at the last check, with the time of that check.
+    bundles: Mutex<Option<(Instant, JwtBundleSet)>>,
 }
 
 impl JwtSvidAuthenticator {
@@ -33,8 +45,55 @@ impl JwtSvidAuthenticator {
         Some(JwtSvidAuthenticator {
             client,
             admins: admins.into(),
+            cache: None,
+            bundles: Mutex::new(None),
         })
     }
+
+    /// Cache up to `capacity` validated tokens for `ttl`, instead of validating every token with
+    /// the Workload API. The cache is emptied every time the JWT trust bundles change.
+    ///
+    /// A cached token stays accepted until the end of the `ttl`, even if it expires before.
+    pub fn with_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.cache = Some(AuthCache::new(capacity, ttl));
+
+        self
+    }
+
+    /// Empty the cache if the trust bundles have changed since they were last checked.
+    fn check_bundles(&self, cache: &AuthCache<Vec<u8>, String>) -> Result<()> {
+        let mut bundles = self.bundles.lock().expect("Trust bundles lock poisoned");
+        if let Some((checked, _)) = *bundles {
+            if checked.elapsed() < BUNDLE_CHECK_INTERVAL {
+                return Ok(());
+            }
+        }
+        let current = self.client.fetch_jwt_bundles().map_err(|e| {
+            error!("Failed to fetch the JWT trust bundles ({}).", e);
+            ResponseStatus::AuthenticationError
+        })?;
+        match bundles.take() {
+            Some((_, previous)) if previous == current => (),
+            Some(_) => {
+                info!("The JWT trust bundles changed, emptying the authentication cache.");
+                cache.clear();
+            }
+            None => cache.clear(),
+        }
+        *bundles = Some((Instant::now(), current));
+        Ok(())
+    }
+
+    fn validate(&self, svid: &str) -> Result<String> {
+        let jwt_token = self
+            .client
+            .validate_jwt_token("parsec", svid)
+            .map_err(|e| {
+                error!("The validation of the JWT-SVID failed ({}).", e);
+                ResponseStatus::AuthenticationError
+            })?;
+        Ok(jwt_token.spiffe_id().to_string())
+    }
 }
 
 impl Authenticate for JwtSvidAuthenticator {
@@ -63,14 +122,21 @@ impl Authenticate for JwtSvidAuthenticator {
             ResponseStatus::InvalidEncoding
         })?;
 
-        let jwt_token = self
-            .client
-            .validate_jwt_token("parsec", svid)
-            .map_err(|e| {
-                error!("The validation of the JWT-SVID failed ({}).", e);
-                ResponseStatus::AuthenticationError
-            })?;
-        let app_name = jwt_token.spiffe_id().to_string();
+        let app_name = match &self.cache {
+            Some(cache) => {
+                self.check_bundles(cache)?;
+                let token_hash = digest(&SHA256, svid.as_bytes()).as_ref().to_vec();
+                match cache.get(&token_hash) {
+                    Some(app_name) => app_name,
+                    None => {
+                        let app_name = self.validate(svid)?;
+                        cache.insert(token_hash, app_name.clone());
+                        app_name
+                    }
+                }
+            }
+            None => self.validate(svid)?,
+        };
         let is_admin = self.admins.is_admin(&app_name);
         Ok(Application {
             identity: ApplicationIdentity {
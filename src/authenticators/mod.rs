@@ -13,9 +13,14 @@
     feature = "direct-authenticator",
     feature = "unix-peer-credentials-authenticator",
     feature = "jwt-svid-authenticator",
+    feature = "attestation-token-authenticator",
+    feature = "external-authenticator",
 )))]
 compile_error!("Please provide in at least one authenticator");
 
+pub mod cache;
+pub mod tenants;
+
 #[cfg(feature = "direct-authenticator")]
 pub mod direct_authenticator;
 
@@ -25,8 +30,15 @@ pub mod unix_peer_credentials_authenticator;
 #[cfg(feature = "jwt-svid-authenticator")]
 pub mod jwt_svid_authenticator;
 
+#[cfg(feature = "attestation-token-authenticator")]
+pub mod attestation_token_authenticator;
+
+#[cfg(feature = "external-authenticator")]
+pub mod external_authenticator;
+
 use crate::front::listener::ConnectionMetadata;
 use crate::utils::config::Admin;
+use crate::utils::logging::Redacted;
 use parsec_interface::operations::list_authenticators;
 use parsec_interface::requests::request::RequestAuth;
 use parsec_interface::requests::{AuthType, Result};
@@ -47,7 +59,8 @@ impl fmt::Display for ApplicationIdentity {
         write!(
             f,
             "ApplicationIdentity: [name=\"{}\", authenticator_id=\"{}\"]",
-            self.name, self.authenticator_id
+            Redacted(&self.name),
+            self.authenticator_id
         )
     }
 }
@@ -0,0 +1,363 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Isolation of tenants
+//!
+//! A tenant groups the applications of one orchestrator sharing the service with others. Once
+//! authenticated, the name of an application of a tenant is put in the namespace of the tenant,
+//! `<tenant>/<application>`, so that the Key Info Managers keep its keys apart from the ones of
+//! the other tenants, even for applications of the same name. A tenant can also be restricted to
+//! some authenticators and providers, and have a quota on the storage used by all its
+//! applications.
+//!
+//! Applications of a tenant never get administrator rights, which apply to the whole service.
+//! Applications outside of any tenant are served as without tenants, unless their name could be
+//! mistaken for one in the namespace of a tenant.
+use super::Application;
+use super::ApplicationIdentity;
+use crate::utils::config::TenantConfig;
+use crate::utils::logging::Redacted;
+use log::{error, warn};
+use parsec_interface::requests::{AuthType, ProviderId, ResponseStatus, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+
+/// Separator between the name of the tenant and the one of the application
+const SEPARATOR: char = '/';
+
+/// Namespace of an application name, if it has one.
+pub fn namespace(application_name: &str) -> Option<&str> {
+    application_name
+        .split_once(SEPARATOR)
+        .map(|(namespace, _)| namespace)
+}
+
+#[derive(Debug)]
+struct Tenant {
+    name: String,
+    applications: Vec<String>,
+    authenticators: Option<HashSet<AuthType>>,
+    providers: Option<HashSet<ProviderId>>,
+}
+
+impl Tenant {
+    fn contains(&self, application_name: &str) -> bool {
+        self.applications
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => application_name.starts_with(prefix),
+                None => application_name == pattern,
+            })
+    }
+}
+
+/// Tenants of the service
+#[derive(Debug)]
+pub struct Tenants {
+    tenants: Vec<Tenant>,
+}
+
+fn auth_type(name: &str) -> Option<AuthType> {
+    match name {
+        "Direct" => Some(AuthType::Direct),
+        "UnixPeerCredentials" => Some(AuthType::UnixPeerCredentials),
+        "JwtSvid" => Some(AuthType::JwtSvid),
+        "AttestationToken" => Some(AuthType::Jwt),
+        _ => None,
+    }
+}
+
+impl Tenants {
+    /// Create the tenants of the configuration, given the IDs of the providers by name.
+    pub fn new(
+        config: &BTreeMap<String, TenantConfig>,
+        providers: &HashMap<String, ProviderId>,
+    ) -> std::io::Result<Self> {
+        // In alphabetical order, so that an application matching several tenants always gets the
+        // same one.
+        let mut tenants = Vec::with_capacity(config.len());
+        for (name, tenant) in config {
+            if name.is_empty() || name.contains(SEPARATOR) {
+                error!(
+                    "Tenant names must not be empty nor contain \"{}\": \"{}\".",
+                    SEPARATOR, name
+                );
+                return Err(Error::new(ErrorKind::InvalidData, "invalid tenant name"));
+            }
+            let authenticators = match &tenant.authenticators {
+                Some(names) => Some(
+                    names
+                        .iter()
+                        .map(|auth| {
+                            auth_type(auth).ok_or_else(|| {
+                                error!("Unknown authenticator \"{}\" in tenant {}.", auth, name);
+                                Error::new(ErrorKind::InvalidData, "unknown tenant authenticator")
+                            })
+                        })
+                        .collect::<std::io::Result<_>>()?,
+                ),
+                None => None,
+            };
+            let providers = match &tenant.providers {
+                Some(names) => Some(
+                    names
+                        .iter()
+                        .map(|provider| {
+                            providers.get(provider).copied().ok_or_else(|| {
+                                error!("Unknown provider \"{}\" in tenant {}.", provider, name);
+                                Error::new(ErrorKind::InvalidData, "unknown tenant provider")
+                            })
+                        })
+                        .collect::<std::io::Result<_>>()?,
+                ),
+                None => None,
+            };
+            tenants.push(Tenant {
+                name: name.clone(),
+                applications: tenant.applications.clone(),
+                authenticators,
+                providers,
+            });
+        }
+        Ok(Tenants { tenants })
+    }
+
+    /// Put an authenticated application in the namespace of its tenant, checking that the tenant
+    /// can send the request with this authenticator and to this provider.
+    pub fn admit(&self, app: Application, provider: ProviderId) -> Result<Application> {
+        let identity = app.identity();
+        let tenant = match self.tenants.iter().find(|t| t.contains(identity.name())) {
+            Some(tenant) => tenant,
+            None => {
+                let namespace = namespace(identity.name());
+                if self
+                    .tenants
+                    .iter()
+                    .any(|t| Some(t.name.as_str()) == namespace)
+                {
+                    warn!(
+                        "Application \"{}\" is outside of tenant {} but has a name in its namespace.",
+                        Redacted(identity.name()),
+                        namespace.unwrap_or_default()
+                    );
+                    return Err(ResponseStatus::AuthenticationError);
+                }
+                return Ok(app);
+            }
+        };
+        if let Some(authenticators) = &tenant.authenticators {
+            if !authenticators.contains(identity.authenticator_id()) {
+                warn!(
+                    "Tenant {} cannot use the {}.",
+                    tenant.name,
+                    identity.authenticator_id()
+                );
+                return Err(ResponseStatus::AuthenticatorNotRegistered);
+            }
+        }
+        if let Some(providers) = &tenant.providers {
+            if provider != ProviderId::Core && !providers.contains(&provider) {
+                warn!(
+                    "Tenant {} cannot use the {} provider.",
+                    tenant.name, provider
+                );
+                return Err(ResponseStatus::PsaErrorNotPermitted);
+            }
+        }
+        Ok(Application::new(
+            ApplicationIdentity::new(
+                format!("{}{}{}", tenant.name, SEPARATOR, identity.name()),
+                *identity.authenticator_id(),
+            ),
+            false,
+        ))
+    }
+
+    /// Storage quotas of the tenants, by namespace.
+    pub fn quotas(config: &BTreeMap<String, TenantConfig>) -> HashMap<String, usize> {
+        config
+            .iter()
+            .filter_map(|(name, tenant)| Some((name.clone(), tenant.key_storage_quota?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tenant(applications: &[&str], providers: Option<Vec<String>>) -> TenantConfig {
+        TenantConfig {
+            applications: applications.iter().map(|app| app.to_string()).collect(),
+            authenticators: Some(vec![String::from("Direct")]),
+            providers,
+            key_storage_quota: None,
+        }
+    }
+
+    fn app(name: &str, auth_type: AuthType) -> Application {
+        Application::new(ApplicationIdentity::new(name.to_string(), auth_type), true)
+    }
+
+    fn providers() -> HashMap<String, ProviderId> {
+        let mut providers = HashMap::new();
+        let _ = providers.insert(String::from("tpm"), ProviderId::Tpm);
+        providers
+    }
+
+    /// The tenants "alpha", with the applications of a SPIFFE trust domain restricted to the TPM
+    /// provider, and "beta", with a single application.
+    fn tenants() -> Tenants {
+        let mut config = BTreeMap::new();
+        let _ = config.insert(
+            String::from("alpha"),
+            tenant(&["spiffe://alpha/*"], Some(vec![String::from("tpm")])),
+        );
+        let _ = config.insert(String::from("beta"), tenant(&["app"], None));
+        Tenants::new(&config, &providers()).unwrap()
+    }
+
+    #[test]
+    fn applications_are_put_in_their_namespace() {
+        let admitted = tenants()
+            .admit(app("app", AuthType::Direct), ProviderId::MbedCrypto)
+            .unwrap();
+        assert_eq!(admitted.identity().name(), "beta/app");
+        assert_eq!(namespace(admitted.identity().name()), Some("beta"));
+        assert_eq!(*admitted.identity().authenticator_id(), AuthType::Direct);
+    }
+
+    #[test]
+    fn applications_are_matched_by_prefix() {
+        let tenants = tenants();
+        let admitted = tenants
+            .admit(app("spiffe://alpha/web", AuthType::Direct), ProviderId::Tpm)
+            .unwrap();
+        assert_eq!(admitted.identity().name(), "alpha/spiffe://alpha/web");
+        let outside = tenants
+            .admit(
+                app("spiffe://alphabet/web", AuthType::Direct),
+                ProviderId::Tpm,
+            )
+            .unwrap();
+        assert_eq!(outside.identity().name(), "spiffe://alphabet/web");
+    }
+
+    #[test]
+    fn first_tenant_in_alphabetical_order_wins() {
+        let mut config = BTreeMap::new();
+        let _ = config.insert(String::from("zulu"), tenant(&["app"], None));
+        let _ = config.insert(String::from("bravo"), tenant(&["ap*"], None));
+        let admitted = Tenants::new(&config, &providers())
+            .unwrap()
+            .admit(app("app", AuthType::Direct), ProviderId::Core)
+            .unwrap();
+        assert_eq!(admitted.identity().name(), "bravo/app");
+    }
+
+    #[test]
+    fn tenants_are_never_administrators() {
+        let admitted = tenants()
+            .admit(app("app", AuthType::Direct), ProviderId::Core)
+            .unwrap();
+        assert!(!admitted.is_admin());
+    }
+
+    #[test]
+    fn providers_are_restricted() {
+        let tenants = tenants();
+        assert_eq!(
+            tenants
+                .admit(
+                    app("spiffe://alpha/web", AuthType::Direct),
+                    ProviderId::Pkcs11
+                )
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        // The core provider is always available.
+        assert!(tenants
+            .admit(
+                app("spiffe://alpha/web", AuthType::Direct),
+                ProviderId::Core
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn authenticators_are_restricted() {
+        assert_eq!(
+            tenants()
+                .admit(app("app", AuthType::UnixPeerCredentials), ProviderId::Core)
+                .unwrap_err(),
+            ResponseStatus::AuthenticatorNotRegistered
+        );
+    }
+
+    #[test]
+    fn names_in_a_tenant_namespace_are_refused_outside_of_it() {
+        assert_eq!(
+            tenants()
+                .admit(app("beta/app", AuthType::Direct), ProviderId::Core)
+                .unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
+    #[test]
+    fn other_applications_are_served_as_without_tenants() {
+        let tenants = tenants();
+        let outside = tenants
+            .admit(app("other", AuthType::Direct), ProviderId::Core)
+            .unwrap();
+        assert_eq!(outside.identity().name(), "other");
+        assert!(outside.is_admin());
+        // Namespaces of no tenant can be used.
+        let outside = tenants
+            .admit(app("gamma/app", AuthType::Direct), ProviderId::Core)
+            .unwrap();
+        assert_eq!(outside.identity().name(), "gamma/app");
+    }
+
+    #[test]
+    fn invalid_tenant_names_are_refused() {
+        for name in &["a/b", ""] {
+            let mut config = BTreeMap::new();
+            let _ = config.insert(name.to_string(), tenant(&["x"], None));
+            assert_eq!(
+                Tenants::new(&config, &providers()).unwrap_err().kind(),
+                ErrorKind::InvalidData
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_authenticators_are_refused() {
+        let mut config = BTreeMap::new();
+        let mut alpha = tenant(&["x"], None);
+        alpha.authenticators = Some(vec![String::from("Kerberos")]);
+        let _ = config.insert(String::from("alpha"), alpha);
+        assert!(Tenants::new(&config, &providers()).is_err());
+    }
+
+    #[test]
+    fn unknown_providers_are_refused() {
+        let mut config = BTreeMap::new();
+        let _ = config.insert(
+            String::from("alpha"),
+            tenant(&["x"], Some(vec![String::from("pkcs11")])),
+        );
+        assert!(Tenants::new(&config, &providers()).is_err());
+    }
+
+    #[test]
+    fn quotas_are_given_by_namespace() {
+        let mut config = BTreeMap::new();
+        let mut alpha = tenant(&["x"], None);
+        alpha.key_storage_quota = Some(1024);
+        let _ = config.insert(String::from("alpha"), alpha);
+        let _ = config.insert(String::from("beta"), tenant(&["y"], None));
+        let quotas = Tenants::quotas(&config);
+        assert_eq!(quotas.len(), 1);
+        assert_eq!(quotas.get("alpha"), Some(&1024));
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Container identity of the connecting processes
+//!
+//! Processes in different containers often share the same UID, 0 most of the time. The container
+//! of a process is found from its PID, either in its cgroup path, where the container runtimes
+//! (Docker, containerd, CRI-O, Podman) put the container ID, or by a program given the PID, for
+//! deployments where the cgroup path is not enough, for example to ask the runtime for the name of
+//! the pod.
+//!
+//! Only the cgroups created by the runtimes are trusted, as described in [`cgroup`]: the
+//! containers of rootless runtimes are identified by the program.
+//!
+//! The PID is the one of the process which opened the connection: it is resolved when each request
+//! is authenticated. The output of the program is cached for each process, identified by its PID
+//! and start time so that a reused PID is resolved again.
+use crate::utils::cgroup;
+use crate::utils::config::ContainerIdentityConfig;
+use log::error;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time after which a resolver program is killed, if not configured (in milliseconds)
+const DEFAULT_RESOLVER_TIMEOUT: u64 = 1000;
+
+/// Maximum length of the output of the resolver program read
+const MAX_RESOLVER_OUTPUT: u64 = 4096;
+
+/// Number of processes whose container identity is cached, emptied once full
+const CACHE_SIZE: usize = 1024;
+
+/// Container identities of the processes, by PID and start time
+type ExecCache = Arc<Mutex<HashMap<(i32, u64), Option<String>>>>;
+
+/// Way of finding the container of a process
+#[derive(Clone, Debug)]
+pub enum ContainerResolver {
+    /// From the cgroup path of the process
+    Cgroup,
+    /// From the output of a program
+    Exec {
+        program: String,
+        args: Vec<String>,
+        timeout: Duration,
+        cache: ExecCache,
+    },
+}
+
+impl ContainerResolver {
+    /// Create the resolver of the configuration
+    pub fn new(config: &ContainerIdentityConfig) -> Self {
+        match config {
+            ContainerIdentityConfig::Cgroup => ContainerResolver::Cgroup,
+            ContainerIdentityConfig::Exec {
+                program,
+                args,
+                timeout,
+            } => ContainerResolver::Exec {
+                program: program.clone(),
+                args: args.clone().unwrap_or_default(),
+                timeout: Duration::from_millis(timeout.unwrap_or(DEFAULT_RESOLVER_TIMEOUT)),
+                cache: Default::default(),
+            },
+        }
+    }
+
+    /// Identity of the container of the process, `None` if it is not in a container
+    pub fn resolve(&self, pid: i32) -> Result<Option<String>> {
+        match self {
+            ContainerResolver::Cgroup => cgroup::container_id(pid),
+            ContainerResolver::Exec {
+                program,
+                args,
+                timeout,
+                cache,
+            } => {
+                let process = (pid, start_time(pid)?);
+                if let Some(identity) = cache.lock().expect("Cache lock poisoned").get(&process) {
+                    return Ok(identity.clone());
+                }
+                let identity = exec(program, args, pid, *timeout)?;
+                let mut cache = cache.lock().expect("Cache lock poisoned");
+                if cache.len() >= CACHE_SIZE {
+                    cache.clear();
+                }
+                let _ = cache.insert(process, identity.clone());
+                Ok(identity)
+            }
+        }
+    }
+}
+
+/// Start time of the process, in clock ticks after the boot
+fn start_time(pid: i32) -> Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // The name of the process, in parentheses, can contain spaces: the start time is the 20th
+    // field after it.
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(19))
+        .and_then(|start_time| start_time.parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed process status"))
+}
+
+/// First line of the output of the resolver program, `None` if empty
+///
+/// The output is read while the program runs, so that it does not block once the pipe is full.
+fn exec(program: &str, args: &[String], pid: i32, timeout: Duration) -> Result<Option<String>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .arg(pid.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let start = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        let _ = thread::spawn(move || {
+            let mut output = String::new();
+            let result = stdout
+                .take(MAX_RESOLVER_OUTPUT)
+                .read_to_string(&mut output)
+                .map(|_| output);
+            // The receiver is gone if the program timed out.
+            let _ = sender.send(result);
+        });
+    }
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            error!("The container resolver was killed after the timeout.");
+            return Err(Error::new(ErrorKind::TimedOut, "killed after the timeout"));
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    if !status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("the container resolver exited with {}", status),
+        ));
+    }
+    // Processes started by the program could still hold the pipe open.
+    let output = receiver
+        .recv_timeout(timeout.saturating_sub(start.elapsed()))
+        .map_err(|_| {
+            error!("The output of the container resolver was not closed before the timeout.");
+            Error::new(ErrorKind::TimedOut, "output not closed before the timeout")
+        })??;
+    Ok(output
+        .lines()
+        .next()
+        .map(str::trim)
+        .filter(|identity| !identity.is_empty())
+        .map(String::from))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolver(script: &str, timeout: u64) -> ContainerResolver {
+        ContainerResolver::new(&ContainerIdentityConfig::Exec {
+            program: String::from("sh"),
+            args: Some(vec![String::from("-c"), String::from(script)]),
+            timeout: Some(timeout),
+        })
+    }
+
+    #[test]
+    fn first_line_of_the_resolver_is_the_identity() {
+        let resolver = resolver("echo \" pod-$0 \"; echo other", 1000);
+        let pid = std::process::id() as i32;
+        assert_eq!(resolver.resolve(pid).unwrap(), Some(format!("pod-{}", pid)));
+    }
+
+    #[test]
+    fn empty_output_is_no_container() {
+        let resolver = resolver("true", 1000);
+        assert_eq!(resolver.resolve(std::process::id() as i32).unwrap(), None);
+    }
+
+    #[test]
+    fn failed_resolvers_fail() {
+        let resolver = resolver("echo pod; exit 1", 1000);
+        assert!(resolver.resolve(std::process::id() as i32).is_err());
+    }
+
+    #[test]
+    fn slow_resolvers_are_killed() {
+        let resolver = resolver("sleep 5", 50);
+        let start = Instant::now();
+        assert_eq!(
+            resolver
+                .resolve(std::process::id() as i32)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::TimedOut
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn long_output_does_not_block_the_resolver() {
+        // More than the capacity of a pipe, written before exiting.
+        let resolver = resolver("echo pod; head -c 200000 /dev/zero; exit 0", 2000);
+        assert_eq!(
+            resolver.resolve(std::process::id() as i32).unwrap(),
+            Some(String::from("pod"))
+        );
+    }
+
+    #[test]
+    fn identities_are_cached_per_process() {
+        let directory = tempfile::tempdir().unwrap();
+        let calls = directory.path().join("calls");
+        let resolver = resolver(&format!("echo >> {}; echo pod", calls.display()), 1000);
+        let pid = std::process::id() as i32;
+        assert_eq!(resolver.resolve(pid).unwrap(), Some(String::from("pod")));
+        assert_eq!(resolver.resolve(pid).unwrap(), Some(String::from("pod")));
+        assert_eq!(fs::read_to_string(&calls).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn start_time_of_the_process() {
+        let pid = std::process::id() as i32;
+        assert_eq!(start_time(pid).unwrap(), start_time(pid).unwrap());
+        assert!(start_time(-1).is_err());
+    }
+}
@@ -7,11 +7,19 @@
 //! peer credentials also allow us to access the effective Unix group ID (GID) of the connecting
 //! process, although this information is currently unused.
 //!
-//! Currently, the stringified UID is used as the application name.
+//! Currently, the stringified UID is used as the application name. On systems with SELinux or
+//! AppArmor, processes can instead be identified by their security context: a process whose
+//! context is listed is authenticated as the application given for it, on top of the UID check.
+//! Processes running in containers can also be identified by their container, see the
+//! [`container`] module.
+
+mod container;
 
 use super::{AdminList, Application, ApplicationIdentity, Authenticate};
 use crate::front::listener::ConnectionMetadata;
-use crate::utils::config::Admin;
+use crate::utils::config::{Admin, ContainerIdentityConfig, SecurityContextConfig};
+use crate::utils::logging::Redacted;
+use container::ContainerResolver;
 use log::error;
 use parsec_interface::operations::list_authenticators;
 use parsec_interface::requests::request::RequestAuth;
@@ -21,9 +29,12 @@ use parsec_interface::secrecy::ExposeSecret;
 use std::convert::TryInto;
 
 /// Unix peer credentials authenticator.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct UnixPeerCredentialsAuthenticator {
     admins: AdminList,
+    security_contexts: Vec<SecurityContextConfig>,
+    require_security_context: bool,
+    container_resolver: Option<ContainerResolver>,
 }
 
 impl UnixPeerCredentialsAuthenticator {
@@ -31,7 +42,103 @@ impl UnixPeerCredentialsAuthenticator {
     pub fn new(admins: Vec<Admin>) -> Self {
         UnixPeerCredentialsAuthenticator {
             admins: admins.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Identify the processes in the given security contexts as their application, and reject
+    /// the processes in no listed context if `required`.
+    ///
+    /// The application names can not be numbers, which are the names of the applications
+    /// identified by their UID.
+    pub fn with_security_contexts(
+        mut self,
+        security_contexts: &[SecurityContextConfig],
+        required: bool,
+    ) -> std::io::Result<Self> {
+        for security_context in security_contexts {
+            if security_context.context().is_empty() || security_context.context() == "*" {
+                error!("A security context to identify an application must not be empty.");
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "empty security context",
+                ));
+            }
+            if security_context.application().parse::<u32>().is_ok() {
+                error!(
+                    "The application \"{}\" of security context \"{}\" is a UID.",
+                    Redacted(security_context.application()),
+                    security_context.context()
+                );
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "security context application named as a UID",
+                ));
+            }
         }
+        self.security_contexts = security_contexts.to_vec();
+        self.require_security_context = required;
+        Ok(self)
+    }
+
+    /// Identify the processes running in a container as their container, instead of their UID.
+    pub fn with_container_identity(mut self, config: &ContainerIdentityConfig) -> Self {
+        self.container_resolver = Some(ContainerResolver::new(config));
+        self
+    }
+
+    /// Name of the application of the container of the process, if it runs in one
+    fn container_application(&self, pid: Option<i32>) -> Result<Option<String>> {
+        let resolver = match &self.container_resolver {
+            Some(resolver) => resolver,
+            None => return Ok(None),
+        };
+        // Without the PID, the process could be in any container.
+        let pid = pid.ok_or_else(|| {
+            error!("The PID of the process is needed to find its container.");
+            ResponseStatus::AuthenticationError
+        })?;
+        let identity = resolver.resolve(pid).map_err(|err| {
+            format_error!("Failed to find the container of the process", err);
+            ResponseStatus::AuthenticationError
+        })?;
+        match identity {
+            Some(identity) if identity.parse::<u32>().is_ok() => {
+                error!(
+                    "The container identity \"{}\" is a UID.",
+                    Redacted(identity)
+                );
+                Err(ResponseStatus::AuthenticationError)
+            }
+            identity => Ok(identity),
+        }
+    }
+
+    /// Application of the first listed context matching the one of the peer
+    fn security_context_application(&self, security_context: &str) -> Option<&str> {
+        let security_context = confinement(security_context);
+        self.security_contexts
+            .iter()
+            .find(|listed| match listed.context().strip_suffix('*') {
+                Some(prefix) => security_context.starts_with(prefix),
+                None => security_context == listed.context(),
+            })
+            .map(SecurityContextConfig::application)
+    }
+}
+
+/// Modes of the AppArmor profiles, as given at the end of their labels
+const APPARMOR_MODES: &[&str] = &["enforce", "complain", "kill", "unconfined", "user", "mixed"];
+
+/// Confinement given by a security context: the profile of an AppArmor label, `profile (mode)`,
+/// or the whole context for the other security modules, such as the SELinux ones.
+fn confinement(security_context: &str) -> &str {
+    match security_context
+        .strip_suffix(')')
+        .and_then(|label| label.rsplit_once(" ("))
+    {
+        Some((profile, mode)) if !profile.is_empty() && APPARMOR_MODES.contains(&mode) => profile,
+        _ => security_context,
     }
 }
 
@@ -76,8 +183,13 @@ impl Authenticate for UnixPeerCredentialsAuthenticator {
         })?;
 
         #[allow(unreachable_patterns)]
-        let (uid, _gid, _pid) = match meta {
-            ConnectionMetadata::UnixPeerCredentials { uid, gid, pid } => (uid, gid, pid),
+        let (uid, _gid, pid, security_context) = match meta {
+            ConnectionMetadata::UnixPeerCredentials {
+                uid,
+                gid,
+                pid,
+                security_context,
+            } => (uid, gid, pid, security_context),
             _ => {
                 error!("Wrong metadata type given to Unix peer credentials authenticator.");
                 return Err(ResponseStatus::AuthenticationError);
@@ -87,7 +199,22 @@ impl Authenticate for UnixPeerCredentialsAuthenticator {
         // Authentication is successful if the _actual_ UID from the Unix peer credentials equals
         // the self-declared UID in the authentication request.
         if uid == expected_uid {
-            let app_name = uid.to_string();
+            let app_name = match security_context
+                .as_deref()
+                .and_then(|context| self.security_context_application(context))
+            {
+                Some(application) => application.to_string(),
+                None if self.require_security_context => {
+                    error!(
+                        "The security context of the process ({}) is not one of the configured ones.",
+                        security_context.as_deref().unwrap_or("none")
+                    );
+                    return Err(ResponseStatus::AuthenticationError);
+                }
+                None => self
+                    .container_application(pid)?
+                    .unwrap_or_else(|| uid.to_string()),
+            };
             let is_admin = self.admins.is_admin(&app_name);
             Ok(Application {
                 identity: ApplicationIdentity {
@@ -106,9 +233,10 @@ impl Authenticate for UnixPeerCredentialsAuthenticator {
 #[cfg(test)]
 mod test {
     use super::super::Authenticate;
-    use super::UnixPeerCredentialsAuthenticator;
+    use super::{confinement, UnixPeerCredentialsAuthenticator};
     use crate::front::domain_socket::peer_credentials;
     use crate::front::listener::ConnectionMetadata;
+    use crate::utils::config::SecurityContextConfig;
     use libc::{getuid, uid_t};
     use parsec_interface::requests::request::RequestAuth;
     use parsec_interface::requests::ResponseStatus;
@@ -129,6 +257,7 @@ mod test {
 
         let authenticator = UnixPeerCredentialsAuthenticator {
             admins: Default::default(),
+            ..Default::default()
         };
 
         let req_auth_data = cred_a.uid.to_le_bytes().to_vec();
@@ -137,6 +266,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: None,
+            security_context: None,
         });
 
         let application = authenticator
@@ -162,6 +292,7 @@ mod test {
 
         let authenticator = UnixPeerCredentialsAuthenticator {
             admins: Default::default(),
+            ..Default::default()
         };
 
         let wrong_uid = cred_a.uid + 1;
@@ -171,6 +302,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: cred_a.pid,
+            security_context: None,
         });
 
         let auth_result = authenticator
@@ -192,6 +324,7 @@ mod test {
 
         let authenticator = UnixPeerCredentialsAuthenticator {
             admins: Default::default(),
+            ..Default::default()
         };
 
         let garbage_data = rand::thread_rng().gen::<[u8; 32]>().to_vec();
@@ -200,6 +333,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: cred_a.pid,
+            security_context: None,
         });
 
         let auth_result = authenticator
@@ -212,6 +346,7 @@ mod test {
     fn unsuccessful_authentication_no_metadata() {
         let authenticator = UnixPeerCredentialsAuthenticator {
             admins: Default::default(),
+            ..Default::default()
         };
         let req_auth = RequestAuth::new("secret".into());
 
@@ -235,6 +370,7 @@ mod test {
         let admin = toml::from_str(&format!("name = '{}'", current_uid)).unwrap();
         let authenticator = UnixPeerCredentialsAuthenticator {
             admins: vec![admin].into(),
+            ..Default::default()
         };
 
         let req_auth_data = cred_a.uid.to_le_bytes().to_vec();
@@ -243,6 +379,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: None,
+            security_context: None,
         });
 
         let application = authenticator
@@ -253,6 +390,117 @@ mod test {
         assert!(application.is_admin);
     }
 
+    #[test]
+    fn security_context_authentication() {
+        let uid: uid_t = unsafe { getuid() };
+        let security_contexts: Vec<SecurityContextConfig> = vec![
+            toml::from_str("context = 'system_u:system_r:httpd_t:s0'\napplication = 'httpd'")
+                .unwrap(),
+            toml::from_str("context = '/usr/bin/agent*'\napplication = 'agent'").unwrap(),
+        ];
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new())
+            .with_security_contexts(&security_contexts, true)
+            .unwrap();
+        let authenticate = |security_context: Option<&str>| {
+            authenticator.authenticate(
+                &RequestAuth::new(uid.to_le_bytes().to_vec()),
+                Some(ConnectionMetadata::UnixPeerCredentials {
+                    uid,
+                    gid: 0,
+                    pid: None,
+                    security_context: security_context.map(String::from),
+                }),
+            )
+        };
+
+        let application = authenticate(Some("system_u:system_r:httpd_t:s0")).unwrap();
+        assert_eq!(application.identity.name, "httpd");
+        let application = authenticate(Some("/usr/bin/agent-v2 (enforce)")).unwrap();
+        assert_eq!(application.identity.name, "agent");
+        assert_eq!(
+            authenticate(Some("unconfined")).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+        assert_eq!(
+            authenticate(None).unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+
+        // Without the requirement, the other processes keep their UID as application name.
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new())
+            .with_security_contexts(&security_contexts, false)
+            .unwrap();
+        let application = authenticator
+            .authenticate(
+                &RequestAuth::new(uid.to_le_bytes().to_vec()),
+                Some(ConnectionMetadata::UnixPeerCredentials {
+                    uid,
+                    gid: 0,
+                    pid: None,
+                    security_context: Some(String::from("unconfined")),
+                }),
+            )
+            .unwrap();
+        assert_eq!(application.identity.name, uid.to_string());
+
+        let uid_named: Vec<SecurityContextConfig> =
+            vec![toml::from_str("context = 'unconfined'\napplication = '0'").unwrap()];
+        assert!(UnixPeerCredentialsAuthenticator::new(Vec::new())
+            .with_security_contexts(&uid_named, false)
+            .is_err());
+    }
+
+    #[test]
+    fn apparmor_label_confinement() {
+        assert_eq!(confinement("/usr/bin/agent (enforce)"), "/usr/bin/agent");
+        assert_eq!(confinement("/usr/bin/agent (complain)"), "/usr/bin/agent");
+        assert_eq!(
+            confinement("/usr/bin/agent//&docker-default (mixed)"),
+            "/usr/bin/agent//&docker-default"
+        );
+        assert_eq!(confinement("unconfined"), "unconfined");
+        // Only the modes of AppArmor end its labels.
+        assert_eq!(confinement("/usr/bin/agent (v2)"), "/usr/bin/agent (v2)");
+        assert_eq!(confinement(" (enforce)"), " (enforce)");
+        assert_eq!(
+            confinement("system_u:system_r:httpd_t:s0"),
+            "system_u:system_r:httpd_t:s0"
+        );
+    }
+
+    #[test]
+    fn apparmor_profile_modes_authentication() {
+        let uid: uid_t = unsafe { getuid() };
+        let security_contexts: Vec<SecurityContextConfig> =
+            vec![toml::from_str("context = '/usr/bin/agent'\napplication = 'agent'").unwrap()];
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new())
+            .with_security_contexts(&security_contexts, true)
+            .unwrap();
+        let authenticate = |security_context: &str| {
+            authenticator.authenticate(
+                &RequestAuth::new(uid.to_le_bytes().to_vec()),
+                Some(ConnectionMetadata::UnixPeerCredentials {
+                    uid,
+                    gid: 0,
+                    pid: None,
+                    security_context: Some(String::from(security_context)),
+                }),
+            )
+        };
+
+        for label in &["/usr/bin/agent (enforce)", "/usr/bin/agent (complain)"] {
+            assert_eq!(authenticate(label).unwrap().identity.name, "agent");
+        }
+        assert_eq!(
+            authenticate("/usr/bin/agent (v2)").unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+        assert_eq!(
+            authenticate("/usr/bin/agent-v2 (enforce)").unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+
     #[test]
     fn unsuccessful_authentication_wrong_metadata() {
         // TODO(new_metadata_variant): this test needs implementing when we have more than one
@@ -0,0 +1,263 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Size limits and tag layouts of the AEAD operations
+//!
+//! In front of each provider, the additional data and the plaintext of the PsaAeadEncrypt and
+//! PsaAeadDecrypt requests can be bounded, whatever the provider would accept: AEAD operations are
+//! done in a single call, their whole input being held in memory by the service and the backend.
+//! The plaintext of a decryption is taken to be the ciphertext without its tag. Requests over a
+//! limit are refused with `PsaErrorInvalidArgument`.
+//!
+//! The PSA ciphertexts end with their tag, as in RFC 5116. With the `aead-detached-tag` feature,
+//! formats carrying the tag in a field of its own, before the encrypted data, can use the detached
+//! tag layout instead, by suffixing the key name with `#detached-tag`: the ciphertext returned by
+//! PsaAeadEncrypt and the one given to PsaAeadDecrypt then start with the tag, so that it can be
+//! split from the encrypted data at the tag length of the algorithm. Without the feature, the
+//! suffix is part of the key name, as any other.
+use crate::utils::config::AeadLimitsConfig;
+use crate::utils::key_suffixes;
+use log::{error, warn};
+use parsec_interface::operations::psa_algorithm::Aead;
+use parsec_interface::requests::{ProviderId, ResponseStatus, Result};
+
+/// Length of the tags of the AEAD algorithms with a default length tag
+const DEFAULT_TAG_LENGTH: usize = 16;
+
+/// Limits of the sizes of the AEAD requests to a provider
+#[derive(Debug, Copy, Clone)]
+pub struct AeadLimits {
+    provider_id: ProviderId,
+    max_additional_data_size: Option<usize>,
+    max_plaintext_size: Option<usize>,
+}
+
+impl AeadLimits {
+    /// Create the limits of the configuration, for the given provider.
+    pub fn new(config: &AeadLimitsConfig, provider_id: ProviderId) -> Self {
+        AeadLimits {
+            provider_id,
+            max_additional_data_size: config.max_additional_data_size,
+            max_plaintext_size: config.max_plaintext_size,
+        }
+    }
+
+    /// Check the sizes of the additional data and of the plaintext of a request.
+    pub fn check(&self, additional_data_size: usize, plaintext_size: usize) -> Result<()> {
+        for (name, size, max_size) in [
+            (
+                "additional data",
+                additional_data_size,
+                self.max_additional_data_size,
+            ),
+            ("plaintext", plaintext_size, self.max_plaintext_size),
+        ]
+        .iter()
+        {
+            if let Some(max_size) = max_size {
+                if size > max_size {
+                    warn!(
+                        "AEAD request to {} refused: {} bytes of {}, more than the limit of {}.",
+                        self.provider_id, size, name, max_size
+                    );
+                    return Err(ResponseStatus::PsaErrorInvalidArgument);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove the detached tag suffix from the key name, returning whether it had it. Key names are
+/// left as they are without the `aead-detached-tag` feature.
+pub fn strip_detached_tag(key_name: &mut String) -> bool {
+    cfg!(feature = "aead-detached-tag")
+        && key_suffixes::strip_flag(key_name, key_suffixes::DETACHED_TAG)
+}
+
+/// Length of the tags of the algorithm
+pub fn tag_length(alg: Aead) -> usize {
+    match alg {
+        Aead::AeadWithDefaultLengthTag(_) => DEFAULT_TAG_LENGTH,
+        Aead::AeadWithShortenedTag { tag_length, .. } => tag_length,
+    }
+}
+
+/// Move the tag at the end of the ciphertext to its start.
+pub fn detach_tag(alg: Aead, ciphertext: &mut [u8]) -> Result<()> {
+    let tag_length = tag_length(alg);
+    if ciphertext.len() < tag_length {
+        error!("The ciphertext is shorter than the tag of {:?}.", alg);
+        return Err(ResponseStatus::PsaErrorGenericError);
+    }
+    ciphertext.rotate_right(tag_length);
+    Ok(())
+}
+
+/// Move the tag at the start of the ciphertext to its end.
+pub fn attach_tag(alg: Aead, ciphertext: &mut [u8]) -> Result<()> {
+    let tag_length = tag_length(alg);
+    if ciphertext.len() < tag_length {
+        error!("The ciphertext is shorter than the tag of {:?}.", alg);
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    ciphertext.rotate_left(tag_length);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::psa_algorithm::AeadWithDefaultLengthTag;
+
+    /// Ciphertext and tag of the AES-128-GCM test case 2 of "The Galois/Counter Mode of Operation",
+    /// 16 zero bytes encrypted with a zero key and IV
+    const GCM_CIPHERTEXT: [u8; 16] = [
+        0x03, 0x88, 0xda, 0xce, 0x60, 0xb6, 0xa3, 0x92, 0xf3, 0x28, 0xc2, 0xb9, 0x71, 0xb2, 0xfe,
+        0x78,
+    ];
+    const GCM_TAG: [u8; 16] = [
+        0xab, 0x6e, 0x47, 0xd4, 0x2c, 0xec, 0x13, 0xbd, 0xf5, 0x3a, 0x67, 0xb2, 0x12, 0x57, 0xbd,
+        0xdf,
+    ];
+
+    const GCM: Aead = Aead::AeadWithDefaultLengthTag(AeadWithDefaultLengthTag::Gcm);
+
+    fn limits(config: &str) -> AeadLimits {
+        AeadLimits::new(&toml::from_str(config).unwrap(), ProviderId::MbedCrypto)
+    }
+
+    fn shortened(aead_alg: AeadWithDefaultLengthTag, tag_length: usize) -> Aead {
+        Aead::AeadWithShortenedTag {
+            aead_alg,
+            tag_length,
+        }
+    }
+
+    #[test]
+    fn additional_data_limit() {
+        let limits = limits("max_additional_data_size = 16");
+        assert_eq!(limits.check(16, 1 << 20), Ok(()));
+        assert_eq!(
+            limits.check(17, 0),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn plaintext_limit() {
+        let limits = limits("max_plaintext_size = 1024");
+        assert_eq!(limits.check(1 << 20, 1024), Ok(()));
+        assert_eq!(
+            limits.check(0, 1025),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn both_limits_apply() {
+        let limits = limits("max_additional_data_size = 16\nmax_plaintext_size = 1024");
+        assert_eq!(limits.check(16, 1024), Ok(()));
+        for (additional_data_size, plaintext_size) in [(17, 1024), (16, 1025), (17, 1025)] {
+            assert_eq!(
+                limits.check(additional_data_size, plaintext_size),
+                Err(ResponseStatus::PsaErrorInvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn no_limits() {
+        let limits = limits("");
+        assert_eq!(limits.check(usize::MAX, usize::MAX), Ok(()));
+    }
+
+    #[cfg(feature = "aead-detached-tag")]
+    #[test]
+    fn strip_suffix() {
+        let mut key_name = String::from("records#detached-tag");
+        assert!(strip_detached_tag(&mut key_name));
+        assert_eq!(key_name, "records");
+        assert!(!strip_detached_tag(&mut key_name));
+        assert_eq!(key_name, "records");
+
+        let mut key_name = String::from("records#detached-tags");
+        assert!(!strip_detached_tag(&mut key_name));
+        assert_eq!(key_name, "records#detached-tags");
+    }
+
+    #[cfg(not(feature = "aead-detached-tag"))]
+    #[test]
+    fn suffix_is_kept_without_the_feature() {
+        let mut key_name = String::from("records#detached-tag");
+        assert!(!strip_detached_tag(&mut key_name));
+        assert_eq!(key_name, "records#detached-tag");
+    }
+
+    #[test]
+    fn tag_lengths() {
+        for aead_alg in [
+            AeadWithDefaultLengthTag::Ccm,
+            AeadWithDefaultLengthTag::Gcm,
+            AeadWithDefaultLengthTag::Chacha20Poly1305,
+        ] {
+            assert_eq!(tag_length(Aead::AeadWithDefaultLengthTag(aead_alg)), 16);
+            for length in [4, 8, 12] {
+                assert_eq!(tag_length(shortened(aead_alg, length)), length);
+            }
+        }
+    }
+
+    #[test]
+    fn detach_gcm_tag() {
+        let mut ciphertext = GCM_CIPHERTEXT.to_vec();
+        ciphertext.extend_from_slice(&GCM_TAG);
+        detach_tag(GCM, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext[..16], GCM_TAG);
+        assert_eq!(ciphertext[16..], GCM_CIPHERTEXT);
+
+        attach_tag(GCM, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext[..16], GCM_CIPHERTEXT);
+        assert_eq!(ciphertext[16..], GCM_TAG);
+    }
+
+    #[test]
+    fn detach_shortened_tag() {
+        // GCM tags are shortened by truncation.
+        let alg = shortened(AeadWithDefaultLengthTag::Gcm, 4);
+        let mut ciphertext = GCM_CIPHERTEXT.to_vec();
+        ciphertext.extend_from_slice(&GCM_TAG[..4]);
+        detach_tag(alg, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext[..4], GCM_TAG[..4]);
+        assert_eq!(ciphertext[4..], GCM_CIPHERTEXT);
+        attach_tag(alg, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext[..16], GCM_CIPHERTEXT);
+        assert_eq!(ciphertext[16..], GCM_TAG[..4]);
+    }
+
+    #[test]
+    fn ciphertext_of_empty_plaintext_is_the_tag() {
+        let mut ciphertext = GCM_TAG.to_vec();
+        detach_tag(GCM, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, GCM_TAG);
+        attach_tag(GCM, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, GCM_TAG);
+    }
+
+    #[test]
+    fn refuse_ciphertexts_shorter_than_the_tag() {
+        // A provider returning such a ciphertext fails, a client sending one is wrong.
+        assert_eq!(
+            detach_tag(GCM, &mut [0; 15]),
+            Err(ResponseStatus::PsaErrorGenericError)
+        );
+        assert_eq!(
+            attach_tag(GCM, &mut [0; 15]),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+        let alg = shortened(AeadWithDefaultLengthTag::Ccm, 4);
+        assert_eq!(
+            attach_tag(alg, &mut [0; 3]),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+}
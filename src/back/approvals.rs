@@ -0,0 +1,730 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Dual-control approval of sensitive operations
+//!
+//! Deployments under a two-person rule can make the configured sensitive operations wait for the
+//! approval of a second administrator: exporting a key, and administrators deleting the keys of an
+//! application with DeleteClient. Administrators can only destroy their own keys with
+//! PsaDestroyKey, which is not gated. The first time such an operation is requested, it is
+//! refused with `PsaErrorNotPermitted` and recorded as pending, under an approval ID written to the
+//! audit log. Each application has a limited number of operations pending at the same time.
+//!
+//! The names of the requests, the key name or the name of the application whose keys are deleted,
+//! then carry the approval ID behind an `#approval=` suffix, only parsed when approvals are
+//! configured:
+//! * the same request made by an administrator other than the requester approves the pending
+//!   operation, without executing it. It is answered with `APPROVED`, not with a success, and the
+//!   approval is written to the audit log. `parsec admin pending-approvals` lists the pending
+//!   operations and `parsec admin approve` sends that request;
+//! * the same request made by the requester executes the operation once it is approved.
+//!
+//! An approval is used once, and must be approved and then used within the configured time.
+//! Operations which were not approved or used in time are removed from the database.
+//!
+//! The requests, approvals and executions are stored with their time in an SQLite database so that
+//! pending approvals persist across restarts and can be audited. Every step is also written to the
+//! audit log.
+use crate::authenticators::{Application, ApplicationIdentity};
+use crate::utils::config::{ApprovalsConfig, SensitiveOperation};
+use crate::utils::key_suffixes;
+use crate::utils::logging::AUDIT_TARGET;
+use anyhow::Context;
+use log::{error, info, warn};
+use num_traits::FromPrimitive;
+use parsec_interface::requests::{AuthType, ProviderId, ResponseStatus, Result};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::convert::TryFrom;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default path of the database holding the approvals
+pub const DEFAULT_DB_PATH: &str = "/var/lib/parsec/approvals.sqlite3";
+
+/// File permissions of the database, only accessible to the service
+const FILE_PERMISSION: u32 = 0o600;
+const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+/// Number of operations an application can have waiting for approval at the same time
+const MAX_PENDING_PER_REQUESTER: u32 = 16;
+
+fn operation_code(operation: SensitiveOperation) -> i64 {
+    match operation {
+        SensitiveOperation::ExportKey => 0,
+        SensitiveOperation::ForceDestroy => 1,
+    }
+}
+
+fn operation_from_code(code: i64) -> Option<SensitiveOperation> {
+    match code {
+        0 => Some(SensitiveOperation::ExportKey),
+        1 => Some(SensitiveOperation::ForceDestroy),
+        _ => None,
+    }
+}
+
+/// Status of the requests approving the pending operation of another application, which are not
+/// executed
+pub const APPROVED: ResponseStatus = ResponseStatus::PsaErrorBadState;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Remove the approval suffix from the name, returning the approval ID it gives.
+///
+/// # Errors
+///
+/// If the approval ID is not a number, PsaErrorInvalidArgument is returned.
+pub fn strip_from(name: &mut String) -> Result<Option<u64>> {
+    key_suffixes::strip_argument(name, key_suffixes::APPROVAL)
+        .map(|id| {
+            id.parse().map_err(|_| {
+                error!("Invalid approval ID \"{}\".", id);
+                ResponseStatus::PsaErrorInvalidArgument
+            })
+        })
+        .transpose()
+}
+
+/// Outcome of a sensitive operation allowed to go on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Authorization {
+    /// The operation can be executed
+    Execute,
+    /// The request approved the pending operation of another application and must not be executed
+    Approved,
+}
+
+/// Sensitive operation waiting for the approval of a second administrator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingApproval {
+    /// ID of the approval
+    pub id: u64,
+    /// Operation requested
+    pub operation: SensitiveOperation,
+    /// Application which requested it
+    pub requester: ApplicationIdentity,
+    /// Provider the operation is made on
+    pub provider_id: ProviderId,
+    /// Key, or application whose keys are deleted, the operation is made on
+    pub target: String,
+    /// Time of the request
+    pub requested: SystemTime,
+}
+
+fn database_path(config: &ApprovalsConfig) -> PathBuf {
+    PathBuf::from(
+        config
+            .store_path
+            .clone()
+            .unwrap_or_else(|| String::from(DEFAULT_DB_PATH)),
+    )
+}
+
+fn ttl(config: &ApprovalsConfig) -> Duration {
+    config.ttl.map_or(DEFAULT_TTL, Duration::from_secs)
+}
+
+/// Operations waiting for approval in the database of the configuration, oldest first. The
+/// database is only read, for example by `parsec admin` while the service is running.
+pub fn pending(config: &ApprovalsConfig) -> anyhow::Result<Vec<PendingApproval>> {
+    let database_path = database_path(config);
+    let conn = Connection::open_with_flags(&database_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("open {:?}", database_path))?;
+    let rows = conn
+        .prepare(
+            "SELECT id, operation, authenticator_id, requester, provider_id, target, requested
+             FROM approval WHERE approved IS NULL AND requested + ?1 > ?2 ORDER BY id",
+        )?
+        .query_map(params![ttl(config).as_secs(), now()], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, u8>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, u8>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, u64>(6)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    rows.into_iter()
+        .map(
+            |(id, operation, authenticator_id, requester, provider_id, target, requested)| {
+                let corrupted = || anyhow::anyhow!("approval {} is corrupted", id);
+                Ok(PendingApproval {
+                    id,
+                    operation: operation_from_code(operation).ok_or_else(corrupted)?,
+                    requester: ApplicationIdentity::new(
+                        requester,
+                        AuthType::from_u8(authenticator_id).ok_or_else(corrupted)?,
+                    ),
+                    provider_id: ProviderId::try_from(provider_id).map_err(|_| corrupted())?,
+                    target,
+                    requested: UNIX_EPOCH + Duration::from_secs(requested),
+                })
+            },
+        )
+        .collect()
+}
+
+/// Approval of the database
+struct ApprovalRow {
+    operation: i64,
+    authenticator_id: u8,
+    requester: String,
+    provider_id: u8,
+    target: String,
+    requested: u64,
+    approved: Option<u64>,
+    executed: Option<u64>,
+}
+
+/// Approvals of the sensitive operations, stored in a database
+#[derive(Debug)]
+pub struct Approvals {
+    operations: Vec<SensitiveOperation>,
+    ttl: Duration,
+    conn: Mutex<Connection>,
+}
+
+impl Approvals {
+    /// Open the database of the approvals, creating it if it does not exist.
+    pub fn new(config: &ApprovalsConfig) -> anyhow::Result<Self> {
+        let database_path = database_path(config);
+        if let Some(directory_path) = database_path.parent() {
+            fs::create_dir_all(directory_path)
+                .with_context(|| format!("create directory {:?}", directory_path))?;
+        }
+        let conn = Connection::open(&database_path)?;
+        fs::set_permissions(&database_path, Permissions::from_mode(FILE_PERMISSION))?;
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS approval (
+                id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+                operation           INTEGER NOT NULL,
+                authenticator_id    INTEGER NOT NULL,
+                requester           TEXT NOT NULL,
+                provider_id         INTEGER NOT NULL,
+                target              TEXT NOT NULL,
+                requested           INTEGER NOT NULL,
+                approver            TEXT,
+                approved            INTEGER,
+                executed            INTEGER
+            );
+            ",
+        )?;
+
+        Ok(Approvals {
+            operations: config.operations.clone(),
+            ttl: ttl(config),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Whether the operation needs an approval
+    pub fn requires(&self, operation: SensitiveOperation) -> bool {
+        self.operations.contains(&operation)
+    }
+
+    /// Check that the operation requested by the application can go on.
+    ///
+    /// Without an approval ID, the operation is recorded as pending, if it is not already. With
+    /// one, the application either uses up the approval of its own operation, or approves, as an
+    /// administrator, the pending operation of another application.
+    ///
+    /// # Errors
+    ///
+    /// If the operation can not go on, PsaErrorNotPermitted is returned. If deleting keys is
+    /// requested by an application which is not an administrator, AdminOperation is returned. If
+    /// the application has too many operations pending, PsaErrorInsufficientStorage is returned.
+    pub fn authorize(
+        &self,
+        app: &Application,
+        operation: SensitiveOperation,
+        provider_id: ProviderId,
+        target: &str,
+        approval_id: Option<u64>,
+    ) -> Result<Authorization> {
+        if !self.requires(operation) {
+            return Ok(Authorization::Execute);
+        }
+        if operation == SensitiveOperation::ForceDestroy && !app.is_admin() {
+            return Err(ResponseStatus::AdminOperation);
+        }
+        let description = format!(
+            "{:?} of \"{}\" in provider {}",
+            operation, target, provider_id
+        );
+        let conn = self.conn.lock().expect("Approvals lock poisoned");
+        let result = match approval_id {
+            Some(id) => self.use_approval(&conn, app, operation, provider_id, target, id),
+            None => self.request(&conn, app.identity(), operation, provider_id, target),
+        };
+        let name = app.identity().name();
+        match result {
+            Ok(Ok(authorization)) => {
+                match authorization {
+                    Authorization::Execute => info!(
+                        target: AUDIT_TARGET,
+                        "Approval {:?} used by application \"{}\": {} executed.",
+                        approval_id,
+                        name,
+                        description
+                    ),
+                    Authorization::Approved => warn!(
+                        target: AUDIT_TARGET,
+                        "Application \"{}\" approved approval {:?}: {}, not executed until used by its requester.",
+                        name,
+                        approval_id,
+                        description
+                    ),
+                }
+                Ok(authorization)
+            }
+            Ok(Err((id, status))) => {
+                match (approval_id, status) {
+                    (None, ResponseStatus::PsaErrorNotPermitted) => warn!(
+                        target: AUDIT_TARGET,
+                        "Approval {} pending: {} by application \"{}\" needs the approval of another administrator.",
+                        id,
+                        description,
+                        name
+                    ),
+                    (None, _) => warn!(
+                        target: AUDIT_TARGET,
+                        "Approval of the {} by application \"{}\" refused: too many operations are pending.",
+                        description,
+                        name
+                    ),
+                    (Some(_), _) => warn!(
+                        target: AUDIT_TARGET,
+                        "Approval {} refused to application \"{}\" for the {}: it is neither an approved operation of the application, nor a pending operation of another one it can approve.",
+                        id,
+                        name,
+                        description
+                    ),
+                }
+                Err(status)
+            }
+            Err(e) => {
+                format_error!("Failed to store the approval of a sensitive operation", e);
+                Err(ResponseStatus::PsaErrorStorageFailure)
+            }
+        }
+    }
+
+    /// Record the operation as pending, if it is not already, returning its approval ID.
+    fn request(
+        &self,
+        conn: &Connection,
+        requester: &ApplicationIdentity,
+        operation: SensitiveOperation,
+        provider_id: ProviderId,
+        target: &str,
+    ) -> rusqlite::Result<std::result::Result<Authorization, (u64, ResponseStatus)>> {
+        let now = now();
+        let ttl = self.ttl.as_secs();
+        let _ = conn.execute(
+            "DELETE FROM approval WHERE executed IS NULL
+             AND ((approved IS NULL AND requested + ?1 <= ?2) OR approved + ?1 <= ?2)",
+            params![ttl, now],
+        )?;
+        let id = conn
+            .query_row(
+                "SELECT id FROM approval
+                 WHERE operation = ?1 AND authenticator_id = ?2 AND requester = ?3
+                 AND provider_id = ?4 AND target = ?5 AND approved IS NULL",
+                params![
+                    operation_code(operation),
+                    *requester.authenticator_id() as u8,
+                    requester.name(),
+                    provider_id as u8,
+                    target
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = id {
+            return Ok(Err((id, ResponseStatus::PsaErrorNotPermitted)));
+        }
+        let pending: u32 = conn.query_row(
+            "SELECT COUNT(*) FROM approval
+             WHERE authenticator_id = ?1 AND requester = ?2 AND approved IS NULL",
+            params![*requester.authenticator_id() as u8, requester.name()],
+            |row| row.get(0),
+        )?;
+        if pending >= MAX_PENDING_PER_REQUESTER {
+            return Ok(Err((0, ResponseStatus::PsaErrorInsufficientStorage)));
+        }
+        let _ = conn.execute(
+            "INSERT INTO approval
+             (operation, authenticator_id, requester, provider_id, target, requested)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                operation_code(operation),
+                *requester.authenticator_id() as u8,
+                requester.name(),
+                provider_id as u8,
+                target,
+                now
+            ],
+        )?;
+        Ok(Err((
+            conn.last_insert_rowid() as u64,
+            ResponseStatus::PsaErrorNotPermitted,
+        )))
+    }
+
+    /// Use up the approval of the operation of the application, or approve the pending operation
+    /// of another application.
+    fn use_approval(
+        &self,
+        conn: &Connection,
+        app: &Application,
+        operation: SensitiveOperation,
+        provider_id: ProviderId,
+        target: &str,
+        id: u64,
+    ) -> rusqlite::Result<std::result::Result<Authorization, (u64, ResponseStatus)>> {
+        let refused = Ok(Err((id, ResponseStatus::PsaErrorNotPermitted)));
+        let row = conn
+            .query_row(
+                "SELECT operation, authenticator_id, requester, provider_id, target, requested,
+                        approved, executed
+                 FROM approval WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok(ApprovalRow {
+                        operation: row.get(0)?,
+                        authenticator_id: row.get(1)?,
+                        requester: row.get(2)?,
+                        provider_id: row.get(3)?,
+                        target: row.get(4)?,
+                        requested: row.get(5)?,
+                        approved: row.get(6)?,
+                        executed: row.get(7)?,
+                    })
+                },
+            )
+            .optional()?;
+        let row = match row {
+            Some(row)
+                if row.operation == operation_code(operation)
+                    && row.provider_id == provider_id as u8
+                    && row.target == target
+                    && row.executed.is_none() =>
+            {
+                row
+            }
+            _ => return refused,
+        };
+        let now = now();
+        let ttl = self.ttl.as_secs();
+        let identity = app.identity();
+        let is_requester = row.authenticator_id == *identity.authenticator_id() as u8
+            && row.requester == *identity.name();
+        if is_requester {
+            match row.approved {
+                Some(approved) if approved + ttl > now => {
+                    let _ = conn.execute(
+                        "UPDATE approval SET executed = ?1 WHERE id = ?2",
+                        params![now, id],
+                    )?;
+                    Ok(Ok(Authorization::Execute))
+                }
+                _ => refused,
+            }
+        } else if *app.is_admin() && row.approved.is_none() && row.requested + ttl > now {
+            let _ = conn.execute(
+                "UPDATE approval SET approver = ?1, approved = ?2 WHERE id = ?3",
+                params![identity.name(), now, id],
+            )?;
+            Ok(Ok(Authorization::Approved))
+        } else {
+            refused
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Fixture {
+        // Removed with the database when dropped.
+        _directory: tempfile::TempDir,
+        config: ApprovalsConfig,
+        approvals: Approvals,
+    }
+
+    fn fixture(operations: Vec<SensitiveOperation>, ttl: Option<u64>) -> Fixture {
+        let directory = tempfile::tempdir().unwrap();
+        let config = ApprovalsConfig {
+            operations,
+            store_path: Some(
+                directory
+                    .path()
+                    .join("approvals.sqlite3")
+                    .display()
+                    .to_string(),
+            ),
+            ttl,
+        };
+        let approvals = Approvals::new(&config).unwrap();
+        Fixture {
+            _directory: directory,
+            config,
+            approvals,
+        }
+    }
+
+    fn app(name: &str, is_admin: bool) -> Application {
+        Application::new(
+            ApplicationIdentity::new(String::from(name), AuthType::Direct),
+            is_admin,
+        )
+    }
+
+    fn export(
+        approvals: &Approvals,
+        app: &Application,
+        approval_id: Option<u64>,
+    ) -> Result<Authorization> {
+        approvals.authorize(
+            app,
+            SensitiveOperation::ExportKey,
+            ProviderId::Tpm,
+            "key",
+            approval_id,
+        )
+    }
+
+    /// Request the export as alice, returning the approval ID of the pending operation.
+    fn request_export(fixture: &Fixture) -> u64 {
+        assert_eq!(
+            export(&fixture.approvals, &app("alice", true), None),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        pending(&fixture.config).unwrap().last().unwrap().id
+    }
+
+    #[test]
+    fn operations_not_configured_are_executed() {
+        let fixture = fixture(vec![SensitiveOperation::ExportKey], None);
+        assert_eq!(
+            fixture.approvals.authorize(
+                &app("alice", true),
+                SensitiveOperation::ForceDestroy,
+                ProviderId::Tpm,
+                "key",
+                None
+            ),
+            Ok(Authorization::Execute)
+        );
+    }
+
+    #[test]
+    fn requests_are_pending_once() {
+        let fixture = fixture(vec![SensitiveOperation::ExportKey], None);
+        let id = request_export(&fixture);
+        assert_eq!(
+            export(&fixture.approvals, &app("alice", true), None),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let pending = pending(&fixture.config).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, id);
+        assert_eq!(pending[0].operation, SensitiveOperation::ExportKey);
+        assert_eq!(pending[0].requester, *app("alice", true).identity());
+        assert_eq!(pending[0].provider_id, ProviderId::Tpm);
+        assert_eq!(pending[0].target, "key");
+    }
+
+    #[test]
+    fn pending_approvals_persist_across_restarts() {
+        let fixture = fixture(vec![SensitiveOperation::ExportKey], None);
+        let id = request_export(&fixture);
+        let approvals = Approvals::new(&fixture.config).unwrap();
+        assert_eq!(
+            export(&approvals, &app("bob", true), Some(id)),
+            Ok(Authorization::Approved)
+        );
+    }
+
+    #[test]
+    fn approved_operations_are_executed_once() {
+        let fixture = fixture(vec![SensitiveOperation::ExportKey], None);
+        let id = request_export(&fixture);
+        let alice = app("alice", true);
+        assert_eq!(
+            export(&fixture.approvals, &alice, Some(id)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            export(&fixture.approvals, &app("bob", true), Some(id)),
+            Ok(Authorization::Approved)
+        );
+        assert!(pending(&fixture.config).unwrap().is_empty());
+        assert_eq!(
+            export(&fixture.approvals, &alice, Some(id)),
+            Ok(Authorization::Execute)
+        );
+        assert_eq!(
+            export(&fixture.approvals, &alice, Some(id)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn operations_are_approved_once_by_another_administrator() {
+        let fixture = fixture(vec![SensitiveOperation::ExportKey], None);
+        let id = request_export(&fixture);
+        assert_eq!(
+            export(&fixture.approvals, &app("eve", false), Some(id)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            export(&fixture.approvals, &app("bob", true), Some(id)),
+            Ok(Authorization::Approved)
+        );
+        assert_eq!(
+            export(&fixture.approvals, &app("carol", true), Some(id)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn approvals_only_apply_to_their_operation() {
+        let fixture = fixture(
+            vec![
+                SensitiveOperation::ExportKey,
+                SensitiveOperation::ForceDestroy,
+            ],
+            None,
+        );
+        let id = request_export(&fixture);
+        let bob = app("bob", true);
+        assert_eq!(
+            fixture.approvals.authorize(
+                &bob,
+                SensitiveOperation::ExportKey,
+                ProviderId::Tpm,
+                "other-key",
+                Some(id)
+            ),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            fixture.approvals.authorize(
+                &bob,
+                SensitiveOperation::ExportKey,
+                ProviderId::Pkcs11,
+                "key",
+                Some(id)
+            ),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            fixture.approvals.authorize(
+                &bob,
+                SensitiveOperation::ForceDestroy,
+                ProviderId::Tpm,
+                "key",
+                Some(id)
+            ),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn expired_operations_are_not_approved_and_removed() {
+        let fixture = fixture(vec![SensitiveOperation::ExportKey], Some(0));
+        assert_eq!(
+            export(&fixture.approvals, &app("alice", true), None),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let conn = fixture.approvals.conn.lock().unwrap();
+        let id: u64 = conn
+            .query_row("SELECT id FROM approval", [], |row| row.get(0))
+            .unwrap();
+        drop(conn);
+        assert!(pending(&fixture.config).unwrap().is_empty());
+        assert_eq!(
+            export(&fixture.approvals, &app("bob", true), Some(id)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+
+        assert_eq!(
+            export(&fixture.approvals, &app("alice", true), None),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let count: u32 = fixture
+            .approvals
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM approval", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn pending_operations_are_capped() {
+        let fixture = fixture(vec![SensitiveOperation::ExportKey], None);
+        let alice = app("alice", true);
+        for index in 0..MAX_PENDING_PER_REQUESTER {
+            assert_eq!(
+                fixture.approvals.authorize(
+                    &alice,
+                    SensitiveOperation::ExportKey,
+                    ProviderId::Tpm,
+                    &format!("key-{}", index),
+                    None
+                ),
+                Err(ResponseStatus::PsaErrorNotPermitted)
+            );
+        }
+        assert_eq!(
+            export(&fixture.approvals, &alice, None),
+            Err(ResponseStatus::PsaErrorInsufficientStorage)
+        );
+        assert_eq!(
+            export(&fixture.approvals, &app("bob", true), None),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn deleting_keys_needs_an_administrator() {
+        let fixture = fixture(vec![SensitiveOperation::ForceDestroy], None);
+        assert_eq!(
+            fixture.approvals.authorize(
+                &app("eve", false),
+                SensitiveOperation::ForceDestroy,
+                ProviderId::Core,
+                "alice",
+                None
+            ),
+            Err(ResponseStatus::AdminOperation)
+        );
+        assert!(pending(&fixture.config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn approval_ids_are_stripped_from_names() {
+        let mut key_name = String::from("key#approval=12");
+        assert_eq!(strip_from(&mut key_name), Ok(Some(12)));
+        assert_eq!(key_name, "key");
+        assert_eq!(strip_from(&mut key_name), Ok(None));
+        let mut key_name = String::from("key#approval=x");
+        assert_eq!(
+            strip_from(&mut key_name),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+}
@@ -5,18 +5,87 @@
 //! The backend handler embodies the last processing step from external request
 //! to internal function call - parsing of the request body and conversion to a
 //! native operation which is then passed to the provider.
-use crate::authenticators::Application;
-use crate::providers::Provide;
+use super::aead::{self, AeadLimits};
+#[cfg(feature = "approvals")]
+use super::approvals::{self, Approvals, Authorization};
+#[cfg(feature = "cose-signing")]
+use super::cose_sign1;
+use super::ecdsa_nonces::EcdsaNonces;
+#[cfg(feature = "import-checks")]
+use super::import_checks::ImportChecks;
+#[cfg(feature = "jws-signing")]
+use super::jws;
+use super::key_defaults::KeyDefaults;
+#[cfg(feature = "key-disabling")]
+use super::key_disabling::{self, DisabledUses};
+#[cfg(feature = "key-export-formats")]
+use super::key_formats::export::{self, PublicKeyFormat};
+use super::key_requirements::{KeyRequirements, ProviderAssurance};
+use super::key_templates::{self, KeyTemplates};
+use super::leases::KeyLeases;
+use super::priority::{PriorityGate, RequestPriority};
+use super::random_limits::RandomLimits;
+use super::result_cache::ResultCache;
+#[cfg(feature = "signing-policies")]
+use super::signing_policy::SigningPolicies;
+#[cfg(feature = "tls13-signing")]
+use super::tls13;
+use crate::authenticators::{Application, ApplicationIdentity};
+#[cfg(feature = "sqlite-kim")]
+use crate::key_info_managers::lifecycle::KeyState;
+use crate::key_info_managers::KeyIdentity;
+#[cfg(feature = "aes-key-wrap")]
+use crate::providers::utils::aes_kw::KeyWrapAlgorithm;
+use crate::providers::utils::ecdsa_signature;
+#[cfg(feature = "xchacha20-poly1305")]
+use crate::providers::utils::xchacha20;
+use crate::providers::{error_detail, Provide};
+use crate::utils::config::{AeadLimitsConfig, HookEvent, RandomLimitsConfig, SensitiveOperation};
+use crate::utils::event_hooks::{Event, EventHooks};
+#[cfg(feature = "fault-injection")]
+use crate::utils::fault_injection::FaultInjection;
+use crate::utils::key_suffixes;
+use crate::utils::logging::Redacted;
+#[cfg(any(feature = "sqlite-kim", feature = "key-disabling"))]
+use crate::utils::logging::AUDIT_TARGET;
+use crate::utils::service_status::ServiceStatus;
 use derivative::Derivative;
+#[cfg(any(feature = "sqlite-kim", feature = "key-disabling"))]
+use log::info;
 use log::{error, trace, warn};
+use once_cell::sync::OnceCell;
+use parsec_interface::operations::attest_key;
+use parsec_interface::operations::psa_algorithm::AsymmetricSignature;
+use parsec_interface::operations::psa_destroy_key;
+#[cfg(feature = "aes-key-wrap")]
+use parsec_interface::operations::psa_export_key;
+#[cfg(any(feature = "key-export-formats", feature = "wrapping-key-export"))]
+use parsec_interface::operations::psa_export_public_key;
+use parsec_interface::operations::psa_key_attributes::Attributes;
 use parsec_interface::operations::Convert;
+#[cfg(any(
+    feature = "jws-signing",
+    feature = "cose-signing",
+    feature = "tls13-signing"
+))]
+use parsec_interface::operations::{psa_sign_hash, psa_sign_message};
 use parsec_interface::operations::{NativeOperation, NativeResult};
 use parsec_interface::requests::{
     request::RequestHeader, Request, Response, ResponseStatus, Result,
 };
-use parsec_interface::requests::{BodyType, ProviderId};
+use parsec_interface::requests::{BodyType, Opcode, ProviderId};
+#[cfg(any(
+    feature = "aes-key-wrap",
+    feature = "key-import-formats",
+    feature = "import-checks"
+))]
+use parsec_interface::secrecy::ExposeSecret;
+#[cfg(any(feature = "aes-key-wrap", feature = "key-import-formats"))]
+use parsec_interface::secrecy::Secret;
+use std::collections::HashSet;
 use std::io::{Error, ErrorKind};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Back end handler component
 ///
@@ -36,6 +105,23 @@ pub struct BackEndHandler {
     provider_id: ProviderId,
     content_type: BodyType,
     accept_type: BodyType,
+    key_requirements: KeyRequirements,
+    key_defaults: Option<Arc<KeyDefaults>>,
+    key_templates: Option<Arc<KeyTemplates>>,
+    #[cfg(feature = "import-checks")]
+    import_checks: Option<Arc<ImportChecks>>,
+    ecdsa_nonces: Option<EcdsaNonces>,
+    request_priority: Option<(Arc<RequestPriority>, Arc<PriorityGate>)>,
+    key_leases: Option<Arc<KeyLeases>>,
+    #[cfg(feature = "signing-policies")]
+    signing_policies: Option<Arc<SigningPolicies>>,
+    #[cfg(feature = "approvals")]
+    approvals: Option<Arc<Approvals>>,
+    random_limits: Option<RandomLimits>,
+    aead_limits: Option<AeadLimits>,
+    result_cache: Option<ResultCache>,
+    event_hooks: Option<EventHooks>,
+    opcodes: OnceCell<HashSet<Opcode>>,
 }
 
 impl BackEndHandler {
@@ -49,6 +135,532 @@ impl BackEndHandler {
         response
     }
 
+    /// Convert the result of a cacheable operation into a response, keeping it in the cache.
+    fn cached_response(
+        &self,
+        cache_key: Option<Vec<u8>>,
+        result: Result<NativeResult>,
+        request_hdr: RequestHeader,
+    ) -> Response {
+        if let (Some(result_cache), Some(cache_key)) = (&self.result_cache, cache_key) {
+            result_cache.store(request_hdr.opcode, cache_key, &result);
+        }
+        match result {
+            Ok(result) => self.result_to_response(result, request_hdr),
+            Err(status) => Response::from_request_header(request_hdr, status),
+        }
+    }
+
+    /// Identity under which an operation on a key is executed: the owner's if the key is leased to
+    /// the application, in which case the lease suffix is removed from the key name. The state of
+    /// the key must permit the operation, if the lifecycle of the keys is tracked, and so must its
+    /// usage flags, if keys can be disabled.
+    fn key_user(
+        &self,
+        app: &Application,
+        opcode: Opcode,
+        key_name: &mut String,
+    ) -> Result<ApplicationIdentity> {
+        let user = self
+            .key_leases
+            .as_ref()
+            .and_then(|leases| leases.resolve(app.identity(), self.provider_id, opcode, key_name))
+            .unwrap_or_else(|| app.identity().clone());
+        #[cfg(any(feature = "sqlite-kim", feature = "key-disabling"))]
+        if let Some(key_info_store) = self.provider.key_info_store() {
+            let key_identity = key_info_store.get_key_identity(user.clone(), key_name.clone());
+            #[cfg(feature = "sqlite-kim")]
+            key_info_store.check_key_state(&key_identity, opcode)?;
+            // A key missing from the Key Info Manager is left to the provider to refuse.
+            #[cfg(feature = "key-disabling")]
+            if let Ok(attributes) = key_info_store.get_key_attributes(&key_identity) {
+                key_disabling::check(&attributes.policy.usage_flags, opcode)?;
+            }
+        }
+        Ok(user)
+    }
+
+    /// Remove the `#state=` suffix from the name of a key being created, if the lifecycle of the
+    /// keys is tracked, returning whether the key starts in pre-activation.
+    #[cfg(feature = "sqlite-kim")]
+    fn strip_initial_state(&self, key_name: &mut String) -> Result<bool> {
+        if !self
+            .provider
+            .key_info_store()
+            .map_or(false, |key_info_store| key_info_store.tracks_lifecycle())
+        {
+            return Ok(false);
+        }
+        match key_suffixes::strip_argument(key_name, key_suffixes::STATE) {
+            None => Ok(false),
+            Some(state) => match KeyState::from_name(&state) {
+                Some(KeyState::PreActivation) => Ok(true),
+                Some(KeyState::Active) => Ok(false),
+                _ => {
+                    error!(
+                        "Keys can only be created in pre-activation or active, not \"{}\".",
+                        state
+                    );
+                    Err(ResponseStatus::PsaErrorInvalidArgument)
+                }
+            },
+        }
+    }
+
+    #[cfg(not(feature = "sqlite-kim"))]
+    fn strip_initial_state(&self, _key_name: &mut String) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Create a key with `create`, in pre-activation if `pre_activation` is true.
+    #[cfg_attr(not(feature = "sqlite-kim"), allow(unused_variables))]
+    fn create_key<T>(
+        &self,
+        app: &Application,
+        key_name: &str,
+        pre_activation: bool,
+        create: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        #[cfg(feature = "sqlite-kim")]
+        if let (true, Some(key_info_store)) = (pre_activation, self.provider.key_info_store()) {
+            let key_identity =
+                key_info_store.get_key_identity(app.identity().clone(), key_name.to_string());
+            key_info_store.create_in_pre_activation(&key_identity, true);
+            let result = create();
+            if result.is_err() {
+                key_info_store.create_in_pre_activation(&key_identity, false);
+            }
+            return result;
+        }
+        create()
+    }
+
+    /// Move the key to the state of its `#state=` suffix, if it has one and the lifecycle of the
+    /// keys is tracked, returning whether it did.
+    #[cfg(feature = "sqlite-kim")]
+    fn change_key_state(&self, app: &Application, key_name: &mut String) -> Result<bool> {
+        let key_info_store = match self.provider.key_info_store() {
+            Some(key_info_store) if key_info_store.tracks_lifecycle() => key_info_store,
+            _ => return Ok(false),
+        };
+        let state = match key_suffixes::strip_argument(key_name, key_suffixes::STATE) {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        let state = KeyState::from_name(&state).ok_or_else(|| {
+            error!("Keys have no \"{}\" state to go to.", state);
+            ResponseStatus::PsaErrorInvalidArgument
+        })?;
+        let key_identity =
+            key_info_store.get_key_identity(app.identity().clone(), key_name.clone());
+        key_info_store.change_key_state(&key_identity, state)?;
+        info!(
+            target: AUDIT_TARGET,
+            "Application \"{}\" moved key \"{}\" of the {} to the {:?} state.",
+            app.identity().name(),
+            key_name,
+            self.provider_id,
+            state
+        );
+        Ok(true)
+    }
+
+    #[cfg(not(feature = "sqlite-kim"))]
+    fn change_key_state(&self, _app: &Application, _key_name: &mut String) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Disable the uses of the key given in its `#disable=` suffix, if it has one, returning
+    /// whether it did.
+    #[cfg(feature = "key-disabling")]
+    fn disable_key(&self, app: &Application, key_name: &mut String) -> Result<bool> {
+        let uses = match key_suffixes::strip_argument(key_name, key_suffixes::DISABLE) {
+            Some(uses) => uses,
+            None => return Ok(false),
+        };
+        let uses = DisabledUses::from_name(&uses).ok_or_else(|| {
+            error!("Keys have no \"{}\" uses to disable.", uses);
+            ResponseStatus::PsaErrorInvalidArgument
+        })?;
+        let key_info_store = self.provider.key_info_store().ok_or_else(|| {
+            error!("The keys of this provider can not be disabled.");
+            ResponseStatus::PsaErrorNotSupported
+        })?;
+        let key_identity =
+            key_info_store.get_key_identity(app.identity().clone(), key_name.clone());
+        let mut attributes = key_info_store.get_key_attributes(&key_identity)?;
+        attributes.policy.usage_flags = uses.remaining(attributes.policy.usage_flags);
+        key_info_store.replace_key_attributes(key_identity, attributes)?;
+        info!(
+            target: AUDIT_TARGET,
+            "Application \"{}\" disabled key \"{}\" of the {} for {:?}.",
+            app.identity().name(),
+            key_name,
+            self.provider_id,
+            uses
+        );
+        Ok(true)
+    }
+
+    #[cfg(not(feature = "key-disabling"))]
+    fn disable_key(&self, _app: &Application, _key_name: &mut String) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Encode the public key exported from the provider in the format asked for.
+    #[cfg(feature = "key-export-formats")]
+    fn encode_public_key(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: String,
+        data: &[u8],
+        format: PublicKeyFormat,
+    ) -> Result<Vec<u8>> {
+        let key_info_store = self.provider.key_info_store().ok_or_else(|| {
+            error!("The format of the public keys of this provider can not be chosen.");
+            ResponseStatus::PsaErrorNotSupported
+        })?;
+        let key_identity = key_info_store.get_key_identity(user.clone(), key_name);
+        let attributes = key_info_store.get_key_attributes(&key_identity)?;
+        export::encode(&attributes, data, format)
+    }
+
+    /// Attributes of a key to sign a message wrapped by the service with, and the algorithm to
+    /// sign with.
+    #[cfg(any(
+        feature = "jws-signing",
+        feature = "cose-signing",
+        feature = "tls13-signing"
+    ))]
+    fn wrapping_key(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+        mut alg: AsymmetricSignature,
+    ) -> Result<(Attributes, AsymmetricSignature)> {
+        let key_info_store = self.provider.key_info_store().ok_or_else(|| {
+            error!("Signatures can not be wrapped with the keys of this provider.");
+            ResponseStatus::PsaErrorNotSupported
+        })?;
+        let key_identity = key_info_store.get_key_identity(user.clone(), key_name.to_string());
+        let attributes = key_info_store.get_key_attributes(&key_identity)?;
+        self.adapt_ecdsa_alg(user, key_name, &mut alg);
+        Ok((attributes, alg))
+    }
+
+    /// Sign the hash of a message wrapped by the service, as a `PsaSignHash` request would.
+    #[cfg(any(
+        feature = "jws-signing",
+        feature = "cose-signing",
+        feature = "tls13-signing"
+    ))]
+    fn sign_wrapped_hash(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: String,
+        alg: AsymmetricSignature,
+        hash: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let field_len = self.ecdsa_field_len(user, &key_name, alg);
+        let reserved = self.reserve_signature(user, &key_name)?;
+        let result = self.provider.psa_sign_hash(
+            user,
+            psa_sign_hash::Operation {
+                key_name,
+                alg,
+                hash: hash.into(),
+            },
+        );
+        self.release_signature(reserved, result.is_ok());
+        let mut result = result?;
+        self.normalize_signature(field_len, &mut result.signature)?;
+        Ok(result.signature.to_vec())
+    }
+
+    /// Refuse to sign a structure wrapping the message of a request with a key restricted by a
+    /// signing policy, the digest signed not being the one of the message.
+    #[cfg(all(
+        any(
+            feature = "jws-signing",
+            feature = "cose-signing",
+            feature = "tls13-signing"
+        ),
+        feature = "signing-policies"
+    ))]
+    fn check_wrapped_message(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+        structure: &str,
+    ) -> Result<()> {
+        match &self.signing_policies {
+            Some(signing_policies) => {
+                signing_policies.check_wrapped_message(user, key_name, structure)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sign the message of the request as the payload of a JSON Web Signature.
+    #[cfg(feature = "jws-signing")]
+    fn sign_jws(
+        &self,
+        user: &ApplicationIdentity,
+        op_sign_message: psa_sign_message::Operation,
+    ) -> Result<psa_sign_message::Result> {
+        let key_name = op_sign_message.key_name;
+        #[cfg(feature = "signing-policies")]
+        self.check_wrapped_message(user, &key_name, "JWS")?;
+        let (attributes, alg) = self.wrapping_key(user, &key_name, op_sign_message.alg)?;
+        let (name, hash) = jws::algorithm(&attributes, alg)?;
+        let signing_input = jws::signing_input(name, &op_sign_message.message);
+        let signature =
+            self.sign_wrapped_hash(user, key_name, alg, jws::digest(hash, &signing_input))?;
+        Ok(psa_sign_message::Result {
+            signature: jws::compact(signing_input, &signature).into(),
+        })
+    }
+
+    /// Sign the message of the request as the payload of a COSE_Sign1 structure.
+    #[cfg(feature = "cose-signing")]
+    fn sign_cose_sign1(
+        &self,
+        user: &ApplicationIdentity,
+        op_sign_message: psa_sign_message::Operation,
+    ) -> Result<psa_sign_message::Result> {
+        let key_name = op_sign_message.key_name;
+        #[cfg(feature = "signing-policies")]
+        self.check_wrapped_message(user, &key_name, "COSE_Sign1 structure")?;
+        let (attributes, alg) = self.wrapping_key(user, &key_name, op_sign_message.alg)?;
+        let (algorithm, hash) = cose_sign1::algorithm(&attributes, alg)?;
+        let protected_header = cose_sign1::protected_header(algorithm);
+        let payload = op_sign_message.message;
+        let signature = self.sign_wrapped_hash(
+            user,
+            key_name,
+            alg,
+            cose_sign1::digest(hash, &protected_header, &payload),
+        )?;
+        Ok(psa_sign_message::Result {
+            signature: cose_sign1::encode(&protected_header, &payload, &signature).into(),
+        })
+    }
+
+    /// Sign the transcript hash of the request as a TLS 1.3 CertificateVerify message of the side.
+    #[cfg(feature = "tls13-signing")]
+    fn sign_tls13(
+        &self,
+        user: &ApplicationIdentity,
+        op_sign_message: psa_sign_message::Operation,
+        side: tls13::Side,
+    ) -> Result<psa_sign_message::Result> {
+        let key_name = op_sign_message.key_name;
+        #[cfg(feature = "signing-policies")]
+        self.check_wrapped_message(user, &key_name, "TLS 1.3 CertificateVerify message")?;
+        let (attributes, alg) = self.wrapping_key(user, &key_name, op_sign_message.alg)?;
+        let hash = tls13::scheme_hash(&attributes, alg)?;
+        let digest = tls13::digest(hash, side, &op_sign_message.message)?;
+        let signature = self.sign_wrapped_hash(user, key_name, alg, digest)?;
+        Ok(psa_sign_message::Result {
+            signature: tls13::encode_signature(alg, signature)?.into(),
+        })
+    }
+
+    /// Remove the approval suffix from the name, if approvals are configured, returning the
+    /// approval ID it gives. Names are left as they are otherwise.
+    #[cfg(feature = "approvals")]
+    fn strip_approval(&self, name: &mut String) -> Result<Option<u64>> {
+        match self.approvals {
+            Some(_) => approvals::strip_from(name),
+            None => Ok(None),
+        }
+    }
+
+    #[cfg(not(feature = "approvals"))]
+    fn strip_approval(&self, _name: &mut str) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Check that the sensitive operation requested by the application on the key, or on the
+    /// application whose keys are deleted, can be executed. Requests approving the pending
+    /// operation of another application instead are not executed and fail with
+    /// `approvals::APPROVED`.
+    #[cfg(feature = "approvals")]
+    fn authorize_sensitive(
+        &self,
+        app: &Application,
+        operation: SensitiveOperation,
+        name: &str,
+        approval_id: Option<u64>,
+    ) -> Result<()> {
+        match &self.approvals {
+            Some(approvals) => {
+                match approvals.authorize(app, operation, self.provider_id, name, approval_id)? {
+                    Authorization::Execute => Ok(()),
+                    Authorization::Approved => Err(approvals::APPROVED),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "approvals"))]
+    fn authorize_sensitive(
+        &self,
+        _app: &Application,
+        _operation: SensitiveOperation,
+        _name: &str,
+        _approval_id: Option<u64>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Select the nonces of an ECDSA key about to be created, if the service selects them.
+    fn select_ecdsa_nonces(&self, attributes: &mut Attributes) -> Result<()> {
+        match &self.ecdsa_nonces {
+            Some(ecdsa_nonces) => ecdsa_nonces.select(self.provider_id, attributes),
+            None => Ok(()),
+        }
+    }
+
+    /// Sign or verify with the ECDSA nonces recorded for the key, if the service selects them.
+    fn adapt_ecdsa_alg(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+        alg: &mut AsymmetricSignature,
+    ) {
+        if let (Some(ecdsa_nonces), Some(key_info_store)) =
+            (&self.ecdsa_nonces, self.provider.key_info_store())
+        {
+            let key_identity = key_info_store.get_key_identity(user.clone(), key_name.to_string());
+            // A missing key is reported by the provider.
+            if let Ok(attributes) = key_info_store.get_key_attributes(&key_identity) {
+                ecdsa_nonces.adapt(attributes.policy.permitted_algorithms, alg);
+            }
+        }
+    }
+
+    /// Length of r and s in the signatures of a key used with an ECDSA algorithm, to exchange them
+    /// in the PSA format whatever the provider uses.
+    fn ecdsa_field_len(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+        alg: AsymmetricSignature,
+    ) -> Option<usize> {
+        if !matches!(
+            alg,
+            AsymmetricSignature::Ecdsa { .. } | AsymmetricSignature::DeterministicEcdsa { .. }
+        ) {
+            return None;
+        }
+        let key_info_store = self.provider.key_info_store()?;
+        let key_identity = key_info_store.get_key_identity(user.clone(), key_name.to_string());
+        // A missing key is reported by the provider.
+        let attributes = key_info_store.get_key_attributes(&key_identity).ok()?;
+        Some(ecdsa_signature::field_len(attributes.bits))
+    }
+
+    /// Convert an ECDSA signature made by the provider to the PSA format.
+    fn normalize_signature(&self, field_len: Option<usize>, signature: &mut Vec<u8>) -> Result<()> {
+        if let Some(field_len) = field_len {
+            *signature = ecdsa_signature::normalize(signature, field_len).ok_or_else(|| {
+                error!(
+                    "The {} returned an ECDSA signature in an unknown format.",
+                    self.provider_id
+                );
+                ResponseStatus::PsaErrorGenericError
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Convert an ECDSA signature to verify to the PSA format, accepting DER-encoded ones too.
+    /// Signatures in neither are left for the provider to refuse.
+    fn normalize_signature_to_verify(field_len: Option<usize>, signature: &mut Vec<u8>) {
+        if let Some(normalized) =
+            field_len.and_then(|field_len| ecdsa_signature::normalize(signature, field_len))
+        {
+            *signature = normalized;
+        }
+    }
+
+    /// Count a signature about to be made with a key, if the signatures are counted. Returns the
+    /// identity of the key to release the signature with.
+    #[cfg(feature = "sqlite-kim")]
+    fn reserve_signature(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+    ) -> Result<Option<KeyIdentity>> {
+        if let Some(key_info_store) = self.provider.key_info_store() {
+            let key_identity = key_info_store.get_key_identity(user.clone(), key_name.to_string());
+            if key_info_store.reserve_signature(&key_identity)? {
+                return Ok(Some(key_identity));
+            }
+        }
+        Ok(None)
+    }
+
+    #[cfg(not(feature = "sqlite-kim"))]
+    fn reserve_signature(
+        &self,
+        _user: &ApplicationIdentity,
+        _key_name: &str,
+    ) -> Result<Option<KeyIdentity>> {
+        Ok(None)
+    }
+
+    /// Record whether a signature counted by `reserve_signature` was made.
+    #[cfg(feature = "sqlite-kim")]
+    fn release_signature(&self, key_identity: Option<KeyIdentity>, signed: bool) {
+        if let (Some(key_identity), Some(key_info_store)) =
+            (key_identity, self.provider.key_info_store())
+        {
+            key_info_store.release_signature(&key_identity, signed);
+        }
+    }
+
+    #[cfg(not(feature = "sqlite-kim"))]
+    fn release_signature(&self, _key_identity: Option<KeyIdentity>, _signed: bool) {}
+
+    /// Report an event on a key of the application to the hooks.
+    fn notify(&self, kind: HookEvent, app: &Application, key_name: &str) {
+        if let Some(event_hooks) = &self.event_hooks {
+            event_hooks.notify(Event::key(
+                kind,
+                app.identity().name(),
+                key_name,
+                self.provider_id,
+            ));
+        }
+    }
+
+    /// Check that a key with the given attributes can be created in this provider.
+    fn check_key_requirements(&self, attributes: &Attributes) -> Result<()> {
+        if let Some(key_templates) = &self.key_templates {
+            key_templates.check(self.provider_id, attributes)?;
+        }
+        if self.key_requirements == KeyRequirements::default() {
+            return Ok(());
+        }
+        // The opcodes of a provider do not change, they are only asked once.
+        let opcodes = match self.opcodes.get() {
+            Some(opcodes) => opcodes,
+            None => {
+                // Described once active, with all the operations it supports.
+                self.provider.activate()?;
+                let (_, opcodes) = self.provider.describe()?;
+                self.opcodes.get_or_init(|| opcodes)
+            }
+        };
+        self.key_requirements.check(
+            ProviderAssurance::new(self.provider_id, opcodes),
+            attributes,
+        )
+    }
+
     /// Assess whether the backend handler-provider pair is capable of handling
     /// the request.
     ///
@@ -81,9 +693,33 @@ impl BackEndHandler {
     /// the result back.
     ///
     /// If any of the steps fails, a response containing an appropriate status code is
-    /// returned.
+    /// returned. Any error detail the provider attached to the failure is logged: it is not part of
+    /// the response.
     pub fn execute_request(&self, request: Request, app: Option<Application>) -> Response {
         trace!("execute_request ingress");
+        // Discard any detail left over by a previous request handled on this thread.
+        let _ = error_detail::take();
+        let _pass = self
+            .request_priority
+            .as_ref()
+            .map(|(priority, gate)| gate.enter(priority.class(app.as_ref())));
+        let response = self.handle_request(request, app);
+        ServiceStatus::record_provider_operation(
+            self.provider_id,
+            response.header.status == ResponseStatus::Success,
+        );
+        if response.header.status != ResponseStatus::Success {
+            if let Some(detail) = error_detail::take() {
+                error!(
+                    "{:?} operation failed with {}, caused by {}.",
+                    response.header.opcode, response.header.status, detail
+                );
+            }
+        }
+        response
+    }
+
+    fn handle_request(&self, request: Request, app: Option<Application>) -> Response {
         let opcode = request.header.opcode;
         let header = request.header;
 
@@ -102,124 +738,364 @@ impl BackEndHandler {
             if !app.is_admin() {
                 warn!(
                     "Application name \"{}\" tried to perform an admin operation ({:?}).",
-                    app.identity().name(),
+                    Redacted(app.identity().name()),
                     opcode
                 );
                 return Response::from_request_header(header, ResponseStatus::AdminOperation);
             }
         }
 
+        let cache_key = self
+            .result_cache
+            .as_ref()
+            .filter(|_| ResultCache::is_cacheable(opcode))
+            .map(|_| request.body.bytes().to_vec());
+        if let (Some(result_cache), Some(cache_key)) = (&self.result_cache, &cache_key) {
+            // CanDoCrypto is only answered to authenticated applications.
+            if opcode != Opcode::CanDoCrypto || app.is_some() {
+                match result_cache.get(opcode, cache_key) {
+                    Some(Ok(result)) => return self.result_to_response(result, header),
+                    Some(Err(status)) => return Response::from_request_header(header, status),
+                    None => (),
+                }
+            }
+        }
+
+        #[cfg(feature = "fault-injection")]
+        if self.provider_id != ProviderId::Core {
+            unwrap_or_else_return!(FaultInjection::before_provider_operation());
+        }
+
         match unwrap_or_else_return!(self.converter.body_to_operation(request.body, opcode)) {
             NativeOperation::ListProviders(op_list_providers) => {
-                let result =
-                    unwrap_or_else_return!(self.provider.list_providers(op_list_providers));
+                let result = self
+                    .provider
+                    .list_providers(op_list_providers)
+                    .map(NativeResult::ListProviders);
                 trace!("list_providers egress");
-                self.result_to_response(NativeResult::ListProviders(result), header)
+                self.cached_response(cache_key, result, header)
             }
             NativeOperation::ListOpcodes(op_list_opcodes) => {
-                let result = unwrap_or_else_return!(self.provider.list_opcodes(op_list_opcodes));
+                let result = self
+                    .provider
+                    .list_opcodes(op_list_opcodes)
+                    .map(NativeResult::ListOpcodes);
                 trace!("list_opcodes egress");
-                self.result_to_response(NativeResult::ListOpcodes(result), header)
+                self.cached_response(cache_key, result, header)
             }
             NativeOperation::Ping(op_ping) => {
                 let result = unwrap_or_else_return!(self.provider.ping(op_ping));
                 trace!("ping egress");
                 self.result_to_response(NativeResult::Ping(result), header)
             }
-            NativeOperation::PsaGenerateKey(op_generate_key) => {
+            NativeOperation::PsaGenerateKey(mut op_generate_key) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
-                    .provider
-                    .psa_generate_key(app.identity(), op_generate_key));
+                if let Some(key_templates) = &self.key_templates {
+                    if let Some(template) = key_templates::strip_from(&mut op_generate_key.key_name)
+                    {
+                        op_generate_key.attributes =
+                            unwrap_or_else_return!(key_templates.get(self.provider_id, &template));
+                    }
+                }
+                let pre_activation =
+                    unwrap_or_else_return!(self.strip_initial_state(&mut op_generate_key.key_name));
+                unwrap_or_else_return!(key_suffixes::check_new(&op_generate_key.key_name));
+                if let Some(key_defaults) = &self.key_defaults {
+                    unwrap_or_else_return!(
+                        key_defaults.complete(&mut op_generate_key.attributes, true)
+                    );
+                }
+                unwrap_or_else_return!(self.check_key_requirements(&op_generate_key.attributes));
+                unwrap_or_else_return!(self.select_ecdsa_nonces(&mut op_generate_key.attributes));
+                let key_name = op_generate_key.key_name.clone();
+                let result = unwrap_or_else_return!(self.create_key(
+                    &app,
+                    &key_name,
+                    pre_activation,
+                    || self
+                        .provider
+                        .psa_generate_key(app.identity(), op_generate_key)
+                ));
+                self.notify(HookEvent::KeyCreated, &app, &key_name);
                 trace!("psa_generate_key egress");
                 self.result_to_response(NativeResult::PsaGenerateKey(result), header)
             }
-            NativeOperation::PsaImportKey(op_import_key) => {
+            NativeOperation::PsaImportKey(mut op_import_key) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
-                    .provider
-                    .psa_import_key(app.identity(), op_import_key));
+                #[cfg(feature = "aes-key-wrap")]
+                let key_wrap = KeyWrapAlgorithm::strip_from(&mut op_import_key.key_name);
+                let pre_activation =
+                    unwrap_or_else_return!(self.strip_initial_state(&mut op_import_key.key_name));
+                unwrap_or_else_return!(key_suffixes::check_new(&op_import_key.key_name));
+                // Wrapped keys are unwrapped first, to be converted and checked as the others.
+                #[cfg(feature = "aes-key-wrap")]
+                if let Some((alg, wrapping_key_name)) = key_wrap {
+                    let data = unwrap_or_else_return!(self.provider.aes_key_unwrap(
+                        app.identity(),
+                        &wrapping_key_name,
+                        op_import_key.data.expose_secret(),
+                        alg
+                    ));
+                    op_import_key.data = Secret::new(data.to_vec());
+                }
+                #[cfg(feature = "key-import-formats")]
+                if let Some(data) = unwrap_or_else_return!(super::key_formats::import::convert(
+                    &mut op_import_key.attributes,
+                    op_import_key.data.expose_secret()
+                )) {
+                    op_import_key.data = Secret::new(data.to_vec());
+                }
+                if let Some(key_defaults) = &self.key_defaults {
+                    unwrap_or_else_return!(
+                        key_defaults.complete(&mut op_import_key.attributes, false)
+                    );
+                }
+                unwrap_or_else_return!(self.check_key_requirements(&op_import_key.attributes));
+                #[cfg(feature = "import-checks")]
+                if let Some(import_checks) = &self.import_checks {
+                    unwrap_or_else_return!(import_checks.check(
+                        &op_import_key.attributes,
+                        op_import_key.data.expose_secret()
+                    ));
+                }
+                unwrap_or_else_return!(self.select_ecdsa_nonces(&mut op_import_key.attributes));
+                let key_name = op_import_key.key_name.clone();
+                let result = unwrap_or_else_return!(self.create_key(
+                    &app,
+                    &key_name,
+                    pre_activation,
+                    || self.provider.psa_import_key(app.identity(), op_import_key)
+                ));
+                self.notify(HookEvent::KeyCreated, &app, &key_name);
                 trace!("psa_import_key egress");
                 self.result_to_response(NativeResult::PsaImportKey(result), header)
             }
-            NativeOperation::PsaExportPublicKey(op_export_public_key) => {
+            NativeOperation::PsaExportPublicKey(mut op_export_public_key) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                #[cfg(feature = "wrapping-key-export")]
+                if op_export_public_key.key_name == key_suffixes::WRAPPING_KEY {
+                    let data = unwrap_or_else_return!(self.provider.wrapping_public_key());
+                    trace!("psa_export_public_key egress");
+                    return self.result_to_response(
+                        NativeResult::PsaExportPublicKey(psa_export_public_key::Result {
+                            data: data.into(),
+                        }),
+                        header,
+                    );
+                }
+                #[cfg(feature = "key-export-formats")]
+                let format = PublicKeyFormat::strip_from(&mut op_export_public_key.key_name);
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_export_public_key.key_name
+                ));
+                #[cfg(feature = "key-export-formats")]
+                let key_name = op_export_public_key.key_name.clone();
                 let result = unwrap_or_else_return!(self
                     .provider
-                    .psa_export_public_key(app.identity(), op_export_public_key));
+                    .psa_export_public_key(&user, op_export_public_key));
+                #[cfg(feature = "key-export-formats")]
+                let result = match format {
+                    Some(format) => psa_export_public_key::Result {
+                        data: zeroize::Zeroizing::new(unwrap_or_else_return!(
+                            self.encode_public_key(&user, key_name, &result.data, format)
+                        )),
+                    },
+                    None => result,
+                };
                 trace!("psa_export_public_key egress");
                 self.result_to_response(NativeResult::PsaExportPublicKey(result), header)
             }
-            NativeOperation::PsaExportKey(op_export_key) => {
+            NativeOperation::PsaExportKey(mut op_export_key) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let approval_id =
+                    unwrap_or_else_return!(self.strip_approval(&mut op_export_key.key_name));
+                #[cfg(feature = "aes-key-wrap")]
+                let key_wrap = KeyWrapAlgorithm::strip_from(&mut op_export_key.key_name);
+                unwrap_or_else_return!(self.authorize_sensitive(
+                    &app,
+                    SensitiveOperation::ExportKey,
+                    &op_export_key.key_name,
+                    approval_id
+                ));
+                #[cfg(feature = "aes-key-wrap")]
+                if let Some((alg, wrapping_key_name)) = key_wrap {
+                    let result = psa_export_key::Result {
+                        data: Secret::new(unwrap_or_else_return!(self.provider.aes_key_wrap(
+                            app.identity(),
+                            &wrapping_key_name,
+                            &op_export_key.key_name,
+                            alg
+                        ))),
+                    };
+                    trace!("psa_export_key egress");
+                    return self.result_to_response(NativeResult::PsaExportKey(result), header);
+                }
                 let result = unwrap_or_else_return!(self
                     .provider
                     .psa_export_key(app.identity(), op_export_key));
                 trace!("psa_export_key egress");
                 self.result_to_response(NativeResult::PsaExportKey(result), header)
             }
-            NativeOperation::PsaDestroyKey(op_destroy_key) => {
+            NativeOperation::PsaDestroyKey(mut op_destroy_key) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                if unwrap_or_else_return!(self.change_key_state(&app, &mut op_destroy_key.key_name))
+                    || unwrap_or_else_return!(self.disable_key(&app, &mut op_destroy_key.key_name))
+                {
+                    trace!("psa_destroy_key egress");
+                    return self.result_to_response(
+                        NativeResult::PsaDestroyKey(psa_destroy_key::Result {}),
+                        header,
+                    );
+                }
+                let key_name = op_destroy_key.key_name.clone();
                 let result = unwrap_or_else_return!(self
                     .provider
                     .psa_destroy_key(app.identity(), op_destroy_key));
+                self.notify(HookEvent::KeyDestroyed, &app, &key_name);
                 trace!("psa_destroy_key egress");
                 self.result_to_response(NativeResult::PsaDestroyKey(result), header)
             }
-            NativeOperation::PsaSignHash(op_sign_hash) => {
+            NativeOperation::PsaSignHash(mut op_sign_hash) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
-                    .provider
-                    .psa_sign_hash(app.identity(), op_sign_hash));
+                let user =
+                    unwrap_or_else_return!(self.key_user(&app, opcode, &mut op_sign_hash.key_name));
+                #[cfg(feature = "signing-policies")]
+                if let Some(signing_policies) = &self.signing_policies {
+                    unwrap_or_else_return!(signing_policies.check_hash(
+                        &user,
+                        &op_sign_hash.key_name,
+                        &op_sign_hash.hash
+                    ));
+                }
+                self.adapt_ecdsa_alg(&user, &op_sign_hash.key_name, &mut op_sign_hash.alg);
+                let field_len =
+                    self.ecdsa_field_len(&user, &op_sign_hash.key_name, op_sign_hash.alg);
+                let reserved =
+                    unwrap_or_else_return!(self.reserve_signature(&user, &op_sign_hash.key_name));
+                let result = self.provider.psa_sign_hash(&user, op_sign_hash);
+                self.release_signature(reserved, result.is_ok());
+                let mut result = unwrap_or_else_return!(result);
+                unwrap_or_else_return!(self.normalize_signature(field_len, &mut result.signature));
                 trace!("psa_sign_hash egress");
                 self.result_to_response(NativeResult::PsaSignHash(result), header)
             }
-            NativeOperation::PsaVerifyHash(op_verify_hash) => {
+            NativeOperation::PsaVerifyHash(mut op_verify_hash) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
-                    .provider
-                    .psa_verify_hash(app.identity(), op_verify_hash));
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_verify_hash.key_name
+                ));
+                self.adapt_ecdsa_alg(&user, &op_verify_hash.key_name, &mut op_verify_hash.alg);
+                BackEndHandler::normalize_signature_to_verify(
+                    self.ecdsa_field_len(&user, &op_verify_hash.key_name, op_verify_hash.alg),
+                    &mut op_verify_hash.signature,
+                );
+                let result =
+                    unwrap_or_else_return!(self.provider.psa_verify_hash(&user, op_verify_hash));
                 trace!("psa_verify_hash egress");
                 self.result_to_response(NativeResult::PsaVerifyHash(result), header)
             }
-            NativeOperation::PsaAsymmetricEncrypt(op_asymmetric_encrypt) => {
+            NativeOperation::PsaAsymmetricEncrypt(mut op_asymmetric_encrypt) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_asymmetric_encrypt.key_name
+                ));
                 let result = unwrap_or_else_return!(self
                     .provider
-                    .psa_asymmetric_encrypt(app.identity(), op_asymmetric_encrypt));
+                    .psa_asymmetric_encrypt(&user, op_asymmetric_encrypt));
                 trace!("psa_asymmetric_encrypt egress");
                 self.result_to_response(NativeResult::PsaAsymmetricEncrypt(result), header)
             }
-            NativeOperation::PsaAsymmetricDecrypt(op_asymmetric_decrypt) => {
+            NativeOperation::PsaAsymmetricDecrypt(mut op_asymmetric_decrypt) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_asymmetric_decrypt.key_name
+                ));
                 let result = unwrap_or_else_return!(self
                     .provider
-                    .psa_asymmetric_decrypt(app.identity(), op_asymmetric_decrypt));
+                    .psa_asymmetric_decrypt(&user, op_asymmetric_decrypt));
                 trace!("psa_asymmetric_decrypt egress");
                 self.result_to_response(NativeResult::PsaAsymmetricDecrypt(result), header)
             }
-            NativeOperation::PsaAeadEncrypt(op_aead_encrypt) => {
+            NativeOperation::PsaAeadEncrypt(mut op_aead_encrypt) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
-                    .provider
-                    .psa_aead_encrypt(app.identity(), op_aead_encrypt));
+                let detached_tag = aead::strip_detached_tag(&mut op_aead_encrypt.key_name);
+                #[cfg(feature = "xchacha20-poly1305")]
+                let xchacha = xchacha20::strip_from(&mut op_aead_encrypt.key_name);
+                #[cfg(not(feature = "xchacha20-poly1305"))]
+                let xchacha = false;
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_aead_encrypt.key_name
+                ));
+                if let Some(aead_limits) = &self.aead_limits {
+                    unwrap_or_else_return!(aead_limits.check(
+                        op_aead_encrypt.additional_data.len(),
+                        op_aead_encrypt.plaintext.len()
+                    ));
+                }
+                let alg = op_aead_encrypt.alg;
+                let mut result = unwrap_or_else_return!(if xchacha {
+                    self.provider
+                        .xchacha20_poly1305_encrypt(&user, op_aead_encrypt)
+                } else {
+                    self.provider.psa_aead_encrypt(&user, op_aead_encrypt)
+                });
+                if detached_tag {
+                    unwrap_or_else_return!(aead::detach_tag(alg, &mut result.ciphertext));
+                }
                 trace!("psa_aead_encrypt egress");
                 self.result_to_response(NativeResult::PsaAeadEncrypt(result), header)
             }
-            NativeOperation::PsaAeadDecrypt(op_aead_decrypt) => {
+            NativeOperation::PsaAeadDecrypt(mut op_aead_decrypt) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
-                    .provider
-                    .psa_aead_decrypt(app.identity(), op_aead_decrypt));
+                let detached_tag = aead::strip_detached_tag(&mut op_aead_decrypt.key_name);
+                #[cfg(feature = "xchacha20-poly1305")]
+                let xchacha = xchacha20::strip_from(&mut op_aead_decrypt.key_name);
+                #[cfg(not(feature = "xchacha20-poly1305"))]
+                let xchacha = false;
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_aead_decrypt.key_name
+                ));
+                if let Some(aead_limits) = &self.aead_limits {
+                    let tag_length = aead::tag_length(op_aead_decrypt.alg);
+                    unwrap_or_else_return!(aead_limits.check(
+                        op_aead_decrypt.additional_data.len(),
+                        op_aead_decrypt.ciphertext.len().saturating_sub(tag_length)
+                    ));
+                }
+                if detached_tag {
+                    unwrap_or_else_return!(aead::attach_tag(
+                        op_aead_decrypt.alg,
+                        &mut op_aead_decrypt.ciphertext
+                    ));
+                }
+                let result = unwrap_or_else_return!(if xchacha {
+                    self.provider
+                        .xchacha20_poly1305_decrypt(&user, op_aead_decrypt)
+                } else {
+                    self.provider.psa_aead_decrypt(&user, op_aead_decrypt)
+                });
                 trace!("psa_aead_decrypt egress");
                 self.result_to_response(NativeResult::PsaAeadDecrypt(result), header)
             }
             NativeOperation::ListAuthenticators(op_list_authenticators) => {
-                let result = unwrap_or_else_return!(self
+                let result = self
                     .provider
-                    .list_authenticators(op_list_authenticators));
+                    .list_authenticators(op_list_authenticators)
+                    .map(NativeResult::ListAuthenticators);
                 trace!("list_authenticators egress");
-                self.result_to_response(NativeResult::ListAuthenticators(result), header)
+                self.cached_response(cache_key, result, header)
             }
             NativeOperation::ListKeys(op_list_keys) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
@@ -233,8 +1109,18 @@ impl BackEndHandler {
                 trace!("list_clients egress");
                 self.result_to_response(NativeResult::ListClients(result), header)
             }
-            NativeOperation::DeleteClient(op_delete_client) => {
+            NativeOperation::DeleteClient(mut op_delete_client) => {
+                // Applications which are not administrators were refused above, before their
+                // request could be recorded as pending or approve another one.
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let approval_id =
+                    unwrap_or_else_return!(self.strip_approval(&mut op_delete_client.client));
+                unwrap_or_else_return!(self.authorize_sensitive(
+                    &app,
+                    SensitiveOperation::ForceDestroy,
+                    &op_delete_client.client,
+                    approval_id
+                ));
                 let result = unwrap_or_else_return!(self
                     .provider
                     .delete_client(app.identity(), op_delete_client));
@@ -253,59 +1139,142 @@ impl BackEndHandler {
                 trace!("psa_hash_compare egress");
                 self.result_to_response(NativeResult::PsaHashCompare(result), header)
             }
-            NativeOperation::PsaRawKeyAgreement(op_raw_key_agreement) => {
+            NativeOperation::PsaRawKeyAgreement(mut op_raw_key_agreement) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_raw_key_agreement.private_key_name
+                ));
                 let result = unwrap_or_else_return!(self
                     .provider
-                    .psa_raw_key_agreement(app.identity(), op_raw_key_agreement));
+                    .psa_raw_key_agreement(&user, op_raw_key_agreement));
                 trace!("psa_raw_key_agreement egress");
                 self.result_to_response(NativeResult::PsaRawKeyAgreement(result), header)
             }
             NativeOperation::PsaGenerateRandom(op_generate_random) => {
+                if let Some(random_limits) = &self.random_limits {
+                    unwrap_or_else_return!(
+                        random_limits.check(app.as_ref(), op_generate_random.size)
+                    );
+                }
                 let result =
                     unwrap_or_else_return!(self.provider.psa_generate_random(op_generate_random));
+                ServiceStatus::record_random_bytes(result.random_bytes.len());
                 trace!("psa_generate_random egress");
                 self.result_to_response(NativeResult::PsaGenerateRandom(result), header)
             }
-            NativeOperation::PsaSignMessage(op_sign_message) => {
+            NativeOperation::PsaSignMessage(mut op_sign_message) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
-                    .provider
-                    .psa_sign_message(app.identity(), op_sign_message));
+                #[cfg(feature = "jws-signing")]
+                let jws = jws::strip_from(&mut op_sign_message.key_name);
+                #[cfg(feature = "cose-signing")]
+                let cose_sign1 = cose_sign1::strip_from(&mut op_sign_message.key_name);
+                #[cfg(feature = "tls13-signing")]
+                let tls13_side = tls13::Side::strip_from(&mut op_sign_message.key_name);
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_sign_message.key_name
+                ));
+                #[cfg(feature = "jws-signing")]
+                if jws {
+                    let result = unwrap_or_else_return!(self.sign_jws(&user, op_sign_message));
+                    trace!("psa_sign_message egress");
+                    return self.result_to_response(NativeResult::PsaSignMessage(result), header);
+                }
+                #[cfg(feature = "cose-signing")]
+                if cose_sign1 {
+                    let result =
+                        unwrap_or_else_return!(self.sign_cose_sign1(&user, op_sign_message));
+                    trace!("psa_sign_message egress");
+                    return self.result_to_response(NativeResult::PsaSignMessage(result), header);
+                }
+                #[cfg(feature = "tls13-signing")]
+                if let Some(side) = tls13_side {
+                    let result =
+                        unwrap_or_else_return!(self.sign_tls13(&user, op_sign_message, side));
+                    trace!("psa_sign_message egress");
+                    return self.result_to_response(NativeResult::PsaSignMessage(result), header);
+                }
+                #[cfg(feature = "signing-policies")]
+                if let Some(signing_policies) = &self.signing_policies {
+                    unwrap_or_else_return!(signing_policies.check_message(
+                        &user,
+                        &op_sign_message.key_name,
+                        op_sign_message.alg,
+                        &op_sign_message.message
+                    ));
+                }
+                self.adapt_ecdsa_alg(&user, &op_sign_message.key_name, &mut op_sign_message.alg);
+                let field_len =
+                    self.ecdsa_field_len(&user, &op_sign_message.key_name, op_sign_message.alg);
+                let reserved = unwrap_or_else_return!(
+                    self.reserve_signature(&user, &op_sign_message.key_name)
+                );
+                let result = self.provider.psa_sign_message(&user, op_sign_message);
+                self.release_signature(reserved, result.is_ok());
+                let mut result = unwrap_or_else_return!(result);
+                unwrap_or_else_return!(self.normalize_signature(field_len, &mut result.signature));
                 trace!("psa_sign_message egress");
                 self.result_to_response(NativeResult::PsaSignMessage(result), header)
             }
-            NativeOperation::PsaVerifyMessage(op_verify_message) => {
+            NativeOperation::PsaVerifyMessage(mut op_verify_message) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_verify_message.key_name
+                ));
+                self.adapt_ecdsa_alg(
+                    &user,
+                    &op_verify_message.key_name,
+                    &mut op_verify_message.alg,
+                );
+                BackEndHandler::normalize_signature_to_verify(
+                    self.ecdsa_field_len(&user, &op_verify_message.key_name, op_verify_message.alg),
+                    &mut op_verify_message.signature,
+                );
                 let result = unwrap_or_else_return!(self
                     .provider
-                    .psa_verify_message(app.identity(), op_verify_message));
+                    .psa_verify_message(&user, op_verify_message));
                 trace!("psa_verify_message egress");
                 self.result_to_response(NativeResult::PsaVerifyMessage(result), header)
             }
-            NativeOperation::PsaCipherEncrypt(op_cipher_encrypt) => {
+            NativeOperation::PsaCipherEncrypt(mut op_cipher_encrypt) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_cipher_encrypt.key_name
+                ));
                 let result = unwrap_or_else_return!(self
                     .provider
-                    .psa_cipher_encrypt(app.identity(), op_cipher_encrypt));
+                    .psa_cipher_encrypt(&user, op_cipher_encrypt));
                 trace!("op_cipher_encrypt egress");
                 self.result_to_response(NativeResult::PsaCipherEncrypt(result), header)
             }
-            NativeOperation::PsaCipherDecrypt(op_cipher_decrypt) => {
+            NativeOperation::PsaCipherDecrypt(mut op_cipher_decrypt) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let user = unwrap_or_else_return!(self.key_user(
+                    &app,
+                    opcode,
+                    &mut op_cipher_decrypt.key_name
+                ));
                 let result = unwrap_or_else_return!(self
                     .provider
-                    .psa_cipher_decrypt(app.identity(), op_cipher_decrypt));
+                    .psa_cipher_decrypt(&user, op_cipher_decrypt));
                 trace!("psa_cipher_decrypt egress");
                 self.result_to_response(NativeResult::PsaCipherDecrypt(result), header)
             }
             NativeOperation::CanDoCrypto(op_can_do_crypto) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
-                let result = unwrap_or_else_return!(self
+                let result = self
                     .provider
-                    .can_do_crypto(app.identity(), op_can_do_crypto));
+                    .can_do_crypto(app.identity(), op_can_do_crypto)
+                    .map(NativeResult::CanDoCrypto);
                 trace!("can_do_crypto egress");
-                self.result_to_response(NativeResult::CanDoCrypto(result), header)
+                self.cached_response(cache_key, result, header)
             }
             NativeOperation::PrepareKeyAttestation(op_prepare_key_attestation) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
@@ -317,8 +1286,17 @@ impl BackEndHandler {
             }
             NativeOperation::AttestKey(op_attest_key) => {
                 let app = unwrap_or_else_return!(app.ok_or(ResponseStatus::NotAuthenticated));
+                let key_name = match &op_attest_key {
+                    attest_key::Operation::ActivateCredential {
+                        attested_key_name, ..
+                    } => Some(attested_key_name.clone()),
+                    _ => None,
+                };
                 let result =
                     unwrap_or_else_return!(self.provider.attest_key(app.identity(), op_attest_key));
+                if let Some(key_name) = key_name {
+                    self.notify(HookEvent::KeyAttested, &app, &key_name);
+                }
                 trace!("attest_key egress");
                 self.result_to_response(NativeResult::AttestKey(result), header)
             }
@@ -337,6 +1315,22 @@ pub struct BackEndHandlerBuilder {
     provider_id: Option<ProviderId>,
     content_type: Option<BodyType>,
     accept_type: Option<BodyType>,
+    key_requirements: Option<KeyRequirements>,
+    key_defaults: Option<Arc<KeyDefaults>>,
+    key_templates: Option<Arc<KeyTemplates>>,
+    #[cfg(feature = "import-checks")]
+    import_checks: Option<Arc<ImportChecks>>,
+    ecdsa_nonces: Option<EcdsaNonces>,
+    request_priority: Option<Arc<RequestPriority>>,
+    key_leases: Option<Arc<KeyLeases>>,
+    #[cfg(feature = "signing-policies")]
+    signing_policies: Option<Arc<SigningPolicies>>,
+    #[cfg(feature = "approvals")]
+    approvals: Option<Arc<Approvals>>,
+    random_limits: Option<RandomLimitsConfig>,
+    aead_limits: Option<AeadLimitsConfig>,
+    result_cache_ttl: Option<Duration>,
+    event_hooks: Option<EventHooks>,
 }
 
 impl BackEndHandlerBuilder {
@@ -348,6 +1342,22 @@ impl BackEndHandlerBuilder {
             provider_id: None,
             content_type: None,
             accept_type: None,
+            key_requirements: None,
+            key_defaults: None,
+            key_templates: None,
+            #[cfg(feature = "import-checks")]
+            import_checks: None,
+            ecdsa_nonces: None,
+            request_priority: None,
+            key_leases: None,
+            #[cfg(feature = "signing-policies")]
+            signing_policies: None,
+            #[cfg(feature = "approvals")]
+            approvals: None,
+            random_limits: None,
+            aead_limits: None,
+            result_cache_ttl: None,
+            event_hooks: None,
         }
     }
 
@@ -381,8 +1391,92 @@ impl BackEndHandlerBuilder {
         self
     }
 
+    /// Set the requirements that keys created through the BackEndHandler must meet
+    pub fn with_key_requirements(mut self, key_requirements: KeyRequirements) -> Self {
+        self.key_requirements = Some(key_requirements);
+        self
+    }
+
+    /// Set the attributes filled in the requests creating keys through the BackEndHandler
+    pub fn with_key_defaults(mut self, key_defaults: Arc<KeyDefaults>) -> Self {
+        self.key_defaults = Some(key_defaults);
+        self
+    }
+
+    /// Set the templates that keys created through the BackEndHandler must match in strict mode
+    pub fn with_key_templates(mut self, key_templates: Arc<KeyTemplates>) -> Self {
+        self.key_templates = Some(key_templates);
+        self
+    }
+
+    /// Set the checks done on the key material of imported keys
+    #[cfg(feature = "import-checks")]
+    pub fn with_import_checks(mut self, import_checks: Arc<ImportChecks>) -> Self {
+        self.import_checks = Some(import_checks);
+        self
+    }
+
+    /// Set the nonces selected for the ECDSA keys created through the BackEndHandler
+    pub fn with_ecdsa_nonces(mut self, ecdsa_nonces: EcdsaNonces) -> Self {
+        self.ecdsa_nonces = Some(ecdsa_nonces);
+        self
+    }
+
+    /// Set the classification of requests used to give interactive requests priority
+    pub fn with_request_priority(mut self, request_priority: Arc<RequestPriority>) -> Self {
+        self.request_priority = Some(request_priority);
+        self
+    }
+
+    /// Set the leases giving applications use of keys of other applications
+    pub fn with_key_leases(mut self, key_leases: Arc<KeyLeases>) -> Self {
+        self.key_leases = Some(key_leases);
+        self
+    }
+
+    /// Set the signing policies restricting keys to the digests they may sign
+    #[cfg(feature = "signing-policies")]
+    pub fn with_signing_policies(mut self, signing_policies: Arc<SigningPolicies>) -> Self {
+        self.signing_policies = Some(signing_policies);
+        self
+    }
+
+    /// Make the sensitive operations wait for the approval of a second administrator
+    #[cfg(feature = "approvals")]
+    pub fn with_approvals(mut self, approvals: Arc<Approvals>) -> Self {
+        self.approvals = Some(approvals);
+        self
+    }
+
+    /// Set the limits on the random bytes generated for each application
+    pub fn with_random_limits(mut self, random_limits: RandomLimitsConfig) -> Self {
+        self.random_limits = Some(random_limits);
+        self
+    }
+
+    /// Set the limits on the sizes of the AEAD requests
+    pub fn with_aead_limits(mut self, aead_limits: AeadLimitsConfig) -> Self {
+        self.aead_limits = Some(aead_limits);
+        self
+    }
+
+    /// Keep the responses to capability queries for the given time
+    pub fn with_result_cache_ttl(mut self, result_cache_ttl: Duration) -> Self {
+        self.result_cache_ttl = Some(result_cache_ttl);
+        self
+    }
+
+    /// Report the keys created, destroyed and attested through the BackEndHandler to the hooks
+    pub fn with_event_hooks(mut self, event_hooks: EventHooks) -> Self {
+        self.event_hooks = Some(event_hooks);
+        self
+    }
+
     /// Build into a BackEndHandler
     pub fn build(self) -> std::io::Result<BackEndHandler> {
+        let provider_id = self
+            .provider_id
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "provider_id is missing"))?;
         Ok(BackEndHandler {
             provider: self
                 .provider
@@ -390,15 +1484,264 @@ impl BackEndHandlerBuilder {
             converter: self
                 .converter
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "converter is missing"))?,
-            provider_id: self
-                .provider_id
-                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "provider_id is missing"))?,
+            provider_id,
             content_type: self
                 .content_type
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "content_type is missing"))?,
             accept_type: self
                 .accept_type
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "accept_type is missing"))?,
+            key_requirements: self.key_requirements.unwrap_or_default(),
+            key_defaults: self.key_defaults,
+            key_templates: self.key_templates,
+            #[cfg(feature = "import-checks")]
+            import_checks: self.import_checks,
+            ecdsa_nonces: self.ecdsa_nonces,
+            request_priority: self.request_priority.map(|request_priority| {
+                let gate = request_priority.gate();
+                (request_priority, gate)
+            }),
+            key_leases: self.key_leases,
+            #[cfg(feature = "signing-policies")]
+            signing_policies: self.signing_policies,
+            #[cfg(feature = "approvals")]
+            approvals: self.approvals,
+            random_limits: self
+                .random_limits
+                .map(|random_limits| RandomLimits::new(&random_limits, provider_id)),
+            aead_limits: self
+                .aead_limits
+                .map(|aead_limits| AeadLimits::new(&aead_limits, provider_id)),
+            result_cache: self.result_cache_ttl.map(ResultCache::new),
+            event_hooks: self.event_hooks,
+            opcodes: OnceCell::new(),
         })
     }
 }
+
+#[cfg(all(test, feature = "signing-policies"))]
+mod test {
+    use super::*;
+    use crate::utils::config::{SigningPoliciesConfig, SigningPolicyConfig};
+    use parsec_interface::operations::list_providers::{ProviderInfo, Uuid};
+    use parsec_interface::operations::psa_algorithm::{Algorithm, Hash, SignHash};
+    use parsec_interface::operations::psa_key_attributes::{Lifetime, Policy, Type, UsageFlags};
+    use parsec_interface::operations::{
+        list_clients, list_keys, psa_generate_key, psa_sign_message,
+    };
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::request::RequestAuth;
+    use parsec_interface::requests::AuthType;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// SHA-256 of "test"
+    const SHA256_OF_TEST: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    // Signs messages with empty signatures, counting them.
+    #[derive(Default)]
+    struct SigningProvider {
+        signatures: AtomicUsize,
+    }
+
+    impl Provide for SigningProvider {
+        fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+            Ok((
+                ProviderInfo {
+                    uuid: Uuid::nil(),
+                    description: String::new(),
+                    vendor: String::new(),
+                    version_maj: 0,
+                    version_min: 0,
+                    version_rev: 0,
+                    id: ProviderId::MbedCrypto,
+                },
+                HashSet::new(),
+            ))
+        }
+
+        fn list_keys(
+            &self,
+            _application_identity: &ApplicationIdentity,
+            _op: list_keys::Operation,
+        ) -> Result<list_keys::Result> {
+            Ok(list_keys::Result { keys: Vec::new() })
+        }
+
+        fn list_clients(&self, _op: list_clients::Operation) -> Result<list_clients::Result> {
+            Ok(list_clients::Result {
+                clients: Vec::new(),
+            })
+        }
+
+        fn psa_sign_message(
+            &self,
+            _application_identity: &ApplicationIdentity,
+            _op: psa_sign_message::Operation,
+        ) -> Result<psa_sign_message::Result> {
+            let _ = self.signatures.fetch_add(1, Ordering::Relaxed);
+            Ok(psa_sign_message::Result {
+                signature: Vec::new().into(),
+            })
+        }
+    }
+
+    /// Handler of the provider, the key "release" of "builder" being restricted to the digest of
+    /// "test"
+    fn handler(provider: Arc<SigningProvider>) -> BackEndHandler {
+        let signing_policies = SigningPolicies::new(
+            &SigningPoliciesConfig {
+                policy: Some(vec![SigningPolicyConfig {
+                    owner: String::from("builder"),
+                    key_name: String::from("release"),
+                    digests: Some(vec![String::from(SHA256_OF_TEST)]),
+                    digests_file: None,
+                }]),
+            },
+            AuthType::Direct,
+        )
+        .unwrap();
+        BackEndHandlerBuilder::new()
+            .with_provider(provider)
+            .with_converter(Box::new(ProtobufConverter {}))
+            .with_provider_id(ProviderId::MbedCrypto)
+            .with_content_type(BodyType::Protobuf)
+            .with_accept_type(BodyType::Protobuf)
+            .with_signing_policies(Arc::new(signing_policies))
+            .build()
+            .unwrap()
+    }
+
+    fn sign_message(handler: &BackEndHandler, key_name: &str, message: &[u8]) -> ResponseStatus {
+        let operation = NativeOperation::PsaSignMessage(psa_sign_message::Operation {
+            key_name: String::from(key_name),
+            alg: AsymmetricSignature::Ecdsa {
+                hash_alg: SignHash::Specific(Hash::Sha256),
+            },
+            message: message.to_vec().into(),
+        });
+        let request = Request {
+            header: RequestHeader {
+                provider: ProviderId::MbedCrypto,
+                session: 0,
+                content_type: BodyType::Protobuf,
+                accept_type: BodyType::Protobuf,
+                auth_type: AuthType::Direct,
+                opcode: Opcode::PsaSignMessage,
+            },
+            body: ProtobufConverter {}.operation_to_body(operation).unwrap(),
+            auth: RequestAuth::new(Vec::new()),
+        };
+        let app = Application::new(
+            ApplicationIdentity::new(String::from("builder"), AuthType::Direct),
+            false,
+        );
+        handler.execute_request(request, Some(app)).header.status
+    }
+
+    fn generate_key(handler: &BackEndHandler, key_name: &str) -> ResponseStatus {
+        let operation = NativeOperation::PsaGenerateKey(psa_generate_key::Operation {
+            key_name: String::from(key_name),
+            attributes: Attributes {
+                lifetime: Lifetime::Persistent,
+                key_type: Type::RawData,
+                bits: 256,
+                policy: Policy {
+                    usage_flags: UsageFlags::default(),
+                    permitted_algorithms: Algorithm::None,
+                },
+            },
+        });
+        let request = Request {
+            header: RequestHeader {
+                provider: ProviderId::MbedCrypto,
+                session: 0,
+                content_type: BodyType::Protobuf,
+                accept_type: BodyType::Protobuf,
+                auth_type: AuthType::Direct,
+                opcode: Opcode::PsaGenerateKey,
+            },
+            body: ProtobufConverter {}.operation_to_body(operation).unwrap(),
+            auth: RequestAuth::new(Vec::new()),
+        };
+        let app = Application::new(
+            ApplicationIdentity::new(String::from("builder"), AuthType::Direct),
+            false,
+        );
+        handler.execute_request(request, Some(app)).header.status
+    }
+
+    #[test]
+    fn keys_are_not_created_with_suffixes() {
+        let handler = handler(Arc::new(SigningProvider::default()));
+        assert_eq!(
+            generate_key(&handler, "key#jws"),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+        assert_eq!(
+            generate_key(&handler, "key#template=web"),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+        // The provider, which can not generate keys, is reached with other names.
+        assert_eq!(
+            generate_key(&handler, "key#1"),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn messages_of_restricted_keys_are_checked() {
+        let provider = Arc::new(SigningProvider::default());
+        let handler = handler(provider.clone());
+        assert_eq!(
+            sign_message(&handler, "release", b"test"),
+            ResponseStatus::Success
+        );
+        assert_eq!(
+            sign_message(&handler, "release", b"other image"),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        assert_eq!(provider.signatures.load(Ordering::Relaxed), 1);
+    }
+
+    /// Check that the message of the restricted key is not signed with the suffix, even if its
+    /// digest is permitted, while the one of another key reaches the provider, which can not wrap
+    /// it.
+    #[cfg(any(
+        feature = "jws-signing",
+        feature = "cose-signing",
+        feature = "tls13-signing"
+    ))]
+    fn check_wrapped_messages_are_refused(suffix: &str) {
+        let provider = Arc::new(SigningProvider::default());
+        let handler = handler(provider.clone());
+        assert_eq!(
+            sign_message(&handler, &format!("release{}", suffix), b"test"),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        assert_eq!(
+            sign_message(&handler, &format!("other{}", suffix), b"test"),
+            ResponseStatus::PsaErrorNotSupported
+        );
+        assert_eq!(provider.signatures.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(feature = "jws-signing")]
+    #[test]
+    fn jws_of_restricted_keys_are_refused() {
+        check_wrapped_messages_are_refused(key_suffixes::JWS);
+    }
+
+    #[cfg(feature = "cose-signing")]
+    #[test]
+    fn cose_sign1_of_restricted_keys_are_refused() {
+        check_wrapped_messages_are_refused(key_suffixes::COSE_SIGN1);
+    }
+
+    #[cfg(feature = "tls13-signing")]
+    #[test]
+    fn tls13_signatures_of_restricted_keys_are_refused() {
+        check_wrapped_messages_are_refused(key_suffixes::TLS13_SERVER);
+        check_wrapped_messages_are_refused(key_suffixes::TLS13_CLIENT);
+    }
+}
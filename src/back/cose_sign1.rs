@@ -0,0 +1,227 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! COSE single signer signatures
+//!
+//! Devices emitting attestation evidence, such as Entity Attestation Tokens, can have their
+//! COSE_Sign1 structures (RFC 9052) made entirely by the service: a `PsaSignMessage` request on the
+//! key name suffixed with `#cose-sign1` signs its message as the payload, and the signature
+//! returned is the tagged COSE_Sign1 structure, with the payload attached and no external data.
+//!
+//! As for JSON Web Signatures, the protected header only holds the `alg` parameter, derived from
+//! the key: the algorithm of the request must be the one the key permits, with a specific hash,
+//! and ECDSA keys must be on the curve matching the hash, for example P-384 for ES384. The key must
+//! be permitted to sign hashes.
+use crate::utils::cbor::{self, Value};
+use crate::utils::cose;
+use crate::utils::key_suffixes;
+use log::error;
+use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_key_attributes::{Attributes, EccFamily, Type};
+use parsec_interface::requests::{ResponseStatus, Result};
+use ring::digest;
+
+/// Tag of the COSE_Sign1 structures
+const COSE_SIGN1_TAG: u64 = 18;
+
+/// Context of the signatures of COSE_Sign1 structures
+const SIGNATURE1_CONTEXT: &str = "Signature1";
+
+/// Smallest RSA keys permitted by RFC 8230
+const MIN_RSA_BITS: usize = 2048;
+
+/// Remove the COSE_Sign1 suffix from the key name, returning whether it had it.
+pub fn strip_from(key_name: &mut String) -> bool {
+    key_suffixes::strip_flag(key_name, key_suffixes::COSE_SIGN1)
+}
+
+/// COSE algorithm of the signatures made with the algorithm by the key of the given attributes,
+/// with the hash of the algorithm
+pub fn algorithm(attributes: &Attributes, alg: AsymmetricSignature) -> Result<(i64, Hash)> {
+    if attributes.policy.permitted_algorithms != Algorithm::AsymmetricSignature(alg) {
+        error!("COSE_Sign1 structures can only be signed with the algorithm the key permits.");
+        return Err(ResponseStatus::PsaErrorNotPermitted);
+    }
+    let algorithm = match (alg, attributes.key_type, attributes.bits) {
+        (
+            AsymmetricSignature::RsaPkcs1v15Sign {
+                hash_alg: SignHash::Specific(hash),
+            }
+            | AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Specific(hash),
+            },
+            Type::RsaKeyPair,
+            bits,
+        ) if bits >= MIN_RSA_BITS => {
+            cose::signature_algorithm_identifier(alg).map(|identifier| (identifier, hash))
+        }
+        (
+            AsymmetricSignature::Ecdsa {
+                hash_alg: SignHash::Specific(hash),
+            }
+            | AsymmetricSignature::DeterministicEcdsa {
+                hash_alg: SignHash::Specific(hash),
+            },
+            Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1,
+            },
+            bits,
+        ) => match (bits, hash) {
+            // Deterministic signatures are verified as any other.
+            (256, Hash::Sha256) | (384, Hash::Sha384) | (521, Hash::Sha512) => {
+                cose::signature_algorithm_identifier(AsymmetricSignature::Ecdsa {
+                    hash_alg: hash.into(),
+                })
+                .map(|identifier| (identifier, hash))
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    algorithm.ok_or_else(|| {
+        error!(
+            "No COSE algorithm signs with {:?} and a {}-bit {:?} key.",
+            alg, attributes.bits, attributes.key_type
+        );
+        ResponseStatus::PsaErrorNotSupported
+    })
+}
+
+/// Encoded protected header of the structures signed with the COSE algorithm
+pub fn protected_header(algorithm: i64) -> Vec<u8> {
+    cbor::encode(&Value::labelled(vec![(
+        cose::HEADER_ALGORITHM,
+        Value::Integer(algorithm),
+    )]))
+}
+
+/// Encoded `Sig_structure` of the protected header and payload, without external data
+fn to_be_signed(protected_header: &[u8], payload: &[u8]) -> Vec<u8> {
+    cbor::encode(&Value::Array(vec![
+        Value::Text(String::from(SIGNATURE1_CONTEXT)),
+        Value::bytes(protected_header),
+        Value::bytes(&[]),
+        Value::bytes(payload),
+    ]))
+}
+
+/// Hash of the `Sig_structure` of the protected header and payload, to be signed
+pub fn digest(hash: Hash, protected_header: &[u8], payload: &[u8]) -> Vec<u8> {
+    let to_be_signed = to_be_signed(protected_header, payload);
+    let algorithm = match hash {
+        Hash::Sha384 => &digest::SHA384,
+        Hash::Sha512 => &digest::SHA512,
+        _ => &digest::SHA256,
+    };
+    digest::digest(algorithm, &to_be_signed).as_ref().to_vec()
+}
+
+/// Tagged COSE_Sign1 structure of the protected header, payload and signature, in the PSA format
+pub fn encode(protected_header: &[u8], payload: &[u8], signature: &[u8]) -> Vec<u8> {
+    cbor::encode_tagged(
+        COSE_SIGN1_TAG,
+        &Value::Array(vec![
+            Value::bytes(protected_header),
+            Value::Map(Vec::new()),
+            Value::bytes(payload),
+            Value::bytes(signature),
+        ]),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_helpers::attributes;
+
+    fn p256() -> Type {
+        Type::EccKeyPair {
+            curve_family: EccFamily::SecpR1,
+        }
+    }
+
+    #[test]
+    fn strip_suffix() {
+        let mut key_name = String::from("attestation#cose-sign1");
+        assert!(strip_from(&mut key_name));
+        assert_eq!(key_name, "attestation");
+        assert!(!strip_from(&mut key_name));
+    }
+
+    #[test]
+    fn ecdsa_algorithm_matches_curve() {
+        let deterministic = AsymmetricSignature::DeterministicEcdsa {
+            hash_alg: Hash::Sha256.into(),
+        };
+        assert_eq!(
+            algorithm(&attributes(p256(), 256, deterministic), deterministic),
+            Ok((-7, Hash::Sha256))
+        );
+        assert_eq!(
+            algorithm(&attributes(p256(), 384, deterministic), deterministic),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[test]
+    fn rsa_algorithm_needs_large_keys() {
+        let pss = AsymmetricSignature::RsaPss {
+            hash_alg: Hash::Sha384.into(),
+        };
+        assert_eq!(
+            algorithm(&attributes(Type::RsaKeyPair, 3072, pss), pss),
+            Ok((-38, Hash::Sha384))
+        );
+        assert_eq!(
+            algorithm(&attributes(Type::RsaKeyPair, 1024, pss), pss),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[test]
+    fn algorithm_must_be_permitted() {
+        let deterministic = AsymmetricSignature::DeterministicEcdsa {
+            hash_alg: Hash::Sha256.into(),
+        };
+        let pss = AsymmetricSignature::RsaPss {
+            hash_alg: Hash::Sha384.into(),
+        };
+        assert_eq!(
+            algorithm(&attributes(p256(), 256, deterministic), pss),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn sig_structure_known_answer() {
+        // RFC 9052, C.2.1: ES256 signature of "This is the content."
+        let protected_header = protected_header(-7);
+        assert_eq!(protected_header, [0xa1, 0x01, 0x26]);
+        let mut expected = vec![0x84, 0x6a];
+        expected.extend_from_slice(b"Signature1");
+        expected.extend_from_slice(&[0x43, 0xa1, 0x01, 0x26, 0x40, 0x54]);
+        expected.extend_from_slice(b"This is the content.");
+        assert_eq!(
+            to_be_signed(&protected_header, b"This is the content."),
+            expected
+        );
+        assert_eq!(
+            digest(Hash::Sha256, &protected_header, b"This is the content."),
+            [
+                0x4c, 0x33, 0x63, 0xb4, 0x99, 0xe1, 0xda, 0xc4, 0xaa, 0xfc, 0x8d, 0x69, 0x23, 0xf1,
+                0xca, 0x65, 0x77, 0xdf, 0xda, 0x80, 0xda, 0x24, 0xe5, 0x4f, 0xb9, 0x24, 0x24, 0x90,
+                0x64, 0x82, 0x7c, 0x88,
+            ]
+        );
+    }
+
+    #[test]
+    fn tagged_structure() {
+        assert_eq!(
+            encode(&protected_header(-7), b"claims", &[0x55; 2]),
+            [
+                0xd2, 0x84, 0x43, 0xa1, 0x01, 0x26, 0xa0, 0x46, b'c', b'l', b'a', b'i', b'm', b's',
+                0x42, 0x55, 0x55,
+            ]
+        );
+    }
+}
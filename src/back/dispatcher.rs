@@ -5,8 +5,9 @@
 //! The dispatcher's role is to direct requests to the provider they specify, if
 //! said provider is available on the system, thus acting as a multiplexer.
 use super::backend_handler::BackEndHandler;
+use super::mirroring::Mirroring;
 use crate::authenticators::Application;
-use log::trace;
+use log::{trace, warn};
 use parsec_interface::requests::request::Request;
 use parsec_interface::requests::ProviderId;
 use parsec_interface::requests::{Response, ResponseStatus};
@@ -23,6 +24,7 @@ use std::io::{Error, ErrorKind, Result};
 #[derive(Debug)]
 pub struct Dispatcher {
     backends: HashMap<ProviderId, BackEndHandler>,
+    mirroring: Option<Mirroring>,
 }
 
 impl Dispatcher {
@@ -38,28 +40,62 @@ impl Dispatcher {
             if let Err(status) = backend.is_capable(&request) {
                 Response::from_request_header(request.header, status)
             } else {
+                match self
+                    .mirroring
+                    .filter(|mirroring| mirroring.is_mirrored(&request))
                 {
-                    let response = backend.execute_request(request, app);
-                    trace!("execute_request egress");
-                    response
+                    Some(mirroring) => self.dispatch_mirrored(mirroring, backend, request, app),
+                    None => {
+                        let response = backend.execute_request(request, app);
+                        trace!("execute_request egress");
+                        response
+                    }
                 }
             }
         } else {
             Response::from_request_header(request.header, ResponseStatus::ProviderNotRegistered)
         }
     }
+
+    /// Execute a request and a copy of it on the mirror provider, comparing their responses.
+    /// Only the response of the provider the request was sent to is returned.
+    fn dispatch_mirrored(
+        &self,
+        mirroring: Mirroring,
+        backend: &BackEndHandler,
+        request: Request,
+        app: Option<Application>,
+    ) -> Response {
+        let header = request.header;
+        let (request, mirrored) = match mirroring.mirror_request(request) {
+            Ok(requests) => requests,
+            Err(status) => return Response::from_request_header(header, status),
+        };
+        let response = backend.execute_request(request, app.clone());
+        trace!("execute_request egress");
+        // The mirror backend was checked when the dispatcher was built.
+        if let Some(mirror_backend) = self.backends.get(&mirroring.mirror()) {
+            let mirrored_response = mirror_backend.execute_request(mirrored, app.clone());
+            mirroring.compare(app.as_ref(), &response, &mirrored_response);
+        }
+        response
+    }
 }
 
 /// `Dispatcher` builder
 #[derive(Debug, Default)]
 pub struct DispatcherBuilder {
     backends: Option<HashMap<ProviderId, BackEndHandler>>,
+    mirroring: Option<Mirroring>,
 }
 
 impl DispatcherBuilder {
     /// Create a new Dispatcher builder
     pub fn new() -> Self {
-        DispatcherBuilder { backends: None }
+        DispatcherBuilder {
+            backends: None,
+            mirroring: None,
+        }
     }
 
     /// Add a BackEndHandler with a specific Provider ID to the dispatcher
@@ -84,12 +120,32 @@ impl DispatcherBuilder {
         self
     }
 
+    /// Mirror the read-only operations of a provider to another one
+    pub fn with_mirroring(mut self, mirroring: Mirroring) -> Self {
+        self.mirroring = Some(mirroring);
+
+        self
+    }
+
     /// Build the builder into a dispatcher
     pub fn build(self) -> Result<Dispatcher> {
+        let backends = self
+            .backends
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "backends is missing"))?;
+        // A provider skipped on this platform disables the mirroring, not the service.
+        let mirroring = self.mirroring.filter(|mirroring| {
+            let registered = backends.contains_key(&mirroring.mirror());
+            if !registered {
+                warn!(
+                    "The mirror provider {} is not registered, requests are not mirrored.",
+                    mirroring.mirror()
+                );
+            }
+            registered
+        });
         Ok(Dispatcher {
-            backends: self
-                .backends
-                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "backends is missing"))?,
+            backends,
+            mirroring,
         })
     }
 }
@@ -0,0 +1,301 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Selection of the nonces of ECDSA signatures
+//!
+//! Each ECDSA signature needs a secret nonce. Randomized ECDSA draws it from a random number
+//! generator, and a device whose generator repeats a value leaks the key; deterministic ECDSA
+//! (RFC 6979) derives it from the key and the hash signed, which also makes signatures
+//! reproducible. Clients pick the nonces of a key with its permitted algorithm.
+//!
+//! The service can instead be configured to select them for all the ECDSA keys created, where the
+//! provider offers the selected ones. The permitted algorithm of the key is changed accordingly, so
+//! that the nonces used are recorded with its attributes. Signing and verification requests naming
+//! the other mode are then done in the mode of the key: the signatures of both are verified in the
+//! same way.
+use crate::utils::config::{EcdsaNonceMode, EcdsaNoncesConfig};
+use log::{error, warn};
+use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, SignHash};
+use parsec_interface::operations::psa_key_attributes::Attributes;
+use parsec_interface::requests::{ProviderId, ResponseStatus, Result};
+
+/// Whether a provider can sign with deterministic ECDSA
+fn offers_deterministic(provider_id: ProviderId) -> bool {
+    matches!(
+        provider_id,
+        ProviderId::MbedCrypto | ProviderId::TrustedService
+    )
+}
+
+/// Nonces and hash of an ECDSA algorithm
+pub fn nonce_mode(alg: AsymmetricSignature) -> Option<(EcdsaNonceMode, SignHash)> {
+    match alg {
+        AsymmetricSignature::Ecdsa { hash_alg } => Some((EcdsaNonceMode::Randomized, hash_alg)),
+        AsymmetricSignature::DeterministicEcdsa { hash_alg } => {
+            Some((EcdsaNonceMode::Deterministic, hash_alg))
+        }
+        _ => None,
+    }
+}
+
+fn ecdsa(mode: EcdsaNonceMode, hash_alg: SignHash) -> AsymmetricSignature {
+    match mode {
+        EcdsaNonceMode::Randomized => AsymmetricSignature::Ecdsa { hash_alg },
+        EcdsaNonceMode::Deterministic => AsymmetricSignature::DeterministicEcdsa { hash_alg },
+    }
+}
+
+/// Nonces selected for the ECDSA keys
+#[derive(Copy, Clone, Debug)]
+pub struct EcdsaNonces {
+    mode: EcdsaNonceMode,
+    strict: bool,
+}
+
+impl From<EcdsaNoncesConfig> for EcdsaNonces {
+    fn from(config: EcdsaNoncesConfig) -> Self {
+        EcdsaNonces {
+            mode: config.mode,
+            strict: config.strict.unwrap_or(false),
+        }
+    }
+}
+
+impl EcdsaNonces {
+    /// Select the nonces of an ECDSA key pair about to be created in a provider, changing its
+    /// permitted algorithm.
+    ///
+    /// # Errors
+    /// * `PsaErrorNotSupported` in strict mode, if the provider does not offer the selected nonces
+    pub fn select(&self, provider_id: ProviderId, attributes: &mut Attributes) -> Result<()> {
+        if !attributes.key_type.is_ecc_key_pair() {
+            return Ok(());
+        }
+        let (requested, hash_alg) = match attributes.policy.permitted_algorithms {
+            Algorithm::AsymmetricSignature(alg) => match nonce_mode(alg) {
+                Some(mode) => mode,
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        let mode =
+            if self.mode == EcdsaNonceMode::Deterministic && !offers_deterministic(provider_id) {
+                if self.strict {
+                    error!(
+                        "ECDSA key refused: the {} does not offer deterministic nonces.",
+                        provider_id
+                    );
+                    return Err(ResponseStatus::PsaErrorNotSupported);
+                }
+                EcdsaNonceMode::Randomized
+            } else {
+                self.mode
+            };
+        if mode != requested {
+            warn!(
+                "ECDSA key created with {:?} nonces instead of the {:?} ones requested.",
+                mode, requested
+            );
+        }
+        attributes.policy.permitted_algorithms = ecdsa(mode, hash_alg).into();
+        Ok(())
+    }
+
+    /// Change the ECDSA algorithm of a signing or verification request to the nonces recorded in
+    /// the permitted algorithm of the key.
+    pub fn adapt(&self, permitted_algorithm: Algorithm, alg: &mut AsymmetricSignature) {
+        if let (Algorithm::AsymmetricSignature(permitted), Some((_, hash_alg))) =
+            (permitted_algorithm, nonce_mode(*alg))
+        {
+            if let Some((mode, permitted_hash)) = nonce_mode(permitted) {
+                // A wildcard hash in the policy stays satisfied by the hash of the request.
+                if permitted_hash == hash_alg || permitted_hash == SignHash::Any {
+                    *alg = ecdsa(mode, hash_alg);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::psa_algorithm::{Hash, KeyAgreement, RawKeyAgreement};
+    use parsec_interface::operations::psa_key_attributes::{
+        EccFamily, Lifetime, Policy, Type, UsageFlags,
+    };
+
+    fn attributes(key_type: Type, permitted_algorithms: Algorithm) -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type,
+            bits: 256,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms,
+            },
+        }
+    }
+
+    fn ecc_key_pair() -> Type {
+        Type::EccKeyPair {
+            curve_family: EccFamily::SecpR1,
+        }
+    }
+
+    fn randomized(hash_alg: SignHash) -> AsymmetricSignature {
+        AsymmetricSignature::Ecdsa { hash_alg }
+    }
+
+    fn deterministic(hash_alg: SignHash) -> AsymmetricSignature {
+        AsymmetricSignature::DeterministicEcdsa { hash_alg }
+    }
+
+    fn nonces(mode: EcdsaNonceMode, strict: Option<bool>) -> EcdsaNonces {
+        EcdsaNonces::from(EcdsaNoncesConfig { mode, strict })
+    }
+
+    /// Permitted algorithm of an ECDSA key pair with randomized nonces, once selected
+    fn selected(nonces: EcdsaNonces, provider_id: ProviderId) -> Result<Algorithm> {
+        let mut attributes = attributes(ecc_key_pair(), randomized(Hash::Sha256.into()).into());
+        nonces.select(provider_id, &mut attributes)?;
+        Ok(attributes.policy.permitted_algorithms)
+    }
+
+    #[test]
+    fn deterministic_nonces_are_selected_where_offered() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, Some(true));
+        for provider_id in &[ProviderId::MbedCrypto, ProviderId::TrustedService] {
+            assert_eq!(
+                selected(nonces, *provider_id),
+                Ok(deterministic(Hash::Sha256.into()).into())
+            );
+        }
+    }
+
+    #[test]
+    fn strict_mode_refuses_providers_without_deterministic_nonces() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, Some(true));
+        for provider_id in &[
+            ProviderId::Tpm,
+            ProviderId::Pkcs11,
+            ProviderId::CryptoAuthLib,
+        ] {
+            assert_eq!(
+                selected(nonces, *provider_id),
+                Err(ResponseStatus::PsaErrorNotSupported)
+            );
+        }
+    }
+
+    #[test]
+    fn randomized_nonces_are_used_where_deterministic_are_not_offered() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, None);
+        assert_eq!(
+            selected(nonces, ProviderId::Tpm),
+            Ok(randomized(Hash::Sha256.into()).into())
+        );
+    }
+
+    #[test]
+    fn randomized_nonces_are_selected() {
+        let nonces = nonces(EcdsaNonceMode::Randomized, Some(true));
+        let mut attributes = attributes(ecc_key_pair(), deterministic(Hash::Sha384.into()).into());
+        nonces
+            .select(ProviderId::MbedCrypto, &mut attributes)
+            .unwrap();
+        assert_eq!(
+            attributes.policy.permitted_algorithms,
+            randomized(Hash::Sha384.into()).into()
+        );
+    }
+
+    #[test]
+    fn wildcard_hash_is_kept() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, None);
+        let mut attributes = attributes(ecc_key_pair(), randomized(SignHash::Any).into());
+        nonces
+            .select(ProviderId::MbedCrypto, &mut attributes)
+            .unwrap();
+        assert_eq!(
+            attributes.policy.permitted_algorithms,
+            deterministic(SignHash::Any).into()
+        );
+    }
+
+    #[test]
+    fn other_keys_are_left_unchanged() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, Some(true));
+        let ecdh = Algorithm::KeyAgreement(KeyAgreement::Raw(RawKeyAgreement::Ecdh));
+        let rsa = AsymmetricSignature::RsaPkcs1v15Sign {
+            hash_alg: Hash::Sha256.into(),
+        }
+        .into();
+        let ecc_public_key = Type::EccPublicKey {
+            curve_family: EccFamily::SecpR1,
+        };
+        for (key_type, alg) in &[
+            (ecc_key_pair(), ecdh),
+            (Type::RsaKeyPair, rsa),
+            (ecc_public_key, randomized(Hash::Sha256.into()).into()),
+        ] {
+            let mut attributes = attributes(*key_type, *alg);
+            nonces.select(ProviderId::Tpm, &mut attributes).unwrap();
+            assert_eq!(attributes.policy.permitted_algorithms, *alg);
+        }
+    }
+
+    #[test]
+    fn requests_take_the_nonces_of_the_key() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, None);
+        let mut alg = randomized(Hash::Sha256.into());
+        nonces.adapt(deterministic(Hash::Sha256.into()).into(), &mut alg);
+        assert_eq!(alg, deterministic(Hash::Sha256.into()));
+        let mut alg = deterministic(Hash::Sha256.into());
+        nonces.adapt(randomized(Hash::Sha256.into()).into(), &mut alg);
+        assert_eq!(alg, randomized(Hash::Sha256.into()));
+    }
+
+    #[test]
+    fn requests_for_another_hash_are_left_unchanged() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, None);
+        // Left for the provider to refuse
+        let mut alg = randomized(Hash::Sha384.into());
+        nonces.adapt(deterministic(Hash::Sha256.into()).into(), &mut alg);
+        assert_eq!(alg, randomized(Hash::Sha384.into()));
+    }
+
+    #[test]
+    fn wildcard_hash_policy_adapts_any_hash() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, None);
+        let mut alg = randomized(Hash::Sha384.into());
+        nonces.adapt(deterministic(SignHash::Any).into(), &mut alg);
+        assert_eq!(alg, deterministic(Hash::Sha384.into()));
+    }
+
+    #[test]
+    fn requests_for_other_keys_are_left_unchanged() {
+        let nonces = nonces(EcdsaNonceMode::Deterministic, None);
+        let mut alg = randomized(Hash::Sha256.into());
+        let rsa = AsymmetricSignature::RsaPkcs1v15Sign {
+            hash_alg: Hash::Sha256.into(),
+        };
+        nonces.adapt(rsa.into(), &mut alg);
+        assert_eq!(alg, randomized(Hash::Sha256.into()));
+        let mut alg = rsa;
+        nonces.adapt(deterministic(Hash::Sha256.into()).into(), &mut alg);
+        assert_eq!(alg, rsa);
+    }
+
+    #[test]
+    fn only_ecdsa_has_nonces() {
+        assert_eq!(
+            nonce_mode(deterministic(Hash::Sha256.into())),
+            Some((EcdsaNonceMode::Deterministic, Hash::Sha256.into()))
+        );
+        assert_eq!(
+            nonce_mode(randomized(SignHash::Any)),
+            Some((EcdsaNonceMode::Randomized, SignHash::Any))
+        );
+        assert_eq!(nonce_mode(AsymmetricSignature::RsaPkcs1v15SignRaw), None);
+    }
+}
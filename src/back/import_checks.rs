@@ -0,0 +1,484 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Checks on the key material of imported keys
+//!
+//! The service can be configured to refuse imported keys which are well encoded but weak: RSA
+//! keys with small, shared or close factors or with a low public exponent, elliptic curve points
+//! which are not on their curve and symmetric keys showing little entropy. The checks are done
+//! before the key data is handed to a provider, whichever it is.
+use crate::providers::utils::key_validation::{
+    parse_rsa_private_key, parse_rsa_public_key, validate_ecc_public_key, RsaPublicKeyComponents,
+};
+use crate::providers::utils::weak_keys::{self, FERMAT_ROUNDS};
+use crate::utils::config::ImportChecksConfig;
+use log::{error, warn};
+use num_bigint::BigUint;
+use parsec_interface::operations::psa_key_attributes::{Attributes, Type};
+use parsec_interface::requests::{ResponseStatus, Result};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Smallest RSA public exponent accepted by default, the one recommended by FIPS 186-4
+const DEFAULT_MIN_RSA_PUBLIC_EXPONENT: u64 = 65537;
+
+/// Number of RSA moduli kept by default to find shared factors
+const DEFAULT_SHARED_FACTOR_HISTORY: usize = 64;
+
+/// Smallest estimate of the entropy of symmetric keys accepted by default, in bits
+const DEFAULT_MIN_SYMMETRIC_KEY_ENTROPY: u32 = 16;
+
+/// Checks done on the key data of `PsaImportKey` requests
+#[derive(Debug)]
+pub struct ImportChecks {
+    min_rsa_public_exponent: BigUint,
+    fermat_check: bool,
+    shared_factor_history: usize,
+    min_symmetric_key_entropy: u32,
+    // Moduli of the last RSA keys accepted, the oldest first
+    moduli: Mutex<VecDeque<BigUint>>,
+}
+
+impl ImportChecks {
+    /// Create the checks from their configuration
+    pub fn new(config: &ImportChecksConfig) -> Self {
+        ImportChecks {
+            min_rsa_public_exponent: BigUint::from(
+                config
+                    .min_rsa_public_exponent
+                    .unwrap_or(DEFAULT_MIN_RSA_PUBLIC_EXPONENT),
+            ),
+            fermat_check: config.rsa_fermat_check.unwrap_or(false),
+            shared_factor_history: config
+                .rsa_shared_factor_history
+                .unwrap_or(DEFAULT_SHARED_FACTOR_HISTORY),
+            min_symmetric_key_entropy: config
+                .min_symmetric_key_entropy
+                .unwrap_or(DEFAULT_MIN_SYMMETRIC_KEY_ENTROPY),
+            moduli: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Check the data of a key about to be imported with the given attributes.
+    ///
+    /// # Errors
+    /// * `PsaErrorInvalidArgument` if the key data is malformed or not a valid key
+    /// * `PsaErrorNotPermitted` if the key is valid but weaker than configured
+    pub fn check(&self, attributes: &Attributes, data: &[u8]) -> Result<()> {
+        match attributes.key_type {
+            Type::RsaPublicKey => self.check_rsa(parse_rsa_public_key(data)?, None),
+            Type::RsaKeyPair => {
+                let key = parse_rsa_private_key(data)?;
+                self.check_rsa(key.public_key(), Some((&key.prime_1, &key.prime_2)))
+            }
+            Type::EccPublicKey { curve_family } => {
+                let bits = validate_ecc_public_key(data, curve_family, attributes.bits)?;
+                match weak_keys::is_on_curve(data, curve_family, bits) {
+                    Some(false) => {
+                        error!("Imported ECC public key is not a point of its curve.");
+                        Err(ResponseStatus::PsaErrorInvalidArgument)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Type::Aes
+            | Type::Des
+            | Type::Camellia
+            | Type::Arc4
+            | Type::Chacha20
+            | Type::Hmac
+            | Type::Derive => {
+                if weak_keys::estimate_entropy(data) < f64::from(self.min_symmetric_key_entropy) {
+                    warn!(
+                        "Imported {} refused: its estimated entropy is below {} bits.",
+                        attributes.key_type, self.min_symmetric_key_entropy
+                    );
+                    return Err(ResponseStatus::PsaErrorNotPermitted);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_rsa(&self, key: RsaPublicKeyComponents, primes: Option<(&[u8], &[u8])>) -> Result<()> {
+        if BigUint::from_bytes_be(&key.public_exponent) < self.min_rsa_public_exponent {
+            warn!("Imported RSA key refused: its public exponent is below the configured floor.");
+            return Err(ResponseStatus::PsaErrorNotPermitted);
+        }
+
+        let modulus = BigUint::from_bytes_be(&key.modulus);
+        if weak_keys::small_factor(&modulus).is_some() {
+            error!("Imported RSA modulus has a small factor.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+
+        if self.fermat_check {
+            let close = match primes {
+                Some((prime_1, prime_2)) => weak_keys::primes_too_close(
+                    &BigUint::from_bytes_be(prime_1),
+                    &BigUint::from_bytes_be(prime_2),
+                    modulus.bits(),
+                ),
+                None => weak_keys::has_close_factors(&modulus, FERMAT_ROUNDS),
+            };
+            if close {
+                error!("Imported RSA modulus has factors too close to each other.");
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+        }
+
+        if self.shared_factor_history == 0 {
+            return Ok(());
+        }
+        let mut moduli = self.moduli.lock().expect("Import checks lock poisoned");
+        if moduli
+            .iter()
+            .any(|other| weak_keys::shares_factor(&modulus, other))
+        {
+            error!("Imported RSA modulus shares a factor with the modulus of another key.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+        // The same public key can be imported several times.
+        if !moduli.contains(&modulus) {
+            if moduli.len() == self.shared_factor_history {
+                let _ = moduli.pop_front();
+            }
+            moduli.push_back(modulus);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_helpers::hex;
+    use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash};
+    use parsec_interface::operations::psa_key_attributes::{
+        EccFamily, Lifetime, Policy, UsageFlags,
+    };
+
+    fn attributes(key_type: Type, bits: usize) -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type,
+            bits,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: Algorithm::AsymmetricSignature(
+                    AsymmetricSignature::RsaPkcs1v15Sign {
+                        hash_alg: Hash::Sha256.into(),
+                    },
+                ),
+            },
+        }
+    }
+
+    // RSAPublicKey DER encoding of a modulus and exponent, both shorter than 128 bytes
+    fn rsa_public_key(modulus: &[u8], exponent: &[u8]) -> Vec<u8> {
+        let integer = |value: &[u8]| {
+            let mut value = value.to_vec();
+            if value[0] & 0x80 != 0 {
+                value.insert(0, 0);
+            }
+            [vec![0x02, value.len() as u8], value].concat()
+        };
+        let body = [integer(modulus), integer(exponent)].concat();
+        [vec![0x30, body.len() as u8], body].concat()
+    }
+
+    /// Configuration with the defaults and the Fermat check enabled
+    fn config() -> ImportChecksConfig {
+        ImportChecksConfig {
+            min_rsa_public_exponent: None,
+            rsa_fermat_check: Some(true),
+            rsa_shared_factor_history: None,
+            min_symmetric_key_entropy: None,
+        }
+    }
+
+    fn rsa() -> Attributes {
+        attributes(Type::RsaPublicKey, 0)
+    }
+
+    // Products of 256-bit primes, all sharing one, the last one with a close one
+    fn modulus() -> Vec<u8> {
+        hex(
+            "bf8be0a95521d82c5db2e3d1bfe6d1e2a374c7c5d575178d941bc421fa618de6\
+             7ef9dc32025eac0cdd87b17df46d2ce20aa4b0e3abec3157f959f24b441dcbcb",
+        )
+    }
+
+    fn sharing_modulus() -> Vec<u8> {
+        hex(
+            "d386f195e040a2857d6f9e31947d7d5511213a29e0374542d2199100c520c3b0\
+             363d4cee2bbe6e7a1d71e9daef281ba0048da4d84d6b1f05d9f10e24ce3b4023",
+        )
+    }
+
+    fn close_modulus() -> Vec<u8> {
+        hex(
+            "bd6c71699d7f6b705c49ef89eedb618e596134fcfc7960dc4cb76f6e9d545f1c\
+             38bc16c57f3e17bc4c63f2894e4a85b27114861b338db8176431adb2e21992ab",
+        )
+    }
+
+    // Product of 256-bit primes sharing none with the others
+    fn unrelated_modulus() -> Vec<u8> {
+        hex(
+            "edc369316f0076bba03ba4c623a56cf3665ecf540a73cf01e7b0206585d82b24\
+             8cd595d5f22ea3c7f1d8b5347b902fce91e8ad6734c6d309c4af486351267bd5",
+        )
+    }
+
+    const EXPONENT: [u8; 3] = [0x01, 0x00, 0x01];
+
+    // RSAPrivateKey DER encodings of 512-bit key pairs, checked with `openssl rsa -check`
+    fn close_primes_key_pair() -> Vec<u8> {
+        hex(
+            "30820139020100024100acab78e0306fc02ee6d82ca9f3f7b25be616b8f7f69b6bb9f45de666d79b0f27\
+             93551453eee7322cd1d11a5b8f4efe17aa06255f60c7cc32479777ddd7e831dd0203010001024069fe\
+             60970e64ab0e9f7a3800a3d87b4e5a71bc5dcaae2174e702f53a5043c90aba1097553d2fc9b08552bc\
+             a5757f11efc501bd3c59b5390b6015e5b3c52c17e9022100d23f0824128b2f330c5c7fd0a6a3a45065\
+             13270e269e0d37f2a74de452e6b4bf022100d23f0824128b2f330c5c7fd0a6a3a4506513270e269e0d\
+             37f2a74de452e6b46302206fe59c2f96aefd2fee244ce27fce1865b165c02387ea169ef0469aee2a31\
+             1f8102200161f6bcbdfe7a55fc9bd31f5d2930587301b83d0895fb8245af41fce16ab0ff0220748cae\
+             4084b6e54b9a54ab0724b952bd432939d2f994d53d9f8ed4eb17b1fa75",
+        )
+    }
+
+    fn far_primes_key_pair() -> Vec<u8> {
+        hex(
+            "3082013b020100024100881690242d78cad41e12057b89698f351f8ecf04a05589fb4470a804af0a0805\
+             1d4df9fdf813d667841a3550e41bbf05e5a55b36ec46e9caed7b56ab9e6ca25502030100010240629485\
+             a1cf56bb06e12988d32e7d4b01de7462be252f119ef4baeedb45b8ec893ed4a7d5ae089d32704ccd3d5b\
+             fa838b10a21ca5dfe2fa386dda7793535b3159022100f6f675cc81e74ef5e8e25d940ed904759531985d\
+             5d9dc9f81818e811892f90670221008d116ece1738f7d93d9c172411e20b8f6b0d549b6f03675a1600a3\
+             5a099951e3022100df8beac7aad2f163a161be6936237568e61ad59bb6110d705887bb119c8784830220\
+             01a6a59455e33dcb56144095147c7fc422ed9a02e3aeb783ef63fc856e3d541f02210084b5985f5466b1\
+             efb307ba8fb7da29ed209fd1711bcaef410ddae75f494eddbc",
+        )
+    }
+
+    fn p256() -> Attributes {
+        attributes(
+            Type::EccPublicKey {
+                curve_family: EccFamily::SecpR1,
+            },
+            256,
+        )
+    }
+
+    // Generator of P-256, from SEC 2
+    fn p256_generator() -> Vec<u8> {
+        hex(
+            "046b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c2\
+             964fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51\
+             f5",
+        )
+    }
+
+    const RANDOM_AES_KEY: &[u8; 16] =
+        b"\x8e\x1f\xa4\x07\x5b\xd2\x39\xc6\x71\x0a\xee\x94\x2d\x63\xb8\x50";
+
+    #[test]
+    fn low_public_exponent_is_refused() {
+        let checks = ImportChecks::new(&config());
+        assert_eq!(
+            checks.check(&rsa(), &rsa_public_key(&modulus(), &[0x03])),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn public_exponent_floor_is_configurable() {
+        let checks = ImportChecks::new(&ImportChecksConfig {
+            min_rsa_public_exponent: Some(3),
+            ..config()
+        });
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&modulus(), &[0x03]))
+            .is_ok());
+    }
+
+    #[test]
+    fn same_public_key_is_imported_again() {
+        let checks = ImportChecks::new(&config());
+        for _ in 0..2 {
+            assert!(checks
+                .check(&rsa(), &rsa_public_key(&modulus(), &EXPONENT))
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn shared_factor_is_refused() {
+        let checks = ImportChecks::new(&config());
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&modulus(), &EXPONENT))
+            .is_ok());
+        assert_eq!(
+            checks.check(&rsa(), &rsa_public_key(&sharing_modulus(), &EXPONENT)),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn shared_factors_are_looked_for_in_the_history_only() {
+        let checks = ImportChecks::new(&ImportChecksConfig {
+            rsa_fermat_check: None,
+            rsa_shared_factor_history: Some(1),
+            ..config()
+        });
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&modulus(), &EXPONENT))
+            .is_ok());
+        // Pushes the first modulus out of the history
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&unrelated_modulus(), &EXPONENT))
+            .is_ok());
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&sharing_modulus(), &EXPONENT))
+            .is_ok());
+    }
+
+    #[test]
+    fn empty_history_disables_the_shared_factor_check() {
+        let checks = ImportChecks::new(&ImportChecksConfig {
+            rsa_shared_factor_history: Some(0),
+            ..config()
+        });
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&modulus(), &EXPONENT))
+            .is_ok());
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&sharing_modulus(), &EXPONENT))
+            .is_ok());
+    }
+
+    #[test]
+    fn close_factors_are_refused() {
+        let checks = ImportChecks::new(&config());
+        assert_eq!(
+            checks.check(&rsa(), &rsa_public_key(&close_modulus(), &EXPONENT)),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn close_factors_are_only_looked_for_if_configured() {
+        let checks = ImportChecks::new(&ImportChecksConfig {
+            rsa_fermat_check: None,
+            ..config()
+        });
+        assert!(checks
+            .check(&rsa(), &rsa_public_key(&close_modulus(), &EXPONENT))
+            .is_ok());
+    }
+
+    #[test]
+    fn small_factor_is_refused() {
+        let checks = ImportChecks::new(&config());
+        // A multiple of 3
+        let mut small_factor = vec![0xff; 64];
+        small_factor[63] = 0x03;
+        assert_eq!(
+            checks.check(&rsa(), &rsa_public_key(&small_factor, &EXPONENT)),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn key_pair_primes_are_compared() {
+        let checks = ImportChecks::new(&config());
+        let key_pair = attributes(Type::RsaKeyPair, 0);
+        assert_eq!(
+            checks.check(&key_pair, &close_primes_key_pair()),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+        assert!(checks.check(&key_pair, &far_primes_key_pair()).is_ok());
+    }
+
+    #[test]
+    fn malformed_rsa_keys_are_refused() {
+        let checks = ImportChecks::new(&config());
+        assert_eq!(
+            checks.check(&rsa(), &[0x30, 0x03, 0x02, 0x01, 0x01]),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn points_on_the_curve_are_accepted() {
+        let checks = ImportChecks::new(&config());
+        assert!(checks.check(&p256(), &p256_generator()).is_ok());
+    }
+
+    #[test]
+    fn points_off_the_curve_are_refused() {
+        let checks = ImportChecks::new(&config());
+        let mut point = p256_generator();
+        point[64] ^= 0x01;
+        assert_eq!(
+            checks.check(&p256(), &point),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn points_of_unknown_curves_are_not_checked() {
+        let checks = ImportChecks::new(&config());
+        let brainpool = attributes(
+            Type::EccPublicKey {
+                curve_family: EccFamily::BrainpoolPR1,
+            },
+            256,
+        );
+        assert!(checks.check(&brainpool, &p256_generator()).is_ok());
+    }
+
+    #[test]
+    fn low_entropy_symmetric_keys_are_refused() {
+        let checks = ImportChecks::new(&config());
+        let aes = attributes(Type::Aes, 128);
+        assert_eq!(
+            checks.check(&aes, &[0x5a; 16]),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            checks.check(&aes, &(0..16).collect::<Vec<u8>>()),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            checks.check(&attributes(Type::Hmac, 128), &[0x00; 16]),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn random_symmetric_keys_are_accepted() {
+        let checks = ImportChecks::new(&config());
+        assert!(checks
+            .check(&attributes(Type::Aes, 128), RANDOM_AES_KEY)
+            .is_ok());
+    }
+
+    #[test]
+    fn entropy_floor_is_configurable() {
+        let checks = ImportChecks::new(&ImportChecksConfig {
+            min_symmetric_key_entropy: Some(0),
+            ..config()
+        });
+        assert!(checks
+            .check(&attributes(Type::Aes, 128), &[0x5a; 16])
+            .is_ok());
+    }
+
+    #[test]
+    fn other_key_types_are_not_checked() {
+        let checks = ImportChecks::new(&config());
+        assert!(checks
+            .check(&attributes(Type::RawData, 0), &[0x00; 16])
+            .is_ok());
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! JSON Web Signatures
+//!
+//! Services issuing tokens can have their JSON Web Signatures (RFC 7515) made entirely by the
+//! service instead of building them around `PsaSignHash`: a `PsaSignMessage` request on the key
+//! name suffixed with `#jws` signs its message as the JWS payload, and the signature returned is
+//! the complete JWS compact serialization, `header.payload.signature`.
+//!
+//! The protected header only holds the `alg` parameter, which is derived from the key and not
+//! chosen by the client: the algorithm of the request must be the one the key permits, with a
+//! specific hash, and the key must be of the type and size the JWS algorithm requires, for example
+//! a P-384 key for ES384. The key must be permitted to sign hashes.
+use crate::utils::key_suffixes;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use log::error;
+use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_key_attributes::{Attributes, EccFamily, Type};
+use parsec_interface::requests::{ResponseStatus, Result};
+use ring::digest;
+
+/// Smallest RSA keys permitted by JWA (RFC 7518)
+const MIN_RSA_BITS: usize = 2048;
+
+/// Remove the JWS suffix from the key name, returning whether it had it.
+pub fn strip_from(key_name: &mut String) -> bool {
+    key_suffixes::strip_flag(key_name, key_suffixes::JWS)
+}
+
+/// JWS algorithm of the signatures made with the algorithm by the key of the given attributes,
+/// with the hash of the algorithm
+pub fn algorithm(
+    attributes: &Attributes,
+    alg: AsymmetricSignature,
+) -> Result<(&'static str, Hash)> {
+    if attributes.policy.permitted_algorithms != Algorithm::AsymmetricSignature(alg) {
+        error!("JSON Web Signatures can only be made with the algorithm the key permits.");
+        return Err(ResponseStatus::PsaErrorNotPermitted);
+    }
+    let name = match (alg, attributes.key_type, attributes.bits) {
+        (
+            AsymmetricSignature::RsaPkcs1v15Sign {
+                hash_alg: SignHash::Specific(hash),
+            },
+            Type::RsaKeyPair,
+            bits,
+        ) if bits >= MIN_RSA_BITS => match hash {
+            Hash::Sha256 => Some(("RS256", hash)),
+            Hash::Sha384 => Some(("RS384", hash)),
+            Hash::Sha512 => Some(("RS512", hash)),
+            _ => None,
+        },
+        (
+            AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Specific(hash),
+            },
+            Type::RsaKeyPair,
+            bits,
+        ) if bits >= MIN_RSA_BITS => match hash {
+            Hash::Sha256 => Some(("PS256", hash)),
+            Hash::Sha384 => Some(("PS384", hash)),
+            Hash::Sha512 => Some(("PS512", hash)),
+            _ => None,
+        },
+        (
+            AsymmetricSignature::Ecdsa {
+                hash_alg: SignHash::Specific(hash),
+            }
+            | AsymmetricSignature::DeterministicEcdsa {
+                hash_alg: SignHash::Specific(hash),
+            },
+            Type::EccKeyPair { curve_family },
+            bits,
+        ) => match (curve_family, bits, hash) {
+            (EccFamily::SecpR1, 256, Hash::Sha256) => Some(("ES256", hash)),
+            (EccFamily::SecpR1, 384, Hash::Sha384) => Some(("ES384", hash)),
+            (EccFamily::SecpR1, 521, Hash::Sha512) => Some(("ES512", hash)),
+            (EccFamily::SecpK1, 256, Hash::Sha256) => Some(("ES256K", hash)),
+            _ => None,
+        },
+        _ => None,
+    };
+    name.ok_or_else(|| {
+        error!(
+            "No JSON Web Signature algorithm signs with {:?} and a {}-bit {:?} key.",
+            alg, attributes.bits, attributes.key_type
+        );
+        ResponseStatus::PsaErrorNotSupported
+    })
+}
+
+/// JWS signing input of the payload: its protected header and itself, encoded
+pub fn signing_input(name: &str, payload: &[u8]) -> String {
+    let header = format!("{{\"alg\":\"{}\"}}", name);
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header),
+        URL_SAFE_NO_PAD.encode(payload)
+    )
+}
+
+/// Hash of the signing input, to be signed
+pub fn digest(hash: Hash, signing_input: &str) -> Vec<u8> {
+    let algorithm = match hash {
+        Hash::Sha384 => &digest::SHA384,
+        Hash::Sha512 => &digest::SHA512,
+        _ => &digest::SHA256,
+    };
+    digest::digest(algorithm, signing_input.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// JWS compact serialization of the signing input and its signature, in the PSA format
+pub fn compact(signing_input: String, signature: &[u8]) -> Vec<u8> {
+    let mut jws = signing_input;
+    jws.push('.');
+    jws.push_str(&URL_SAFE_NO_PAD.encode(signature));
+    jws.into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_helpers::{attributes, hex};
+
+    /// Payload of the examples of RFC 7515, appendix A
+    const PAYLOAD: &[u8] =
+        b"{\"iss\":\"joe\",\r\n \"exp\":1300819380,\r\n \"http://example.com/is_root\":true}";
+    /// Signing input of the ES256 example of RFC 7515, appendix A.3
+    const ES256_SIGNING_INPUT: &str = concat!(
+        "eyJhbGciOiJFUzI1NiJ9.",
+        "eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFtcGxlLmNvbS9pc19yb290",
+        "Ijp0cnVlfQ"
+    );
+    /// Signature of the ES256 example of RFC 7515, encoded
+    const ES256_SIGNATURE: &str =
+        "DtEhU3ljbEg8L38VWAfUAqOyKAM6-Xx-F4GawxaepmXFCgfTjDxw5djxLa8ISlSApmWQxfKTUJqPP3-Kg6NU1Q";
+
+    fn ec(curve_family: EccFamily) -> Type {
+        Type::EccKeyPair { curve_family }
+    }
+
+    fn ecdsa(hash: Hash) -> AsymmetricSignature {
+        AsymmetricSignature::Ecdsa {
+            hash_alg: hash.into(),
+        }
+    }
+
+    /// JWS algorithm of the key permitting only the algorithm
+    fn name(key_type: Type, bits: usize, alg: AsymmetricSignature) -> Result<&'static str> {
+        algorithm(&attributes(key_type, bits, alg), alg).map(|(name, hash)| {
+            assert_eq!(alg.hash(), Some(SignHash::Specific(hash)));
+            name
+        })
+    }
+
+    #[test]
+    fn strip_suffix() {
+        let mut key_name = String::from("issuer#jws");
+        assert!(strip_from(&mut key_name));
+        assert_eq!(key_name, "issuer");
+        assert!(!strip_from(&mut key_name));
+        assert_eq!(key_name, "issuer");
+
+        let mut key_name = String::from("#jwsissuer");
+        assert!(!strip_from(&mut key_name));
+        assert_eq!(key_name, "#jwsissuer");
+        let mut key_name = String::from("issuer#jws#jws");
+        assert!(strip_from(&mut key_name));
+        assert_eq!(key_name, "issuer#jws");
+    }
+
+    #[test]
+    fn rsa_algorithms() {
+        let algorithms = [
+            (Hash::Sha256, "RS256", "PS256"),
+            (Hash::Sha384, "RS384", "PS384"),
+            (Hash::Sha512, "RS512", "PS512"),
+        ];
+        for (hash, pkcs1v15, pss) in algorithms {
+            for bits in [2048, 3072, 4096] {
+                let alg = AsymmetricSignature::RsaPkcs1v15Sign {
+                    hash_alg: hash.into(),
+                };
+                assert_eq!(name(Type::RsaKeyPair, bits, alg), Ok(pkcs1v15));
+                let alg = AsymmetricSignature::RsaPss {
+                    hash_alg: hash.into(),
+                };
+                assert_eq!(name(Type::RsaKeyPair, bits, alg), Ok(pss));
+            }
+        }
+    }
+
+    #[test]
+    fn refuse_small_rsa_keys() {
+        let alg = AsymmetricSignature::RsaPkcs1v15Sign {
+            hash_alg: Hash::Sha256.into(),
+        };
+        assert_eq!(
+            name(Type::RsaKeyPair, 1024, alg),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+        let alg = AsymmetricSignature::RsaPss {
+            hash_alg: Hash::Sha256.into(),
+        };
+        assert_eq!(
+            name(Type::RsaKeyPair, 2047, alg),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[test]
+    fn ec_algorithms() {
+        let algorithms = [
+            (EccFamily::SecpR1, 256, Hash::Sha256, "ES256"),
+            (EccFamily::SecpR1, 384, Hash::Sha384, "ES384"),
+            (EccFamily::SecpR1, 521, Hash::Sha512, "ES512"),
+            (EccFamily::SecpK1, 256, Hash::Sha256, "ES256K"),
+        ];
+        for (curve_family, bits, hash, expected) in algorithms {
+            assert_eq!(name(ec(curve_family), bits, ecdsa(hash)), Ok(expected));
+            let deterministic = AsymmetricSignature::DeterministicEcdsa {
+                hash_alg: hash.into(),
+            };
+            assert_eq!(name(ec(curve_family), bits, deterministic), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn ec_hash_must_match_the_curve() {
+        let mismatched = [
+            (EccFamily::SecpR1, 256, Hash::Sha384),
+            (EccFamily::SecpR1, 384, Hash::Sha256),
+            (EccFamily::SecpR1, 521, Hash::Sha384),
+            (EccFamily::SecpK1, 256, Hash::Sha512),
+            // Curves without a JWS algorithm
+            (EccFamily::SecpR1, 224, Hash::Sha256),
+            (EccFamily::BrainpoolPR1, 256, Hash::Sha256),
+        ];
+        for (curve_family, bits, hash) in mismatched {
+            assert_eq!(
+                name(ec(curve_family), bits, ecdsa(hash)),
+                Err(ResponseStatus::PsaErrorNotSupported)
+            );
+        }
+    }
+
+    #[test]
+    fn algorithm_must_be_the_permitted_one() {
+        let p384 = ec(EccFamily::SecpR1);
+        assert_eq!(
+            algorithm(
+                &attributes(p384, 384, ecdsa(Hash::Sha384)),
+                ecdsa(Hash::Sha256)
+            ),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let pss = AsymmetricSignature::RsaPss {
+            hash_alg: Hash::Sha256.into(),
+        };
+        let pkcs1v15 = AsymmetricSignature::RsaPkcs1v15Sign {
+            hash_alg: Hash::Sha256.into(),
+        };
+        assert_eq!(
+            algorithm(&attributes(Type::RsaKeyPair, 2048, pss), pkcs1v15),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn refuse_algorithms_without_jws_name() {
+        let unsupported = [
+            AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Any,
+            },
+            AsymmetricSignature::RsaPkcs1v15Sign {
+                hash_alg: Hash::Sha224.into(),
+            },
+            AsymmetricSignature::RsaPkcs1v15SignRaw,
+        ];
+        for alg in unsupported {
+            assert_eq!(
+                name(Type::RsaKeyPair, 2048, alg),
+                Err(ResponseStatus::PsaErrorNotSupported)
+            );
+        }
+        // The algorithm must be one of the type of the key.
+        assert_eq!(
+            name(Type::RsaKeyPair, 2048, ecdsa(Hash::Sha256)),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+        let pss = AsymmetricSignature::RsaPss {
+            hash_alg: Hash::Sha256.into(),
+        };
+        assert_eq!(
+            name(ec(EccFamily::SecpR1), 256, pss),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[test]
+    fn signing_input_of_rfc_7515_examples() {
+        assert_eq!(signing_input("ES256", PAYLOAD), ES256_SIGNING_INPUT);
+        // Only the header differs in the RS256 example of RFC 7515, appendix A.2.
+        assert_eq!(
+            signing_input("RS256", PAYLOAD),
+            ES256_SIGNING_INPUT.replace("eyJhbGciOiJFUzI1NiJ9", "eyJhbGciOiJSUzI1NiJ9")
+        );
+        assert_eq!(signing_input("ES256", b""), "eyJhbGciOiJFUzI1NiJ9.");
+    }
+
+    #[test]
+    fn digest_of_the_signing_input() {
+        assert_eq!(
+            digest(Hash::Sha256, ES256_SIGNING_INPUT),
+            hex("21c67368f436577f447f805162ca13b80d046a3fe467247e65ea477aa750fa2e")
+        );
+        assert_eq!(
+            digest(Hash::Sha384, ES256_SIGNING_INPUT),
+            hex(concat!(
+                "864b8f21292f70669e8cb12c03fe2b2304e236ef6e5d2161",
+                "ac174cf8e25e643a8dc7e5f19d3bf0ec49f7318d92e28dd4"
+            ))
+        );
+        assert_eq!(
+            digest(Hash::Sha512, ES256_SIGNING_INPUT),
+            hex(concat!(
+                "ad3d1505b16587c4316ebcb3a0c7b11b7871c0524965b2f3848ca3a6256e7b45",
+                "cb5ed90bcd8ce50d70aa609014b26fcc77a39407b402dbe33a0b14cae8fcdc33"
+            ))
+        );
+    }
+
+    #[test]
+    fn compact_serialization_of_rfc_7515_example() {
+        let signature = URL_SAFE_NO_PAD.decode(ES256_SIGNATURE).unwrap();
+        assert_eq!(signature.len(), 64);
+        assert_eq!(
+            compact(String::from(ES256_SIGNING_INPUT), &signature),
+            format!("{}.{}", ES256_SIGNING_INPUT, ES256_SIGNATURE).into_bytes()
+        );
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Default attributes of the keys created
+//!
+//! Operators can configure the size and the permitted algorithm of the keys of each type, and the
+//! hash of the signature algorithms, for the service to fill them in when a request to create a key
+//! omits them: a size of 0 when generating a key, the `None` algorithm, or a signature algorithm
+//! permitting any hash. Clients are then written without choosing those values themselves, and get
+//! the ones approved by their organisation.
+//!
+//! In strict mode, the values given by the requests must be the configured ones.
+use crate::utils::config::{KeyDefaultConfig, KeyDefaultsConfig};
+use log::{error, warn};
+use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, Lifetime, Policy, Type, UsageFlags,
+};
+use parsec_interface::requests::{ResponseStatus, Result};
+use std::io::{Error, ErrorKind};
+
+/// Hash of a signature algorithm, if it has one
+fn signature_hash(alg: &mut AsymmetricSignature) -> Option<&mut SignHash> {
+    match alg {
+        AsymmetricSignature::RsaPkcs1v15Sign { hash_alg }
+        | AsymmetricSignature::RsaPss { hash_alg }
+        | AsymmetricSignature::Ecdsa { hash_alg }
+        | AsymmetricSignature::DeterministicEcdsa { hash_alg } => Some(hash_alg),
+        _ => None,
+    }
+}
+
+/// Attributes filled in the key creation requests omitting them
+#[derive(Debug)]
+pub struct KeyDefaults {
+    defaults: Vec<KeyDefaultConfig>,
+    signature_hash: Option<Hash>,
+    strict: bool,
+}
+
+impl KeyDefaults {
+    /// Create the defaults from their configuration, checking that the algorithms are compatible
+    /// with their key types.
+    pub fn new(config: &KeyDefaultsConfig) -> std::io::Result<Self> {
+        for (index, default) in config.default.iter().enumerate() {
+            if config.default[..index]
+                .iter()
+                .any(|other| other.key_type == default.key_type)
+            {
+                error!(
+                    "The defaults of the {} keys are given twice.",
+                    default.key_type
+                );
+                return Err(Error::new(ErrorKind::InvalidData, "duplicate key defaults"));
+            }
+            if let Some(algorithm) = default.algorithm {
+                let attributes = Attributes {
+                    key_type: default.key_type,
+                    bits: 0,
+                    lifetime: Lifetime::Persistent,
+                    policy: Policy {
+                        usage_flags: UsageFlags::default(),
+                        permitted_algorithms: algorithm,
+                    },
+                };
+                if !attributes.is_compatible_with_alg(algorithm) {
+                    error!(
+                        "The default algorithm of the {} keys is not compatible with them.",
+                        default.key_type
+                    );
+                    return Err(Error::new(ErrorKind::InvalidData, "invalid key defaults"));
+                }
+            }
+        }
+        Ok(KeyDefaults {
+            defaults: config.default.clone(),
+            signature_hash: config.signature_hash,
+            strict: config.strict.unwrap_or(false),
+        })
+    }
+
+    /// Fill in the attributes omitted from a request to generate, or import, a key.
+    ///
+    /// # Errors
+    /// * `PsaErrorNotPermitted` in strict mode, if the request gives other values than the defaults
+    pub fn complete(&self, attributes: &mut Attributes, generating: bool) -> Result<()> {
+        let default = self
+            .defaults
+            .iter()
+            .find(|default| default.key_type == attributes.key_type);
+
+        if let Some(bits) = default.and_then(|default| default.bits) {
+            // Imported keys without a size get the one of their data.
+            if attributes.bits == 0 && generating {
+                attributes.bits = bits;
+            } else if attributes.bits != 0 && attributes.bits != bits && self.strict {
+                return self.refuse("size", attributes.key_type);
+            }
+        }
+
+        if let Some(algorithm) = default.and_then(|default| default.algorithm) {
+            if attributes.policy.permitted_algorithms == Algorithm::None {
+                attributes.policy.permitted_algorithms = algorithm;
+            }
+        }
+
+        if let Some(hash) = self.signature_hash {
+            if let Algorithm::AsymmetricSignature(alg) = &mut attributes.policy.permitted_algorithms
+            {
+                match signature_hash(alg) {
+                    Some(hash_alg @ SignHash::Any) => *hash_alg = hash.into(),
+                    Some(SignHash::Specific(given)) if *given != hash && self.strict => {
+                        return self.refuse("signature hash", attributes.key_type);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        // Compared once the hash is filled in, as it is in the default algorithm.
+        if let Some(algorithm) = default.and_then(|default| default.algorithm) {
+            if attributes.policy.permitted_algorithms != algorithm && self.strict {
+                return self.refuse("algorithm", attributes.key_type);
+            }
+        }
+        Ok(())
+    }
+
+    fn refuse(&self, attribute: &str, key_type: Type) -> Result<()> {
+        warn!(
+            "Key creation refused: the {} of the {} key is not the mandated one.",
+            attribute, key_type
+        );
+        Err(ResponseStatus::PsaErrorNotPermitted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::psa_key_attributes::EccFamily;
+
+    fn defaults(strict: bool) -> KeyDefaults {
+        KeyDefaults::new(&KeyDefaultsConfig {
+            strict: Some(strict),
+            signature_hash: Some(Hash::Sha384),
+            default: vec![KeyDefaultConfig {
+                key_type: Type::RsaKeyPair,
+                bits: Some(3072),
+                algorithm: Some(
+                    AsymmetricSignature::RsaPss {
+                        hash_alg: Hash::Sha384.into(),
+                    }
+                    .into(),
+                ),
+            }],
+        })
+        .unwrap()
+    }
+
+    fn attributes(bits: usize, permitted_algorithms: Algorithm) -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::RsaKeyPair,
+            bits,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms,
+            },
+        }
+    }
+
+    fn pss(hash_alg: SignHash) -> Algorithm {
+        AsymmetricSignature::RsaPss { hash_alg }.into()
+    }
+
+    fn refused(strict: bool, mut attributes: Attributes) -> bool {
+        defaults(strict).complete(&mut attributes, true)
+            == Err(ResponseStatus::PsaErrorNotPermitted)
+    }
+
+    #[test]
+    fn omitted_size_and_algorithm_are_filled_in() {
+        for strict in &[false, true] {
+            let mut omitted = attributes(0, Algorithm::None);
+            defaults(*strict).complete(&mut omitted, true).unwrap();
+            assert_eq!(omitted, attributes(3072, pss(Hash::Sha384.into())));
+        }
+    }
+
+    #[test]
+    fn any_hash_is_filled_in() {
+        for strict in &[false, true] {
+            let mut any_hash = attributes(3072, pss(SignHash::Any));
+            defaults(*strict).complete(&mut any_hash, true).unwrap();
+            assert_eq!(any_hash, attributes(3072, pss(Hash::Sha384.into())));
+        }
+    }
+
+    #[test]
+    fn signature_hash_is_filled_in_for_all_key_types() {
+        let mut ecc = Attributes {
+            key_type: Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1,
+            },
+            ..attributes(
+                256,
+                AsymmetricSignature::Ecdsa {
+                    hash_alg: SignHash::Any,
+                }
+                .into(),
+            )
+        };
+        defaults(true).complete(&mut ecc, true).unwrap();
+        assert_eq!(
+            ecc.policy.permitted_algorithms,
+            AsymmetricSignature::Ecdsa {
+                hash_alg: Hash::Sha384.into(),
+            }
+            .into()
+        );
+        assert_eq!(ecc.bits, 256);
+    }
+
+    #[test]
+    fn imported_keys_keep_the_size_of_their_data() {
+        let mut imported = attributes(0, pss(SignHash::Any));
+        defaults(true).complete(&mut imported, false).unwrap();
+        assert_eq!(imported.bits, 0);
+    }
+
+    #[test]
+    fn other_values_are_kept() {
+        let mut other = attributes(2048, pss(Hash::Sha256.into()));
+        defaults(false).complete(&mut other, true).unwrap();
+        assert_eq!(other, attributes(2048, pss(Hash::Sha256.into())));
+    }
+
+    #[test]
+    fn strict_mode_refuses_other_sizes() {
+        assert!(refused(true, attributes(2048, Algorithm::None)));
+    }
+
+    #[test]
+    fn strict_mode_refuses_other_hashes() {
+        assert!(refused(true, attributes(0, pss(Hash::Sha256.into()))));
+    }
+
+    #[test]
+    fn strict_mode_refuses_other_algorithms() {
+        assert!(refused(
+            true,
+            attributes(
+                0,
+                AsymmetricSignature::RsaPkcs1v15Sign {
+                    hash_alg: Hash::Sha384.into(),
+                }
+                .into(),
+            )
+        ));
+    }
+
+    #[test]
+    fn algorithms_without_hash_are_kept() {
+        let defaults = KeyDefaults::new(&KeyDefaultsConfig {
+            strict: Some(true),
+            signature_hash: Some(Hash::Sha384),
+            default: Vec::new(),
+        })
+        .unwrap();
+        let raw = AsymmetricSignature::RsaPkcs1v15SignRaw.into();
+        let mut attributes = attributes(2048, raw);
+        defaults.complete(&mut attributes, true).unwrap();
+        assert_eq!(attributes.policy.permitted_algorithms, raw);
+    }
+
+    #[test]
+    fn keys_without_defaults_are_kept() {
+        let defaults = KeyDefaults::new(&KeyDefaultsConfig {
+            strict: Some(true),
+            signature_hash: None,
+            default: Vec::new(),
+        })
+        .unwrap();
+        let mut key = attributes(0, pss(SignHash::Any));
+        defaults.complete(&mut key, true).unwrap();
+        assert_eq!(key, attributes(0, pss(SignHash::Any)));
+    }
+
+    #[test]
+    fn incompatible_default_algorithms_are_refused() {
+        let error = KeyDefaults::new(&KeyDefaultsConfig {
+            strict: None,
+            signature_hash: None,
+            default: vec![KeyDefaultConfig {
+                key_type: Type::Aes,
+                bits: None,
+                algorithm: Some(pss(SignHash::Any)),
+            }],
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn duplicate_defaults_are_refused() {
+        let default = KeyDefaultConfig {
+            key_type: Type::RsaKeyPair,
+            bits: Some(2048),
+            algorithm: None,
+        };
+        let error = KeyDefaults::new(&KeyDefaultsConfig {
+            strict: None,
+            signature_hash: None,
+            default: vec![default, default],
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Disabling keys without destroying them
+//!
+//! A PsaDestroyKey request on a key name suffixed with `#disable=` does not destroy the key: it
+//! clears usage flags of the key in the Key Info Manager. `#disable=sign-encrypt` keeps the key
+//! usable to verify and decrypt, for staged rotations where what the old key signed or encrypted
+//! must still be checked or read, and `#disable=all` only keeps the export, copy and cache flags.
+//! Disabling can not be undone, as no request widens the usage flags of a key.
+//!
+//! The back end handler refuses the operations on a key whose usage flags do not permit them,
+//! before they reach the provider: not every provider checks the flags stored in the Key Info
+//! Manager, and the key material of some keeps the policy it was created with.
+use log::error;
+use parsec_interface::operations::psa_key_attributes::UsageFlags;
+use parsec_interface::requests::{Opcode, ResponseStatus, Result};
+
+/// Uses of a key which can be disabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisabledUses {
+    /// Signing and encrypting, with the key agreements
+    SignEncrypt,
+    /// Every use of the key
+    All,
+}
+
+impl DisabledUses {
+    /// Uses named by the argument of the `#disable=` suffix
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sign-encrypt" => Some(DisabledUses::SignEncrypt),
+            "all" => Some(DisabledUses::All),
+            _ => None,
+        }
+    }
+
+    /// Usage flags left to a key once these uses are disabled. Flags are only ever cleared.
+    pub fn remaining(self, usage_flags: UsageFlags) -> UsageFlags {
+        let mut remaining = UsageFlags::default();
+        if usage_flags.export() {
+            let _ = remaining.set_export();
+        }
+        if usage_flags.copy() {
+            let _ = remaining.set_copy();
+        }
+        if usage_flags.cache() {
+            let _ = remaining.set_cache();
+        }
+        if self == DisabledUses::SignEncrypt {
+            if usage_flags.decrypt() {
+                let _ = remaining.set_decrypt();
+            }
+            if usage_flags.verify_message() {
+                let _ = remaining.set_verify_message();
+                // The verify_hash setter also sets verify_message.
+                if usage_flags.verify_hash() {
+                    let _ = remaining.set_verify_hash();
+                }
+            }
+        }
+        remaining
+    }
+}
+
+/// Refuse the operation if the usage flags of the key do not permit it.
+pub fn check(usage_flags: &UsageFlags, opcode: Opcode) -> Result<()> {
+    let permitted = match opcode {
+        Opcode::PsaSignHash => usage_flags.sign_hash(),
+        Opcode::PsaSignMessage => usage_flags.sign_message(),
+        Opcode::PsaVerifyHash => usage_flags.verify_hash(),
+        Opcode::PsaVerifyMessage => usage_flags.verify_message(),
+        Opcode::PsaAsymmetricEncrypt | Opcode::PsaAeadEncrypt | Opcode::PsaCipherEncrypt => {
+            usage_flags.encrypt()
+        }
+        Opcode::PsaAsymmetricDecrypt | Opcode::PsaAeadDecrypt | Opcode::PsaCipherDecrypt => {
+            usage_flags.decrypt()
+        }
+        Opcode::PsaRawKeyAgreement => usage_flags.derive(),
+        _ => true,
+    };
+    if permitted {
+        Ok(())
+    } else {
+        error!("The usage flags of the key do not permit {:?}.", opcode);
+        Err(ResponseStatus::PsaErrorNotPermitted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn all_flags() -> UsageFlags {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags
+            .set_export()
+            .set_copy()
+            .set_cache()
+            .set_encrypt()
+            .set_decrypt()
+            .set_sign_hash()
+            .set_verify_hash()
+            .set_derive();
+        usage_flags
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            DisabledUses::from_name("sign-encrypt"),
+            Some(DisabledUses::SignEncrypt)
+        );
+        assert_eq!(DisabledUses::from_name("all"), Some(DisabledUses::All));
+        assert_eq!(DisabledUses::from_name("sign"), None);
+    }
+
+    #[test]
+    fn sign_encrypt_keeps_verify_and_decrypt() {
+        let usage_flags = DisabledUses::SignEncrypt.remaining(all_flags());
+        for opcode in [
+            Opcode::PsaVerifyHash,
+            Opcode::PsaVerifyMessage,
+            Opcode::PsaAsymmetricDecrypt,
+            Opcode::PsaAeadDecrypt,
+            Opcode::PsaCipherDecrypt,
+            Opcode::PsaExportPublicKey,
+        ] {
+            assert!(check(&usage_flags, opcode).is_ok());
+        }
+        for opcode in [
+            Opcode::PsaSignHash,
+            Opcode::PsaSignMessage,
+            Opcode::PsaAsymmetricEncrypt,
+            Opcode::PsaAeadEncrypt,
+            Opcode::PsaCipherEncrypt,
+            Opcode::PsaRawKeyAgreement,
+        ] {
+            assert_eq!(
+                check(&usage_flags, opcode),
+                Err(ResponseStatus::PsaErrorNotPermitted)
+            );
+        }
+        assert!(usage_flags.export() && usage_flags.copy() && usage_flags.cache());
+    }
+
+    #[test]
+    fn all_keeps_management_flags() {
+        let usage_flags = DisabledUses::All.remaining(all_flags());
+        assert_eq!(
+            check(&usage_flags, Opcode::PsaVerifyHash),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            check(&usage_flags, Opcode::PsaAeadDecrypt),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert!(usage_flags.export() && usage_flags.copy() && usage_flags.cache());
+    }
+
+    #[test]
+    fn never_widens() {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags.set_sign_hash().set_verify_message();
+        let remaining = DisabledUses::SignEncrypt.remaining(usage_flags);
+        assert!(remaining.verify_message());
+        assert!(!remaining.verify_hash());
+        assert!(!remaining.export());
+        assert_eq!(
+            DisabledUses::All.remaining(usage_flags),
+            UsageFlags::default()
+        );
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Public points of elliptic curve private keys
+//!
+//! The public point of a private value `d` is `d·G`, computed with a Montgomery ladder in Jacobian
+//! coordinates, with the `dbl-2007-bl` and `add-2007-bl` formulas of the Explicit-Formulas
+//! Database. The arithmetic, on `BigUint`, is neither constant time nor zeroized: it is only used
+//! to check that the public key a client gives along with a private key is the one of that key.
+use log::error;
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+use parsec_interface::requests::{ResponseStatus, Result};
+use std::convert::TryFrom;
+
+/// Short Weierstrass curve `y² = x³ + ax + b` over the field of order `p`, with the base point `G`
+/// of order `n`, in hexadecimal
+#[derive(Debug)]
+struct Domain {
+    /// Name of the curve in JSON Web Keys
+    jwk_name: &'static str,
+    p: &'static str,
+    a: &'static str,
+    gx: &'static str,
+    gy: &'static str,
+    n: &'static str,
+}
+
+/// Domains of the curves of JSON Web Keys, from SEC 2
+const DOMAINS: &[Domain] = &[
+    Domain {
+        jwk_name: "P-256",
+        p: "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff",
+        a: "ffffffff00000001000000000000000000000000fffffffffffffffffffffffc",
+        gx: "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296",
+        gy: "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5",
+        n: "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551",
+    },
+    Domain {
+        jwk_name: "P-384",
+        p: "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffe\
+            ffffffff0000000000000000ffffffff",
+        a: "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffe\
+            ffffffff0000000000000000fffffffc",
+        gx: "aa87ca22be8b05378eb1c71ef320ad746e1d3b628ba79b9859f741e082542a38\
+             5502f25dbf55296c3a545e3872760ab7",
+        gy: "3617de4a96262c6f5d9e98bf9292dc29f8f41dbd289a147ce9da3113b5f0b8c0\
+             0a60b1ce1d7e819d7a431d7c90ea0e5f",
+        n: "ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf\
+            581a0db248b0a77aecec196accc52973",
+    },
+    Domain {
+        jwk_name: "P-521",
+        p: "01ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff\
+            ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff\
+            ffff",
+        a: "01ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff\
+            ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff\
+            fffc",
+        gx: "00c6858e06b70404e9cd9e3ecb662395b4429c648139053fb521f828af606b4d\
+             3dbaa14b5e77efe75928fe1dc127a2ffa8de3348b3c1856a429bf97e7e31c2e5\
+             bd66",
+        gy: "011839296a789a3bc0045c8a5fb42c7d1bd998f54449579b446817afbd17273e\
+             662c97ee72995ef42640c550b9013fad0761353c7086a272c24088be94769fd1\
+             6650",
+        n: "01ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff\
+            fffa51868783bf2f966b7fcc0148f709a5d03bb5c9b8899c47aebb6fb71e9138\
+            6409",
+    },
+    Domain {
+        jwk_name: "secp256k1",
+        p: "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f",
+        a: "00",
+        gx: "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        gy: "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8",
+        n: "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+    },
+];
+
+fn integer(hex: &str) -> BigUint {
+    BigUint::parse_bytes(hex.as_bytes(), 16).expect("Invalid curve parameter")
+}
+
+/// Point in Jacobian coordinates, `(X / Z², Y / Z³)`, the point at infinity having a zero `Z`
+#[derive(Clone, Debug)]
+struct Point {
+    x: BigUint,
+    y: BigUint,
+    z: BigUint,
+}
+
+impl Point {
+    fn infinity() -> Self {
+        Point {
+            x: BigUint::one(),
+            y: BigUint::one(),
+            z: BigUint::zero(),
+        }
+    }
+}
+
+/// Arithmetic on the points of a curve
+struct Curve {
+    p: BigUint,
+    a: BigUint,
+}
+
+impl Curve {
+    fn reduce(&self, value: BigUint) -> BigUint {
+        value % &self.p
+    }
+
+    /// Difference of two reduced values
+    fn sub(&self, left: &BigUint, right: &BigUint) -> BigUint {
+        self.reduce(left + &self.p - right)
+    }
+
+    fn square(&self, value: &BigUint) -> BigUint {
+        self.reduce(value * value)
+    }
+
+    fn double(&self, point: &Point) -> Point {
+        if point.z.is_zero() {
+            return point.clone();
+        }
+        let xx = self.square(&point.x);
+        let yy = self.square(&point.y);
+        let yyyy = self.square(&yy);
+        let zz = self.square(&point.z);
+        let s =
+            self.reduce(self.sub(&self.sub(&self.square(&(&point.x + &yy)), &xx), &yyyy) * 2u32);
+        let m = self.reduce(&xx * 3u32 + &self.a * self.square(&zz));
+        let x = self.sub(&self.square(&m), &self.reduce(&s * 2u32));
+        let y = self.sub(
+            &self.reduce(&m * self.sub(&s, &x)),
+            &self.reduce(&yyyy * 8u32),
+        );
+        let z = self.sub(&self.sub(&self.square(&(&point.y + &point.z)), &yy), &zz);
+        Point { x, y, z }
+    }
+
+    fn add(&self, left: &Point, right: &Point) -> Point {
+        if left.z.is_zero() {
+            return right.clone();
+        }
+        if right.z.is_zero() {
+            return left.clone();
+        }
+        let z1z1 = self.square(&left.z);
+        let z2z2 = self.square(&right.z);
+        let u1 = self.reduce(&left.x * &z2z2);
+        let u2 = self.reduce(&right.x * &z1z1);
+        let s1 = self.reduce(self.reduce(&left.y * &right.z) * &z2z2);
+        let s2 = self.reduce(self.reduce(&right.y * &left.z) * &z1z1);
+        let h = self.sub(&u2, &u1);
+        let r = self.reduce(self.sub(&s2, &s1) * 2u32);
+        if h.is_zero() {
+            return if r.is_zero() {
+                self.double(left)
+            } else {
+                Point::infinity()
+            };
+        }
+        let i = self.square(&self.reduce(&h * 2u32));
+        let j = self.reduce(&h * &i);
+        let v = self.reduce(&u1 * &i);
+        let x = self.sub(&self.sub(&self.square(&r), &j), &self.reduce(&v * 2u32));
+        let y = self.sub(
+            &self.reduce(&r * self.sub(&v, &x)),
+            &self.reduce(&s1 * &j * 2u32),
+        );
+        let z = self
+            .reduce(self.sub(&self.sub(&self.square(&(&left.z + &right.z)), &z1z1), &z2z2) * &h);
+        Point { x, y, z }
+    }
+}
+
+/// Uncompressed public point of the private value on the curve of the given JSON Web Key name
+pub fn public_point(jwk_name: &str, private_value: &[u8]) -> Result<Vec<u8>> {
+    let domain = DOMAINS
+        .iter()
+        .find(|domain| domain.jwk_name == jwk_name)
+        .ok_or_else(|| {
+            error!(
+                "The public point of keys on the {} curve can not be computed.",
+                jwk_name
+            );
+            ResponseStatus::PsaErrorNotSupported
+        })?;
+    let private_value = BigUint::from_bytes_be(private_value);
+    if private_value.is_zero() || private_value >= integer(domain.n) {
+        error!("The private value is not smaller than the order of the curve.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+
+    let curve = Curve {
+        p: integer(domain.p),
+        a: integer(domain.a),
+    };
+    let mut low = Point::infinity();
+    let mut high = Point {
+        x: integer(domain.gx),
+        y: integer(domain.gy),
+        z: BigUint::one(),
+    };
+    for bit in (0..private_value.bits()).rev() {
+        if private_value.bit(bit) {
+            low = curve.add(&low, &high);
+            high = curve.double(&high);
+        } else {
+            high = curve.add(&low, &high);
+            low = curve.double(&low);
+        }
+    }
+
+    // Fermat's inverse of Z, the field order being prime
+    let z_inverse = low.z.modpow(&(&curve.p - 2u32), &curve.p);
+    let z_inverse_squared = curve.square(&z_inverse);
+    let x = curve.reduce(&low.x * &z_inverse_squared);
+    let y = curve.reduce(curve.reduce(&low.y * &z_inverse_squared) * &z_inverse);
+    let length = usize::try_from((curve.p.bits() + 7) / 8).expect("Curve too large");
+    let mut point = Vec::with_capacity(1 + 2 * length);
+    point.push(0x04);
+    for coordinate in [x, y].iter() {
+        let bytes = coordinate.to_bytes_be();
+        point.resize(point.len() + length - bytes.len(), 0);
+        point.extend_from_slice(&bytes);
+    }
+    Ok(point)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    fn decode(value: &str) -> Vec<u8> {
+        URL_SAFE_NO_PAD.decode(value).unwrap()
+    }
+
+    fn check_point(jwk_name: &str, x: &str, y: &str, d: &str) {
+        let mut point = vec![0x04];
+        point.extend(decode(x));
+        point.extend(decode(y));
+        assert_eq!(public_point(jwk_name, &decode(d)).unwrap(), point);
+    }
+
+    #[test]
+    fn rfc7517_p256_key() {
+        // Appendix A.2 of RFC 7517
+        check_point(
+            "P-256",
+            "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+            "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+            "870MB6gfuTJ4HtUnUvYMyJpr5eUZNP4Bk43bVdj3eAE",
+        );
+    }
+
+    // The keys of the other curves were generated with OpenSSL.
+
+    #[test]
+    fn p384_key() {
+        check_point(
+            "P-384",
+            "ymKTEtveEQQ1x9Pf1WywCIs8npppyAw_emhrdu6BSj0d5cJFe1pMHOOiDWH3Taxx",
+            "1rhj1T4PRZ-7YaXRlo_VYCU3hvOLZVMQTKWcEAoIL3WvfPvNLIV1c9VM7KcRp2uh",
+            "8SbD5NWMRnhbbLsDQC6bB0vbo8P0gkB5ZSGcVuTO-BXcAZFq65b8Y3luonXJQwP5",
+        );
+    }
+
+    #[test]
+    fn p521_key() {
+        check_point(
+            "P-521",
+            concat!(
+                "ANNMqCT1v6ntwXI42T_j8hA5Y68__chK1agfhQ9fNw-v",
+                "0VK-fHXHVSCNALdPR4Jo80VITI7V2j4oRoxaaNsSppsh"
+            ),
+            concat!(
+                "AN8KDcDkRqP14oKOjs5EsMaMGfy8Xyk9OGBXLG1U4Ts9",
+                "8WdPbctxcS6HFfpRTtYffLtGP4YjU9iaP7EnMx8gOyGq"
+            ),
+            concat!(
+                "Ad06UJqEHRoJv96xnI61qcbzMGM21sEdYUKayGiQPDBW",
+                "iZIXU7vXpxlPFoVcyunNtjn7Lt-Jwf0CCh1WUXv27pNk"
+            ),
+        );
+    }
+
+    #[test]
+    fn secp256k1_key() {
+        check_point(
+            "secp256k1",
+            "AzMKvu8R-oxHWi8jenuhCj7wC4cPh_MjhtjHMCICNsM",
+            "ohWcUI_uCIL7QlnufMQwoYreiJfuK7ijFX06jxa3NnU",
+            "nM2eWycvXCweIaw1BijDiftmnnP_lxw6qS3eSMz79h4",
+        );
+    }
+
+    #[test]
+    fn order_minus_one_gives_the_opposite_of_the_base_point() {
+        for domain in DOMAINS {
+            let n = integer(domain.n);
+            let p = integer(domain.p);
+            let point = public_point(domain.jwk_name, &(&n - 1u32).to_bytes_be()).unwrap();
+            let length = point.len() / 2;
+            assert_eq!(
+                BigUint::from_bytes_be(&point[1..=length]),
+                integer(domain.gx)
+            );
+            assert_eq!(
+                BigUint::from_bytes_be(&point[length + 1..]),
+                &p - integer(domain.gy)
+            );
+        }
+    }
+
+    #[test]
+    fn private_values_must_be_in_range() {
+        for private_value in [
+            vec![0; 32],
+            integer(DOMAINS[0].n).to_bytes_be(),
+            vec![0xff; 32],
+        ] {
+            assert_eq!(
+                public_point("P-256", &private_value),
+                Err(ResponseStatus::PsaErrorInvalidArgument)
+            );
+        }
+    }
+}
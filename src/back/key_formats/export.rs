@@ -0,0 +1,581 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Public keys exported in common encodings
+//!
+//! `PsaExportPublicKey` returns the public keys in the format specified by PSA: the DER encoded
+//! `RSAPublicKey` for RSA keys, the uncompressed point for elliptic curve keys. The format of the
+//! RSA and elliptic curve keys can instead be chosen by suffixing the name of the key in the
+//! request with `#` and one of:
+//!
+//! * `pem`: the PEM encoded SubjectPublicKeyInfo, `-----BEGIN PUBLIC KEY-----`.
+//! * `jwk`: the JSON Web Key, for the keys on the `P-256`, `P-384`, `P-521` and `secp256k1`
+//!   curves.
+//! * `ssh`: the OpenSSH public key, `ssh-rsa AAAA...`, for the keys on the NIST curves.
+//! * `cose`: the CBOR encoded COSE_Key, for the keys on the `P-256`, `P-384`, `P-521` and
+//!   `secp256k1` curves, with the COSE algorithm of the key if it has one.
+//!
+//! For example, `signing-key#pem` exports the public key of `signing-key` in PEM. The suffix is
+//! always taken as a format: a key whose name ends with one of them can only be exported by adding
+//! a second suffix to its name.
+use super::{der_element, der_sequence, Curve, CURVES};
+use crate::providers::utils::key_validation::parse_rsa_public_key;
+use crate::utils::cbor::{self, Value};
+use crate::utils::cose;
+use crate::utils::key_suffixes;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use log::error;
+use parsec_interface::operations::psa_algorithm::Algorithm;
+use parsec_interface::operations::psa_key_attributes::{Attributes, EccFamily, Type};
+use parsec_interface::requests::{ResponseStatus, Result};
+
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+
+/// Length of the lines of the PEM data
+const PEM_LINE_LENGTH: usize = 64;
+
+/// Format of an exported public key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicKeyFormat {
+    /// PEM encoded SubjectPublicKeyInfo
+    Pem,
+    /// JSON Web Key
+    Jwk,
+    /// OpenSSH public key
+    Ssh,
+    /// COSE_Key
+    Cose,
+}
+
+impl PublicKeyFormat {
+    /// Format named by the suffix of the key name, which is then removed. `None` is returned if
+    /// the key name has no format suffix.
+    pub fn strip_from(key_name: &mut String) -> Option<Self> {
+        for (format, suffix) in [
+            (PublicKeyFormat::Pem, key_suffixes::PEM),
+            (PublicKeyFormat::Jwk, key_suffixes::JWK),
+            (PublicKeyFormat::Ssh, key_suffixes::SSH),
+            (PublicKeyFormat::Cose, key_suffixes::COSE),
+        ] {
+            if key_suffixes::strip_flag(key_name, suffix) {
+                return Some(format);
+            }
+        }
+        None
+    }
+}
+
+/// Encode in the given format the public key exported in the PSA format, of a key with the given
+/// attributes.
+pub fn encode(attributes: &Attributes, data: &[u8], format: PublicKeyFormat) -> Result<Vec<u8>> {
+    match attributes.key_type {
+        Type::RsaKeyPair | Type::RsaPublicKey => encode_rsa(attributes, data, format),
+        Type::EccKeyPair { curve_family } | Type::EccPublicKey { curve_family } => encode_ecc(
+            attributes,
+            data,
+            curve(curve_family, attributes.bits)?,
+            format,
+        ),
+        key_type => {
+            error!(
+                "Public keys of type {:?} can only be exported raw.",
+                key_type
+            );
+            Err(ResponseStatus::PsaErrorNotSupported)
+        }
+    }
+}
+
+fn curve(family: EccFamily, bits: usize) -> Result<&'static Curve> {
+    CURVES
+        .iter()
+        .find(|curve| curve.family == family && curve.bits == bits)
+        .ok_or_else(|| {
+            error!("Public keys on this curve can only be exported raw.");
+            ResponseStatus::PsaErrorNotSupported
+        })
+}
+
+fn encode_rsa(attributes: &Attributes, data: &[u8], format: PublicKeyFormat) -> Result<Vec<u8>> {
+    let key = parse_rsa_public_key(data)?;
+    Ok(match format {
+        PublicKeyFormat::Pem => {
+            let mut algorithm = der_element(TAG_OID, OID_RSA_ENCRYPTION).to_vec();
+            algorithm.extend_from_slice(&[TAG_NULL, 0]);
+            spki_pem(&algorithm, data)
+        }
+        PublicKeyFormat::Jwk => serde_json::to_vec(&serde_json::json!({
+            "kty": "RSA",
+            "n": URL_SAFE_NO_PAD.encode(key.modulus),
+            "e": URL_SAFE_NO_PAD.encode(key.public_exponent),
+        }))
+        .map_err(|e| {
+            format_error!("Failed to encode the JSON Web Key", e);
+            ResponseStatus::PsaErrorGenericError
+        })?,
+        PublicKeyFormat::Ssh => {
+            let mut blob = Vec::new();
+            ssh_string(b"ssh-rsa", &mut blob);
+            ssh_mpint(&key.public_exponent, &mut blob);
+            ssh_mpint(&key.modulus, &mut blob);
+            format!("ssh-rsa {}", STANDARD.encode(blob)).into_bytes()
+        }
+        PublicKeyFormat::Cose => cose_key(
+            attributes,
+            vec![
+                (cose::KEY_TYPE, Value::Integer(cose::KEY_TYPE_RSA)),
+                (cose::RSA_N, Value::bytes(&key.modulus)),
+                (cose::RSA_E, Value::bytes(&key.public_exponent)),
+            ],
+        ),
+    })
+}
+
+fn encode_ecc(
+    attributes: &Attributes,
+    point: &[u8],
+    curve: &Curve,
+    format: PublicKeyFormat,
+) -> Result<Vec<u8>> {
+    let length = curve.length();
+    let (x, y) = match point {
+        [0x04, coordinates @ ..] if coordinates.len() == 2 * length => coordinates.split_at(length),
+        _ => {
+            error!("The exported public key is not an uncompressed point of its curve.");
+            return Err(ResponseStatus::PsaErrorCorruptionDetected);
+        }
+    };
+    match format {
+        PublicKeyFormat::Pem => {
+            let mut algorithm = der_element(TAG_OID, OID_EC_PUBLIC_KEY).to_vec();
+            algorithm.extend_from_slice(&der_element(TAG_OID, curve.oid));
+            Ok(spki_pem(&algorithm, point))
+        }
+        PublicKeyFormat::Jwk => {
+            let crv = curve.jwk_name.ok_or_else(|| {
+                error!("Public keys on this curve can not be exported as JSON Web Keys.");
+                ResponseStatus::PsaErrorNotSupported
+            })?;
+            serde_json::to_vec(&serde_json::json!({
+                "kty": "EC",
+                "crv": crv,
+                "x": URL_SAFE_NO_PAD.encode(x),
+                "y": URL_SAFE_NO_PAD.encode(y),
+            }))
+            .map_err(|e| {
+                format_error!("Failed to encode the JSON Web Key", e);
+                ResponseStatus::PsaErrorGenericError
+            })
+        }
+        PublicKeyFormat::Ssh => {
+            let ssh_name = curve.ssh_name.ok_or_else(|| {
+                error!("Public keys on this curve can not be exported as OpenSSH keys.");
+                ResponseStatus::PsaErrorNotSupported
+            })?;
+            let key_type = format!("ecdsa-sha2-{}", ssh_name);
+            let mut blob = Vec::new();
+            ssh_string(key_type.as_bytes(), &mut blob);
+            ssh_string(ssh_name.as_bytes(), &mut blob);
+            ssh_string(point, &mut blob);
+            Ok(format!("{} {}", key_type, STANDARD.encode(blob)).into_bytes())
+        }
+        PublicKeyFormat::Cose => {
+            let identifier = cose::curve_identifier(curve.family, curve.bits).ok_or_else(|| {
+                error!("Public keys on this curve can not be exported as COSE keys.");
+                ResponseStatus::PsaErrorNotSupported
+            })?;
+            Ok(cose_key(
+                attributes,
+                vec![
+                    (cose::KEY_TYPE, Value::Integer(cose::KEY_TYPE_EC2)),
+                    (cose::EC2_CURVE, Value::Integer(identifier)),
+                    (cose::EC2_X, Value::bytes(x)),
+                    (cose::EC2_Y, Value::bytes(y)),
+                ],
+            ))
+        }
+    }
+}
+
+/// CBOR encoded COSE_Key of the given parameters, with the algorithm of the key
+fn cose_key(attributes: &Attributes, mut parameters: Vec<(i64, Value)>) -> Vec<u8> {
+    if let Algorithm::AsymmetricSignature(alg) = attributes.policy.permitted_algorithms {
+        if let Some(identifier) = cose::signature_algorithm_identifier(alg) {
+            // The labels are in the order of their encoding, the positive ones first.
+            parameters.insert(1, (cose::KEY_ALGORITHM, Value::Integer(identifier)));
+        }
+    }
+    cbor::encode(&Value::labelled(parameters))
+}
+
+/// PEM encoded SubjectPublicKeyInfo of the given algorithm identifier contents and public key
+fn spki_pem(algorithm: &[u8], public_key: &[u8]) -> Vec<u8> {
+    let mut bit_string = vec![0];
+    bit_string.extend_from_slice(public_key);
+    let mut contents = der_sequence(algorithm).to_vec();
+    contents.extend_from_slice(&der_element(TAG_BIT_STRING, &bit_string));
+    let base64 = STANDARD.encode(der_sequence(&contents));
+
+    let mut pem = String::from("-----BEGIN PUBLIC KEY-----\n");
+    for start in (0..base64.len()).step_by(PEM_LINE_LENGTH) {
+        pem.push_str(&base64[start..base64.len().min(start + PEM_LINE_LENGTH)]);
+        pem.push('\n');
+    }
+    pem.push_str("-----END PUBLIC KEY-----\n");
+    pem.into_bytes()
+}
+
+fn ssh_string(string: &[u8], blob: &mut Vec<u8>) {
+    blob.extend_from_slice(&(string.len() as u32).to_be_bytes());
+    blob.extend_from_slice(string);
+}
+
+/// Positive multiple precision integer of the given big-endian magnitude
+fn ssh_mpint(magnitude: &[u8], blob: &mut Vec<u8>) {
+    let magnitude = &magnitude[magnitude.iter().take_while(|byte| **byte == 0).count()..];
+    let sign_byte = magnitude.first().map_or(false, |first| first & 0x80 != 0);
+    blob.extend_from_slice(&((magnitude.len() + usize::from(sign_byte)) as u32).to_be_bytes());
+    if sign_byte {
+        blob.push(0);
+    }
+    blob.extend_from_slice(magnitude);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash};
+    use parsec_interface::operations::psa_key_attributes::{Lifetime, Policy, UsageFlags};
+
+    // RSA key of 1024 bits generated with OpenSSL, which gave the other encodings of its public key
+    const RSA_PUBLIC_KEY: &str = "\
+        MIGJAoGBALwyWPB7x3KTZ+9neC9t9OhkW1ftbqn44wFXZZkiwma7ltCYK0DuF6eR\
+        INr6DezwagZNIsRl/pZc7oa6cnlLHzapEvngynkhJbGMYajC6X//W2pD4Uss9UFZ\
+        LOe/FwwCstuXg2IDgzi1mW7Zkf5MC20ylIxhYI2zrRis0SeXVUSxAgMBAAE=";
+    const RSA_PEM: &str = "-----BEGIN PUBLIC KEY-----
+MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQC8Mljwe8dyk2fvZ3gvbfToZFtX
+7W6p+OMBV2WZIsJmu5bQmCtA7henkSDa+g3s8GoGTSLEZf6WXO6GunJ5Sx82qRL5
+4Mp5ISWxjGGowul//1tqQ+FLLPVBWSznvxcMArLbl4NiA4M4tZlu2ZH+TAttMpSM
+YWCNs60YrNEnl1VEsQIDAQAB
+-----END PUBLIC KEY-----
+";
+    const RSA_SSH: &str = "ssh-rsa \
+        AAAAB3NzaC1yc2EAAAADAQABAAAAgQC8Mljwe8dyk2fvZ3gvbfToZFtX7W6p+OMBV2WZIsJmu5bQmCtA7henkSDa\
+        +g3s8GoGTSLEZf6WXO6GunJ5Sx82qRL54Mp5ISWxjGGowul//1tqQ+FLLPVBWSznvxcMArLbl4NiA4M4tZlu2ZH+\
+        TAttMpSMYWCNs60YrNEnl1VEsQ==";
+    const RSA_N: &str = "\
+        vDJY8HvHcpNn72d4L2306GRbV-1uqfjjAVdlmSLCZruW0JgrQO4Xp5Eg2voN7PBqBk0ixGX-llzuhrpyeUsfNqkS\
+        -eDKeSElsYxhqMLpf_9bakPhSyz1QVks578XDAKy25eDYgODOLWZbtmR_kwLbTKUjGFgjbOtGKzRJ5dVRLE";
+
+    // The P-256 key of RFC 7517, appendix A.1, with its other encodings given by OpenSSL
+    const P256_X: &str = "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4";
+    const P256_Y: &str = "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM";
+    const P256_PEM: &str = "-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEMKBCTNIcKUSDii11ySs3526iDZ8A
+iTo7Tu6KPAqv7D7gS2XpJFbZiItSs3m9+9Ue6GnvHw/GW2ZZaVtszggXIw==
+-----END PUBLIC KEY-----
+";
+    const P256_SSH: &str = "ecdsa-sha2-nistp256 \
+        AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBDCgQkzSHClEg4otdckrN+duog2fAIk6O07u\
+        ijwKr+w+4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM=";
+
+    fn attributes(key_type: Type, bits: usize, permitted_algorithms: Algorithm) -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type,
+            bits,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms,
+            },
+        }
+    }
+
+    fn rsa(permitted_algorithms: Algorithm) -> Attributes {
+        attributes(Type::RsaPublicKey, 1024, permitted_algorithms)
+    }
+
+    fn ecc(curve_family: EccFamily, bits: usize) -> Attributes {
+        attributes(
+            Type::EccPublicKey { curve_family },
+            bits,
+            Algorithm::AsymmetricSignature(AsymmetricSignature::Ecdsa {
+                hash_alg: Hash::Sha256.into(),
+            }),
+        )
+    }
+
+    fn rsa_public_key() -> Vec<u8> {
+        STANDARD.decode(RSA_PUBLIC_KEY).unwrap()
+    }
+
+    fn p256_point() -> Vec<u8> {
+        let mut point = vec![0x04];
+        point.extend(URL_SAFE_NO_PAD.decode(P256_X).unwrap());
+        point.extend(URL_SAFE_NO_PAD.decode(P256_Y).unwrap());
+        point
+    }
+
+    // Uncompressed point of the given size, not checked to be on the curve
+    fn point(length: usize) -> Vec<u8> {
+        let mut point = vec![0x04];
+        point.extend(vec![0x11; 2 * length]);
+        point
+    }
+
+    fn text(encoded: Vec<u8>) -> String {
+        String::from_utf8(encoded).unwrap()
+    }
+
+    #[test]
+    fn format_suffixes_are_stripped() {
+        for (suffix, format) in [
+            ("pem", PublicKeyFormat::Pem),
+            ("jwk", PublicKeyFormat::Jwk),
+            ("ssh", PublicKeyFormat::Ssh),
+            ("cose", PublicKeyFormat::Cose),
+        ] {
+            let mut key_name = format!("signing-key#{}", suffix);
+            assert_eq!(PublicKeyFormat::strip_from(&mut key_name), Some(format));
+            assert_eq!(key_name, "signing-key");
+        }
+    }
+
+    #[test]
+    fn only_the_last_suffix_is_stripped() {
+        let mut key_name = String::from("signing#key#ssh");
+        assert_eq!(
+            PublicKeyFormat::strip_from(&mut key_name),
+            Some(PublicKeyFormat::Ssh)
+        );
+        assert_eq!(key_name, "signing#key");
+    }
+
+    #[test]
+    fn names_without_format_suffix_are_kept() {
+        for name in [
+            "signing-key",
+            "signing#key",
+            "signing-key#PEM",
+            "signing-key#",
+        ] {
+            let mut key_name = String::from(name);
+            assert_eq!(PublicKeyFormat::strip_from(&mut key_name), None);
+            assert_eq!(key_name, name);
+        }
+    }
+
+    #[test]
+    fn rsa_keys_are_exported_in_pem() {
+        let pem = encode(
+            &rsa(Algorithm::None),
+            &rsa_public_key(),
+            PublicKeyFormat::Pem,
+        )
+        .unwrap();
+        assert_eq!(text(pem), RSA_PEM);
+    }
+
+    #[test]
+    fn rsa_keys_are_exported_as_jwk() {
+        let jwk = encode(
+            &rsa(Algorithm::None),
+            &rsa_public_key(),
+            PublicKeyFormat::Jwk,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&jwk).unwrap(),
+            serde_json::json!({"kty": "RSA", "n": RSA_N, "e": "AQAB"})
+        );
+    }
+
+    #[test]
+    fn rsa_keys_are_exported_for_ssh() {
+        let ssh = encode(
+            &rsa(Algorithm::None),
+            &rsa_public_key(),
+            PublicKeyFormat::Ssh,
+        )
+        .unwrap();
+        assert_eq!(text(ssh), RSA_SSH);
+    }
+
+    #[test]
+    fn rsa_keys_are_exported_as_cose_keys() {
+        let attributes = rsa(Algorithm::AsymmetricSignature(
+            AsymmetricSignature::RsaPss {
+                hash_alg: Hash::Sha256.into(),
+            },
+        ));
+        let cose_key = encode(&attributes, &rsa_public_key(), PublicKeyFormat::Cose).unwrap();
+        let cose_key = cbor::decode(&cose_key).unwrap();
+        assert_eq!(
+            cose_key.get(cose::KEY_TYPE),
+            Some(&Value::Integer(cose::KEY_TYPE_RSA))
+        );
+        assert_eq!(
+            cose_key.get(cose::KEY_ALGORITHM),
+            Some(&Value::Integer(-37))
+        );
+        assert_eq!(
+            cose_key.get(cose::RSA_N),
+            Some(&Value::bytes(&URL_SAFE_NO_PAD.decode(RSA_N).unwrap()))
+        );
+        assert_eq!(cose_key.get(cose::RSA_E), Some(&Value::bytes(&[1, 0, 1])));
+    }
+
+    #[test]
+    fn invalid_rsa_keys_are_refused() {
+        let mut public_key = rsa_public_key();
+        public_key.truncate(public_key.len() - 1);
+        assert!(encode(&rsa(Algorithm::None), &public_key, PublicKeyFormat::Pem).is_err());
+    }
+
+    #[test]
+    fn ecc_keys_are_exported_in_pem() {
+        let pem = encode(
+            &ecc(EccFamily::SecpR1, 256),
+            &p256_point(),
+            PublicKeyFormat::Pem,
+        )
+        .unwrap();
+        assert_eq!(text(pem), P256_PEM);
+    }
+
+    #[test]
+    fn ecc_keys_are_exported_as_jwk() {
+        let jwk = encode(
+            &ecc(EccFamily::SecpR1, 256),
+            &p256_point(),
+            PublicKeyFormat::Jwk,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&jwk).unwrap(),
+            serde_json::json!({"kty": "EC", "crv": "P-256", "x": P256_X, "y": P256_Y})
+        );
+    }
+
+    #[test]
+    fn ecc_keys_are_exported_for_ssh() {
+        let ssh = encode(
+            &ecc(EccFamily::SecpR1, 256),
+            &p256_point(),
+            PublicKeyFormat::Ssh,
+        )
+        .unwrap();
+        assert_eq!(text(ssh), P256_SSH);
+    }
+
+    #[test]
+    fn ecc_keys_are_exported_as_cose_keys() {
+        let cose_key = encode(
+            &ecc(EccFamily::SecpR1, 256),
+            &p256_point(),
+            PublicKeyFormat::Cose,
+        )
+        .unwrap();
+        let cose_key = cbor::decode(&cose_key).unwrap();
+        assert_eq!(
+            cose_key.get(cose::KEY_TYPE),
+            Some(&Value::Integer(cose::KEY_TYPE_EC2))
+        );
+        assert_eq!(cose_key.get(cose::KEY_ALGORITHM), Some(&Value::Integer(-7)));
+        assert_eq!(cose_key.get(cose::EC2_CURVE), Some(&Value::Integer(1)));
+        assert_eq!(
+            cose_key.get(cose::EC2_X),
+            Some(&Value::bytes(&URL_SAFE_NO_PAD.decode(P256_X).unwrap()))
+        );
+        assert_eq!(
+            cose_key.get(cose::EC2_Y),
+            Some(&Value::bytes(&URL_SAFE_NO_PAD.decode(P256_Y).unwrap()))
+        );
+    }
+
+    #[test]
+    fn cose_keys_have_no_algorithm_without_a_cose_one() {
+        let mut attributes = ecc(EccFamily::SecpR1, 256);
+        attributes.policy.permitted_algorithms = Algorithm::None;
+        let cose_key = encode(&attributes, &p256_point(), PublicKeyFormat::Cose).unwrap();
+        assert_eq!(
+            cbor::decode(&cose_key).unwrap().get(cose::KEY_ALGORITHM),
+            None
+        );
+    }
+
+    #[test]
+    fn curves_without_a_name_are_only_exported_in_pem() {
+        let attributes = ecc(EccFamily::BrainpoolPR1, 256);
+        let point = point(32);
+        assert!(encode(&attributes, &point, PublicKeyFormat::Pem).is_ok());
+        for format in [
+            PublicKeyFormat::Jwk,
+            PublicKeyFormat::Ssh,
+            PublicKeyFormat::Cose,
+        ] {
+            assert_eq!(
+                encode(&attributes, &point, format).unwrap_err(),
+                ResponseStatus::PsaErrorNotSupported
+            );
+        }
+    }
+
+    #[test]
+    fn secp256k1_keys_are_not_exported_for_ssh() {
+        let attributes = ecc(EccFamily::SecpK1, 256);
+        let point = point(32);
+        assert!(encode(&attributes, &point, PublicKeyFormat::Jwk).is_ok());
+        assert_eq!(
+            encode(&attributes, &point, PublicKeyFormat::Ssh).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn points_must_be_uncompressed_points_of_the_curve() {
+        let attributes = ecc(EccFamily::SecpR1, 256);
+        let point = p256_point();
+        assert_eq!(
+            encode(&attributes, &point[..33], PublicKeyFormat::Jwk).unwrap_err(),
+            ResponseStatus::PsaErrorCorruptionDetected
+        );
+        let mut compressed = point[..33].to_vec();
+        compressed[0] = 0x03;
+        assert_eq!(
+            encode(&attributes, &compressed, PublicKeyFormat::Pem).unwrap_err(),
+            ResponseStatus::PsaErrorCorruptionDetected
+        );
+        assert_eq!(
+            encode(&ecc(EccFamily::SecpR1, 384), &point, PublicKeyFormat::Pem).unwrap_err(),
+            ResponseStatus::PsaErrorCorruptionDetected
+        );
+    }
+
+    #[test]
+    fn unknown_curves_are_refused() {
+        assert_eq!(
+            encode(
+                &ecc(EccFamily::SecpR1, 192),
+                &point(24),
+                PublicKeyFormat::Pem
+            )
+            .unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn other_key_types_are_refused() {
+        let attributes = attributes(Type::RawData, 256, Algorithm::None);
+        assert_eq!(
+            encode(&attributes, &[0; 32], PublicKeyFormat::Pem).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Common encodings of asymmetric keys
+//!
+//! The PSA formats of the keys are not the ones in which keys are mostly stored and exchanged:
+//! PEM files, JSON Web Keys or, for public keys, OpenSSH. The keys in these formats are converted
+//! from and to the PSA formats when they are imported and exported, before and after the provider,
+//! whichever it is, so that clients do not have to do it.
+use parsec_interface::operations::psa_key_attributes::EccFamily;
+use zeroize::Zeroizing;
+
+#[cfg(feature = "key-import-formats")]
+mod ecc;
+#[cfg(feature = "key-export-formats")]
+pub mod export;
+#[cfg(feature = "key-import-formats")]
+pub mod import;
+
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// Named elliptic curve
+#[derive(Debug)]
+struct Curve {
+    oid: &'static [u8],
+    /// Name of the curve in JSON Web Keys
+    jwk_name: Option<&'static str>,
+    /// Name of the curve in OpenSSH keys
+    #[cfg_attr(not(feature = "key-export-formats"), allow(dead_code))]
+    ssh_name: Option<&'static str>,
+    family: EccFamily,
+    bits: usize,
+}
+
+impl Curve {
+    /// Size of the private value and of each coordinate of the points, in bytes
+    fn length(&self) -> usize {
+        (self.bits + 7) / 8
+    }
+}
+
+const CURVES: &[Curve] = &[
+    Curve {
+        oid: &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07],
+        jwk_name: Some("P-256"),
+        ssh_name: Some("nistp256"),
+        family: EccFamily::SecpR1,
+        bits: 256,
+    },
+    Curve {
+        oid: &[0x2b, 0x81, 0x04, 0x00, 0x22],
+        jwk_name: Some("P-384"),
+        ssh_name: Some("nistp384"),
+        family: EccFamily::SecpR1,
+        bits: 384,
+    },
+    Curve {
+        oid: &[0x2b, 0x81, 0x04, 0x00, 0x23],
+        jwk_name: Some("P-521"),
+        ssh_name: Some("nistp521"),
+        family: EccFamily::SecpR1,
+        bits: 521,
+    },
+    Curve {
+        oid: &[0x2b, 0x81, 0x04, 0x00, 0x0a],
+        jwk_name: Some("secp256k1"),
+        ssh_name: None,
+        family: EccFamily::SecpK1,
+        bits: 256,
+    },
+    Curve {
+        oid: &[0x2b, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x07],
+        jwk_name: None,
+        ssh_name: None,
+        family: EccFamily::BrainpoolPR1,
+        bits: 256,
+    },
+    Curve {
+        oid: &[0x2b, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0b],
+        jwk_name: None,
+        ssh_name: None,
+        family: EccFamily::BrainpoolPR1,
+        bits: 384,
+    },
+    Curve {
+        oid: &[0x2b, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0d],
+        jwk_name: None,
+        ssh_name: None,
+        family: EccFamily::BrainpoolPR1,
+        bits: 512,
+    },
+];
+
+fn der_length(length: usize, der: &mut Vec<u8>) {
+    if length < 0x80 {
+        der.push(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let significant = &bytes[bytes.iter().take_while(|byte| **byte == 0).count()..];
+        der.push(0x80 | significant.len() as u8);
+        der.extend_from_slice(significant);
+    }
+}
+
+/// DER element of the given tag and contents
+fn der_element(tag: u8, contents: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut der = Zeroizing::new(Vec::with_capacity(contents.len() + 6));
+    der.push(tag);
+    der_length(contents.len(), &mut der);
+    der.extend_from_slice(contents);
+    der
+}
+
+fn der_sequence(contents: &[u8]) -> Zeroizing<Vec<u8>> {
+    der_element(TAG_SEQUENCE, contents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn short_lengths_are_a_single_byte() {
+        assert_eq!(*der_element(TAG_SEQUENCE, &[1; 0x7f]), {
+            let mut der = vec![TAG_SEQUENCE, 0x7f];
+            der.extend_from_slice(&[1; 0x7f]);
+            der
+        });
+    }
+
+    #[test]
+    fn long_lengths_are_minimal() {
+        assert_eq!(&der_sequence(&[0; 0x80])[..3], &[TAG_SEQUENCE, 0x81, 0x80]);
+        assert_eq!(
+            &der_sequence(&[0; 0x100])[..4],
+            &[TAG_SEQUENCE, 0x82, 0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn curves_are_unique() {
+        for (index, curve) in CURVES.iter().enumerate() {
+            assert!(CURVES[index + 1..]
+                .iter()
+                .all(|other| other.oid != curve.oid
+                    && (other.family != curve.family || other.bits != curve.bits)));
+        }
+    }
+
+    #[test]
+    fn curve_lengths_round_up() {
+        let p521 = CURVES.iter().find(|curve| curve.bits == 521).unwrap();
+        assert_eq!(p521.length(), 66);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Residency and assurance requirements on created keys
+//!
+//! The service can be configured to only let keys be generated or imported in providers that
+//! meet some requirements, for example that keys live in hardware or that they can be attested,
+//! and to refuse keys that could be exported.
+use crate::utils::config::KeyRequirementsConfig;
+use log::error;
+use parsec_interface::operations::psa_key_attributes::Attributes;
+use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
+use std::collections::HashSet;
+
+/// Requirements that keys created through a provider must meet
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyRequirements {
+    hardware_backed: bool,
+    attestable: bool,
+    non_exportable: bool,
+}
+
+impl From<KeyRequirementsConfig> for KeyRequirements {
+    fn from(config: KeyRequirementsConfig) -> Self {
+        KeyRequirements {
+            hardware_backed: config.hardware_backed.unwrap_or(false),
+            attestable: config.attestable.unwrap_or(false),
+            non_exportable: config.non_exportable.unwrap_or(false),
+        }
+    }
+}
+
+/// Assurance offered by a provider for the keys it stores
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProviderAssurance {
+    hardware_backed: bool,
+    attestable: bool,
+}
+
+impl ProviderAssurance {
+    /// Assurance of a provider, given its ID and the operations it supports
+    pub fn new(provider_id: ProviderId, opcodes: &HashSet<Opcode>) -> Self {
+        ProviderAssurance {
+            hardware_backed: matches!(
+                provider_id,
+                ProviderId::Tpm
+                    | ProviderId::Pkcs11
+                    | ProviderId::CryptoAuthLib
+                    | ProviderId::TrustedService
+            ),
+            attestable: opcodes.contains(&Opcode::AttestKey),
+        }
+    }
+}
+
+impl KeyRequirements {
+    /// Check that a key with the given attributes can be created in a provider offering the given
+    /// assurance.
+    pub fn check(&self, assurance: ProviderAssurance, attributes: &Attributes) -> Result<()> {
+        if self.hardware_backed && !assurance.hardware_backed {
+            error!("Keys must be hardware-backed but this provider stores them in software.");
+            return Err(ResponseStatus::PsaErrorNotPermitted);
+        }
+        if self.attestable && !assurance.attestable {
+            error!("Keys must be attestable but this provider does not support key attestation.");
+            return Err(ResponseStatus::PsaErrorNotPermitted);
+        }
+        if self.non_exportable
+            && (attributes.policy.usage_flags.export() || attributes.policy.usage_flags.copy())
+        {
+            error!("Keys must not be exportable but the key policy allows it.");
+            return Err(ResponseStatus::PsaErrorNotPermitted);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash};
+    use parsec_interface::operations::psa_key_attributes::{Lifetime, Policy, Type, UsageFlags};
+
+    fn attributes(exportable: bool) -> Attributes {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags.set_sign_hash();
+        if exportable {
+            let _ = usage_flags.set_export();
+        }
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::RsaKeyPair,
+            bits: 2048,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms: Algorithm::AsymmetricSignature(
+                    AsymmetricSignature::RsaPkcs1v15Sign {
+                        hash_alg: Hash::Sha256.into(),
+                    },
+                ),
+            },
+        }
+    }
+
+    fn requirements(
+        hardware_backed: bool,
+        attestable: bool,
+        non_exportable: bool,
+    ) -> KeyRequirements {
+        KeyRequirements::from(KeyRequirementsConfig {
+            hardware_backed: Some(hardware_backed),
+            attestable: Some(attestable),
+            non_exportable: Some(non_exportable),
+        })
+    }
+
+    #[test]
+    fn nothing_required_by_default() {
+        let config = KeyRequirementsConfig {
+            hardware_backed: None,
+            attestable: None,
+            non_exportable: None,
+        };
+        assert_eq!(KeyRequirements::from(config), KeyRequirements::default());
+        let software = ProviderAssurance::new(ProviderId::MbedCrypto, &HashSet::new());
+        assert_eq!(
+            KeyRequirements::default().check(software, &attributes(true)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn hardware_backed_keys() {
+        let requirements = requirements(true, false, false);
+        for provider_id in &[ProviderId::MbedCrypto, ProviderId::Core] {
+            let software = ProviderAssurance::new(*provider_id, &HashSet::new());
+            assert_eq!(
+                requirements.check(software, &attributes(false)),
+                Err(ResponseStatus::PsaErrorNotPermitted)
+            );
+        }
+        for provider_id in &[
+            ProviderId::Tpm,
+            ProviderId::Pkcs11,
+            ProviderId::CryptoAuthLib,
+            ProviderId::TrustedService,
+        ] {
+            let hardware = ProviderAssurance::new(*provider_id, &HashSet::new());
+            assert_eq!(requirements.check(hardware, &attributes(false)), Ok(()));
+        }
+    }
+
+    #[test]
+    fn attestable_keys() {
+        let requirements = requirements(false, true, false);
+        let tpm = ProviderAssurance::new(ProviderId::Tpm, &HashSet::new());
+        assert_eq!(
+            requirements.check(tpm, &attributes(false)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let opcodes = [Opcode::AttestKey].iter().copied().collect();
+        let attesting_tpm = ProviderAssurance::new(ProviderId::Tpm, &opcodes);
+        assert_eq!(
+            requirements.check(attesting_tpm, &attributes(false)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn non_exportable_keys() {
+        let requirements = requirements(false, false, true);
+        let tpm = ProviderAssurance::new(ProviderId::Tpm, &HashSet::new());
+        assert_eq!(requirements.check(tpm, &attributes(false)), Ok(()));
+        assert_eq!(
+            requirements.check(tpm, &attributes(true)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let mut copyable = attributes(false);
+        let _ = copyable.policy.usage_flags.set_copy();
+        assert_eq!(
+            requirements.check(tpm, &copyable),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+}
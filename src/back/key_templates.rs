@@ -0,0 +1,212 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Key creation templates
+//!
+//! Operators can define named templates of keys in the configuration: the provider creating them
+//! and their complete attributes. Keys are then generated by template name, without the
+//! applications choosing the algorithms themselves: when templates are configured, the key name of
+//! a PsaGenerateKey request suffixed with `#template=` followed by the name of a template of the
+//! provider creates the key with the attributes of the template, whatever the request gives. Key
+//! names are left as they are when no template is configured. In strict mode, the keys generated or imported
+//! in a provider must have the exact attributes of one of its templates, which keeps the crypto
+//! policy in the configuration of the service rather than in the code of each application.
+use crate::utils::config::{KeyTemplatesConfig, KeyUsage};
+use crate::utils::key_suffixes;
+use log::error;
+use parsec_interface::operations::psa_key_attributes::{Attributes, Lifetime, Policy, UsageFlags};
+use parsec_interface::requests::{ProviderId, ResponseStatus, Result};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+/// Remove the template suffix from the key name, returning the template name it gives.
+pub fn strip_from(key_name: &mut String) -> Option<String> {
+    key_suffixes::strip_argument(key_name, key_suffixes::TEMPLATE)
+}
+
+/// Templates of the keys that can be created, by name
+#[derive(Debug)]
+pub struct KeyTemplates {
+    templates: HashMap<String, (ProviderId, Attributes)>,
+    strict: bool,
+}
+
+fn usage_flags(usage: &[KeyUsage]) -> UsageFlags {
+    let mut usage_flags = UsageFlags::default();
+    for usage in usage {
+        let _ = match usage {
+            KeyUsage::Export => usage_flags.set_export(),
+            KeyUsage::Copy => usage_flags.set_copy(),
+            KeyUsage::Cache => usage_flags.set_cache(),
+            KeyUsage::Encrypt => usage_flags.set_encrypt(),
+            KeyUsage::Decrypt => usage_flags.set_decrypt(),
+            KeyUsage::SignMessage => usage_flags.set_sign_message(),
+            KeyUsage::VerifyMessage => usage_flags.set_verify_message(),
+            KeyUsage::SignHash => usage_flags.set_sign_hash(),
+            KeyUsage::VerifyHash => usage_flags.set_verify_hash(),
+            KeyUsage::Derive => usage_flags.set_derive(),
+        };
+    }
+    usage_flags
+}
+
+impl KeyTemplates {
+    /// Create the templates from their configuration, given the IDs of the configured providers
+    /// by name.
+    pub fn new(
+        config: &KeyTemplatesConfig,
+        providers: &HashMap<String, ProviderId>,
+    ) -> std::io::Result<Self> {
+        let mut templates = HashMap::new();
+        for template in &config.template {
+            let provider_id = *providers.get(&template.provider).ok_or_else(|| {
+                error!(
+                    "The provider \"{}\" of the key template {} is not configured.",
+                    template.provider, template.name
+                );
+                Error::new(ErrorKind::InvalidData, "unknown key template provider")
+            })?;
+            let attributes = Attributes {
+                lifetime: template.lifetime.unwrap_or(Lifetime::Persistent),
+                key_type: template.key_type,
+                bits: template.bits,
+                policy: Policy {
+                    usage_flags: usage_flags(&template.usage),
+                    permitted_algorithms: template.algorithm,
+                },
+            };
+            if !attributes.is_alg_permitted(template.algorithm) {
+                error!(
+                    "The algorithm of the key template {} is not compatible with its key type.",
+                    template.name
+                );
+                return Err(Error::new(ErrorKind::InvalidData, "invalid key template"));
+            }
+            if templates
+                .insert(template.name.clone(), (provider_id, attributes))
+                .is_some()
+            {
+                error!("The key template {} is defined twice.", template.name);
+                return Err(Error::new(ErrorKind::InvalidData, "duplicate key template"));
+            }
+        }
+        Ok(KeyTemplates {
+            templates,
+            strict: config.strict.unwrap_or(false),
+        })
+    }
+
+    /// Attributes of the keys of a template of the provider
+    pub fn get(&self, provider_id: ProviderId, name: &str) -> Result<Attributes> {
+        match self.templates.get(name) {
+            Some((template_provider_id, attributes)) if *template_provider_id == provider_id => {
+                Ok(*attributes)
+            }
+            Some((template_provider_id, _)) => {
+                error!(
+                    "The key template {} is a template of provider {}, not {}.",
+                    name, template_provider_id, provider_id
+                );
+                Err(ResponseStatus::PsaErrorInvalidArgument)
+            }
+            None => {
+                error!("There is no key template named {}.", name);
+                Err(ResponseStatus::PsaErrorDoesNotExist)
+            }
+        }
+    }
+
+    /// Check, in strict mode, that a key created in a provider matches one of its templates.
+    pub fn check(&self, provider_id: ProviderId, attributes: &Attributes) -> Result<()> {
+        if !self.strict
+            || self
+                .templates
+                .values()
+                .any(|template| *template == (provider_id, *attributes))
+        {
+            Ok(())
+        } else {
+            error!("The attributes of the key do not match any key template of this provider.");
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::config::KeyTemplateConfig;
+    use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash};
+    use parsec_interface::operations::psa_key_attributes::{EccFamily, Type};
+
+    fn templates(strict: bool) -> KeyTemplates {
+        let providers = HashMap::from([(String::from("tpm-provider"), ProviderId::Tpm)]);
+        KeyTemplates::new(
+            &KeyTemplatesConfig {
+                strict: Some(strict),
+                template: vec![KeyTemplateConfig {
+                    name: String::from("tls-server"),
+                    provider: String::from("tpm-provider"),
+                    key_type: Type::EccKeyPair {
+                        curve_family: EccFamily::SecpR1,
+                    },
+                    bits: 256,
+                    algorithm: Algorithm::AsymmetricSignature(AsymmetricSignature::Ecdsa {
+                        hash_alg: Hash::Sha256.into(),
+                    }),
+                    usage: vec![KeyUsage::SignHash, KeyUsage::VerifyHash],
+                    lifetime: None,
+                }],
+            },
+            &providers,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn templates_are_got_by_provider() {
+        let templates = templates(false);
+        let attributes = templates.get(ProviderId::Tpm, "tls-server").unwrap();
+        assert!(attributes.policy.usage_flags.sign_hash());
+        assert!(!attributes.policy.usage_flags.export());
+        assert_eq!(attributes.bits, 256);
+        assert_eq!(
+            templates.get(ProviderId::MbedCrypto, "tls-server"),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+        assert_eq!(
+            templates.get(ProviderId::Tpm, "code-signing"),
+            Err(ResponseStatus::PsaErrorDoesNotExist)
+        );
+    }
+
+    #[test]
+    fn keys_must_match_a_template_in_strict_mode() {
+        let strict = templates(true);
+        let attributes = strict.get(ProviderId::Tpm, "tls-server").unwrap();
+        assert_eq!(strict.check(ProviderId::Tpm, &attributes), Ok(()));
+        assert_eq!(
+            strict.check(ProviderId::MbedCrypto, &attributes),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let mut exportable = attributes;
+        let _ = exportable.policy.usage_flags.set_export();
+        assert_eq!(
+            strict.check(ProviderId::Tpm, &exportable),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(templates(false).check(ProviderId::Tpm, &exportable), Ok(()));
+    }
+
+    #[test]
+    fn template_suffix_is_stripped() {
+        let mut key_name = String::from("web#template=tls-server");
+        assert_eq!(strip_from(&mut key_name), Some(String::from("tls-server")));
+        assert_eq!(key_name, "web");
+
+        for name in ["web", "web#template=", "#template=tls-server"] {
+            let mut key_name = String::from(name);
+            assert_eq!(strip_from(&mut key_name), None);
+            assert_eq!(key_name, name);
+        }
+    }
+}
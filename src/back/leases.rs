@@ -0,0 +1,169 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Time-limited grants of keys to other applications
+//!
+//! A lease lets an application, the grantee, use a key of another application, the owner, until
+//! the lease expires. The grantee names the leased key by suffixing its name with `#lease=` and the
+//! name of the owner, for example `signing-key#lease=alice`, in the requests to the provider holding
+//! the key. Keys can not be created with such names, so the names of the grantee's own keys never
+//! stand for a leased key. Leases only cover the operations using a key, never its export,
+//! destruction or attestation.
+//!
+//! Leases are declared in the configuration by the administrator, with their expiration time.
+//! Expired leases are dropped when they are next looked up. Every use and expiry is written to the
+//! audit log.
+use crate::authenticators::ApplicationIdentity;
+use crate::utils::config::KeyLeasesConfig;
+use crate::utils::key_suffixes;
+use crate::utils::logging::AUDIT_TARGET;
+use log::info;
+use parsec_interface::requests::{AuthType, Opcode, ProviderId};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone)]
+struct Lease {
+    owner: ApplicationIdentity,
+    key_name: String,
+    grantee: ApplicationIdentity,
+    expires: SystemTime,
+}
+
+impl Lease {
+    fn matches(&self, grantee: &ApplicationIdentity, owner: &str, key_name: &str) -> bool {
+        self.grantee == *grantee && self.owner.name() == owner && self.key_name == key_name
+    }
+}
+
+/// Leases in force
+#[derive(Debug)]
+pub struct KeyLeases {
+    leases: Mutex<Vec<Lease>>,
+}
+
+impl KeyLeases {
+    /// Create the leases of the configuration, for applications of the given authenticator.
+    pub fn new(config: &KeyLeasesConfig, auth_type: AuthType) -> Self {
+        let leases = config
+            .lease
+            .iter()
+            .flatten()
+            .map(|lease| Lease {
+                owner: ApplicationIdentity::new(lease.owner.clone(), auth_type),
+                key_name: lease.key_name.clone(),
+                grantee: ApplicationIdentity::new(lease.grantee.clone(), auth_type),
+                expires: UNIX_EPOCH + Duration::from_secs(lease.expires),
+            })
+            .collect();
+        KeyLeases {
+            leases: Mutex::new(leases),
+        }
+    }
+
+    /// Find the lease giving the application use of the key it names. If there is one, the lease
+    /// suffix is removed from the key name and the identity of the owner is returned.
+    pub fn resolve(
+        &self,
+        app: &ApplicationIdentity,
+        provider_id: ProviderId,
+        opcode: Opcode,
+        key_name: &mut String,
+    ) -> Option<ApplicationIdentity> {
+        let mut leases = self.leases.lock().expect("Key leases lock poisoned");
+        let now = SystemTime::now();
+        leases.retain(|lease| {
+            let expired = lease.expires <= now;
+            if expired {
+                info!(
+                    target: AUDIT_TARGET,
+                    "The lease of key \"{}\" of application \"{}\" to \"{}\" expired.",
+                    lease.key_name,
+                    lease.owner.name(),
+                    lease.grantee.name()
+                );
+            }
+            !expired
+        });
+        let mut base_name = key_name.clone();
+        let owner = key_suffixes::strip_argument(&mut base_name, key_suffixes::LEASE)?;
+        let lease = leases
+            .iter()
+            .find(|lease| lease.matches(app, &owner, &base_name))?;
+        info!(
+            target: AUDIT_TARGET,
+            "Application \"{}\" used key \"{}\" of application \"{}\" in provider {} ({:?}) under a lease.",
+            app.name(),
+            lease.key_name,
+            lease.owner.name(),
+            provider_id,
+            opcode
+        );
+        *key_name = base_name;
+        Some(lease.owner.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::config::KeyLeaseConfig;
+
+    fn leases(expires: SystemTime) -> KeyLeases {
+        KeyLeases::new(
+            &KeyLeasesConfig {
+                lease: Some(vec![KeyLeaseConfig {
+                    owner: "alice".to_string(),
+                    key_name: "key".to_string(),
+                    grantee: "bob".to_string(),
+                    expires: expires.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+                }]),
+            },
+            AuthType::Direct,
+        )
+    }
+
+    fn resolve(
+        leases: &KeyLeases,
+        app: &str,
+        key_name: &str,
+    ) -> Option<(ApplicationIdentity, String)> {
+        let mut key_name = key_name.to_string();
+        leases
+            .resolve(
+                &ApplicationIdentity::new(app.to_string(), AuthType::Direct),
+                ProviderId::MbedCrypto,
+                Opcode::PsaSignHash,
+                &mut key_name,
+            )
+            .map(|owner| (owner, key_name))
+    }
+
+    #[test]
+    fn grantee_names_leased_key_with_owner_suffix() {
+        let leases = leases(SystemTime::now() + Duration::from_secs(60));
+        assert_eq!(
+            resolve(&leases, "bob", "key#lease=alice"),
+            Some((
+                ApplicationIdentity::new("alice".to_string(), AuthType::Direct),
+                "key".to_string()
+            ))
+        );
+        assert_eq!(resolve(&leases, "bob", "other#lease=alice"), None);
+        assert_eq!(resolve(&leases, "bob", "key#lease=eve"), None);
+        assert_eq!(resolve(&leases, "bob", "key"), None);
+        assert_eq!(resolve(&leases, "bob", "alice/key"), None);
+    }
+
+    #[test]
+    fn other_applications_do_not_get_the_lease() {
+        let leases = leases(SystemTime::now() + Duration::from_secs(60));
+        assert_eq!(resolve(&leases, "eve", "key#lease=alice"), None);
+    }
+
+    #[test]
+    fn expired_leases_are_dropped() {
+        let leases = leases(SystemTime::now() - Duration::from_secs(1));
+        assert_eq!(resolve(&leases, "bob", "key#lease=alice"), None);
+        assert!(leases.leases.lock().unwrap().is_empty());
+    }
+}
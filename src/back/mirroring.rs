@@ -0,0 +1,298 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Mirroring of read-only operations to a second provider
+//!
+//! Before moving the keys of an application from one provider to another, for example from
+//! software keys to an HSM, operators can import the same keys in both and have the dispatcher
+//! send the read-only operations made on the first provider to the second one as well. The
+//! applications only get the responses of the first provider; the responses which differ are
+//! logged, showing whether the second provider can take over.
+use crate::authenticators::Application;
+use crate::utils::config::MirroringConfig;
+use crate::utils::logging::Redacted;
+use log::{error, trace, warn};
+use parsec_interface::requests::{Opcode, ProviderId, Request, Response, Result};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+/// Providers whose read-only operations are compared
+#[derive(Debug, Copy, Clone)]
+pub struct Mirroring {
+    provider: ProviderId,
+    mirror: ProviderId,
+}
+
+impl Mirroring {
+    /// Create the mirroring from its configuration, given the IDs of the configured providers by
+    /// name.
+    pub fn new(
+        config: &MirroringConfig,
+        providers: &HashMap<String, ProviderId>,
+    ) -> std::io::Result<Self> {
+        let provider_id = |name: &String| {
+            providers.get(name).copied().ok_or_else(|| {
+                error!("The mirrored provider \"{}\" is not configured.", name);
+                Error::new(ErrorKind::InvalidData, "unknown mirrored provider")
+            })
+        };
+        let provider = provider_id(&config.provider)?;
+        let mirror = provider_id(&config.mirror_provider)?;
+        if provider == mirror {
+            error!("A provider can not be mirrored to a provider with the same ID.");
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "provider mirrored to itself",
+            ));
+        }
+        Ok(Mirroring { provider, mirror })
+    }
+
+    /// ID of the provider receiving the mirrored requests
+    pub fn mirror(&self) -> ProviderId {
+        self.mirror
+    }
+
+    /// Whether a request is mirrored: it is a read-only operation of the mirrored provider.
+    pub fn is_mirrored(&self, request: &Request) -> bool {
+        request.header.provider == self.provider
+            && matches!(
+                request.header.opcode,
+                Opcode::PsaVerifyHash
+                    | Opcode::PsaVerifyMessage
+                    | Opcode::PsaExportPublicKey
+                    | Opcode::CanDoCrypto
+            )
+    }
+
+    /// Copy a request for the mirror provider, returning the original as well.
+    pub fn mirror_request(&self, request: Request) -> Result<(Request, Request)> {
+        // Requests can not be cloned: they are copied through their wire format.
+        let mut bytes = Vec::new();
+        request.write_to_stream(&mut bytes)?;
+        let original = Request::read_from_stream(&mut bytes.as_slice(), bytes.len())?;
+        let mut mirrored = Request::read_from_stream(&mut bytes.as_slice(), bytes.len())?;
+        mirrored.header.provider = self.mirror;
+        Ok((original, mirrored))
+    }
+
+    /// Compare the responses of both providers to a request, logging them if they differ.
+    pub fn compare(&self, app: Option<&Application>, response: &Response, mirrored: &Response) {
+        let application = match app {
+            Some(app) => format!("application \"{}\"", Redacted(app.identity().name())),
+            None => String::from("an unauthenticated client"),
+        };
+        match difference(response, mirrored) {
+            Some(Difference::Status) => warn!(
+                "Mirrored {:?} request of {} failed differently: {} from {}, {} from {}.",
+                response.header.opcode,
+                application,
+                response.header.status,
+                self.provider,
+                mirrored.header.status,
+                self.mirror
+            ),
+            Some(Difference::Result) => warn!(
+                "Mirrored {:?} request of {} got different results from {} and {}.",
+                response.header.opcode, application, self.provider, self.mirror
+            ),
+            None => trace!(
+                "Mirrored {:?} request got the same response from both providers.",
+                response.header.opcode
+            ),
+        }
+    }
+}
+
+/// How the response of the mirror provider differs from the one of the mirrored provider
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Difference {
+    Status,
+    Result,
+}
+
+fn difference(response: &Response, mirrored: &Response) -> Option<Difference> {
+    if response.header.status != mirrored.header.status {
+        Some(Difference::Status)
+    } else if response.body.bytes() != mirrored.body.bytes() {
+        Some(Difference::Result)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::{
+        psa_export_public_key, Convert, NativeOperation, NativeResult,
+    };
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+    use parsec_interface::requests::{AuthType, BodyType, ResponseStatus};
+    use parsec_interface::secrecy::ExposeSecret;
+
+    fn providers() -> HashMap<String, ProviderId> {
+        HashMap::from([
+            (String::from("mbed-crypto-provider"), ProviderId::MbedCrypto),
+            (String::from("pkcs11-provider"), ProviderId::Pkcs11),
+            (String::from("other-pkcs11-provider"), ProviderId::Pkcs11),
+        ])
+    }
+
+    fn mirroring(provider: &str, mirror_provider: &str) -> std::io::Result<Mirroring> {
+        Mirroring::new(
+            &MirroringConfig {
+                provider: String::from(provider),
+                mirror_provider: String::from(mirror_provider),
+            },
+            &providers(),
+        )
+    }
+
+    /// Mirroring of the Mbed Crypto provider to the PKCS 11 one
+    fn to_pkcs11() -> Mirroring {
+        mirroring("mbed-crypto-provider", "pkcs11-provider").unwrap()
+    }
+
+    fn build_request(provider: ProviderId, opcode: Opcode) -> Request {
+        let operation = NativeOperation::PsaExportPublicKey(psa_export_public_key::Operation {
+            key_name: String::from("migrated-key"),
+        });
+        Request {
+            header: RequestHeader {
+                provider,
+                session: 0,
+                content_type: BodyType::Protobuf,
+                accept_type: BodyType::Protobuf,
+                auth_type: AuthType::Direct,
+                opcode,
+            },
+            body: ProtobufConverter {}.operation_to_body(operation).unwrap(),
+            auth: RequestAuth::new(b"app".to_vec()),
+        }
+    }
+
+    fn response(status: ResponseStatus, public_key: &[u8]) -> Response {
+        let mut response = Response::from_request_header(
+            build_request(ProviderId::MbedCrypto, Opcode::PsaExportPublicKey).header,
+            status,
+        );
+        if status == ResponseStatus::Success {
+            let result = NativeResult::PsaExportPublicKey(psa_export_public_key::Result {
+                data: public_key.to_vec().into(),
+            });
+            response.body = ProtobufConverter {}.result_to_body(result).unwrap();
+        }
+        response
+    }
+
+    #[test]
+    fn read_only_operations_are_mirrored() {
+        let mirroring = to_pkcs11();
+        for opcode in &[
+            Opcode::PsaVerifyHash,
+            Opcode::PsaVerifyMessage,
+            Opcode::PsaExportPublicKey,
+            Opcode::CanDoCrypto,
+        ] {
+            assert!(mirroring.is_mirrored(&build_request(ProviderId::MbedCrypto, *opcode)));
+        }
+    }
+
+    #[test]
+    fn other_operations_are_not_mirrored() {
+        let mirroring = to_pkcs11();
+        for opcode in &[
+            Opcode::PsaSignHash,
+            Opcode::PsaGenerateKey,
+            Opcode::PsaDestroyKey,
+            Opcode::PsaExportKey,
+            Opcode::PsaGenerateRandom,
+        ] {
+            assert!(!mirroring.is_mirrored(&build_request(ProviderId::MbedCrypto, *opcode)));
+        }
+    }
+
+    #[test]
+    fn requests_to_other_providers_are_not_mirrored() {
+        let mirroring = to_pkcs11();
+        for provider in &[ProviderId::Pkcs11, ProviderId::Tpm, ProviderId::Core] {
+            assert!(!mirroring.is_mirrored(&build_request(*provider, Opcode::PsaExportPublicKey)));
+        }
+    }
+
+    #[test]
+    fn mirrored_requests_go_to_the_mirror() {
+        let mirroring = to_pkcs11();
+        assert_eq!(mirroring.mirror(), ProviderId::Pkcs11);
+        let request = build_request(ProviderId::MbedCrypto, Opcode::PsaExportPublicKey);
+        let body = request.body.bytes().to_vec();
+        let (request, mirrored) = mirroring.mirror_request(request).unwrap();
+        assert_eq!(request.header.provider, ProviderId::MbedCrypto);
+        assert_eq!(request.body.bytes(), &body[..]);
+        assert_eq!(mirrored.header.provider, ProviderId::Pkcs11);
+        assert_eq!(mirrored.header.opcode, Opcode::PsaExportPublicKey);
+        assert_eq!(mirrored.header.auth_type, AuthType::Direct);
+        assert_eq!(mirrored.body.bytes(), &body[..]);
+        assert_eq!(mirrored.auth.buffer.expose_secret(), b"app");
+    }
+
+    #[test]
+    fn unknown_providers_are_refused() {
+        assert_eq!(
+            mirroring("mbed-crypto-provider", "tpm-provider")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            mirroring("tpm-provider", "pkcs11-provider")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn providers_of_the_same_id_are_refused() {
+        assert_eq!(
+            mirroring("pkcs11-provider", "other-pkcs11-provider")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn same_responses_do_not_differ() {
+        assert_eq!(
+            difference(
+                &response(ResponseStatus::Success, b"key"),
+                &response(ResponseStatus::Success, b"key")
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn different_statuses_are_found() {
+        assert_eq!(
+            difference(
+                &response(ResponseStatus::Success, b"key"),
+                &response(ResponseStatus::PsaErrorDoesNotExist, b"")
+            ),
+            Some(Difference::Status)
+        );
+    }
+
+    #[test]
+    fn different_results_are_found() {
+        assert_eq!(
+            difference(
+                &response(ResponseStatus::Success, b"key"),
+                &response(ResponseStatus::Success, b"other key")
+            ),
+            Some(Difference::Result)
+        );
+    }
+}
@@ -1,5 +1,31 @@
 // Copyright 2019 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
 //! Routing and parsing requests for processing by providers
+pub mod aead;
+#[cfg(feature = "approvals")]
+pub mod approvals;
 pub mod backend_handler;
+#[cfg(feature = "cose-signing")]
+pub mod cose_sign1;
 pub mod dispatcher;
+pub mod ecdsa_nonces;
+#[cfg(feature = "import-checks")]
+pub mod import_checks;
+#[cfg(feature = "jws-signing")]
+pub mod jws;
+pub mod key_defaults;
+#[cfg(feature = "key-disabling")]
+pub mod key_disabling;
+#[cfg(any(feature = "key-import-formats", feature = "key-export-formats"))]
+pub mod key_formats;
+pub mod key_requirements;
+pub mod key_templates;
+pub mod leases;
+pub mod mirroring;
+pub mod priority;
+pub mod random_limits;
+pub mod result_cache;
+#[cfg(feature = "signing-policies")]
+pub mod signing_policy;
+#[cfg(feature = "tls13-signing")]
+pub mod tls13;
@@ -0,0 +1,231 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Priority between interactive and batch requests
+//!
+//! Applications can be configured as batch clients, for example bulk-signing jobs. Their requests
+//! are held back in front of each provider: only a limited number of them are executed at the same
+//! time, and none starts while an interactive request is being executed by the same provider. An
+//! interactive request then only waits for the few batch requests already started, instead of
+//! queuing behind all of them for the provider's resources.
+use crate::authenticators::Application;
+use crate::utils::config::RequestPriorityConfig;
+use std::collections::HashSet;
+use std::sync::{Arc, Condvar, Mutex};
+
+const DEFAULT_BATCH_CONCURRENCY: usize = 1;
+
+/// Priority class of a request
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RequestClass {
+    /// Latency-sensitive request, never held back
+    Interactive,
+    /// Request that can wait for the interactive ones
+    Batch,
+}
+
+/// Classification of the requests of the applications
+#[derive(Debug, Default)]
+pub struct RequestPriority {
+    batch_applications: HashSet<String>,
+    batch_concurrency: usize,
+}
+
+impl RequestPriority {
+    /// Class of the requests of an application. Unauthenticated requests are interactive.
+    pub fn class(&self, app: Option<&Application>) -> RequestClass {
+        match app {
+            Some(app) if self.batch_applications.contains(app.identity().name()) => {
+                RequestClass::Batch
+            }
+            _ => RequestClass::Interactive,
+        }
+    }
+
+    /// Create the gate ordering the requests of a provider.
+    pub fn gate(&self) -> Arc<PriorityGate> {
+        Arc::new(PriorityGate {
+            state: Mutex::new(GateState::default()),
+            changed: Condvar::new(),
+            batch_concurrency: self.batch_concurrency.max(1),
+        })
+    }
+}
+
+impl From<&RequestPriorityConfig> for RequestPriority {
+    fn from(config: &RequestPriorityConfig) -> Self {
+        RequestPriority {
+            batch_applications: config
+                .batch_applications
+                .iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            batch_concurrency: config
+                .batch_concurrency
+                .unwrap_or(DEFAULT_BATCH_CONCURRENCY),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    interactive: usize,
+    batch: usize,
+}
+
+/// Requests being executed by a provider, by class
+#[derive(Debug)]
+pub struct PriorityGate {
+    state: Mutex<GateState>,
+    changed: Condvar,
+    batch_concurrency: usize,
+}
+
+impl PriorityGate {
+    /// Wait until a request of the given class can be executed. It is accounted for until the
+    /// returned pass is dropped.
+    pub fn enter(&self, class: RequestClass) -> GatePass<'_> {
+        let mut state = self.state.lock().expect("Priority gate lock poisoned");
+        match class {
+            RequestClass::Interactive => state.interactive += 1,
+            RequestClass::Batch => {
+                while state.interactive > 0 || state.batch >= self.batch_concurrency {
+                    state = self
+                        .changed
+                        .wait(state)
+                        .expect("Priority gate lock poisoned");
+                }
+                state.batch += 1;
+            }
+        }
+        GatePass { gate: self, class }
+    }
+}
+
+/// Accounts for a request being executed, until dropped
+#[derive(Debug)]
+pub struct GatePass<'a> {
+    gate: &'a PriorityGate,
+    class: RequestClass,
+}
+
+impl Drop for GatePass<'_> {
+    fn drop(&mut self) {
+        let mut state = self.gate.state.lock().expect("Priority gate lock poisoned");
+        match self.class {
+            RequestClass::Interactive => state.interactive -= 1,
+            RequestClass::Batch => state.batch -= 1,
+        }
+        self.gate.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::authenticators::ApplicationIdentity;
+    use parsec_interface::requests::AuthType;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    // Long enough for a request that is not held back to get through the gate.
+    const HELD_BACK: Duration = Duration::from_millis(100);
+
+    fn priority(batch_concurrency: usize) -> RequestPriority {
+        RequestPriority::from(&RequestPriorityConfig {
+            batch_applications: Some(vec!["bulk-signer".to_string()]),
+            batch_concurrency: Some(batch_concurrency),
+        })
+    }
+
+    fn application(name: &str) -> Application {
+        Application::new(
+            ApplicationIdentity::new(name.to_string(), AuthType::UnixPeerCredentials),
+            false,
+        )
+    }
+
+    // Enter the gate with a batch request from another thread, which leaves when told to or at
+    // the end of the test.
+    fn enter_batch(gate: &Arc<PriorityGate>) -> (mpsc::Receiver<()>, mpsc::Sender<()>) {
+        let (entered, has_entered) = mpsc::channel();
+        let (leave, should_leave) = mpsc::channel::<()>();
+        let gate = gate.clone();
+        let _ = thread::spawn(move || {
+            let _pass = gate.enter(RequestClass::Batch);
+            entered.send(()).unwrap();
+            let _ = should_leave.recv();
+        });
+        (has_entered, leave)
+    }
+
+    #[test]
+    fn configured_applications_are_batch() {
+        let priority = priority(1);
+        assert_eq!(
+            priority.class(Some(&application("bulk-signer"))),
+            RequestClass::Batch
+        );
+        assert_eq!(
+            priority.class(Some(&application("browser"))),
+            RequestClass::Interactive
+        );
+        assert_eq!(priority.class(None), RequestClass::Interactive);
+    }
+
+    #[test]
+    fn default_is_interactive_only() {
+        let priority = RequestPriority::from(&RequestPriorityConfig {
+            batch_applications: None,
+            batch_concurrency: None,
+        });
+        assert_eq!(
+            priority.class(Some(&application("bulk-signer"))),
+            RequestClass::Interactive
+        );
+        assert_eq!(priority.batch_concurrency, DEFAULT_BATCH_CONCURRENCY);
+    }
+
+    #[test]
+    fn batch_concurrency_is_at_least_one() {
+        let gate = priority(0).gate();
+        let (has_entered, _leave) = enter_batch(&gate);
+        has_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn interactive_requests_are_never_held_back() {
+        let gate = priority(1).gate();
+        let (has_entered, _leave) = enter_batch(&gate);
+        has_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+        let _first = gate.enter(RequestClass::Interactive);
+        let _second = gate.enter(RequestClass::Interactive);
+    }
+
+    #[test]
+    fn batch_requests_wait_for_interactive_ones() {
+        let gate = priority(1).gate();
+        let interactive = gate.enter(RequestClass::Interactive);
+        let (has_entered, _leave) = enter_batch(&gate);
+        assert!(has_entered.recv_timeout(HELD_BACK).is_err());
+
+        drop(interactive);
+        has_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn batch_requests_are_limited() {
+        let gate = priority(2).gate();
+        let (first_entered, first_leave) = enter_batch(&gate);
+        let (second_entered, _second_leave) = enter_batch(&gate);
+        first_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+        second_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let (third_entered, _third_leave) = enter_batch(&gate);
+        assert!(third_entered.recv_timeout(HELD_BACK).is_err());
+
+        first_leave.send(()).unwrap();
+        third_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Limits on the generation of random bytes
+//!
+//! The random number generators of secure elements and TPMs are slow and shared by all the
+//! applications. In front of each provider, the size of a single PsaGenerateRandom request is
+//! bounded, and each application draws its bytes from a bucket refilled at the configured rate, up
+//! to the burst size. Requests over the size limit are refused with `PsaErrorInvalidArgument`,
+//! requests over the rate with `PsaErrorInsufficientEntropy`. Refusals are written to the audit
+//! log and the applications refused are reported in the status of the service.
+use crate::authenticators::{Application, ApplicationIdentity};
+use crate::utils::config::RandomLimitsConfig;
+use crate::utils::logging::AUDIT_TARGET;
+use crate::utils::service_status::ServiceStatus;
+use log::warn;
+use parsec_interface::requests::{ProviderId, ResponseStatus, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Maximum number of applications whose bucket is kept
+const MAX_BUCKETS: usize = 1024;
+
+#[derive(Debug)]
+struct Bucket {
+    bytes: f64,
+    refilled: Instant,
+}
+
+/// Limits of the random bytes generated by a provider
+#[derive(Debug)]
+pub struct RandomLimits {
+    provider_id: ProviderId,
+    max_size: Option<usize>,
+    bytes_per_second: Option<f64>,
+    burst_size: f64,
+    // Unauthenticated requests share a single bucket.
+    buckets: Mutex<HashMap<Option<ApplicationIdentity>, Bucket>>,
+}
+
+impl RandomLimits {
+    /// Create the limits of the configuration, for the given provider.
+    pub fn new(config: &RandomLimitsConfig, provider_id: ProviderId) -> Self {
+        let burst_size = config
+            .burst_size
+            .or(config.max_size)
+            .map_or(0.0, |size| size as f64)
+            .max(config.bytes_per_second.unwrap_or(0) as f64);
+        RandomLimits {
+            provider_id,
+            max_size: config.max_size,
+            bytes_per_second: config.bytes_per_second.map(|rate| rate as f64),
+            burst_size,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check that an application can get the given number of random bytes, taking them from its
+    /// bucket.
+    pub fn check(&self, app: Option<&Application>, size: usize) -> Result<()> {
+        let name = app.map_or("(unauthenticated)", |app| app.identity().name().as_str());
+        if let Some(max_size) = self.max_size {
+            if size > max_size {
+                warn!(
+                    target: AUDIT_TARGET,
+                    "Application \"{}\" asked {} for {} random bytes, more than the limit of {}.",
+                    name,
+                    self.provider_id,
+                    size,
+                    max_size
+                );
+                ServiceStatus::record_random_refusal(name);
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+        }
+        let bytes_per_second = match self.bytes_per_second {
+            Some(bytes_per_second) => bytes_per_second,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("Random limits lock poisoned");
+        if buckets.len() >= MAX_BUCKETS {
+            // Full buckets are the same as new ones.
+            let burst_size = self.burst_size;
+            buckets.retain(|_, bucket| {
+                bucket.bytes + now.duration_since(bucket.refilled).as_secs_f64() * bytes_per_second
+                    < burst_size
+            });
+        }
+        let bucket = buckets
+            .entry(app.map(|app| app.identity().clone()))
+            .or_insert(Bucket {
+                bytes: self.burst_size,
+                refilled: now,
+            });
+        bucket.bytes = (bucket.bytes
+            + now.duration_since(bucket.refilled).as_secs_f64() * bytes_per_second)
+            .min(self.burst_size);
+        bucket.refilled = now;
+        if size as f64 > bucket.bytes {
+            warn!(
+                target: AUDIT_TARGET,
+                "Application \"{}\" asked {} for {} random bytes, over its rate of {} bytes per second.",
+                name,
+                self.provider_id,
+                size,
+                bytes_per_second
+            );
+            ServiceStatus::record_random_refusal(name);
+            return Err(ResponseStatus::PsaErrorInsufficientEntropy);
+        }
+        bucket.bytes -= size as f64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::requests::AuthType;
+    use std::thread;
+    use std::time::Duration;
+
+    fn limits(
+        max_size: Option<usize>,
+        bytes_per_second: Option<u64>,
+        burst_size: Option<usize>,
+    ) -> RandomLimits {
+        RandomLimits::new(
+            &RandomLimitsConfig {
+                max_size,
+                bytes_per_second,
+                burst_size,
+            },
+            ProviderId::MbedCrypto,
+        )
+    }
+
+    fn application(name: &str) -> Application {
+        Application::new(
+            ApplicationIdentity::new(name.to_string(), AuthType::UnixPeerCredentials),
+            false,
+        )
+    }
+
+    #[test]
+    fn oversized_requests_are_refused() {
+        let limits = limits(Some(64), None, None);
+        let alice = application("alice");
+        assert_eq!(
+            limits.check(Some(&alice), 65),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+        assert!(limits.check(Some(&alice), 64).is_ok());
+    }
+
+    #[test]
+    fn size_alone_does_not_limit_the_rate() {
+        let limits = limits(Some(64), None, None);
+        let alice = application("alice");
+        for _ in 0..100 {
+            assert!(limits.check(Some(&alice), 64).is_ok());
+        }
+    }
+
+    #[test]
+    fn rate_is_limited_per_application() {
+        let limits = limits(Some(64), Some(1), Some(100));
+        let alice = application("alice");
+        let bob = application("bob");
+        assert!(limits.check(Some(&alice), 64).is_ok());
+        assert_eq!(
+            limits.check(Some(&alice), 64),
+            Err(ResponseStatus::PsaErrorInsufficientEntropy)
+        );
+        assert!(limits.check(Some(&alice), 32).is_ok());
+        assert!(limits.check(Some(&bob), 64).is_ok());
+    }
+
+    #[test]
+    fn unauthenticated_requests_share_a_bucket() {
+        let limits = limits(None, Some(1), Some(100));
+        assert!(limits.check(None, 60).is_ok());
+        assert_eq!(
+            limits.check(None, 60),
+            Err(ResponseStatus::PsaErrorInsufficientEntropy)
+        );
+        assert!(limits.check(Some(&application("alice")), 60).is_ok());
+    }
+
+    #[test]
+    fn buckets_are_refilled_at_the_rate() {
+        let limits = limits(None, Some(100), Some(100));
+        let alice = application("alice");
+        assert!(limits.check(Some(&alice), 100).is_ok());
+        assert_eq!(
+            limits.check(Some(&alice), 5),
+            Err(ResponseStatus::PsaErrorInsufficientEntropy)
+        );
+        thread::sleep(Duration::from_millis(100));
+        assert!(limits.check(Some(&alice), 5).is_ok());
+    }
+
+    #[test]
+    fn buckets_are_not_refilled_over_the_burst_size() {
+        let limits = limits(None, Some(100), Some(100));
+        let alice = application("alice");
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            limits.check(Some(&alice), 101),
+            Err(ResponseStatus::PsaErrorInsufficientEntropy)
+        );
+        assert!(limits.check(Some(&alice), 100).is_ok());
+    }
+
+    #[test]
+    fn burst_size_defaults_to_the_maximum_size() {
+        let limits = limits(Some(64), Some(1), None);
+        let alice = application("alice");
+        assert!(limits.check(Some(&alice), 64).is_ok());
+        assert_eq!(
+            limits.check(Some(&alice), 1),
+            Err(ResponseStatus::PsaErrorInsufficientEntropy)
+        );
+    }
+
+    #[test]
+    fn burst_size_is_at_least_the_rate() {
+        let limits = limits(Some(4), Some(10), Some(1));
+        let alice = application("alice");
+        assert!(limits.check(Some(&alice), 4).is_ok());
+        assert!(limits.check(Some(&alice), 4).is_ok());
+        assert_eq!(
+            limits.check(Some(&alice), 4),
+            Err(ResponseStatus::PsaErrorInsufficientEntropy)
+        );
+    }
+
+    #[test]
+    fn full_buckets_are_forgotten() {
+        let limits = limits(None, Some(1), Some(100));
+        assert!(limits.check(Some(&application("drained")), 100).is_ok());
+        for i in 1..MAX_BUCKETS {
+            assert!(limits
+                .check(Some(&application(&format!("app-{}", i))), 0)
+                .is_ok());
+        }
+        assert_eq!(limits.buckets.lock().unwrap().len(), MAX_BUCKETS);
+        assert!(limits.check(Some(&application("new")), 0).is_ok());
+        assert_eq!(limits.buckets.lock().unwrap().len(), 2);
+        assert_eq!(
+            limits.check(Some(&application("drained")), 1),
+            Err(ResponseStatus::PsaErrorInsufficientEntropy)
+        );
+    }
+}
@@ -0,0 +1,255 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Caching of the results of capability queries
+//!
+//! Client processes often discover the capabilities of the service when they start, sending the
+//! same queries: the lists of providers, authenticators and opcodes, or CanDoCrypto checks. As
+//! those queries do not depend on the keys nor on the application and do not change the state
+//! of the service, their responses are kept for a while by each back-end handler, keyed by their
+//! opcode and request body, and served again without reaching the provider.
+use parsec_interface::operations::{
+    can_do_crypto, list_authenticators, list_opcodes, list_providers, NativeResult,
+};
+use parsec_interface::requests::{Opcode, ResponseStatus, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Maximum number of results kept by a cache
+const MAX_ENTRIES: usize = 1024;
+
+#[derive(Debug)]
+struct CachedResult {
+    stored: Instant,
+    result: Result<NativeResult>,
+}
+
+/// Results of the cacheable queries of a provider
+#[derive(Debug)]
+pub struct ResultCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<(Opcode, Vec<u8>), CachedResult>>,
+}
+
+impl ResultCache {
+    /// Create a cache keeping results for `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        ResultCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the results of an operation can be cached.
+    pub fn is_cacheable(opcode: Opcode) -> bool {
+        matches!(
+            opcode,
+            Opcode::ListProviders
+                | Opcode::ListOpcodes
+                | Opcode::ListAuthenticators
+                | Opcode::CanDoCrypto
+        )
+    }
+
+    /// Get the result of a request, given its opcode and body, if cached and still fresh.
+    pub fn get(&self, opcode: Opcode, body: &[u8]) -> Option<Result<NativeResult>> {
+        let entries = self.entries.lock().expect("Result cache lock poisoned");
+        let cached = entries
+            .get(&(opcode, body.to_vec()))
+            .filter(|cached| cached.stored.elapsed() < self.ttl)?;
+        match &cached.result {
+            Ok(result) => clone_result(result).map(Ok),
+            Err(status) => Some(Err(*status)),
+        }
+    }
+
+    /// Store the result of a request. Only the answers that do not come from a transient
+    /// failure are kept: successes and unsupported cases.
+    pub fn store(&self, opcode: Opcode, body: Vec<u8>, result: &Result<NativeResult>) {
+        let result = match result {
+            Ok(result) => match clone_result(result) {
+                Some(result) => Ok(result),
+                None => return,
+            },
+            Err(ResponseStatus::PsaErrorNotSupported) => Err(ResponseStatus::PsaErrorNotSupported),
+            Err(_) => return,
+        };
+        let mut entries = self.entries.lock().expect("Result cache lock poisoned");
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, cached| cached.stored.elapsed() < self.ttl);
+            if entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        let _ = entries.insert(
+            (opcode, body),
+            CachedResult {
+                stored: Instant::now(),
+                result,
+            },
+        );
+    }
+}
+
+/// Copy the results of the cacheable operations, which do not implement `Clone`.
+fn clone_result(result: &NativeResult) -> Option<NativeResult> {
+    match result {
+        NativeResult::ListProviders(result) => {
+            Some(NativeResult::ListProviders(list_providers::Result {
+                providers: result.providers.clone(),
+            }))
+        }
+        NativeResult::ListOpcodes(result) => {
+            Some(NativeResult::ListOpcodes(list_opcodes::Result {
+                opcodes: result.opcodes.clone(),
+            }))
+        }
+        NativeResult::ListAuthenticators(result) => Some(NativeResult::ListAuthenticators(
+            list_authenticators::Result {
+                authenticators: result.authenticators.clone(),
+            },
+        )),
+        NativeResult::CanDoCrypto(_) => Some(NativeResult::CanDoCrypto(can_do_crypto::Result)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::ping;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn list_opcodes() -> Result<NativeResult> {
+        Ok(NativeResult::ListOpcodes(list_opcodes::Result {
+            opcodes: vec![Opcode::Ping].into_iter().collect(),
+        }))
+    }
+
+    fn can_do_crypto() -> Result<NativeResult> {
+        Ok(NativeResult::CanDoCrypto(can_do_crypto::Result))
+    }
+
+    #[test]
+    fn only_capability_queries_are_cacheable() {
+        assert!(ResultCache::is_cacheable(Opcode::ListProviders));
+        assert!(ResultCache::is_cacheable(Opcode::ListOpcodes));
+        assert!(ResultCache::is_cacheable(Opcode::ListAuthenticators));
+        assert!(ResultCache::is_cacheable(Opcode::CanDoCrypto));
+        assert!(!ResultCache::is_cacheable(Opcode::ListKeys));
+        assert!(!ResultCache::is_cacheable(Opcode::PsaGenerateRandom));
+    }
+
+    #[test]
+    fn successes_are_cached() {
+        let cache = ResultCache::new(TTL);
+        cache.store(Opcode::ListOpcodes, vec![2], &list_opcodes());
+        match cache.get(Opcode::ListOpcodes, &[2]) {
+            Some(Ok(NativeResult::ListOpcodes(result))) => {
+                assert!(result.opcodes.contains(&Opcode::Ping))
+            }
+            other => panic!("unexpected cached result {:?}", other),
+        }
+        // Results are served as many times as asked.
+        assert!(cache.get(Opcode::ListOpcodes, &[2]).is_some());
+    }
+
+    #[test]
+    fn unsupported_cases_are_cached() {
+        let cache = ResultCache::new(TTL);
+        cache.store(
+            Opcode::CanDoCrypto,
+            vec![1],
+            &Err(ResponseStatus::PsaErrorNotSupported),
+        );
+        assert_eq!(
+            cache.get(Opcode::CanDoCrypto, &[1]).unwrap().unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn transient_failures_are_not_cached() {
+        let cache = ResultCache::new(TTL);
+        cache.store(
+            Opcode::ListProviders,
+            vec![],
+            &Err(ResponseStatus::PsaErrorGenericError),
+        );
+        cache.store(
+            Opcode::CanDoCrypto,
+            vec![1],
+            &Err(ResponseStatus::PsaErrorInsufficientMemory),
+        );
+        assert!(cache.get(Opcode::ListProviders, &[]).is_none());
+        assert!(cache.get(Opcode::CanDoCrypto, &[1]).is_none());
+    }
+
+    #[test]
+    fn other_results_are_not_cached() {
+        let cache = ResultCache::new(TTL);
+        cache.store(
+            Opcode::Ping,
+            vec![],
+            &Ok(NativeResult::Ping(ping::Result {
+                wire_protocol_version_maj: 1,
+                wire_protocol_version_min: 0,
+            })),
+        );
+        assert!(cache.get(Opcode::Ping, &[]).is_none());
+    }
+
+    #[test]
+    fn results_are_kept_by_opcode_and_body() {
+        let cache = ResultCache::new(TTL);
+        cache.store(Opcode::CanDoCrypto, vec![1], &can_do_crypto());
+        assert!(cache.get(Opcode::CanDoCrypto, &[1]).is_some());
+        assert!(cache.get(Opcode::CanDoCrypto, &[2]).is_none());
+        assert!(cache.get(Opcode::ListOpcodes, &[1]).is_none());
+    }
+
+    #[test]
+    fn results_expire() {
+        let cache = ResultCache::new(Duration::from_millis(50));
+        cache.store(Opcode::CanDoCrypto, vec![1], &can_do_crypto());
+        assert!(cache.get(Opcode::CanDoCrypto, &[1]).is_some());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get(Opcode::CanDoCrypto, &[1]).is_none());
+    }
+
+    #[test]
+    fn number_of_results_is_bounded() {
+        let cache = ResultCache::new(TTL);
+        for body in 0..MAX_ENTRIES as u32 {
+            cache.store(
+                Opcode::CanDoCrypto,
+                body.to_le_bytes().to_vec(),
+                &can_do_crypto(),
+            );
+        }
+        let last = (MAX_ENTRIES as u32).to_le_bytes();
+        cache.store(Opcode::CanDoCrypto, last.to_vec(), &can_do_crypto());
+        assert!(cache.get(Opcode::CanDoCrypto, &last).is_none());
+        assert!(cache
+            .get(Opcode::CanDoCrypto, &0u32.to_le_bytes())
+            .is_some());
+    }
+
+    #[test]
+    fn expired_results_make_room() {
+        let cache = ResultCache::new(Duration::from_millis(50));
+        for body in 0..MAX_ENTRIES as u32 {
+            cache.store(
+                Opcode::CanDoCrypto,
+                body.to_le_bytes().to_vec(),
+                &can_do_crypto(),
+            );
+        }
+        std::thread::sleep(Duration::from_millis(60));
+        let last = (MAX_ENTRIES as u32).to_le_bytes();
+        cache.store(Opcode::CanDoCrypto, last.to_vec(), &can_do_crypto());
+        assert!(cache.get(Opcode::CanDoCrypto, &last).is_some());
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Restricted signing keys
+//!
+//! Build-signing appliances must not sign arbitrary blobs with their release keys: only the
+//! digests of the images their release pipeline has validated. A signing policy restricts a key of
+//! an application to the digests it lists, inline in the configuration or in a file the pipeline
+//! appends to once it has validated the manifest of an image. The file holds one hexadecimal digest
+//! per line, blank lines and lines starting with `#` being ignored. It is read once and again when
+//! its modification time or length changes, so that newly validated digests are signed without
+//! restarting the service.
+//!
+//! The policy is enforced by the back end before the request reaches the provider: PsaSignHash
+//! requests on a restricted key are refused with `PsaErrorNotPermitted` unless their hash is one of
+//! the digests of the policy. PsaSignMessage requests on it are refused unless the digest of their
+//! message, with the hash algorithm of their signature algorithm, is one of them. Messages are
+//! hashed by the service, with SHA-2 only: signature algorithms with another hash are refused with
+//! `PsaErrorNotSupported`. The requests wrapping the message in a JWS, a COSE_Sign1 structure or a
+//! TLS 1.3 CertificateVerify message are refused on a restricted key: the service signs the digest
+//! of the structure, which no pipeline has validated, not the one of the message. Every refusal is
+//! written to the audit log.
+use crate::authenticators::ApplicationIdentity;
+use crate::utils::config::SigningPoliciesConfig;
+use crate::utils::logging::{Redacted, AUDIT_TARGET};
+use log::{error, warn};
+use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
+use parsec_interface::requests::{AuthType, ResponseStatus, Result};
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512, Sha512_224, Sha512_256};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Digests read from a digests file, with the modification time and length it had
+#[derive(Debug)]
+struct FileDigests {
+    modified: SystemTime,
+    len: u64,
+    digests: Vec<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct SigningPolicy {
+    owner: ApplicationIdentity,
+    key_name: String,
+    digests: Vec<Vec<u8>>,
+    digests_file: Option<PathBuf>,
+    file_digests: Mutex<Option<FileDigests>>,
+}
+
+impl SigningPolicy {
+    fn read_error(&self, path: &Path, e: Error) -> ResponseStatus {
+        error!(
+            "Failed to read the digests file {} of key \"{}\": {}",
+            path.display(),
+            Redacted(&self.key_name),
+            e
+        );
+        ResponseStatus::PsaErrorGenericError
+    }
+
+    fn permits(&self, hash: &[u8]) -> Result<bool> {
+        if self.digests.iter().any(|digest| digest.as_slice() == hash) {
+            return Ok(true);
+        }
+        let path = match &self.digests_file {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let metadata = fs::metadata(path).map_err(|e| self.read_error(path, e))?;
+        let modified = metadata.modified().map_err(|e| self.read_error(path, e))?;
+        let mut file_digests = self
+            .file_digests
+            .lock()
+            .expect("Signing policy digests lock poisoned");
+        let up_to_date = file_digests
+            .as_ref()
+            .filter(|file| file.modified == modified && file.len == metadata.len());
+        if up_to_date.is_none() {
+            let digests = fs::read_to_string(path).map_err(|e| self.read_error(path, e))?;
+            *file_digests = Some(FileDigests {
+                modified,
+                len: metadata.len(),
+                digests: digests
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .filter_map(decode_digest)
+                    .collect(),
+            });
+        }
+        Ok(file_digests
+            .iter()
+            .flat_map(|file| file.digests.iter())
+            .any(|digest| digest.as_slice() == hash))
+    }
+}
+
+/// Digest of the message with the hash algorithm of the signature algorithm, if it is one the
+/// service can compute.
+fn message_digest(alg: AsymmetricSignature, message: &[u8]) -> Option<Vec<u8>> {
+    let digest = match alg.hash()? {
+        SignHash::Specific(Hash::Sha224) => Sha224::digest(message).to_vec(),
+        SignHash::Specific(Hash::Sha256) => Sha256::digest(message).to_vec(),
+        SignHash::Specific(Hash::Sha384) => Sha384::digest(message).to_vec(),
+        SignHash::Specific(Hash::Sha512) => Sha512::digest(message).to_vec(),
+        SignHash::Specific(Hash::Sha512_224) => Sha512_224::digest(message).to_vec(),
+        SignHash::Specific(Hash::Sha512_256) => Sha512_256::digest(message).to_vec(),
+        _ => return None,
+    };
+    Some(digest)
+}
+
+fn decode_digest(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+/// Signing policies of the restricted keys
+#[derive(Debug)]
+pub struct SigningPolicies {
+    policies: Vec<SigningPolicy>,
+}
+
+impl SigningPolicies {
+    /// Create the policies of the configuration, for applications of the given authenticator.
+    pub fn new(config: &SigningPoliciesConfig, auth_type: AuthType) -> std::io::Result<Self> {
+        let policies = config
+            .policy
+            .iter()
+            .flatten()
+            .map(|policy| {
+                let digests = policy
+                    .digests
+                    .iter()
+                    .flatten()
+                    .map(|digest| {
+                        decode_digest(digest).ok_or_else(|| {
+                            Error::new(
+                                ErrorKind::InvalidData,
+                                format!(
+                                    "Digest \"{}\" of the signing policy of key \"{}\" is not hexadecimal",
+                                    digest, policy.key_name
+                                ),
+                            )
+                        })
+                    })
+                    .collect::<std::io::Result<_>>()?;
+                Ok(SigningPolicy {
+                    owner: ApplicationIdentity::new(policy.owner.clone(), auth_type),
+                    key_name: policy.key_name.clone(),
+                    digests,
+                    digests_file: policy.digests_file.as_ref().map(PathBuf::from),
+                    file_digests: Mutex::new(None),
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(SigningPolicies { policies })
+    }
+
+    fn find(&self, user: &ApplicationIdentity, key_name: &str) -> Option<&SigningPolicy> {
+        self.policies
+            .iter()
+            .find(|policy| policy.owner == *user && policy.key_name == key_name)
+    }
+
+    /// Check that the key of the user may sign the hash.
+    pub fn check_hash(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+        hash: &[u8],
+    ) -> Result<()> {
+        let policy = match self.find(user, key_name) {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        if policy.permits(hash)? {
+            return Ok(());
+        }
+        warn!(
+            target: AUDIT_TARGET,
+            "Signature of key \"{}\" of application \"{}\" refused: digest {} is not permitted by its signing policy.",
+            key_name,
+            user.name(),
+            hash.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()
+        );
+        Err(ResponseStatus::PsaErrorNotPermitted)
+    }
+
+    /// Check that the key of the user may sign the message with the algorithm: the digest of the
+    /// message must be permitted by the policy of the key.
+    pub fn check_message(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+        alg: AsymmetricSignature,
+        message: &[u8],
+    ) -> Result<()> {
+        if self.find(user, key_name).is_none() {
+            return Ok(());
+        }
+        match message_digest(alg, message) {
+            Some(digest) => self.check_hash(user, key_name, &digest),
+            None => {
+                warn!(
+                    target: AUDIT_TARGET,
+                    "Message signature of key \"{}\" of application \"{}\" refused: the service cannot hash the message for algorithm {:?}.",
+                    key_name,
+                    user.name(),
+                    alg
+                );
+                Err(ResponseStatus::PsaErrorNotSupported)
+            }
+        }
+    }
+
+    /// Check that the key of the user may sign a structure wrapping the message of a request,
+    /// which it may not if it is restricted.
+    #[cfg(any(
+        feature = "jws-signing",
+        feature = "cose-signing",
+        feature = "tls13-signing"
+    ))]
+    pub fn check_wrapped_message(
+        &self,
+        user: &ApplicationIdentity,
+        key_name: &str,
+        structure: &str,
+    ) -> Result<()> {
+        if self.find(user, key_name).is_none() {
+            return Ok(());
+        }
+        warn!(
+            target: AUDIT_TARGET,
+            "Message signature of key \"{}\" of application \"{}\" refused: the message of a restricted key can not be signed as a {}.",
+            key_name,
+            user.name(),
+            structure
+        );
+        Err(ResponseStatus::PsaErrorNotPermitted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::config::SigningPolicyConfig;
+
+    const SHA256_OF_IMAGE: &str =
+        "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    const SIGN_SHA256: AsymmetricSignature = AsymmetricSignature::Ecdsa {
+        hash_alg: SignHash::Specific(Hash::Sha256),
+    };
+
+    fn policies(digests_file: Option<&Path>) -> SigningPolicies {
+        SigningPolicies::new(
+            &SigningPoliciesConfig {
+                policy: Some(vec![SigningPolicyConfig {
+                    owner: "builder".to_string(),
+                    key_name: "release".to_string(),
+                    digests: Some(vec!["00ff".to_string()]),
+                    digests_file: digests_file.map(|path| path.display().to_string()),
+                }]),
+            },
+            AuthType::Direct,
+        )
+        .unwrap()
+    }
+
+    fn builder() -> ApplicationIdentity {
+        ApplicationIdentity::new("builder".to_string(), AuthType::Direct)
+    }
+
+    #[test]
+    fn only_permitted_hashes_are_signed() {
+        let policies = policies(None);
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x00, 0xff]),
+            Ok(())
+        );
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x0a, 0x0b]),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn unrestricted_keys_sign_anything() {
+        let policies = policies(None);
+        let other = ApplicationIdentity::new("other".to_string(), AuthType::Direct);
+        assert_eq!(policies.check_hash(&builder(), "test", &[0x0a]), Ok(()));
+        assert_eq!(policies.check_hash(&other, "release", &[0x0a]), Ok(()));
+        assert_eq!(
+            policies.check_message(&other, "release", SIGN_SHA256, b"anything"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn digests_file_is_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let digests_file = dir.path().join("digests");
+        fs::write(&digests_file, "# release 1.2\n\n0a0b0c\nnot hexadecimal\n").unwrap();
+        let policies = policies(Some(&digests_file));
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x0a, 0x0b, 0x0c]),
+            Ok(())
+        );
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x00, 0xff]),
+            Ok(())
+        );
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x0a, 0x0b]),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn digests_file_is_reloaded_when_it_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let digests_file = dir.path().join("digests");
+        fs::write(&digests_file, "0a0b0c\n").unwrap();
+        let policies = policies(Some(&digests_file));
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x0d, 0x0e]),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+
+        fs::write(&digests_file, "0a0b0c\n0d0e\n").unwrap();
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x0d, 0x0e]),
+            Ok(())
+        );
+
+        fs::write(&digests_file, "0a0b0c\n").unwrap();
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x0d, 0x0e]),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn missing_digests_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let policies = policies(Some(&dir.path().join("digests")));
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x0a, 0x0b, 0x0c]),
+            Err(ResponseStatus::PsaErrorGenericError)
+        );
+        // Digests of the configuration do not need the file.
+        assert_eq!(
+            policies.check_hash(&builder(), "release", &[0x00, 0xff]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn messages_are_checked_by_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let digests_file = dir.path().join("digests");
+        fs::write(&digests_file, SHA256_OF_IMAGE).unwrap();
+        let policies = policies(Some(&digests_file));
+        // SHA-256 of "test"
+        assert_eq!(
+            policies.check_message(&builder(), "release", SIGN_SHA256, b"test"),
+            Ok(())
+        );
+        assert_eq!(
+            policies.check_message(&builder(), "release", SIGN_SHA256, b"other image"),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        let sign_sha384 = AsymmetricSignature::RsaPss {
+            hash_alg: SignHash::Specific(Hash::Sha384),
+        };
+        assert_eq!(
+            policies.check_message(&builder(), "release", sign_sha384, b"test"),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn messages_not_hashed_with_sha2_are_refused() {
+        let policies = policies(None);
+        let sign_sha3 = AsymmetricSignature::Ecdsa {
+            hash_alg: SignHash::Specific(Hash::Sha3_256),
+        };
+        assert_eq!(
+            policies.check_message(&builder(), "release", sign_sha3, b"test"),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+        assert_eq!(
+            policies.check_message(
+                &builder(),
+                "release",
+                AsymmetricSignature::RsaPkcs1v15SignRaw,
+                b"test"
+            ),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[cfg(any(
+        feature = "jws-signing",
+        feature = "cose-signing",
+        feature = "tls13-signing"
+    ))]
+    #[test]
+    fn wrapped_messages_of_restricted_keys_are_refused() {
+        let policies = policies(None);
+        assert_eq!(
+            policies.check_wrapped_message(&builder(), "release", "JWS"),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            policies.check_wrapped_message(&builder(), "other", "JWS"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn invalid_digests_are_not_decoded() {
+        assert_eq!(decode_digest("0a0B"), Some(vec![0x0a, 0x0b]));
+        assert!(decode_digest("0g").is_none());
+        assert!(decode_digest("0a0").is_none());
+        assert!(decode_digest("é0").is_none());
+    }
+}
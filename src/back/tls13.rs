@@ -0,0 +1,422 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! TLS 1.3 CertificateVerify signatures
+//!
+//! TLS stacks delegating their private keys to the service, for example local proxies terminating
+//! TLS, only need it to sign the CertificateVerify messages of the handshakes (RFC 8446, section
+//! 4.4.3). A `PsaSignMessage` request on the key name suffixed with `#tls13-server` or
+//! `#tls13-client` signs the transcript hash given as its message, which the service wraps with
+//! the context string of the side of the connection before hashing it. The signature returned is
+//! the one the CertificateVerify message carries: DER encoded for ECDSA.
+//!
+//! The algorithm of the request must be the one the key permits and a TLS 1.3 signature scheme:
+//! RSA-PSS with SHA-256, SHA-384 or SHA-512 and, as in PSA, a salt as long as the hash, TLS 1.3
+//! forbidding PKCS #1 v1.5 signatures of the handshake, or ECDSA on the curve matching the hash,
+//! for example P-384 with SHA-384. The transcript hash must be as long as the output of the hash.
+//! The key must be permitted to sign hashes.
+//!
+//! With rustls, a `SigningKey` can hand out a `Signer` forwarding the transcript hash to Parsec.
+//! rustls gives the signer the content to sign, of which the transcript hash is the end:
+//!
+//! ```ignore
+//! impl Signer for ParsecSigner {
+//!     fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+//!         let transcript_hash = &message[message.len() - self.hash_len..];
+//!         let key_name = format!("{}#tls13-server", self.key_name);
+//!         self.client
+//!             .lock()
+//!             .unwrap()
+//!             .psa_sign_message(&key_name, transcript_hash, self.alg)
+//!             .map_err(|e| rustls::Error::General(e.to_string()))
+//!     }
+//!
+//!     fn scheme(&self) -> SignatureScheme {
+//!         self.scheme
+//!     }
+//! }
+//! ```
+use crate::providers::utils::ecdsa_signature;
+use crate::utils::key_suffixes;
+use log::error;
+use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_key_attributes::{Attributes, EccFamily, Type};
+use parsec_interface::requests::{ResponseStatus, Result};
+use ring::digest;
+
+/// Context string of the server CertificateVerify messages
+const SERVER_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify";
+/// Context string of the client CertificateVerify messages
+const CLIENT_CONTEXT: &[u8] = b"TLS 1.3, client CertificateVerify";
+/// Length of the padding of spaces before the context string
+const PADDING_LEN: usize = 64;
+
+/// Side of the connection whose CertificateVerify message is signed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    /// The server, authenticating to the client
+    Server,
+    /// The client, authenticating to the server with a certificate
+    Client,
+}
+
+impl Side {
+    /// Remove the suffix of a side from the key name, returning that side.
+    pub fn strip_from(key_name: &mut String) -> Option<Self> {
+        if key_suffixes::strip_flag(key_name, key_suffixes::TLS13_SERVER) {
+            Some(Side::Server)
+        } else if key_suffixes::strip_flag(key_name, key_suffixes::TLS13_CLIENT) {
+            Some(Side::Client)
+        } else {
+            None
+        }
+    }
+
+    fn context(self) -> &'static [u8] {
+        match self {
+            Side::Server => SERVER_CONTEXT,
+            Side::Client => CLIENT_CONTEXT,
+        }
+    }
+}
+
+/// Hash of the TLS 1.3 signature scheme of the algorithm and the key of the given attributes
+pub fn scheme_hash(attributes: &Attributes, alg: AsymmetricSignature) -> Result<Hash> {
+    if attributes.policy.permitted_algorithms != Algorithm::AsymmetricSignature(alg) {
+        error!("CertificateVerify messages can only be signed with the algorithm the key permits.");
+        return Err(ResponseStatus::PsaErrorNotPermitted);
+    }
+    let hash = match (alg, attributes.key_type, attributes.bits) {
+        (
+            AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Specific(hash),
+            },
+            Type::RsaKeyPair,
+            _,
+        ) if matches!(hash, Hash::Sha256 | Hash::Sha384 | Hash::Sha512) => Some(hash),
+        (
+            AsymmetricSignature::Ecdsa {
+                hash_alg: SignHash::Specific(hash),
+            }
+            | AsymmetricSignature::DeterministicEcdsa {
+                hash_alg: SignHash::Specific(hash),
+            },
+            Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1,
+            },
+            bits,
+        ) => match (bits, hash) {
+            (256, Hash::Sha256) | (384, Hash::Sha384) | (521, Hash::Sha512) => Some(hash),
+            _ => None,
+        },
+        _ => None,
+    };
+    hash.ok_or_else(|| {
+        error!(
+            "No TLS 1.3 signature scheme signs with {:?} and a {}-bit {:?} key.",
+            alg, attributes.bits, attributes.key_type
+        );
+        ResponseStatus::PsaErrorNotSupported
+    })
+}
+
+/// Hash of the content covered by the CertificateVerify signature of the side, to be signed
+pub fn digest(hash: Hash, side: Side, transcript_hash: &[u8]) -> Result<Vec<u8>> {
+    let algorithm = match hash {
+        Hash::Sha384 => &digest::SHA384,
+        Hash::Sha512 => &digest::SHA512,
+        _ => &digest::SHA256,
+    };
+    if transcript_hash.len() != algorithm.output_len {
+        error!(
+            "The transcript hash is {} bytes long instead of the {} bytes of {:?}.",
+            transcript_hash.len(),
+            algorithm.output_len,
+            hash
+        );
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    let mut context = digest::Context::new(algorithm);
+    context.update(&[b' '; PADDING_LEN]);
+    context.update(side.context());
+    context.update(&[0]);
+    context.update(transcript_hash);
+    Ok(context.finish().as_ref().to_vec())
+}
+
+/// Signature of the CertificateVerify message, from the one in the PSA format
+pub fn encode_signature(alg: AsymmetricSignature, signature: Vec<u8>) -> Result<Vec<u8>> {
+    if !alg.is_ecc_alg() {
+        return Ok(signature);
+    }
+    ecdsa_signature::raw_to_der(&signature).ok_or_else(|| {
+        error!("The ECDSA signature of the CertificateVerify message can not be DER encoded.");
+        ResponseStatus::PsaErrorGenericError
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_helpers::{attributes, hex};
+    use ring::rand::SystemRandom;
+    use ring::signature::{
+        EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1,
+        ECDSA_P256_SHA256_FIXED_SIGNING,
+    };
+
+    /// "TLS 1.3, server CertificateVerify", as given in RFC 8446, section 4.4.3
+    const RFC_8446_SERVER_CONTEXT: &str =
+        "544c5320312e332c207365727665722043657274696669636174655665726966 79";
+
+    fn ec(curve_family: EccFamily) -> Type {
+        Type::EccKeyPair { curve_family }
+    }
+
+    fn ecdsa(hash: Hash) -> AsymmetricSignature {
+        AsymmetricSignature::Ecdsa {
+            hash_alg: hash.into(),
+        }
+    }
+
+    fn pss(hash: Hash) -> AsymmetricSignature {
+        AsymmetricSignature::RsaPss {
+            hash_alg: hash.into(),
+        }
+    }
+
+    /// Hash of the scheme of the key permitting only the algorithm
+    fn hash(key_type: Type, bits: usize, alg: AsymmetricSignature) -> Result<Hash> {
+        scheme_hash(&attributes(key_type, bits, alg), alg)
+    }
+
+    /// Content covered by the signature of the example of RFC 8446, section 4.4.3, whose
+    /// transcript hash is 32 bytes of 01
+    fn rfc_8446_content() -> Vec<u8> {
+        let mut content = vec![0x20; 64];
+        content.extend(hex(RFC_8446_SERVER_CONTEXT));
+        content.push(0x00);
+        content.extend_from_slice(&[0x01; 32]);
+        content
+    }
+
+    #[test]
+    fn strip_suffixes() {
+        let mut key_name = String::from("proxy#tls13-client");
+        assert_eq!(Side::strip_from(&mut key_name), Some(Side::Client));
+        assert_eq!(key_name, "proxy");
+        assert_eq!(Side::strip_from(&mut key_name), None);
+        assert_eq!(key_name, "proxy");
+
+        let mut key_name = String::from("proxy#tls13-server");
+        assert_eq!(Side::strip_from(&mut key_name), Some(Side::Server));
+        assert_eq!(key_name, "proxy");
+
+        // Only the last suffix is removed.
+        let mut key_name = String::from("proxy#tls13-server#tls13-client");
+        assert_eq!(Side::strip_from(&mut key_name), Some(Side::Client));
+        assert_eq!(key_name, "proxy#tls13-server");
+
+        let mut key_name = String::from("proxy#tls13");
+        assert_eq!(Side::strip_from(&mut key_name), None);
+        assert_eq!(key_name, "proxy#tls13");
+    }
+
+    #[test]
+    fn rsa_pss_schemes() {
+        for scheme_hash in [Hash::Sha256, Hash::Sha384, Hash::Sha512] {
+            for bits in [2048, 3072, 4096] {
+                assert_eq!(
+                    hash(Type::RsaKeyPair, bits, pss(scheme_hash)),
+                    Ok(scheme_hash)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn refuse_other_rsa_algorithms() {
+        let unsupported = [
+            // Forbidden for the handshake signatures
+            AsymmetricSignature::RsaPkcs1v15Sign {
+                hash_alg: Hash::Sha256.into(),
+            },
+            AsymmetricSignature::RsaPkcs1v15SignRaw,
+            pss(Hash::Sha224),
+            AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Any,
+            },
+        ];
+        for alg in unsupported {
+            assert_eq!(
+                hash(Type::RsaKeyPair, 2048, alg),
+                Err(ResponseStatus::PsaErrorNotSupported)
+            );
+        }
+    }
+
+    #[test]
+    fn ecdsa_schemes() {
+        for (bits, scheme_hash) in [
+            (256, Hash::Sha256),
+            (384, Hash::Sha384),
+            (521, Hash::Sha512),
+        ] {
+            let secp_r1 = ec(EccFamily::SecpR1);
+            assert_eq!(hash(secp_r1, bits, ecdsa(scheme_hash)), Ok(scheme_hash));
+            let deterministic = AsymmetricSignature::DeterministicEcdsa {
+                hash_alg: scheme_hash.into(),
+            };
+            assert_eq!(hash(secp_r1, bits, deterministic), Ok(scheme_hash));
+        }
+    }
+
+    #[test]
+    fn refuse_ecdsa_without_scheme() {
+        let unsupported = [
+            // Hashes not matching the curve
+            (EccFamily::SecpR1, 384, Hash::Sha256),
+            (EccFamily::SecpR1, 256, Hash::Sha384),
+            (EccFamily::SecpR1, 521, Hash::Sha256),
+            // Curves without a TLS 1.3 signature scheme
+            (EccFamily::SecpR1, 224, Hash::Sha256),
+            (EccFamily::SecpK1, 256, Hash::Sha256),
+            (EccFamily::BrainpoolPR1, 256, Hash::Sha256),
+        ];
+        for (curve_family, bits, scheme_hash) in unsupported {
+            assert_eq!(
+                hash(ec(curve_family), bits, ecdsa(scheme_hash)),
+                Err(ResponseStatus::PsaErrorNotSupported)
+            );
+        }
+        assert_eq!(
+            hash(Type::RsaKeyPair, 2048, ecdsa(Hash::Sha256)),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+        assert_eq!(
+            hash(ec(EccFamily::SecpR1), 256, pss(Hash::Sha256)),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[test]
+    fn algorithm_must_be_the_permitted_one() {
+        let attributes = attributes(Type::RsaKeyPair, 2048, pss(Hash::Sha384));
+        assert_eq!(
+            scheme_hash(&attributes, pss(Hash::Sha256)),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn digest_of_rfc_8446_example() {
+        assert_eq!(
+            digest(Hash::Sha256, Side::Server, &[0x01; 32]).unwrap(),
+            digest::digest(&digest::SHA256, &rfc_8446_content()).as_ref()
+        );
+    }
+
+    #[test]
+    fn digests_of_both_sides() {
+        // SHA-2 of the content covered by the signatures of a transcript hash of 01 bytes
+        let digests = [
+            (
+                Hash::Sha256,
+                Side::Server,
+                "c77c68e9bbbd2a07754bb3f85bec5fedbebf7afa09e22b4304b24f6ee7938a6c",
+            ),
+            (
+                Hash::Sha256,
+                Side::Client,
+                "e8dd44affaa96f95becf86068ccb967612efa9ce694526df4c7167c80ef441fe",
+            ),
+            (
+                Hash::Sha384,
+                Side::Server,
+                "fd93cc3cf17329920ea876dc7ed2bf38475c8e39a33b9fcf
+                 63bae9474f3b00ff16f57b319921095afe4d5d3bc85ee17e",
+            ),
+            (
+                Hash::Sha384,
+                Side::Client,
+                "e440ca5d14a404ecbf6e995bc08500ac3c1416724502c7a2
+                 22c45778915c2761a0393f44cc3bb357a468c12e94a67ef8",
+            ),
+            (
+                Hash::Sha512,
+                Side::Server,
+                "b5913296d876cfe1afc8dcf65fed935a4eeb65ebc8b45088f43776c792902c0d
+                 e9cc568ebecf210b7b2a36be364628daedd3c0f536dc499b145a2c3595897297",
+            ),
+            (
+                Hash::Sha512,
+                Side::Client,
+                "54570e12518e8724ea9f6c26eba01f2f23cb45d4f4aff9f16dc3162b0790cb34
+                 26e0bf4e8567ee1ca082019af9e0a874db892a8af13867c5a4f6fb8ec48db148",
+            ),
+        ];
+        for (scheme_hash, side, expected) in digests {
+            // The transcript hash is as long as the digest.
+            let expected = hex(expected);
+            assert_eq!(
+                digest(scheme_hash, side, &vec![0x01; expected.len()]).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn refuse_transcript_hash_of_another_length() {
+        for (scheme_hash, len) in [(Hash::Sha256, 48), (Hash::Sha384, 32), (Hash::Sha512, 0)] {
+            assert_eq!(
+                digest(scheme_hash, Side::Client, &vec![0x01; len]),
+                Err(ResponseStatus::PsaErrorInvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn ecdsa_signature_verifies_as_certificate_verify() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        // ring hashes what it signs with SHA-256, the hash of the scheme.
+        let content = rfc_8446_content();
+        let raw = key_pair.sign(&rng, &content).unwrap().as_ref().to_vec();
+
+        let signature = encode_signature(ecdsa(Hash::Sha256), raw).unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, key_pair.public_key().as_ref())
+            .verify(&content, &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn ecdsa_signature_is_der_encoded() {
+        // r has its high bit set and needs a leading zero, s has leading zeros to strip.
+        let mut raw = vec![0x80; 32];
+        raw.extend_from_slice(&[0x00; 31]);
+        raw.push(0x01);
+        let mut der = vec![0x30, 0x26, 0x02, 0x21, 0x00];
+        der.extend_from_slice(&[0x80; 32]);
+        der.extend_from_slice(&[0x02, 0x01, 0x01]);
+        assert_eq!(encode_signature(ecdsa(Hash::Sha256), raw).unwrap(), der);
+    }
+
+    #[test]
+    fn refuse_invalid_ecdsa_signatures() {
+        for raw in [Vec::new(), vec![0x01; 63]] {
+            assert_eq!(
+                encode_signature(ecdsa(Hash::Sha256), raw),
+                Err(ResponseStatus::PsaErrorGenericError)
+            );
+        }
+    }
+
+    #[test]
+    fn rsa_signature_is_kept() {
+        assert_eq!(
+            encode_signature(pss(Hash::Sha256), vec![0x22; 256]).unwrap(),
+            vec![0x22; 256]
+        );
+    }
+}
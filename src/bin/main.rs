@@ -40,10 +40,14 @@
 
 use anyhow::Result;
 use libc::{getuid, uid_t};
-use log::{info, trace};
-use parsec_service::utils::cli::Opts;
+use log::{info, trace, warn};
+use parsec_service::front::stdio;
+use parsec_service::utils::admin;
+use parsec_service::utils::cli::{Command, Opts};
+use parsec_service::utils::logging;
+use parsec_service::utils::service_status::ServiceStatus;
 use parsec_service::utils::{config::ServiceConfig, ServiceBuilder};
-use signal_hook::{consts::SIGHUP, consts::SIGINT, consts::SIGTERM, flag};
+use signal_hook::{consts::SIGHUP, consts::SIGINT, consts::SIGTERM, consts::SIGUSR1, flag};
 use std::io::{Error, ErrorKind};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -65,6 +69,9 @@ fn main() -> Result<()> {
     let _ = flag::register(SIGTERM, kill_signal.clone())?;
     let _ = flag::register(SIGINT, kill_signal.clone())?;
     let _ = flag::register(SIGHUP, reload_signal.clone())?;
+    // Register a boolean set to true when the SIGUSR1 signal is received.
+    let status_signal = Arc::new(AtomicBool::new(false));
+    let _ = flag::register(SIGUSR1, status_signal.clone())?;
 
     let mut config_file = ::std::fs::read_to_string(opts.config.clone()).map_err(|e| {
         Error::new(
@@ -79,6 +86,10 @@ fn main() -> Result<()> {
         )
     })?;
 
+    if let Some(Command::Admin(admin_opts)) = &opts.command {
+        return admin::run(admin_opts, &config);
+    }
+
     // Guard against running as root. This check can be overridden by changing `allow_root` inside
     // the config file.
     let allow_root = config.core_settings.allow_root.unwrap_or(false);
@@ -92,17 +103,51 @@ fn main() -> Result<()> {
         .into());
     }
 
-    log_setup(&config);
+    logging::init(&config.core_settings)?;
+
+    if let Some(Command::MigrateKeyInfo(migrate_opts)) = &opts.command {
+        let migrated = ServiceBuilder::migrate_key_info(
+            &config,
+            &migrate_opts.from,
+            &migrate_opts.to,
+            migrate_opts.remove_source,
+        )?;
+        println!(
+            "Migrated {} mappings from {} to {}.",
+            migrated, migrate_opts.from, migrate_opts.to
+        );
+        return Ok(());
+    }
+
+    if opts.check_config {
+        let report = ServiceBuilder::check_config(&config);
+        println!("{}", report);
+        if !report.is_ok() {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid configuration").into());
+        }
+        return Ok(());
+    }
+
+    if opts.stdio {
+        // Logs go to the standard error, or to the configured sink, never to the standard output.
+        let front_end_handler = ServiceBuilder::build_service(&config)?;
+        let connection = stdio::connection(Duration::from_millis(config.listener.timeout))?;
+        front_end_handler.handle_request(connection);
+        return Ok(());
+    }
 
     info!("Parsec started. Configuring the service...");
+    ServiceStatus::mark_started();
+    ServiceStatus::set_config(&config_file);
 
-    let front_end_handler = ServiceBuilder::build_service(&config)?;
+    let front_end_handler =
+        ServiceBuilder::build_measured_service(&config, config_file.as_bytes())?;
     // Multiple threads can not just have a reference of the front end handler because they could
     // outlive the run function. It is needed to give them all ownership of the front end handler
     // through an Arc.
     let mut front_end_handler = Arc::from(front_end_handler);
     let mut listener = ServiceBuilder::start_listener(config.listener)?;
-    let mut threadpool = ServiceBuilder::build_threadpool(config.core_settings.thread_pool_size);
+    let mut executor = ServiceBuilder::build_executor(&config.core_settings)?;
 
     // Notify systemd that the daemon is ready, the start command will block until this point.
     let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
@@ -114,14 +159,14 @@ fn main() -> Result<()> {
             let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Reloading]);
             info!("SIGHUP signal received. Reloading the configuration...");
 
-            threadpool.join();
+            executor.join();
 
             // Explicitely call drop now because otherwise Rust will drop these variables only
             // after they have been overwritten, in which case some values/libraries might be
             // initialized twice.
             drop(front_end_handler);
             drop(listener);
-            drop(threadpool);
+            drop(executor);
 
             config_file = ::std::fs::read_to_string(opts.config.clone()).map_err(|e| {
                 Error::new(
@@ -135,17 +180,44 @@ fn main() -> Result<()> {
                     format!("Failed to parse service configuration ({})", e),
                 )
             })?;
+            if let Some(pcr) = config.core_settings.configuration_pcr {
+                warn!(
+                    "The reloaded configuration is not measured into PCR {}, which still holds the measurement of the configuration the service started with.",
+                    pcr
+                );
+            }
             front_end_handler = Arc::from(ServiceBuilder::build_service(&config)?);
+            ServiceStatus::set_config(&config_file);
             listener = ServiceBuilder::start_listener(config.listener)?;
-            threadpool = ServiceBuilder::build_threadpool(config.core_settings.thread_pool_size);
+            executor = ServiceBuilder::build_executor(&config.core_settings)?;
 
             let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
             info!("Parsec configuration reloaded.");
         }
 
-        if let Some(connection) = listener.accept() {
+        if status_signal.swap(false, Ordering::Relaxed) {
+            info!(
+                "SIGUSR1 signal received. Status of the service:\n{}",
+                ServiceStatus::report(&executor)
+            );
+        }
+
+        // Accepting new connections is delayed while in-flight requests hold all the memory allowed
+        // or while the maximum number of connections are handled or waiting for a thread.
+        let connections_exhausted = config
+            .core_settings
+            .max_connections
+            .map_or(false, |max_connections| {
+                executor.active_count() + executor.queued_count() >= max_connections
+            });
+        let connection = if front_end_handler.is_memory_exhausted() || connections_exhausted {
+            None
+        } else {
+            listener.accept()
+        };
+        if let Some(connection) = connection {
             let front_end_handler = front_end_handler.clone();
-            threadpool.execute(move || {
+            executor.execute(move || {
                 front_end_handler.handle_request(connection);
                 trace!("handle_request egress");
             });
@@ -161,22 +233,8 @@ fn main() -> Result<()> {
 
     let _ = sd_notify::notify(true, &[sd_notify::NotifyState::Stopping]);
     info!("SIGTERM or SIGINT signal received. Shutting down Parsec, waiting for all threads to finish...");
-    threadpool.join();
+    executor.join();
     info!("Parsec is now terminated.");
 
     Ok(())
 }
-
-fn log_setup(config: &ServiceConfig) {
-    let mut env_log_builder = env_logger::builder();
-
-    if let Some(level) = config.core_settings.log_level {
-        let _ = env_log_builder.filter_level(level);
-    }
-    if let Some(true) = config.core_settings.log_timestamp {
-        let _ = env_log_builder.format_timestamp_millis();
-    } else {
-        let _ = env_log_builder.format_timestamp(None);
-    }
-    env_log_builder.init();
-}
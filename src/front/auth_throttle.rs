@@ -0,0 +1,276 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Throttling of the authentication failures
+//!
+//! Failed authentications are counted per peer: the UID of the client process for Unix Domain
+//! Socket and D-Bus connections, with its container and security context, if any, so that the
+//! containers and confined applications sharing a UID do not block each other. The container is
+//! the one of the cgroups created by the container runtimes, see [`cgroup`], which the process can
+//! not leave to reset its count. Connections without metadata can not be told apart: they are not
+//! throttled, as one of them failing would otherwise block all the others. After a few
+//! consecutive failures, the requests of the peer are rejected without being authenticated for a
+//! delay doubling with every new failure, up to a maximum. This makes guessing the authentication
+//! values, for example application names with the direct authenticator, or tokens, impractical.
+//! A successful authentication resets the count of the peer.
+//!
+//! Every failure and every rejected request is logged, with the peer and the authenticator used.
+use super::listener::ConnectionMetadata;
+use crate::utils::cgroup;
+use crate::utils::config::AuthThrottleConfig;
+use log::{info, warn};
+use parsec_interface::requests::AuthType;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_FREE_ATTEMPTS: u32 = 5;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Maximum number of peers tracked, the ones which failed last are kept
+const MAX_PEERS: usize = 4096;
+
+/// Origin of a connection, as far as the service can tell
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Peer {
+    uid: u32,
+    container: Option<String>,
+    security_context: Option<String>,
+}
+
+impl Peer {
+    /// Peer of a connection, `None` if it can not be told apart from the other connections
+    pub fn from_metadata(metadata: Option<&ConnectionMetadata>) -> Option<Self> {
+        metadata.map(
+            |ConnectionMetadata::UnixPeerCredentials {
+                 uid,
+                 pid,
+                 security_context,
+                 ..
+             }| Peer {
+                uid: *uid,
+                // Processes which already exited are counted with the others of their UID.
+                container: pid.and_then(|pid| cgroup::container_id(pid).ok().flatten()),
+                security_context: security_context.clone(),
+            },
+        )
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UID {}", self.uid)?;
+        if let Some(container) = &self.container {
+            write!(f, " in container {}", container)?;
+        }
+        if let Some(security_context) = &self.security_context {
+            write!(f, " with security context {}", security_context)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// Consecutive authentication failures of the peers
+#[derive(Debug)]
+pub struct AuthThrottle {
+    free_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    failures: Mutex<HashMap<Peer, Failures>>,
+}
+
+impl AuthThrottle {
+    /// Check that the peer is allowed to authenticate now.
+    pub fn check(&self, peer: &Peer, auth_type: AuthType) -> bool {
+        let failures = self
+            .failures
+            .lock()
+            .expect("Authentication throttle lock poisoned");
+        match failures
+            .get(peer)
+            .and_then(|failures| failures.blocked_until)
+        {
+            Some(blocked_until) if blocked_until > Instant::now() => {
+                warn!(
+                    "Rejected a request of {} using {}: too many authentication failures.",
+                    peer, auth_type
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Record a failed authentication, returning for how long the peer is now blocked.
+    pub fn record_failure(&self, peer: &Peer, auth_type: AuthType) -> Option<Duration> {
+        let mut failures = self
+            .failures
+            .lock()
+            .expect("Authentication throttle lock poisoned");
+        let now = Instant::now();
+        if failures.len() >= MAX_PEERS && !failures.contains_key(peer) {
+            if let Some(oldest) = failures
+                .iter()
+                .min_by_key(|(_, failures)| failures.last)
+                .map(|(peer, _)| peer.clone())
+            {
+                let _ = failures.remove(&oldest);
+            }
+        }
+        let entry = failures.entry(peer.clone()).or_insert(Failures {
+            count: 0,
+            last: now,
+            blocked_until: None,
+        });
+        // The count is reset once the peer has not failed for the maximum delay.
+        if now.duration_since(entry.last) > self.max_backoff {
+            entry.count = 0;
+        }
+        entry.count = entry.count.saturating_add(1);
+        entry.last = now;
+        let delay = self.delay(entry.count);
+        entry.blocked_until = delay.map(|delay| now + delay);
+        match delay {
+            Some(delay) => warn!(
+                "Authentication of {} using {} failed {} times in a row, blocking it for {:?}.",
+                peer, auth_type, entry.count, delay
+            ),
+            None => warn!("Authentication of {} using {} failed.", peer, auth_type),
+        }
+        delay
+    }
+
+    /// Record a successful authentication.
+    pub fn record_success(&self, peer: &Peer) {
+        let mut failures = self
+            .failures
+            .lock()
+            .expect("Authentication throttle lock poisoned");
+        if let Some(previous) = failures.remove(peer) {
+            info!(
+                "Authentication of {} succeeded after {} failure(s).",
+                peer, previous.count
+            );
+        }
+    }
+
+    fn delay(&self, count: u32) -> Option<Duration> {
+        let excess = count.checked_sub(self.free_attempts)?;
+        let factor = 1u32.checked_shl(excess).unwrap_or(u32::MAX);
+        Some(
+            self.backoff
+                .checked_mul(factor)
+                .map_or(self.max_backoff, |delay| delay.min(self.max_backoff)),
+        )
+    }
+}
+
+impl From<&AuthThrottleConfig> for AuthThrottle {
+    fn from(config: &AuthThrottleConfig) -> Self {
+        AuthThrottle {
+            free_attempts: config.free_attempts.unwrap_or(DEFAULT_FREE_ATTEMPTS),
+            backoff: config
+                .backoff
+                .map_or(DEFAULT_BACKOFF, Duration::from_millis),
+            max_backoff: config
+                .max_backoff
+                .map_or(DEFAULT_MAX_BACKOFF, Duration::from_secs),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn throttle() -> AuthThrottle {
+        AuthThrottle::from(&AuthThrottleConfig {
+            free_attempts: Some(2),
+            backoff: Some(1000),
+            max_backoff: Some(3),
+        })
+    }
+
+    fn peer(uid: u32, container: Option<&str>, security_context: Option<&str>) -> Peer {
+        Peer {
+            uid,
+            container: container.map(String::from),
+            security_context: security_context.map(String::from),
+        }
+    }
+
+    #[test]
+    fn failures_back_off() {
+        let throttle = throttle();
+        let peer = peer(1000, None, None);
+
+        assert_eq!(throttle.record_failure(&peer, AuthType::Direct), None);
+        assert!(throttle.check(&peer, AuthType::Direct));
+        assert_eq!(
+            throttle.record_failure(&peer, AuthType::Direct),
+            Some(Duration::from_secs(1))
+        );
+        assert!(!throttle.check(&peer, AuthType::Direct));
+        assert_eq!(
+            throttle.record_failure(&peer, AuthType::Direct),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            throttle.record_failure(&peer, AuthType::Direct),
+            Some(Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn successes_reset_the_failures() {
+        let throttle = throttle();
+        let peer = peer(1000, None, None);
+        let _ = throttle.record_failure(&peer, AuthType::Direct);
+        let _ = throttle.record_failure(&peer, AuthType::Direct);
+        assert!(!throttle.check(&peer, AuthType::Direct));
+
+        throttle.record_success(&peer);
+        assert!(throttle.check(&peer, AuthType::Direct));
+        assert_eq!(throttle.record_failure(&peer, AuthType::Direct), None);
+    }
+
+    #[test]
+    fn peers_sharing_a_uid_are_throttled_apart() {
+        let throttle = throttle();
+        let blocked = peer(0, Some("a"), None);
+        let _ = throttle.record_failure(&blocked, AuthType::Direct);
+        let _ = throttle.record_failure(&blocked, AuthType::Direct);
+        assert!(!throttle.check(&blocked, AuthType::Direct));
+
+        assert!(throttle.check(&peer(1, Some("a"), None), AuthType::Direct));
+        assert!(throttle.check(&peer(0, Some("b"), None), AuthType::Direct));
+        assert!(throttle.check(&peer(0, None, None), AuthType::Direct));
+        assert!(throttle.check(&peer(0, Some("a"), Some("confined")), AuthType::Direct));
+    }
+
+    #[test]
+    fn connections_without_metadata_are_not_throttled() {
+        assert_eq!(Peer::from_metadata(None), None);
+    }
+
+    #[test]
+    fn peers_of_the_connection_metadata() {
+        let metadata = ConnectionMetadata::UnixPeerCredentials {
+            uid: 1000,
+            gid: 1000,
+            pid: None,
+            security_context: Some(String::from("confined")),
+        };
+        let peer = Peer::from_metadata(Some(&metadata)).unwrap();
+        assert_eq!(peer, self::peer(1000, None, Some("confined")));
+        assert_eq!(peer.to_string(), "UID 1000 with security context confined");
+    }
+}
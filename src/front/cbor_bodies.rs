@@ -0,0 +1,687 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Bodies encoded in CBOR
+//!
+//! Constrained clients already using CBOR and COSE can encode the bodies of their requests and
+//! of the responses in CBOR instead of protobuf, by setting both the content type and the accept
+//! type of the request header to `BODY_TYPE_CBOR`. The bodies are maps of integer labels, in
+//! which algorithms and keys are given as in COSE:
+//!
+//! * `Ping`: empty request. The response is `{1: major version, 2: minor version}`.
+//! * `PsaGenerateKey`: `{1: key name, 2: COSE_Key template, 3: size in bits}`, the size being only
+//!   needed for RSA keys. The template gives the type of the key (label 1), its curve for EC2 keys
+//!   (-1), the signature algorithm it permits (3) and the operations it permits (4), signing and
+//!   verifying if absent. The response is empty.
+//! * `PsaDestroyKey`: `{1: key name}`. The response is empty.
+//! * `PsaExportPublicKey`: `{1: key name}`. The response is the COSE_Key of the public key.
+//! * `PsaSignHash`: `{1: key name, 2: algorithm, 3: hash}`. The response is the signature, a byte
+//!   string.
+//! * `PsaVerifyHash`: `{1: key name, 2: algorithm, 3: hash, 4: signature}`. The response is empty.
+//! * `PsaGenerateRandom`: `{1: size}`. The response is the random bytes, a byte string.
+//!
+//! Other operations are answered with `ContentTypeNotSupported`. The front end converts the bodies
+//! from and to protobuf, so that the rest of the service handles the requests like any other.
+//! The responses with a CBOR body are not compressed.
+use super::wire_protocol::{BODY_TYPE_CBOR, CONTENT_TYPE_OFFSET};
+use crate::utils::cbor::{self, Value};
+use crate::utils::cose;
+use crate::utils::key_suffixes;
+use log::error;
+use parsec_interface::operations::psa_algorithm::AsymmetricSignature;
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, Lifetime, Policy, Type, UsageFlags,
+};
+use parsec_interface::operations::{
+    ping, psa_destroy_key, psa_export_public_key, psa_generate_key, psa_generate_random,
+    psa_sign_hash, psa_verify_hash,
+};
+use parsec_interface::operations::{Convert, NativeOperation, NativeResult};
+use parsec_interface::operations_protobuf::ProtobufConverter;
+use parsec_interface::requests::request::RequestBody;
+use parsec_interface::requests::response::ResponseBody;
+use parsec_interface::requests::{Opcode, Response, ResponseStatus, Result};
+use std::convert::TryFrom;
+use std::io::Write;
+use zeroize::Zeroizing;
+
+/// Offset of the body length in the encoded header
+const BODY_LEN_OFFSET: usize = 22;
+/// Length of the encoded header
+const HEADER_LEN: usize = 36;
+
+const KEY_NAME: i64 = 1;
+const ALGORITHM: i64 = 2;
+const HASH: i64 = 3;
+const SIGNATURE: i64 = 4;
+const KEY_TEMPLATE: i64 = 2;
+const KEY_BITS: i64 = 3;
+const RANDOM_SIZE: i64 = 1;
+const VERSION_MAJ: i64 = 1;
+const VERSION_MIN: i64 = 2;
+
+/// Protobuf body of the request of the given CBOR body
+pub(crate) fn request_body(body: &[u8], opcode: Opcode) -> Result<RequestBody> {
+    let body = if body.is_empty() {
+        Value::Map(Vec::new())
+    } else {
+        cbor::decode(body)?
+    };
+    let operation = match opcode {
+        Opcode::Ping => NativeOperation::Ping(ping::Operation),
+        Opcode::PsaGenerateKey => NativeOperation::PsaGenerateKey(psa_generate_key::Operation {
+            key_name: text(&body, KEY_NAME)?,
+            attributes: key_attributes(&body)?,
+        }),
+        Opcode::PsaDestroyKey => NativeOperation::PsaDestroyKey(psa_destroy_key::Operation {
+            key_name: text(&body, KEY_NAME)?,
+        }),
+        // The public key is exported as a COSE_Key.
+        Opcode::PsaExportPublicKey => {
+            NativeOperation::PsaExportPublicKey(psa_export_public_key::Operation {
+                key_name: text(&body, KEY_NAME)? + key_suffixes::COSE,
+            })
+        }
+        Opcode::PsaSignHash => NativeOperation::PsaSignHash(psa_sign_hash::Operation {
+            key_name: text(&body, KEY_NAME)?,
+            alg: signature_algorithm(member(&body, ALGORITHM)?)?,
+            hash: bytes(&body, HASH)?,
+        }),
+        Opcode::PsaVerifyHash => NativeOperation::PsaVerifyHash(psa_verify_hash::Operation {
+            key_name: text(&body, KEY_NAME)?,
+            alg: signature_algorithm(member(&body, ALGORITHM)?)?,
+            hash: bytes(&body, HASH)?,
+            signature: bytes(&body, SIGNATURE)?,
+        }),
+        Opcode::PsaGenerateRandom => {
+            NativeOperation::PsaGenerateRandom(psa_generate_random::Operation {
+                size: usize::try_from(integer(member(&body, RANDOM_SIZE)?)?).map_err(|_| {
+                    error!("Invalid size of random bytes in the CBOR body.");
+                    ResponseStatus::DeserializingBodyFailed
+                })?,
+            })
+        }
+        _ => {
+            error!("{:?} requests can not have a CBOR body.", opcode);
+            return Err(ResponseStatus::ContentTypeNotSupported);
+        }
+    };
+    ProtobufConverter {}.operation_to_body(operation)
+}
+
+/// Write the response to the stream, with its protobuf body converted to CBOR
+pub(crate) fn write_response<W: Write>(response: Response, stream: &mut W) -> Result<()> {
+    let mut header = response.header;
+    let body = if header.status == ResponseStatus::Success {
+        response_body(response.body, header.opcode).unwrap_or_else(|status| {
+            header.status = status;
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+
+    let empty_body = Response::from_status(ResponseStatus::Success).body;
+    let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+    Response {
+        header,
+        body: empty_body,
+    }
+    .write_to_stream(&mut message)?;
+    message[CONTENT_TYPE_OFFSET] = BODY_TYPE_CBOR;
+    message[BODY_LEN_OFFSET..BODY_LEN_OFFSET + 4]
+        .copy_from_slice(&u32::try_from(body.len())?.to_le_bytes());
+    message.extend_from_slice(&body);
+    stream.write_all(&message)?;
+    Ok(())
+}
+
+fn response_body(body: ResponseBody, opcode: Opcode) -> Result<Vec<u8>> {
+    let result = ProtobufConverter {}.body_to_result(body, opcode)?;
+    let value = match result {
+        NativeResult::Ping(result) => Value::labelled(vec![
+            (
+                VERSION_MAJ,
+                Value::Integer(result.wire_protocol_version_maj.into()),
+            ),
+            (
+                VERSION_MIN,
+                Value::Integer(result.wire_protocol_version_min.into()),
+            ),
+        ]),
+        NativeResult::PsaGenerateKey(_)
+        | NativeResult::PsaDestroyKey(_)
+        | NativeResult::PsaVerifyHash(_) => return Ok(Vec::new()),
+        // The key is already a COSE_Key.
+        NativeResult::PsaExportPublicKey(result) => return Ok(result.data.to_vec()),
+        NativeResult::PsaSignHash(result) => Value::bytes(&result.signature),
+        NativeResult::PsaGenerateRandom(result) => Value::bytes(&result.random_bytes),
+        _ => {
+            error!("{:?} responses can not have a CBOR body.", opcode);
+            return Err(ResponseStatus::AcceptTypeNotSupported);
+        }
+    };
+    Ok(cbor::encode(&value))
+}
+
+fn member(value: &Value, label: i64) -> Result<&Value> {
+    value.get(label).ok_or_else(|| {
+        error!("The label {} is missing from the CBOR body.", label);
+        ResponseStatus::DeserializingBodyFailed
+    })
+}
+
+fn integer(value: &Value) -> Result<i64> {
+    match value {
+        Value::Integer(integer) => Ok(*integer),
+        _ => {
+            error!("Expected an integer in the CBOR body.");
+            Err(ResponseStatus::DeserializingBodyFailed)
+        }
+    }
+}
+
+fn text(value: &Value, label: i64) -> Result<String> {
+    match member(value, label)? {
+        Value::Text(text) => Ok(text.clone()),
+        _ => {
+            error!("Expected a text string in the CBOR body.");
+            Err(ResponseStatus::DeserializingBodyFailed)
+        }
+    }
+}
+
+fn bytes(value: &Value, label: i64) -> Result<Zeroizing<Vec<u8>>> {
+    match member(value, label)? {
+        Value::Bytes(bytes) => Ok(bytes.clone()),
+        _ => {
+            error!("Expected a byte string in the CBOR body.");
+            Err(ResponseStatus::DeserializingBodyFailed)
+        }
+    }
+}
+
+fn signature_algorithm(value: &Value) -> Result<AsymmetricSignature> {
+    let alg = integer(value)?;
+    cose::signature_algorithm(alg).ok_or_else(|| {
+        error!(
+            "The COSE algorithm {} is not a supported signature algorithm.",
+            alg
+        );
+        ResponseStatus::PsaErrorNotSupported
+    })
+}
+
+/// Attributes of the key to generate from its COSE_Key template
+fn key_attributes(body: &Value) -> Result<Attributes> {
+    let template = member(body, KEY_TEMPLATE)?;
+    let alg = signature_algorithm(member(template, cose::KEY_ALGORITHM)?)?;
+    let (key_type, bits) = match integer(member(template, cose::KEY_TYPE)?)? {
+        cose::KEY_TYPE_EC2 => {
+            let curve = integer(member(template, cose::EC2_CURVE)?)?;
+            let (curve_family, bits) = cose::curve(curve).ok_or_else(|| {
+                error!("The COSE curve {} is not supported.", curve);
+                ResponseStatus::PsaErrorNotSupported
+            })?;
+            (Type::EccKeyPair { curve_family }, bits)
+        }
+        cose::KEY_TYPE_RSA => {
+            let bits = usize::try_from(integer(member(body, KEY_BITS)?)?).map_err(|_| {
+                error!("Invalid key size in the CBOR body.");
+                ResponseStatus::DeserializingBodyFailed
+            })?;
+            (Type::RsaKeyPair, bits)
+        }
+        kty => {
+            error!("The COSE key type {} is not supported.", kty);
+            return Err(ResponseStatus::PsaErrorNotSupported);
+        }
+    };
+
+    let mut usage_flags = UsageFlags::default();
+    let key_ops = match template.get(cose::KEY_OPS) {
+        None => vec![cose::KEY_OP_SIGN, cose::KEY_OP_VERIFY],
+        Some(Value::Array(key_ops)) => key_ops.iter().map(integer).collect::<Result<_>>()?,
+        Some(_) => {
+            error!("The operations of the COSE_Key template are not an array.");
+            return Err(ResponseStatus::DeserializingBodyFailed);
+        }
+    };
+    for key_op in key_ops {
+        match key_op {
+            cose::KEY_OP_SIGN => {
+                let _ = usage_flags.set_sign_hash().set_sign_message();
+            }
+            cose::KEY_OP_VERIFY => {
+                let _ = usage_flags.set_verify_hash().set_verify_message();
+            }
+            _ => {
+                error!("The COSE key operation {} is not supported.", key_op);
+                return Err(ResponseStatus::PsaErrorNotSupported);
+            }
+        }
+    }
+    Ok(Attributes {
+        lifetime: Lifetime::Persistent,
+        key_type,
+        bits,
+        policy: Policy {
+            usage_flags,
+            permitted_algorithms: alg.into(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::list_keys;
+    use parsec_interface::operations::psa_algorithm::{Hash, SignHash};
+    use parsec_interface::operations::psa_key_attributes::EccFamily;
+
+    /// Offset of the opcode in the encoded header, followed by the status
+    const OPCODE_OFFSET: usize = 28;
+    const STATUS_OFFSET: usize = 32;
+
+    fn text(text: &str) -> Value {
+        Value::Text(String::from(text))
+    }
+
+    fn decode(body: Value, opcode: Opcode) -> Result<NativeOperation> {
+        ProtobufConverter {}.body_to_operation(request_body(&cbor::encode(&body), opcode)?, opcode)
+    }
+
+    fn generate_key(template: Vec<(i64, Value)>, bits: Option<i64>) -> Result<Attributes> {
+        let mut body = vec![
+            (KEY_NAME, text("signing-key")),
+            (KEY_TEMPLATE, Value::labelled(template)),
+        ];
+        if let Some(bits) = bits {
+            body.push((KEY_BITS, Value::Integer(bits)));
+        }
+        match decode(Value::labelled(body), Opcode::PsaGenerateKey)? {
+            NativeOperation::PsaGenerateKey(operation) => {
+                assert_eq!(operation.key_name, "signing-key");
+                Ok(operation.attributes)
+            }
+            _ => panic!("Unexpected operation"),
+        }
+    }
+
+    fn ec2_template(curve: i64, alg: i64) -> Vec<(i64, Value)> {
+        vec![
+            (cose::KEY_TYPE, Value::Integer(cose::KEY_TYPE_EC2)),
+            (cose::KEY_ALGORITHM, Value::Integer(alg)),
+            (cose::EC2_CURVE, Value::Integer(curve)),
+        ]
+    }
+
+    fn signing(alg: Value, hash: Value) -> Value {
+        Value::labelled(vec![
+            (KEY_NAME, text("signing-key")),
+            (ALGORITHM, alg),
+            (HASH, hash),
+        ])
+    }
+
+    /// Status and body of the CBOR response of the result
+    fn written(response: Response) -> (u16, Vec<u8>) {
+        let opcode = response.header.opcode;
+        let mut message = Vec::new();
+        write_response(response, &mut message).unwrap();
+        assert_eq!(message[CONTENT_TYPE_OFFSET], BODY_TYPE_CBOR);
+        assert_eq!(
+            message[OPCODE_OFFSET..OPCODE_OFFSET + 4],
+            (opcode as u32).to_le_bytes()
+        );
+        let body_len = u32::from_le_bytes([
+            message[BODY_LEN_OFFSET],
+            message[BODY_LEN_OFFSET + 1],
+            message[BODY_LEN_OFFSET + 2],
+            message[BODY_LEN_OFFSET + 3],
+        ]);
+        assert_eq!(message.len(), HEADER_LEN + body_len as usize);
+        let status = u16::from_le_bytes([message[STATUS_OFFSET], message[STATUS_OFFSET + 1]]);
+        (status, message.split_off(HEADER_LEN))
+    }
+
+    fn success(opcode: Opcode, result: NativeResult) -> (u16, Vec<u8>) {
+        let mut response = Response::from_status(ResponseStatus::Success);
+        response.header.opcode = opcode;
+        response.body = ProtobufConverter {}.result_to_body(result).unwrap();
+        written(response)
+    }
+
+    const SUCCESS: u16 = ResponseStatus::Success as u16;
+
+    #[test]
+    fn ping_body_may_be_empty() {
+        for body in [&[][..], &[0xa0]] {
+            let request = request_body(body, Opcode::Ping).unwrap();
+            assert!(matches!(
+                ProtobufConverter {}.body_to_operation(request, Opcode::Ping),
+                Ok(NativeOperation::Ping(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn generate_key_with_the_template_operations() {
+        let mut template = ec2_template(1, -7);
+        template.push((cose::KEY_OPS, Value::Array(vec![Value::Integer(1)])));
+        let attributes = generate_key(template, None).unwrap();
+        assert_eq!(attributes.lifetime, Lifetime::Persistent);
+        assert_eq!(
+            attributes.key_type,
+            Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1
+            }
+        );
+        assert_eq!(attributes.bits, 256);
+        assert!(attributes.policy.usage_flags.sign_hash());
+        assert!(attributes.policy.usage_flags.sign_message());
+        assert!(!attributes.policy.usage_flags.verify_hash());
+        assert!(!attributes.policy.usage_flags.verify_message());
+        assert_eq!(
+            attributes.policy.permitted_algorithms,
+            AsymmetricSignature::Ecdsa {
+                hash_alg: SignHash::Specific(Hash::Sha256)
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn generate_key_permits_signing_and_verifying_by_default() {
+        let attributes = generate_key(ec2_template(8, -35), None).unwrap();
+        assert_eq!(
+            attributes.key_type,
+            Type::EccKeyPair {
+                curve_family: EccFamily::SecpK1
+            }
+        );
+        assert_eq!(attributes.bits, 256);
+        assert!(attributes.policy.usage_flags.sign_hash());
+        assert!(attributes.policy.usage_flags.verify_hash());
+    }
+
+    #[test]
+    fn generate_rsa_key_of_the_given_size() {
+        let template = vec![
+            (cose::KEY_TYPE, Value::Integer(cose::KEY_TYPE_RSA)),
+            (cose::KEY_ALGORITHM, Value::Integer(-37)),
+        ];
+        let attributes = generate_key(template.clone(), Some(2048)).unwrap();
+        assert_eq!(attributes.key_type, Type::RsaKeyPair);
+        assert_eq!(attributes.bits, 2048);
+        assert_eq!(
+            attributes.policy.permitted_algorithms,
+            AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Specific(Hash::Sha256)
+            }
+            .into()
+        );
+
+        for bits in [None, Some(-1)] {
+            assert_eq!(
+                generate_key(template.clone(), bits).unwrap_err(),
+                ResponseStatus::DeserializingBodyFailed
+            );
+        }
+    }
+
+    #[test]
+    fn refuse_unsupported_templates() {
+        // Symmetric key type, X25519 curve and EdDSA algorithm
+        let mut symmetric = ec2_template(1, -7);
+        symmetric[0].1 = Value::Integer(4);
+        let mut encrypting = ec2_template(1, -7);
+        encrypting.push((cose::KEY_OPS, Value::Array(vec![Value::Integer(3)])));
+        for template in [
+            symmetric,
+            ec2_template(4, -7),
+            ec2_template(1, -8),
+            encrypting,
+        ] {
+            assert_eq!(
+                generate_key(template, None).unwrap_err(),
+                ResponseStatus::PsaErrorNotSupported
+            );
+        }
+
+        let mut template = ec2_template(1, -7);
+        template.push((cose::KEY_OPS, Value::Integer(1)));
+        assert_eq!(
+            generate_key(template, None).unwrap_err(),
+            ResponseStatus::DeserializingBodyFailed
+        );
+        assert_eq!(
+            generate_key(ec2_template(1, -7)[1..].to_vec(), None).unwrap_err(),
+            ResponseStatus::DeserializingBodyFailed
+        );
+    }
+
+    #[test]
+    fn destroy_key() {
+        let body = Value::labelled(vec![(KEY_NAME, text("signing-key"))]);
+        match decode(body, Opcode::PsaDestroyKey).unwrap() {
+            NativeOperation::PsaDestroyKey(operation) => {
+                assert_eq!(operation.key_name, "signing-key")
+            }
+            _ => panic!("Unexpected operation"),
+        }
+    }
+
+    #[test]
+    fn export_public_key_as_cose_key() {
+        let body = Value::labelled(vec![(KEY_NAME, text("signing-key"))]);
+        match decode(body, Opcode::PsaExportPublicKey).unwrap() {
+            NativeOperation::PsaExportPublicKey(operation) => {
+                assert_eq!(operation.key_name, "signing-key#cose")
+            }
+            _ => panic!("Unexpected operation"),
+        }
+    }
+
+    #[test]
+    fn sign_hash() {
+        let body = signing(Value::Integer(-257), Value::bytes(&[0xaa; 32]));
+        match decode(body, Opcode::PsaSignHash).unwrap() {
+            NativeOperation::PsaSignHash(operation) => {
+                assert_eq!(operation.key_name, "signing-key");
+                assert_eq!(
+                    operation.alg,
+                    AsymmetricSignature::RsaPkcs1v15Sign {
+                        hash_alg: SignHash::Specific(Hash::Sha256)
+                    }
+                );
+                assert_eq!(*operation.hash, vec![0xaa; 32]);
+            }
+            _ => panic!("Unexpected operation"),
+        }
+    }
+
+    #[test]
+    fn verify_hash() {
+        let mut body = signing(Value::Integer(-7), Value::bytes(&[0xaa; 32]));
+        if let Value::Map(entries) = &mut body {
+            entries.push((Value::Integer(SIGNATURE), Value::bytes(&[0x55; 64])));
+        }
+        match decode(body, Opcode::PsaVerifyHash).unwrap() {
+            NativeOperation::PsaVerifyHash(operation) => {
+                assert_eq!(operation.key_name, "signing-key");
+                assert_eq!(
+                    operation.alg,
+                    AsymmetricSignature::Ecdsa {
+                        hash_alg: SignHash::Specific(Hash::Sha256)
+                    }
+                );
+                assert_eq!(*operation.hash, vec![0xaa; 32]);
+                assert_eq!(*operation.signature, vec![0x55; 64]);
+            }
+            _ => panic!("Unexpected operation"),
+        }
+
+        let body = signing(Value::Integer(-7), Value::bytes(&[0xaa; 32]));
+        assert_eq!(
+            decode(body, Opcode::PsaVerifyHash).unwrap_err(),
+            ResponseStatus::DeserializingBodyFailed
+        );
+    }
+
+    #[test]
+    fn generate_random() {
+        let body = Value::labelled(vec![(RANDOM_SIZE, Value::Integer(32))]);
+        match decode(body, Opcode::PsaGenerateRandom).unwrap() {
+            NativeOperation::PsaGenerateRandom(operation) => assert_eq!(operation.size, 32),
+            _ => panic!("Unexpected operation"),
+        }
+
+        let body = Value::labelled(vec![(RANDOM_SIZE, Value::Integer(-1))]);
+        assert_eq!(
+            decode(body, Opcode::PsaGenerateRandom).unwrap_err(),
+            ResponseStatus::DeserializingBodyFailed
+        );
+    }
+
+    #[test]
+    fn refuse_unsupported_algorithms() {
+        // EdDSA
+        let body = signing(Value::Integer(-8), Value::bytes(&[0xaa; 32]));
+        assert_eq!(
+            decode(body, Opcode::PsaSignHash).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn refuse_malformed_bodies() {
+        assert_eq!(
+            request_body(&[0xff], Opcode::PsaDestroyKey).unwrap_err(),
+            ResponseStatus::DeserializingBodyFailed
+        );
+        let malformed = [
+            // Not a map
+            Value::Array(vec![text("signing-key")]),
+            // Missing hash
+            Value::labelled(vec![
+                (KEY_NAME, text("signing-key")),
+                (ALGORITHM, Value::Integer(-7)),
+            ]),
+            // Members of the wrong types
+            Value::labelled(vec![
+                (KEY_NAME, Value::bytes(b"signing-key")),
+                (ALGORITHM, Value::Integer(-7)),
+                (HASH, Value::bytes(&[0xaa; 32])),
+            ]),
+            signing(text("ES256"), Value::bytes(&[0xaa; 32])),
+            signing(Value::Integer(-7), text("hash")),
+        ];
+        for body in malformed {
+            assert_eq!(
+                decode(body, Opcode::PsaSignHash).unwrap_err(),
+                ResponseStatus::DeserializingBodyFailed
+            );
+        }
+    }
+
+    #[test]
+    fn refuse_other_operations() {
+        for opcode in [
+            Opcode::ListKeys,
+            Opcode::PsaImportKey,
+            Opcode::PsaAeadEncrypt,
+        ] {
+            assert_eq!(
+                decode(Value::Map(Vec::new()), opcode).unwrap_err(),
+                ResponseStatus::ContentTypeNotSupported
+            );
+        }
+    }
+
+    #[test]
+    fn ping_response() {
+        let result = NativeResult::Ping(ping::Result {
+            wire_protocol_version_maj: 1,
+            wire_protocol_version_min: 0,
+        });
+        assert_eq!(
+            success(Opcode::Ping, result),
+            (SUCCESS, vec![0xa2, 0x01, 0x01, 0x02, 0x00])
+        );
+    }
+
+    #[test]
+    fn empty_responses() {
+        let results = [
+            (
+                Opcode::PsaGenerateKey,
+                NativeResult::PsaGenerateKey(psa_generate_key::Result),
+            ),
+            (
+                Opcode::PsaDestroyKey,
+                NativeResult::PsaDestroyKey(psa_destroy_key::Result),
+            ),
+            (
+                Opcode::PsaVerifyHash,
+                NativeResult::PsaVerifyHash(psa_verify_hash::Result),
+            ),
+        ];
+        for (opcode, result) in results {
+            assert_eq!(success(opcode, result), (SUCCESS, Vec::new()));
+        }
+    }
+
+    #[test]
+    fn byte_string_responses() {
+        let result = NativeResult::PsaSignHash(psa_sign_hash::Result {
+            signature: Zeroizing::new(vec![1, 2, 3]),
+        });
+        assert_eq!(
+            success(Opcode::PsaSignHash, result),
+            (SUCCESS, vec![0x43, 0x01, 0x02, 0x03])
+        );
+        let result = NativeResult::PsaGenerateRandom(psa_generate_random::Result {
+            random_bytes: Zeroizing::new(vec![0x42; 24]),
+        });
+        let mut expected = vec![0x58, 0x18];
+        expected.extend_from_slice(&[0x42; 24]);
+        assert_eq!(
+            success(Opcode::PsaGenerateRandom, result),
+            (SUCCESS, expected)
+        );
+    }
+
+    #[test]
+    fn public_key_is_sent_as_is() {
+        let cose_key = cbor::encode(&Value::labelled(vec![(
+            cose::KEY_TYPE,
+            Value::Integer(cose::KEY_TYPE_EC2),
+        )]));
+        let result = NativeResult::PsaExportPublicKey(psa_export_public_key::Result {
+            data: Zeroizing::new(cose_key.clone()),
+        });
+        assert_eq!(
+            success(Opcode::PsaExportPublicKey, result),
+            (SUCCESS, cose_key)
+        );
+    }
+
+    #[test]
+    fn failed_responses_have_no_body() {
+        let mut response = Response::from_status(ResponseStatus::PsaErrorDoesNotExist);
+        response.header.opcode = Opcode::PsaSignHash;
+        assert_eq!(
+            written(response),
+            (ResponseStatus::PsaErrorDoesNotExist as u16, Vec::new())
+        );
+    }
+
+    #[test]
+    fn refuse_other_responses() {
+        let result = NativeResult::ListKeys(list_keys::Result { keys: Vec::new() });
+        assert_eq!(
+            success(Opcode::ListKeys, result),
+            (ResponseStatus::AcceptTypeNotSupported as u16, Vec::new())
+        );
+    }
+}
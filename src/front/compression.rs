@@ -0,0 +1,104 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Compression of large response bodies
+//!
+//! Clients setting the `FLAG_COMPRESSION` bit in the flags of the request header accept a
+//! compressed response body. When compression is enabled in the configuration and the body is at
+//! least as large as the configured threshold, it is compressed in the LZ4 block format,
+//! preceded by its uncompressed length (4 bytes, little endian), and the same bit is set in the
+//! flags of the response header. Bodies that would not get smaller are sent as they are, without
+//! the flag.
+//!
+//! `parsec-interface` writes the response headers with empty flags, so the flags and the body
+//! length of a compressed response are set in the encoded header.
+use super::wire_protocol::FLAG_COMPRESSION;
+use parsec_interface::requests::{Response, ResponseStatus, Result};
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// Offset of the flags in the encoded header
+const FLAGS_OFFSET: usize = 8;
+/// Offset of the body length in the encoded header
+const BODY_LEN_OFFSET: usize = 22;
+/// Length of the encoded header
+const HEADER_LEN: usize = 36;
+
+/// Write the response to the stream, with its body compressed if it is at least `threshold` bytes
+/// long and compression makes it smaller.
+pub(crate) fn write_response<W: Write>(
+    response: Response,
+    stream: &mut W,
+    threshold: usize,
+) -> Result<()> {
+    if response.body.len() < threshold {
+        return response.write_to_stream(stream);
+    }
+    // The uncompressed length is prepended on 4 bytes.
+    let _ = u32::try_from(response.body.len())?;
+    let body = lz4_flex::block::compress_prepend_size(response.body.bytes());
+    if body.len() >= response.body.len() {
+        return response.write_to_stream(stream);
+    }
+
+    let empty_body = Response::from_status(ResponseStatus::Success).body;
+    let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+    Response {
+        header: response.header,
+        body: empty_body,
+    }
+    .write_to_stream(&mut message)?;
+    message[FLAGS_OFFSET..FLAGS_OFFSET + 2].copy_from_slice(&FLAG_COMPRESSION.to_le_bytes());
+    message[BODY_LEN_OFFSET..BODY_LEN_OFFSET + 4]
+        .copy_from_slice(&u32::try_from(body.len())?.to_le_bytes());
+    message.extend_from_slice(&body);
+    stream.write_all(&message)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn response_with_body(body: &[u8]) -> Response {
+        let mut message = Vec::new();
+        Response::from_status(ResponseStatus::Success)
+            .write_to_stream(&mut message)
+            .unwrap();
+        message[BODY_LEN_OFFSET..BODY_LEN_OFFSET + 4]
+            .copy_from_slice(&(body.len() as u32).to_le_bytes());
+        message.extend_from_slice(body);
+        Response::read_from_stream(&mut message.as_slice(), body.len()).unwrap()
+    }
+
+    #[test]
+    fn large_bodies_are_compressed() {
+        let body = [0x42; 1000];
+        let mut message = Vec::new();
+        write_response(response_with_body(&body), &mut message, 512).unwrap();
+        assert_eq!(
+            message[FLAGS_OFFSET..FLAGS_OFFSET + 2],
+            FLAG_COMPRESSION.to_le_bytes()
+        );
+        let body_len = u32::from_le_bytes(
+            message[BODY_LEN_OFFSET..BODY_LEN_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(message.len(), HEADER_LEN + body_len as usize);
+        assert_eq!(message[HEADER_LEN..HEADER_LEN + 4], 1000_u32.to_le_bytes());
+        assert_eq!(
+            lz4_flex::block::decompress_size_prepended(&message[HEADER_LEN..]).unwrap(),
+            body
+        );
+
+        // Below the threshold or without gain, the body is sent as it is.
+        let mut message = Vec::new();
+        write_response(response_with_body(&body), &mut message, 1001).unwrap();
+        assert_eq!(message[FLAGS_OFFSET..FLAGS_OFFSET + 2], [0, 0]);
+        assert_eq!(message.len(), HEADER_LEN + 1000);
+        let mut message = Vec::new();
+        write_response(response_with_body(b"incompressible"), &mut message, 1).unwrap();
+        assert_eq!(message[FLAGS_OFFSET..FLAGS_OFFSET + 2], [0, 0]);
+    }
+}
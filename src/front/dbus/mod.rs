@@ -0,0 +1,1476 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! D-Bus interface
+//!
+//! Desktop components can use Parsec through D-Bus instead of its socket: the service owns the
+//! `org.parallaxsecond.Parsec` name on the system bus and offers a subset of the operations as
+//! methods of the `org.parallaxsecond.Parsec` interface of the `/org/parallaxsecond/Parsec` object:
+//! * `Ping() -> (y wire_protocol_version_maj, y wire_protocol_version_min)`
+//! * `ListProviders() -> (a(ys) providers)`: the ID and description of each provider
+//! * `ListKeys() -> (a(ys) keys)`: the provider ID and name of each key of the caller
+//! * `ExportPublicKey(y provider, s key_name) -> (ay data)`
+//! * `GenerateRandom(y provider, u size) -> (ay random_bytes)`
+//! * `DestroyKey(y provider, s key_name)`
+//!
+//! Each call is turned into a request to the service, authenticated with the Unix peer
+//! credentials authenticator, which must be configured, as coming from the user of the caller, as
+//! given by the bus along with its security context. The request is then handled like those
+//! received on the socket: its response is turned back into the return of the method, or into an
+//! error named after the response status, for example
+//! `org.parallaxsecond.Parsec.Error.PsaErrorDoesNotExist`. The credentials are only taken from
+//! replies sent by the bus itself. Calls whose credentials the bus does not give within 25 seconds
+//! are refused, as are calls beyond 64 waiting for their credentials.
+//!
+//! The object also emits the `KeyCreated`, `KeyDestroyed` and `KeyAttested` signals, with the
+//! application name, key name and provider ID of the key, and the `AuthenticationFailed` signal,
+//! with the peer which failed to authenticate, for all the requests of the service. As they tell
+//! about the keys of every application, the policy of the bus only lets the members of the
+//! `parsec-events` group receive them.
+//!
+//! The bus only lets the service own its name, clients call it and receive its signals, if its
+//! policy allows it: see the `org.parallaxsecond.Parsec.conf` file of the `systemd-daemon`
+//! directory.
+//!
+//! The connection to the bus and the messages are handled by zbus, through its blocking API: a
+//! thread of the service receives the calls and another one emits the signals.
+use super::listener::{Connection, ConnectionMetadata, Listen};
+use crate::utils::config::{DbusConfig, HookEvent};
+use crate::utils::event_hooks::{self, Event};
+use derivative::Derivative;
+use log::{error, info, warn};
+use parsec_interface::operations::{
+    list_keys, list_providers, ping, psa_destroy_key, psa_export_public_key, psa_generate_random,
+};
+use parsec_interface::operations::{Convert, NativeOperation, NativeResult};
+use parsec_interface::operations_protobuf::ProtobufConverter;
+use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+use parsec_interface::requests::{
+    AuthType, BodyType, Opcode, ProviderId, Request, Response, ResponseStatus,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use zbus::blocking::{Connection as Bus, ConnectionBuilder, MessageIterator};
+use zbus::fdo::{ConnectionCredentials, RequestNameFlags, RequestNameReply};
+use zbus::{AuthMechanism, Message, MessageFlags, MessageType};
+
+/// Name owned by the service on the bus
+const BUS_NAME: &str = "org.parallaxsecond.Parsec";
+/// Object offering the interface
+const OBJECT_PATH: &str = "/org/parallaxsecond/Parsec";
+/// Interface of the methods and signals
+const INTERFACE: &str = "org.parallaxsecond.Parsec";
+/// Prefix of the names of the errors returned for the response statuses
+const ERROR_PREFIX: &str = "org.parallaxsecond.Parsec.Error.";
+/// Address of the system bus if not configured nor given by `DBUS_SYSTEM_BUS_ADDRESS`
+const DEFAULT_BUS_ADDRESS: &str = "unix:path=/run/dbus/system_bus_socket";
+const BUS: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER: &str = "org.freedesktop.DBus.Peer";
+/// Sender of the messages of the service, which the bus sets itself
+const NO_SENDER: Option<&str> = None;
+/// Calls waiting for a thread of the service
+const MAX_QUEUED_CALLS: usize = 64;
+/// Calls waiting for the bus to give the credentials of their caller
+const MAX_PENDING_CALLS: usize = 64;
+/// Time after which the calls whose credentials the bus did not give are refused, the default
+/// timeout of the method calls of D-Bus
+const CREDENTIALS_TIMEOUT: Duration = Duration::from_secs(25);
+/// Time after which the thread emitting the signals checks whether the listener was dropped
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.parallaxsecond.Parsec">
+    <method name="Ping">
+      <arg name="wire_protocol_version_maj" type="y" direction="out"/>
+      <arg name="wire_protocol_version_min" type="y" direction="out"/>
+    </method>
+    <method name="ListProviders">
+      <arg name="providers" type="a(ys)" direction="out"/>
+    </method>
+    <method name="ListKeys">
+      <arg name="keys" type="a(ys)" direction="out"/>
+    </method>
+    <method name="ExportPublicKey">
+      <arg name="provider" type="y" direction="in"/>
+      <arg name="key_name" type="s" direction="in"/>
+      <arg name="data" type="ay" direction="out"/>
+    </method>
+    <method name="GenerateRandom">
+      <arg name="provider" type="y" direction="in"/>
+      <arg name="size" type="u" direction="in"/>
+      <arg name="random_bytes" type="ay" direction="out"/>
+    </method>
+    <method name="DestroyKey">
+      <arg name="provider" type="y" direction="in"/>
+      <arg name="key_name" type="s" direction="in"/>
+    </method>
+    <signal name="KeyCreated">
+      <arg name="application" type="s"/>
+      <arg name="key_name" type="s"/>
+      <arg name="provider" type="y"/>
+    </signal>
+    <signal name="KeyDestroyed">
+      <arg name="application" type="s"/>
+      <arg name="key_name" type="s"/>
+      <arg name="provider" type="y"/>
+    </signal>
+    <signal name="KeyAttested">
+      <arg name="application" type="s"/>
+      <arg name="key_name" type="s"/>
+      <arg name="provider" type="y"/>
+    </signal>
+    <signal name="AuthenticationFailed">
+      <arg name="peer" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+/// Method of the Parsec interface
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Method {
+    Ping,
+    ListProviders,
+    ListKeys,
+    ExportPublicKey,
+    GenerateRandom,
+    DestroyKey,
+}
+
+impl Method {
+    fn from_member(member: &str) -> Option<Self> {
+        match member {
+            "Ping" => Some(Method::Ping),
+            "ListProviders" => Some(Method::ListProviders),
+            "ListKeys" => Some(Method::ListKeys),
+            "ExportPublicKey" => Some(Method::ExportPublicKey),
+            "GenerateRandom" => Some(Method::GenerateRandom),
+            "DestroyKey" => Some(Method::DestroyKey),
+            _ => None,
+        }
+    }
+
+    fn signature(self) -> &'static str {
+        match self {
+            Method::Ping | Method::ListProviders | Method::ListKeys => "",
+            Method::ExportPublicKey | Method::DestroyKey => "ys",
+            Method::GenerateRandom => "yu",
+        }
+    }
+
+    /// The operation called and the provider it is sent to
+    fn operation(self, call: &Message) -> Option<(ProviderId, NativeOperation)> {
+        // The body is only read with the signature of the method, but its absence is not checked.
+        let signature = match call.body_signature() {
+            Ok(signature) => signature.as_str().to_owned(),
+            Err(zbus::Error::NoBodySignature) => String::new(),
+            Err(_) => return None,
+        };
+        if signature != self.signature() {
+            return None;
+        }
+        let provider = |id: u8| ProviderId::try_from(id).ok();
+        Some(match self {
+            Method::Ping => (ProviderId::Core, NativeOperation::Ping(ping::Operation {})),
+            Method::ListProviders => (
+                ProviderId::Core,
+                NativeOperation::ListProviders(list_providers::Operation {}),
+            ),
+            Method::ListKeys => (
+                ProviderId::Core,
+                NativeOperation::ListKeys(list_keys::Operation {}),
+            ),
+            Method::ExportPublicKey => {
+                let (id, key_name): (u8, String) = call.body().ok()?;
+                (
+                    provider(id)?,
+                    NativeOperation::PsaExportPublicKey(psa_export_public_key::Operation {
+                        key_name,
+                    }),
+                )
+            }
+            Method::GenerateRandom => {
+                let (id, size): (u8, u32) = call.body().ok()?;
+                (
+                    provider(id)?,
+                    NativeOperation::PsaGenerateRandom(psa_generate_random::Operation {
+                        size: size as usize,
+                    }),
+                )
+            }
+            Method::DestroyKey => {
+                let (id, key_name): (u8, String) = call.body().ok()?;
+                (
+                    provider(id)?,
+                    NativeOperation::PsaDestroyKey(psa_destroy_key::Operation { key_name }),
+                )
+            }
+        })
+    }
+
+    /// The return of the method, given the result of its operation
+    fn reply(self, call: &Message, result: NativeResult) -> zbus::Result<Message> {
+        match result {
+            NativeResult::Ping(result) => Message::method_reply(
+                NO_SENDER,
+                call,
+                &(
+                    result.wire_protocol_version_maj,
+                    result.wire_protocol_version_min,
+                ),
+            ),
+            NativeResult::ListProviders(result) => {
+                let providers: Vec<(u8, String)> = result
+                    .providers
+                    .into_iter()
+                    .map(|provider| (provider.id as u8, provider.description))
+                    .collect();
+                Message::method_reply(NO_SENDER, call, &providers)
+            }
+            NativeResult::ListKeys(result) => {
+                let keys: Vec<(u8, String)> = result
+                    .keys
+                    .into_iter()
+                    .map(|key| (key.provider_id as u8, key.name))
+                    .collect();
+                Message::method_reply(NO_SENDER, call, &keys)
+            }
+            NativeResult::PsaExportPublicKey(result) => {
+                Message::method_reply(NO_SENDER, call, &*result.data)
+            }
+            NativeResult::PsaGenerateRandom(result) => {
+                Message::method_reply(NO_SENDER, call, &*result.random_bytes)
+            }
+            NativeResult::PsaDestroyKey(_) => Message::method_reply(NO_SENDER, call, &()),
+            _ => {
+                error!("Unexpected result of the {:?} D-Bus method.", self);
+                status_error(call, ResponseStatus::InvalidEncoding)
+            }
+        }
+    }
+}
+
+fn error_reply(call: &Message, name: &str, description: &str) -> zbus::Result<Message> {
+    Message::method_error(NO_SENDER, call, name, &description)
+}
+
+fn status_error(call: &Message, status: ResponseStatus) -> zbus::Result<Message> {
+    error_reply(
+        call,
+        &format!("{}{:?}", ERROR_PREFIX, status),
+        &status.to_string(),
+    )
+}
+
+fn send(bus: &Bus, message: zbus::Result<Message>) {
+    if let Err(e) = message.and_then(|message| bus.send_message(message)) {
+        format_error!("Failed to send a D-Bus message", e);
+    }
+}
+
+/// Whether the caller expects a reply to its call
+fn replied(call: &Message) -> bool {
+    !call
+        .primary_header()
+        .flags()
+        .contains(MessageFlags::NoReplyExpected)
+}
+
+/// Unique name of the sender of a message, as set by the bus
+fn sender(message: &Message) -> Option<String> {
+    let header = message.header().ok()?;
+    let sender = header.sender().ok()??;
+    Some(sender.to_string())
+}
+
+/// Stream of a request made from a D-Bus call, sending the response back as the reply of the call
+/// when dropped
+struct CallStream {
+    request: Cursor<Vec<u8>>,
+    response: Vec<u8>,
+    call: Arc<Message>,
+    method: Method,
+    opcode: Opcode,
+    bus: Bus,
+}
+
+impl Read for CallStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.request.read(buf)
+    }
+}
+
+impl Write for CallStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.response.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for CallStream {
+    fn drop(&mut self) {
+        if !replied(&self.call) {
+            return;
+        }
+        let limit = self.response.len();
+        let reply = match Response::read_from_stream(&mut self.response.as_slice(), limit) {
+            Ok(response) if response.header.status == ResponseStatus::Success => {
+                let converter = ProtobufConverter {};
+                match converter.body_to_result(response.body, self.opcode) {
+                    Ok(result) => self.method.reply(&self.call, result),
+                    Err(status) => status_error(&self.call, status),
+                }
+            }
+            Ok(response) => status_error(&self.call, response.header.status),
+            Err(status) => status_error(&self.call, status),
+        };
+        send(&self.bus, reply);
+    }
+}
+
+/// Call waiting for the credentials of its caller
+struct PendingCall {
+    call: Arc<Message>,
+    method: Method,
+    provider: ProviderId,
+    operation: NativeOperation,
+}
+
+/// Listener receiving the calls made through D-Bus
+#[derive(Debug)]
+pub struct DbusListener {
+    calls: Receiver<Connection>,
+    bus: Bus,
+    stopped: Arc<AtomicBool>,
+}
+
+impl DbusListener {
+    /// Connect to the bus, own the name of the service and start the threads receiving the calls
+    /// and emitting the signals.
+    pub fn start(config: &DbusConfig) -> Result<Self> {
+        let address = config
+            .bus_address
+            .clone()
+            .or_else(|| std::env::var("DBUS_SYSTEM_BUS_ADDRESS").ok())
+            .unwrap_or_else(|| String::from(DEFAULT_BUS_ADDRESS));
+        let bus = connect(&address)?;
+        // Receives the calls made as soon as the name is owned.
+        let messages = MessageIterator::from(&bus);
+        let flags = RequestNameFlags::AllowReplacement | RequestNameFlags::ReplaceExisting;
+        match bus.request_name_with_flags(BUS_NAME, flags) {
+            Ok(RequestNameReply::PrimaryOwner) | Ok(RequestNameReply::AlreadyOwner) => (),
+            Ok(_) => {
+                error!("The {} D-Bus name is owned by another service.", BUS_NAME);
+                return Err(Error::new(ErrorKind::AddrInUse, "D-Bus name not available"));
+            }
+            Err(e) => {
+                format_error!("Failed to own the D-Bus name", e);
+                return Err(Error::new(ErrorKind::Other, e));
+            }
+        }
+        info!("Owning the {} name on the D-Bus bus {}.", BUS_NAME, address);
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (sender, calls) = mpsc::sync_channel(MAX_QUEUED_CALLS);
+        let receiving_bus = bus.clone();
+        let receiving_stopped = stopped.clone();
+        let _ = thread::Builder::new()
+            .name(String::from("dbus-calls"))
+            .spawn(move || receive_calls(messages, receiving_bus, sender, receiving_stopped))?;
+        let events = event_hooks::subscribe();
+        let emitting_bus = bus.clone();
+        let emitting_stopped = stopped.clone();
+        let _ = thread::Builder::new()
+            .name(String::from("dbus-signals"))
+            .spawn(move || emit_signals(events, emitting_bus, emitting_stopped))?;
+
+        Ok(DbusListener {
+            calls,
+            bus,
+            stopped,
+        })
+    }
+}
+
+impl Listen for DbusListener {
+    // Requests are read from memory, they do not time out.
+    fn set_timeout(&mut self, _duration: Duration) {}
+
+    fn accept(&self) -> Option<Connection> {
+        self.calls.try_recv().ok()
+    }
+}
+
+impl Drop for DbusListener {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // The reply of the bus wakes the thread receiving the calls up, which then ends. The
+        // connection is closed once the threads are done with it.
+        if self.bus.is_bus() {
+            if let Err(e) = self.bus.release_name(BUS_NAME) {
+                format_error!("Failed to release the D-Bus name", e);
+            }
+        }
+    }
+}
+
+/// Listener accepting the connections of another listener and the D-Bus calls in turn
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct WithDbusListener {
+    #[derivative(Debug = "ignore")]
+    listener: Box<dyn Listen>,
+    dbus: DbusListener,
+    // Checked first on the next call to accept, so that neither is starved.
+    dbus_first: AtomicBool,
+}
+
+impl WithDbusListener {
+    /// Accept the connections of the listener and the calls of the D-Bus listener.
+    pub fn new(listener: Box<dyn Listen>, dbus: DbusListener) -> Self {
+        WithDbusListener {
+            listener,
+            dbus,
+            dbus_first: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Listen for WithDbusListener {
+    fn set_timeout(&mut self, duration: Duration) {
+        self.listener.set_timeout(duration);
+    }
+
+    fn accept(&self) -> Option<Connection> {
+        if self.dbus_first.fetch_xor(true, Ordering::Relaxed) {
+            self.dbus.accept().or_else(|| self.listener.accept())
+        } else {
+            self.listener.accept().or_else(|| self.dbus.accept())
+        }
+    }
+}
+
+/// Connect to the first reachable server of a D-Bus server address, authenticated as the user of
+/// the service with the EXTERNAL mechanism.
+fn connect(address: &str) -> Result<Bus> {
+    let mut last_error = Error::new(ErrorKind::InvalidInput, "no server in the bus address");
+    for address in address.split(';') {
+        let connected = ConnectionBuilder::address(address)
+            .and_then(|builder| builder.auth_mechanisms(&[AuthMechanism::External]).build());
+        match connected {
+            Ok(bus) => return Ok(bus),
+            Err(e) => last_error = Error::new(ErrorKind::Other, e),
+        }
+    }
+    format_error!("Failed to connect to the D-Bus bus", last_error);
+    Err(last_error)
+}
+
+/// Receive the messages of the bus until the connection is closed or the listener dropped.
+fn receive_calls(
+    messages: MessageIterator,
+    bus: Bus,
+    calls: SyncSender<Connection>,
+    stopped: Arc<AtomicBool>,
+) {
+    // Calls by the serial of the request of the credentials of their caller, with the time the
+    // request was sent
+    let mut pending: HashMap<u32, (Instant, PendingCall)> = HashMap::new();
+    for message in messages {
+        if stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                format_error!("Failed to receive a D-Bus message", e);
+                continue;
+            }
+        };
+        expire(&bus, &mut pending);
+        match message.message_type() {
+            MessageType::MethodCall => {
+                if let Some(call) = method_call(&bus, message) {
+                    if pending.len() >= MAX_PENDING_CALLS {
+                        warn!(
+                            "Too many D-Bus calls are waiting for credentials, refusing the call."
+                        );
+                        send(
+                            &bus,
+                            error_reply(
+                                &call.call,
+                                "org.freedesktop.DBus.Error.LimitsExceeded",
+                                "Too many calls are waiting",
+                            ),
+                        );
+                        continue;
+                    }
+                    let caller = sender(&call.call).unwrap_or_default();
+                    let serial = Message::method(
+                        NO_SENDER,
+                        Some(BUS),
+                        BUS_PATH,
+                        Some(BUS),
+                        "GetConnectionCredentials",
+                        &(caller,),
+                    )
+                    .and_then(|request| bus.send_message(request));
+                    match serial {
+                        Ok(serial) => {
+                            let _ = pending.insert(serial, (Instant::now(), call));
+                        }
+                        Err(e) => format_error!("Failed to send a D-Bus message", e),
+                    }
+                }
+            }
+            // Only the bus vouches for the credentials of a caller: the serials of its requests are
+            // predictable, so replies from other senders are ignored.
+            MessageType::MethodReturn | MessageType::Error
+                if sender(&message).as_deref() == Some(BUS) =>
+            {
+                let call = match message
+                    .reply_serial()
+                    .and_then(|serial| pending.remove(&serial))
+                {
+                    Some((_, call)) => call,
+                    None => continue,
+                };
+                let metadata = match credentials(&message) {
+                    Some(metadata) => metadata,
+                    None => {
+                        warn!("The credentials of a D-Bus caller are not available.");
+                        send(
+                            &bus,
+                            error_reply(
+                                &call.call,
+                                "org.freedesktop.DBus.Error.AccessDenied",
+                                "The credentials of the caller are not available",
+                            ),
+                        );
+                        continue;
+                    }
+                };
+                let reply_to = call.call.clone();
+                match calls.try_send(connection(call, metadata, &bus)) {
+                    Ok(()) => (),
+                    Err(TrySendError::Full(_)) => {
+                        warn!("Too many D-Bus calls are waiting, refusing the call.");
+                        send(
+                            &bus,
+                            error_reply(
+                                &reply_to,
+                                "org.freedesktop.DBus.Error.LimitsExceeded",
+                                "Too many calls are waiting",
+                            ),
+                        );
+                    }
+                    Err(TrySendError::Disconnected(_)) => return,
+                }
+            }
+            // The signals of the bus, like NameAcquired, are not used.
+            _ => (),
+        }
+    }
+    if !stopped.load(Ordering::Relaxed) {
+        error!("Lost the connection to the D-Bus bus.");
+    }
+}
+
+/// Refuse the calls whose credentials the bus did not give in time. They are checked whenever a
+/// message is received, which is also the only time calls are added.
+fn expire(bus: &Bus, pending: &mut HashMap<u32, (Instant, PendingCall)>) {
+    pending.retain(|_, (sent, call)| {
+        if sent.elapsed() < CREDENTIALS_TIMEOUT {
+            return true;
+        }
+        warn!("The D-Bus bus did not give the credentials of a caller in time.");
+        send(
+            bus,
+            error_reply(
+                &call.call,
+                "org.freedesktop.DBus.Error.AccessDenied",
+                "The credentials of the caller are not available",
+            ),
+        );
+        false
+    });
+}
+
+/// Handle a method call, returning it if it is a call of the Parsec interface.
+fn method_call(bus: &Bus, call: Arc<Message>) -> Option<PendingCall> {
+    let replied = replied(&call);
+    let member = call
+        .member()
+        .map(|member| member.to_string())
+        .unwrap_or_default();
+    let interface = call.interface().map(|interface| interface.to_string());
+    match interface.as_deref() {
+        Some(INTROSPECTABLE) if member == "Introspect" => {
+            send(bus, Message::method_reply(NO_SENDER, &call, &INTROSPECTION));
+            return None;
+        }
+        Some(PEER) if member == "Ping" => {
+            if replied {
+                send(bus, Message::method_reply(NO_SENDER, &call, &()));
+            }
+            return None;
+        }
+        None | Some(INTERFACE) => (),
+        Some(_) => {
+            if replied {
+                send(
+                    bus,
+                    error_reply(
+                        &call,
+                        "org.freedesktop.DBus.Error.UnknownInterface",
+                        "Unknown interface",
+                    ),
+                );
+            }
+            return None;
+        }
+    }
+
+    let on_object = call
+        .path()
+        .map_or(false, |path| path.as_str() == OBJECT_PATH);
+    let method = match Method::from_member(&member) {
+        Some(method) if on_object => method,
+        _ => {
+            if replied {
+                send(
+                    bus,
+                    error_reply(
+                        &call,
+                        "org.freedesktop.DBus.Error.UnknownMethod",
+                        "Unknown method",
+                    ),
+                );
+            }
+            return None;
+        }
+    };
+    match method.operation(&call) {
+        Some((provider, operation)) => Some(PendingCall {
+            call,
+            method,
+            provider,
+            operation,
+        }),
+        None => {
+            send(
+                bus,
+                error_reply(
+                    &call,
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    &format!("Expected arguments of signature \"{}\"", method.signature()),
+                ),
+            );
+            None
+        }
+    }
+}
+
+/// Metadata of the connection of the caller, from the reply of the bus to
+/// GetConnectionCredentials
+fn credentials(reply: &Message) -> Option<ConnectionMetadata> {
+    if reply.message_type() != MessageType::MethodReturn {
+        return None;
+    }
+    let credentials: ConnectionCredentials = reply.body().ok()?;
+    // A NUL-terminated array of bytes, as given by SO_PEERSEC
+    let security_context = credentials.linux_security_label().and_then(|label| {
+        let label = label.split(|byte| *byte == 0).next().unwrap_or_default();
+        String::from_utf8(label.to_vec()).ok()
+    });
+    Some(ConnectionMetadata::UnixPeerCredentials {
+        uid: credentials.unix_user_id()?,
+        // The authenticators do not use the group: the first one is given.
+        gid: credentials
+            .unix_group_ids()
+            .and_then(|groups| groups.first().copied())
+            .unwrap_or(u32::MAX),
+        pid: credentials
+            .process_id()
+            .and_then(|pid| i32::try_from(pid).ok()),
+        security_context,
+    })
+}
+
+/// Connection carrying the request of a call, as coming from its caller
+fn connection(call: PendingCall, metadata: ConnectionMetadata, bus: &Bus) -> Connection {
+    let opcode = call.operation.opcode();
+    let provider = call.provider;
+    let mut request = Vec::new();
+    let encoded = ProtobufConverter {}
+        .operation_to_body(call.operation)
+        .and_then(|body| {
+            let ConnectionMetadata::UnixPeerCredentials { uid, .. } = metadata;
+            let parsec_request = Request {
+                header: RequestHeader {
+                    provider,
+                    session: 0,
+                    content_type: BodyType::Protobuf,
+                    accept_type: BodyType::Protobuf,
+                    auth_type: AuthType::UnixPeerCredentials,
+                    opcode,
+                },
+                body,
+                auth: RequestAuth::new(uid.to_le_bytes().to_vec()),
+            };
+            parsec_request.write_to_stream(&mut request)
+        });
+    if let Err(status) = encoded {
+        // The front end answers the empty request with an error.
+        format_error!("Failed to encode the request of a D-Bus call", status);
+        request.clear();
+    }
+    Connection {
+        stream: Box::new(CallStream {
+            request: Cursor::new(request),
+            response: Vec::new(),
+            call: call.call,
+            method: call.method,
+            opcode,
+            bus: bus.clone(),
+        }),
+        metadata: Some(metadata),
+        auth_type: Some(AuthType::UnixPeerCredentials),
+    }
+}
+
+/// Emit the signals of the events until the listener is dropped.
+fn emit_signals(events: Receiver<Event>, bus: Bus, stopped: Arc<AtomicBool>) {
+    while !stopped.load(Ordering::Relaxed) {
+        let event = match events.recv_timeout(SIGNAL_POLL_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let member = format!("{:?}", event.kind());
+        let signal = match event.kind() {
+            HookEvent::KeyCreated | HookEvent::KeyDestroyed | HookEvent::KeyAttested => {
+                Message::signal(
+                    NO_SENDER,
+                    None::<&str>,
+                    OBJECT_PATH,
+                    INTERFACE,
+                    member.as_str(),
+                    &(
+                        event.application().unwrap_or_default(),
+                        event.key_name().unwrap_or_default(),
+                        event.provider().map_or(0, |provider| provider as u8),
+                    ),
+                )
+            }
+            HookEvent::AuthenticationFailed => Message::signal(
+                NO_SENDER,
+                None::<&str>,
+                OBJECT_PATH,
+                INTERFACE,
+                member.as_str(),
+                &(event.peer().unwrap_or_default(),),
+            ),
+        };
+        send(&bus, signal);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::list_authenticators;
+    use parsec_interface::secrecy::ExposeSecret;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Mutex;
+    use std::thread::JoinHandle;
+    use zbus::zvariant::DynamicType;
+    use zbus::{Guid, MessageBuilder};
+
+    const CALLER: &str = ":1.42";
+
+    // Connection of the service to a bus, the other end of which is driven by the tests
+    struct TestBus {
+        service: Bus,
+        // Messages received by the service
+        received: MessageIterator,
+        bus: Bus,
+        // Messages sent by the service
+        sent: MessageIterator,
+    }
+
+    impl TestBus {
+        fn new() -> Self {
+            let (stream, peer) = UnixStream::pair().unwrap();
+            let server = thread::spawn(move || {
+                let guid = Guid::generate();
+                ConnectionBuilder::unix_stream(peer)
+                    .server(&guid)
+                    .p2p()
+                    .build()
+                    .unwrap()
+            });
+            let service = ConnectionBuilder::unix_stream(stream)
+                .p2p()
+                .build()
+                .unwrap();
+            let bus = server.join().unwrap();
+            TestBus {
+                received: MessageIterator::from(&service),
+                service,
+                sent: MessageIterator::from(&bus),
+                bus,
+            }
+        }
+
+        // Send a message to the service, returning it as received.
+        fn deliver(&mut self, message: Message) -> Arc<Message> {
+            let _ = self.bus.send_message(message).unwrap();
+            self.received.next().unwrap().unwrap()
+        }
+
+        fn read(&mut self) -> Arc<Message> {
+            self.sent.next().unwrap().unwrap()
+        }
+    }
+
+    fn call<B: serde::Serialize + DynamicType>(
+        path: &str,
+        interface: Option<&str>,
+        member: &str,
+        body: &B,
+    ) -> Message {
+        Message::method(Some(CALLER), Some(BUS_NAME), path, interface, member, body).unwrap()
+    }
+
+    fn export_public_key() -> Message {
+        call(
+            OBJECT_PATH,
+            Some(INTERFACE),
+            "ExportPublicKey",
+            &(ProviderId::MbedCrypto as u8, "desktop-key"),
+        )
+    }
+
+    fn user() -> ConnectionCredentials {
+        ConnectionCredentials::default().set_unix_user_id(1000)
+    }
+
+    // Reply of the bus to GetConnectionCredentials
+    fn credentials_reply(request: &Message, credentials: ConnectionCredentials) -> Message {
+        Message::method_reply(Some(BUS), request, &credentials).unwrap()
+    }
+
+    fn error_name(message: &Message) -> Option<String> {
+        let header = message.header().unwrap();
+        header.error_name().unwrap().map(ToString::to_string)
+    }
+
+    fn destination(message: &Message) -> Option<String> {
+        let header = message.header().unwrap();
+        header.destination().unwrap().map(ToString::to_string)
+    }
+
+    fn pending(bus: &Bus, call: Arc<Message>) -> PendingCall {
+        match method_call(bus, call) {
+            Some(pending) => pending,
+            None => panic!("the call was not accepted"),
+        }
+    }
+
+    // Write the response of the service to the request of the connection, and drop it.
+    fn respond(mut connection: Connection, status: ResponseStatus, result: Option<NativeResult>) {
+        let request = Request::read_from_stream(&mut connection.stream, 1024).unwrap();
+        let mut response = Response::from_request_header(request.header, status);
+        if let Some(result) = result {
+            response.body = ProtobufConverter {}.result_to_body(result).unwrap();
+        }
+        response.write_to_stream(&mut connection.stream).unwrap();
+    }
+
+    #[test]
+    fn calls_are_turned_into_requests() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let pending = pending(&test_bus.service, call);
+        assert_eq!(pending.provider, ProviderId::MbedCrypto);
+        let reply = credentials_reply(&pending.call, user());
+        let metadata = credentials(&reply).unwrap();
+        let mut connection = connection(pending, metadata, &test_bus.service);
+
+        let request = Request::read_from_stream(&mut connection.stream, 1024).unwrap();
+        assert_eq!(request.header.opcode, Opcode::PsaExportPublicKey);
+        assert_eq!(request.header.provider, ProviderId::MbedCrypto);
+        assert_eq!(request.header.auth_type, AuthType::UnixPeerCredentials);
+        assert_eq!(request.auth.buffer.expose_secret(), &1000u32.to_le_bytes());
+        assert_eq!(connection.auth_type, Some(AuthType::UnixPeerCredentials));
+        let ConnectionMetadata::UnixPeerCredentials { uid, .. } = connection.metadata.unwrap();
+        assert_eq!(uid, 1000);
+    }
+
+    #[test]
+    fn calls_without_interface_are_accepted() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(call(OBJECT_PATH, None, "Ping", &()));
+        assert!(method_call(&test_bus.service, call).is_some());
+    }
+
+    #[test]
+    fn arguments_of_the_wrong_signature_are_refused() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(call(OBJECT_PATH, Some(INTERFACE), "DestroyKey", &()));
+        let serial = call.primary_header().serial_num().copied();
+        assert!(method_call(&test_bus.service, call).is_none());
+        let error = test_bus.read();
+        assert_eq!(error.message_type(), MessageType::Error);
+        assert_eq!(
+            error_name(&error).as_deref(),
+            Some("org.freedesktop.DBus.Error.InvalidArgs")
+        );
+        assert_eq!(error.reply_serial(), serial);
+        assert_eq!(destination(&error).as_deref(), Some(CALLER));
+    }
+
+    #[test]
+    fn unknown_methods_are_refused() {
+        let mut test_bus = TestBus::new();
+        for (path, member) in [(OBJECT_PATH, "SignHash"), ("/org/example", "Ping")] {
+            let call = test_bus.deliver(call(path, Some(INTERFACE), member, &()));
+            assert!(method_call(&test_bus.service, call).is_none());
+            assert_eq!(
+                error_name(&test_bus.read()).as_deref(),
+                Some("org.freedesktop.DBus.Error.UnknownMethod")
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_interfaces_are_refused() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(call(OBJECT_PATH, Some("org.example.I"), "Ping", &()));
+        assert!(method_call(&test_bus.service, call).is_none());
+        assert_eq!(
+            error_name(&test_bus.read()).as_deref(),
+            Some("org.freedesktop.DBus.Error.UnknownInterface")
+        );
+    }
+
+    #[test]
+    fn calls_expecting_no_reply_are_not_replied_to() {
+        let mut test_bus = TestBus::new();
+        let refused = MessageBuilder::method_call(OBJECT_PATH, "Ping")
+            .unwrap()
+            .sender(CALLER)
+            .unwrap()
+            .interface("org.example.I")
+            .unwrap()
+            .with_flags(MessageFlags::NoReplyExpected)
+            .unwrap()
+            .build(&())
+            .unwrap();
+        let refused = test_bus.deliver(refused);
+        assert!(method_call(&test_bus.service, refused).is_none());
+        // The first message received is the reply to the next call.
+        let call = test_bus.deliver(call(OBJECT_PATH, Some(PEER), "Ping", &()));
+        let serial = call.primary_header().serial_num().copied();
+        assert!(method_call(&test_bus.service, call).is_none());
+        assert_eq!(test_bus.read().reply_serial(), serial);
+    }
+
+    #[test]
+    fn objects_are_introspected() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(call(OBJECT_PATH, Some(INTROSPECTABLE), "Introspect", &()));
+        let serial = call.primary_header().serial_num().copied();
+        assert!(method_call(&test_bus.service, call).is_none());
+        let reply = test_bus.read();
+        assert_eq!(reply.message_type(), MessageType::MethodReturn);
+        assert_eq!(reply.reply_serial(), serial);
+        assert_eq!(reply.body::<String>().unwrap(), INTROSPECTION);
+    }
+
+    #[test]
+    fn peers_are_pinged() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(call(OBJECT_PATH, Some(PEER), "Ping", &()));
+        let serial = call.primary_header().serial_num().copied();
+        assert!(method_call(&test_bus.service, call).is_none());
+        let reply = test_bus.read();
+        assert_eq!(reply.message_type(), MessageType::MethodReturn);
+        assert_eq!(reply.reply_serial(), serial);
+        assert_eq!(destination(&reply).as_deref(), Some(CALLER));
+    }
+
+    #[test]
+    fn methods_are_turned_into_operations() {
+        assert_eq!(Method::from_member("SignHash"), None);
+        let (provider, operation) = Method::from_member("Ping")
+            .unwrap()
+            .operation(&call(OBJECT_PATH, Some(INTERFACE), "Ping", &()))
+            .unwrap();
+        assert_eq!(provider, ProviderId::Core);
+        assert_eq!(operation.opcode(), Opcode::Ping);
+        let generate_random = call(
+            OBJECT_PATH,
+            Some(INTERFACE),
+            "GenerateRandom",
+            &(ProviderId::Tpm as u8, 16u32),
+        );
+        let (provider, operation) = Method::GenerateRandom.operation(&generate_random).unwrap();
+        assert_eq!(provider, ProviderId::Tpm);
+        match operation {
+            NativeOperation::PsaGenerateRandom(operation) => assert_eq!(operation.size, 16),
+            _ => panic!("unexpected operation"),
+        }
+    }
+
+    #[test]
+    fn unknown_providers_are_refused() {
+        let destroy_key = call(OBJECT_PATH, Some(INTERFACE), "DestroyKey", &(200u8, "key"));
+        assert!(Method::DestroyKey.operation(&destroy_key).is_none());
+    }
+
+    #[test]
+    fn results_are_encoded_in_the_reply() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let reply = Method::Ping
+            .reply(
+                &call,
+                NativeResult::Ping(ping::Result {
+                    wire_protocol_version_maj: 1,
+                    wire_protocol_version_min: 0,
+                }),
+            )
+            .unwrap();
+        assert_eq!(reply.body::<(u8, u8)>().unwrap(), (1, 0));
+        let reply = Method::GenerateRandom
+            .reply(
+                &call,
+                NativeResult::PsaGenerateRandom(psa_generate_random::Result {
+                    random_bytes: vec![1, 2].into(),
+                }),
+            )
+            .unwrap();
+        assert_eq!(reply.body::<Vec<u8>>().unwrap(), vec![1, 2]);
+        let reply = Method::DestroyKey
+            .reply(
+                &call,
+                NativeResult::PsaDestroyKey(psa_destroy_key::Result {}),
+            )
+            .unwrap();
+        assert_eq!(reply.message_type(), MessageType::MethodReturn);
+        assert!(reply.body_as_bytes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unexpected_results_are_errors() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let reply = Method::Ping
+            .reply(
+                &call,
+                NativeResult::ListAuthenticators(list_authenticators::Result {
+                    authenticators: Vec::new(),
+                }),
+            )
+            .unwrap();
+        assert_eq!(
+            error_name(&reply).as_deref(),
+            Some("org.parallaxsecond.Parsec.Error.InvalidEncoding")
+        );
+    }
+
+    #[test]
+    fn statuses_are_named_errors() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let error = status_error(&call, ResponseStatus::PsaErrorDoesNotExist).unwrap();
+        assert_eq!(
+            error_name(&error).as_deref(),
+            Some("org.parallaxsecond.Parsec.Error.PsaErrorDoesNotExist")
+        );
+        assert_eq!(
+            error.body::<String>().unwrap(),
+            ResponseStatus::PsaErrorDoesNotExist.to_string()
+        );
+    }
+
+    #[test]
+    fn credentials_are_read() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let reply = credentials_reply(
+            &call,
+            user()
+                .set_process_id(4321)
+                .add_unix_group_id(100)
+                .add_unix_group_id(200)
+                .set_linux_security_label(b"unconfined\0".to_vec())
+                // Not used
+                .set_windows_sid(String::from("S-1-5-18")),
+        );
+        let ConnectionMetadata::UnixPeerCredentials {
+            uid,
+            gid,
+            pid,
+            security_context,
+        } = credentials(&reply).unwrap();
+        assert_eq!(uid, 1000);
+        assert_eq!(gid, 100);
+        assert_eq!(pid, Some(4321));
+        assert_eq!(security_context.as_deref(), Some("unconfined"));
+    }
+
+    #[test]
+    fn missing_groups_are_not_guessed() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let ConnectionMetadata::UnixPeerCredentials {
+            gid,
+            pid,
+            security_context,
+            ..
+        } = credentials(&credentials_reply(&call, user())).unwrap();
+        assert_eq!(gid, u32::MAX);
+        assert_eq!(pid, None);
+        assert_eq!(security_context, None);
+    }
+
+    #[test]
+    fn credentials_without_user_are_refused() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let reply = credentials_reply(&call, ConnectionCredentials::default().set_process_id(4321));
+        assert!(credentials(&reply).is_none());
+    }
+
+    #[test]
+    fn credentials_errors_are_refused() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let error = Message::method_error(
+            Some(BUS),
+            &call,
+            "org.freedesktop.DBus.Error.NameHasNoOwner",
+            &"gone",
+        )
+        .unwrap();
+        assert!(credentials(&error).is_none());
+    }
+
+    #[test]
+    fn responses_are_sent_as_replies() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let serial = call.primary_header().serial_num().copied();
+        let pending = pending(&test_bus.service, call);
+        let metadata = credentials(&credentials_reply(&pending.call, user())).unwrap();
+        respond(
+            connection(pending, metadata, &test_bus.service),
+            ResponseStatus::Success,
+            Some(NativeResult::PsaExportPublicKey(
+                psa_export_public_key::Result {
+                    data: vec![4, 5].into(),
+                },
+            )),
+        );
+        let reply = test_bus.read();
+        assert_eq!(reply.message_type(), MessageType::MethodReturn);
+        assert_eq!(reply.reply_serial(), serial);
+        assert_eq!(destination(&reply).as_deref(), Some(CALLER));
+        assert_eq!(reply.body::<Vec<u8>>().unwrap(), vec![4, 5]);
+    }
+
+    #[test]
+    fn failures_are_sent_as_errors() {
+        let mut test_bus = TestBus::new();
+        let call = test_bus.deliver(export_public_key());
+        let serial = call.primary_header().serial_num().copied();
+        let pending = pending(&test_bus.service, call);
+        let metadata = credentials(&credentials_reply(&pending.call, user())).unwrap();
+        respond(
+            connection(pending, metadata, &test_bus.service),
+            ResponseStatus::PsaErrorDoesNotExist,
+            None,
+        );
+        let error = test_bus.read();
+        assert_eq!(error.reply_serial(), serial);
+        assert_eq!(
+            error_name(&error).as_deref(),
+            Some("org.parallaxsecond.Parsec.Error.PsaErrorDoesNotExist")
+        );
+    }
+
+    // Receive the calls of the bus in a thread, as the listener does. The end driven by the tests
+    // is returned, the thread ends once it is dropped.
+    fn receive(test_bus: TestBus) -> (Receiver<Connection>, Bus, MessageIterator, JoinHandle<()>) {
+        let TestBus {
+            service,
+            received,
+            bus,
+            sent,
+        } = test_bus;
+        let (sender, calls) = mpsc::sync_channel(MAX_QUEUED_CALLS);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let receiver = thread::spawn(move || receive_calls(received, service, sender, stopped));
+        (calls, bus, sent, receiver)
+    }
+
+    fn next(messages: &mut MessageIterator) -> Arc<Message> {
+        messages.next().unwrap().unwrap()
+    }
+
+    #[test]
+    fn calls_are_received_with_their_credentials() {
+        let (calls, bus, mut sent, receiver) = receive(TestBus::new());
+
+        let _ = bus.send_message(export_public_key()).unwrap();
+        let request = next(&mut sent);
+        assert_eq!(
+            request.member().map(|member| member.to_string()).as_deref(),
+            Some("GetConnectionCredentials")
+        );
+        assert_eq!(destination(&request).as_deref(), Some(BUS));
+        assert_eq!(request.body::<String>().unwrap(), CALLER);
+        let _ = bus
+            .send_message(credentials_reply(&request, user()))
+            .unwrap();
+        let connection = calls.recv_timeout(Duration::from_secs(10)).unwrap();
+        let ConnectionMetadata::UnixPeerCredentials { uid, .. } = connection.metadata.unwrap();
+        assert_eq!(uid, 1000);
+
+        drop((bus, sent));
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn forged_credentials_replies_are_ignored() {
+        let (calls, bus, mut sent, receiver) = receive(TestBus::new());
+
+        let _ = bus.send_message(export_public_key()).unwrap();
+        let request = next(&mut sent);
+        // Other peers of the bus can guess the serial of the request, but not send as the bus.
+        let root = ConnectionCredentials::default().set_unix_user_id(0);
+        for forger in [Some(":1.99"), None] {
+            let forged = Message::method_reply(forger, &request, &root).unwrap();
+            let _ = bus.send_message(forged).unwrap();
+        }
+        let _ = bus
+            .send_message(credentials_reply(&request, user()))
+            .unwrap();
+
+        let connection = calls.recv_timeout(Duration::from_secs(10)).unwrap();
+        let ConnectionMetadata::UnixPeerCredentials { uid, .. } = connection.metadata.unwrap();
+        assert_eq!(uid, 1000);
+        assert!(calls.try_recv().is_err());
+
+        drop((bus, sent));
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn calls_waiting_for_credentials_are_limited() {
+        let (_calls, bus, mut sent, receiver) = receive(TestBus::new());
+
+        let mut serial = 0;
+        for _ in 0..=MAX_PENDING_CALLS {
+            serial = bus.send_message(export_public_key()).unwrap();
+        }
+        for _ in 0..MAX_PENDING_CALLS {
+            assert_eq!(
+                next(&mut sent)
+                    .member()
+                    .map(|member| member.to_string())
+                    .as_deref(),
+                Some("GetConnectionCredentials")
+            );
+        }
+        let refusal = next(&mut sent);
+        assert_eq!(refusal.reply_serial(), Some(serial));
+        assert_eq!(
+            error_name(&refusal).as_deref(),
+            Some("org.freedesktop.DBus.Error.LimitsExceeded")
+        );
+
+        drop((bus, sent));
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn calls_without_credentials_in_time_are_refused() {
+        let mut test_bus = TestBus::new();
+        let late_call = test_bus.deliver(export_public_key());
+        let serial = late_call.primary_header().serial_num().copied();
+        let late_call = pending(&test_bus.service, late_call);
+        let call = test_bus.deliver(export_public_key());
+        let call = pending(&test_bus.service, call);
+        let late = Instant::now().checked_sub(CREDENTIALS_TIMEOUT).unwrap();
+        let mut waiting = HashMap::new();
+        let _ = waiting.insert(1, (late, late_call));
+        let _ = waiting.insert(2, (Instant::now(), call));
+
+        expire(&test_bus.service, &mut waiting);
+        assert_eq!(waiting.keys().collect::<Vec<_>>(), vec![&2]);
+        let refusal = test_bus.read();
+        assert_eq!(refusal.reply_serial(), serial);
+        assert_eq!(
+            error_name(&refusal).as_deref(),
+            Some("org.freedesktop.DBus.Error.AccessDenied")
+        );
+    }
+
+    #[test]
+    fn calls_without_credentials_are_refused() {
+        let (calls, bus, mut sent, receiver) = receive(TestBus::new());
+
+        let serial = bus.send_message(export_public_key()).unwrap();
+        let request = next(&mut sent);
+        let error = Message::method_error(
+            Some(BUS),
+            &request,
+            "org.freedesktop.DBus.Error.NameHasNoOwner",
+            &"",
+        )
+        .unwrap();
+        let _ = bus.send_message(error).unwrap();
+        let refusal = next(&mut sent);
+        assert_eq!(refusal.reply_serial(), Some(serial));
+        assert_eq!(
+            error_name(&refusal).as_deref(),
+            Some("org.freedesktop.DBus.Error.AccessDenied")
+        );
+        assert!(calls.try_recv().is_err());
+
+        drop((bus, sent));
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn events_are_emitted_as_signals() {
+        let mut test_bus = TestBus::new();
+        let (sender, events) = mpsc::channel();
+        sender
+            .send(Event::key(
+                HookEvent::KeyCreated,
+                "app",
+                "desktop-key",
+                ProviderId::MbedCrypto,
+            ))
+            .unwrap();
+        sender
+            .send(Event::authentication_failed(String::from("pid 42")))
+            .unwrap();
+        drop(sender);
+        // Returns once the events are emitted and the sender is gone.
+        emit_signals(
+            events,
+            test_bus.service.clone(),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        let signal = test_bus.read();
+        assert_eq!(signal.message_type(), MessageType::Signal);
+        assert_eq!(
+            signal.path().map(|path| path.to_string()).as_deref(),
+            Some(OBJECT_PATH)
+        );
+        assert_eq!(
+            signal.member().map(|member| member.to_string()).as_deref(),
+            Some("KeyCreated")
+        );
+        assert_eq!(
+            signal.body::<(String, String, u8)>().unwrap(),
+            (
+                String::from("app"),
+                String::from("desktop-key"),
+                ProviderId::MbedCrypto as u8
+            )
+        );
+        let signal = test_bus.read();
+        assert_eq!(
+            signal.member().map(|member| member.to_string()).as_deref(),
+            Some("AuthenticationFailed")
+        );
+        assert_eq!(signal.body::<String>().unwrap(), "pid 42");
+    }
+
+    #[test]
+    fn unreachable_buses_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        assert!(connect(&format!("unix:path={}", missing.display())).is_err());
+        assert!(connect("").is_err());
+    }
+
+    struct Queued(Mutex<Vec<Connection>>);
+
+    impl Listen for Queued {
+        fn set_timeout(&mut self, _duration: Duration) {}
+
+        fn accept(&self) -> Option<Connection> {
+            self.0.lock().unwrap().pop()
+        }
+    }
+
+    fn marked(auth_type: AuthType) -> Connection {
+        Connection {
+            stream: Box::new(Cursor::new(Vec::new())),
+            metadata: None,
+            auth_type: Some(auth_type),
+        }
+    }
+
+    #[test]
+    fn listeners_are_accepted_from_in_turn() {
+        let (sender, calls) = mpsc::sync_channel(MAX_QUEUED_CALLS);
+        for _ in 0..2 {
+            sender
+                .try_send(marked(AuthType::UnixPeerCredentials))
+                .unwrap();
+        }
+        let dbus = DbusListener {
+            calls,
+            bus: TestBus::new().service,
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+        let listener = Queued(Mutex::new(vec![
+            marked(AuthType::Direct),
+            marked(AuthType::Direct),
+        ]));
+        let both = WithDbusListener::new(Box::new(listener), dbus);
+        let accepted: Vec<_> = std::iter::from_fn(|| both.accept())
+            .map(|connection| connection.auth_type.unwrap())
+            .collect();
+        assert_eq!(
+            accepted,
+            vec![
+                AuthType::Direct,
+                AuthType::UnixPeerCredentials,
+                AuthType::Direct,
+                AuthType::UnixPeerCredentials,
+            ]
+        );
+    }
+}
@@ -3,106 +3,174 @@
 //! Service front using Unix domain sockets
 //!
 //! Expose Parsec functionality using Unix domain sockets as an IPC layer.
-//! The local socket is created at a predefined location.
+//! The local socket is created at a predefined location. Additional sockets can be created, each
+//! possibly restricted to one authenticator. On Linux, a path starting with `@` names a socket in
+//! the abstract namespace instead of a file.
 use super::listener;
 use anyhow::{Context, Result};
 use listener::Listen;
 use listener::{Connection, ConnectionMetadata};
 use log::{error, warn};
+use parsec_interface::requests::AuthType;
+use std::ffi::CString;
 use std::fs;
 use std::fs::Permissions;
 use std::io::{Error, ErrorKind};
+#[cfg(target_os = "linux")]
+use std::mem::size_of;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
 use std::os::unix::fs::FileTypeExt;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::FromRawFd;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::OwnedFd;
 use std::os::unix::io::RawFd;
-use std::os::unix::net::UnixListener;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-static DEFAULT_SOCKET_PATH: &str = "/run/parsec/parsec.sock";
+/// Path of the socket used when none is configured
+pub static DEFAULT_SOCKET_PATH: &str = "/run/parsec/parsec.sock";
+
+/// Permissions of the sockets when none are configured, allowing clients of different users to
+/// connect.
+const DEFAULT_SOCKET_MODE: u32 = 0o666;
+
+/// Unix Domain Socket the listener accepts connections on
+#[derive(Clone, Debug)]
+pub struct SocketEndpoint {
+    /// Path of the socket
+    pub path: PathBuf,
+    /// User owning the socket, if it needs changing
+    pub owner: Option<u32>,
+    /// Group owning the socket, if it needs changing
+    pub group: Option<u32>,
+    /// Permissions of the socket
+    pub mode: Option<u32>,
+    /// Only authenticator allowed for the requests received on this socket, if restricted
+    pub auth_type: Option<AuthType>,
+}
+
+impl SocketEndpoint {
+    /// Socket at the given path, with the default ownership and permissions
+    pub fn new(path: PathBuf) -> Self {
+        SocketEndpoint {
+            path,
+            owner: None,
+            group: None,
+            mode: None,
+            auth_type: None,
+        }
+    }
+}
 
 /// Unix Domain Socket IPC manager
 ///
 /// Listener implementation for Unix sockets as the underlying IPC mechanism.
 ///
-/// Holds references to a `UnixListener`.
+/// Holds references to a `UnixListener` for each socket.
 #[derive(Debug)]
 pub struct DomainSocketListener {
-    listener: UnixListener,
+    listeners: Vec<(UnixListener, Option<AuthType>)>,
+    // Socket checked first on the next call to accept, so that no socket is starved.
+    next: AtomicUsize,
     timeout: Duration,
+    idle_timeout: Option<Duration>,
 }
 
-impl DomainSocketListener {
-    /// Initialise the connection to the Unix socket.
-    pub fn new(timeout: Duration, socket_path: PathBuf) -> Result<Self> {
-        // If Parsec was service activated or not started under systemd, this
-        // will return `0`. `1` will be returned in case Parsec is socket activated.
-        let listeners: Vec<RawFd> = sd_notify::listen_fds()?.collect();
-        let listener = match listeners.len() {
-            0 => {
-                if socket_path.exists() {
-                    let meta = fs::metadata(&socket_path)?;
-                    if meta.file_type().is_socket() {
-                        warn!(
-                            "Removing the existing socket file at {}.",
-                            socket_path.display()
-                        );
-                        fs::remove_file(&socket_path)?;
-                    } else {
-                        error!(
-                            "A file exists at {} but is not a Unix Domain Socket.",
-                            socket_path.display()
-                        );
-                    }
-                }
+/// Stream failing the reads once its deadline has passed
+#[derive(Debug)]
+struct DeadlineStream {
+    stream: UnixStream,
+    timeout: Duration,
+    deadline: Instant,
+}
 
-                // Will fail if a file already exists at the path.
-                let listener = UnixListener::bind(&socket_path).with_context(|| {
-                    format!("Failed to bind to Unix socket at {:?}", socket_path)
-                })?;
-                listener.set_nonblocking(true)?;
+impl std::io::Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self
+            .deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::TimedOut,
+                    "request not received before the deadline",
+                )
+            })?;
+        self.stream
+            .set_read_timeout(Some(remaining.min(self.timeout)))?;
+        self.stream.read(buf)
+    }
+}
 
-                // Set the socket's permission to 666 to allow clients of different user to
-                // connect.
-                let permissions = Permissions::from_mode(0o666);
-                fs::set_permissions(socket_path, permissions)?;
+impl std::io::Write for DeadlineStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
 
-                listener
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl DomainSocketListener {
+    /// Initialise the connection to the Unix sockets, creating their directory if needed.
+    pub fn new(
+        timeout: Duration,
+        idle_timeout: Option<Duration>,
+        endpoints: Vec<SocketEndpoint>,
+        create_socket_dir: bool,
+    ) -> Result<Self> {
+        // If Parsec was service activated or not started under systemd, this
+        // will return `0`. Otherwise one file descriptor is received for each socket.
+        let fds: Vec<RawFd> = sd_notify::listen_fds()?.collect();
+        let mut listeners = Vec::with_capacity(endpoints.len());
+        if fds.is_empty() {
+            for endpoint in endpoints {
+                let listener = bind(&endpoint, create_socket_dir)?;
+                listeners.push((listener, endpoint.auth_type));
             }
-            1 => {
+        } else if fds.len() == endpoints.len() {
+            // The sockets are expected in the order in which they are configured.
+            for (nfd, endpoint) in fds.into_iter().zip(endpoints) {
                 // No need to set the socket as non-blocking, parsec.service
                 // already requests that.
-                let nfd = listeners[0];
-                // Safe as listen_fds gives us the information that one file descriptor was
-                // received and its value starts from SD_LISTEN_FDS_START.
-                unsafe { UnixListener::from_raw_fd(nfd) }
-                // Expect the socket created by systemd to be 666 on permissions.
+                // Safe as listen_fds gives us the information that those file descriptors were
+                // received and their values start from SD_LISTEN_FDS_START.
+                let listener = unsafe { UnixListener::from_raw_fd(nfd) };
+                // Expect the socket created by systemd to have the right permissions.
+                listeners.push((listener, endpoint.auth_type));
             }
-            n => {
-                error!(
-                    "Received too many file descriptors ({} received, 0 or 1 expected).",
-                    n
-                );
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "too many file descriptors received",
-                )
-                .into());
-            }
-        };
-
-        Ok(Self { listener, timeout })
-    }
-}
+        } else {
+            error!(
+                "Received an unexpected number of file descriptors ({} received, 0 or {} expected).",
+                fds.len(),
+                endpoints.len()
+            );
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "unexpected number of file descriptors received",
+            )
+            .into());
+        }
 
-impl Listen for DomainSocketListener {
-    fn set_timeout(&mut self, duration: Duration) {
-        self.timeout = duration;
+        Ok(Self {
+            listeners,
+            next: AtomicUsize::new(0),
+            timeout,
+            idle_timeout,
+        })
     }
 
-    fn accept(&self) -> Option<Connection> {
-        let stream_result = self.listener.accept();
+    fn accept_on(
+        &self,
+        listener: &UnixListener,
+        auth_type: Option<AuthType>,
+    ) -> Option<Connection> {
+        let stream_result = listener.accept();
         match stream_result {
             Ok((stream, _)) => {
                 if let Err(err) = stream.set_read_timeout(Some(self.timeout)) {
@@ -124,13 +192,29 @@ impl Listen for DomainSocketListener {
                             err
                         })
                         .ok()?;
+                    // Without a context, only the authentications not needing one can succeed.
+                    let security_context = peer_credentials::peer_security_context(&stream)
+                        .unwrap_or_else(|err| {
+                            format_error!("Failed to grab the security context of the peer", err);
+                            None
+                        });
+                    let stream: Box<dyn listener::ReadWrite + Send> = match self.idle_timeout {
+                        Some(idle_timeout) => Box::new(DeadlineStream {
+                            stream,
+                            timeout: self.timeout,
+                            deadline: Instant::now() + idle_timeout,
+                        }),
+                        None => Box::new(stream),
+                    };
                     Some(Connection {
-                        stream: Box::new(stream),
+                        stream,
                         metadata: Some(ConnectionMetadata::UnixPeerCredentials {
                             uid: ucred.uid,
                             gid: ucred.gid,
                             pid: ucred.pid,
+                            security_context,
                         }),
+                        auth_type,
                     })
                 }
             }
@@ -146,11 +230,273 @@ impl Listen for DomainSocketListener {
     }
 }
 
+/// Prefix of the socket paths naming a socket in the abstract namespace
+pub const ABSTRACT_SOCKET_PREFIX: char = '@';
+
+/// Name of the socket in the abstract namespace, if the path designates one
+pub fn abstract_name(socket_path: &Path) -> Option<&[u8]> {
+    socket_path
+        .as_os_str()
+        .as_bytes()
+        .strip_prefix(&[ABSTRACT_SOCKET_PREFIX as u8])
+}
+
+/// Socket of the given name in the abstract namespace, bound and listening or connected.
+///
+/// The standard library only supports abstract addresses since Rust 1.70, above the minimum
+/// supported version, so the socket is created with libc.
+#[cfg(target_os = "linux")]
+fn abstract_socket(name: &[u8], connect: bool) -> std::io::Result<OwnedFd> {
+    // Safety: sockaddr_un is plain data, for which all zeroes is a valid value.
+    let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    // The name follows the NUL byte starting the path of abstract addresses.
+    if name.len() >= address.sun_path.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "abstract socket name too long",
+        ));
+    }
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (path_byte, name_byte) in address.sun_path[1..].iter_mut().zip(name) {
+        *path_byte = *name_byte as libc::c_char;
+    }
+    // The name is not NUL-terminated: its length is given by the length of the address.
+    let address_len = size_of::<libc::sa_family_t>() + 1 + name.len();
+
+    // Safety: socket has no preconditions, its result is checked.
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    // Safety: the descriptor was just created and is owned by nothing else.
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let address_ptr = std::ptr::addr_of!(address).cast::<libc::sockaddr>();
+    // Safety: the address is a valid sockaddr_un of at least address_len bytes.
+    let result = unsafe {
+        if connect {
+            libc::connect(fd, address_ptr, address_len as libc::socklen_t)
+        } else {
+            libc::bind(fd, address_ptr, address_len as libc::socklen_t)
+        }
+    };
+    if result < 0 {
+        return Err(Error::last_os_error());
+    }
+    // Safety: the descriptor is a bound socket. The backlog is the one of the standard library.
+    if !connect && unsafe { libc::listen(fd, 128) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Create a listening socket of the given name in the abstract namespace.
+#[cfg(target_os = "linux")]
+pub fn listen_abstract(name: &[u8]) -> std::io::Result<UnixListener> {
+    abstract_socket(name, false).map(UnixListener::from)
+}
+
+/// Connect to the socket of the given name in the abstract namespace.
+#[cfg(target_os = "linux")]
+pub fn connect_abstract(name: &[u8]) -> std::io::Result<UnixStream> {
+    abstract_socket(name, true).map(UnixStream::from)
+}
+
+/// Create a listening socket of the given name in the abstract namespace.
+#[cfg(not(target_os = "linux"))]
+pub fn listen_abstract(_name: &[u8]) -> std::io::Result<UnixListener> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+/// Connect to the socket of the given name in the abstract namespace.
+#[cfg(not(target_os = "linux"))]
+pub fn connect_abstract(_name: &[u8]) -> std::io::Result<UnixStream> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+/// Create a socket in the abstract namespace. Those sockets have no file and no permissions: any
+/// process in the same network namespace can connect to them.
+fn bind_abstract(endpoint: &SocketEndpoint, name: &[u8]) -> Result<UnixListener> {
+    if endpoint.owner.is_some() || endpoint.group.is_some() || endpoint.mode.is_some() {
+        warn!("The ownership and permissions of abstract sockets can not be set.");
+    }
+    let listener = listen_abstract(name)
+        .with_context(|| format!("Failed to bind to abstract Unix socket {:?}", endpoint.path))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Create the socket of an endpoint, replacing a previous socket file.
+fn bind(endpoint: &SocketEndpoint, create_socket_dir: bool) -> Result<UnixListener> {
+    let socket_path = &endpoint.path;
+    if let Some(name) = abstract_name(socket_path) {
+        return bind_abstract(endpoint, name);
+    }
+    if socket_path.exists() {
+        let meta = fs::metadata(socket_path)?;
+        if meta.file_type().is_socket() {
+            warn!(
+                "Removing the existing socket file at {}.",
+                socket_path.display()
+            );
+            fs::remove_file(socket_path)?;
+        } else {
+            error!(
+                "A file exists at {} but is not a Unix Domain Socket.",
+                socket_path.display()
+            );
+        }
+    }
+    if create_socket_dir {
+        if let Some(directory) = socket_path.parent() {
+            fs::create_dir_all(directory).with_context(|| {
+                format!("Failed to create the socket directory {:?}", directory)
+            })?;
+        }
+    }
+
+    // The socket is created in a private directory and moved into place once its owner and
+    // permissions are set, so that it can not be connected to before.
+    let private_dir = private_dir(socket_path)?;
+    let result = bind_private(endpoint, &private_dir);
+    if let Err(e) = fs::remove_dir_all(&private_dir) {
+        warn!(
+            "Failed to remove the directory {}: {}",
+            private_dir.display(),
+            e
+        );
+    }
+    result
+}
+
+/// Create a directory only accessible to the service next to the socket, on the same file system.
+fn private_dir(socket_path: &Path) -> Result<PathBuf> {
+    let file_name = socket_path
+        .file_name()
+        .with_context(|| format!("The socket path {:?} has no file name", socket_path))?;
+    let mut dir_name = std::ffi::OsString::from(".");
+    dir_name.push(file_name);
+    dir_name.push(format!(".{}", std::process::id()));
+    let private_dir = socket_path.with_file_name(dir_name);
+    // Left over by a previous instance which had the same process ID.
+    if private_dir.exists() {
+        fs::remove_dir_all(&private_dir)?;
+    }
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .with_context(|| format!("Failed to create the directory {:?}", private_dir))?;
+    Ok(private_dir)
+}
+
+/// Create the socket in the private directory, set its owner and permissions and move it to the
+/// path of the endpoint.
+fn bind_private(endpoint: &SocketEndpoint, private_dir: &Path) -> Result<UnixListener> {
+    let socket_path = &endpoint.path;
+    let private_path = private_dir.join("socket");
+
+    // Will fail if a file already exists at the path.
+    let listener = UnixListener::bind(&private_path)
+        .with_context(|| format!("Failed to bind to Unix socket at {:?}", socket_path))?;
+    listener.set_nonblocking(true)?;
+
+    if endpoint.owner.is_some() || endpoint.group.is_some() {
+        chown(&private_path, endpoint.owner, endpoint.group)
+            .with_context(|| format!("Failed to change the owner of {:?}", socket_path))?;
+    }
+    let permissions = Permissions::from_mode(endpoint.mode.unwrap_or(DEFAULT_SOCKET_MODE));
+    fs::set_permissions(&private_path, permissions)?;
+
+    fs::rename(&private_path, socket_path)
+        .with_context(|| format!("Failed to move the socket to {:?}", socket_path))?;
+
+    Ok(listener)
+}
+
+fn chown(path: &Path, owner: Option<u32>, group: Option<u32>) -> std::io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "path contains a null byte"))?;
+    // -1 leaves the owner or group unchanged.
+    let owner = owner.unwrap_or(u32::MAX);
+    let group = group.unwrap_or(u32::MAX);
+    // Safe as the path is a valid null-terminated string.
+    if unsafe { libc::chown(path.as_ptr(), owner, group) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Find the ID of a user from its name or its numeric ID.
+pub fn user_id(user: &str) -> std::io::Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = CString::new(user)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "user name contains a null byte"))?;
+    // Safe as the name is a valid null-terminated string; the service is still single-threaded
+    // when setting up its listener.
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("unknown user {}", user),
+        ));
+    }
+    // Safe as getpwnam returned a valid entry.
+    Ok(unsafe { (*passwd).pw_uid })
+}
+
+/// Find the ID of a group from its name or its numeric ID.
+pub fn group_id(group: &str) -> std::io::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "group name contains a null byte"))?;
+    // Safe as the name is a valid null-terminated string; the service is still single-threaded
+    // when setting up its listener.
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("unknown group {}", group),
+        ));
+    }
+    // Safe as getgrnam returned a valid entry.
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+impl Listen for DomainSocketListener {
+    fn set_timeout(&mut self, duration: Duration) {
+        self.timeout = duration;
+    }
+
+    fn accept(&self) -> Option<Connection> {
+        let count = self.listeners.len();
+        let first = self.next.fetch_add(1, Ordering::Relaxed) % count;
+        (0..count).find_map(|offset| {
+            let (listener, auth_type) = &self.listeners[(first + offset) % count];
+            self.accept_on(listener, *auth_type)
+        })
+    }
+}
+
 /// Builder for `DomainSocketListener`
 #[derive(Clone, Debug, Default)]
 pub struct DomainSocketListenerBuilder {
     timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
     socket_path: Option<PathBuf>,
+    socket_owner: Option<u32>,
+    socket_group: Option<u32>,
+    socket_mode: Option<u32>,
+    create_socket_dir: bool,
+    additional_sockets: Vec<SocketEndpoint>,
 }
 
 impl DomainSocketListenerBuilder {
@@ -158,7 +504,13 @@ impl DomainSocketListenerBuilder {
     pub fn new() -> Self {
         DomainSocketListenerBuilder {
             timeout: None,
+            idle_timeout: None,
             socket_path: None,
+            socket_owner: None,
+            socket_group: None,
+            socket_mode: None,
+            create_socket_dir: false,
+            additional_sockets: Vec::new(),
         }
     }
 
@@ -168,21 +520,63 @@ impl DomainSocketListenerBuilder {
         self
     }
 
+    /// Add a limit on the time clients have to send a complete request
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
     /// Specify the Unix Domain Socket path
     pub fn with_socket_path(mut self, socket_path: Option<PathBuf>) -> Self {
         self.socket_path = socket_path;
         self
     }
 
+    /// Specify the user and group owning the Unix Domain Socket
+    pub fn with_socket_ownership(mut self, owner: Option<u32>, group: Option<u32>) -> Self {
+        self.socket_owner = owner;
+        self.socket_group = group;
+        self
+    }
+
+    /// Specify the permissions of the Unix Domain Socket
+    pub fn with_socket_mode(mut self, mode: Option<u32>) -> Self {
+        self.socket_mode = mode;
+        self
+    }
+
+    /// Create the directories of the sockets if they do not exist
+    pub fn with_create_socket_dir(mut self, create_socket_dir: bool) -> Self {
+        self.create_socket_dir = create_socket_dir;
+        self
+    }
+
+    /// Add another socket to listen on
+    pub fn with_additional_socket(mut self, socket: SocketEndpoint) -> Self {
+        self.additional_sockets.push(socket);
+        self
+    }
+
     /// Build the builder into the listener
     pub fn build(self) -> Result<DomainSocketListener> {
+        let mut endpoints = vec![SocketEndpoint {
+            path: self
+                .socket_path
+                .unwrap_or_else(|| DEFAULT_SOCKET_PATH.into()),
+            owner: self.socket_owner,
+            group: self.socket_group,
+            mode: self.socket_mode,
+            auth_type: None,
+        }];
+        endpoints.extend(self.additional_sockets);
         DomainSocketListener::new(
             self.timeout.ok_or_else(|| {
                 error!("The listener timeout was not set.");
                 Error::new(ErrorKind::InvalidInput, "listener timeout missing")
             })?,
-            self.socket_path
-                .unwrap_or_else(|| DEFAULT_SOCKET_PATH.into()),
+            self.idle_timeout,
+            endpoints,
+            self.create_socket_dir,
         )
     }
 }
@@ -223,7 +617,7 @@ pub mod peer_credentials {
     }
 
     #[cfg(any(target_os = "android", target_os = "linux"))]
-    pub use self::impl_linux::peer_cred;
+    pub use self::impl_linux::{peer_cred, peer_security_context};
 
     #[cfg(any(
         target_os = "dragonfly",
@@ -234,11 +628,19 @@ pub mod peer_credentials {
     ))]
     pub use self::impl_bsd::peer_cred;
 
+    /// Security context of the peer: only Linux security modules give one.
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    pub fn peer_security_context(
+        _socket: &std::os::unix::net::UnixStream,
+    ) -> std::io::Result<Option<String>> {
+        Ok(None)
+    }
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     #[allow(missing_docs, trivial_casts)] // docs not required; only used for selective compilation.
     pub mod impl_linux {
         use super::UCred;
-        use libc::{c_void, getsockopt, socklen_t, ucred, SOL_SOCKET, SO_PEERCRED};
+        use libc::{c_void, getsockopt, socklen_t, ucred, SOL_SOCKET, SO_PEERCRED, SO_PEERSEC};
         use std::os::unix::io::AsRawFd;
         use std::os::unix::net::UnixStream;
         use std::{io, mem};
@@ -277,6 +679,46 @@ pub mod peer_credentials {
                 }
             }
         }
+
+        /// SELinux context or AppArmor label of the peer, `None` if no security module gives one.
+        pub fn peer_security_context(socket: &UnixStream) -> io::Result<Option<String>> {
+            let mut context = vec![0u8; 256];
+            loop {
+                let mut context_size = context.len() as socklen_t;
+                let ret = unsafe {
+                    getsockopt(
+                        socket.as_raw_fd(),
+                        SOL_SOCKET,
+                        SO_PEERSEC,
+                        context.as_mut_ptr() as *mut c_void,
+                        &mut context_size,
+                    )
+                };
+                if ret == 0 {
+                    context.truncate(context_size as usize);
+                    break;
+                }
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    // The buffer was too small: the needed size has been written back.
+                    Some(libc::ERANGE) if context_size as usize > context.len() => {
+                        context.resize(context_size as usize, 0)
+                    }
+                    Some(libc::ENOPROTOOPT) => return Ok(None),
+                    _ => return Err(err),
+                }
+            }
+            // Some security modules terminate the label.
+            while let Some(0) | Some(b'\n') = context.last() {
+                let _ = context.pop();
+            }
+            if context.is_empty() {
+                return Ok(None);
+            }
+            String::from_utf8(context)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
     }
 
     #[cfg(any(
@@ -311,3 +753,89 @@ pub mod peer_credentials {
         }
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+
+    fn name(test: &str) -> String {
+        format!("parsec-domain-socket-test-{}-{}", test, std::process::id())
+    }
+
+    #[test]
+    fn abstract_names() {
+        assert_eq!(
+            abstract_name(Path::new("@parsec.sock")),
+            Some(&b"parsec.sock"[..])
+        );
+        assert_eq!(abstract_name(Path::new("/run/parsec/parsec.sock")), None);
+    }
+
+    #[test]
+    fn connect_to_abstract_socket() {
+        let name = name("connect");
+        let listener = listen_abstract(name.as_bytes()).unwrap();
+        let mut client = connect_abstract(name.as_bytes()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut received = [0; 4];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"ping");
+        assert!(server.local_addr().unwrap().as_pathname().is_none());
+    }
+
+    #[test]
+    fn abstract_names_are_exclusive() {
+        let name = name("exclusive");
+        let _listener = listen_abstract(name.as_bytes()).unwrap();
+        assert_eq!(
+            listen_abstract(name.as_bytes()).unwrap_err().kind(),
+            ErrorKind::AddrInUse
+        );
+    }
+
+    #[test]
+    fn unbound_abstract_names_are_refused() {
+        assert_eq!(
+            connect_abstract(name("unbound").as_bytes())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn refuse_long_abstract_names() {
+        assert_eq!(
+            connect_abstract(&[b'a'; 108]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert!(listen_abstract(&[b'a'; 107]).is_ok());
+    }
+
+    #[test]
+    fn abstract_endpoint_is_non_blocking() {
+        let endpoint = SocketEndpoint::new(PathBuf::from(format!("@{}", name("endpoint"))));
+        let listener = bind(&endpoint, false).unwrap();
+        assert_eq!(listener.accept().unwrap_err().kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn socket_is_moved_into_place_with_its_permissions() {
+        let dir = std::env::temp_dir().join(name("permissions"));
+        let mut endpoint = SocketEndpoint::new(dir.join("parsec.sock"));
+        endpoint.mode = Some(0o600);
+        let listener = bind(&endpoint, true).unwrap();
+
+        let meta = fs::metadata(&endpoint.path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        // Only the socket is left in the directory.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _client = UnixStream::connect(&endpoint.path).unwrap();
+        assert!(listener.accept().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
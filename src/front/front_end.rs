@@ -4,16 +4,38 @@
 //!
 //! The front end handler accepts streams of data that it can use to read requests,
 //! pass them to the rest of the service and write the responses back.
+//!
+//! A request body is read from the stream into a buffer owned by the request, which is then moved
+//! (not copied) to the converter of the targeted provider. Decoding the body into an operation is
+//! the only copy made of its contents: the buffer is dropped as soon as the operation exists, so a
+//! request holds at most twice the size of its body. Sharing the received buffer with the
+//! operation (for example with `Bytes`) would remove that copy, but the body and operation types
+//! are defined by `parsec-interface` and would have to change there first.
+use crate::authenticators::tenants::Tenants;
 use crate::authenticators::Authenticate;
 use crate::back::dispatcher::Dispatcher;
+use crate::front::auth_throttle::{AuthThrottle, Peer};
+#[cfg(feature = "cbor-bodies")]
+use crate::front::cbor_bodies;
+#[cfg(feature = "compression")]
+use crate::front::compression;
 use crate::front::listener::Connection;
+use crate::front::memory_budget::MemoryBudget;
+#[cfg(feature = "compression")]
+use crate::front::wire_protocol::FLAG_COMPRESSION;
+use crate::front::wire_protocol::{self, RequestFraming};
+use crate::utils::event_hooks::{Event, EventHooks};
+use crate::utils::logging::{CorrelationScope, Redacted};
+use crate::utils::service_status::ServiceStatus;
 use derivative::Derivative;
 use log::{info, trace};
 use parsec_interface::requests::AuthType;
+#[cfg(feature = "cbor-bodies")]
+use parsec_interface::requests::Request;
+use parsec_interface::requests::Response;
 use parsec_interface::requests::ResponseStatus;
-use parsec_interface::requests::{Request, Response};
 use std::collections::HashMap;
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, ErrorKind, Result, Write};
 
 /// Read and verify request from IPC stream
 ///
@@ -30,6 +52,17 @@ pub struct FrontEndHandler {
     authenticators: HashMap<AuthType, Box<dyn Authenticate + Send + Sync>>,
     /// Value used to limit the size of the request body to be that can be accepted by the service.
     body_len_limit: usize,
+    /// Memory that requests being handled can hold.
+    memory_budget: MemoryBudget,
+    /// Backoff applied to the peers failing to authenticate.
+    auth_throttle: Option<AuthThrottle>,
+    /// Size from which the response bodies are compressed for the clients asking for it.
+    #[cfg(feature = "compression")]
+    response_compression_threshold: Option<usize>,
+    /// Tenants isolating the applications of different orchestrators.
+    tenants: Option<Tenants>,
+    /// Hooks told of the failed authentications.
+    event_hooks: Option<EventHooks>,
 }
 
 impl FrontEndHandler {
@@ -44,11 +77,17 @@ impl FrontEndHandler {
         trace!("handle_request ingress");
         // Read bytes from stream
         // De-Serialise bytes into a request
-        let request = match Request::read_from_stream(&mut connection.stream, self.body_len_limit) {
+        // The memory held by the request is accounted for until its response is sent.
+        let (request, framing, mut reservation) = match wire_protocol::read_request(
+            &mut connection.stream,
+            self.body_len_limit,
+            &self.memory_budget,
+        ) {
             Ok(request) => request,
             Err(status) => {
                 format_error!("Failed to read request", status);
 
+                ServiceStatus::record_request(false);
                 let response = Response::from_status(status);
                 if response.header.status != ResponseStatus::Success {
                     format_error!("Sending back an error", response.header.status);
@@ -60,20 +99,93 @@ impl FrontEndHandler {
             }
         };
 
+        // The session field of the header is not used for sessions, clients put in it the
+        // correlation ID of the request in their own logs. It is echoed in the response.
+        let _correlation = CorrelationScope::enter(match request.header.session {
+            0 => None,
+            session => Some(session),
+        });
+
+        #[cfg(feature = "cbor-bodies")]
+        let request = if framing.cbor_bodies {
+            match cbor_bodies::request_body(request.body.bytes(), request.header.opcode) {
+                Ok(body) => Request { body, ..request },
+                Err(status) => {
+                    ServiceStatus::record_request(false);
+                    let response = Response::from_request_header(request.header, status);
+                    if let Err(status) =
+                        self.write_response(response, framing, &mut connection.stream)
+                    {
+                        format_error!("Failed to write response", status);
+                    }
+                    return;
+                }
+            }
+        } else {
+            request
+        };
+
         // Check if the request was sent without authentication
         let (app, err_response) = if AuthType::NoAuth == request.header.auth_type {
             (None, None)
+        // Requests on a restricted endpoint can only use its authenticator
+        } else if connection
+            .auth_type
+            .map_or(false, |auth_type| auth_type != request.header.auth_type)
+        {
+            (
+                None,
+                Some(Response::from_request_header(
+                    request.header,
+                    ResponseStatus::AuthenticatorNotRegistered,
+                )),
+            )
         // Otherwise find an authenticator that is capable to authenticate the request
         } else if let Some(authenticator) = self.authenticators.get(&request.header.auth_type) {
-            // Authenticate the request
-            match authenticator.authenticate(&request.auth, connection.metadata) {
-                // Send the request to the dispatcher
-                // Get a response back
-                Ok(app) => (Some(app), None),
-                Err(status) => (
+            // Finding the container of the peer reads its cgroups: only done when it is used.
+            let peer = if self.auth_throttle.is_some() || self.event_hooks.is_some() {
+                Peer::from_metadata(connection.metadata.as_ref())
+            } else {
+                None
+            };
+            let auth_type = request.header.auth_type;
+            let throttle = self.auth_throttle.as_ref().zip(peer.as_ref());
+            // Peers which failed too often are not authenticated until their backoff ends
+            if throttle.map_or(false, |(throttle, peer)| !throttle.check(peer, auth_type)) {
+                (
                     None,
-                    Some(Response::from_request_header(request.header, status)),
-                ),
+                    Some(Response::from_request_header(
+                        request.header,
+                        ResponseStatus::AuthenticationError,
+                    )),
+                )
+            } else {
+                // Authenticate the request
+                match authenticator.authenticate(&request.auth, connection.metadata) {
+                    // Send the request to the dispatcher
+                    // Get a response back
+                    Ok(app) => {
+                        if let Some((throttle, peer)) = throttle {
+                            throttle.record_success(peer);
+                        }
+                        (Some(app), None)
+                    }
+                    Err(status) => {
+                        if let Some((throttle, peer)) = throttle {
+                            let _ = throttle.record_failure(peer, auth_type);
+                        }
+                        if let Some(event_hooks) = &self.event_hooks {
+                            event_hooks.notify(Event::authentication_failed(peer.map_or_else(
+                                || String::from("unknown peer"),
+                                |peer| peer.to_string(),
+                            )));
+                        }
+                        (
+                            None,
+                            Some(Response::from_request_header(request.header, status)),
+                        )
+                    }
+                }
             }
         } else {
             (
@@ -85,6 +197,18 @@ impl FrontEndHandler {
             )
         };
 
+        // Applications of a tenant are only known in its namespace.
+        let (app, err_response) = match (app, &self.tenants) {
+            (Some(app), Some(tenants)) => match tenants.admit(app, request.header.provider) {
+                Ok(app) => (Some(app), err_response),
+                Err(status) => (
+                    None,
+                    Some(Response::from_request_header(request.header, status)),
+                ),
+            },
+            (app, _) => (app, err_response),
+        };
+
         let response = if let Some(err_response) = err_response {
             err_response
         } else {
@@ -92,7 +216,7 @@ impl FrontEndHandler {
                 if let Some(app) = &app.as_ref() {
                     info!(
                         "New request received from application name \"{}\"",
-                        app.identity().name()
+                        Redacted(app.identity().name())
                     )
                 } else {
                     info!("New request received without authentication")
@@ -103,15 +227,18 @@ impl FrontEndHandler {
             response
         };
 
+        ServiceStatus::record_request(response.header.status == ResponseStatus::Success);
+        reservation.add(response.body.len());
+
         // Serialise the response into bytes
         // Write bytes to stream
-        match response.write_to_stream(&mut connection.stream) {
+        match self.write_response(response, framing, &mut connection.stream) {
             Ok(_) => {
                 if crate::utils::GlobalConfig::log_error_details() {
                     if let Some(app) = app {
                         info!(
                             "Response for application name \"{}\" sent back",
-                            app.identity().name()
+                            Redacted(app.identity().name())
                         );
                     } else {
                         info!("Response sent back from request without authentication");
@@ -121,6 +248,36 @@ impl FrontEndHandler {
             Err(err) => format_error!("Failed to send response", err),
         }
     }
+
+    /// Write the response to the stream, in the framing the request asked for.
+    #[cfg_attr(
+        not(any(feature = "cbor-bodies", feature = "compression")),
+        allow(unused_variables)
+    )]
+    fn write_response<W: Write>(
+        &self,
+        response: Response,
+        framing: RequestFraming,
+        stream: &mut W,
+    ) -> std::result::Result<(), ResponseStatus> {
+        #[cfg(feature = "cbor-bodies")]
+        if framing.cbor_bodies {
+            return cbor_bodies::write_response(response, stream);
+        }
+        #[cfg(feature = "compression")]
+        if let Some(threshold) = self.response_compression_threshold {
+            if framing.flags & FLAG_COMPRESSION != 0 {
+                return compression::write_response(response, stream, threshold);
+            }
+        }
+        response.write_to_stream(stream)
+    }
+
+    /// Whether the requests being handled hold all the memory allowed. New connections should not
+    /// be accepted until this is false again.
+    pub fn is_memory_exhausted(&self) -> bool {
+        self.memory_budget.is_exhausted()
+    }
 }
 
 /// Builder for `FrontEndHandler`
@@ -131,6 +288,12 @@ pub struct FrontEndHandlerBuilder {
     #[derivative(Debug = "ignore")]
     authenticators: Option<HashMap<AuthType, Box<dyn Authenticate + Send + Sync>>>,
     body_len_limit: Option<usize>,
+    in_flight_memory_limit: Option<usize>,
+    auth_throttle: Option<AuthThrottle>,
+    #[cfg(feature = "compression")]
+    response_compression_threshold: Option<usize>,
+    tenants: Option<Tenants>,
+    event_hooks: Option<EventHooks>,
 }
 
 impl FrontEndHandlerBuilder {
@@ -140,6 +303,12 @@ impl FrontEndHandlerBuilder {
             dispatcher: None,
             authenticators: None,
             body_len_limit: None,
+            in_flight_memory_limit: None,
+            auth_throttle: None,
+            #[cfg(feature = "compression")]
+            response_compression_threshold: None,
+            tenants: None,
+            event_hooks: None,
         }
     }
 
@@ -175,6 +344,37 @@ impl FrontEndHandlerBuilder {
         self
     }
 
+    /// Set a limit on the memory held by the requests being handled
+    pub fn with_in_flight_memory_limit(mut self, in_flight_memory_limit: usize) -> Self {
+        self.in_flight_memory_limit = Some(in_flight_memory_limit);
+        self
+    }
+
+    /// Throttle the peers repeatedly failing to authenticate
+    pub fn with_auth_throttle(mut self, auth_throttle: AuthThrottle) -> Self {
+        self.auth_throttle = Some(auth_throttle);
+        self
+    }
+
+    /// Compress the response bodies of at least the given size, for the clients asking for it
+    #[cfg(feature = "compression")]
+    pub fn with_response_compression_threshold(mut self, threshold: usize) -> Self {
+        self.response_compression_threshold = Some(threshold);
+        self
+    }
+
+    /// Isolate the applications of the tenants
+    pub fn with_tenants(mut self, tenants: Tenants) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    /// Report the failed authentications to the hooks
+    pub fn with_event_hooks(mut self, event_hooks: EventHooks) -> Self {
+        self.event_hooks = Some(event_hooks);
+        self
+    }
+
     /// Build into a FrontEndHandler
     pub fn build(self) -> Result<FrontEndHandler> {
         Ok(FrontEndHandler {
@@ -187,6 +387,15 @@ impl FrontEndHandlerBuilder {
             body_len_limit: self
                 .body_len_limit
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "body_len_limit is missing"))?,
+            memory_budget: self
+                .in_flight_memory_limit
+                .map(MemoryBudget::new)
+                .unwrap_or_else(MemoryBudget::unlimited),
+            auth_throttle: self.auth_throttle,
+            #[cfg(feature = "compression")]
+            response_compression_threshold: self.response_compression_threshold,
+            tenants: self.tenants,
+            event_hooks: self.event_hooks,
         })
     }
 }
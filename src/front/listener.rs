@@ -6,6 +6,7 @@
 //! trait acts as an interface for the operations that must be supported by any implementation
 //! of the IPC mechanism used as a Parsec front.
 use derivative::Derivative;
+use parsec_interface::requests::AuthType;
 use std::time::Duration;
 
 /// This trait is created to allow the iterator returned by incoming to iterate over a trait object
@@ -15,7 +16,7 @@ pub trait ReadWrite: std::io::Read + std::io::Write {}
 impl<T: std::io::Read + std::io::Write> ReadWrite for T {}
 
 /// Specifies metadata associated with a connection, if any.
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum ConnectionMetadata {
     /// Unix peer credentials metadata for Unix domain sockets.
     UnixPeerCredentials {
@@ -26,6 +27,9 @@ pub enum ConnectionMetadata {
         /// The optional PID of the connecting process. This is an Option<u32> because not all
         /// platforms support retrieving PID via a domain socket.
         pid: Option<i32>,
+        /// The SELinux context or AppArmor label of the connecting process, if a security module
+        /// gives one for the socket.
+        security_context: Option<String>,
     },
     // NOTE: there is currently only _one_ variant of the ConnectionMetadata enum. When a second
     //       variant is added, you will need to update some tests!
@@ -41,6 +45,8 @@ pub struct Connection {
     pub stream: Box<dyn ReadWrite + Send>,
     /// Metadata associated with the connection that might be useful elsewhere (i.e. authentication, etc)
     pub metadata: Option<ConnectionMetadata>,
+    /// Only authenticator allowed on the endpoint the connection was accepted on, if restricted
+    pub auth_type: Option<AuthType>,
 }
 
 /// IPC front manager interface
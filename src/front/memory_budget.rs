@@ -0,0 +1,134 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Accounting of the memory held by in-flight requests
+//!
+//! Every request reserves the size of its body from a shared budget when its header is read,
+//! before the body is, and the size of its response body once it is computed; both are released
+//! when the response has been sent.
+//! New requests are rejected while the budget is exhausted, and the service stops accepting
+//! connections until enough in-flight requests complete.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Budget of memory shared by the requests being handled
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    in_use: AtomicUsize,
+}
+
+impl MemoryBudget {
+    /// Create a budget of `limit` bytes.
+    pub fn new(limit: usize) -> Self {
+        MemoryBudget {
+            limit,
+            in_use: AtomicUsize::new(0),
+        }
+    }
+
+    /// Create a budget that is never exhausted.
+    pub fn unlimited() -> Self {
+        MemoryBudget::new(usize::MAX)
+    }
+
+    /// Reserve `size` bytes, if it does not take the memory in use over the limit.
+    pub fn reserve(&self, size: usize) -> Option<Reservation<'_>> {
+        self.in_use
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_use| {
+                in_use
+                    .checked_add(size)
+                    .filter(|total| *total <= self.limit)
+            })
+            .ok()
+            .map(|_| Reservation { budget: self, size })
+    }
+
+    /// Whether the memory in use has reached the limit
+    pub fn is_exhausted(&self) -> bool {
+        self.in_use.load(Ordering::Acquire) >= self.limit
+    }
+
+    /// Number of bytes currently reserved
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Acquire)
+    }
+}
+
+/// Memory reserved by a request, released when dropped
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    size: usize,
+}
+
+impl Reservation<'_> {
+    /// Account for `size` more bytes, which are already allocated. This can take the memory in
+    /// use over the limit.
+    pub fn add(&mut self, size: usize) {
+        let _ = self.budget.in_use.fetch_add(size, Ordering::AcqRel);
+        self.size += size;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let _ = self.budget.in_use.fetch_sub(self.size, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reservations_are_bounded() {
+        let budget = MemoryBudget::new(100);
+        let _first = budget.reserve(60).unwrap();
+        assert!(budget.reserve(50).is_none());
+        assert!(budget.reserve(40).is_some());
+        assert_eq!(budget.in_use(), 60);
+    }
+
+    #[test]
+    fn reservations_are_released_when_dropped() {
+        let budget = MemoryBudget::new(100);
+        let first = budget.reserve(60).unwrap();
+        let second = budget.reserve(40).unwrap();
+        assert_eq!(budget.in_use(), 100);
+        drop(first);
+        assert_eq!(budget.in_use(), 40);
+        drop(second);
+        assert_eq!(budget.in_use(), 0);
+        assert!(budget.reserve(100).is_some());
+    }
+
+    #[test]
+    fn responses_can_exceed_the_limit() {
+        let budget = MemoryBudget::new(100);
+        let mut request = budget.reserve(60).unwrap();
+        request.add(80);
+        assert_eq!(budget.in_use(), 140);
+        assert!(budget.is_exhausted());
+        // Nothing more fits until the response is sent.
+        assert!(budget.reserve(1).is_none());
+        drop(request);
+        assert_eq!(budget.in_use(), 0);
+        assert!(!budget.is_exhausted());
+    }
+
+    #[test]
+    fn empty_requests_fit_a_full_budget() {
+        let budget = MemoryBudget::new(100);
+        let _full = budget.reserve(100).unwrap();
+        assert!(budget.is_exhausted());
+        assert!(budget.reserve(0).is_some());
+    }
+
+    #[test]
+    fn unlimited_budget() {
+        let budget = MemoryBudget::unlimited();
+        let _large = budget.reserve(usize::MAX / 2).unwrap();
+        assert!(!budget.is_exhausted());
+        // The sum of the reservations can not overflow.
+        assert!(budget.reserve(usize::MAX).is_none());
+    }
+}
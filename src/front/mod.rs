@@ -1,6 +1,16 @@
 // Copyright 2019 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
 //! IPC front handlers
+pub mod auth_throttle;
+#[cfg(feature = "cbor-bodies")]
+mod cbor_bodies;
+#[cfg(feature = "compression")]
+mod compression;
+#[cfg(feature = "dbus-interface")]
+pub mod dbus;
 pub mod domain_socket;
 pub mod front_end;
 pub mod listener;
+pub mod memory_budget;
+pub mod stdio;
+pub mod wire_protocol;
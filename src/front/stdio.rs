@@ -0,0 +1,161 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Service front using the standard input and output
+//!
+//! In this inetd-style mode, the service does not create any socket: it reads a single request
+//! from its standard input, writes the response to its standard output and exits. When the
+//! standard input is a Unix Domain Socket, as when started by inetd or by a systemd socket unit
+//! with `Accept=yes`, the connection is used directly and its peer credentials are given to the
+//! authenticators.
+use super::domain_socket::peer_credentials;
+use super::listener::{Connection, ConnectionMetadata};
+use std::io::{Read, Result, Stdin, Stdout, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+const STDIN_FD: RawFd = 0;
+
+/// Standard input and output used as a single stream
+#[derive(Debug)]
+struct StdioStream {
+    stdin: Stdin,
+    stdout: Stdout,
+}
+
+impl Read for StdioStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stdin.read(buf)
+    }
+}
+
+impl Write for StdioStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stdout.flush()
+    }
+}
+
+fn is_socket(fd: RawFd) -> bool {
+    // Safe as the stat structure is only read if fstat succeeded.
+    unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        libc::fstat(fd, &mut stat) == 0 && (stat.st_mode & libc::S_IFMT) == libc::S_IFSOCK
+    }
+}
+
+/// Get the connection to the client on the standard input and output. The timeout only applies
+/// when they are a socket.
+pub fn connection(timeout: Duration) -> Result<Connection> {
+    if !is_socket(STDIN_FD) {
+        return Ok(Connection {
+            stream: Box::new(StdioStream {
+                stdin: std::io::stdin(),
+                stdout: std::io::stdout(),
+            }),
+            metadata: None,
+            auth_type: None,
+        });
+    }
+
+    // Safe as the standard input is an open socket, and nothing else in the service uses it.
+    socket_connection(unsafe { UnixStream::from_raw_fd(STDIN_FD) }, timeout)
+}
+
+fn socket_connection(stream: UnixStream, timeout: Duration) -> Result<Connection> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    // The socket might not be a Unix Domain Socket, in which case there are no peer credentials.
+    let metadata = peer_credentials::peer_cred(&stream).ok().map(|ucred| {
+        ConnectionMetadata::UnixPeerCredentials {
+            uid: ucred.uid,
+            gid: ucred.gid,
+            pid: ucred.pid,
+            security_context: peer_credentials::peer_security_context(&stream)
+                .ok()
+                .flatten(),
+        }
+    });
+
+    Ok(Connection {
+        stream: Box::new(stream),
+        metadata,
+        auth_type: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_socket, socket_connection};
+    use crate::front::listener::ConnectionMetadata;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    #[test]
+    fn sockets_are_detected() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        assert!(is_socket(stream.as_raw_fd()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(is_socket(stream.as_raw_fd()));
+    }
+
+    #[test]
+    fn other_files_are_not_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = File::create(dir.path().join("request")).unwrap();
+        assert!(!is_socket(file.as_raw_fd()));
+        assert!(!is_socket(File::open("/dev/null").unwrap().as_raw_fd()));
+        assert!(!is_socket(-1));
+    }
+
+    #[test]
+    fn socket_gives_peer_credentials() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        let connection = socket_connection(stream, Duration::from_secs(1)).unwrap();
+        assert!(connection.auth_type.is_none());
+        match connection.metadata {
+            Some(ConnectionMetadata::UnixPeerCredentials { uid, gid, pid, .. }) => {
+                // Safety: those functions have no preconditions and cannot fail.
+                assert_eq!(uid, unsafe { libc::geteuid() });
+                assert_eq!(gid, unsafe { libc::getegid() });
+                assert_eq!(pid, Some(std::process::id() as i32));
+            }
+            None => panic!("No peer credentials for a Unix Domain Socket"),
+        }
+    }
+
+    #[test]
+    fn socket_is_used_as_stream() {
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        let mut connection = socket_connection(stream, Duration::from_secs(1)).unwrap();
+        peer.write_all(b"request").unwrap();
+        let mut request = [0; 7];
+        connection.stream.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"request");
+
+        connection.stream.write_all(b"response").unwrap();
+        let mut response = [0; 8];
+        peer.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"response");
+    }
+
+    #[test]
+    fn socket_timeout_applies() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        let mut connection = socket_connection(stream, Duration::from_millis(10)).unwrap();
+        let error = connection.stream.read(&mut [0; 1]).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ));
+    }
+}
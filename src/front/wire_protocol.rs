@@ -0,0 +1,339 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Negotiation of the wire protocol version
+//!
+//! Clients learn the version of the wire protocol spoken by the service from the response to a Ping
+//! request. Requests of a later minor version of the same major version are accepted: their header
+//! has the same layout, and the response is sent with the version of the service, which tells the
+//! client which minor version to use. Requests of another major version are answered with
+//! `WireProtocolVersionNotSupported`, in a header of the version of the service.
+//!
+//! The optional features supported by the service are only logged, along with its version, when
+//! such a request is refused: neither the Ping response nor the header has a place for them, so
+//! clients can not learn them until the protocol carries them. Features a client wants for a
+//! request are asked for with the flags of the request header, which `parsec-interface` ignores.
+//! Requests asking for compressed responses are refused with `AcceptTypeNotSupported` when the
+//! `compression` feature is not compiled in.
+//! The CBOR bodies are instead asked for with the content and accept types of the request header.
+use super::memory_budget::{MemoryBudget, Reservation};
+use log::{debug, error, warn};
+#[cfg(feature = "cbor-bodies")]
+use parsec_interface::requests::BodyType;
+use parsec_interface::requests::{Request, ResponseStatus, Result};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Cursor, Read};
+
+/// Major version of the wire protocol spoken by the service
+pub const VERSION_MAJ: u8 = 1;
+/// Minor version of the wire protocol spoken by the service
+pub const VERSION_MIN: u8 = 0;
+
+/// Large response bodies compressed for the clients accepting it
+pub const FEATURE_COMPRESSION: u32 = 1 << 0;
+/// Request and response bodies encoded in CBOR
+pub const FEATURE_CBOR_BODIES: u32 = 1 << 1;
+/// Optional features supported by the service
+pub const SUPPORTED_FEATURES: u32 = (if cfg!(feature = "compression") {
+    FEATURE_COMPRESSION
+} else {
+    0
+}) | (if cfg!(feature = "cbor-bodies") {
+    FEATURE_CBOR_BODIES
+} else {
+    0
+});
+
+const FEATURE_NAMES: [(u32, &str); 2] = [
+    (FEATURE_COMPRESSION, "compression"),
+    (FEATURE_CBOR_BODIES, "CBOR bodies"),
+];
+
+/// Flag of the header of requests accepting a compressed response, and of compressed responses
+pub const FLAG_COMPRESSION: u16 = 1 << 0;
+
+/// Content and accept type of the bodies encoded in CBOR, which `parsec-interface` does not know
+pub const BODY_TYPE_CBOR: u8 = 1;
+
+/// Magic number starting the requests
+const MAGIC_NUMBER: u32 = 0x5EC0_A710;
+/// Bytes of the header before the version: magic number and header size
+const VERSION_OFFSET: usize = 6;
+/// Offset of the flags in the header
+const FLAGS_OFFSET: usize = 8;
+/// Offset of the content type in the header, followed by the accept type
+#[cfg(feature = "cbor-bodies")]
+pub(crate) const CONTENT_TYPE_OFFSET: usize = 19;
+/// Offset of the body length in the header
+const BODY_LEN_OFFSET: usize = 22;
+
+/// Version and optional features of the wire protocol
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WireCapabilities {
+    /// Major version
+    pub version_maj: u8,
+    /// Minor version
+    pub version_min: u8,
+    /// Optional features, as a combination of the `FEATURE_` flags
+    pub features: u32,
+}
+
+impl WireCapabilities {
+    /// Capabilities of the service
+    pub fn supported() -> Self {
+        WireCapabilities {
+            version_maj: VERSION_MAJ,
+            version_min: VERSION_MIN,
+            features: SUPPORTED_FEATURES,
+        }
+    }
+
+    /// Whether the given feature is supported
+    pub fn supports(&self, feature: u32) -> bool {
+        self.features & feature == feature
+    }
+}
+
+impl fmt::Display for WireCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.version_maj, self.version_min)?;
+        let features: Vec<&str> = FEATURE_NAMES
+            .iter()
+            .filter(|(feature, _)| self.supports(*feature))
+            .map(|(_, name)| *name)
+            .collect();
+        if features.is_empty() {
+            write!(f, " (no optional features)")
+        } else {
+            write!(f, " ({})", features.join(", "))
+        }
+    }
+}
+
+/// What the header of a request tells of the way to answer it, beyond what `parsec-interface`
+/// reads
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RequestFraming {
+    /// Flags of the header
+    pub flags: u16,
+    /// Whether the request and response bodies are encoded in CBOR. The content and accept types
+    /// of the request are then given to `parsec-interface` as protobuf.
+    pub cbor_bodies: bool,
+}
+
+/// Read a request from the stream, negotiating the version of the wire protocol, and return it
+/// with its framing and the memory reserved for it.
+///
+/// The size of the body is reserved from the budget once the header gives it, before the body is
+/// read: requests which do not fit are refused with `PsaErrorInsufficientMemory` without reading
+/// their body.
+pub(crate) fn read_request<'a, R: Read>(
+    stream: &mut R,
+    body_len_limit: usize,
+    memory_budget: &'a MemoryBudget,
+) -> Result<(Request, RequestFraming, Reservation<'a>)> {
+    let mut prefix = [0; BODY_LEN_OFFSET + 4];
+    stream.read_exact(&mut prefix)?;
+    let (version_maj, version_min) = (prefix[VERSION_OFFSET], prefix[VERSION_OFFSET + 1]);
+    // Streams which are not Parsec requests are rejected when reading the header.
+    let is_request = prefix[..4] == MAGIC_NUMBER.to_le_bytes();
+    if is_request && version_maj != VERSION_MAJ {
+        error!(
+            "Wire protocol version {}.{} is not supported, the service speaks {}.",
+            version_maj,
+            version_min,
+            WireCapabilities::supported()
+        );
+        return Err(ResponseStatus::WireProtocolVersionNotSupported);
+    }
+    if is_request && version_min > VERSION_MIN {
+        debug!(
+            "Request of wire protocol version {}.{} answered with version {}.{}.",
+            version_maj, version_min, VERSION_MAJ, VERSION_MIN
+        );
+        prefix[VERSION_OFFSET + 1] = VERSION_MIN;
+    }
+    let framing = RequestFraming {
+        flags: u16::from_le_bytes([prefix[FLAGS_OFFSET], prefix[FLAGS_OFFSET + 1]]),
+        cbor_bodies: is_request && cbor_bodies(&mut prefix)?,
+    };
+    if is_request
+        && framing.flags & FLAG_COMPRESSION != 0
+        && !WireCapabilities::supported().supports(FEATURE_COMPRESSION)
+    {
+        error!("Compressed responses are asked for but compression was not compiled in the Parsec binary.");
+        return Err(ResponseStatus::AcceptTypeNotSupported);
+    }
+    let body_len = usize::try_from(u32::from_le_bytes([
+        prefix[BODY_LEN_OFFSET],
+        prefix[BODY_LEN_OFFSET + 1],
+        prefix[BODY_LEN_OFFSET + 2],
+        prefix[BODY_LEN_OFFSET + 3],
+    ]))?;
+    // Streams which are not requests and bodies over the limit are refused by parsec-interface,
+    // without reading the body either, whatever the budget.
+    let reservation = if is_request && body_len <= body_len_limit {
+        Some(memory_budget.reserve(body_len).ok_or_else(|| {
+            warn!("Limit on the memory held by in-flight requests reached, rejecting the request.");
+            ResponseStatus::PsaErrorInsufficientMemory
+        })?)
+    } else {
+        None
+    };
+    let request =
+        Request::read_from_stream(&mut Cursor::new(prefix).chain(stream), body_len_limit)?;
+    let reservation = reservation.ok_or_else(|| {
+        error!("Request read without reserving the memory of its body.");
+        ResponseStatus::InvalidHeader
+    })?;
+    Ok((request, framing, reservation))
+}
+
+/// Whether the bodies of the request whose header starts with the prefix are encoded in CBOR, in
+/// which case its content and accept types are replaced by protobuf. CBOR is used for both
+/// bodies or for none.
+#[cfg(feature = "cbor-bodies")]
+fn cbor_bodies(prefix: &mut [u8]) -> Result<bool> {
+    let (content_type, accept_type) =
+        (prefix[CONTENT_TYPE_OFFSET], prefix[CONTENT_TYPE_OFFSET + 1]);
+    match (
+        content_type == BODY_TYPE_CBOR,
+        accept_type == BODY_TYPE_CBOR,
+    ) {
+        (false, false) => Ok(false),
+        (true, true) => {
+            prefix[CONTENT_TYPE_OFFSET] = BodyType::Protobuf as u8;
+            prefix[CONTENT_TYPE_OFFSET + 1] = BodyType::Protobuf as u8;
+            Ok(true)
+        }
+        (true, false) => {
+            error!("Requests with a CBOR body must accept a CBOR response body.");
+            Err(ResponseStatus::AcceptTypeNotSupported)
+        }
+        (false, true) => {
+            error!("Responses with a CBOR body are only sent to requests with a CBOR body.");
+            Err(ResponseStatus::ContentTypeNotSupported)
+        }
+    }
+}
+
+#[cfg(not(feature = "cbor-bodies"))]
+fn cbor_bodies(_prefix: &mut [u8]) -> Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::requests::Opcode;
+
+    fn ping_request(version_maj: u8, version_min: u8) -> Vec<u8> {
+        let mut request = MAGIC_NUMBER.to_le_bytes().to_vec();
+        request.extend_from_slice(&30_u16.to_le_bytes());
+        request.extend_from_slice(&[version_maj, version_min]);
+        let mut header = [0; 28];
+        header[20..24].copy_from_slice(&(Opcode::Ping as u32).to_le_bytes());
+        request.extend_from_slice(&header);
+        request
+    }
+
+    #[test]
+    fn later_minor_versions_are_accepted() {
+        let budget = MemoryBudget::unlimited();
+        let (request, framing, _) =
+            read_request(&mut ping_request(1, 0).as_slice(), 1024, &budget).unwrap();
+        assert_eq!(request.header.opcode, Opcode::Ping);
+        assert_eq!(framing.flags, 0);
+        assert!(!framing.cbor_bodies);
+        let (request, ..) =
+            read_request(&mut ping_request(1, 3).as_slice(), 1024, &budget).unwrap();
+        assert_eq!(request.header.opcode, Opcode::Ping);
+        assert_eq!(
+            read_request(&mut ping_request(2, 0).as_slice(), 1024, &budget).unwrap_err(),
+            ResponseStatus::WireProtocolVersionNotSupported
+        );
+    }
+
+    #[test]
+    fn compressed_responses_are_asked_for_with_the_flags() {
+        let budget = MemoryBudget::unlimited();
+        let mut request = ping_request(1, 0);
+        request[FLAGS_OFFSET..FLAGS_OFFSET + 2].copy_from_slice(&FLAG_COMPRESSION.to_le_bytes());
+        let framing =
+            read_request(&mut request.as_slice(), 1024, &budget).map(|(_, framing, _)| framing);
+        #[cfg(feature = "compression")]
+        assert_eq!(framing.unwrap().flags, FLAG_COMPRESSION);
+        #[cfg(not(feature = "compression"))]
+        assert_eq!(framing.unwrap_err(), ResponseStatus::AcceptTypeNotSupported);
+    }
+
+    #[test]
+    fn bodies_are_reserved_before_being_read() {
+        let mut request = ping_request(1, 0);
+        request[BODY_LEN_OFFSET..BODY_LEN_OFFSET + 4].copy_from_slice(&100_u32.to_le_bytes());
+        request.extend_from_slice(&[0; 100]);
+        let budget = MemoryBudget::new(150);
+        let (_, _, reservation) = read_request(&mut request.as_slice(), 1024, &budget).unwrap();
+        assert_eq!(budget.in_use(), 100);
+
+        // Only the start of the header is read from the stream of a request over the budget.
+        let mut stream = request.as_slice();
+        assert_eq!(
+            read_request(&mut stream, 1024, &budget).unwrap_err(),
+            ResponseStatus::PsaErrorInsufficientMemory
+        );
+        assert_eq!(stream.len(), request.len() - (BODY_LEN_OFFSET + 4));
+        drop(reservation);
+        assert_eq!(budget.in_use(), 0);
+        assert!(read_request(&mut request.as_slice(), 1024, &budget).is_ok());
+    }
+
+    #[cfg(feature = "cbor-bodies")]
+    #[test]
+    fn cbor_bodies_are_negotiated() {
+        let mut request = ping_request(1, 0);
+        request[CONTENT_TYPE_OFFSET..CONTENT_TYPE_OFFSET + 2]
+            .copy_from_slice(&[BODY_TYPE_CBOR, BODY_TYPE_CBOR]);
+        let budget = MemoryBudget::unlimited();
+        let (request, framing, _) = read_request(&mut request.as_slice(), 1024, &budget).unwrap();
+        assert_eq!(request.header.content_type, BodyType::Protobuf);
+        assert!(framing.cbor_bodies);
+
+        let mut request = ping_request(1, 0);
+        request[CONTENT_TYPE_OFFSET] = BODY_TYPE_CBOR;
+        assert_eq!(
+            read_request(&mut request.as_slice(), 1024, &budget).unwrap_err(),
+            ResponseStatus::AcceptTypeNotSupported
+        );
+    }
+
+    #[test]
+    fn capabilities_are_displayed() {
+        let supported = WireCapabilities::supported();
+        assert_eq!(
+            supported.supports(FEATURE_COMPRESSION),
+            cfg!(feature = "compression")
+        );
+        assert_eq!(
+            supported.supports(FEATURE_CBOR_BODIES),
+            cfg!(feature = "cbor-bodies")
+        );
+        let capabilities = WireCapabilities {
+            features: FEATURE_COMPRESSION | FEATURE_CBOR_BODIES,
+            ..supported
+        };
+        assert_eq!(capabilities.to_string(), "1.0 (compression, CBOR bodies)");
+        let capabilities = WireCapabilities {
+            features: FEATURE_CBOR_BODIES,
+            ..supported
+        };
+        assert!(capabilities.supports(FEATURE_CBOR_BODIES));
+        assert!(!capabilities.supports(FEATURE_COMPRESSION));
+        assert_eq!(capabilities.to_string(), "1.0 (CBOR bodies)");
+        let capabilities = WireCapabilities {
+            features: 0,
+            ..capabilities
+        };
+        assert_eq!(capabilities.to_string(), "1.0 (no optional features)");
+    }
+}
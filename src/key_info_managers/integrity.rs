@@ -0,0 +1,387 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Integrity protection of the stored mappings
+//!
+//! When a Key Info Manager is given an integrity key, an HMAC-SHA256 tag is appended to the key ID
+//! of every mapping it stores. The tag covers the identity of the key (application, provider and
+//! key name) as well as its ID and attributes, so that a mapping edited, or moved to another
+//! application or key, in the storage of the Key Info Manager is rejected when it is read. The
+//! authenticator of the application is not covered for the on-disk manager, which does not store
+//! it.
+//!
+//! The integrity key is read from a file only accessible to the service, of at least 32 bytes.
+use super::{KeyIdentity, KeyInfo};
+use anyhow::Result;
+#[cfg(feature = "kim-integrity")]
+use log::error;
+#[cfg(feature = "kim-integrity")]
+use parsec_interface::requests::ResponseStatus;
+#[cfg(feature = "kim-integrity")]
+use ring::hmac;
+#[cfg(not(feature = "kim-integrity"))]
+use std::io::{Error, ErrorKind};
+
+#[cfg(feature = "kim-integrity")]
+const MIN_KEY_LEN: usize = 32;
+#[cfg(feature = "kim-integrity")]
+const TAG_LEN: usize = 32;
+
+/// Key protecting the mappings of a Key Info Manager
+#[cfg(feature = "kim-integrity")]
+#[derive(Debug)]
+pub(super) struct RecordIntegrity {
+    key: hmac::Key,
+}
+
+/// Integrity protection is not compiled in: no key can be created.
+#[cfg(not(feature = "kim-integrity"))]
+#[derive(Debug)]
+pub(super) enum RecordIntegrity {}
+
+#[cfg(feature = "kim-integrity")]
+impl RecordIntegrity {
+    /// Read the integrity key from a file.
+    pub(super) fn new(key_path: &str) -> Result<Self> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(key_path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            error!(
+                "The Key Info Manager integrity key {} can be accessed by other users.",
+                key_path
+            );
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "integrity key accessible by other users",
+            )
+            .into());
+        }
+        let secret = zeroize::Zeroizing::new(std::fs::read(key_path)?);
+        if secret.len() < MIN_KEY_LEN {
+            error!(
+                "The Key Info Manager integrity key must be at least {} bytes long.",
+                MIN_KEY_LEN
+            );
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "integrity key too short",
+            )
+            .into());
+        }
+        Ok(RecordIntegrity {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &secret),
+        })
+    }
+
+    fn tag(
+        &self,
+        key_identity: &KeyIdentity,
+        with_auth_type: bool,
+        key_info: &KeyInfo,
+    ) -> hmac::Tag {
+        let application = key_identity.application();
+        let provider = key_identity.provider();
+        let auth_type = if with_auth_type {
+            Some(*application.authenticator_id() as u8)
+        } else {
+            None
+        };
+        // Serialized together so that the boundaries between the fields are authenticated.
+        let message = bincode::serialize(&(
+            application.name(),
+            auth_type,
+            provider.uuid(),
+            provider.name(),
+            key_identity.key_name(),
+            &key_info.id,
+            &key_info.attributes,
+        ))
+        .expect("Failed to serialize a mapping");
+        hmac::sign(&self.key, &message)
+    }
+
+    /// Append the tag of the mapping to its key ID.
+    pub(super) fn protect(
+        &self,
+        key_identity: &KeyIdentity,
+        with_auth_type: bool,
+        key_info: &mut KeyInfo,
+    ) {
+        let tag = self.tag(key_identity, with_auth_type, key_info);
+        key_info.id.extend_from_slice(tag.as_ref());
+    }
+
+    /// Check the tag of a stored mapping and return its key ID, without the tag.
+    pub(super) fn verify<'a>(
+        &self,
+        key_identity: &KeyIdentity,
+        with_auth_type: bool,
+        key_info: &'a KeyInfo,
+    ) -> parsec_interface::requests::Result<&'a [u8]> {
+        let verified = key_info
+            .id
+            .len()
+            .checked_sub(TAG_LEN)
+            .map(|id_len| key_info.id.split_at(id_len))
+            .filter(|(id, tag)| {
+                let unprotected = KeyInfo {
+                    id: id.to_vec(),
+                    attributes: key_info.attributes,
+                };
+                let expected = self.tag(key_identity, with_auth_type, &unprotected);
+                ring::constant_time::verify_slices_are_equal(expected.as_ref(), tag).is_ok()
+            });
+        match verified {
+            Some((id, _)) => Ok(id),
+            None => {
+                format_error!(
+                    "The integrity check of a stored mapping failed",
+                    key_identity
+                );
+                Err(ResponseStatus::PsaErrorDataCorrupt)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "kim-integrity"))]
+impl RecordIntegrity {
+    pub(super) fn new(_key_path: &str) -> Result<Self> {
+        log::error!("A Key Info Manager integrity key was configured but the \"kim-integrity\" feature was not compiled in the Parsec binary.");
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "Key Info Manager integrity not compiled",
+        )
+        .into())
+    }
+
+    pub(super) fn protect(&self, _: &KeyIdentity, _: bool, _: &mut KeyInfo) {
+        match *self {}
+    }
+
+    pub(super) fn verify<'a>(
+        &self,
+        _: &KeyIdentity,
+        _: bool,
+        _: &'a KeyInfo,
+    ) -> parsec_interface::requests::Result<&'a [u8]> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "kim-integrity", feature = "sqlite-kim"))]
+mod test {
+    use super::*;
+    use crate::authenticators::ApplicationIdentity;
+    use crate::key_info_managers::KeyInfoManagerFactory;
+    use crate::providers::ProviderIdentity;
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::operations::psa_algorithm::{Algorithm, Cipher};
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::AuthType;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn write_key(path: &Path, key: &[u8], mode: u32) {
+        std::fs::write(path, key).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn integrity(dir: &TempDir, key: &[u8]) -> RecordIntegrity {
+        let path = dir.path().join(format!("integrity-{}.key", key[0]));
+        write_key(&path, key, 0o600);
+        RecordIntegrity::new(path.to_str().unwrap()).unwrap()
+    }
+
+    fn key_identity(application: &str, provider: &str, key_name: &str) -> KeyIdentity {
+        KeyIdentity::new(
+            ApplicationIdentity::new(application.to_string(), AuthType::Direct),
+            ProviderIdentity::new(format!("uuid-{}", provider), provider.to_string()),
+            key_name.to_string(),
+        )
+    }
+
+    fn alice_key() -> KeyIdentity {
+        key_identity("alice", "provider", "key")
+    }
+
+    fn key_info() -> KeyInfo {
+        KeyInfo {
+            id: vec![1, 2, 3],
+            attributes: Attributes {
+                lifetime: Lifetime::Persistent,
+                key_type: Type::Aes,
+                bits: 128,
+                policy: Policy {
+                    usage_flags: UsageFlags::default(),
+                    permitted_algorithms: Algorithm::Cipher(Cipher::Ctr),
+                },
+            },
+        }
+    }
+
+    fn protected_key_info(integrity: &RecordIntegrity) -> KeyInfo {
+        let mut key_info = key_info();
+        integrity.protect(&alice_key(), true, &mut key_info);
+        key_info
+    }
+
+    fn kim_config(dir: &TempDir, key_path: &Path) -> KeyInfoManagerConfig {
+        KeyInfoManagerConfig {
+            name: "sqlite-manager".to_string(),
+            manager_type: KeyInfoManagerType::SQLite,
+            store_path: None,
+            sqlite_db_path: Some(dir.path().join("kim.sqlite3").to_str().unwrap().to_string()),
+            application_quota: None,
+            integrity_key_path: Some(key_path.to_str().unwrap().to_string()),
+        }
+    }
+
+    #[test]
+    fn key_accessible_by_others_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("integrity.key");
+        for mode in &[0o640, 0o604, 0o644] {
+            write_key(&path, &[7; 32], *mode);
+            assert!(RecordIntegrity::new(path.to_str().unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn short_key_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("integrity.key");
+        write_key(&path, &[7; MIN_KEY_LEN - 1], 0o600);
+        assert!(RecordIntegrity::new(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn missing_key_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("integrity.key");
+        assert!(RecordIntegrity::new(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn tag_is_appended_to_key_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let integrity = integrity(&dir, &[7; 32]);
+        let key_info = protected_key_info(&integrity);
+        assert_eq!(key_info.id.len(), 3 + TAG_LEN);
+        assert_eq!(&key_info.id[..3], &[1, 2, 3]);
+        assert_eq!(key_info.attributes, self::key_info().attributes);
+        assert_eq!(
+            integrity.verify(&alice_key(), true, &key_info).unwrap(),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn mapping_moved_to_another_key_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let integrity = integrity(&dir, &[7; 32]);
+        let key_info = protected_key_info(&integrity);
+        for other in &[
+            key_identity("bob", "provider", "key"),
+            key_identity("alice", "other-provider", "key"),
+            key_identity("alice", "provider", "other-key"),
+        ] {
+            assert_eq!(
+                integrity.verify(other, true, &key_info).unwrap_err(),
+                ResponseStatus::PsaErrorDataCorrupt
+            );
+        }
+    }
+
+    #[test]
+    fn authenticator_is_covered_when_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let integrity = integrity(&dir, &[7; 32]);
+        let key_info = protected_key_info(&integrity);
+        let other_authenticator = KeyIdentity::new(
+            ApplicationIdentity::new("alice".to_string(), AuthType::UnixPeerCredentials),
+            alice_key().provider().clone(),
+            "key".to_string(),
+        );
+        assert!(integrity
+            .verify(&other_authenticator, true, &key_info)
+            .is_err());
+        assert!(integrity.verify(&alice_key(), false, &key_info).is_err());
+
+        let mut key_info = self::key_info();
+        integrity.protect(&alice_key(), false, &mut key_info);
+        assert!(integrity
+            .verify(&other_authenticator, false, &key_info)
+            .is_ok());
+    }
+
+    #[test]
+    fn edited_mapping_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let integrity = integrity(&dir, &[7; 32]);
+
+        let mut key_info = protected_key_info(&integrity);
+        key_info.attributes.bits = 256;
+        assert!(integrity.verify(&alice_key(), true, &key_info).is_err());
+
+        let mut key_info = protected_key_info(&integrity);
+        key_info.id[0] = 4;
+        assert!(integrity.verify(&alice_key(), true, &key_info).is_err());
+
+        let mut key_info = protected_key_info(&integrity);
+        *key_info.id.last_mut().unwrap() ^= 1;
+        assert!(integrity.verify(&alice_key(), true, &key_info).is_err());
+    }
+
+    #[test]
+    fn untagged_mapping_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let integrity = integrity(&dir, &[7; 32]);
+        assert_eq!(
+            integrity
+                .verify(&alice_key(), true, &key_info())
+                .unwrap_err(),
+            ResponseStatus::PsaErrorDataCorrupt
+        );
+    }
+
+    #[test]
+    fn tag_depends_on_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_info = protected_key_info(&integrity(&dir, &[7; 32]));
+        let other = integrity(&dir, &[8; 32]);
+        assert!(other.verify(&alice_key(), true, &key_info).is_err());
+    }
+
+    #[test]
+    fn stored_mappings_are_protected() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("integrity.key");
+        write_key(&key_path, &[7; 32], 0o600);
+        let factory =
+            KeyInfoManagerFactory::new(&kim_config(&dir, &key_path), AuthType::Direct).unwrap();
+        let client = factory.build_client(alice_key().provider().clone());
+        client
+            .insert_key_info(alice_key(), &3u32, key_info().attributes)
+            .unwrap();
+        assert_eq!(client.get_key_id::<u32>(&alice_key()).unwrap(), 3);
+
+        // The mapping is edited in the storage of the Key Info Manager.
+        let mut edited = key_info();
+        edited.id = bincode::serialize(&4u32).unwrap();
+        integrity(&dir, &[8; 32]).protect(&alice_key(), true, &mut edited);
+        let _ = factory
+            .key_info_manager_impl
+            .write()
+            .unwrap()
+            .insert(alice_key(), edited)
+            .unwrap();
+        assert_eq!(
+            client.get_key_id::<u32>(&alice_key()).unwrap_err(),
+            ResponseStatus::PsaErrorDataCorrupt
+        );
+    }
+}
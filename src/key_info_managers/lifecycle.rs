@@ -0,0 +1,564 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Lifecycle of the keys
+//!
+//! Deployments which must demonstrate that their keys follow the states of NIST SP 800-57 Part 1
+//! can have the Key Info Managers record the state of every key, and the back end handlers refuse
+//! the operations which the state of a key does not permit:
+//! * a key in pre-activation can only have its public key exported;
+//! * an active key is used as its policy permits;
+//! * a suspended or deactivated key can only verify, decrypt and have its public key exported, to
+//!   process what it protected before;
+//! * destroying a key is what makes it destroyed.
+//!
+//! A key created or imported is active, unless its key name has the `#state=pre-activation`
+//! suffix. Its owner moves it to another state with a PsaDestroyKey request on its key name with
+//! the `#state=` suffix followed by the name of the state, which does not destroy it. The
+//! transitions are the ones of NIST SP 800-57: from pre-activation to active, from active to
+//! suspended, from suspended back to active, and from both to deactivated. Any key can be
+//! destroyed. The compromised states are not tracked. Keys which were created before the lifecycle
+//! was recorded have no state and are active.
+//!
+//! The states, and every transition with its time, are stored in an SQLite database so that they
+//! persist across restarts and can be audited. They are not replicated with the mappings.
+use super::{KeyIdentity, KeyInfoManagerClient};
+use crate::utils::config::KeyLifecycleConfig;
+use crate::utils::logging::Redacted;
+use anyhow::{Context, Result};
+use log::error;
+use parsec_interface::requests::{Opcode, ResponseStatus};
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default path of the database holding the states of the keys
+pub const DEFAULT_DB_PATH: &str = "/var/lib/parsec/kim-mappings/sqlite/key-lifecycle.sqlite3";
+
+/// File permissions of the database, only accessible to the service
+const FILE_PERMISSION: u32 = 0o600;
+
+/// State of a key in its lifecycle
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyState {
+    /// Created, but only usable to export its public key
+    PreActivation,
+    /// Usable for every operation its policy permits
+    Active,
+    /// Temporarily only usable to process what it protected
+    Suspended,
+    /// Only usable to process what it protected, for good
+    Deactivated,
+    /// Destroyed, the key name can be used again
+    Destroyed,
+}
+
+impl KeyState {
+    fn code(self) -> i64 {
+        match self {
+            KeyState::PreActivation => 0,
+            KeyState::Active => 1,
+            KeyState::Suspended => 2,
+            KeyState::Deactivated => 3,
+            KeyState::Destroyed => 4,
+        }
+    }
+
+    fn from_code(code: i64) -> Option<Self> {
+        match code {
+            0 => Some(KeyState::PreActivation),
+            1 => Some(KeyState::Active),
+            2 => Some(KeyState::Suspended),
+            3 => Some(KeyState::Deactivated),
+            4 => Some(KeyState::Destroyed),
+            _ => None,
+        }
+    }
+
+    /// State named by the argument of a `#state=` suffix. Keys are only destroyed by destroying
+    /// them.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pre-activation" => Some(KeyState::PreActivation),
+            "active" => Some(KeyState::Active),
+            "suspended" => Some(KeyState::Suspended),
+            "deactivated" => Some(KeyState::Deactivated),
+            _ => None,
+        }
+    }
+
+    /// Whether a key can go from this state to the other one with a PsaDestroyKey request.
+    fn can_become(self, to: KeyState) -> bool {
+        matches!(
+            (self, to),
+            (KeyState::PreActivation, KeyState::Active)
+                | (KeyState::Active, KeyState::Suspended)
+                | (KeyState::Suspended, KeyState::Active)
+                | (KeyState::Active, KeyState::Deactivated)
+                | (KeyState::Suspended, KeyState::Deactivated)
+        )
+    }
+
+    /// Whether a key in this state can be used by the operation.
+    fn permits(self, opcode: Opcode) -> bool {
+        match self {
+            KeyState::Active => true,
+            KeyState::PreActivation => opcode == Opcode::PsaExportPublicKey,
+            KeyState::Suspended | KeyState::Deactivated => matches!(
+                opcode,
+                Opcode::PsaExportPublicKey
+                    | Opcode::PsaVerifyHash
+                    | Opcode::PsaVerifyMessage
+                    | Opcode::PsaAsymmetricDecrypt
+                    | Opcode::PsaAeadDecrypt
+                    | Opcode::PsaCipherDecrypt
+            ),
+            KeyState::Destroyed => false,
+        }
+    }
+}
+
+// Keys are told apart by provider name, as the states of the keys of all the Key Info Managers
+// are kept together.
+pub(super) type StateKey = (u8, String, String, String);
+
+pub(super) fn state_key(key_identity: &KeyIdentity) -> StateKey {
+    (
+        *key_identity.application().authenticator_id() as u8,
+        key_identity.application().name().clone(),
+        key_identity.provider().name().clone(),
+        key_identity.key_name().clone(),
+    )
+}
+
+/// States of the keys, stored in a database
+#[derive(Debug)]
+pub struct KeyLifecycle {
+    database_path: PathBuf,
+    states: Mutex<HashMap<StateKey, KeyState>>,
+    // Keys being created in pre-activation
+    pre_activation: Mutex<HashSet<StateKey>>,
+}
+
+impl KeyLifecycle {
+    /// Open the database of the states, creating it if it does not exist.
+    pub fn new(config: &KeyLifecycleConfig) -> Result<Self> {
+        let database_path = PathBuf::from(
+            config
+                .store_path
+                .clone()
+                .unwrap_or_else(|| String::from(DEFAULT_DB_PATH)),
+        );
+        if let Some(directory_path) = database_path.parent() {
+            fs::create_dir_all(directory_path)
+                .with_context(|| format!("create directory {:?}", directory_path))?;
+        }
+        let conn = Connection::open(&database_path)?;
+        fs::set_permissions(&database_path, Permissions::from_mode(FILE_PERMISSION))?;
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS key_state (
+                authenticator_id    INTEGER NOT NULL,
+                application_name    TEXT NOT NULL,
+                provider_name       TEXT NOT NULL,
+                key_name            TEXT NOT NULL,
+                state               INTEGER NOT NULL,
+                PRIMARY KEY (authenticator_id, application_name, provider_name, key_name)
+            );
+            CREATE TABLE IF NOT EXISTS key_state_transition (
+                authenticator_id    INTEGER NOT NULL,
+                application_name    TEXT NOT NULL,
+                provider_name       TEXT NOT NULL,
+                key_name            TEXT NOT NULL,
+                from_state          INTEGER,
+                to_state            INTEGER NOT NULL,
+                time                INTEGER NOT NULL
+            );
+            ",
+        )?;
+
+        let mut states = HashMap::new();
+        let mut statement = conn.prepare(
+            "SELECT authenticator_id, application_name, provider_name, key_name, state FROM key_state",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let code: i64 = row.get(4)?;
+            let state = KeyState::from_code(code)
+                .with_context(|| format!("unknown key state {} in {:?}", code, database_path))?;
+            let _ = states.insert((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?), state);
+        }
+        drop(rows);
+        drop(statement);
+
+        Ok(KeyLifecycle {
+            database_path,
+            states: Mutex::new(states),
+            pre_activation: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// State of a key, active if it has none.
+    fn state(&self, key_identity: &KeyIdentity) -> KeyState {
+        self.states
+            .lock()
+            .expect("Key lifecycle lock poisoned")
+            .get(&state_key(key_identity))
+            .copied()
+            .unwrap_or(KeyState::Active)
+    }
+
+    /// Store the new state of a key and the transition to it.
+    fn record(
+        &self,
+        key_identity: &KeyIdentity,
+        from: Option<KeyState>,
+        to: KeyState,
+    ) -> parsec_interface::requests::Result<()> {
+        let key = state_key(key_identity);
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let result = Connection::open(&self.database_path).and_then(|conn| {
+            let _ = conn.execute(
+                "REPLACE INTO key_state VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![key.0, key.1, key.2, key.3, to.code()],
+            )?;
+            conn.execute(
+                "INSERT INTO key_state_transition VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    key.0,
+                    key.1,
+                    key.2,
+                    key.3,
+                    from.map(KeyState::code),
+                    to.code(),
+                    time
+                ],
+            )
+        });
+        match result {
+            Ok(_) => {
+                let _ = self
+                    .states
+                    .lock()
+                    .expect("Key lifecycle lock poisoned")
+                    .insert(key, to);
+                Ok(())
+            }
+            Err(e) => {
+                format_error!("Failed to store the state of a key", e);
+                Err(ResponseStatus::KeyInfoManagerError)
+            }
+        }
+    }
+
+    fn lock_pre_activation(&self) -> MutexGuard<'_, HashSet<StateKey>> {
+        self.pre_activation
+            .lock()
+            .expect("Key lifecycle lock poisoned")
+    }
+
+    /// Record that a key was created, in pre-activation if it was created with the suffix.
+    pub(super) fn created(
+        &self,
+        key_identity: &KeyIdentity,
+    ) -> parsec_interface::requests::Result<()> {
+        let state = if self.lock_pre_activation().remove(&state_key(key_identity)) {
+            KeyState::PreActivation
+        } else {
+            KeyState::Active
+        };
+        self.record(key_identity, None, state)
+    }
+
+    /// Record that a key was destroyed.
+    pub(super) fn destroyed(&self, key_identity: &KeyIdentity) {
+        let from = self.state(key_identity);
+        if self
+            .record(key_identity, Some(from), KeyState::Destroyed)
+            .is_err()
+        {
+            error!(
+                "The key \"{}\" was destroyed but its state could not be changed.",
+                Redacted(key_identity.key_name())
+            );
+        }
+    }
+}
+
+impl KeyInfoManagerClient {
+    /// Whether the lifecycle of the keys is tracked, the `#state=` suffix being recognised.
+    pub fn tracks_lifecycle(&self) -> bool {
+        self.lifecycle.is_some()
+    }
+
+    /// Have the key of the identity start in pre-activation when it is created, or not if
+    /// `pre_activation` is false, for example as its creation failed.
+    pub fn create_in_pre_activation(&self, key_identity: &KeyIdentity, pre_activation: bool) {
+        if let Some(lifecycle) = &self.lifecycle {
+            let key = state_key(key_identity);
+            let mut keys = lifecycle.lock_pre_activation();
+            if pre_activation {
+                let _ = keys.insert(key);
+            } else {
+                let _ = keys.remove(&key);
+            }
+        }
+    }
+
+    /// Check that the state of the key permits the operation. Keys which do not exist are left for
+    /// the provider to report.
+    ///
+    /// # Errors
+    ///
+    /// If the state of the key does not permit the operation, PsaErrorNotPermitted is returned.
+    pub fn check_key_state(
+        &self,
+        key_identity: &KeyIdentity,
+        opcode: Opcode,
+    ) -> parsec_interface::requests::Result<()> {
+        if let Some(lifecycle) = &self.lifecycle {
+            let state = lifecycle.state(key_identity);
+            if !state.permits(opcode) {
+                error!(
+                    "The key \"{}\" is in the {:?} state, which does not permit {:?}.",
+                    Redacted(key_identity.key_name()),
+                    state,
+                    opcode
+                );
+                return Err(ResponseStatus::PsaErrorNotPermitted);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the key to a state.
+    ///
+    /// # Errors
+    ///
+    /// If the lifecycle is not tracked, PsaErrorNotSupported is returned. If the key does not
+    /// exist, PsaErrorDoesNotExist is returned. If the key can not go from its state to the new
+    /// one, PsaErrorBadState is returned.
+    pub fn change_key_state(
+        &self,
+        key_identity: &KeyIdentity,
+        state: KeyState,
+    ) -> parsec_interface::requests::Result<()> {
+        let lifecycle = self
+            .lifecycle
+            .as_ref()
+            .ok_or(ResponseStatus::PsaErrorNotSupported)?;
+        let _ = self.get_key_attributes(key_identity)?;
+        let from = lifecycle.state(key_identity);
+        if !from.can_become(state) {
+            error!(
+                "The key \"{}\" can not go from the {:?} to the {:?} state.",
+                Redacted(key_identity.key_name()),
+                from,
+                state
+            );
+            return Err(ResponseStatus::PsaErrorBadState);
+        }
+        lifecycle.record(key_identity, Some(from), state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::authenticators::ApplicationIdentity;
+    use crate::key_info_managers::KeyInfoManagerFactory;
+    use crate::providers::ProviderIdentity;
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::operations::psa_algorithm::Algorithm;
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::AuthType;
+    use std::sync::Arc;
+
+    fn config(name: &str) -> KeyLifecycleConfig {
+        let db_path = format!("{}/kim/sqlite/{}.sqlite3", env!("OUT_DIR"), name);
+        let _ = fs::remove_file(&db_path);
+        KeyLifecycleConfig {
+            store_path: Some(db_path),
+        }
+    }
+
+    fn key_identity() -> KeyIdentity {
+        KeyIdentity::new(
+            ApplicationIdentity::new(String::from("app"), AuthType::Direct),
+            ProviderIdentity::new(String::from("uuid"), String::from("provider")),
+            String::from("key"),
+        )
+    }
+
+    #[test]
+    fn state_codes_round_trip() {
+        for state in [
+            KeyState::PreActivation,
+            KeyState::Active,
+            KeyState::Suspended,
+            KeyState::Deactivated,
+            KeyState::Destroyed,
+        ] {
+            assert_eq!(KeyState::from_code(state.code()), Some(state));
+        }
+        assert!(KeyState::from_code(-1).is_none());
+        assert!(KeyState::from_code(5).is_none());
+    }
+
+    #[test]
+    fn transitions_follow_nist_sp_800_57() {
+        assert!(KeyState::PreActivation.can_become(KeyState::Active));
+        assert!(KeyState::Active.can_become(KeyState::Suspended));
+        assert!(KeyState::Suspended.can_become(KeyState::Active));
+        assert!(KeyState::Suspended.can_become(KeyState::Deactivated));
+        assert!(!KeyState::Active.can_become(KeyState::PreActivation));
+        assert!(!KeyState::Deactivated.can_become(KeyState::Active));
+        assert!(!KeyState::PreActivation.can_become(KeyState::Suspended));
+        assert_eq!(KeyState::from_name("destroyed"), None);
+    }
+
+    #[test]
+    fn states_restrict_the_operations() {
+        assert!(KeyState::Active.permits(Opcode::PsaSignHash));
+        assert!(KeyState::PreActivation.permits(Opcode::PsaExportPublicKey));
+        assert!(!KeyState::PreActivation.permits(Opcode::PsaVerifyHash));
+        for state in [KeyState::Suspended, KeyState::Deactivated] {
+            assert!(state.permits(Opcode::PsaVerifyHash));
+            assert!(state.permits(Opcode::PsaAeadDecrypt));
+            assert!(!state.permits(Opcode::PsaSignHash));
+            assert!(!state.permits(Opcode::PsaCipherEncrypt));
+            assert!(!state.permits(Opcode::PsaRawKeyAgreement));
+        }
+    }
+
+    #[test]
+    fn keys_can_be_created_in_pre_activation() {
+        let lifecycle = KeyLifecycle::new(&config("lifecycle_pre_activation")).unwrap();
+        let key_identity = key_identity();
+        let _ = lifecycle
+            .lock_pre_activation()
+            .insert(state_key(&key_identity));
+        lifecycle.created(&key_identity).unwrap();
+        assert_eq!(lifecycle.state(&key_identity), KeyState::PreActivation);
+        lifecycle.destroyed(&key_identity);
+        lifecycle.created(&key_identity).unwrap();
+        assert_eq!(lifecycle.state(&key_identity), KeyState::Active);
+    }
+
+    #[test]
+    fn keys_without_state_are_active() {
+        let lifecycle = KeyLifecycle::new(&config("lifecycle_no_state")).unwrap();
+        assert_eq!(lifecycle.state(&key_identity()), KeyState::Active);
+    }
+
+    #[test]
+    fn states_persist_across_restarts() {
+        let config = config("lifecycle_restarts");
+        let key_identity = key_identity();
+
+        KeyLifecycle::new(&config)
+            .unwrap()
+            .created(&key_identity)
+            .unwrap();
+        let lifecycle = KeyLifecycle::new(&config).unwrap();
+        assert_eq!(lifecycle.state(&key_identity), KeyState::Active);
+        lifecycle.destroyed(&key_identity);
+        assert_eq!(
+            KeyLifecycle::new(&config).unwrap().state(&key_identity),
+            KeyState::Destroyed
+        );
+    }
+
+    #[test]
+    fn transitions_are_logged() {
+        let config = config("lifecycle_transitions");
+        let key_identity = key_identity();
+        let lifecycle = KeyLifecycle::new(&config).unwrap();
+        lifecycle.created(&key_identity).unwrap();
+        lifecycle.destroyed(&key_identity);
+
+        let conn = Connection::open(config.store_path.unwrap()).unwrap();
+        let mut statement = conn
+            .prepare("SELECT from_state, to_state FROM key_state_transition ORDER BY rowid")
+            .unwrap();
+        let transitions = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(Option<i64>, i64)>>>()
+            .unwrap();
+        assert_eq!(transitions, vec![(None, 1), (Some(1), 4)]);
+    }
+
+    #[test]
+    fn client_moves_keys_between_states() {
+        let dir = tempfile::tempdir().unwrap();
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(dir.path().join("kim.sqlite3").display().to_string()),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap()
+        .with_lifecycle(Arc::new(
+            KeyLifecycle::new(&config("lifecycle_client")).unwrap(),
+        ));
+        let key_identity = key_identity();
+        let client = factory.build_client(key_identity.provider().clone());
+        let attributes = Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::RawData,
+            bits: 256,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: Algorithm::None,
+            },
+        };
+
+        assert_eq!(
+            client
+                .change_key_state(&key_identity, KeyState::Active)
+                .unwrap_err(),
+            ResponseStatus::PsaErrorDoesNotExist
+        );
+        client.create_in_pre_activation(&key_identity, true);
+        client
+            .insert_key_info(key_identity.clone(), &1u32, attributes)
+            .unwrap();
+        assert_eq!(
+            client
+                .check_key_state(&key_identity, Opcode::PsaSignHash)
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        client
+            .change_key_state(&key_identity, KeyState::Active)
+            .unwrap();
+        client
+            .check_key_state(&key_identity, Opcode::PsaSignHash)
+            .unwrap();
+        client
+            .change_key_state(&key_identity, KeyState::Deactivated)
+            .unwrap();
+        client
+            .check_key_state(&key_identity, Opcode::PsaVerifyHash)
+            .unwrap();
+        assert_eq!(
+            client
+                .change_key_state(&key_identity, KeyState::Active)
+                .unwrap_err(),
+            ResponseStatus::PsaErrorBadState
+        );
+    }
+}
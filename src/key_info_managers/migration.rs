@@ -0,0 +1,284 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Migration of the mappings between Key Info Managers
+//!
+//! All the mappings of the given providers are copied from a source Key Info Manager to a
+//! destination one, possibly of a different type. The copy is refused if the destination already
+//! holds a different mapping for one of the keys. Once written, every mapping is read back from
+//! the destination and compared with its source; if one differs, the mappings written are removed
+//! again. The source is only changed, if asked, after all the mappings have been verified.
+//!
+//! The service must not run while its mappings are migrated: the Key Info Managers keep their
+//! mappings in memory and would not see the changes.
+use super::{KeyIdentity, KeyInfo, KeyInfoManagerFactory};
+use crate::providers::ProviderIdentity;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+impl KeyInfoManagerFactory {
+    /// Copy the mappings of the providers to the destination, returning how many were copied.
+    /// The mappings are removed from this Key Info Manager if `remove_source` is set, once the
+    /// copies have been verified.
+    pub fn migrate_to(
+        &self,
+        destination: &KeyInfoManagerFactory,
+        providers: &[ProviderIdentity],
+        remove_source: bool,
+    ) -> Result<usize> {
+        let mut records: Vec<(KeyIdentity, KeyInfo)> = Vec::new();
+        {
+            let source = self
+                .key_info_manager_impl
+                .read()
+                .expect("Key Info Manager lock poisoned");
+            for provider in providers {
+                for key_identity in source.get_all(provider.clone()).map_err(|e| anyhow!(e))? {
+                    let key_info = source
+                        .get(&key_identity)
+                        .map_err(|e| anyhow!(e))?
+                        .ok_or_else(|| anyhow!("mapping of {} disappeared", key_identity))?
+                        .clone();
+                    records.push((key_identity, key_info));
+                }
+            }
+        }
+
+        let mut destination = destination
+            .key_info_manager_impl
+            .write()
+            .expect("Key Info Manager lock poisoned");
+        let mut to_write = Vec::new();
+        for (key_identity, key_info) in &records {
+            match destination.get(key_identity).map_err(|e| anyhow!(e))? {
+                Some(existing) if existing == key_info => (),
+                Some(_) => {
+                    return Err(anyhow!(
+                        "the destination already has a different mapping for {}",
+                        key_identity
+                    ))
+                }
+                None => to_write.push((key_identity.clone(), key_info.clone())),
+            }
+        }
+        info!(
+            "Migrating {} mappings, {} of which are already in the destination.",
+            records.len(),
+            records.len() - to_write.len()
+        );
+
+        let mut written = Vec::new();
+        let mut failure = None;
+        for (key_identity, key_info) in to_write {
+            if let Err(e) = destination.insert(key_identity.clone(), key_info) {
+                failure = Some(anyhow!(
+                    "failed to write the mapping of {}: {}",
+                    key_identity,
+                    e
+                ));
+                break;
+            }
+            written.push(key_identity);
+        }
+        if failure.is_none() {
+            failure = records
+                .iter()
+                .find(|(key_identity, key_info)| {
+                    !matches!(destination.get(key_identity), Ok(Some(copy)) if copy == key_info)
+                })
+                .map(|(key_identity, _)| {
+                    anyhow!("the copy of the mapping of {} does not match", key_identity)
+                });
+        }
+        if let Some(failure) = failure {
+            for key_identity in &written {
+                if let Err(e) = destination.remove(key_identity) {
+                    warn!(
+                        "Failed to remove the copy of the mapping of {}: {}",
+                        key_identity, e
+                    );
+                }
+            }
+            return Err(failure);
+        }
+
+        if remove_source {
+            let mut source = self
+                .key_info_manager_impl
+                .write()
+                .expect("Key Info Manager lock poisoned");
+            for (key_identity, _) in &records {
+                let _ = source.remove(key_identity).map_err(|e| anyhow!(e))?;
+            }
+        }
+        Ok(records.len())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::super::{KeyIdentity, KeyInfoManagerFactory};
+    use crate::authenticators::ApplicationIdentity;
+    use crate::providers::ProviderIdentity;
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::operations::psa_algorithm::{Algorithm, Cipher};
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::AuthType;
+    use tempfile::TempDir;
+
+    fn factory(dir: &TempDir, name: &str) -> KeyInfoManagerFactory {
+        let db_path = dir.path().join(format!("{}.sqlite3", name));
+        KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: name.to_string(),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(db_path.to_str().unwrap().to_string()),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap()
+    }
+
+    fn provider(name: &str) -> ProviderIdentity {
+        ProviderIdentity::new(format!("uuid-{}", name), name.to_string())
+    }
+
+    fn attributes() -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::Aes,
+            bits: 128,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: Algorithm::Cipher(Cipher::Ctr),
+            },
+        }
+    }
+
+    fn key_identity(provider: &ProviderIdentity, key_name: &str) -> KeyIdentity {
+        KeyIdentity::new(
+            ApplicationIdentity::new("alice".to_string(), AuthType::Direct),
+            provider.clone(),
+            key_name.to_string(),
+        )
+    }
+
+    fn insert(
+        factory: &KeyInfoManagerFactory,
+        provider: &ProviderIdentity,
+        key_name: &str,
+        id: u32,
+    ) {
+        factory
+            .build_client(provider.clone())
+            .insert_key_info(key_identity(provider, key_name), &id, attributes())
+            .unwrap();
+    }
+
+    fn key_id(
+        factory: &KeyInfoManagerFactory,
+        provider: &ProviderIdentity,
+        key_name: &str,
+    ) -> Option<u32> {
+        factory
+            .build_client(provider.clone())
+            .get_key_id(&key_identity(provider, key_name))
+            .ok()
+    }
+
+    #[test]
+    fn mappings_are_copied() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (factory(&dir, "old"), factory(&dir, "new"));
+        let provider = provider("provider");
+        insert(&old, &provider, "key-1", 1);
+        insert(&old, &provider, "key-2", 2);
+
+        assert_eq!(old.migrate_to(&new, &[provider.clone()], false).unwrap(), 2);
+        assert_eq!(key_id(&new, &provider, "key-1"), Some(1));
+        assert_eq!(key_id(&new, &provider, "key-2"), Some(2));
+        assert_eq!(
+            new.build_client(provider.clone())
+                .get_key_attributes(&key_identity(&provider, "key-2"))
+                .unwrap(),
+            attributes()
+        );
+        assert_eq!(key_id(&old, &provider, "key-1"), Some(1));
+    }
+
+    #[test]
+    fn source_is_removed_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (factory(&dir, "old"), factory(&dir, "new"));
+        let provider = provider("provider");
+        insert(&old, &provider, "key-1", 1);
+
+        assert_eq!(old.migrate_to(&new, &[provider.clone()], true).unwrap(), 1);
+        assert!(old
+            .build_client(provider.clone())
+            .get_all()
+            .unwrap()
+            .is_empty());
+        assert_eq!(key_id(&new, &provider, "key-1"), Some(1));
+    }
+
+    #[test]
+    fn only_given_providers_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (factory(&dir, "old"), factory(&dir, "new"));
+        let (migrated, other) = (provider("migrated"), provider("other"));
+        insert(&old, &migrated, "key-1", 1);
+        insert(&old, &other, "key-2", 2);
+
+        assert_eq!(old.migrate_to(&new, &[migrated.clone()], true).unwrap(), 1);
+        assert_eq!(key_id(&new, &migrated, "key-1"), Some(1));
+        assert_eq!(key_id(&new, &other, "key-2"), None);
+        assert_eq!(key_id(&old, &other, "key-2"), Some(2));
+    }
+
+    #[test]
+    fn nothing_to_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (factory(&dir, "old"), factory(&dir, "new"));
+        assert_eq!(
+            old.migrate_to(&new, &[provider("provider")], true).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn identical_mappings_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (factory(&dir, "old"), factory(&dir, "new"));
+        let provider = provider("provider");
+        insert(&old, &provider, "key-1", 1);
+        insert(&old, &provider, "key-2", 2);
+        insert(&new, &provider, "key-1", 1);
+
+        // A migration interrupted after some copies can be run again.
+        assert_eq!(old.migrate_to(&new, &[provider.clone()], false).unwrap(), 2);
+        assert_eq!(key_id(&new, &provider, "key-1"), Some(1));
+        assert_eq!(key_id(&new, &provider, "key-2"), Some(2));
+    }
+
+    #[test]
+    fn conflicting_mappings_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (factory(&dir, "old"), factory(&dir, "new"));
+        let provider = provider("provider");
+        insert(&old, &provider, "key-1", 1);
+        insert(&old, &provider, "key-2", 2);
+        insert(&new, &provider, "key-2", 3);
+
+        assert!(old.migrate_to(&new, &[provider.clone()], true).is_err());
+        // Nothing is written nor removed.
+        assert_eq!(key_id(&new, &provider, "key-1"), None);
+        assert_eq!(key_id(&new, &provider, "key-2"), Some(3));
+        assert_eq!(key_id(&old, &provider, "key-1"), Some(1));
+        assert_eq!(key_id(&old, &provider, "key-2"), Some(2));
+    }
+}
@@ -6,25 +6,51 @@
 //! trait to help providers to store in a persistent manner the mapping between the name and the
 //! information of the keys they manage. Different implementors might store this mapping using different
 //! means but it has to be persistent.
-use crate::authenticators::ApplicationIdentity;
+use crate::authenticators::{tenants, ApplicationIdentity};
+use crate::key_info_managers::integrity::RecordIntegrity;
+#[cfg(feature = "sqlite-kim")]
+use crate::key_info_managers::lifecycle::KeyLifecycle;
 #[allow(deprecated)]
 use crate::key_info_managers::on_disk_manager::KeyTriple;
+use crate::key_info_managers::quota::{stored_size, StorageUsage};
+use crate::key_info_managers::replication::Replication;
+#[cfg(feature = "sqlite-kim")]
+use crate::key_info_managers::signature_counters::SignatureCounters;
 use crate::providers::ProviderIdentity;
 use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+#[cfg(feature = "fault-injection")]
+use crate::utils::fault_injection::FaultInjection;
+use crate::utils::logging::Redacted;
+use crate::utils::service_status::{KeyInfoWrite, ServiceStatus};
 use anyhow::Result;
 use derivative::Derivative;
+use log::error;
 use parsec_interface::operations::psa_key_attributes::Attributes;
 use parsec_interface::requests::{AuthType, ResponseStatus};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+#[cfg(not(feature = "sqlite-kim"))]
+use std::io::{Error, ErrorKind};
 use std::sync::{Arc, RwLock};
 use zeroize::Zeroize;
 
+mod integrity;
+#[cfg(feature = "sqlite-kim")]
+pub mod lifecycle;
+mod migration;
 pub mod on_disk_manager;
+mod quota;
+pub mod replication;
+#[cfg(feature = "sqlite-kim")]
+pub mod signature_counters;
+#[cfg(feature = "sqlite-kim")]
 pub mod sqlite_manager;
+#[cfg(feature = "sqlite-kim")]
+pub mod write_behind;
 
 /// This structure corresponds to a unique identifier of the key. It is used internally by the Key
 /// ID manager to refer to a key.
@@ -60,7 +86,9 @@ impl fmt::Display for KeyIdentity {
         write!(
             f,
             "KeyIdentity: {{\n{},\n{},\nkey_name: \"{}\",\n}}",
-            self.application, self.provider, self.key_name
+            self.application,
+            self.provider,
+            Redacted(&self.key_name)
         )
     }
 }
@@ -173,15 +201,73 @@ trait ManageKeyInfo {
 
 /// KeyInfoManager client structure that bridges between the KIM and the providers that need
 /// to use it.
-#[derive(Derivative)]
+#[derive(Derivative, Clone)]
 #[derivative(Debug)]
 pub struct KeyInfoManagerClient {
     provider_identity: ProviderIdentity,
     #[derivative(Debug = "ignore")]
     key_info_manager_impl: Arc<RwLock<dyn ManageKeyInfo + Send + Sync>>,
+    application_quota: Option<usize>,
+    // Storage quotas of the tenants, by namespace of their applications
+    tenant_quotas: Arc<HashMap<String, usize>>,
+    // Bytes stored by the applications, shared with the clients of the other providers
+    storage_usage: Arc<StorageUsage>,
+    // Public part of the keys, so that exporting it does not need the provider's backend. It is
+    // only kept in memory and filled again, key by key, after a restart.
+    #[derivative(Debug = "ignore")]
+    public_keys: Arc<RwLock<HashMap<KeyIdentity, Vec<u8>>>>,
+    replication: Option<Arc<Replication>>,
+    integrity: Option<Arc<RecordIntegrity>>,
+    #[cfg(feature = "sqlite-kim")]
+    lifecycle: Option<Arc<KeyLifecycle>>,
+    #[cfg(feature = "sqlite-kim")]
+    signature_counters: Option<Arc<SignatureCounters>>,
 }
 
 impl KeyInfoManagerClient {
+    /// Whether the tags of the mappings cover the authenticator of the application: not for the
+    /// on-disk manager, which does not store it.
+    fn tag_auth_type(key_info_manager_impl: &(dyn ManageKeyInfo + Send + Sync)) -> bool {
+        !matches!(
+            key_info_manager_impl.key_info_manager_type(),
+            KeyInfoManagerType::OnDisk
+        )
+    }
+
+    /// Key ID of a stored mapping, checked against its tag if the mappings are protected.
+    fn verified_id<'a>(
+        &self,
+        key_info_manager_impl: &(dyn ManageKeyInfo + Send + Sync),
+        key_identity: &KeyIdentity,
+        key_info: &'a KeyInfo,
+    ) -> parsec_interface::requests::Result<&'a [u8]> {
+        match &self.integrity {
+            Some(integrity) => integrity.verify(
+                key_identity,
+                Self::tag_auth_type(key_info_manager_impl),
+                key_info,
+            ),
+            None => Ok(&key_info.id),
+        }
+    }
+
+    /// Mapping to store, tagged if the mappings are protected.
+    fn protected_key_info(
+        &self,
+        key_info_manager_impl: &(dyn ManageKeyInfo + Send + Sync),
+        key_identity: &KeyIdentity,
+        mut key_info: KeyInfo,
+    ) -> KeyInfo {
+        if let Some(integrity) = &self.integrity {
+            integrity.protect(
+                key_identity,
+                Self::tag_auth_type(key_info_manager_impl),
+                &mut key_info,
+            );
+        }
+        key_info
+    }
+
     /// Get the KeyIdentity representing a key.
     pub fn get_key_identity(
         &self,
@@ -214,9 +300,10 @@ impl KeyInfoManagerClient {
             Ok(None) => return Err(ResponseStatus::PsaErrorDoesNotExist),
             Err(string) => return Err(to_response_status(string)),
         };
+        let id = self.verified_id(&*key_info_manager_impl, key_identity, key_info)?;
         // The `deserialize` call below creates a new instance of T decoupled from the
         // scope of the lock acquired above.
-        Ok(bincode::deserialize(&key_info.id)?)
+        Ok(bincode::deserialize(id)?)
     }
 
     /// Get the `Attributes` for a given KeyIdentity
@@ -238,6 +325,7 @@ impl KeyInfoManagerClient {
             Ok(None) => return Err(ResponseStatus::PsaErrorDoesNotExist),
             Err(string) => return Err(to_response_status(string)),
         };
+        let _ = self.verified_id(&*key_info_manager_impl, key_identity, key_info)?;
         Ok(key_info.attributes)
     }
 
@@ -263,14 +351,40 @@ impl KeyInfoManagerClient {
         &self,
         key_identity: &KeyIdentity,
     ) -> parsec_interface::requests::Result<()> {
+        #[cfg(feature = "fault-injection")]
+        FaultInjection::before_key_info_write()?;
         let mut key_info_manager_impl = self
             .key_info_manager_impl
             .write()
             .expect("Key Info Manager lock poisoned");
+        let _ = self
+            .public_keys
+            .write()
+            .expect("Public key cache lock poisoned")
+            .remove(key_identity);
         match key_info_manager_impl.remove(key_identity) {
-            Ok(Some(_key_info)) => Ok(()),
+            Ok(Some(key_info)) => {
+                self.storage_usage
+                    .record(key_identity, stored_size(key_identity, &key_info), 0);
+                if let Some(replication) = &self.replication {
+                    replication.record_remove(key_identity);
+                }
+                #[cfg(feature = "sqlite-kim")]
+                if let Some(lifecycle) = &self.lifecycle {
+                    lifecycle.destroyed(key_identity);
+                }
+                #[cfg(feature = "sqlite-kim")]
+                if let Some(signature_counters) = &self.signature_counters {
+                    signature_counters.forget(key_identity);
+                }
+                ServiceStatus::record_key_info_write(KeyInfoWrite::Remove, true);
+                Ok(())
+            }
             Ok(None) => Err(ResponseStatus::PsaErrorDoesNotExist),
-            Err(string) => Err(to_response_status(string)),
+            Err(string) => {
+                ServiceStatus::record_key_info_write(KeyInfoWrite::Remove, false);
+                Err(to_response_status(string))
+            }
         }
     }
 
@@ -286,19 +400,75 @@ impl KeyInfoManagerClient {
         key_id: &T,
         attributes: Attributes,
     ) -> parsec_interface::requests::Result<()> {
+        #[cfg(feature = "fault-injection")]
+        FaultInjection::before_key_info_write()?;
         let mut key_info_manager_impl = self
             .key_info_manager_impl
             .write()
             .expect("Key Info Manager lock poisoned");
-        let key_info = KeyInfo {
-            id: bincode::serialize(key_id)?,
-            attributes,
-        };
+        let key_info = self.protected_key_info(
+            &*key_info_manager_impl,
+            &key_identity,
+            KeyInfo {
+                id: bincode::serialize(key_id)?,
+                attributes,
+            },
+        );
 
-        match key_info_manager_impl.insert(key_identity, key_info) {
-            Ok(None) => Ok(()),
+        let size = stored_size(&key_identity, &key_info);
+        if let Some(quota) = self.application_quota {
+            let usage =
+                self.application_usage(&*key_info_manager_impl, key_identity.application())?;
+            if usage.saturating_add(size) > quota {
+                error!(
+                    "Storing the key would take the application over its quota of {} bytes.",
+                    quota
+                );
+                return Err(ResponseStatus::PsaErrorInsufficientStorage);
+            }
+        }
+        let namespace = tenants::namespace(key_identity.application().name());
+        if let Some((namespace, quota)) = self
+            .tenant_quotas
+            .get_key_value(namespace.unwrap_or_default())
+        {
+            let usage = self.usage(&*key_info_manager_impl, |application| {
+                tenants::namespace(application.name()) == Some(namespace)
+            })?;
+            if usage.saturating_add(size) > *quota {
+                error!(
+                    "Storing the key would take tenant {} over its quota of {} bytes.",
+                    namespace, quota
+                );
+                return Err(ResponseStatus::PsaErrorInsufficientStorage);
+            }
+        }
+
+        #[cfg(feature = "sqlite-kim")]
+        if let Some(lifecycle) = &self.lifecycle {
+            // Recorded first, so that no stored key misses its creation in the history.
+            lifecycle.created(&key_identity)?;
+        }
+
+        // Kept to be replicated once inserted.
+        let replicated = self
+            .replication
+            .as_ref()
+            .map(|replication| (replication, key_identity.clone(), key_info.clone()));
+        match key_info_manager_impl.insert(key_identity.clone(), key_info) {
+            Ok(None) => {
+                self.storage_usage.record(&key_identity, 0, size);
+                if let Some((replication, key_identity, key_info)) = replicated {
+                    replication.record_insert(&key_identity, &key_info);
+                }
+                ServiceStatus::record_key_info_write(KeyInfoWrite::Insert, true);
+                Ok(())
+            }
             Ok(Some(_)) => Err(ResponseStatus::PsaErrorAlreadyExists),
-            Err(string) => Err(to_response_status(string)),
+            Err(string) => {
+                ServiceStatus::record_key_info_write(KeyInfoWrite::Insert, false);
+                Err(to_response_status(string))
+            }
         }
     }
 
@@ -314,6 +484,8 @@ impl KeyInfoManagerClient {
         key_id: &T,
         attributes: Attributes,
     ) -> parsec_interface::requests::Result<()> {
+        #[cfg(feature = "fault-injection")]
+        FaultInjection::before_key_info_write()?;
         let mut key_info_manager_impl = self
             .key_info_manager_impl
             .write()
@@ -322,7 +494,62 @@ impl KeyInfoManagerClient {
             id: bincode::serialize(key_id)?,
             attributes,
         };
+        self.replace_stored_key_info(&mut *key_info_manager_impl, key_identity, key_info)
+    }
+
+    /// Replace the Attributes saved for a given KeyIdentity, keeping its key ID
+    ///
+    /// # Errors
+    ///
+    /// If the key identity doesn't exist in the KIM, PsaErrorDoesNotExist is returned. For
+    /// any other error occurring in the KIM, KeyInfoManagerError is returned.
+    pub fn replace_key_attributes(
+        &self,
+        key_identity: KeyIdentity,
+        attributes: Attributes,
+    ) -> parsec_interface::requests::Result<()> {
+        #[cfg(feature = "fault-injection")]
+        FaultInjection::before_key_info_write()?;
+        let mut key_info_manager_impl = self
+            .key_info_manager_impl
+            .write()
+            .expect("Key Info Manager lock poisoned");
+        let key_info = match key_info_manager_impl.get(&key_identity) {
+            Ok(Some(key_info)) => key_info,
+            Ok(None) => return Err(ResponseStatus::PsaErrorDoesNotExist),
+            Err(string) => return Err(to_response_status(string)),
+        };
+        let id = self
+            .verified_id(&*key_info_manager_impl, &key_identity, key_info)?
+            .to_vec();
+        self.replace_stored_key_info(
+            &mut *key_info_manager_impl,
+            key_identity,
+            KeyInfo { id, attributes },
+        )
+    }
 
+    /// Replace the mapping of a key which must exist, with the lock of the KIM held.
+    fn replace_stored_key_info(
+        &self,
+        key_info_manager_impl: &mut (dyn ManageKeyInfo + Send + Sync),
+        key_identity: KeyIdentity,
+        key_info: KeyInfo,
+    ) -> parsec_interface::requests::Result<()> {
+        let key_info = self.protected_key_info(key_info_manager_impl, &key_identity, key_info);
+
+        let size = stored_size(&key_identity, &key_info);
+
+        // The key might have been replaced by a different one.
+        let _ = self
+            .public_keys
+            .write()
+            .expect("Public key cache lock poisoned")
+            .remove(&key_identity);
+        let replicated = self
+            .replication
+            .as_ref()
+            .map(|replication| (replication, key_info.clone()));
         match key_info_manager_impl.insert(key_identity.clone(), key_info) {
             Ok(None) => {
                 let _ = key_info_manager_impl
@@ -330,8 +557,54 @@ impl KeyInfoManagerClient {
                     .map_err(to_response_status)?;
                 Err(ResponseStatus::PsaErrorDoesNotExist)
             }
-            Ok(Some(_)) => Ok(()),
-            Err(string) => Err(to_response_status(string)),
+            Ok(Some(previous)) => {
+                self.storage_usage.record(
+                    &key_identity,
+                    stored_size(&key_identity, &previous),
+                    size,
+                );
+                if let Some((replication, key_info)) = replicated {
+                    replication.record_insert(&key_identity, &key_info);
+                }
+                ServiceStatus::record_key_info_write(KeyInfoWrite::Insert, true);
+                Ok(())
+            }
+            Err(string) => {
+                ServiceStatus::record_key_info_write(KeyInfoWrite::Insert, false);
+                Err(to_response_status(string))
+            }
+        }
+    }
+
+    /// Get the cached public part of a key, if it was stored since the service started.
+    pub fn get_public_key(&self, key_identity: &KeyIdentity) -> Option<Vec<u8>> {
+        self.public_keys
+            .read()
+            .expect("Public key cache lock poisoned")
+            .get(key_identity)
+            .cloned()
+    }
+
+    /// Store the public part of a key, in the format of PsaExportPublicKey, for it to be exported
+    /// without using the provider's backend. It is discarded when the key info is removed or
+    /// replaced, and not stored if the key info was removed in the meantime.
+    pub fn cache_public_key(&self, key_identity: &KeyIdentity, public_key: Vec<u8>) {
+        // Held while checking that the key exists so that it can not be removed, and its cached
+        // public key discarded, before the public key is stored.
+        let key_info_manager_impl = self
+            .key_info_manager_impl
+            .write()
+            .expect("Key Info Manager lock poisoned");
+        match key_info_manager_impl.exists(key_identity) {
+            Ok(true) => {
+                let _ = self
+                    .public_keys
+                    .write()
+                    .expect("Public key cache lock poisoned")
+                    .insert(key_identity.clone(), public_key);
+            }
+            Ok(false) => (),
+            Err(string) => error!("Failed to check that the key to cache exists: {}", string),
         }
     }
 
@@ -359,6 +632,36 @@ impl KeyInfoManagerClient {
         Ok(clients)
     }
 
+    /// Number of bytes stored for the keys of an application in this provider
+    fn application_usage(
+        &self,
+        key_info_manager_impl: &(dyn ManageKeyInfo + Send + Sync),
+        application: &ApplicationIdentity,
+    ) -> parsec_interface::requests::Result<usize> {
+        // Same matching as when listing the keys of an application.
+        let on_disk = matches!(
+            key_info_manager_impl.key_info_manager_type(),
+            KeyInfoManagerType::OnDisk
+        );
+        self.usage(key_info_manager_impl, |owner| {
+            if on_disk {
+                owner.name() == application.name()
+            } else {
+                owner == application
+            }
+        })
+    }
+
+    /// Number of bytes stored for the keys of the matching applications in this provider
+    fn usage(
+        &self,
+        key_info_manager_impl: &(dyn ManageKeyInfo + Send + Sync),
+        owned: impl Fn(&ApplicationIdentity) -> bool,
+    ) -> parsec_interface::requests::Result<usize> {
+        self.storage_usage
+            .usage(key_info_manager_impl, &self.provider_identity, owned)
+    }
+
     /// Returns a Vec of the KeyInfo objects corresponding to the given ApplicationIdentity,
     /// and the KIM client ProviderIdentity.
     ///
@@ -405,6 +708,13 @@ impl KeyInfoManagerClient {
                 Some(key_info) => key_info,
                 _ => continue,
             };
+            // Tampered mappings are left out, the error is logged.
+            if self
+                .verified_id(&*key_info_manager_impl, &key_identity, key_info)
+                .is_err()
+            {
+                continue;
+            }
 
             #[allow(deprecated)]
             let key_triple =
@@ -423,6 +733,36 @@ impl KeyInfoManagerClient {
         Ok(keys)
     }
 
+    /// Check the tags of all the mappings of the provider, so that tampering is reported when the
+    /// service starts rather than when the keys are next used.
+    fn verify_all(&self) {
+        let key_info_manager_impl = self
+            .key_info_manager_impl
+            .read()
+            .expect("Key Info Manager lock poisoned");
+        let key_identities = match key_info_manager_impl.get_all(self.provider_identity.clone()) {
+            Ok(key_identities) => key_identities,
+            Err(e) => {
+                format_error!("Failed to list the mappings to verify", e);
+                return;
+            }
+        };
+        let tampered = key_identities
+            .iter()
+            .filter(|key_identity| {
+                matches!(key_info_manager_impl.get(key_identity), Ok(Some(key_info))
+                    if self.verified_id(&*key_info_manager_impl, key_identity, key_info).is_err())
+            })
+            .count();
+        if tampered > 0 {
+            error!(
+                "{} mappings of provider {} failed their integrity check and will be rejected.",
+                tampered,
+                self.provider_identity.name()
+            );
+        }
+    }
+
     /// Check if a KeyIdentity exists in the Key Info Manager and return a ResponseStatus
     ///
     /// # Errors
@@ -447,11 +787,21 @@ impl KeyInfoManagerClient {
 }
 
 /// Builder for KeyInfoManager clients
-#[derive(Derivative)]
+#[derive(Derivative, Clone)]
 #[derivative(Debug)]
 pub struct KeyInfoManagerFactory {
     #[derivative(Debug = "ignore")]
     key_info_manager_impl: Arc<RwLock<dyn ManageKeyInfo + Send + Sync>>,
+    application_quota: Option<usize>,
+    tenant_quotas: Arc<HashMap<String, usize>>,
+    storage_usage: Arc<StorageUsage>,
+    replication: Option<Arc<Replication>>,
+    integrity: Option<Arc<RecordIntegrity>>,
+    #[cfg(feature = "sqlite-kim")]
+    lifecycle: Option<Arc<KeyLifecycle>>,
+    #[cfg(feature = "sqlite-kim")]
+    signature_counters: Option<Arc<SignatureCounters>>,
+    default_auth_type: AuthType,
 }
 
 impl KeyInfoManagerFactory {
@@ -467,8 +817,19 @@ impl KeyInfoManagerFactory {
                 let manager = builder.build()?;
                 KeyInfoManagerFactory {
                     key_info_manager_impl: Arc::new(RwLock::new(manager)),
+                    application_quota: config.application_quota,
+                    tenant_quotas: Arc::new(HashMap::new()),
+                    storage_usage: Arc::new(StorageUsage::default()),
+                    replication: None,
+                    integrity: None,
+                    #[cfg(feature = "sqlite-kim")]
+                    lifecycle: None,
+                    #[cfg(feature = "sqlite-kim")]
+                    signature_counters: None,
+                    default_auth_type,
                 }
             }
+            #[cfg(feature = "sqlite-kim")]
             KeyInfoManagerType::SQLite => {
                 let mut builder = sqlite_manager::SQLiteKeyInfoManagerBuilder::new();
                 if let Some(sqlite_db_path) = &config.sqlite_db_path {
@@ -477,18 +838,305 @@ impl KeyInfoManagerFactory {
                 let manager = builder.build()?;
                 KeyInfoManagerFactory {
                     key_info_manager_impl: Arc::new(RwLock::new(manager)),
+                    application_quota: config.application_quota,
+                    tenant_quotas: Arc::new(HashMap::new()),
+                    storage_usage: Arc::new(StorageUsage::default()),
+                    replication: None,
+                    integrity: None,
+                    #[cfg(feature = "sqlite-kim")]
+                    lifecycle: None,
+                    #[cfg(feature = "sqlite-kim")]
+                    signature_counters: None,
+                    default_auth_type,
                 }
             }
+            #[cfg(not(feature = "sqlite-kim"))]
+            KeyInfoManagerType::SQLite => {
+                error!("The SQLite Key Info Manager was configured but the \"sqlite-kim\" feature was not compiled in the Parsec binary.");
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "SQLite Key Info Manager not compiled",
+                )
+                .into());
+            }
         };
+        let integrity = match &config.integrity_key_path {
+            Some(key_path) => Some(Arc::new(RecordIntegrity::new(key_path)?)),
+            None => None,
+        };
+
+        Ok(KeyInfoManagerFactory {
+            integrity,
+            ..factory
+        })
+    }
+
+    /// Authenticator type of the applications by default
+    pub fn default_auth_type(&self) -> AuthType {
+        self.default_auth_type
+    }
 
-        Ok(factory)
+    /// Limit the storage used by the applications of each tenant, given by the namespace of their
+    /// names, for the clients built from now on.
+    pub fn with_tenant_quotas(mut self, tenant_quotas: HashMap<String, usize>) -> Self {
+        self.tenant_quotas = Arc::new(tenant_quotas);
+        self
+    }
+
+    /// Replicate the mappings of the clients built from now on.
+    pub fn with_replication(mut self, replication: Arc<Replication>) -> Self {
+        self.replication = Some(replication);
+        self
+    }
+
+    /// Track the lifecycle of the keys of the clients built from now on.
+    #[cfg(feature = "sqlite-kim")]
+    pub fn with_lifecycle(mut self, lifecycle: Arc<KeyLifecycle>) -> Self {
+        self.lifecycle = Some(lifecycle);
+        self
+    }
+
+    /// Count the signatures made with the keys of the clients built from now on.
+    #[cfg(feature = "sqlite-kim")]
+    pub fn with_signature_counters(mut self, signature_counters: Arc<SignatureCounters>) -> Self {
+        self.signature_counters = Some(signature_counters);
+        self
     }
 
     /// Build a KeyInfoManagerClient
     pub fn build_client(&self, provider_identity: ProviderIdentity) -> KeyInfoManagerClient {
-        KeyInfoManagerClient {
+        let public_keys = Arc::new(RwLock::new(HashMap::new()));
+        if let Some(replication) = &self.replication {
+            replication.register(
+                provider_identity.clone(),
+                self.key_info_manager_impl.clone(),
+                public_keys.clone(),
+                self.storage_usage.clone(),
+            );
+        }
+        let client = KeyInfoManagerClient {
             key_info_manager_impl: self.key_info_manager_impl.clone(),
             provider_identity,
+            application_quota: self.application_quota,
+            tenant_quotas: self.tenant_quotas.clone(),
+            storage_usage: self.storage_usage.clone(),
+            public_keys,
+            replication: self.replication.clone(),
+            integrity: self.integrity.clone(),
+            #[cfg(feature = "sqlite-kim")]
+            lifecycle: self.lifecycle.clone(),
+            #[cfg(feature = "sqlite-kim")]
+            signature_counters: self.signature_counters.clone(),
+        };
+        if client.integrity.is_some() {
+            client.verify_all();
         }
+        client
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::{KeyIdentity, KeyInfoManagerFactory};
+    use crate::authenticators::ApplicationIdentity;
+    use crate::providers::ProviderIdentity;
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::operations::psa_algorithm::{Algorithm, Cipher};
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::{AuthType, ResponseStatus};
+    use std::fs;
+
+    fn aes_attributes() -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::Aes,
+            bits: 128,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: Algorithm::Cipher(Cipher::Ctr),
+            },
+        }
+    }
+
+    // Factory whose quota fits two of the keys stored by `quota_key`, in a new database.
+    fn quota_factory(db_path: &str, new: bool) -> KeyInfoManagerFactory {
+        if new {
+            let _ = fs::remove_file(db_path);
+        }
+        // Each key takes its 5 bytes name and its serialized key information: 8 bytes of length
+        // prefix, the 64 bytes of ID serialized with their own 8 bytes of length prefix, and the
+        // attributes.
+        let attributes_size = bincode::serialized_size(&aes_attributes()).unwrap() as usize;
+        KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(db_path.to_string()),
+                application_quota: Some(2 * (5 + 8 + 8 + 64 + attributes_size)),
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn application_quota_is_enforced() {
+        let db_path = format!("{}/kim/sqlite/quota.sqlite3", env!("OUT_DIR"));
+        let factory = quota_factory(&db_path, true);
+        let provider = ProviderIdentity::new("uuid".to_string(), "provider".to_string());
+        let client = factory.build_client(provider.clone());
+        let attributes = aes_attributes();
+        let alice = ApplicationIdentity::new("alice".to_string(), AuthType::Direct);
+        let bob = ApplicationIdentity::new("bob".to_string(), AuthType::Direct);
+        let key = |application: &ApplicationIdentity, name: &str| {
+            KeyIdentity::new(application.clone(), provider.clone(), name.to_string())
+        };
+        let id = vec![0u8; 64];
+
+        client
+            .insert_key_info(key(&alice, "key-1"), &id, attributes)
+            .unwrap();
+        client
+            .insert_key_info(key(&alice, "key-2"), &id, attributes)
+            .unwrap();
+        assert_eq!(
+            client.insert_key_info(key(&alice, "key-3"), &id, attributes),
+            Err(ResponseStatus::PsaErrorInsufficientStorage)
+        );
+        client
+            .insert_key_info(key(&bob, "key-1"), &id, attributes)
+            .unwrap();
+        client.remove_key_info(&key(&alice, "key-1")).unwrap();
+        client
+            .insert_key_info(key(&alice, "key-3"), &id, attributes)
+            .unwrap();
+        // The quota is full, until a key is replaced by one with a smaller ID.
+        assert_eq!(
+            client.insert_key_info(key(&alice, "key-4"), &0u8, attributes),
+            Err(ResponseStatus::PsaErrorInsufficientStorage)
+        );
+        client
+            .replace_key_info(key(&alice, "key-3"), &0u8, attributes)
+            .unwrap();
+        client
+            .insert_key_info(key(&alice, "key-4"), &0u8, attributes)
+            .unwrap();
+    }
+
+    #[test]
+    fn stored_keys_count_towards_the_quota() {
+        let db_path = format!("{}/kim/sqlite/quota_restart.sqlite3", env!("OUT_DIR"));
+        let provider = ProviderIdentity::new("uuid".to_string(), "provider".to_string());
+        let alice = ApplicationIdentity::new("alice".to_string(), AuthType::Direct);
+        let key = |name: &str| KeyIdentity::new(alice.clone(), provider.clone(), name.to_string());
+        let id = vec![0u8; 64];
+
+        let client = quota_factory(&db_path, true).build_client(provider.clone());
+        client
+            .insert_key_info(key("key-1"), &id, aes_attributes())
+            .unwrap();
+        client
+            .insert_key_info(key("key-2"), &id, aes_attributes())
+            .unwrap();
+        drop(client);
+
+        // The keys stored before the service restarted are counted.
+        let client = quota_factory(&db_path, false).build_client(provider.clone());
+        assert_eq!(
+            client.insert_key_info(key("key-3"), &id, aes_attributes()),
+            Err(ResponseStatus::PsaErrorInsufficientStorage)
+        );
+    }
+
+    #[test]
+    fn public_keys_are_cached() {
+        let db_path = format!("{}/kim/sqlite/public_keys.sqlite3", env!("OUT_DIR"));
+        let _ = fs::remove_file(&db_path);
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(db_path),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap();
+        let provider = ProviderIdentity::new("uuid".to_string(), "provider".to_string());
+        let client = factory.build_client(provider.clone());
+        let attributes = Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::RsaKeyPair,
+            bits: 1024,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: Algorithm::None,
+            },
+        };
+        let key = KeyIdentity::new(
+            ApplicationIdentity::new("alice".to_string(), AuthType::Direct),
+            provider,
+            "key".to_string(),
+        );
+
+        client
+            .insert_key_info(key.clone(), &1u32, attributes)
+            .unwrap();
+        assert_eq!(client.get_public_key(&key), None);
+        client.cache_public_key(&key, vec![1, 2, 3]);
+        assert_eq!(client.get_public_key(&key), Some(vec![1, 2, 3]));
+        client
+            .replace_key_info(key.clone(), &2u32, attributes)
+            .unwrap();
+        assert_eq!(client.get_public_key(&key), None);
+        client.cache_public_key(&key, vec![4, 5, 6]);
+        client.remove_key_info(&key).unwrap();
+        assert_eq!(client.get_public_key(&key), None);
+        // Public keys read before the key was destroyed are not cached.
+        client.cache_public_key(&key, vec![7, 8, 9]);
+        assert_eq!(client.get_public_key(&key), None);
+    }
+
+    #[test]
+    fn attributes_are_replaced_keeping_the_key_id() {
+        let db_path = format!("{}/kim/sqlite/replace_attributes.sqlite3", env!("OUT_DIR"));
+        let _ = fs::remove_file(&db_path);
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(db_path),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap();
+        let provider = ProviderIdentity::new("uuid".to_string(), "provider".to_string());
+        let client = factory.build_client(provider.clone());
+        let alice = ApplicationIdentity::new("alice".to_string(), AuthType::Direct);
+        let key = |name: &str| KeyIdentity::new(alice.clone(), provider.clone(), name.to_string());
+        let mut attributes = aes_attributes();
+
+        client
+            .insert_key_info(key("key"), &7u32, attributes)
+            .unwrap();
+        let _ = attributes.policy.usage_flags.set_decrypt();
+        client
+            .replace_key_attributes(key("key"), attributes)
+            .unwrap();
+        assert_eq!(client.get_key_id::<u32>(&key("key")).unwrap(), 7);
+        assert_eq!(client.get_key_attributes(&key("key")).unwrap(), attributes);
+        assert_eq!(
+            client.replace_key_attributes(key("missing"), attributes),
+            Err(ResponseStatus::PsaErrorDoesNotExist)
+        );
     }
 }
@@ -14,6 +14,7 @@
 //! For security reasons, only the PARSEC service should have the ability to modify these files.
 use crate::authenticators::{Application, ApplicationIdentity};
 use crate::utils::config::KeyInfoManagerType;
+use crate::utils::logging::Redacted;
 
 use super::{KeyIdentity, KeyInfo, ManageKeyInfo, ProviderIdentity};
 use crate::providers::core::Provider as CoreProvider;
@@ -143,7 +144,9 @@ impl fmt::Display for KeyTriple {
         write!(
             f,
             "KeyTriple: app_name=\"{}\" provider_id={} key_name=\"{}\"",
-            self.app_name, self.provider_id, self.key_name
+            Redacted(&self.app_name),
+            self.provider_id,
+            Redacted(&self.key_name)
         )
     }
 }
@@ -325,6 +328,16 @@ fn os_str_to_provider_id(os_str: &OsStr) -> std::io::Result<ProviderId> {
     }
 }
 
+/// Overwrite a mapping file with zeros before removing it, so that the key ID it held cannot be
+/// read back from the disk blocks.
+fn scrub_file(path: &Path) -> std::io::Result<()> {
+    let length = fs::metadata(path)?.len();
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0; length as usize])?;
+    file.sync_all()?;
+    fs::remove_file(path)
+}
+
 /// Lists all the directory paths in the given directory path.
 fn list_dirs(path: &Path) -> std::io::Result<Vec<PathBuf>> {
     // read_dir returning an iterator over Result<DirEntry>, there is first a conversion to a path
@@ -486,7 +499,7 @@ impl OnDiskKeyInfoManager {
         fs::set_permissions(&app_dir_path, dir_permissions.clone())?;
         fs::set_permissions(&provider_dir_path, dir_permissions)?;
         if key_name_file_path.exists() {
-            fs::remove_file(&key_name_file_path)?;
+            scrub_file(&key_name_file_path)?;
         }
 
         let mut mapping_file = fs::File::create(&key_name_file_path).map_err(|e| {
@@ -515,7 +528,7 @@ impl OnDiskKeyInfoManager {
             .join(prov)
             .join(key_name);
         if key_name_file_path.exists() {
-            fs::remove_file(key_name_file_path)
+            scrub_file(&key_name_file_path)
         } else {
             Ok(())
         }
@@ -895,6 +908,77 @@ mod test {
         fs::remove_dir_all(path).unwrap();
     }
 
+    /// The only mapping file stored under the given directory
+    fn mapping_file(dir: &std::path::Path) -> PathBuf {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        assert_eq!(files.len(), 1);
+        files.remove(0)
+    }
+
+    #[test]
+    fn scrubbed_files_are_zeroed_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mapping");
+        // The link keeps the content of the file readable once it is removed.
+        let link = dir.path().join("link");
+        fs::write(&path, b"key id").unwrap();
+        fs::hard_link(&path, &link).unwrap();
+        super::scrub_file(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read(&link).unwrap(), vec![0; 6]);
+    }
+
+    #[test]
+    fn removed_mappings_are_scrubbed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager =
+            OnDiskKeyInfoManager::new(dir.path().join("mappings"), AuthType::NoAuth).unwrap();
+        let key_identity = new_key_identity("removed_mappings_are_scrubbed".to_string());
+        let _ = manager
+            .insert(key_identity.clone(), test_key_info())
+            .unwrap();
+        let link = dir.path().join("link");
+        fs::hard_link(mapping_file(&dir.path().join("mappings")), &link).unwrap();
+
+        assert!(manager.remove(&key_identity).unwrap().is_some());
+        let content = fs::read(&link).unwrap();
+        assert!(!content.is_empty());
+        assert!(content.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn replaced_mappings_are_scrubbed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager =
+            OnDiskKeyInfoManager::new(dir.path().join("mappings"), AuthType::NoAuth).unwrap();
+        let key_identity = new_key_identity("replaced_mappings_are_scrubbed".to_string());
+        let _ = manager
+            .insert(key_identity.clone(), test_key_info())
+            .unwrap();
+        let link = dir.path().join("link");
+        fs::hard_link(mapping_file(&dir.path().join("mappings")), &link).unwrap();
+
+        let mut key_info = test_key_info();
+        key_info.id = vec![0x44, 0x55, 0x66];
+        assert!(manager
+            .insert(key_identity.clone(), key_info.clone())
+            .unwrap()
+            .is_some());
+        assert!(fs::read(&link).unwrap().iter().all(|byte| *byte == 0));
+        assert_eq!(manager.get(&key_identity).unwrap(), Some(&key_info));
+    }
+
     fn new_key_identity(key_name: String) -> KeyIdentity {
         KeyIdentity::new(
             ApplicationIdentity::new("Testing Application 😎".to_string(), AuthType::NoAuth),
@@ -0,0 +1,104 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Storage used by the applications, checked against their quotas
+//!
+//! The bytes stored for the keys of the applications of a provider are counted from the stored
+//! mappings the first time a quota is checked in it. The totals are then kept up to date as keys
+//! are inserted, replaced and removed, under the write lock of the Key Info Manager, so that a
+//! quota is checked without going through all the mappings. The totals are shared by the clients
+//! built from the same factory.
+use super::{to_response_status, KeyIdentity, KeyInfo, ManageKeyInfo};
+use crate::authenticators::ApplicationIdentity;
+use crate::providers::ProviderIdentity;
+use parsec_interface::requests::Result;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+/// Number of bytes accounted to the application owning a key for its mapping: its name and its
+/// serialized key information.
+pub(super) fn stored_size(key_identity: &KeyIdentity, key_info: &KeyInfo) -> usize {
+    let key_info_size = bincode::serialized_size(key_info)
+        .ok()
+        .and_then(|size| usize::try_from(size).ok())
+        .unwrap_or(usize::MAX);
+    key_identity.key_name.len().saturating_add(key_info_size)
+}
+
+/// Bytes stored by the applications, by provider
+#[derive(Debug, Default)]
+pub(super) struct StorageUsage {
+    // Totals of the providers counted so far, by application
+    totals: Mutex<HashMap<ProviderIdentity, HashMap<ApplicationIdentity, usize>>>,
+}
+
+impl StorageUsage {
+    /// Number of bytes stored for the keys of the matching applications in the provider.
+    pub(super) fn usage(
+        &self,
+        key_info_manager_impl: &(dyn ManageKeyInfo + Send + Sync),
+        provider: &ProviderIdentity,
+        owned: impl Fn(&ApplicationIdentity) -> bool,
+    ) -> Result<usize> {
+        let mut totals = self.totals.lock().expect("Storage usage lock poisoned");
+        if !totals.contains_key(provider) {
+            let provider_totals = count(key_info_manager_impl, provider)?;
+            let _ = totals.insert(provider.clone(), provider_totals);
+        }
+        Ok(totals[provider]
+            .iter()
+            .filter(|(application, _)| owned(application))
+            .fold(0, |usage, (_, total)| usage.saturating_add(*total)))
+    }
+
+    /// Record that a mapping of `stored` bytes took the place of one of `previous` bytes for a key,
+    /// 0 standing for no mapping.
+    pub(super) fn record(&self, key_identity: &KeyIdentity, previous: usize, stored: usize) {
+        let mut totals = self.totals.lock().expect("Storage usage lock poisoned");
+        // Providers not counted yet will be from the stored mappings.
+        let provider_totals = match totals.get_mut(&key_identity.provider) {
+            Some(provider_totals) => provider_totals,
+            None => return,
+        };
+        let total = provider_totals
+            .entry(key_identity.application.clone())
+            .or_insert(0);
+        *total = total.saturating_sub(previous).saturating_add(stored);
+        if *total == 0 {
+            let _ = provider_totals.remove(&key_identity.application);
+        }
+    }
+
+    /// Count the provider again from its stored mappings, which were changed without going
+    /// through its clients.
+    pub(super) fn forget(&self, provider: &ProviderIdentity) {
+        let _ = self
+            .totals
+            .lock()
+            .expect("Storage usage lock poisoned")
+            .remove(provider);
+    }
+}
+
+/// Count the bytes stored by each application in the provider.
+fn count(
+    key_info_manager_impl: &(dyn ManageKeyInfo + Send + Sync),
+    provider: &ProviderIdentity,
+) -> Result<HashMap<ApplicationIdentity, usize>> {
+    let mut totals = HashMap::new();
+    for key_identity in key_info_manager_impl
+        .get_all(provider.clone())
+        .map_err(to_response_status)?
+    {
+        if let Some(key_info) = key_info_manager_impl
+            .get(&key_identity)
+            .map_err(to_response_status)?
+        {
+            let total = totals
+                .entry(key_identity.application.clone())
+                .or_insert(0usize);
+            *total = total.saturating_add(stored_size(&key_identity, key_info));
+        }
+    }
+    Ok(totals)
+}
@@ -0,0 +1,822 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Replication of the key mappings to a standby instance
+//!
+//! In an active/standby deployment where both instances use the same key storage, for example a
+//! networked HSM, the active instance streams every change made to its mappings to the standby
+//! one, so that the standby instance knows all the keys when it takes over.
+//!
+//! The active instance connects to a Unix Domain Socket of the standby instance, announcing its
+//! providers. The socket is only accessible to the user running the standby instance, which also
+//! checks the UID of the peer of each connection. The standby instance only accepts the connection
+//! if it has the same providers, with the same names and UUIDs. The active instance then sends all
+//! its mappings, which replace the ones of the standby instance once they are all checked, and
+//! each change as it is made. It reconnects and sends everything
+//! again if the connection is lost. Instances on different hosts can be connected by forwarding the
+//! socket through an authenticated and encrypted channel, like an SSH tunnel.
+use super::quota::StorageUsage;
+use super::{KeyIdentity, KeyInfo, ManageKeyInfo};
+use crate::authenticators::ApplicationIdentity;
+use crate::front::domain_socket::peer_credentials;
+use crate::providers::ProviderIdentity;
+use crate::utils::config::{ReplicationConfig, ReplicationRole};
+use derivative::Derivative;
+use log::{error, info, warn};
+use num_traits::FromPrimitive;
+use parsec_interface::requests::AuthType;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Version of the replication protocol
+const PROTOCOL_VERSION: u32 = 1;
+/// Maximum size of a message, bounding the size of the mappings sent at once
+const MAX_MESSAGE_LEN: usize = 64 << 20;
+/// Interval at which the active instance sends a message, even without any change
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+/// Time after which the standby instance considers that the active instance is gone
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Delay between two connection attempts of the active instance
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Identity of a key, as sent between instances
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+struct RecordKey {
+    provider_uuid: String,
+    provider_name: String,
+    application: String,
+    auth_type: u8,
+    key_name: String,
+}
+
+impl From<&KeyIdentity> for RecordKey {
+    fn from(key_identity: &KeyIdentity) -> Self {
+        RecordKey {
+            provider_uuid: key_identity.provider().uuid().clone(),
+            provider_name: key_identity.provider().name().clone(),
+            application: key_identity.application().name().clone(),
+            auth_type: *key_identity.application().authenticator_id() as u8,
+            key_name: key_identity.key_name().clone(),
+        }
+    }
+}
+
+impl RecordKey {
+    fn key_identity(&self) -> Result<KeyIdentity> {
+        let auth_type = AuthType::from_u8(self.auth_type)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid authentication type"))?;
+        Ok(KeyIdentity::new(
+            ApplicationIdentity::new(self.application.clone(), auth_type),
+            ProviderIdentity::new(self.provider_uuid.clone(), self.provider_name.clone()),
+            self.key_name.clone(),
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Record {
+    key: RecordKey,
+    key_info: KeyInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Message {
+    Hello {
+        version: u32,
+        providers: Vec<(String, String)>,
+    },
+    Welcome,
+    Rejected(String),
+    Snapshot(Vec<Record>),
+    Insert(Record),
+    Remove(RecordKey),
+    Heartbeat,
+}
+
+fn write_message(stream: &mut impl Write, message: &Message) -> Result<()> {
+    let bytes = bincode::serialize(message).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let len: u32 = bytes
+        .len()
+        .try_into()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "message too long"))?;
+    stream.write_all(&len.to_le_bytes())?;
+    stream.write_all(&bytes)
+}
+
+fn read_message(stream: &mut impl Read) -> Result<Message> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, "message too long"));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes)?;
+    bincode::deserialize(&bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Mappings of a provider, as replicated
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct Replica {
+    provider: ProviderIdentity,
+    #[derivative(Debug = "ignore")]
+    manager: Arc<RwLock<dyn ManageKeyInfo + Send + Sync>>,
+    #[derivative(Debug = "ignore")]
+    public_keys: Arc<RwLock<HashMap<KeyIdentity, Vec<u8>>>>,
+    storage_usage: Arc<StorageUsage>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    role: ReplicationRole,
+    socket_path: PathBuf,
+    active_uid: u32,
+    replicas: Mutex<Vec<Replica>>,
+    // Changes not sent yet, only recorded while connected to the standby instance.
+    queue: Mutex<Option<VecDeque<Message>>>,
+    queued: Condvar,
+    stop: AtomicBool,
+}
+
+/// Replication of the mappings of the providers, stopped when dropped
+#[derive(Debug)]
+pub struct Replication {
+    shared: Arc<Shared>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Replication {
+    /// Create the replication described by the configuration. It is started with `start`, once the
+    /// providers are registered.
+    pub fn new(config: &ReplicationConfig) -> Arc<Self> {
+        Arc::new(Replication {
+            shared: Arc::new(Shared {
+                role: config.role,
+                socket_path: config.socket_path.clone().into(),
+                // Safety: geteuid has no preconditions and cannot fail.
+                active_uid: config
+                    .active_uid
+                    .unwrap_or_else(|| unsafe { libc::geteuid() }),
+                replicas: Mutex::new(Vec::new()),
+                queue: Mutex::new(None),
+                queued: Condvar::new(),
+                stop: AtomicBool::new(false),
+            }),
+            thread: Mutex::new(None),
+        })
+    }
+
+    /// Replicate the mappings of a provider.
+    pub(super) fn register(
+        &self,
+        provider: ProviderIdentity,
+        manager: Arc<RwLock<dyn ManageKeyInfo + Send + Sync>>,
+        public_keys: Arc<RwLock<HashMap<KeyIdentity, Vec<u8>>>>,
+        storage_usage: Arc<StorageUsage>,
+    ) {
+        self.shared
+            .replicas
+            .lock()
+            .expect("Replication lock poisoned")
+            .push(Replica {
+                provider,
+                manager,
+                public_keys,
+                storage_usage,
+            });
+    }
+
+    /// Record the insertion of a mapping. Must be called while holding the write lock of the Key
+    /// Info Manager, so that changes are sent in the order they were made.
+    pub(super) fn record_insert(&self, key_identity: &KeyIdentity, key_info: &KeyInfo) {
+        self.shared.record(|| {
+            Message::Insert(Record {
+                key: key_identity.into(),
+                key_info: key_info.clone(),
+            })
+        });
+    }
+
+    /// Record the removal of a mapping, with the same constraint as `record_insert`.
+    pub(super) fn record_remove(&self, key_identity: &KeyIdentity) {
+        self.shared.record(|| Message::Remove(key_identity.into()));
+    }
+
+    /// Start replicating: connect to the standby instance, or wait for the active one.
+    pub fn start(&self) -> Result<()> {
+        let shared = self.shared.clone();
+        let thread = match self.shared.role {
+            ReplicationRole::Active => thread::Builder::new()
+                .name("kim-replication".to_string())
+                .spawn(move || shared.run_active())?,
+            ReplicationRole::Standby => {
+                let listener = self.shared.bind()?;
+                thread::Builder::new()
+                    .name("kim-replication".to_string())
+                    .spawn(move || shared.run_standby(listener))?
+            }
+        };
+        *self.thread.lock().expect("Replication lock poisoned") = Some(thread);
+        Ok(())
+    }
+}
+
+impl Drop for Replication {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        self.shared.queued.notify_all();
+        if let Some(thread) = self
+            .thread
+            .lock()
+            .expect("Replication lock poisoned")
+            .take()
+        {
+            if thread.join().is_err() {
+                error!("The replication thread panicked.");
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Acquire)
+    }
+
+    fn record(&self, message: impl FnOnce() -> Message) {
+        if self.role != ReplicationRole::Active {
+            return;
+        }
+        if let Some(queue) = self
+            .queue
+            .lock()
+            .expect("Replication lock poisoned")
+            .as_mut()
+        {
+            queue.push_back(message());
+            self.queued.notify_one();
+        }
+    }
+
+    fn providers(&self) -> Vec<(String, String)> {
+        self.replicas
+            .lock()
+            .expect("Replication lock poisoned")
+            .iter()
+            .map(|replica| {
+                (
+                    replica.provider.uuid().clone(),
+                    replica.provider.name().clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn snapshot(&self) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        for replica in self
+            .replicas
+            .lock()
+            .expect("Replication lock poisoned")
+            .iter()
+        {
+            let manager = replica
+                .manager
+                .read()
+                .expect("Key Info Manager lock poisoned");
+            let key_identities = manager
+                .get_all(replica.provider.clone())
+                .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            for key_identity in key_identities {
+                if let Some(key_info) = manager
+                    .get(&key_identity)
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?
+                {
+                    records.push(Record {
+                        key: (&key_identity).into(),
+                        key_info: key_info.clone(),
+                    });
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let queue = self.queue.lock().expect("Replication lock poisoned");
+        let _ = self
+            .queued
+            .wait_timeout_while(queue, duration, |_| !self.stopped())
+            .expect("Replication lock poisoned");
+    }
+
+    fn run_active(&self) {
+        let mut connected = true;
+        while !self.stopped() {
+            match UnixStream::connect(&self.socket_path) {
+                Ok(stream) => {
+                    connected = true;
+                    if let Err(e) = self.send_to(stream) {
+                        warn!("Replication to the standby instance interrupted ({}).", e);
+                    }
+                    *self.queue.lock().expect("Replication lock poisoned") = None;
+                }
+                Err(e) if connected => {
+                    warn!("Can not connect to the standby instance ({}), retrying.", e);
+                    connected = false;
+                }
+                Err(_) => (),
+            }
+            self.sleep(RETRY_INTERVAL);
+        }
+    }
+
+    fn send_to(&self, mut stream: UnixStream) -> Result<()> {
+        stream.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+        write_message(
+            &mut stream,
+            &Message::Hello {
+                version: PROTOCOL_VERSION,
+                providers: self.providers(),
+            },
+        )?;
+        match read_message(&mut stream)? {
+            Message::Welcome => (),
+            Message::Rejected(reason) => {
+                error!("The standby instance rejected the replication: {}.", reason);
+                return Err(Error::new(ErrorKind::InvalidData, "replication rejected"));
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unexpected message")),
+        }
+
+        // Changes are recorded before the snapshot is made: those made in between are sent twice,
+        // which is harmless as they are applied in order.
+        *self.queue.lock().expect("Replication lock poisoned") = Some(VecDeque::new());
+        let snapshot = self.snapshot()?;
+        info!(
+            "Replicating {} key mapping(s) to the standby instance.",
+            snapshot.len()
+        );
+        write_message(&mut stream, &Message::Snapshot(snapshot))?;
+
+        while !self.stopped() {
+            let messages: Vec<Message> = {
+                let queue = self.queue.lock().expect("Replication lock poisoned");
+                let (mut queue, _) = self
+                    .queued
+                    .wait_timeout_while(queue, HEARTBEAT_INTERVAL, |queue| {
+                        !self.stopped() && queue.as_ref().map_or(false, VecDeque::is_empty)
+                    })
+                    .expect("Replication lock poisoned");
+                queue
+                    .as_mut()
+                    .map_or_else(Vec::new, |queue| queue.drain(..).collect())
+            };
+            if messages.is_empty() {
+                write_message(&mut stream, &Message::Heartbeat)?;
+            }
+            for message in messages {
+                write_message(&mut stream, &message)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn bind(&self) -> Result<UnixListener> {
+        if self.socket_path.exists() {
+            fs::remove_file(&self.socket_path)?;
+        }
+        // Only the user running the service can replicate to it. The socket is created in a
+        // private directory, in which no one else can connect to it before its permissions are
+        // restricted, and then moved in place.
+        let mut private_dir = self.socket_path.clone().into_os_string();
+        private_dir.push(".d");
+        let private_dir = PathBuf::from(private_dir);
+        if private_dir.exists() {
+            fs::remove_dir_all(&private_dir)?;
+        }
+        fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+        let private_path = private_dir.join("socket");
+        let listener = UnixListener::bind(&private_path).and_then(|listener| {
+            fs::set_permissions(&private_path, fs::Permissions::from_mode(0o600))?;
+            fs::rename(&private_path, &self.socket_path)?;
+            Ok(listener)
+        });
+        let _ = fs::remove_dir_all(&private_dir);
+        let listener = listener?;
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    fn run_standby(&self, listener: UnixListener) {
+        info!("Waiting for the active instance to replicate its key mappings.");
+        while !self.stopped() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = self.receive_from(stream) {
+                        warn!("Replication from the active instance interrupted ({}).", e);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100))
+                }
+                Err(e) => {
+                    warn!("Failed to accept a replication connection ({}).", e);
+                    thread::sleep(RETRY_INTERVAL);
+                }
+            }
+        }
+    }
+
+    fn receive_from(&self, mut stream: UnixStream) -> Result<()> {
+        let uid = peer_credentials::peer_cred(&stream)?.uid;
+        if uid != self.active_uid {
+            error!(
+                "Closed a replication connection from UID {} instead of the UID {} of the active instance.",
+                uid, self.active_uid
+            );
+            return Ok(());
+        }
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+        match read_message(&mut stream)? {
+            Message::Hello { version, providers } => {
+                if let Err(reason) = self.check_hello(version, providers) {
+                    error!("Rejected the replication: {}.", reason);
+                    write_message(&mut stream, &Message::Rejected(reason))?;
+                    return Ok(());
+                }
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unexpected message")),
+        }
+        write_message(&mut stream, &Message::Welcome)?;
+
+        while !self.stopped() {
+            match read_message(&mut stream)? {
+                Message::Snapshot(records) => {
+                    info!(
+                        "Received {} key mapping(s) from the active instance.",
+                        records.len()
+                    );
+                    self.apply_snapshot(records)?
+                }
+                Message::Insert(record) => self.apply_insert(record)?,
+                Message::Remove(key) => self.apply_remove(&key)?,
+                Message::Heartbeat => (),
+                _ => return Err(Error::new(ErrorKind::InvalidData, "unexpected message")),
+            }
+        }
+        Ok(())
+    }
+
+    fn check_hello(
+        &self,
+        version: u32,
+        providers: Vec<(String, String)>,
+    ) -> std::result::Result<(), String> {
+        if version != PROTOCOL_VERSION {
+            return Err(format!("unsupported protocol version {}", version));
+        }
+        let active: HashSet<_> = providers.into_iter().collect();
+        let standby: HashSet<_> = self.providers().into_iter().collect();
+        if active != standby {
+            return Err(format!(
+                "the providers differ, active instance: {:?}, standby instance: {:?}",
+                active, standby
+            ));
+        }
+        Ok(())
+    }
+
+    fn with_replica<T>(
+        &self,
+        key: &RecordKey,
+        f: impl FnOnce(&Replica, KeyIdentity) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let replicas = self.replicas.lock().expect("Replication lock poisoned");
+        match replicas.iter().find(|replica| {
+            *replica.provider.uuid() == key.provider_uuid
+                && *replica.provider.name() == key.provider_name
+        }) {
+            Some(replica) => Ok(Some(f(replica, key.key_identity()?)?)),
+            None => {
+                warn!(
+                    "Ignored a key mapping of the unknown provider \"{}\".",
+                    key.provider_name
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    fn apply_insert(&self, record: Record) -> Result<()> {
+        let Record { key, key_info } = record;
+        let _ = self.with_replica(&key, |replica, key_identity| {
+            // Cleared under the lock of the mappings, like the clients do.
+            let mut manager = replica
+                .manager
+                .write()
+                .expect("Key Info Manager lock poisoned");
+            let _ = replica
+                .public_keys
+                .write()
+                .expect("Public key cache lock poisoned")
+                .remove(&key_identity);
+            let _ = manager
+                .insert(key_identity, key_info)
+                .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            replica.storage_usage.forget(&replica.provider);
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn apply_remove(&self, key: &RecordKey) -> Result<()> {
+        let _ = self.with_replica(key, |replica, key_identity| {
+            // Cleared under the lock of the mappings, like the clients do.
+            let mut manager = replica
+                .manager
+                .write()
+                .expect("Key Info Manager lock poisoned");
+            let _ = replica
+                .public_keys
+                .write()
+                .expect("Public key cache lock poisoned")
+                .remove(&key_identity);
+            let _ = manager
+                .remove(&key_identity)
+                .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            replica.storage_usage.forget(&replica.provider);
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Check that the mappings of a snapshot can all be applied, before any of them is.
+    fn check_snapshot(&self, records: &[Record]) -> Result<()> {
+        let replicas = self.replicas.lock().expect("Replication lock poisoned");
+        let mut keys = HashSet::new();
+        for record in records {
+            let _ = record.key.key_identity()?;
+            if !replicas.iter().any(|replica| {
+                *replica.provider.uuid() == record.key.provider_uuid
+                    && *replica.provider.name() == record.key.provider_name
+            }) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "snapshot with a key mapping of the unknown provider \"{}\"",
+                        record.key.provider_name
+                    ),
+                ));
+            }
+            if !keys.insert(&record.key) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "snapshot with a duplicate key mapping",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_snapshot(&self, records: Vec<Record>) -> Result<()> {
+        self.check_snapshot(&records)?;
+        let replicated: HashSet<RecordKey> =
+            records.iter().map(|record| record.key.clone()).collect();
+        let local: Vec<RecordKey> = {
+            let replicas = self.replicas.lock().expect("Replication lock poisoned");
+            let mut local = Vec::new();
+            for replica in replicas.iter() {
+                let key_identities = replica
+                    .manager
+                    .read()
+                    .expect("Key Info Manager lock poisoned")
+                    .get_all(replica.provider.clone())
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+                local.extend(key_identities.iter().map(RecordKey::from));
+            }
+            local
+        };
+        for key in local.iter().filter(|key| !replicated.contains(key)) {
+            self.apply_remove(key)?;
+        }
+        for record in records {
+            self.apply_insert(record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::*;
+    use crate::key_info_managers::{KeyInfoManagerClient, KeyInfoManagerFactory};
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::operations::psa_algorithm::Algorithm;
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, Lifetime, Policy, Type, UsageFlags,
+    };
+    use std::path::Path;
+    use std::time::Instant;
+
+    fn attributes() -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::RsaKeyPair,
+            bits: 1024,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: Algorithm::None,
+            },
+        }
+    }
+
+    fn client(
+        dir: &Path,
+        role: ReplicationRole,
+        active_uid: Option<u32>,
+    ) -> (Arc<Replication>, KeyInfoManagerClient) {
+        let replication = Replication::new(&ReplicationConfig {
+            role,
+            socket_path: dir.join("replication.sock").display().to_string(),
+            active_uid,
+        });
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(
+                    dir.join(format!("{:?}.sqlite3", role))
+                        .display()
+                        .to_string(),
+                ),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap()
+        .with_replication(replication.clone());
+        let client = factory.build_client(ProviderIdentity::new(
+            "uuid".to_string(),
+            "provider".to_string(),
+        ));
+        (replication, client)
+    }
+
+    fn key(client: &KeyInfoManagerClient, name: &str) -> KeyIdentity {
+        client.get_key_identity(
+            ApplicationIdentity::new("alice".to_string(), AuthType::Direct),
+            name.to_string(),
+        )
+    }
+
+    fn keys(client: &KeyInfoManagerClient) -> Vec<String> {
+        let mut keys: Vec<String> = client
+            .get_all()
+            .unwrap()
+            .iter()
+            .map(|key| key.key_name().clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn wait_for_keys(client: &KeyInfoManagerClient, names: &[&str]) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let keys = keys(client);
+            if keys == names {
+                return;
+            }
+            assert!(Instant::now() < deadline, "standby holds {:?}", keys);
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn record(client: &KeyInfoManagerClient, name: &str) -> Record {
+        Record {
+            key: (&key(client, name)).into(),
+            key_info: KeyInfo {
+                id: vec![1, 0, 0, 0],
+                attributes: attributes(),
+            },
+        }
+    }
+
+    #[test]
+    fn mappings_are_replicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (active, active_client) = client(dir.path(), ReplicationRole::Active, None);
+        let (standby, standby_client) = client(dir.path(), ReplicationRole::Standby, None);
+        active_client
+            .insert_key_info(key(&active_client, "before"), &1u32, attributes())
+            .unwrap();
+        // Unknown to the active instance, removed by the first snapshot.
+        standby_client
+            .insert_key_info(key(&standby_client, "stale"), &2u32, attributes())
+            .unwrap();
+
+        standby.start().unwrap();
+        active.start().unwrap();
+        wait_for_keys(&standby_client, &["before"]);
+
+        active_client
+            .insert_key_info(key(&active_client, "after"), &3u32, attributes())
+            .unwrap();
+        active_client
+            .remove_key_info(&key(&active_client, "before"))
+            .unwrap();
+        wait_for_keys(&standby_client, &["after"]);
+        assert_eq!(
+            standby_client
+                .get_key_id::<u32>(&key(&standby_client, "after"))
+                .unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn socket_is_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let (standby, _) = client(dir.path(), ReplicationRole::Standby, None);
+        standby.start().unwrap();
+        let socket_path = dir.path().join("replication.sock");
+        let metadata = fs::metadata(&socket_path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert!(!dir.path().join("replication.sock.d").exists());
+        assert!(UnixStream::connect(&socket_path).is_ok());
+    }
+
+    #[test]
+    fn connections_of_other_users_are_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        // Safety: geteuid has no preconditions and cannot fail.
+        let other_uid = unsafe { libc::geteuid() }.wrapping_add(1);
+        let (standby, _) = client(dir.path(), ReplicationRole::Standby, Some(other_uid));
+        standby.start().unwrap();
+
+        let mut stream = UnixStream::connect(dir.path().join("replication.sock")).unwrap();
+        stream.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        // The standby can close the connection before the message is written.
+        let _ = write_message(
+            &mut stream,
+            &Message::Hello {
+                version: PROTOCOL_VERSION,
+                providers: vec![("uuid".to_string(), "provider".to_string())],
+            },
+        );
+        // Closed without an answer, the Hello message being unread.
+        assert!(matches!(
+            read_message(&mut stream).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset
+        ));
+    }
+
+    #[test]
+    fn invalid_snapshots_are_not_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let (standby, standby_client) = client(dir.path(), ReplicationRole::Standby, None);
+        standby_client
+            .insert_key_info(key(&standby_client, "stale"), &2u32, attributes())
+            .unwrap();
+
+        let mut unknown_provider = record(&standby_client, "unknown");
+        unknown_provider.key.provider_name = "other-provider".to_string();
+        let mut invalid_auth_type = record(&standby_client, "invalid");
+        invalid_auth_type.key.auth_type = u8::MAX;
+        for invalid in [
+            unknown_provider,
+            invalid_auth_type,
+            record(&standby_client, "new"),
+        ] {
+            assert_eq!(
+                standby
+                    .shared
+                    .apply_snapshot(vec![record(&standby_client, "new"), invalid])
+                    .unwrap_err()
+                    .kind(),
+                ErrorKind::InvalidData
+            );
+            assert_eq!(keys(&standby_client), ["stale"]);
+        }
+
+        standby
+            .shared
+            .apply_snapshot(vec![record(&standby_client, "new")])
+            .unwrap();
+        assert_eq!(keys(&standby_client), ["new"]);
+    }
+}
@@ -0,0 +1,548 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Signatures made with each key
+//!
+//! Keys acting as certificate authorities, or signing anything else of value, often come with a
+//! limit on the number of signatures they may make. The Key Info Managers can count the
+//! signatures of every key, keep the time of its last one and refuse the signing requests of keys
+//! which reached their limit, set for every key or for single keys in the configuration.
+//!
+//! The counters are audited with `parsec admin signature-counters`, which reads the database. The
+//! clients of the service can not read them: no operation of the wire protocol carries metadata of
+//! a key other than its attributes.
+//!
+//! The counters are stored in an SQLite database, but not at every signature: on flash storage
+//! that would wear the device out. A counter is written when it goes past the count last stored,
+//! with a whole batch of signatures reserved ahead. A service which stops uncleanly thus finds
+//! counters at most a batch too high, never too low, so that a limit cannot be overrun. The exact
+//! counts are written when the service stops.
+//!
+//! With the write-behind of the metadata, the exact counters are written behind every signature
+//! instead, and the signatures journaled but not flushed are still counted after a crash.
+use super::lifecycle::{state_key, StateKey};
+use super::write_behind::WriteBehind;
+use super::{KeyIdentity, KeyInfoManagerClient};
+use crate::authenticators::ApplicationIdentity;
+use crate::utils::config::{SignatureCountersConfig, WriteBehindConfig};
+use crate::utils::logging::Redacted;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use num_traits::FromPrimitive;
+use parsec_interface::requests::{AuthType, ResponseStatus};
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::HashMap;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default path of the database holding the signature counters
+pub const DEFAULT_DB_PATH: &str = "/var/lib/parsec/kim-mappings/sqlite/signature-counters.sqlite3";
+
+/// Number of signatures reserved by default at each write of a counter
+const DEFAULT_WRITE_BATCH: u64 = 100;
+
+/// File permissions of the database, only accessible to the service
+const FILE_PERMISSION: u32 = 0o600;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Counter {
+    count: u64,
+    // Count stored in the database, reserving signatures ahead
+    stored_count: u64,
+    last_signature: Option<u64>,
+}
+
+/// Signatures made with a key, as stored in the database
+#[derive(Debug, Clone)]
+pub struct KeySignatures {
+    /// Application owning the key
+    pub owner: ApplicationIdentity,
+    /// Name of the provider of the key
+    pub provider_name: String,
+    /// Name of the key
+    pub key_name: String,
+    /// Signatures made. While the service runs without the write-behind, it includes the
+    /// signatures reserved ahead.
+    pub count: u64,
+    /// Time of the last signature
+    pub last_signature: Option<SystemTime>,
+    /// Number of signatures the key can make, if it is limited
+    pub limit: Option<u64>,
+}
+
+/// Signature counters of the keys, stored in a database
+#[derive(Debug)]
+pub struct SignatureCounters {
+    database_path: PathBuf,
+    write_batch: u64,
+    limit: Option<u64>,
+    // Limits of single keys, by owner and key name
+    key_limits: HashMap<(String, String), u64>,
+    counters: Mutex<HashMap<StateKey, Counter>>,
+    write_behind: Option<WriteBehind>,
+}
+
+impl SignatureCounters {
+    /// Open the database of the counters, creating it if it does not exist. The counters are
+    /// written behind the signatures if the write-behind is configured.
+    pub fn new(
+        config: &SignatureCountersConfig,
+        write_behind: Option<&WriteBehindConfig>,
+    ) -> Result<Self> {
+        let database_path = PathBuf::from(
+            config
+                .store_path
+                .clone()
+                .unwrap_or_else(|| String::from(DEFAULT_DB_PATH)),
+        );
+        if let Some(directory_path) = database_path.parent() {
+            fs::create_dir_all(directory_path)
+                .with_context(|| format!("create directory {:?}", directory_path))?;
+        }
+        let conn = Connection::open(&database_path)?;
+        fs::set_permissions(&database_path, Permissions::from_mode(FILE_PERMISSION))?;
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS key_signatures (
+                authenticator_id    INTEGER NOT NULL,
+                application_name    TEXT NOT NULL,
+                provider_name       TEXT NOT NULL,
+                key_name            TEXT NOT NULL,
+                count               INTEGER NOT NULL,
+                last_signature      INTEGER,
+                PRIMARY KEY (authenticator_id, application_name, provider_name, key_name)
+            );
+            ",
+        )?;
+        // Replaying the journal of a previous run before the counters are read.
+        let write_behind = match write_behind {
+            Some(config) => Some(WriteBehind::new(&database_path, config)?),
+            None => None,
+        };
+
+        let mut counters = HashMap::new();
+        let mut statement = conn.prepare(
+            "SELECT authenticator_id, application_name, provider_name, key_name, count,
+                    last_signature
+             FROM key_signatures",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let count = row.get(4)?;
+            let _ = counters.insert(
+                (row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?),
+                Counter {
+                    count,
+                    stored_count: count,
+                    last_signature: row.get(5)?,
+                },
+            );
+        }
+        drop(rows);
+        drop(statement);
+
+        Ok(SignatureCounters {
+            database_path,
+            write_batch: config.write_batch.unwrap_or(DEFAULT_WRITE_BATCH).max(1),
+            limit: config.max_signatures,
+            key_limits: key_limits(config),
+            counters: Mutex::new(counters),
+            write_behind,
+        })
+    }
+
+    fn lock_counters(&self) -> MutexGuard<'_, HashMap<StateKey, Counter>> {
+        self.counters
+            .lock()
+            .expect("Signature counters lock poisoned")
+    }
+
+    fn store(&self, key: &StateKey, counter: &Counter) -> rusqlite::Result<()> {
+        const STATEMENT: &str = "REPLACE INTO key_signatures VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        if let Some(write_behind) = &self.write_behind {
+            write_behind.write(
+                format!("{:?}", key),
+                STATEMENT,
+                vec![
+                    u64::from(key.0).into(),
+                    key.1.as_str().into(),
+                    key.2.as_str().into(),
+                    key.3.as_str().into(),
+                    counter.stored_count.into(),
+                    counter.last_signature.into(),
+                ],
+            );
+            return Ok(());
+        }
+        let conn = Connection::open(&self.database_path)?;
+        let _ = conn.execute(
+            STATEMENT,
+            params![
+                key.0,
+                key.1,
+                key.2,
+                key.3,
+                counter.stored_count,
+                counter.last_signature
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Count a signature about to be made, refusing it if the key reached its limit.
+    fn reserve(&self, key_identity: &KeyIdentity) -> parsec_interface::requests::Result<()> {
+        let mut counters = self.lock_counters();
+        let counter = counters.entry(state_key(key_identity)).or_default();
+        let limit = self
+            .key_limits
+            .get(&(
+                key_identity.application().name().clone(),
+                key_identity.key_name().clone(),
+            ))
+            .copied()
+            .or(self.limit);
+        if let Some(limit) = limit {
+            if counter.count >= limit {
+                warn!(
+                    "Signature refused: the key \"{}\" made its {} signatures.",
+                    Redacted(key_identity.key_name()),
+                    limit
+                );
+                return Err(ResponseStatus::PsaErrorNotPermitted);
+            }
+        }
+        counter.count += 1;
+        Ok(())
+    }
+
+    /// Record the outcome of a signature reserved.
+    fn release(&self, key_identity: &KeyIdentity, signed: bool) {
+        let key = state_key(key_identity);
+        let mut counters = self.lock_counters();
+        let counter = match counters.get_mut(&key) {
+            Some(counter) => counter,
+            None => return,
+        };
+        if !signed {
+            counter.count = counter.count.saturating_sub(1);
+            return;
+        }
+        counter.last_signature = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .ok();
+        if self.write_behind.is_some() {
+            counter.stored_count = counter.count;
+            // Only queued, it does not fail.
+            let _ = self.store(&key, counter);
+        } else if counter.count > counter.stored_count {
+            let stored_count = counter.stored_count;
+            counter.stored_count = counter.count + self.write_batch - 1;
+            if let Err(e) = self.store(&key, counter) {
+                counter.stored_count = stored_count;
+                format_error!("Failed to store the signature counter of a key", e);
+            }
+        }
+    }
+
+    /// Forget the counter of a key which was destroyed, its name being free again.
+    pub(super) fn forget(&self, key_identity: &KeyIdentity) {
+        let key = state_key(key_identity);
+        let _ = self.lock_counters().remove(&key);
+        const STATEMENT: &str = "DELETE FROM key_signatures WHERE authenticator_id = ?1
+                                 AND application_name = ?2 AND provider_name = ?3
+                                 AND key_name = ?4";
+        if let Some(write_behind) = &self.write_behind {
+            // Replacing the pending write of the counter.
+            write_behind.write(
+                format!("{:?}", key),
+                STATEMENT,
+                vec![
+                    u64::from(key.0).into(),
+                    key.1.as_str().into(),
+                    key.2.as_str().into(),
+                    key.3.as_str().into(),
+                ],
+            );
+            return;
+        }
+        let result = Connection::open(&self.database_path)
+            .and_then(|conn| conn.execute(STATEMENT, params![key.0, key.1, key.2, key.3]));
+        if let Err(e) = result {
+            format_error!("Failed to remove the signature counter of a key", e);
+        }
+    }
+}
+
+/// Limits of single keys in the configuration, by owner and key name
+fn key_limits(config: &SignatureCountersConfig) -> HashMap<(String, String), u64> {
+    config
+        .key_limit
+        .iter()
+        .flatten()
+        .map(|key_limit| {
+            (
+                (key_limit.owner.clone(), key_limit.key_name.clone()),
+                key_limit.max_signatures,
+            )
+        })
+        .collect()
+}
+
+/// Signatures made with the keys, read from the database of the configuration, by owner, provider
+/// and key name. The database is only read, for example by `parsec admin` while the service is
+/// running.
+pub fn read(config: &SignatureCountersConfig) -> Result<Vec<KeySignatures>> {
+    let database_path = config
+        .store_path
+        .clone()
+        .unwrap_or_else(|| String::from(DEFAULT_DB_PATH));
+    let conn = Connection::open_with_flags(&database_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("open {:?}", database_path))?;
+    let rows = conn
+        .prepare(
+            "SELECT authenticator_id, application_name, provider_name, key_name, count,
+                    last_signature
+             FROM key_signatures ORDER BY application_name, provider_name, key_name",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u8>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, u64>(4)?,
+                row.get::<_, Option<u64>>(5)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let key_limits = key_limits(config);
+    rows.into_iter()
+        .map(
+            |(authenticator_id, owner, provider_name, key_name, count, last_signature)| {
+                let auth_type = AuthType::from_u8(authenticator_id)
+                    .ok_or_else(|| anyhow!("the counter of key {} is corrupted", key_name))?;
+                let limit = key_limits
+                    .get(&(owner.clone(), key_name.clone()))
+                    .copied()
+                    .or(config.max_signatures);
+                Ok(KeySignatures {
+                    owner: ApplicationIdentity::new(owner, auth_type),
+                    provider_name,
+                    key_name,
+                    count,
+                    last_signature: last_signature
+                        .map(|time| UNIX_EPOCH + Duration::from_secs(time)),
+                    limit,
+                })
+            },
+        )
+        .collect()
+}
+
+impl Drop for SignatureCounters {
+    fn drop(&mut self) {
+        // The exact counts, releasing the signatures reserved.
+        let counters = self.lock_counters().clone();
+        for (key, mut counter) in counters {
+            if counter.stored_count != counter.count {
+                counter.stored_count = counter.count;
+                if let Err(e) = self.store(&key, &counter) {
+                    format_error!("Failed to store the signature counter of a key", e);
+                }
+            }
+        }
+    }
+}
+
+impl KeyInfoManagerClient {
+    /// Count a signature about to be made with a key. Returns whether it was counted, which it is
+    /// not if the signatures are not counted or the key does not exist, the provider answering
+    /// the request then.
+    ///
+    /// # Errors
+    ///
+    /// If the key reached its limit, PsaErrorNotPermitted is returned.
+    pub fn reserve_signature(
+        &self,
+        key_identity: &KeyIdentity,
+    ) -> parsec_interface::requests::Result<bool> {
+        let signature_counters = match &self.signature_counters {
+            Some(signature_counters) => signature_counters,
+            None => return Ok(false),
+        };
+        match self.get_key_attributes(key_identity) {
+            Err(ResponseStatus::PsaErrorDoesNotExist) => return Ok(false),
+            result => {
+                let _ = result?;
+            }
+        }
+        signature_counters.reserve(key_identity)?;
+        Ok(true)
+    }
+
+    /// Record whether a signature counted by `reserve_signature` was made.
+    pub fn release_signature(&self, key_identity: &KeyIdentity, signed: bool) {
+        if let Some(signature_counters) = &self.signature_counters {
+            signature_counters.release(key_identity, signed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::authenticators::ApplicationIdentity;
+    use crate::providers::ProviderIdentity;
+    use crate::utils::config::KeySignatureLimitConfig;
+
+    fn config(name: &str, max_signatures: Option<u64>) -> SignatureCountersConfig {
+        let db_path = format!("{}/kim/sqlite/{}.sqlite3", env!("OUT_DIR"), name);
+        let _ = fs::remove_file(&db_path);
+        // Left by the tests of the write-behind crashing
+        let _ = fs::remove_file(format!("{}.write-behind", db_path));
+        SignatureCountersConfig {
+            store_path: Some(db_path),
+            write_batch: Some(10),
+            max_signatures,
+            key_limit: None,
+        }
+    }
+
+    fn key_identity() -> KeyIdentity {
+        KeyIdentity::new(
+            ApplicationIdentity::new(String::from("app"), AuthType::Direct),
+            ProviderIdentity::new(String::from("uuid"), String::from("provider")),
+            String::from("key"),
+        )
+    }
+
+    fn count(counters: &SignatureCounters) -> u64 {
+        counters
+            .lock_counters()
+            .get(&state_key(&key_identity()))
+            .map_or(0, |counter| counter.count)
+    }
+
+    fn sign(counters: &SignatureCounters, times: usize) {
+        for _ in 0..times {
+            counters.reserve(&key_identity()).unwrap();
+            counters.release(&key_identity(), true);
+        }
+    }
+
+    #[test]
+    fn only_signatures_made_are_counted() {
+        let counters = SignatureCounters::new(&config("counters_made", None), None).unwrap();
+        sign(&counters, 3);
+        counters.reserve(&key_identity()).unwrap();
+        counters.release(&key_identity(), false);
+        assert_eq!(count(&counters), 3);
+        assert!(counters.lock_counters()[&state_key(&key_identity())]
+            .last_signature
+            .is_some());
+    }
+
+    #[test]
+    fn unclean_stops_find_the_batch_reserved() {
+        let config = config("counters_unclean", None);
+        let counters = SignatureCounters::new(&config, None).unwrap();
+        sign(&counters, 3);
+        std::mem::forget(counters);
+        assert_eq!(count(&SignatureCounters::new(&config, None).unwrap()), 10);
+    }
+
+    #[test]
+    fn clean_stops_write_the_exact_count() {
+        let config = config("counters_clean", None);
+        let counters = SignatureCounters::new(&config, None).unwrap();
+        sign(&counters, 13);
+        drop(counters);
+        assert_eq!(count(&SignatureCounters::new(&config, None).unwrap()), 13);
+    }
+
+    #[test]
+    fn keys_which_made_their_limit_are_refused() {
+        let counters = SignatureCounters::new(&config("counters_limit", Some(2)), None).unwrap();
+        sign(&counters, 2);
+        assert_eq!(
+            counters.reserve(&key_identity()),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(count(&counters), 2);
+    }
+
+    #[test]
+    fn key_limits_take_precedence() {
+        let mut config = config("counters_key_limit", Some(2));
+        config.key_limit = Some(vec![KeySignatureLimitConfig {
+            owner: String::from("app"),
+            key_name: String::from("key"),
+            max_signatures: 3,
+        }]);
+        let counters = SignatureCounters::new(&config, None).unwrap();
+        sign(&counters, 3);
+        assert_eq!(
+            counters.reserve(&key_identity()),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn counters_are_read_from_the_database() {
+        let config = config("counters_read", Some(20));
+        let counters = SignatureCounters::new(&config, None).unwrap();
+        sign(&counters, 3);
+        drop(counters);
+        let key_signatures = read(&config).unwrap();
+        assert_eq!(key_signatures.len(), 1);
+        assert_eq!(key_signatures[0].owner.name(), "app");
+        assert_eq!(key_signatures[0].provider_name, "provider");
+        assert_eq!(key_signatures[0].key_name, "key");
+        assert_eq!(key_signatures[0].count, 3);
+        assert!(key_signatures[0].last_signature.is_some());
+        assert_eq!(key_signatures[0].limit, Some(20));
+    }
+
+    #[test]
+    fn destroyed_keys_are_forgotten() {
+        let config = config("counters_forget", None);
+        let counters = SignatureCounters::new(&config, None).unwrap();
+        sign(&counters, 3);
+        counters.forget(&key_identity());
+        drop(counters);
+        assert_eq!(count(&SignatureCounters::new(&config, None).unwrap()), 0);
+    }
+
+    fn write_behind() -> WriteBehindConfig {
+        WriteBehindConfig {
+            flush_interval: Some(3600),
+            max_pending: None,
+            journal: None,
+            sync_journal: None,
+        }
+    }
+
+    #[test]
+    fn counts_written_behind_survive_a_crash() {
+        let config = config("counters_write_behind", None);
+        let counters = SignatureCounters::new(&config, Some(&write_behind())).unwrap();
+        sign(&counters, 3);
+        std::mem::forget(counters);
+        let counters = SignatureCounters::new(&config, Some(&write_behind())).unwrap();
+        assert_eq!(count(&counters), 3);
+    }
+
+    #[test]
+    fn keys_forgotten_behind_are_not_counted() {
+        let config = config("counters_write_behind_forget", None);
+        let counters = SignatureCounters::new(&config, Some(&write_behind())).unwrap();
+        sign(&counters, 3);
+        counters.forget(&key_identity());
+        std::mem::forget(counters);
+        let counters = SignatureCounters::new(&config, Some(&write_behind())).unwrap();
+        assert_eq!(count(&counters), 0);
+    }
+}
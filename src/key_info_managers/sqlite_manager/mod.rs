@@ -292,6 +292,8 @@ impl SQLiteKeyInfoManager {
         key_info: &KeyInfo,
     ) -> rusqlite::Result<(), RusqliteError> {
         let conn = Connection::open(&self.database_path)?;
+        // The record replaced is overwritten with zeros.
+        conn.execute_batch("PRAGMA secure_delete = ON;")?;
 
         // The key_info.id should already be serialized using bincode at this stage by the
         // KIM client insert_key_info() function.
@@ -334,6 +336,9 @@ impl SQLiteKeyInfoManager {
     /// Will do nothing if the mapping record does not exist.
     fn delete_mapping(&self, key_identity: &KeyIdentity) -> rusqlite::Result<(), RusqliteError> {
         let conn = Connection::open(&self.database_path)?;
+        // The record deleted is overwritten with zeros, so that its key ID cannot be read back
+        // from the free pages of the database.
+        conn.execute_batch("PRAGMA secure_delete = ON;")?;
 
         let _ = conn.execute(
             "
@@ -964,6 +969,33 @@ mod test {
         fs::remove_file(&path).unwrap();
     }
 
+    #[test]
+    fn removed_mappings_are_scrubbed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mappings.sqlite3");
+        let mut manager = SQLiteKeyInfoManager::new(path.clone()).unwrap();
+        let key_identity = new_key_identity("removed_mappings_are_scrubbed".to_string());
+        let key_id = b"scrubbed key id 0123456789".to_vec();
+        let contains_key_id = |content: &[u8]| {
+            content
+                .windows(key_id.len())
+                .any(|window| window == &key_id[..])
+        };
+        let _ = manager
+            .insert(
+                key_identity.clone(),
+                KeyInfo {
+                    id: key_id.clone(),
+                    attributes: test_key_attributes(),
+                },
+            )
+            .unwrap();
+        assert!(contains_key_id(&fs::read(&path).unwrap()));
+
+        assert!(manager.remove(&key_identity).unwrap().is_some());
+        assert!(!contains_key_id(&fs::read(&path).unwrap()));
+    }
+
     fn new_key_identity(key_name: String) -> KeyIdentity {
         KeyIdentity::new(
             ApplicationIdentity::new("Testing Application 😎".to_string(), AuthType::NoAuth),
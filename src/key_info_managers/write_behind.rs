@@ -0,0 +1,474 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Write-behind of the metadata of the keys
+//!
+//! Metadata written at every operation on a key, like its signature counter, wears out the eMMC
+//! or NOR flash of embedded devices: each SQLite write rewrites whole pages, and their journal.
+//! The writes can instead be kept in memory and written to the database together, in a single
+//! transaction, at every flush interval or once enough of them are pending. Only the last write of
+//! each row is kept, so a key used many times in an interval costs a single write.
+//!
+//! A crash loses the writes pending, unless they are journaled: each write is then also appended
+//! to a journal next to the database, which the device does sequentially, and the journal is
+//! replayed into the database when the service starts. The journal can additionally be synced at
+//! every write so that power losses do not lose writes either, at the price of more flash writes.
+use crate::utils::config::WriteBehindConfig;
+use anyhow::{Context, Result};
+use log::error;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{BufReader, ErrorKind, Write as _};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Default time between two flushes, in seconds
+const DEFAULT_FLUSH_INTERVAL: u64 = 60;
+
+/// Default number of pending writes making a flush happen at once
+const DEFAULT_MAX_PENDING: usize = 1000;
+
+/// File permissions of the journal, only accessible to the service
+const FILE_PERMISSION: u32 = 0o600;
+
+/// Value of a column written
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Field {
+    /// SQL NULL
+    Null,
+    /// Integer
+    Integer(i64),
+    /// Text
+    Text(String),
+}
+
+impl From<u64> for Field {
+    fn from(integer: u64) -> Self {
+        Field::Integer(integer as i64)
+    }
+}
+
+impl From<Option<u64>> for Field {
+    fn from(integer: Option<u64>) -> Self {
+        integer.map_or(Field::Null, Field::from)
+    }
+}
+
+impl From<&str> for Field {
+    fn from(text: &str) -> Self {
+        Field::Text(text.to_string())
+    }
+}
+
+impl From<Field> for Value {
+    fn from(field: Field) -> Self {
+        match field {
+            Field::Null => Value::Null,
+            Field::Integer(integer) => Value::Integer(integer),
+            Field::Text(text) => Value::Text(text),
+        }
+    }
+}
+
+/// Write of a row of the database, as journaled
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RowWrite {
+    // Rows are told apart with this, the last write of a row replacing the previous ones
+    row: String,
+    statement: String,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    writes: HashMap<String, RowWrite>,
+    journal: Option<File>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    database_path: PathBuf,
+    sync_journal: bool,
+    max_pending: usize,
+    pending: Mutex<Pending>,
+}
+
+impl Shared {
+    fn lock_pending(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().expect("Write-behind lock poisoned")
+    }
+
+    /// Write the pending writes to the database, then empty the journal.
+    fn flush(&self) {
+        let mut pending = self.lock_pending();
+        if pending.writes.is_empty() {
+            return;
+        }
+        let writes: Vec<RowWrite> = pending.writes.values().cloned().collect();
+        match apply(&self.database_path, writes) {
+            Ok(()) => {
+                pending.writes.clear();
+                if let Some(journal) = &mut pending.journal {
+                    if let Err(e) = journal.set_len(0) {
+                        format_error!("Failed to empty the write-behind journal", e);
+                    }
+                }
+            }
+            // Kept for the next flush.
+            Err(e) => format_error!("Failed to flush the pending metadata writes", e),
+        }
+    }
+}
+
+fn apply(database_path: &Path, writes: Vec<RowWrite>) -> rusqlite::Result<()> {
+    let mut conn = Connection::open(database_path)?;
+    let transaction = conn.transaction()?;
+    for write in writes {
+        let _ = transaction.execute(
+            &write.statement,
+            params_from_iter(write.fields.into_iter().map(Value::from)),
+        )?;
+    }
+    transaction.commit()
+}
+
+/// Writes to a database, done behind the operations
+#[derive(Debug)]
+pub struct WriteBehind {
+    shared: Arc<Shared>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WriteBehind {
+    /// Start writing behind to the database at the given path, after replaying the journal of a
+    /// previous run.
+    pub fn new(database_path: &Path, config: &WriteBehindConfig) -> Result<Self> {
+        let journal_path = if config.journal.unwrap_or(true) {
+            let mut journal_path = database_path.as_os_str().to_owned();
+            journal_path.push(".write-behind");
+            Some(PathBuf::from(journal_path))
+        } else {
+            None
+        };
+        let journal = match &journal_path {
+            Some(journal_path) => {
+                replay(database_path, journal_path)?;
+                let journal = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(journal_path)
+                    .with_context(|| format!("open journal {:?}", journal_path))?;
+                fs::set_permissions(journal_path, Permissions::from_mode(FILE_PERMISSION))?;
+                Some(journal)
+            }
+            None => None,
+        };
+        let shared = Arc::new(Shared {
+            database_path: database_path.to_path_buf(),
+            sync_journal: config.sync_journal.unwrap_or(false),
+            max_pending: config.max_pending.unwrap_or(DEFAULT_MAX_PENDING).max(1),
+            pending: Mutex::new(Pending {
+                writes: HashMap::new(),
+                journal,
+            }),
+        });
+
+        let interval = Duration::from_secs(
+            config
+                .flush_interval
+                .unwrap_or(DEFAULT_FLUSH_INTERVAL)
+                .max(1),
+        );
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("kim-write-behind".to_string())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Acquire) {
+                    thread::park_timeout(interval);
+                    thread_shared.flush();
+                }
+            })?;
+
+        Ok(WriteBehind {
+            shared,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Queue the write of a row, replacing the pending write of the same row.
+    pub fn write(&self, row: String, statement: &str, fields: Vec<Field>) {
+        let write = RowWrite {
+            row,
+            statement: statement.to_string(),
+            fields,
+        };
+        let mut pending = self.shared.lock_pending();
+        if let Some(journal) = &mut pending.journal {
+            let result = bincode::serialize(&write)
+                .map_err(|e| std::io::Error::new(ErrorKind::Other, e))
+                .and_then(|record| journal.write_all(&record))
+                .and_then(|_| {
+                    if self.shared.sync_journal {
+                        journal.sync_data()
+                    } else {
+                        Ok(())
+                    }
+                });
+            if let Err(e) = result {
+                format_error!("Failed to journal a metadata write", e);
+            }
+        }
+        let _ = pending.writes.insert(write.row.clone(), write);
+        let full = pending.writes.len() >= self.shared.max_pending;
+        drop(pending);
+        if full {
+            self.shared.flush();
+        }
+    }
+
+    /// Write the pending writes to the database now.
+    pub fn flush(&self) {
+        self.shared.flush();
+    }
+}
+
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                error!("The write-behind thread panicked.");
+            }
+        }
+        self.shared.flush();
+    }
+}
+
+/// Apply the writes journaled by a previous run, then empty the journal.
+fn replay(database_path: &Path, journal_path: &Path) -> Result<()> {
+    let file = match File::open(journal_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("open journal {:?}", journal_path)),
+    };
+    let mut reader = BufReader::new(file);
+    let mut writes: HashMap<String, RowWrite> = HashMap::new();
+    // Up to the end of the journal, or to a record cut short by the crash
+    while let Ok(write) = bincode::deserialize_from::<_, RowWrite>(&mut reader) {
+        let _ = writes.insert(write.row.clone(), write);
+    }
+    if !writes.is_empty() {
+        apply(database_path, writes.into_values().collect())
+            .with_context(|| format!("replay journal {:?}", journal_path))?;
+    }
+    fs::write(journal_path, [])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    const STATEMENT: &str = "REPLACE INTO counter VALUES (?1, ?2)";
+
+    /// Database of counters in a new directory
+    fn database() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("write-behind.sqlite3");
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch("CREATE TABLE counter (name TEXT PRIMARY KEY, value INTEGER)")
+            .unwrap();
+        (dir, db_path)
+    }
+
+    fn journal_path(db_path: &Path) -> PathBuf {
+        db_path.with_file_name("write-behind.sqlite3.write-behind")
+    }
+
+    fn config(max_pending: usize, journal: Option<bool>) -> WriteBehindConfig {
+        WriteBehindConfig {
+            flush_interval: Some(3600),
+            max_pending: Some(max_pending),
+            journal,
+            sync_journal: None,
+        }
+    }
+
+    /// Sum of the counters in the database
+    fn count(db_path: &Path) -> i64 {
+        Connection::open(db_path)
+            .unwrap()
+            .query_row("SELECT COALESCE(SUM(value), 0) FROM counter", [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    fn write(write_behind: &WriteBehind, name: &str, value: i64) {
+        write_behind.write(
+            name.to_string(),
+            STATEMENT,
+            vec![Field::from(name), Field::Integer(value)],
+        )
+    }
+
+    #[test]
+    fn writes_wait_for_a_flush() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        write(&write_behind, "a", 1);
+        write(&write_behind, "b", 2);
+        assert_eq!(count(&db_path), 0);
+        write_behind.flush();
+        assert_eq!(count(&db_path), 3);
+    }
+
+    #[test]
+    fn last_write_of_a_row_is_kept() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(2, None)).unwrap();
+        write(&write_behind, "a", 1);
+        write(&write_behind, "a", 2);
+        // A single row pending
+        assert_eq!(count(&db_path), 0);
+        write_behind.flush();
+        assert_eq!(count(&db_path), 2);
+    }
+
+    #[test]
+    fn full_queue_is_flushed() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(2, None)).unwrap();
+        write(&write_behind, "a", 1);
+        write(&write_behind, "b", 10);
+        assert_eq!(count(&db_path), 11);
+    }
+
+    #[test]
+    fn writes_are_flushed_at_the_interval() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(
+            &db_path,
+            &WriteBehindConfig {
+                flush_interval: Some(1),
+                ..config(10, None)
+            },
+        )
+        .unwrap();
+        write(&write_behind, "a", 1);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while count(&db_path) == 0 {
+            assert!(Instant::now() < deadline, "the writes were never flushed");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn writes_are_flushed_when_stopping() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        write(&write_behind, "a", 1);
+        drop(write_behind);
+        assert_eq!(count(&db_path), 1);
+    }
+
+    #[test]
+    fn journal_is_replayed_after_a_crash() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        write(&write_behind, "a", 1);
+        write(&write_behind, "a", 3);
+        write(&write_behind, "b", 10);
+        std::mem::forget(write_behind);
+        assert_eq!(count(&db_path), 0);
+        let _write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        assert_eq!(count(&db_path), 13);
+        assert_eq!(fs::metadata(journal_path(&db_path)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn journal_is_emptied_by_flushes() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        write(&write_behind, "a", 1);
+        assert_ne!(fs::metadata(journal_path(&db_path)).unwrap().len(), 0);
+        write_behind.flush();
+        assert_eq!(fs::metadata(journal_path(&db_path)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn journal_is_only_accessible_to_the_service() {
+        let (_dir, db_path) = database();
+        let _write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        let mode = fs::metadata(journal_path(&db_path))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, FILE_PERMISSION);
+    }
+
+    #[test]
+    fn record_cut_short_is_ignored() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        write(&write_behind, "a", 1);
+        write(&write_behind, "b", 10);
+        std::mem::forget(write_behind);
+        let journal = fs::read(journal_path(&db_path)).unwrap();
+        fs::write(journal_path(&db_path), &journal[..journal.len() - 1]).unwrap();
+        let _write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        assert_eq!(count(&db_path), 1);
+    }
+
+    #[test]
+    fn pending_writes_are_lost_without_journal() {
+        let (_dir, db_path) = database();
+        let write_behind = WriteBehind::new(&db_path, &config(10, Some(false))).unwrap();
+        write(&write_behind, "a", 1);
+        std::mem::forget(write_behind);
+        let _write_behind = WriteBehind::new(&db_path, &config(10, Some(false))).unwrap();
+        assert_eq!(count(&db_path), 0);
+        assert!(!journal_path(&db_path).exists());
+    }
+
+    #[test]
+    fn failed_flushes_keep_the_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("write-behind.sqlite3");
+        let write_behind = WriteBehind::new(&db_path, &config(10, None)).unwrap();
+        write(&write_behind, "a", 1);
+        // No table to write to yet
+        write_behind.flush();
+        assert_ne!(fs::metadata(journal_path(&db_path)).unwrap().len(), 0);
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch("CREATE TABLE counter (name TEXT PRIMARY KEY, value INTEGER)")
+            .unwrap();
+        write_behind.flush();
+        assert_eq!(count(&db_path), 1);
+    }
+
+    #[test]
+    fn missing_integers_are_null() {
+        assert_eq!(Field::from(None), Field::Null);
+        assert_eq!(Field::from(Some(7)), Field::Integer(7));
+        assert_eq!(Value::from(Field::Null), Value::Null);
+        assert_eq!(
+            Value::from(Field::from("name")),
+            Value::Text(String::from("name"))
+        );
+    }
+}
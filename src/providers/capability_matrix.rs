@@ -0,0 +1,369 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Capability matrix of the providers
+//!
+//! The matrix records, for every provider, which combinations of key type, key size and algorithm
+//! out of a fixed catalogue can be generated, imported and used. It is built once by probing the
+//! `CanDoCrypto` operation of each provider and then kept, so that operators and tools can find
+//! out what a device can actually do without issuing hundreds of individual queries.
+use super::Provide;
+use crate::authenticators::ApplicationIdentity;
+use parsec_interface::operations::can_do_crypto::{self, CheckType};
+use parsec_interface::operations::psa_algorithm::{
+    Algorithm, AsymmetricEncryption, AsymmetricSignature, Hash, SignHash,
+};
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, EccFamily, Lifetime, Policy, Type, UsageFlags,
+};
+use parsec_interface::requests::{AuthType, ProviderId};
+use std::fmt;
+use std::sync::Arc;
+
+/// Capabilities of a provider for one combination of key type, size and algorithm
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capability {
+    /// Type of the key
+    pub key_type: Type,
+    /// Size of the key, in bits
+    pub bits: usize,
+    /// Algorithm the key is used with
+    pub algorithm: Algorithm,
+    /// A key with those attributes can be generated
+    pub generate: bool,
+    /// A key with those attributes can be imported
+    pub import: bool,
+    /// An existing key with those attributes can be used
+    pub usage: bool,
+}
+
+impl Capability {
+    /// Whether any of the checks succeeded
+    pub fn is_supported(&self) -> bool {
+        self.generate || self.import || self.usage
+    }
+}
+
+/// Capabilities of all the providers of the service
+#[derive(Clone, Debug, Default)]
+pub struct CapabilityMatrix {
+    providers: Vec<(ProviderId, Vec<Capability>)>,
+}
+
+impl CapabilityMatrix {
+    /// Build the matrix by probing every provider with the catalogue of key attributes.
+    pub fn probe(providers: &[Arc<dyn Provide + Send + Sync>]) -> Self {
+        // The checks do not depend on who asks, the identity is only needed by the interface.
+        let identity = ApplicationIdentity::new(String::from("capability-probe"), AuthType::NoAuth);
+        let mut matrix = CapabilityMatrix::default();
+        for provider in providers {
+            // Probing a provider activated on demand would build it.
+            if !provider.is_active() {
+                continue;
+            }
+            let provider_id = match provider.describe() {
+                Ok((provider_info, _)) => provider_info.id,
+                Err(_) => continue,
+            };
+            let capabilities = catalogue()
+                .into_iter()
+                .map(|(key_type, bits, algorithm)| {
+                    let check = |check_type, key_type| {
+                        provider
+                            .can_do_crypto(
+                                &identity,
+                                can_do_crypto::Operation {
+                                    check_type,
+                                    attributes: attributes(key_type, bits, algorithm),
+                                },
+                            )
+                            .is_ok()
+                    };
+                    Capability {
+                        key_type,
+                        bits,
+                        algorithm,
+                        generate: check(CheckType::Generate, key_type),
+                        import: check(CheckType::Import, public_key_type(key_type)),
+                        usage: check(CheckType::Use, key_type),
+                    }
+                })
+                .collect();
+            matrix.providers.push((provider_id, capabilities));
+        }
+        matrix
+    }
+
+    /// Capabilities of the given provider, if it was probed
+    pub fn provider(&self, provider_id: ProviderId) -> Option<&[Capability]> {
+        self.providers
+            .iter()
+            .find(|(id, _)| *id == provider_id)
+            .map(|(_, capabilities)| capabilities.as_slice())
+    }
+
+    /// All the providers probed, with their capabilities
+    pub fn providers(&self) -> &[(ProviderId, Vec<Capability>)] {
+        &self.providers
+    }
+}
+
+impl fmt::Display for CapabilityMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (provider_id, capabilities) in &self.providers {
+            writeln!(f, "{}:", provider_id)?;
+            for capability in capabilities.iter().filter(|c| c.is_supported()) {
+                writeln!(
+                    f,
+                    "  {:?} ({} bits) with {:?}: generate={} import={} use={}",
+                    capability.key_type,
+                    capability.bits,
+                    capability.algorithm,
+                    capability.generate,
+                    capability.import,
+                    capability.usage
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Key pairs are imported through their public part.
+fn public_key_type(key_type: Type) -> Type {
+    match key_type {
+        Type::RsaKeyPair => Type::RsaPublicKey,
+        Type::EccKeyPair { curve_family } => Type::EccPublicKey { curve_family },
+        key_type => key_type,
+    }
+}
+
+fn attributes(key_type: Type, bits: usize, algorithm: Algorithm) -> Attributes {
+    let mut usage_flags = UsageFlags::default();
+    match algorithm {
+        Algorithm::AsymmetricSignature(_) => {
+            let _ = usage_flags.set_sign_hash().set_verify_hash();
+        }
+        Algorithm::AsymmetricEncryption(_) => {
+            let _ = usage_flags.set_encrypt().set_decrypt();
+        }
+        _ => (),
+    }
+    Attributes {
+        lifetime: Lifetime::Persistent,
+        key_type,
+        bits,
+        policy: Policy {
+            usage_flags,
+            permitted_algorithms: algorithm,
+        },
+    }
+}
+
+/// The key attributes probed on every provider
+fn catalogue() -> Vec<(Type, usize, Algorithm)> {
+    let mut catalogue = Vec::new();
+    for bits in [2048, 3072, 4096] {
+        for hash in [Hash::Sha256, Hash::Sha384, Hash::Sha512] {
+            catalogue.push((
+                Type::RsaKeyPair,
+                bits,
+                AsymmetricSignature::RsaPkcs1v15Sign {
+                    hash_alg: SignHash::Specific(hash),
+                }
+                .into(),
+            ));
+            catalogue.push((
+                Type::RsaKeyPair,
+                bits,
+                AsymmetricSignature::RsaPss {
+                    hash_alg: SignHash::Specific(hash),
+                }
+                .into(),
+            ));
+            catalogue.push((
+                Type::RsaKeyPair,
+                bits,
+                AsymmetricEncryption::RsaOaep { hash_alg: hash }.into(),
+            ));
+        }
+        catalogue.push((
+            Type::RsaKeyPair,
+            bits,
+            AsymmetricEncryption::RsaPkcs1v15Crypt.into(),
+        ));
+    }
+    for bits in [256, 384, 521] {
+        for hash in [Hash::Sha256, Hash::Sha384, Hash::Sha512] {
+            catalogue.push((
+                Type::EccKeyPair {
+                    curve_family: EccFamily::SecpR1,
+                },
+                bits,
+                AsymmetricSignature::Ecdsa {
+                    hash_alg: SignHash::Specific(hash),
+                }
+                .into(),
+            ));
+        }
+    }
+    catalogue
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::list_providers::{ProviderInfo, Uuid};
+    use parsec_interface::operations::{list_clients, list_keys};
+    use parsec_interface::requests::{Opcode, ResponseStatus, Result};
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Supports RSA signatures with 2048 bits keys only, imported through their public part.
+    struct RsaOnlyProvider {
+        active: bool,
+        checks: AtomicUsize,
+    }
+
+    impl RsaOnlyProvider {
+        fn new(active: bool) -> Arc<Self> {
+            Arc::new(RsaOnlyProvider {
+                active,
+                checks: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl Provide for RsaOnlyProvider {
+        fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+            Ok((
+                ProviderInfo {
+                    uuid: Uuid::nil(),
+                    description: String::new(),
+                    vendor: String::new(),
+                    version_maj: 0,
+                    version_min: 0,
+                    version_rev: 0,
+                    id: ProviderId::Tpm,
+                },
+                HashSet::new(),
+            ))
+        }
+
+        fn list_keys(
+            &self,
+            _application_identity: &ApplicationIdentity,
+            _op: list_keys::Operation,
+        ) -> Result<list_keys::Result> {
+            Ok(list_keys::Result { keys: Vec::new() })
+        }
+
+        fn list_clients(&self, _op: list_clients::Operation) -> Result<list_clients::Result> {
+            Ok(list_clients::Result {
+                clients: Vec::new(),
+            })
+        }
+
+        fn can_do_crypto(
+            &self,
+            _application_identity: &ApplicationIdentity,
+            op: can_do_crypto::Operation,
+        ) -> Result<can_do_crypto::Result> {
+            let _ = self.checks.fetch_add(1, Ordering::Relaxed);
+            let attributes = op.attributes;
+            let key_type = match op.check_type {
+                CheckType::Import => Type::RsaPublicKey,
+                _ => Type::RsaKeyPair,
+            };
+            match attributes.policy.permitted_algorithms {
+                Algorithm::AsymmetricSignature(_)
+                    if attributes.key_type == key_type && attributes.bits == 2048 =>
+                {
+                    Ok(can_do_crypto::Result)
+                }
+                _ => Err(ResponseStatus::PsaErrorNotSupported),
+            }
+        }
+
+        fn is_active(&self) -> bool {
+            self.active
+        }
+    }
+
+    #[test]
+    fn supported_combinations() {
+        let matrix = CapabilityMatrix::probe(&[RsaOnlyProvider::new(true)]);
+        let capabilities = matrix.provider(ProviderId::Tpm).unwrap();
+        assert_eq!(capabilities.len(), catalogue().len());
+
+        let supported: Vec<&Capability> =
+            capabilities.iter().filter(|c| c.is_supported()).collect();
+        // PKCS#1 v1.5 and PSS with three hashes
+        assert_eq!(supported.len(), 6);
+        for capability in supported {
+            assert_eq!(capability.key_type, Type::RsaKeyPair);
+            assert_eq!(capability.bits, 2048);
+            assert!(matches!(
+                capability.algorithm,
+                Algorithm::AsymmetricSignature(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn key_pairs_are_imported_as_public_keys() {
+        let matrix = CapabilityMatrix::probe(&[RsaOnlyProvider::new(true)]);
+        let capabilities = matrix.provider(ProviderId::Tpm).unwrap();
+        for capability in capabilities.iter().filter(|c| c.is_supported()) {
+            assert!(capability.generate && capability.import && capability.usage);
+        }
+    }
+
+    #[test]
+    fn inactive_providers_are_not_probed() {
+        let provider = RsaOnlyProvider::new(false);
+        let matrix = CapabilityMatrix::probe(&[provider.clone()]);
+        assert!(matrix.providers().is_empty());
+        assert_eq!(provider.checks.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn unknown_provider() {
+        let matrix = CapabilityMatrix::probe(&[RsaOnlyProvider::new(true)]);
+        assert!(matrix.provider(ProviderId::Pkcs11).is_none());
+    }
+
+    #[test]
+    fn usage_flags_follow_the_algorithm() {
+        let signing = attributes(
+            Type::RsaKeyPair,
+            2048,
+            AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Specific(Hash::Sha256),
+            }
+            .into(),
+        );
+        assert!(signing.policy.usage_flags.sign_hash());
+        assert!(signing.policy.usage_flags.verify_hash());
+        assert!(!signing.policy.usage_flags.decrypt());
+
+        let encryption = attributes(
+            Type::RsaKeyPair,
+            2048,
+            AsymmetricEncryption::RsaPkcs1v15Crypt.into(),
+        );
+        assert!(encryption.policy.usage_flags.encrypt());
+        assert!(encryption.policy.usage_flags.decrypt());
+        assert!(!encryption.policy.usage_flags.sign_hash());
+    }
+
+    #[test]
+    fn only_supported_combinations_are_displayed() {
+        let matrix = CapabilityMatrix::probe(&[RsaOnlyProvider::new(true)]);
+        let displayed = matrix.to_string();
+        let lines: Vec<&str> = displayed.lines().collect();
+        assert_eq!(lines.len(), 1 + 6);
+        assert_eq!(lines[0], format!("{}:", ProviderId::Tpm));
+        assert!(lines[1..].iter().all(|line| line.contains("(2048 bits)")
+            && line.ends_with("generate=true import=true use=true")));
+    }
+}
@@ -5,10 +5,12 @@
 //! The core provider acts as a source of information for the Parsec service,
 //! aiding clients in discovering the capabilities offered by their underlying
 //! platform.
+use super::capability_matrix::CapabilityMatrix;
 use super::Provide;
 use crate::authenticators::ApplicationIdentity;
+use crate::front::wire_protocol::{self, WireCapabilities};
 use derivative::Derivative;
-use log::{error, trace};
+use log::{debug, error, trace};
 use parsec_interface::operations::list_providers::Uuid;
 use parsec_interface::operations::{
     delete_client, list_authenticators, list_clients, list_keys, list_opcodes, list_providers,
@@ -44,6 +46,7 @@ pub struct Provider {
     provider_info: Vec<ProviderInfo>,
     provider_opcodes: HashMap<ProviderId, HashSet<Opcode>>,
     authenticator_info: Vec<AuthenticatorInfo>,
+    capability_matrix: CapabilityMatrix,
     #[derivative(Debug = "ignore")]
     prov_list: Vec<Arc<dyn Provide + Send + Sync>>,
 }
@@ -54,25 +57,107 @@ impl Provider {
 
     /// The UUID for this provider
     pub const PROVIDER_UUID: &'static str = "47049873-2a43-4845-9d72-831eab668784";
+
+    /// Version and optional features of the wire protocol spoken by the service
+    ///
+    /// Ping returns the version, the features have no place in the wire protocol yet.
+    pub fn wire_capabilities(&self) -> WireCapabilities {
+        WireCapabilities {
+            version_maj: self.wire_protocol_version_maj,
+            version_min: self.wire_protocol_version_min,
+            features: wire_protocol::SUPPORTED_FEATURES,
+        }
+    }
+
+    /// Capabilities of the providers, as probed when the service started
+    pub fn capability_matrix(&self) -> &CapabilityMatrix {
+        &self.capability_matrix
+    }
+
+    /// Measure the configuration of the service into a register of the hardware of a provider.
+    ///
+    /// The event measured is the content of the configuration file, byte for byte, followed by
+    /// one line per provider registered, in order, with its ID, UUID and version, so that a
+    /// remote attestation of the device proves both how the service was configured and which
+    /// providers it runs. See `configuration_event`.
+    pub fn measure_configuration(
+        &self,
+        provider_id: ProviderId,
+        register: u8,
+        configuration: &[u8],
+    ) -> Result<()> {
+        self.prov_list
+            .iter()
+            .find(|provider| {
+                provider
+                    .describe()
+                    .map_or(false, |(provider_info, _)| provider_info.id == provider_id)
+            })
+            .ok_or(ResponseStatus::ProviderNotRegistered)?
+            .extend_measurement(register, &self.configuration_event(configuration))
+    }
+
+    /// Event measured for the configuration file: its content followed, for each provider, by a
+    /// line feed and `<provider ID> <UUID> <major>.<minor>.<revision>`, the provider ID being
+    /// written as in the logs, for example `Trusted Platform Module provider`.
+    fn configuration_event(&self, configuration: &[u8]) -> Vec<u8> {
+        let mut event = configuration.to_vec();
+        for provider_info in &self.provider_info {
+            event.extend_from_slice(
+                format!(
+                    "\n{} {} {}.{}.{}",
+                    provider_info.id,
+                    provider_info.uuid,
+                    provider_info.version_maj,
+                    provider_info.version_min,
+                    provider_info.version_rev
+                )
+                .as_bytes(),
+            );
+        }
+        event
+    }
 }
 
 impl Provide for Provider {
     fn list_opcodes(&self, op: list_opcodes::Operation) -> Result<list_opcodes::Result> {
         trace!("list_opcodes ingress");
+        // Providers activated on demand support more operations once active.
+        let live_opcodes = self.prov_list.iter().find_map(|provider| {
+            provider
+                .describe()
+                .ok()
+                .filter(|(provider_info, _)| provider_info.id == op.provider_id)
+        });
         Ok(list_opcodes::Result {
-            opcodes: self
-                .provider_opcodes
-                .get(&op.provider_id)
-                .ok_or(ResponseStatus::ProviderNotRegistered)?
-                .clone(),
+            opcodes: match live_opcodes {
+                Some((_, opcodes)) => opcodes,
+                None => self
+                    .provider_opcodes
+                    .get(&op.provider_id)
+                    .ok_or(ResponseStatus::ProviderNotRegistered)?
+                    .clone(),
+            },
         })
     }
 
     fn list_providers(&self, _op: list_providers::Operation) -> Result<list_providers::Result> {
         trace!("list_providers ingress");
-        Ok(list_providers::Result {
-            providers: self.provider_info.clone(),
-        })
+        // The descriptions are taken again as providers activated on demand change theirs once
+        // active. The Core provider comes last.
+        let mut providers: Vec<ProviderInfo> = self
+            .prov_list
+            .iter()
+            .zip(&self.provider_info)
+            .map(|(provider, provider_info)| {
+                provider.describe().map_or_else(
+                    |_| provider_info.clone(),
+                    |(provider_info, _)| provider_info,
+                )
+            })
+            .collect();
+        providers.extend(self.provider_info.last().cloned());
+        Ok(list_providers::Result { providers })
     }
 
     fn list_authenticators(
@@ -299,6 +384,9 @@ impl ProviderBuilder {
             id: ProviderId::Core,
         });
 
+        let capability_matrix = CapabilityMatrix::probe(&self.prov_list);
+        debug!("Capabilities of the providers:\n{}", capability_matrix);
+
         let core_provider = Provider {
             wire_protocol_version_maj: self
                 .version_maj
@@ -309,6 +397,7 @@ impl ProviderBuilder {
             provider_opcodes,
             provider_info: provider_info_vec,
             authenticator_info: self.authenticator_info,
+            capability_matrix,
             prov_list: self.prov_list,
         };
 
@@ -328,6 +417,7 @@ mod tests {
             provider_info: Vec::new(),
             authenticator_info: Vec::new(),
             provider_opcodes: HashMap::new(),
+            capability_matrix: CapabilityMatrix::default(),
             prov_list: Vec::new(),
         };
         let op = ping::Operation {};
@@ -342,6 +432,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn configuration_event_lists_the_providers() {
+        let provider_info = |id, uuid: &str| ProviderInfo {
+            uuid: uuid.parse().unwrap(),
+            description: String::new(),
+            vendor: String::new(),
+            version_maj: 1,
+            version_min: 3,
+            version_rev: 0,
+            id,
+        };
+        let provider = Provider {
+            wire_protocol_version_min: 0,
+            wire_protocol_version_maj: 1,
+            provider_info: vec![
+                provider_info(ProviderId::Tpm, "1e4954a4-ff21-46d3-ab0c-661eeb667e1d"),
+                provider_info(ProviderId::Core, Provider::PROVIDER_UUID),
+            ],
+            authenticator_info: Vec::new(),
+            provider_opcodes: HashMap::new(),
+            capability_matrix: CapabilityMatrix::default(),
+            prov_list: Vec::new(),
+        };
+        assert_eq!(
+            String::from_utf8(provider.configuration_event(b"[core_settings]\n")).unwrap(),
+            format!(
+                "[core_settings]\n\n{} 1e4954a4-ff21-46d3-ab0c-661eeb667e1d 1.3.0\n{} {} 1.3.0",
+                ProviderId::Tpm,
+                ProviderId::Core,
+                Provider::PROVIDER_UUID
+            )
+        );
+    }
+
     #[test]
     fn test_build() {
         let provider_builder = ProviderBuilder::new().with_wire_protocol_version(42, 12);
@@ -20,7 +20,7 @@ struct AccessKey {
 impl Provider {
     /// Read access keys from a configuration file and setup the CALib to use them.
     pub fn set_access_keys(
-        &self,
+        &mut self,
         access_keys_file_name: Option<String>,
     ) -> Option<rust_cryptoauthlib::AtcaStatus> {
         let access_keys_string = match access_keys_file_name.clone() {
@@ -51,7 +51,9 @@ impl Provider {
             if rust_cryptoauthlib::ATCA_ATECC_SLOTS_COUNT > access_key.slot {
                 let err = self.device.add_access_key(access_key.slot, &access_key.key);
                 match err {
-                    rust_cryptoauthlib::AtcaStatus::AtcaSuccess => (),
+                    rust_cryptoauthlib::AtcaStatus::AtcaSuccess => {
+                        self.keep_io_protection_key(access_key.slot, &access_key.key)
+                    }
                     _ => error!(
                         "add_access_key() for slot {} failed, because {}",
                         access_key.slot, err
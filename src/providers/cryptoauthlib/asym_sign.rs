@@ -4,7 +4,6 @@ use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
 use log::error;
-use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
 use parsec_interface::operations::psa_key_attributes::{EccFamily, Type};
 use parsec_interface::operations::{
     psa_sign_hash, psa_sign_message, psa_verify_hash, psa_verify_message,
@@ -87,19 +86,10 @@ impl Provider {
         let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
 
         op.validate(key_attributes)?;
-        if op.hash.len() != Hash::Sha256.hash_length() {
-            return Err(ResponseStatus::PsaErrorInvalidArgument);
-        }
+        let hash = self.hardware_digest(op.alg, &op.hash)?;
 
-        match op.alg {
-            AsymmetricSignature::Ecdsa {
-                hash_alg: SignHash::Specific(Hash::Sha256),
-            } => {
-                let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
-                self.ecdsa_hash_sign(key_id, &op.hash)
-            }
-            _ => Err(ResponseStatus::PsaErrorNotSupported),
-        }
+        let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
+        self.ecdsa_hash_sign(key_id, &hash)
     }
 
     pub(super) fn psa_verify_hash_internal(
@@ -113,20 +103,11 @@ impl Provider {
         let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
 
         op.validate(key_attributes)?;
-        if op.hash.len() != Hash::Sha256.hash_length() {
-            return Err(ResponseStatus::PsaErrorInvalidArgument);
-        }
+        let hash = self.hardware_digest(op.alg, &op.hash)?;
 
-        match op.alg {
-            AsymmetricSignature::Ecdsa {
-                hash_alg: SignHash::Specific(Hash::Sha256),
-            } => {
-                let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
-                let verify_mode = self.ecdsa_verify_mode_get(key_id, key_attributes.key_type)?;
-                self.ecdsa_hash_verify(verify_mode, op.hash, op.signature)
-            }
-            _ => Err(ResponseStatus::PsaErrorNotSupported),
-        }
+        let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
+        let verify_mode = self.ecdsa_verify_mode_get(key_id, key_attributes.key_type)?;
+        self.ecdsa_hash_verify(verify_mode, hash, op.signature)
     }
 
     pub(super) fn psa_sign_message_internal(
@@ -141,20 +122,13 @@ impl Provider {
 
         op.validate(key_attributes)?;
 
-        match op.alg {
-            AsymmetricSignature::Ecdsa {
-                hash_alg: SignHash::Specific(Hash::Sha256),
-            } => {
-                let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
-                // Compute a hash
-                let hash = self.sha256(&op.message)?.hash;
-                // Sign computed hash
-                let result = self.ecdsa_hash_sign(key_id, &hash)?.signature;
+        // Compute a hash
+        let hash = self.hardware_message_digest(op.alg, &op.message)?;
+        let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
+        // Sign computed hash
+        let result = self.ecdsa_hash_sign(key_id, &hash)?.signature;
 
-                Ok(psa_sign_message::Result { signature: result })
-            }
-            _ => Err(ResponseStatus::PsaErrorNotSupported),
-        }
+        Ok(psa_sign_message::Result { signature: result })
     }
 
     pub(super) fn psa_verify_message_internal(
@@ -169,21 +143,14 @@ impl Provider {
 
         op.validate(key_attributes)?;
 
-        match op.alg {
-            AsymmetricSignature::Ecdsa {
-                hash_alg: SignHash::Specific(Hash::Sha256),
-            } => {
-                let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
-                // Calculate a hash of a message
-                let hash = self.sha256(&op.message)?.hash;
-                // Determine verify mode
-                let verify_mode = self.ecdsa_verify_mode_get(key_id, key_attributes.key_type)?;
-                // Verify the hash using public key
-                let _ = self.ecdsa_hash_verify(verify_mode, hash, op.signature)?;
-
-                Ok(psa_verify_message::Result {})
-            }
-            _ => Err(ResponseStatus::PsaErrorNotSupported),
-        }
+        // Calculate a hash of a message
+        let hash = self.hardware_message_digest(op.alg, &op.message)?;
+        let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
+        // Determine verify mode
+        let verify_mode = self.ecdsa_verify_mode_get(key_id, key_attributes.key_type)?;
+        // Verify the hash using public key
+        let _ = self.ecdsa_hash_verify(verify_mode, hash, op.signature)?;
+
+        Ok(psa_verify_message::Result {})
     }
 }
@@ -0,0 +1,203 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Capabilities of the ATECC device
+//!
+//! What a key can be used for depends on the configuration of the slot holding it, read from the
+//! device at startup. A key can be generated or imported if a free slot is configured for its
+//! attributes, and used if any slot is.
+use super::Provider;
+use crate::authenticators::ApplicationIdentity;
+use crate::providers::crypto_capability::CanDoCrypto;
+use log::{info, trace};
+use parsec_interface::operations::can_do_crypto;
+use parsec_interface::operations::psa_key_attributes::{Attributes, Type};
+use parsec_interface::requests::ResponseStatus::PsaErrorNotSupported;
+use parsec_interface::requests::{Opcode, Result};
+
+impl CanDoCrypto for Provider {
+    fn can_do_crypto_internal(
+        &self,
+        _app_identity: &ApplicationIdentity,
+        _op: can_do_crypto::Operation,
+    ) -> Result<can_do_crypto::Result> {
+        trace!("can_do_crypto_internal");
+        Ok(can_do_crypto::Result)
+    }
+
+    fn use_check_internal(&self, attributes: Attributes) -> Result<can_do_crypto::Result> {
+        trace!("use_check_internal");
+        self.key_slots.check_any_slot(&attributes)?;
+        Ok(can_do_crypto::Result)
+    }
+
+    fn generate_check_internal(&self, attributes: Attributes) -> Result<can_do_crypto::Result> {
+        trace!("generate_check_internal");
+        if let Type::EccPublicKey { .. } = attributes.key_type {
+            info!("Public keys cannot be generated");
+            return Err(PsaErrorNotSupported);
+        }
+        self.key_slots
+            .check_free_slot(&attributes, Opcode::PsaGenerateKey)?;
+        Ok(can_do_crypto::Result)
+    }
+
+    fn import_check_internal(&self, attributes: Attributes) -> Result<can_do_crypto::Result> {
+        trace!("import_check_internal");
+        self.key_slots
+            .check_free_slot(&attributes, Opcode::PsaImportKey)?;
+        Ok(can_do_crypto::Result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::key_slot::KeySlotStatus;
+    use super::super::test_device::{aes_slot, private_key_slot, provider};
+    use crate::authenticators::ApplicationIdentity;
+    use crate::providers::crypto_capability::CanDoCrypto;
+    use crate::providers::error_detail;
+    use parsec_interface::operations::can_do_crypto::{CheckType, Operation};
+    use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Cipher, Hash};
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, EccFamily, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::{AuthType, ResponseStatus};
+    use rust_cryptoauthlib::WriteConfig;
+
+    fn signing_key(hash: Hash) -> Attributes {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags.set_sign_hash();
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1,
+            },
+            bits: 256,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms: AsymmetricSignature::Ecdsa {
+                    hash_alg: hash.into(),
+                }
+                .into(),
+            },
+        }
+    }
+
+    fn aes_key() -> Attributes {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags.set_encrypt().set_decrypt();
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::Aes,
+            bits: 128,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms: Cipher::Ctr.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn free_configured_slot_takes_new_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[private_key_slot(2, WriteConfig::Encrypt)], false);
+        let attributes = signing_key(Hash::Sha256);
+        assert!(provider.generate_check_internal(attributes).is_ok());
+        assert!(provider.import_check_internal(attributes).is_ok());
+        assert!(provider.use_check_internal(attributes).is_ok());
+    }
+
+    #[test]
+    fn slots_written_never_only_take_generated_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[private_key_slot(2, WriteConfig::Never)], false);
+        let attributes = signing_key(Hash::Sha256);
+        assert!(provider.generate_check_internal(attributes).is_ok());
+        assert_eq!(
+            provider.import_check_internal(attributes).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn used_slots_can_not_take_new_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[private_key_slot(2, WriteConfig::Encrypt)], false);
+        provider
+            .key_slots
+            .set_slot_status(2, KeySlotStatus::Busy)
+            .unwrap();
+        let attributes = signing_key(Hash::Sha256);
+        assert_eq!(
+            provider.generate_check_internal(attributes).unwrap_err(),
+            ResponseStatus::PsaErrorInsufficientStorage
+        );
+        let detail = error_detail::take().unwrap();
+        assert!(detail.message().ends_with("slot 2 is in use"));
+        // Keys in the used slot can still be used.
+        assert!(provider.use_check_internal(attributes).is_ok());
+    }
+
+    #[test]
+    fn unconfigured_keys_are_not_supported() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[private_key_slot(2, WriteConfig::Encrypt)], false);
+        assert_eq!(
+            provider.use_check_internal(aes_key()).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+        assert!(error_detail::take().is_some());
+        assert_eq!(
+            provider.generate_check_internal(aes_key()).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+
+        let provider = self::provider(&dir, &[aes_slot(9)], false);
+        assert!(provider.generate_check_internal(aes_key()).is_ok());
+    }
+
+    #[test]
+    fn public_keys_can_not_be_generated() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[private_key_slot(9, WriteConfig::Encrypt)], false);
+        let mut attributes = signing_key(Hash::Sha256);
+        attributes.key_type = Type::EccPublicKey {
+            curve_family: EccFamily::SecpR1,
+        };
+        attributes.policy.usage_flags = UsageFlags::default();
+        let _ = attributes.policy.usage_flags.set_verify_hash();
+        assert_eq!(
+            provider.generate_check_internal(attributes).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn emulated_signatures_need_emulation() {
+        let dir = tempfile::tempdir().unwrap();
+        let slots = [private_key_slot(2, WriteConfig::Encrypt)];
+        let attributes = signing_key(Hash::Sha384);
+
+        let provider = provider(&dir, &slots, false);
+        assert_eq!(
+            provider.generate_check_internal(attributes).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+
+        let provider = self::provider(&dir, &slots, true);
+        assert!(provider.generate_check_internal(attributes).is_ok());
+        assert!(provider.use_check_internal(attributes).is_ok());
+    }
+
+    #[test]
+    fn can_do_crypto_is_supported() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[], false);
+        let app_identity = ApplicationIdentity::new("app".to_string(), AuthType::Direct);
+        let op = Operation {
+            check_type: CheckType::Use,
+            attributes: aes_key(),
+        };
+        assert!(provider.can_do_crypto_internal(&app_identity, op).is_ok());
+    }
+}
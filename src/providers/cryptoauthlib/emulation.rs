@@ -0,0 +1,265 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Emulation of ECDSA with other hashes than SHA-256
+//!
+//! The ATECC only signs and verifies ECDSA signatures over SHA-256 digests on the P-256 curve.
+//! ECDSA with another hash only differs by the digest: it is truncated to the bit length of the
+//! curve order, 256 bits on P-256. When the emulation is enabled in the configuration, SHA-384
+//! and SHA-512 digests are truncated in software, messages being hashed in software first, and
+//! then signed or verified by the device as SHA-256 digests. The signatures are the ones of the
+//! algorithm asked for.
+//!
+//! Slots configured for ECDSA with SHA-256 then also accept keys with those algorithms, which
+//! `CanDoCrypto` reports as supported. Every emulated operation is logged.
+use super::Provider;
+use log::{error, info};
+use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_key_attributes::Attributes;
+use parsec_interface::requests::{ResponseStatus, Result};
+use ring::digest;
+use zeroize::Zeroizing;
+
+/// Algorithm signing the SHA-256 digests, the only one supported by the device
+const HARDWARE_ALGORITHM: AsymmetricSignature = AsymmetricSignature::Ecdsa {
+    hash_alg: SignHash::Specific(Hash::Sha256),
+};
+/// Length of the digests signed by the device, the one of the P-256 curve order
+const HARDWARE_DIGEST_LEN: usize = 32;
+
+/// Hash of an algorithm which can be emulated with the hardware one
+fn emulated_hash(alg: AsymmetricSignature) -> Option<Hash> {
+    match alg {
+        AsymmetricSignature::Ecdsa {
+            hash_alg: SignHash::Specific(hash @ (Hash::Sha384 | Hash::Sha512)),
+        } => Some(hash),
+        _ => None,
+    }
+}
+
+/// Attributes of a key as seen by the device: the algorithms which can be emulated are replaced by
+/// the hardware one.
+pub(super) fn hardware_attributes(mut attributes: Attributes) -> Attributes {
+    if let Algorithm::AsymmetricSignature(alg) = attributes.policy.permitted_algorithms {
+        if emulated_hash(alg).is_some() {
+            attributes.policy.permitted_algorithms = HARDWARE_ALGORITHM.into();
+        }
+    }
+    attributes
+}
+
+impl Provider {
+    /// Digest to sign or verify with the device for the hash given with the algorithm.
+    pub(super) fn hardware_digest(
+        &self,
+        alg: AsymmetricSignature,
+        hash: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        if alg == HARDWARE_ALGORITHM {
+            if hash.len() != HARDWARE_DIGEST_LEN {
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+            return Ok(Zeroizing::new(hash.to_vec()));
+        }
+        let hash_alg = self.emulated_hash(alg)?;
+        if hash.len() != hash_alg.hash_length() {
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+        info!("Emulating {:?} with a truncated digest.", alg);
+        Ok(Zeroizing::new(hash[..HARDWARE_DIGEST_LEN].to_vec()))
+    }
+
+    /// Digest to sign or verify with the device for the message given with the algorithm.
+    pub(super) fn hardware_message_digest(
+        &self,
+        alg: AsymmetricSignature,
+        message: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        if alg == HARDWARE_ALGORITHM {
+            return Ok(self.sha256(message)?.hash);
+        }
+        let algorithm = match self.emulated_hash(alg)? {
+            Hash::Sha384 => &digest::SHA384,
+            _ => &digest::SHA512,
+        };
+        info!("Emulating {:?} with a software hash.", alg);
+        Ok(Zeroizing::new(
+            digest::digest(algorithm, message).as_ref()[..HARDWARE_DIGEST_LEN].to_vec(),
+        ))
+    }
+
+    fn emulated_hash(&self, alg: AsymmetricSignature) -> Result<Hash> {
+        match emulated_hash(alg) {
+            Some(hash) if self.signature_emulation => Ok(hash),
+            Some(_) => {
+                error!(
+                    "{:?} needs the signature emulation, which is not enabled.",
+                    alg
+                );
+                Err(ResponseStatus::PsaErrorNotSupported)
+            }
+            None => Err(ResponseStatus::PsaErrorNotSupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test_device::provider;
+    use super::*;
+    use parsec_interface::operations::psa_key_attributes::{
+        EccFamily, Lifetime, Policy, Type, UsageFlags,
+    };
+
+    /// First 32 bytes of the SHA-384 digest of "abc", from FIPS 180-2
+    const SHA384_ABC: [u8; 32] = [
+        0xcb, 0x00, 0x75, 0x3f, 0x45, 0xa3, 0x5e, 0x8b, 0xb5, 0xa0, 0x3d, 0x69, 0x9a, 0xc6, 0x50,
+        0x07, 0x27, 0x2c, 0x32, 0xab, 0x0e, 0xde, 0xd1, 0x63, 0x1a, 0x8b, 0x60, 0x5a, 0x43, 0xff,
+        0x5b, 0xed,
+    ];
+    /// First 32 bytes of the SHA-512 digest of "abc", from FIPS 180-2
+    const SHA512_ABC: [u8; 32] = [
+        0xdd, 0xaf, 0x35, 0xa1, 0x93, 0x61, 0x7a, 0xba, 0xcc, 0x41, 0x73, 0x49, 0xae, 0x20, 0x41,
+        0x31, 0x12, 0xe6, 0xfa, 0x4e, 0x89, 0xa9, 0x7e, 0xa2, 0x0a, 0x9e, 0xee, 0xe6, 0x4b, 0x55,
+        0xd3, 0x9a,
+    ];
+
+    fn ecdsa(hash: Hash) -> AsymmetricSignature {
+        AsymmetricSignature::Ecdsa {
+            hash_alg: hash.into(),
+        }
+    }
+
+    fn signing_key(alg: AsymmetricSignature) -> Attributes {
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1,
+            },
+            bits: 256,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: alg.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn emulated_algorithms_use_the_hardware_one() {
+        for hash in &[Hash::Sha384, Hash::Sha512] {
+            assert_eq!(
+                hardware_attributes(signing_key(ecdsa(*hash)))
+                    .policy
+                    .permitted_algorithms,
+                HARDWARE_ALGORITHM.into()
+            );
+        }
+    }
+
+    #[test]
+    fn other_algorithms_are_kept() {
+        for alg in &[
+            HARDWARE_ALGORITHM,
+            ecdsa(Hash::Sha224),
+            AsymmetricSignature::DeterministicEcdsa {
+                hash_alg: Hash::Sha384.into(),
+            },
+        ] {
+            assert_eq!(hardware_attributes(signing_key(*alg)), signing_key(*alg));
+        }
+    }
+
+    #[test]
+    fn sha256_digests_are_signed_as_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[], false);
+        let hash = [0x42; 32];
+        assert_eq!(
+            *provider.hardware_digest(HARDWARE_ALGORITHM, &hash).unwrap(),
+            hash.to_vec()
+        );
+        assert_eq!(
+            provider
+                .hardware_digest(HARDWARE_ALGORITHM, &[0x42; 48])
+                .unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn emulated_digests_are_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[], true);
+        let hash: Vec<u8> = (0..48).collect();
+        assert_eq!(
+            *provider
+                .hardware_digest(ecdsa(Hash::Sha384), &hash)
+                .unwrap(),
+            hash[..32].to_vec()
+        );
+        assert_eq!(
+            provider
+                .hardware_digest(ecdsa(Hash::Sha512), &hash)
+                .unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn emulated_messages_are_hashed_in_software() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[], true);
+        assert_eq!(
+            *provider
+                .hardware_message_digest(ecdsa(Hash::Sha384), b"abc")
+                .unwrap(),
+            SHA384_ABC.to_vec()
+        );
+        assert_eq!(
+            *provider
+                .hardware_message_digest(ecdsa(Hash::Sha512), b"abc")
+                .unwrap(),
+            SHA512_ABC.to_vec()
+        );
+    }
+
+    #[test]
+    fn emulation_must_be_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[], false);
+        assert_eq!(
+            provider
+                .hardware_digest(ecdsa(Hash::Sha384), &[0; 48])
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+        assert_eq!(
+            provider
+                .hardware_message_digest(ecdsa(Hash::Sha512), b"abc")
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn other_algorithms_are_not_emulated() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[], true);
+        assert_eq!(
+            provider
+                .hardware_digest(ecdsa(Hash::Sha224), &[0; 28])
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+        assert_eq!(
+            provider
+                .hardware_message_digest(
+                    AsymmetricSignature::DeterministicEcdsa {
+                        hash_alg: Hash::Sha384.into(),
+                    },
+                    b"abc"
+                )
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! IO protection key of the ATECC608
+//!
+//! The ATECC608 can encrypt the secrets it sends to the host, such as the shared secret of an
+//! ECDH key agreement, with an IO protection key stored in one of its slots, so that they cannot
+//! be read by sniffing the I2C bus. The slot of the key is set in the chip options of the
+//! configuration zone, which CryptoAuthLib does not expose, so it is also given in the provider
+//! configuration. The host copy of the key is the access key given for that slot in the access
+//! key file.
+use super::Provider;
+use log::{error, info, warn};
+use ring::digest;
+use zeroize::Zeroizing;
+
+/// Size of the blocks masked by the IO protection
+const IO_BLOCK_SIZE: usize = 32;
+/// Bytes of the output nonce used for each block
+const IO_NONCE_SIZE: usize = 16;
+
+impl Provider {
+    /// Keep the host copy of the IO protection key if it is the given access key.
+    pub(super) fn keep_io_protection_key(&mut self, slot: u8, key: &[u8; 32]) {
+        if self.io_protection_key_slot == Some(slot) {
+            self.io_protection_key = Some(Zeroizing::new(*key));
+        }
+    }
+
+    /// Check that the IO protection key can be used if configured, returning false otherwise.
+    pub(super) fn check_io_protection(&self) -> bool {
+        let enabled = self.device.get_device_type()
+            == rust_cryptoauthlib::AtcaDeviceType::ATECC608A
+            && self.device.is_io_protection_key_enabled();
+        let slot = match self.io_protection_key_slot {
+            Some(slot) => slot,
+            None => {
+                if enabled {
+                    warn!("The ATECC has an IO protection key enabled but 'io_protection_key_slot' is not set, secrets sent by the ATECC are not encrypted.");
+                }
+                return true;
+            }
+        };
+        if !enabled {
+            error!("The ATECC does not have an IO protection key enabled.");
+            return false;
+        }
+        if self.io_protection_key.is_none() {
+            error!(
+                "The IO protection key of slot {} is missing from the access key file.",
+                slot
+            );
+            return false;
+        }
+        info!(
+            "Secrets sent by the ATECC are encrypted with the IO protection key of slot {}.",
+            slot
+        );
+        true
+    }
+}
+
+/// Decrypt data sent by the chip with the IO protection key, as `atcah_io_decrypt` of
+/// CryptoAuthLib: each block is masked with the SHA-256 digest of the key followed by
+/// the part of the output nonce for that block.
+pub(super) fn io_decrypt(io_key: &[u8; 32], out_nonce: &[u8], data: &mut [u8]) -> Option<()> {
+    if data.len() % IO_BLOCK_SIZE != 0 {
+        return None;
+    }
+    for (index, block) in data.chunks_mut(IO_BLOCK_SIZE).enumerate() {
+        let nonce = out_nonce.get(index * IO_NONCE_SIZE..(index + 1) * IO_NONCE_SIZE)?;
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(io_key);
+        context.update(nonce);
+        let mask = context.finish();
+        block
+            .iter_mut()
+            .zip(mask.as_ref())
+            .for_each(|(byte, mask)| *byte ^= mask);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test_device::provider;
+    use super::*;
+
+    const IO_KEY: [u8; 32] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
+        0x1e, 0x1f,
+    ];
+    const OUT_NONCE: [u8; 32] = [
+        0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x4b, 0x4c, 0x4d, 0x4e,
+        0x4f, 0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x5b, 0x5c, 0x5d,
+        0x5e, 0x5f,
+    ];
+    // 64 bytes of 0x11 masked with SHA-256(IO_KEY || OUT_NONCE[..16]) followed by
+    // SHA-256(IO_KEY || OUT_NONCE[16..]), computed independently of ring.
+    const ENCRYPTED: [u8; 64] = [
+        0xd8, 0xd2, 0x7b, 0x71, 0xbe, 0x61, 0x03, 0x0f, 0x39, 0xdb, 0xab, 0xcb, 0xb9, 0x8b, 0x2a,
+        0xd7, 0x1b, 0x24, 0x4e, 0x38, 0xb3, 0xc7, 0x65, 0x18, 0x42, 0x56, 0xc2, 0x14, 0x30, 0x5b,
+        0x7b, 0x61, 0x8d, 0x35, 0x36, 0x9a, 0x29, 0xb5, 0x6f, 0x06, 0x46, 0x79, 0xce, 0x37, 0xc7,
+        0xd3, 0x50, 0x9f, 0x3c, 0xf3, 0x33, 0xdc, 0x0b, 0x8a, 0x3c, 0xba, 0x05, 0x82, 0x53, 0xc7,
+        0xad, 0x2a, 0x8f, 0x39,
+    ];
+
+    #[test]
+    fn io_decryption_unmasks_each_block() {
+        let mut data = ENCRYPTED;
+        io_decrypt(&IO_KEY, &OUT_NONCE, &mut data).unwrap();
+        assert_eq!(data, [0x11; 64]);
+    }
+
+    #[test]
+    fn io_decryption_is_its_own_inverse() {
+        let mut data = [0x11; 64];
+        io_decrypt(&IO_KEY, &OUT_NONCE, &mut data).unwrap();
+        assert_eq!(data, ENCRYPTED);
+    }
+
+    #[test]
+    fn partial_blocks_are_rejected() {
+        assert!(io_decrypt(&IO_KEY, &OUT_NONCE, &mut [0; 31]).is_none());
+        assert!(io_decrypt(&IO_KEY, &OUT_NONCE, &mut [0; 33]).is_none());
+    }
+
+    #[test]
+    fn blocks_need_their_part_of_the_nonce() {
+        let mut data = [0; 64];
+        assert!(io_decrypt(&IO_KEY, &OUT_NONCE[..16], &mut data).is_none());
+    }
+
+    #[test]
+    fn only_the_key_of_the_configured_slot_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut provider = provider(&dir, &[], false);
+        provider.keep_io_protection_key(6, &IO_KEY);
+        assert!(provider.io_protection_key.is_none());
+
+        provider.io_protection_key_slot = Some(6);
+        provider.keep_io_protection_key(5, &[0xff; 32]);
+        assert!(provider.io_protection_key.is_none());
+        provider.keep_io_protection_key(6, &IO_KEY);
+        assert_eq!(provider.io_protection_key.as_deref(), Some(&IO_KEY));
+    }
+
+    #[test]
+    fn io_protection_is_optional() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(&dir, &[], false);
+        assert!(provider.check_io_protection());
+    }
+
+    #[test]
+    fn io_protection_needs_an_enabled_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut provider = provider(&dir, &[], false);
+        provider.io_protection_key_slot = Some(6);
+        provider.keep_io_protection_key(6, &IO_KEY);
+        // The test device is not an ATECC608 with an IO protection key enabled.
+        assert!(!provider.check_io_protection());
+    }
+}
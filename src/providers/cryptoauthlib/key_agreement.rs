@@ -1,8 +1,9 @@
 // Copyright 2022 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
-use super::Provider;
+use super::{io_protection, Provider};
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
+use log::error;
 use parsec_interface::operations::psa_algorithm::RawKeyAgreement;
 use parsec_interface::operations::psa_raw_key_agreement;
 use parsec_interface::requests::{ResponseStatus, Result};
@@ -25,9 +26,11 @@ impl Provider {
 
         match op.alg {
             RawKeyAgreement::Ecdh => {
+                // With the IO protection key, the shared secret does not cross the bus in clear.
                 let parameters = rust_cryptoauthlib::EcdhParams {
                     out_target: rust_cryptoauthlib::EcdhTarget::Output,
                     slot_id: Some(key_id),
+                    out_encrypt: self.io_protection_key.is_some(),
                     ..Default::default()
                 };
                 let mut key_data = op.peer_key.to_vec();
@@ -36,7 +39,16 @@ impl Provider {
                 }
                 match self.device.ecdh(parameters, &key_data) {
                     Ok(result) => {
-                        let shared_secret = result.pms.unwrap().to_vec();
+                        let mut shared_secret = result.pms.unwrap().to_vec();
+                        if let Some(io_key) = &self.io_protection_key {
+                            let out_nonce = result.out_nonce.unwrap_or_default();
+                            if io_protection::io_decrypt(io_key, &out_nonce, &mut shared_secret)
+                                .is_none()
+                            {
+                                error!("The shared secret cannot be decrypted with the IO protection key.");
+                                return Err(ResponseStatus::PsaErrorGenericError);
+                            }
+                        }
                         Ok(psa_raw_key_agreement::Result {
                             shared_secret: Secret::new(shared_secret),
                         })
@@ -3,6 +3,8 @@
 use super::key_slot::KeySlotStatus;
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
+use crate::key_info_managers::KeyIdentity;
+use crate::providers::utils::key_destruction::{confirm_destruction, DESTRUCTION_UNCONFIRMED};
 use log::{error, warn};
 use parsec_interface::operations::psa_key_attributes::{Attributes, EccFamily, Type};
 use parsec_interface::operations::utils_deprecated_primitives::CheckDeprecated;
@@ -48,11 +50,15 @@ impl Provider {
         // generate key
         match self.device.gen_key(key_type, slot_id) {
             rust_cryptoauthlib::AtcaStatus::AtcaSuccess => {
-                match self
-                    .key_info_store
-                    .insert_key_info(key_identity, &slot_id, key_attributes)
-                {
-                    Ok(()) => Ok(psa_generate_key::Result {}),
+                match self.key_info_store.insert_key_info(
+                    key_identity.clone(),
+                    &slot_id,
+                    key_attributes,
+                ) {
+                    Ok(()) => {
+                        self.cache_public_key(&key_identity, slot_id, key_attributes.key_type);
+                        Ok(psa_generate_key::Result {})
+                    }
                     Err(error) => {
                         error!("Insert KeyIdentity to KeyInfoManager failed. {}", error);
                         self.key_slots
@@ -86,20 +92,24 @@ impl Provider {
         let key_name = op.key_name;
         let key_identity = self
             .key_info_store
-            .get_key_identity(application_identity.clone(), key_name);
+            .get_key_identity(application_identity.clone(), key_name.clone());
         let key_id = self.key_info_store.get_key_id::<u8>(&key_identity)?;
 
         match self.key_info_store.remove_key_info(&key_identity) {
             Ok(_) => {
-                match self
+                // The key material stays in its slot until the slot is reused, a slot which
+                // cannot be freed still holds it for good.
+                if let Err(error) = self
                     .key_slots
                     .set_slot_status(key_id as usize, KeySlotStatus::Free)
                 {
-                    Ok(()) => (),
-                    Err(error) => {
-                        warn!("Could not set slot {:?} as free because {}", key_id, error);
-                    }
+                    warn!("Could not set slot {:?} as free because {}", key_id, error);
+                    return Err(DESTRUCTION_UNCONFIRMED);
                 }
+                confirm_destruction(
+                    &key_name,
+                    self.key_info_store.get_key_attributes(&key_identity),
+                )?;
                 Ok(psa_destroy_key::Result {})
             }
             Err(error) => {
@@ -139,11 +149,15 @@ impl Provider {
 
         let psa_error_status: ResponseStatus = match atca_error_status {
             rust_cryptoauthlib::AtcaStatus::AtcaSuccess => {
-                match self
-                    .key_info_store
-                    .insert_key_info(key_identity, &slot_id, key_attributes)
-                {
-                    Ok(()) => return Ok(psa_import_key::Result {}),
+                match self.key_info_store.insert_key_info(
+                    key_identity.clone(),
+                    &slot_id,
+                    key_attributes,
+                ) {
+                    Ok(()) => {
+                        self.cache_public_key(&key_identity, slot_id, key_attributes.key_type);
+                        return Ok(psa_import_key::Result {});
+                    }
                     Err(error) => {
                         // This is very bad.
                         error!("Insert KeyIdentity to KeyInfoManager failed. {}", error);
@@ -190,24 +204,36 @@ impl Provider {
             .key_info_store
             .get_key_identity(application_identity.clone(), op.key_name);
         let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
+        if let Some(public_key) = self.key_info_store.get_public_key(&key_identity) {
+            return Ok(psa_export_public_key::Result {
+                data: Zeroizing::new(public_key),
+            });
+        }
+        let slot_number = self.key_info_store.get_key_id(&key_identity)?;
+        let public_key = self.read_public_key(slot_number, key_attributes.key_type)?;
+        self.key_info_store
+            .cache_public_key(&key_identity, public_key.clone());
 
-        match key_attributes.key_type {
+        Ok(psa_export_public_key::Result {
+            data: Zeroizing::new(public_key),
+        })
+    }
+
+    /// Read the public part of the key stored in a slot from the cryptochip.
+    fn read_public_key(&self, slot_number: u8, key_type: Type) -> Result<Vec<u8>> {
+        match key_type {
             Type::EccPublicKey {
                 curve_family: EccFamily::SecpR1,
             }
             | Type::EccKeyPair {
                 curve_family: EccFamily::SecpR1,
             } => {
-                let slot_number = self.key_info_store.get_key_id(&key_identity)?;
                 let mut raw_public_key = Vec::new();
                 let result = self.device.get_public_key(slot_number, &mut raw_public_key);
                 match result {
                     rust_cryptoauthlib::AtcaStatus::AtcaSuccess => {
-                        let public_key =
-                            raw_key_wrap(key_attributes.key_type, &Secret::new(raw_public_key))?;
-                        Ok(psa_export_public_key::Result {
-                            data: Zeroizing::new(public_key.expose_secret().to_vec()),
-                        })
+                        let public_key = raw_key_wrap(key_type, &Secret::new(raw_public_key))?;
+                        Ok(public_key.expose_secret().to_vec())
                     }
                     _ => {
                         error!("Export public key from cryptochip. {}", result);
@@ -219,6 +245,20 @@ impl Provider {
         }
     }
 
+    /// Store the public part of a new ECC key with the Key Info Manager. Failing to do so is not an
+    /// error: it will then be read from the cryptochip when first exported.
+    fn cache_public_key(&self, key_identity: &KeyIdentity, slot_number: u8, key_type: Type) {
+        if !key_type.is_ecc_key_pair() && !key_type.is_ecc_public_key() {
+            return;
+        }
+        match self.read_public_key(slot_number, key_type) {
+            Ok(public_key) => self
+                .key_info_store
+                .cache_public_key(key_identity, public_key),
+            Err(error) => warn!("Failed to read the public part of the new key. {}", error),
+        }
+    }
+
     pub(super) fn psa_export_key_internal(
         &self,
         application_identity: &ApplicationIdentity,
@@ -40,28 +40,78 @@ impl Default for AteccKeySlot {
 }
 
 impl AteccKeySlot {
-    // Check if software key attributes are compatible with hardware slot configuration
+    // Check if software key attributes are compatible with hardware slot configuration.
+    // On mismatch, the reason is returned.
     pub fn key_attr_vs_config(
         &self,
         slot: u8,
         key_attr: &Attributes,
         op: Option<Opcode>,
-    ) -> Result<(), ResponseStatus> {
+    ) -> Result<(), String> {
         // (1) Check attributes.key_type
         if !self.is_key_type_ok(slot, key_attr) {
-            return Err(ResponseStatus::PsaErrorNotSupported);
+            return Err(format!(
+                "slot {} ({}) cannot hold {:?} keys of {} bits",
+                slot,
+                self.description(),
+                key_attr.key_type,
+                key_attr.bits
+            ));
         }
         // (2) Check attributes.policy.usage_flags and slot number
         if !self.is_usage_flags_ok(key_attr) {
-            return Err(ResponseStatus::PsaErrorNotSupported);
+            return Err(format!(
+                "slot {} ({}) does not allow the usage flags {:?}",
+                slot,
+                self.description(),
+                key_attr.policy.usage_flags
+            ));
         }
         // (3) Check attributes.policy.permitted_algorithms
         if !self.is_permitted_algorithms_ok(key_attr, op) {
-            return Err(ResponseStatus::PsaErrorNotSupported);
+            return Err(match op {
+                Some(opcode) => format!(
+                    "slot {} ({}) does not allow {:?} for {:?}",
+                    slot,
+                    self.description(),
+                    key_attr.policy.permitted_algorithms,
+                    opcode
+                ),
+                None => format!(
+                    "slot {} ({}) does not allow {:?}",
+                    slot,
+                    self.description(),
+                    key_attr.policy.permitted_algorithms
+                ),
+            });
         }
         Ok(())
     }
 
+    /// Short description of the slot configuration, for diagnostics
+    pub fn description(&self) -> String {
+        let content = match self.config.key_type {
+            rust_cryptoauthlib::KeyType::P256EccKey if self.config.ecc_key_attr.is_private => {
+                "P256 private key"
+            }
+            rust_cryptoauthlib::KeyType::P256EccKey => "P256 public key",
+            rust_cryptoauthlib::KeyType::Aes => "AES key",
+            rust_cryptoauthlib::KeyType::ShaOrText => "SHA key or data",
+            rust_cryptoauthlib::KeyType::Rfu => "reserved",
+        };
+        let writes = format!("{:?}", self.config.write_config).to_lowercase();
+        format!("{}, {} writes, {}", content, writes, self.status_name())
+    }
+
+    /// Name of the software status of the slot
+    pub fn status_name(&self) -> &'static str {
+        match self.status {
+            KeySlotStatus::Free => "free",
+            KeySlotStatus::Busy => "in use",
+            KeySlotStatus::Locked => "locked",
+        }
+    }
+
     pub fn set_slot_status(&mut self, status: KeySlotStatus) -> Result<(), ResponseStatus> {
         if self.status == KeySlotStatus::Locked {
             return Err(ResponseStatus::PsaErrorNotPermitted);
@@ -1,7 +1,9 @@
 // Copyright 2021 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
+use crate::providers::cryptoauthlib::emulation;
 use crate::providers::cryptoauthlib::key_slot::{AteccKeySlot, KeySlotStatus};
-use log::warn;
+use crate::providers::error_detail::{self, ErrorDetail};
+use log::{info, warn};
 use parsec_interface::operations::psa_key_attributes::Attributes;
 use parsec_interface::requests::{Opcode, ResponseStatus};
 use std::sync::RwLock;
@@ -9,14 +11,26 @@ use std::sync::RwLock;
 #[derive(Debug)]
 pub struct KeySlotStorage {
     storage: RwLock<[AteccKeySlot; rust_cryptoauthlib::ATCA_ATECC_SLOTS_COUNT as usize]>,
+    // Whether keys with emulated signature algorithms are accepted
+    signature_emulation: bool,
 }
 
 impl KeySlotStorage {
-    pub fn new() -> KeySlotStorage {
+    pub fn new(signature_emulation: bool) -> KeySlotStorage {
         KeySlotStorage {
             storage: RwLock::new(
                 [AteccKeySlot::default(); rust_cryptoauthlib::ATCA_ATECC_SLOTS_COUNT as usize],
             ),
+            signature_emulation,
+        }
+    }
+
+    /// Attributes checked against the configuration of the slots
+    fn slot_attributes(&self, key_attr: &Attributes) -> Attributes {
+        if self.signature_emulation {
+            emulation::hardware_attributes(*key_attr)
+        } else {
+            *key_attr
         }
     }
 
@@ -35,6 +49,8 @@ impl KeySlotStorage {
         // (2) if there are no two key identities mapping to a single ATECC slot - warning only ATM
 
         // check (1)
+        // Keys with emulated algorithms are kept even if the emulation was disabled since.
+        let key_attr = &emulation::hardware_attributes(*key_attr);
         match key_slots[key_id as usize].key_attr_vs_config(key_id, key_attr, None) {
             Ok(_) => (),
             Err(err) => {
@@ -100,26 +116,258 @@ impl KeySlotStorage {
         op: Option<Opcode>,
     ) -> Result<u8, ResponseStatus> {
         let mut key_slots = self.storage.write().unwrap();
+        let slot = find_free_slot(&key_slots[..], &self.slot_attributes(key_attr), op)?;
+        match key_slots[slot as usize].set_slot_status(KeySlotStatus::Busy) {
+            Ok(()) => Ok(slot),
+            Err(err) => {
+                warn!(
+                    "find_suitable_slot() - slot {} cannot be marked as busy",
+                    slot
+                );
+                Err(err)
+            }
+        }
+    }
+
+    /// Check, without reserving it, that a free slot can take a new key with the attributes.
+    pub fn check_free_slot(&self, key_attr: &Attributes, op: Opcode) -> Result<(), ResponseStatus> {
+        let key_slots = self.storage.read().unwrap();
+        let _ = find_free_slot(&key_slots[..], &self.slot_attributes(key_attr), Some(op))?;
+        Ok(())
+    }
+
+    /// Check that the configuration of at least one slot allows keys with the attributes.
+    pub fn check_any_slot(&self, key_attr: &Attributes) -> Result<(), ResponseStatus> {
+        let key_slots = self.storage.read().unwrap();
+        let slot_attr = self.slot_attributes(key_attr);
+        let mut refusals = Vec::new();
         for slot in 0..rust_cryptoauthlib::ATCA_ATECC_SLOTS_COUNT {
-            if !key_slots[slot as usize].is_free() {
-                continue;
+            match key_slots[slot as usize].key_attr_vs_config(slot, &slot_attr, None) {
+                Ok(()) => return Ok(()),
+                Err(refusal) => refusals.push(refusal),
             }
-            match key_slots[slot as usize].key_attr_vs_config(slot, key_attr, op) {
-                Ok(_) => {
-                    match key_slots[slot as usize].set_slot_status(KeySlotStatus::Busy) {
-                        Ok(()) => return Ok(slot),
-                        Err(err) => {
-                            warn!(
-                                "find_suitable_slot() - slot {} cannot be marked as busy",
-                                slot
-                            );
-                            return Err(err);
-                        }
-                    };
+        }
+        Err(no_configured_slot(key_attr, &refusals))
+    }
+
+    /// Describe the configuration and status of every slot.
+    pub fn slot_map(&self) -> Vec<String> {
+        let key_slots = self.storage.read().unwrap();
+        key_slots
+            .iter()
+            .enumerate()
+            .map(|(slot, key_slot)| format!("slot {}: {}", slot, key_slot.description()))
+            .collect()
+    }
+}
+
+/// Find a free slot whose configuration matches the attributes. If there is none, the reasons are
+/// attached to the request as error detail.
+fn find_free_slot(
+    key_slots: &[AteccKeySlot],
+    key_attr: &Attributes,
+    op: Option<Opcode>,
+) -> Result<u8, ResponseStatus> {
+    let mut refusals = Vec::new();
+    let mut unavailable = Vec::new();
+    for slot in 0..rust_cryptoauthlib::ATCA_ATECC_SLOTS_COUNT {
+        let key_slot = &key_slots[slot as usize];
+        match key_slot.key_attr_vs_config(slot, key_attr, op) {
+            Ok(()) if key_slot.is_free() => return Ok(slot),
+            Ok(()) => unavailable.push(format!("slot {} is {}", slot, key_slot.status_name())),
+            Err(refusal) => refusals.push(refusal),
+        }
+    }
+    if unavailable.is_empty() {
+        return Err(no_configured_slot(key_attr, &refusals));
+    }
+    let message = format!(
+        "no free slot for {:?} keys of {} bits: {}",
+        key_attr.key_type,
+        key_attr.bits,
+        unavailable.join(", ")
+    );
+    warn!("{}", message);
+    error_detail::set(ErrorDetail::new(
+        "ATECC",
+        String::from("NoFreeSlot"),
+        message,
+    ));
+    Err(ResponseStatus::PsaErrorInsufficientStorage)
+}
+
+fn no_configured_slot(key_attr: &Attributes, refusals: &[String]) -> ResponseStatus {
+    info!(
+        "No ATECC slot is configured for these key attributes: {}",
+        refusals.join("; ")
+    );
+    error_detail::set(ErrorDetail::new(
+        "ATECC",
+        String::from("SlotConfig"),
+        format!(
+            "no slot is configured for {:?} keys of {} bits with these usage flags and algorithm",
+            key_attr.key_type, key_attr.bits
+        ),
+    ));
+    ResponseStatus::PsaErrorNotSupported
+}
+
+#[cfg(test)]
+#[cfg(test)]
+mod tests {
+    use super::super::test_device::{aes_slot, private_key_slot};
+    use super::*;
+    use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash};
+    use parsec_interface::operations::psa_key_attributes::{
+        EccFamily, Lifetime, Policy, Type, UsageFlags,
+    };
+    use rust_cryptoauthlib::{AtcaSlot, WriteConfig};
+
+    fn signing_key() -> Attributes {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags.set_sign_hash();
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1,
+            },
+            bits: 256,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms: AsymmetricSignature::Ecdsa {
+                    hash_alg: Hash::Sha256.into(),
                 }
-                Err(_) => continue,
-            }
+                .into(),
+            },
         }
-        Err(ResponseStatus::PsaErrorInsufficientStorage)
+    }
+
+    fn storage(slots: &[AtcaSlot]) -> KeySlotStorage {
+        let storage = KeySlotStorage::new(false);
+        storage.set_hw_config(slots).unwrap();
+        storage
+    }
+
+    fn locked(mut slot: AtcaSlot) -> AtcaSlot {
+        slot.is_locked = true;
+        slot
+    }
+
+    #[test]
+    fn locked_slots_are_reported() {
+        let storage = storage(&[locked(private_key_slot(2, WriteConfig::Never))]);
+        assert_eq!(
+            storage.find_suitable_slot(&signing_key(), Some(Opcode::PsaGenerateKey)),
+            Err(ResponseStatus::PsaErrorInsufficientStorage)
+        );
+        let detail = error_detail::take().unwrap();
+        assert_eq!(detail.code(), "NoFreeSlot");
+        assert!(detail.message().ends_with("slot 2 is locked"));
+    }
+
+    #[test]
+    fn found_slots_are_reserved() {
+        let storage = storage(&[private_key_slot(2, WriteConfig::Never)]);
+        assert_eq!(
+            storage.find_suitable_slot(&signing_key(), Some(Opcode::PsaGenerateKey)),
+            Ok(2)
+        );
+        assert_eq!(
+            storage.find_suitable_slot(&signing_key(), Some(Opcode::PsaGenerateKey)),
+            Err(ResponseStatus::PsaErrorInsufficientStorage)
+        );
+        assert!(error_detail::take()
+            .unwrap()
+            .message()
+            .ends_with("slot 2 is in use"));
+
+        storage.set_slot_status(2, KeySlotStatus::Free).unwrap();
+        assert_eq!(
+            storage.find_suitable_slot(&signing_key(), Some(Opcode::PsaGenerateKey)),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn checked_slots_are_not_reserved() {
+        let storage = storage(&[private_key_slot(2, WriteConfig::Never)]);
+        storage
+            .check_free_slot(&signing_key(), Opcode::PsaGenerateKey)
+            .unwrap();
+        storage
+            .check_free_slot(&signing_key(), Opcode::PsaGenerateKey)
+            .unwrap();
+        assert_eq!(
+            storage.find_suitable_slot(&signing_key(), Some(Opcode::PsaGenerateKey)),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn unconfigured_keys_are_not_supported() {
+        let storage = storage(&[aes_slot(9)]);
+        assert_eq!(
+            storage.find_suitable_slot(&signing_key(), Some(Opcode::PsaGenerateKey)),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+        assert_eq!(error_detail::take().unwrap().code(), "SlotConfig");
+        assert_eq!(
+            storage.check_any_slot(&signing_key()),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[test]
+    fn any_slot_is_checked_whatever_its_status() {
+        let storage = storage(&[locked(private_key_slot(2, WriteConfig::Never))]);
+        storage.check_any_slot(&signing_key()).unwrap();
+    }
+
+    #[test]
+    fn stored_keys_mark_their_slot_busy() {
+        let storage = storage(&[private_key_slot(2, WriteConfig::Never)]);
+        assert_eq!(
+            storage.key_validate_and_mark_busy(2, &signing_key()),
+            Ok(None)
+        );
+        assert_eq!(
+            storage.find_suitable_slot(&signing_key(), Some(Opcode::PsaGenerateKey)),
+            Err(ResponseStatus::PsaErrorInsufficientStorage)
+        );
+    }
+
+    #[test]
+    fn second_reference_to_a_slot_is_reported() {
+        let storage = storage(&[private_key_slot(2, WriteConfig::Never)]);
+        assert_eq!(
+            storage.key_validate_and_mark_busy(2, &signing_key()),
+            Ok(None)
+        );
+        assert!(storage
+            .key_validate_and_mark_busy(2, &signing_key())
+            .unwrap()
+            .unwrap()
+            .starts_with("Superfluous reference"));
+    }
+
+    #[test]
+    fn stored_keys_must_match_their_slot() {
+        let storage = storage(&[aes_slot(9)]);
+        assert!(storage
+            .key_validate_and_mark_busy(9, &signing_key())
+            .unwrap_err()
+            .starts_with("ATECC slot configuration mismatch"));
+    }
+
+    #[test]
+    fn slot_map_describes_every_slot() {
+        let storage = storage(&[locked(private_key_slot(2, WriteConfig::Never)), aes_slot(9)]);
+        let map = storage.slot_map();
+        assert_eq!(
+            map.len(),
+            rust_cryptoauthlib::ATCA_ATECC_SLOTS_COUNT as usize
+        );
+        assert_eq!(map[2], "slot 2: P256 private key, never writes, locked");
+        assert_eq!(map[9], "slot 9: AES key, always writes, free");
     }
 }
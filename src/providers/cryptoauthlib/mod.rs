@@ -7,30 +7,35 @@
 use super::Provide;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::{KeyIdentity, KeyInfoManagerClient};
+use crate::providers::crypto_capability::CanDoCrypto;
 use crate::providers::cryptoauthlib::key_slot_storage::KeySlotStorage;
 use crate::providers::ProviderIdentity;
 use derivative::Derivative;
-use log::{error, trace, warn};
+use log::{error, info, trace, warn};
 use parsec_interface::operations::list_providers::ProviderInfo;
 use parsec_interface::operations::list_providers::Uuid;
 use parsec_interface::operations::{list_clients, list_keys};
 use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
 use std::collections::HashSet;
 use std::io::{Error, ErrorKind};
+use zeroize::Zeroizing;
 
 use parsec_interface::operations::{
-    psa_aead_decrypt, psa_aead_encrypt, psa_cipher_decrypt, psa_cipher_encrypt, psa_destroy_key,
-    psa_export_key, psa_export_public_key, psa_generate_key, psa_generate_random, psa_hash_compare,
-    psa_hash_compute, psa_import_key, psa_raw_key_agreement, psa_sign_hash, psa_sign_message,
-    psa_verify_hash, psa_verify_message,
+    can_do_crypto, psa_aead_decrypt, psa_aead_encrypt, psa_cipher_decrypt, psa_cipher_encrypt,
+    psa_destroy_key, psa_export_key, psa_export_public_key, psa_generate_key, psa_generate_random,
+    psa_hash_compare, psa_hash_compute, psa_import_key, psa_raw_key_agreement, psa_sign_hash,
+    psa_sign_message, psa_verify_hash, psa_verify_message,
 };
 
 mod access_keys;
 mod aead;
 mod asym_sign;
+mod capability_discovery;
 mod cipher;
+mod emulation;
 mod generate_random;
 mod hash;
+mod io_protection;
 mod key_agreement;
 mod key_management;
 mod key_slot;
@@ -49,6 +54,12 @@ pub struct Provider {
     key_info_store: KeyInfoManagerClient,
     key_slots: KeySlotStorage,
     supported_opcodes: HashSet<Opcode>,
+    // The slot of the IO protection key, if configured.
+    io_protection_key_slot: Option<u8>,
+    #[derivative(Debug = "ignore")]
+    io_protection_key: Option<Zeroizing<[u8; 32]>>,
+    // Whether ECDSA with other hashes than SHA-256 is emulated
+    signature_emulation: bool,
 }
 
 impl Provider {
@@ -64,6 +75,9 @@ impl Provider {
         key_info_store: KeyInfoManagerClient,
         atca_iface: rust_cryptoauthlib::AtcaIfaceCfg,
         access_key_file_name: Option<String>,
+        slot_map_file_name: Option<String>,
+        io_protection_key_slot: Option<u8>,
+        signature_emulation: bool,
     ) -> Option<Provider> {
         // First define communication channel with the device then set it up
         let device = match rust_cryptoauthlib::setup_atecc_device(atca_iface) {
@@ -88,8 +102,11 @@ impl Provider {
                 uuid: String::from(Self::PROVIDER_UUID),
             },
             key_info_store,
-            key_slots: KeySlotStorage::new(),
+            key_slots: KeySlotStorage::new(signature_emulation),
             supported_opcodes: HashSet::new(),
+            io_protection_key_slot,
+            io_protection_key: None,
+            signature_emulation,
         };
 
         // Get the configuration from ATECC...
@@ -139,7 +156,7 @@ impl Provider {
                         Ok(x) => x,
                         Err(err) => {
                             warn!(
-                                "Could not get key info id for KeyIdentity {:?} because {}",
+                                "Could not get key info id for KeyIdentity {} because {}",
                                 key_identity, err
                             );
                             to_remove.push(key_identity.clone());
@@ -153,7 +170,7 @@ impl Provider {
                         Ok(x) => x,
                         Err(err) => {
                             warn!(
-                                "Could not get key attributes for KeyIdentity {:?} because {}",
+                                "Could not get key attributes for KeyIdentity {} because {}",
                                 key_identity, err
                             );
                             to_remove.push(key_identity.clone());
@@ -166,10 +183,10 @@ impl Provider {
                     {
                         Ok(None) => (),
                         Ok(Some(warning)) => {
-                            warn!("{} for KeyIdentity {:?}", warning, key_identity)
+                            warn!("{} for KeyIdentity {}", warning, key_identity)
                         }
                         Err(err) => {
-                            warn!("{} for KeyIdentity {:?}", err, key_identity);
+                            warn!("{} for KeyIdentity {}", err, key_identity);
                             to_remove.push(key_identity.clone());
                             continue;
                         }
@@ -191,6 +208,20 @@ impl Provider {
             }
         }
 
+        // Now that the slots in use are known, report what each slot can hold.
+        let slot_map = cryptoauthlib_provider.key_slots.slot_map();
+        for slot in &slot_map {
+            info!("ATECC {}", slot);
+        }
+        if let Some(file_name) = slot_map_file_name {
+            if let Err(err) = std::fs::write(&file_name, slot_map.join("\n") + "\n") {
+                warn!(
+                    "Cannot write the slot map to {} because: {}.",
+                    file_name, err
+                );
+            }
+        }
+
         if cryptoauthlib_provider.set_opcodes().is_none() {
             warn!("Failed to setup opcodes for cryptoauthlib_provider");
         }
@@ -202,6 +233,9 @@ impl Provider {
                 warn!("Unable to set access keys. This is dangerous for a hardware interface.");
             }
         }
+        if !cryptoauthlib_provider.check_io_protection() {
+            return None;
+        }
 
         Some(cryptoauthlib_provider)
     }
@@ -228,6 +262,7 @@ impl Provider {
                     && self.supported_opcodes.insert(Opcode::PsaAeadEncrypt)
                     && self.supported_opcodes.insert(Opcode::PsaAeadDecrypt)
                     && self.supported_opcodes.insert(Opcode::PsaRawKeyAgreement)
+                    && self.supported_opcodes.insert(Opcode::CanDoCrypto)
                 {
                     Some(())
                 } else {
@@ -498,6 +533,23 @@ impl Provide for Provider {
             self.psa_raw_key_agreement_internal(application_identity, op)
         }
     }
+
+    fn can_do_crypto(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: can_do_crypto::Operation,
+    ) -> Result<can_do_crypto::Result> {
+        trace!("can_do_crypto ingress");
+        if !self.supported_opcodes.contains(&Opcode::CanDoCrypto) {
+            Err(ResponseStatus::PsaErrorNotSupported)
+        } else {
+            self.can_do_crypto_main(application_identity, op)
+        }
+    }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        Some(&self.key_info_store)
+    }
 }
 
 /// CryptoAuthentication Library Provider builder
@@ -515,6 +567,9 @@ pub struct ProviderBuilder {
     bus: Option<u8>,
     baud: Option<u32>,
     access_key_file_name: Option<String>,
+    slot_map_file_name: Option<String>,
+    io_protection_key_slot: Option<u8>,
+    signature_emulation: Option<bool>,
 }
 
 impl ProviderBuilder {
@@ -531,6 +586,9 @@ impl ProviderBuilder {
             bus: None,
             baud: None,
             access_key_file_name: None,
+            slot_map_file_name: None,
+            io_protection_key_slot: None,
+            signature_emulation: None,
         }
     }
 
@@ -604,6 +662,33 @@ impl ProviderBuilder {
         self
     }
 
+    /// Specify the file where the slot map is written
+    pub fn with_slot_map_file(mut self, slot_map_file_name: Option<String>) -> ProviderBuilder {
+        self.slot_map_file_name = slot_map_file_name;
+
+        self
+    }
+
+    /// Specify the slot of the IO protection key
+    pub fn with_io_protection_key_slot(
+        mut self,
+        io_protection_key_slot: Option<u8>,
+    ) -> ProviderBuilder {
+        self.io_protection_key_slot = io_protection_key_slot;
+
+        self
+    }
+
+    /// Specify whether ECDSA with other hashes than SHA-256 is emulated
+    pub fn with_signature_emulation(
+        mut self,
+        signature_emulation: Option<bool>,
+    ) -> ProviderBuilder {
+        self.signature_emulation = signature_emulation;
+
+        self
+    }
+
     /// Attempt to build CryptoAuthLib Provider
     pub fn build(self) -> std::io::Result<Provider> {
         let iface_cfg = match self.iface_type {
@@ -659,6 +744,9 @@ impl ProviderBuilder {
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing key info store"))?,
             iface_cfg,
             self.access_key_file_name,
+            self.slot_map_file_name,
+            self.io_protection_key_slot,
+            self.signature_emulation.unwrap_or(false),
         )
         .ok_or_else(|| {
             Error::new(
@@ -668,3 +756,81 @@ impl ProviderBuilder {
         })
     }
 }
+
+/// Provider on the test device of CryptoAuthLib, whose slots are configured by the tests
+#[cfg(all(test, feature = "sqlite-kim"))]
+pub(super) mod test_device {
+    use super::{Provider, ProviderBuilder};
+    use crate::key_info_managers::KeyInfoManagerFactory;
+    use crate::providers::ProviderIdentity;
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::requests::AuthType;
+    use rust_cryptoauthlib::{AtcaSlot, KeyType, SlotConfig, WriteConfig};
+    use tempfile::TempDir;
+
+    /// Slot holding a P-256 private key used for ECDSA signatures, written as configured
+    pub(super) fn private_key_slot(id: u8, write_config: WriteConfig) -> AtcaSlot {
+        let mut config = SlotConfig {
+            write_config,
+            key_type: KeyType::P256EccKey,
+            is_secret: true,
+            pub_info: true,
+            ..Default::default()
+        };
+        config.ecc_key_attr.is_private = true;
+        config.ecc_key_attr.ext_sign = true;
+        AtcaSlot {
+            id,
+            is_locked: false,
+            config,
+        }
+    }
+
+    /// Slot holding an AES key, written in clear
+    pub(super) fn aes_slot(id: u8) -> AtcaSlot {
+        AtcaSlot {
+            id,
+            is_locked: false,
+            config: SlotConfig {
+                write_config: WriteConfig::Always,
+                key_type: KeyType::Aes,
+                is_secret: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Build a provider on the test device with the given slots, storing its mappings in `dir`.
+    pub(super) fn provider(
+        dir: &TempDir,
+        slots: &[AtcaSlot],
+        signature_emulation: bool,
+    ) -> Provider {
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: "sqlite-manager".to_string(),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(dir.path().join("kim.sqlite3").to_str().unwrap().to_string()),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap();
+        let provider = ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_key_info_store(factory.build_client(ProviderIdentity::new(
+                Provider::PROVIDER_UUID.to_string(),
+                Provider::DEFAULT_PROVIDER_NAME.to_string(),
+            )))
+            .with_iface_type("test-interface".to_string())
+            .with_device_type("always-success".to_string())
+            .with_signature_emulation(Some(signature_emulation))
+            .build()
+            .unwrap();
+        // The test device has no slot configuration of its own.
+        provider.key_slots.set_hw_config(slots).unwrap();
+        provider
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Provider-specific error context
+//!
+//! The `ResponseStatus` returned to clients is generic and loses what the backend actually
+//! reported (TPM response code, PKCS#11 return value, ...). Providers can attach that information
+//! to the request being handled by the current thread with [`set`]; the back end handler then
+//! collects it with [`take`] and logs it next to the status returned on the wire.
+//!
+//! The detail does not reach the client: the responses of the wire protocol only have a status,
+//! so clients still get the generic `ResponseStatus` and the detail is only in the logs of the
+//! service.
+use std::cell::RefCell;
+use std::fmt;
+
+/// Error reported by the library or hardware backing a provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorDetail {
+    backend: &'static str,
+    code: String,
+    message: String,
+}
+
+impl ErrorDetail {
+    /// Create a new error detail for the given backend (e.g. "TSS" or "PKCS#11").
+    pub fn new(backend: &'static str, code: String, message: String) -> Self {
+        ErrorDetail {
+            backend,
+            code,
+            message,
+        }
+    }
+
+    /// Name of the backend that produced the error
+    pub fn backend(&self) -> &str {
+        self.backend
+    }
+
+    /// Backend-specific error code
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Human readable description of the error
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error {}: {}", self.backend, self.code, self.message)
+    }
+}
+
+thread_local! {
+    static ERROR_DETAIL: RefCell<Option<ErrorDetail>> = RefCell::new(None);
+}
+
+/// Attach an error detail to the request handled by the current thread, replacing any previous
+/// one.
+pub fn set(detail: ErrorDetail) {
+    ERROR_DETAIL.with(|cell| *cell.borrow_mut() = Some(detail));
+}
+
+/// Remove and return the error detail attached to the request handled by the current thread.
+pub fn take() -> Option<ErrorDetail> {
+    ERROR_DETAIL.with(|cell| cell.borrow_mut().take())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    fn detail(code: &str) -> ErrorDetail {
+        ErrorDetail::new(
+            "TSS",
+            String::from(code),
+            String::from("the handle is not correct for the use"),
+        )
+    }
+
+    #[test]
+    fn detail_is_taken_once() {
+        assert_eq!(take(), None);
+        set(detail("Handle"));
+        let taken = take().unwrap();
+        assert_eq!(taken.backend(), "TSS");
+        assert_eq!(taken.code(), "Handle");
+        assert_eq!(taken.message(), "the handle is not correct for the use");
+        assert_eq!(take(), None);
+    }
+
+    #[test]
+    fn last_detail_is_kept() {
+        set(detail("Handle"));
+        set(detail("Value"));
+        assert_eq!(take().unwrap().code(), "Value");
+    }
+
+    #[test]
+    fn detail_belongs_to_its_thread() {
+        set(detail("Handle"));
+        assert_eq!(thread::spawn(take).join().unwrap(), None);
+        assert_eq!(take().unwrap().code(), "Handle");
+    }
+
+    #[test]
+    fn displayed_with_backend_and_code() {
+        assert_eq!(
+            detail("Handle").to_string(),
+            "TSS error Handle: the handle is not correct for the use"
+        );
+    }
+}
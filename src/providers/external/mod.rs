@@ -0,0 +1,741 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! External provider
+//!
+//! The operations of this provider are done by a plugin, a separate process, so that proprietary
+//! backends such as vendor HSMs can be used without being linked into the service or built
+//! against its Rust API. The plugin holds the keys and serves the configured provider ID.
+//!
+//! The plugin listens on a Unix domain socket and speaks the Parsec wire protocol, with protobuf
+//! bodies: it behaves as a Parsec service with a single provider, and can be written with any of
+//! the Parsec client libraries or protobuf implementations. It receives:
+//! * the cryptographic operations, addressed to its provider ID, authenticated with the direct
+//!   authenticator as the application which sent them to the service;
+//! * the `PsaHashCompute`, `PsaHashCompare` and `PsaGenerateRandom` operations, addressed to its
+//!   provider ID, without authentication;
+//! * the `ListProviders` and `ListOpcodes` operations, addressed to the Core provider, once at
+//!   startup, to describe the provider. A plugin not answering them gets a default description
+//!   and all the operations it can be sent;
+//! * the `ListKeys` operation, addressed to the Core provider, as the application listing its keys;
+//! * the `ListClients` and `DeleteClient` admin operations, addressed to the Core provider, without
+//!   authentication: the service has checked the admin rights of the client before.
+//!
+//! The plugin implicitly trusts the service: its socket must only be reachable by the service.
+//! The plugin can also be a program started by the service, talking through its standard streams,
+//! for example to run a WebAssembly module in a sandbox: see the [`process`] module.
+use super::Provide;
+use crate::authenticators::ApplicationIdentity;
+use crate::providers::utils::remote_client::RemoteClient;
+use crate::providers::ProviderIdentity;
+use derivative::Derivative;
+use log::{error, trace, warn};
+use parsec_interface::operations::list_providers::{ProviderInfo, Uuid};
+use parsec_interface::operations::{
+    attest_key, can_do_crypto, delete_client, list_clients, list_keys, list_opcodes,
+    list_providers, prepare_key_attestation, psa_aead_decrypt, psa_aead_encrypt,
+    psa_asymmetric_decrypt, psa_asymmetric_encrypt, psa_cipher_decrypt, psa_cipher_encrypt,
+    psa_destroy_key, psa_export_key, psa_export_public_key, psa_generate_key, psa_generate_random,
+    psa_hash_compare, psa_hash_compute, psa_import_key, psa_raw_key_agreement, psa_sign_hash,
+    psa_sign_message, psa_verify_hash, psa_verify_message, NativeOperation, NativeResult,
+};
+use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
+use process::PluginProcess;
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub mod process;
+
+/// Operations which can be sent to the plugin
+const PLUGIN_OPCODES: [Opcode; 22] = [
+    Opcode::PsaGenerateKey,
+    Opcode::PsaImportKey,
+    Opcode::PsaExportPublicKey,
+    Opcode::PsaExportKey,
+    Opcode::PsaDestroyKey,
+    Opcode::PsaSignHash,
+    Opcode::PsaVerifyHash,
+    Opcode::PsaSignMessage,
+    Opcode::PsaVerifyMessage,
+    Opcode::PsaAsymmetricEncrypt,
+    Opcode::PsaAsymmetricDecrypt,
+    Opcode::PsaAeadEncrypt,
+    Opcode::PsaAeadDecrypt,
+    Opcode::PsaCipherEncrypt,
+    Opcode::PsaCipherDecrypt,
+    Opcode::PsaHashCompute,
+    Opcode::PsaHashCompare,
+    Opcode::PsaRawKeyAgreement,
+    Opcode::PsaGenerateRandom,
+    Opcode::CanDoCrypto,
+    Opcode::PrepareKeyAttestation,
+    Opcode::AttestKey,
+];
+
+/// External provider structure
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Provider {
+    // The identity of the provider including uuid & name.
+    provider_identity: ProviderIdentity,
+    // ID served by the plugin
+    provider_id: ProviderId,
+    plugin: Plugin,
+    // Description of the plugin, or the default one
+    #[derivative(Debug = "ignore")]
+    info: ProviderInfo,
+    // Operations of the plugin
+    opcodes: HashSet<Opcode>,
+}
+
+/// Way the plugin is reached
+#[derive(Debug)]
+enum Plugin {
+    Socket(RemoteClient),
+    Process(PluginProcess),
+}
+
+/// Implement an operation of an application by sending it to the plugin.
+macro_rules! plugin_operation {
+    ($method:ident, $operation:ident, $variant:ident) => {
+        fn $method(
+            &self,
+            application_identity: &ApplicationIdentity,
+            op: $operation::Operation,
+        ) -> Result<$operation::Result> {
+            trace!(concat!(stringify!($method), " ingress"));
+            match self.send(
+                self.provider_id,
+                Some(application_identity.name()),
+                NativeOperation::$variant(op),
+            )? {
+                NativeResult::$variant(result) => Ok(result),
+                _ => Err(unexpected_result()),
+            }
+        }
+    };
+}
+
+/// Implement an operation which does not need an application by sending it to the plugin.
+macro_rules! plugin_unauthenticated_operation {
+    ($method:ident, $operation:ident, $variant:ident) => {
+        fn $method(&self, op: $operation::Operation) -> Result<$operation::Result> {
+            trace!(concat!(stringify!($method), " ingress"));
+            match self.send(self.provider_id, None, NativeOperation::$variant(op))? {
+                NativeResult::$variant(result) => Ok(result),
+                _ => Err(unexpected_result()),
+            }
+        }
+    };
+}
+
+fn unexpected_result() -> ResponseStatus {
+    error!("The plugin returned the result of another operation.");
+    ResponseStatus::PsaErrorCommunicationFailure
+}
+
+impl Provider {
+    /// The default provider name for external provider
+    pub const DEFAULT_PROVIDER_NAME: &'static str = "external-provider";
+
+    /// The UUID for this provider
+    pub const PROVIDER_UUID: &'static str = "0d7b3e52-94a6-4c1f-8e2d-5a9c7f1b3e60";
+
+    fn send(
+        &self,
+        provider: ProviderId,
+        application: Option<&str>,
+        operation: NativeOperation,
+    ) -> Result<NativeResult> {
+        match &self.plugin {
+            Plugin::Socket(client) => client.send(provider, application, operation),
+            Plugin::Process(process) => process.send(provider, application, operation),
+        }
+    }
+
+    /// Description of the provider given by the plugin, or the default one if it can not tell.
+    fn plugin_info(&self) -> Result<ProviderInfo> {
+        let operation = NativeOperation::ListProviders(list_providers::Operation {});
+        let described = match self.send(ProviderId::Core, None, operation) {
+            Ok(NativeResult::ListProviders(result)) => result
+                .providers
+                .into_iter()
+                .find(|provider| provider.id == self.provider_id),
+            Ok(_) => {
+                let _ = unexpected_result();
+                None
+            }
+            Err(e) => {
+                warn!("The plugin could not describe itself ({}).", e);
+                None
+            }
+        };
+        match described {
+            Some(info) => Ok(info),
+            None => Ok(ProviderInfo {
+                // Assigned UUID for this provider: 0d7b3e52-94a6-4c1f-8e2d-5a9c7f1b3e60
+                uuid: Uuid::from_str(Self::PROVIDER_UUID)
+                    .or(Err(ResponseStatus::InvalidEncoding))?,
+                description: String::from("External provider, using an out-of-process plugin"),
+                vendor: String::from("Parsec"),
+                version_maj: 0,
+                version_min: 1,
+                version_rev: 0,
+                id: self.provider_id,
+            }),
+        }
+    }
+
+    /// Operations of the plugin, or all those which can be sent to it if it can not tell.
+    fn plugin_opcodes(&self) -> HashSet<Opcode> {
+        let supported: HashSet<Opcode> = PLUGIN_OPCODES.iter().copied().collect();
+        let operation = NativeOperation::ListOpcodes(list_opcodes::Operation {
+            provider_id: self.provider_id,
+        });
+        match self.send(ProviderId::Core, None, operation) {
+            Ok(NativeResult::ListOpcodes(result)) => {
+                result.opcodes.intersection(&supported).copied().collect()
+            }
+            Ok(_) => {
+                let _ = unexpected_result();
+                supported
+            }
+            Err(e) => {
+                warn!(
+                    "The operations of the plugin could not be listed ({}), all are advertised.",
+                    e
+                );
+                supported
+            }
+        }
+    }
+}
+
+impl Provide for Provider {
+    fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+        trace!("describe ingress");
+        Ok((self.info.clone(), self.opcodes.clone()))
+    }
+
+    fn list_keys(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: list_keys::Operation,
+    ) -> Result<list_keys::Result> {
+        trace!("list_keys ingress");
+        match self.send(
+            ProviderId::Core,
+            Some(application_identity.name()),
+            NativeOperation::ListKeys(op),
+        )? {
+            NativeResult::ListKeys(result) => Ok(list_keys::Result {
+                keys: result
+                    .keys
+                    .into_iter()
+                    .filter(|key| key.provider_id == self.provider_id)
+                    .collect(),
+            }),
+            _ => Err(unexpected_result()),
+        }
+    }
+
+    fn list_clients(&self, op: list_clients::Operation) -> Result<list_clients::Result> {
+        trace!("list_clients ingress");
+        match self.send(ProviderId::Core, None, NativeOperation::ListClients(op))? {
+            NativeResult::ListClients(result) => Ok(result),
+            _ => Err(unexpected_result()),
+        }
+    }
+
+    fn delete_client(
+        &self,
+        _application_identity: &ApplicationIdentity,
+        op: delete_client::Operation,
+    ) -> Result<delete_client::Result> {
+        trace!("delete_client ingress");
+        match self.send(ProviderId::Core, None, NativeOperation::DeleteClient(op))? {
+            NativeResult::DeleteClient(result) => Ok(result),
+            _ => Err(unexpected_result()),
+        }
+    }
+
+    plugin_operation!(psa_generate_key, psa_generate_key, PsaGenerateKey);
+    plugin_operation!(psa_import_key, psa_import_key, PsaImportKey);
+    plugin_operation!(
+        psa_export_public_key,
+        psa_export_public_key,
+        PsaExportPublicKey
+    );
+    plugin_operation!(psa_export_key, psa_export_key, PsaExportKey);
+    plugin_operation!(psa_destroy_key, psa_destroy_key, PsaDestroyKey);
+    plugin_operation!(psa_sign_hash, psa_sign_hash, PsaSignHash);
+    plugin_operation!(psa_verify_hash, psa_verify_hash, PsaVerifyHash);
+    plugin_operation!(psa_sign_message, psa_sign_message, PsaSignMessage);
+    plugin_operation!(psa_verify_message, psa_verify_message, PsaVerifyMessage);
+    plugin_operation!(
+        psa_asymmetric_encrypt,
+        psa_asymmetric_encrypt,
+        PsaAsymmetricEncrypt
+    );
+    plugin_operation!(
+        psa_asymmetric_decrypt,
+        psa_asymmetric_decrypt,
+        PsaAsymmetricDecrypt
+    );
+    plugin_operation!(psa_aead_encrypt, psa_aead_encrypt, PsaAeadEncrypt);
+    plugin_operation!(psa_aead_decrypt, psa_aead_decrypt, PsaAeadDecrypt);
+    plugin_operation!(psa_cipher_encrypt, psa_cipher_encrypt, PsaCipherEncrypt);
+    plugin_operation!(psa_cipher_decrypt, psa_cipher_decrypt, PsaCipherDecrypt);
+    plugin_operation!(
+        psa_raw_key_agreement,
+        psa_raw_key_agreement,
+        PsaRawKeyAgreement
+    );
+    plugin_operation!(can_do_crypto, can_do_crypto, CanDoCrypto);
+    plugin_operation!(
+        prepare_key_attestation,
+        prepare_key_attestation,
+        PrepareKeyAttestation
+    );
+    plugin_operation!(attest_key, attest_key, AttestKey);
+    plugin_unauthenticated_operation!(psa_hash_compute, psa_hash_compute, PsaHashCompute);
+    plugin_unauthenticated_operation!(psa_hash_compare, psa_hash_compare, PsaHashCompare);
+    plugin_unauthenticated_operation!(psa_generate_random, psa_generate_random, PsaGenerateRandom);
+}
+
+/// External provider builder
+#[derive(Default, Derivative)]
+#[derivative(Debug)]
+pub struct ProviderBuilder {
+    provider_name: Option<String>,
+    provider_id: Option<ProviderId>,
+    socket_path: Option<String>,
+    command: Option<Vec<String>>,
+    timeout: Option<u64>,
+}
+
+impl ProviderBuilder {
+    /// Create a new provider builder
+    pub fn new() -> ProviderBuilder {
+        ProviderBuilder {
+            provider_name: None,
+            provider_id: None,
+            socket_path: None,
+            command: None,
+            timeout: None,
+        }
+    }
+
+    /// Add a provider name
+    pub fn with_provider_name(mut self, provider_name: String) -> ProviderBuilder {
+        self.provider_name = Some(provider_name);
+
+        self
+    }
+
+    /// Specify the provider ID served by the plugin
+    pub fn with_provider_id(mut self, provider_id: ProviderId) -> ProviderBuilder {
+        self.provider_id = Some(provider_id);
+
+        self
+    }
+
+    /// Specify the path of the socket of the plugin
+    pub fn with_socket_path(mut self, socket_path: String) -> ProviderBuilder {
+        self.socket_path = Some(socket_path);
+
+        self
+    }
+
+    /// Specify the command starting the plugin, the program followed by its arguments
+    pub fn with_command(mut self, command: Vec<String>) -> ProviderBuilder {
+        self.command = Some(command);
+
+        self
+    }
+
+    /// Specify the timeout (in milliseconds) of the requests to the plugin
+    pub fn with_timeout(mut self, timeout: Option<u64>) -> ProviderBuilder {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// Build into an external Provider
+    pub fn build(self) -> std::io::Result<Provider> {
+        let provider_name = self
+            .provider_name
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing provider name"))?;
+        let provider_id = self
+            .provider_id
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing provider ID"))?;
+        if provider_id == ProviderId::Core {
+            error!("A plugin can not serve the Core provider.");
+            return Err(Error::new(ErrorKind::InvalidData, "invalid provider ID"));
+        }
+        let timeout = self.timeout.map(Duration::from_millis);
+        let plugin = match (self.socket_path, self.command) {
+            (Some(socket_path), None) => Plugin::Socket(RemoteClient::new(socket_path, timeout)),
+            (None, Some(command)) => Plugin::Process(PluginProcess::new(command, timeout)?),
+            _ => {
+                error!(
+                    "Exactly one of the socket path and the command of the plugin must be given."
+                );
+                return Err(Error::new(ErrorKind::InvalidData, "invalid plugin"));
+            }
+        };
+
+        let mut provider = Provider {
+            provider_identity: ProviderIdentity::new(
+                Provider::PROVIDER_UUID.to_string(),
+                provider_name,
+            ),
+            provider_id,
+            plugin,
+            info: ProviderInfo {
+                uuid: Uuid::nil(),
+                description: String::new(),
+                vendor: String::new(),
+                version_maj: 0,
+                version_min: 0,
+                version_rev: 0,
+                id: provider_id,
+            },
+            opcodes: HashSet::new(),
+        };
+        provider.info = provider
+            .plugin_info()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        provider.opcodes = provider.plugin_opcodes();
+        Ok(provider)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::psa_algorithm::Algorithm;
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::operations::{list_keys::KeyInfo, Convert};
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::{AuthType, Request, Response};
+    use parsec_interface::secrecy::ExposeSecret;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::thread::{self, JoinHandle};
+
+    type Answer = (ResponseStatus, Option<NativeResult>);
+
+    // Plugin answering the given number of requests, returning them
+    fn plugin(
+        dir: &Path,
+        requests: usize,
+        answer: impl Fn(&Request) -> Answer + Send + 'static,
+    ) -> (String, JoinHandle<Vec<Request>>) {
+        let socket_path = dir.join("plugin.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let plugin = thread::spawn(move || {
+            let mut received = Vec::new();
+            for _ in 0..requests {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = Request::read_from_stream(&mut stream, 1024).unwrap();
+                let (status, result) = answer(&request);
+                let mut response = Response::from_request_header(request.header, status);
+                if let Some(result) = result {
+                    response.body = ProtobufConverter {}.result_to_body(result).unwrap();
+                }
+                response.write_to_stream(&mut stream).unwrap();
+                received.push(request);
+            }
+            received
+        });
+        (socket_path.display().to_string(), plugin)
+    }
+
+    // Plugin which can not describe itself, answering the operations after the description
+    fn undescribed(
+        dir: &Path,
+        operations: usize,
+        answer: impl Fn(&Request) -> Answer + Send + 'static,
+    ) -> (String, JoinHandle<Vec<Request>>) {
+        plugin(dir, operations + 2, move |request| {
+            match request.header.opcode {
+                Opcode::ListProviders | Opcode::ListOpcodes => {
+                    (ResponseStatus::PsaErrorNotSupported, None)
+                }
+                _ => answer(request),
+            }
+        })
+    }
+
+    fn provider(socket_path: String) -> Provider {
+        ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_provider_id(ProviderId::Pkcs11)
+            .with_socket_path(socket_path)
+            .with_timeout(Some(10000))
+            .build()
+            .unwrap()
+    }
+
+    fn application() -> ApplicationIdentity {
+        ApplicationIdentity::new(String::from("app"), AuthType::Direct)
+    }
+
+    fn destroy_key(provider: &Provider) -> Result<psa_destroy_key::Result> {
+        provider.psa_destroy_key(
+            &application(),
+            psa_destroy_key::Operation {
+                key_name: String::from("key"),
+            },
+        )
+    }
+
+    fn info(id: ProviderId, vendor: &str) -> ProviderInfo {
+        ProviderInfo {
+            uuid: Uuid::nil(),
+            description: String::from("HSM plugin"),
+            vendor: String::from(vendor),
+            version_maj: 2,
+            version_min: 0,
+            version_rev: 0,
+            id,
+        }
+    }
+
+    fn key(provider_id: ProviderId, name: &str) -> KeyInfo {
+        KeyInfo {
+            provider_id,
+            name: String::from(name),
+            attributes: Attributes {
+                lifetime: Lifetime::Persistent,
+                key_type: Type::RsaKeyPair,
+                bits: 1024,
+                policy: Policy {
+                    usage_flags: UsageFlags::default(),
+                    permitted_algorithms: Algorithm::None,
+                },
+            },
+        }
+    }
+
+    fn answered(result: NativeResult) -> Answer {
+        (ResponseStatus::Success, Some(result))
+    }
+
+    #[test]
+    fn unreachable_plugins_get_the_default_description() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(dir.path().join("missing.sock").display().to_string());
+        let (info, opcodes) = provider.describe().unwrap();
+        assert_eq!(info.id, ProviderId::Pkcs11);
+        assert_eq!(info.uuid, Uuid::from_str(Provider::PROVIDER_UUID).unwrap());
+        assert_eq!(opcodes, PLUGIN_OPCODES.iter().copied().collect());
+    }
+
+    #[test]
+    fn operations_fail_without_the_plugin() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = provider(dir.path().join("missing.sock").display().to_string());
+        assert_eq!(
+            destroy_key(&provider).unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+    }
+
+    #[test]
+    fn plugins_describe_themselves() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = plugin(dir.path(), 2, |request| match request.header.opcode {
+            Opcode::ListProviders => {
+                answered(NativeResult::ListProviders(list_providers::Result {
+                    providers: vec![
+                        info(ProviderId::Tpm, "Other"),
+                        info(ProviderId::Pkcs11, "Vendor"),
+                    ],
+                }))
+            }
+            _ => answered(NativeResult::ListOpcodes(list_opcodes::Result {
+                // Only the operations which can be sent to the plugin are kept.
+                opcodes: [Opcode::PsaSignHash, Opcode::ListKeys, Opcode::DeleteClient]
+                    .iter()
+                    .copied()
+                    .collect(),
+            })),
+        });
+        let (info, opcodes) = provider(socket_path).describe().unwrap();
+        let requests = plugin.join().unwrap();
+        assert_eq!(info.vendor, "Vendor");
+        assert_eq!(opcodes, [Opcode::PsaSignHash].iter().copied().collect());
+        for request in requests.iter() {
+            assert_eq!(request.header.provider, ProviderId::Core);
+            assert_eq!(request.header.auth_type, AuthType::NoAuth);
+        }
+    }
+
+    #[test]
+    fn descriptions_of_other_providers_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = plugin(dir.path(), 2, |request| match request.header.opcode {
+            Opcode::ListProviders => {
+                answered(NativeResult::ListProviders(list_providers::Result {
+                    providers: vec![info(ProviderId::Tpm, "Other")],
+                }))
+            }
+            _ => (ResponseStatus::PsaErrorNotSupported, None),
+        });
+        let (info, _) = provider(socket_path).describe().unwrap();
+        let _ = plugin.join().unwrap();
+        assert_eq!(info.id, ProviderId::Pkcs11);
+        assert_eq!(info.uuid, Uuid::from_str(Provider::PROVIDER_UUID).unwrap());
+    }
+
+    #[test]
+    fn operations_are_sent_as_the_application() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = undescribed(dir.path(), 1, |_| {
+            answered(NativeResult::PsaDestroyKey(psa_destroy_key::Result {}))
+        });
+        let _ = destroy_key(&provider(socket_path)).unwrap();
+        let requests = plugin.join().unwrap();
+        let request = &requests[2];
+        assert_eq!(request.header.opcode, Opcode::PsaDestroyKey);
+        assert_eq!(request.header.provider, ProviderId::Pkcs11);
+        assert_eq!(request.header.auth_type, AuthType::Direct);
+        assert_eq!(request.auth.buffer.expose_secret(), b"app");
+    }
+
+    #[test]
+    fn operations_without_application_are_not_authenticated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = undescribed(dir.path(), 1, |_| {
+            answered(NativeResult::PsaGenerateRandom(
+                psa_generate_random::Result {
+                    random_bytes: vec![7; 8].into(),
+                },
+            ))
+        });
+        let result = provider(socket_path)
+            .psa_generate_random(psa_generate_random::Operation { size: 8 })
+            .unwrap();
+        let requests = plugin.join().unwrap();
+        assert_eq!(*result.random_bytes, vec![7; 8]);
+        assert_eq!(requests[2].header.provider, ProviderId::Pkcs11);
+        assert_eq!(requests[2].header.auth_type, AuthType::NoAuth);
+    }
+
+    #[test]
+    fn listed_keys_are_those_of_the_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = undescribed(dir.path(), 1, |_| {
+            answered(NativeResult::ListKeys(list_keys::Result {
+                keys: vec![
+                    key(ProviderId::Pkcs11, "mine"),
+                    key(ProviderId::Tpm, "other"),
+                ],
+            }))
+        });
+        let result = provider(socket_path)
+            .list_keys(&application(), list_keys::Operation {})
+            .unwrap();
+        let requests = plugin.join().unwrap();
+        assert_eq!(result.keys.len(), 1);
+        assert_eq!(result.keys[0].name, "mine");
+        assert_eq!(requests[2].header.provider, ProviderId::Core);
+        assert_eq!(requests[2].auth.buffer.expose_secret(), b"app");
+    }
+
+    #[test]
+    fn admin_operations_are_not_authenticated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = undescribed(dir.path(), 1, |_| {
+            answered(NativeResult::ListClients(list_clients::Result {
+                clients: vec![String::from("app")],
+            }))
+        });
+        let result = provider(socket_path)
+            .list_clients(list_clients::Operation {})
+            .unwrap();
+        let requests = plugin.join().unwrap();
+        assert_eq!(result.clients, vec![String::from("app")]);
+        assert_eq!(requests[2].header.provider, ProviderId::Core);
+        assert_eq!(requests[2].header.auth_type, AuthType::NoAuth);
+    }
+
+    #[test]
+    fn plugin_statuses_are_returned_as_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, plugin) = undescribed(dir.path(), 1, |_| {
+            (ResponseStatus::PsaErrorDoesNotExist, None)
+        });
+        assert_eq!(
+            destroy_key(&provider(socket_path)).unwrap_err(),
+            ResponseStatus::PsaErrorDoesNotExist
+        );
+        let _ = plugin.join().unwrap();
+    }
+
+    #[test]
+    fn core_provider_id_is_refused() {
+        assert!(ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_provider_id(ProviderId::Core)
+            .with_socket_path(String::from("/run/parsec/plugin.sock"))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn exactly_one_way_to_the_plugin_is_needed() {
+        assert!(ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_provider_id(ProviderId::Pkcs11)
+            .with_socket_path(String::from("/run/parsec/plugin.sock"))
+            .with_command(vec![String::from("true")])
+            .build()
+            .is_err());
+        assert!(ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_provider_id(ProviderId::Pkcs11)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn provider_name_and_id_are_needed() {
+        assert!(ProviderBuilder::new()
+            .with_provider_id(ProviderId::Pkcs11)
+            .with_socket_path(String::from("/run/parsec/plugin.sock"))
+            .build()
+            .is_err());
+        assert!(ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_socket_path(String::from("/run/parsec/plugin.sock"))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn plugin_process_restarted() {
+        // The program exits without answering: it is started again for each request.
+        let provider = ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_provider_id(ProviderId::Pkcs11)
+            .with_command(vec![String::from("true")])
+            .with_timeout(Some(5000))
+            .build()
+            .unwrap();
+        assert_eq!(provider.opcodes.len(), PLUGIN_OPCODES.len());
+        for _ in 0..2 {
+            assert_eq!(
+                provider
+                    .psa_generate_random(psa_generate_random::Operation { size: 8 })
+                    .unwrap_err(),
+                ResponseStatus::PsaErrorCommunicationFailure
+            );
+        }
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Plugin process started by the service
+//!
+//! Instead of listening on a socket, the plugin can be a program started by the service, reading
+//! the requests on its standard input and writing the responses on its standard output, one at a
+//! time. Its standard error is the one of the service.
+//!
+//! The program is typically a sandbox, such as a WebAssembly runtime running the module of the
+//! plugin with no other capability than these two streams: the module can then only act on the
+//! requests it is given. If the program exits, or does not answer before the timeout, it is
+//! killed and the request fails: the program is started again for the next request.
+//!
+//! The service does not embed a WebAssembly runtime and has no host API for the modules: the
+//! sandbox, and what it lets the plugin reach, is entirely the one of the program configured.
+use crate::providers::utils::remote_client;
+use crate::utils::GlobalConfig;
+use log::{error, info};
+use parsec_interface::operations::{NativeOperation, NativeResult};
+use parsec_interface::requests::{ProviderId, Response, ResponseStatus, Result};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Running plugin program
+#[derive(Debug)]
+struct Running {
+    child: Child,
+    stdin: ChildStdin,
+    responses: Receiver<Result<Response>>,
+}
+
+impl Running {
+    fn stop(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Plugin program, started when needed
+#[derive(Debug)]
+pub struct PluginProcess {
+    command: Vec<String>,
+    timeout: Option<Duration>,
+    running: Mutex<Option<Running>>,
+}
+
+impl PluginProcess {
+    /// Plugin run by the given command, the program followed by its arguments
+    pub fn new(command: Vec<String>, timeout: Option<Duration>) -> std::io::Result<Self> {
+        if command.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "empty plugin command",
+            ));
+        }
+        Ok(PluginProcess {
+            command,
+            timeout,
+            running: Mutex::new(None),
+        })
+    }
+
+    fn start(&self) -> std::io::Result<Running> {
+        info!("Starting the plugin {}.", self.command[0]);
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let (stdin, mut stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "the plugin streams are not available",
+                ));
+            }
+        };
+        // Responses are read by a thread, so that waiting for them can time out.
+        let (sender, responses) = mpsc::channel();
+        let _ = thread::Builder::new()
+            .name(String::from("plugin-responses"))
+            .spawn(move || loop {
+                let response =
+                    Response::read_from_stream(&mut stdout, GlobalConfig::buffer_size_limit());
+                let failed = response.is_err();
+                if sender.send(response).is_err() || failed {
+                    break;
+                }
+            })?;
+        Ok(Running {
+            child,
+            stdin,
+            responses,
+        })
+    }
+
+    /// Send an operation to the plugin, as [`remote_client::RemoteClient::send`] does.
+    pub fn send(
+        &self,
+        provider: ProviderId,
+        application: Option<&str>,
+        operation: NativeOperation,
+    ) -> Result<NativeResult> {
+        let opcode = operation.opcode();
+        let request = remote_client::request(provider, application, operation)?;
+
+        let mut running = self.running.lock().expect("Plugin lock poisoned");
+        let mut plugin = match running.take() {
+            Some(plugin) => plugin,
+            None => self.start().map_err(|e| {
+                format_error!("Failed to start the plugin", e);
+                ResponseStatus::PsaErrorCommunicationFailure
+            })?,
+        };
+        if let Err(e) = request.write_to_stream(&mut plugin.stdin) {
+            format_error!("Failed to send the request to the plugin", e);
+            plugin.stop();
+            return Err(ResponseStatus::PsaErrorCommunicationFailure);
+        }
+        let response = match self.timeout {
+            Some(timeout) => plugin.responses.recv_timeout(timeout),
+            None => plugin
+                .responses
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match response {
+            Ok(Ok(response)) => {
+                *running = Some(plugin);
+                remote_client::result(response, opcode)
+            }
+            Ok(Err(e)) => {
+                format_error!("The plugin did not answer", e);
+                plugin.stop();
+                Err(ResponseStatus::PsaErrorCommunicationFailure)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                error!("The plugin did not answer in time, it is stopped.");
+                plugin.stop();
+                Err(ResponseStatus::PsaErrorCommunicationFailure)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("The plugin exited.");
+                plugin.stop();
+                Err(ResponseStatus::PsaErrorCommunicationFailure)
+            }
+        }
+    }
+}
+
+impl Drop for PluginProcess {
+    fn drop(&mut self) {
+        if let Some(plugin) = self.running.get_mut().ok().and_then(Option::take) {
+            plugin.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PluginProcess;
+    use parsec_interface::operations::{ping, NativeOperation, NativeResult};
+    use parsec_interface::requests::{ProviderId, ResponseStatus};
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    fn shell(script: &str) -> Vec<String> {
+        vec![String::from("sh"), String::from("-c"), String::from(script)]
+    }
+
+    fn ping(plugin: &PluginProcess) -> Result<NativeResult, ResponseStatus> {
+        plugin.send(
+            ProviderId::Core,
+            None,
+            NativeOperation::Ping(ping::Operation {}),
+        )
+    }
+
+    fn starts(log: &Path) -> usize {
+        std::fs::read_to_string(log).map_or(0, |log| log.lines().count())
+    }
+
+    #[test]
+    fn empty_command() {
+        assert!(PluginProcess::new(Vec::new(), None).is_err());
+    }
+
+    #[test]
+    fn running_plugin_is_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("starts");
+        // The request echoed back reads as the response of a successful Ping.
+        let plugin = PluginProcess::new(
+            shell(&format!("echo started >> {}; exec cat", log.display())),
+            Some(Duration::from_secs(10)),
+        )
+        .unwrap();
+        for _ in 0..2 {
+            assert!(matches!(ping(&plugin), Ok(NativeResult::Ping(_))));
+        }
+        assert_eq!(starts(&log), 1);
+    }
+
+    #[test]
+    fn exited_plugin_is_started_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("starts");
+        let plugin =
+            PluginProcess::new(shell(&format!("echo started >> {}", log.display())), None).unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                ping(&plugin).unwrap_err(),
+                ResponseStatus::PsaErrorCommunicationFailure
+            );
+        }
+        assert_eq!(starts(&log), 2);
+    }
+
+    #[test]
+    fn silent_plugin_times_out() {
+        let plugin =
+            PluginProcess::new(shell("exec sleep 30"), Some(Duration::from_millis(100))).unwrap();
+        let start = Instant::now();
+        assert_eq!(
+            ping(&plugin).unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn missing_program() {
+        let plugin =
+            PluginProcess::new(vec![String::from("/nonexistent/parsec-plugin")], None).unwrap();
+        assert_eq!(
+            ping(&plugin).unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+    }
+}
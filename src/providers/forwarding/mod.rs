@@ -0,0 +1,510 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Forwarding provider
+//!
+//! This provider holds no keys: it sends the operations it receives to a provider of another
+//! Parsec service, so that a thin virtual machine or container can offer the Parsec API locally
+//! while the hardware is used by the service of the host or of a dedicated node. It serves the
+//! provider ID of the remote provider.
+//!
+//! Each local application is sent as the remote application it is mapped to in the
+//! configuration, authenticated with the direct authenticator of the remote service, which must
+//! hence only be reachable from the forwarding service. The operations of the applications which
+//! are not mapped are refused. The admin operations are only forwarded if a remote admin
+//! application is configured.
+//!
+//! The remote service is reached through a Unix domain socket, either shared with the host or
+//! carried by a tunnel.
+use super::Provide;
+use crate::authenticators::ApplicationIdentity;
+use crate::providers::utils::remote_client::RemoteClient;
+use crate::providers::ProviderIdentity;
+use crate::utils::config::ForwardedApplicationConfig;
+use crate::utils::logging::Redacted;
+use derivative::Derivative;
+use log::{error, info, trace, warn};
+use parsec_interface::operations::list_providers::{ProviderInfo, Uuid};
+use parsec_interface::operations::{
+    attest_key, can_do_crypto, list_clients, list_keys, list_opcodes, prepare_key_attestation,
+    psa_aead_decrypt, psa_aead_encrypt, psa_asymmetric_decrypt, psa_asymmetric_encrypt,
+    psa_cipher_decrypt, psa_cipher_encrypt, psa_destroy_key, psa_export_key, psa_export_public_key,
+    psa_generate_key, psa_generate_random, psa_hash_compare, psa_hash_compute, psa_import_key,
+    psa_raw_key_agreement, psa_sign_hash, psa_sign_message, psa_verify_hash, psa_verify_message,
+    NativeOperation, NativeResult,
+};
+use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Operations which can be forwarded to a remote provider
+const FORWARDED_OPCODES: [Opcode; 22] = [
+    Opcode::PsaGenerateKey,
+    Opcode::PsaImportKey,
+    Opcode::PsaExportPublicKey,
+    Opcode::PsaExportKey,
+    Opcode::PsaDestroyKey,
+    Opcode::PsaSignHash,
+    Opcode::PsaVerifyHash,
+    Opcode::PsaSignMessage,
+    Opcode::PsaVerifyMessage,
+    Opcode::PsaAsymmetricEncrypt,
+    Opcode::PsaAsymmetricDecrypt,
+    Opcode::PsaAeadEncrypt,
+    Opcode::PsaAeadDecrypt,
+    Opcode::PsaCipherEncrypt,
+    Opcode::PsaCipherDecrypt,
+    Opcode::PsaHashCompute,
+    Opcode::PsaHashCompare,
+    Opcode::PsaRawKeyAgreement,
+    Opcode::PsaGenerateRandom,
+    Opcode::CanDoCrypto,
+    Opcode::PrepareKeyAttestation,
+    Opcode::AttestKey,
+];
+
+/// Forwarding provider structure
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Provider {
+    // The identity of the provider including uuid & name.
+    provider_identity: ProviderIdentity,
+    // ID of the remote provider, served by this one
+    provider_id: ProviderId,
+    client: RemoteClient,
+    // Remote application name of each local application
+    applications: HashMap<String, String>,
+    // Remote application as which the admin operations are sent, if they are forwarded
+    admin_application: Option<String>,
+    // Operations of the remote provider which are forwarded
+    opcodes: HashSet<Opcode>,
+}
+
+/// Implement an operation of an application by forwarding it.
+macro_rules! forward_operation {
+    ($method:ident, $operation:ident, $variant:ident) => {
+        fn $method(
+            &self,
+            application_identity: &ApplicationIdentity,
+            op: $operation::Operation,
+        ) -> Result<$operation::Result> {
+            trace!(concat!(stringify!($method), " ingress"));
+            let application = self.remote_application(application_identity)?;
+            match self.forward(Some(application), NativeOperation::$variant(op))? {
+                NativeResult::$variant(result) => Ok(result),
+                _ => Err(unexpected_result()),
+            }
+        }
+    };
+}
+
+/// Implement an operation which does not need an application by forwarding it.
+macro_rules! forward_unauthenticated_operation {
+    ($method:ident, $operation:ident, $variant:ident) => {
+        fn $method(&self, op: $operation::Operation) -> Result<$operation::Result> {
+            trace!(concat!(stringify!($method), " ingress"));
+            match self.forward(None, NativeOperation::$variant(op))? {
+                NativeResult::$variant(result) => Ok(result),
+                _ => Err(unexpected_result()),
+            }
+        }
+    };
+}
+
+fn unexpected_result() -> ResponseStatus {
+    error!("The remote service returned the result of another operation.");
+    ResponseStatus::PsaErrorCommunicationFailure
+}
+
+impl Provider {
+    /// The default provider name for forwarding provider
+    pub const DEFAULT_PROVIDER_NAME: &'static str = "forwarding-provider";
+
+    /// The UUID for this provider
+    pub const PROVIDER_UUID: &'static str = "6c2a9f4e-8d1b-4e7a-b3c5-1f0e9d8a7b64";
+
+    /// Name of the remote application a local application is sent as.
+    fn remote_application(&self, application_identity: &ApplicationIdentity) -> Result<&str> {
+        self.applications
+            .get(application_identity.name())
+            .map(String::as_str)
+            .ok_or_else(|| {
+                error!(
+                    "The application \"{}\" is not mapped to a remote application, its operation is not forwarded.",
+                    Redacted(application_identity.name())
+                );
+                ResponseStatus::PsaErrorNotPermitted
+            })
+    }
+
+    fn forward(
+        &self,
+        application: Option<&str>,
+        operation: NativeOperation,
+    ) -> Result<NativeResult> {
+        self.client.send(self.provider_id, application, operation)
+    }
+
+    /// Operations of the remote provider, or all those which can be forwarded if the remote
+    /// service can not tell.
+    fn remote_opcodes(&self) -> HashSet<Opcode> {
+        let forwarded: HashSet<Opcode> = FORWARDED_OPCODES.iter().copied().collect();
+        let operation = NativeOperation::ListOpcodes(list_opcodes::Operation {
+            provider_id: self.provider_id,
+        });
+        match self.client.send(ProviderId::Core, None, operation) {
+            Ok(NativeResult::ListOpcodes(result)) => {
+                result.opcodes.intersection(&forwarded).copied().collect()
+            }
+            Ok(_) => {
+                let _ = unexpected_result();
+                forwarded
+            }
+            Err(e) => {
+                warn!(
+                    "The operations of the remote {} provider could not be listed ({}), all are advertised.",
+                    self.provider_id, e
+                );
+                forwarded
+            }
+        }
+    }
+}
+
+impl Provide for Provider {
+    fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+        trace!("describe ingress");
+        Ok((
+            ProviderInfo {
+                // Assigned UUID for this provider: 6c2a9f4e-8d1b-4e7a-b3c5-1f0e9d8a7b64
+                uuid: Uuid::from_str(Self::PROVIDER_UUID)
+                    .or(Err(ResponseStatus::InvalidEncoding))?,
+                description: format!(
+                    "Forwarding provider, using the {} provider of a remote service",
+                    self.provider_id
+                ),
+                vendor: String::from("Parsec"),
+                version_maj: 0,
+                version_min: 1,
+                version_rev: 0,
+                id: self.provider_id,
+            },
+            self.opcodes.clone(),
+        ))
+    }
+
+    fn list_keys(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: list_keys::Operation,
+    ) -> Result<list_keys::Result> {
+        trace!("list_keys ingress");
+        // The applications which are not mapped have no keys in the remote service.
+        let application = match self.applications.get(application_identity.name()) {
+            Some(application) => application,
+            None => return Ok(list_keys::Result { keys: Vec::new() }),
+        };
+        // Keys are listed by the remote Core provider, for all its providers.
+        match self.client.send(
+            ProviderId::Core,
+            Some(application),
+            NativeOperation::ListKeys(op),
+        )? {
+            NativeResult::ListKeys(result) => Ok(list_keys::Result {
+                keys: result
+                    .keys
+                    .into_iter()
+                    .filter(|key| key.provider_id == self.provider_id)
+                    .collect(),
+            }),
+            _ => Err(unexpected_result()),
+        }
+    }
+
+    fn list_clients(&self, op: list_clients::Operation) -> Result<list_clients::Result> {
+        trace!("list_clients ingress");
+        let admin_application = self.admin_application.as_ref().ok_or_else(|| {
+            info!("Admin operations are not forwarded to the remote service.");
+            ResponseStatus::PsaErrorNotPermitted
+        })?;
+        let local_applications: HashMap<&String, &String> = self
+            .applications
+            .iter()
+            .map(|(local, remote)| (remote, local))
+            .collect();
+        match self.client.send(
+            ProviderId::Core,
+            Some(admin_application),
+            NativeOperation::ListClients(op),
+        )? {
+            // Only the clients of this service are returned, under their local name.
+            NativeResult::ListClients(result) => Ok(list_clients::Result {
+                clients: result
+                    .clients
+                    .iter()
+                    .filter_map(|client| {
+                        local_applications.get(client).map(|local| (*local).clone())
+                    })
+                    .collect(),
+            }),
+            _ => Err(unexpected_result()),
+        }
+    }
+
+    forward_operation!(psa_generate_key, psa_generate_key, PsaGenerateKey);
+    forward_operation!(psa_import_key, psa_import_key, PsaImportKey);
+    forward_operation!(
+        psa_export_public_key,
+        psa_export_public_key,
+        PsaExportPublicKey
+    );
+    forward_operation!(psa_export_key, psa_export_key, PsaExportKey);
+    forward_operation!(psa_destroy_key, psa_destroy_key, PsaDestroyKey);
+    forward_operation!(psa_sign_hash, psa_sign_hash, PsaSignHash);
+    forward_operation!(psa_verify_hash, psa_verify_hash, PsaVerifyHash);
+    forward_operation!(psa_sign_message, psa_sign_message, PsaSignMessage);
+    forward_operation!(psa_verify_message, psa_verify_message, PsaVerifyMessage);
+    forward_operation!(
+        psa_asymmetric_encrypt,
+        psa_asymmetric_encrypt,
+        PsaAsymmetricEncrypt
+    );
+    forward_operation!(
+        psa_asymmetric_decrypt,
+        psa_asymmetric_decrypt,
+        PsaAsymmetricDecrypt
+    );
+    forward_operation!(psa_aead_encrypt, psa_aead_encrypt, PsaAeadEncrypt);
+    forward_operation!(psa_aead_decrypt, psa_aead_decrypt, PsaAeadDecrypt);
+    forward_operation!(psa_cipher_encrypt, psa_cipher_encrypt, PsaCipherEncrypt);
+    forward_operation!(psa_cipher_decrypt, psa_cipher_decrypt, PsaCipherDecrypt);
+    forward_operation!(
+        psa_raw_key_agreement,
+        psa_raw_key_agreement,
+        PsaRawKeyAgreement
+    );
+    forward_operation!(can_do_crypto, can_do_crypto, CanDoCrypto);
+    forward_operation!(
+        prepare_key_attestation,
+        prepare_key_attestation,
+        PrepareKeyAttestation
+    );
+    forward_operation!(attest_key, attest_key, AttestKey);
+    forward_unauthenticated_operation!(psa_hash_compute, psa_hash_compute, PsaHashCompute);
+    forward_unauthenticated_operation!(psa_hash_compare, psa_hash_compare, PsaHashCompare);
+    forward_unauthenticated_operation!(psa_generate_random, psa_generate_random, PsaGenerateRandom);
+}
+
+/// Forwarding provider builder
+#[derive(Default, Derivative)]
+#[derivative(Debug)]
+pub struct ProviderBuilder {
+    provider_name: Option<String>,
+    provider_id: Option<ProviderId>,
+    socket_path: Option<String>,
+    applications: Vec<ForwardedApplicationConfig>,
+    admin_application: Option<String>,
+    timeout: Option<u64>,
+}
+
+impl ProviderBuilder {
+    /// Create a new provider builder
+    pub fn new() -> ProviderBuilder {
+        ProviderBuilder {
+            provider_name: None,
+            provider_id: None,
+            socket_path: None,
+            applications: Vec::new(),
+            admin_application: None,
+            timeout: None,
+        }
+    }
+
+    /// Add a provider name
+    pub fn with_provider_name(mut self, provider_name: String) -> ProviderBuilder {
+        self.provider_name = Some(provider_name);
+
+        self
+    }
+
+    /// Specify the ID of the remote provider
+    pub fn with_provider_id(mut self, provider_id: ProviderId) -> ProviderBuilder {
+        self.provider_id = Some(provider_id);
+
+        self
+    }
+
+    /// Specify the path of the socket of the remote service
+    pub fn with_socket_path(mut self, socket_path: String) -> ProviderBuilder {
+        self.socket_path = Some(socket_path);
+
+        self
+    }
+
+    /// Specify the local applications and their name in the remote service
+    pub fn with_applications(
+        mut self,
+        applications: Vec<ForwardedApplicationConfig>,
+    ) -> ProviderBuilder {
+        self.applications = applications;
+
+        self
+    }
+
+    /// Specify the remote application as which the admin operations are forwarded
+    pub fn with_admin_application(mut self, admin_application: Option<String>) -> ProviderBuilder {
+        self.admin_application = admin_application;
+
+        self
+    }
+
+    /// Specify the timeout (in milliseconds) of the requests to the remote service
+    pub fn with_timeout(mut self, timeout: Option<u64>) -> ProviderBuilder {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// Build into a forwarding Provider
+    pub fn build(self) -> std::io::Result<Provider> {
+        let provider_name = self
+            .provider_name
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing provider name"))?;
+        let provider_id = self
+            .provider_id
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing remote provider"))?;
+        if provider_id == ProviderId::Core {
+            error!("The operations of the Core provider can not be forwarded.");
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "invalid remote provider",
+            ));
+        }
+        let socket_path = self
+            .socket_path
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing socket path"))?;
+
+        let mut applications = HashMap::new();
+        let mut remote_applications = HashSet::new();
+        for application in self.applications {
+            // Two local applications sharing a remote one would share its keys.
+            if !remote_applications.insert(application.remote.clone()) {
+                error!(
+                    "The remote application \"{}\" is mapped twice.",
+                    Redacted(&application.remote)
+                );
+                return Err(Error::new(ErrorKind::InvalidData, "duplicate application"));
+            }
+            if applications
+                .insert(application.local.clone(), application.remote)
+                .is_some()
+            {
+                error!(
+                    "The local application \"{}\" is mapped twice.",
+                    Redacted(&application.local)
+                );
+                return Err(Error::new(ErrorKind::InvalidData, "duplicate application"));
+            }
+        }
+        if applications.is_empty() {
+            warn!("No application is mapped, no operation will be forwarded.");
+        }
+
+        let mut provider = Provider {
+            provider_identity: ProviderIdentity::new(
+                Provider::PROVIDER_UUID.to_string(),
+                provider_name,
+            ),
+            provider_id,
+            client: RemoteClient::new(socket_path, self.timeout.map(Duration::from_millis)),
+            applications,
+            admin_application: self.admin_application,
+            opcodes: HashSet::new(),
+        };
+        provider.opcodes = provider.remote_opcodes();
+        Ok(provider)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::requests::AuthType;
+
+    fn build_provider(admin_application: Option<String>) -> Provider {
+        ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_provider_id(ProviderId::Tpm)
+            .with_socket_path(format!("{}/no-remote-service.sock", env!("OUT_DIR")))
+            .with_applications(vec![ForwardedApplicationConfig {
+                local: String::from("guest-app"),
+                remote: String::from("vm1-guest-app"),
+            }])
+            .with_admin_application(admin_application)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn applications_are_mapped() {
+        let provider = build_provider(None);
+        let mapped = ApplicationIdentity::new(String::from("guest-app"), AuthType::Direct);
+        let unmapped = ApplicationIdentity::new(String::from("other-app"), AuthType::Direct);
+        assert_eq!(provider.remote_application(&mapped), Ok("vm1-guest-app"));
+        assert_eq!(
+            provider.remote_application(&unmapped),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+        assert_eq!(
+            provider
+                .list_keys(&unmapped, list_keys::Operation {})
+                .unwrap()
+                .keys,
+            Vec::new()
+        );
+        // The remote service is not running.
+        assert_eq!(
+            provider
+                .psa_destroy_key(
+                    &mapped,
+                    psa_destroy_key::Operation {
+                        key_name: String::from("key"),
+                    }
+                )
+                .unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+    }
+
+    #[test]
+    fn admin_operations_are_not_forwarded_by_default() {
+        assert_eq!(
+            build_provider(None)
+                .list_clients(list_clients::Operation {})
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        assert_eq!(
+            build_provider(Some(String::from("vm1-admin")))
+                .list_clients(list_clients::Operation {})
+                .unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+    }
+
+    #[test]
+    fn applications_are_mapped_once() {
+        let application = ForwardedApplicationConfig {
+            local: String::from("guest-app"),
+            remote: String::from("vm1-guest-app"),
+        };
+        assert!(ProviderBuilder::new()
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_provider_id(ProviderId::Tpm)
+            .with_socket_path(String::from("/run/parsec/remote.sock"))
+            .with_applications(vec![application.clone(), application])
+            .build()
+            .is_err());
+    }
+}
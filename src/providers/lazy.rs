@@ -0,0 +1,627 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Provider built at its first use
+//!
+//! A provider configured to be activated on demand is not built when the service starts: opening
+//! its hardware, logging in to a token or creating TPM contexts is deferred until a request needs
+//! it. This shortens the start of the service and does not hold sessions of hardware which is not
+//! used. Until then, the provider is listed as available but inactive and the keys it holds are
+//! listed from its Key Info Manager.
+use super::{Provide, ProviderIdentity};
+use crate::authenticators::ApplicationIdentity;
+use crate::key_info_managers::KeyInfoManagerClient;
+use crate::providers::utils::aes_kw::KeyWrapAlgorithm;
+use derivative::Derivative;
+use log::{error, info, trace};
+use once_cell::sync::OnceCell;
+use parsec_interface::operations::list_providers::{ProviderInfo, Uuid};
+use parsec_interface::operations::{
+    attest_key, can_do_crypto, delete_client, list_clients, list_keys, prepare_key_attestation,
+    psa_aead_decrypt, psa_aead_encrypt, psa_asymmetric_decrypt, psa_asymmetric_encrypt,
+    psa_cipher_decrypt, psa_cipher_encrypt, psa_destroy_key, psa_export_key, psa_export_public_key,
+    psa_generate_key, psa_generate_random, psa_hash_compare, psa_hash_compute, psa_import_key,
+    psa_raw_key_agreement, psa_sign_hash, psa_sign_message, psa_verify_hash, psa_verify_message,
+};
+use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+type Activated = Arc<dyn Provide + Send + Sync>;
+
+/// Builder of the provider, returning `None` if the provider is skipped
+pub type Activation = Box<dyn Fn() -> anyhow::Result<Option<Activated>> + Send + Sync>;
+
+/// Provider building the one it stands for when it is first used
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct LazyProvider {
+    provider_id: ProviderId,
+    provider_identity: ProviderIdentity,
+    // Built with the service, so that the keys of the provider can be listed while inactive.
+    key_info_store: KeyInfoManagerClient,
+    #[derivative(Debug = "ignore")]
+    provider: OnceCell<Activated>,
+    // Held while building, so that concurrent first requests build the provider once.
+    #[derivative(Debug = "ignore")]
+    activation_lock: Mutex<()>,
+    #[derivative(Debug = "ignore")]
+    activation: Activation,
+}
+
+impl LazyProvider {
+    /// Create a provider built by `activation` at its first use
+    pub fn new(
+        provider_id: ProviderId,
+        provider_identity: ProviderIdentity,
+        key_info_store: KeyInfoManagerClient,
+        activation: Activation,
+    ) -> Self {
+        LazyProvider {
+            provider_id,
+            provider_identity,
+            key_info_store,
+            provider: OnceCell::new(),
+            activation_lock: Mutex::new(()),
+            activation,
+        }
+    }
+
+    /// The provider built, building it first if needed.
+    fn active(&self) -> Result<&Activated> {
+        if let Some(provider) = self.provider.get() {
+            return Ok(provider);
+        }
+        let _guard = self
+            .activation_lock
+            .lock()
+            .expect("Provider activation lock poisoned");
+        if let Some(provider) = self.provider.get() {
+            return Ok(provider);
+        }
+        info!(
+            "Activating the provider \"{}\".",
+            self.provider_identity.name()
+        );
+        match (self.activation)() {
+            Ok(Some(provider)) => Ok(self.provider.get_or_init(|| provider)),
+            Ok(None) => {
+                error!(
+                    "The provider \"{}\" is skipped and cannot be activated.",
+                    self.provider_identity.name()
+                );
+                Err(ResponseStatus::PsaErrorHardwareFailure)
+            }
+            Err(e) => {
+                format_error!(
+                    &format!(
+                        "Failed to activate the provider \"{}\"",
+                        self.provider_identity.name()
+                    ),
+                    e
+                );
+                Err(ResponseStatus::PsaErrorHardwareFailure)
+            }
+        }
+    }
+}
+
+impl Provide for LazyProvider {
+    fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+        trace!("describe ingress");
+        if let Some(provider) = self.provider.get() {
+            return provider.describe();
+        }
+        // Nothing is supported until the provider is activated by a request.
+        Ok((
+            ProviderInfo {
+                uuid: Uuid::parse_str(self.provider_identity.uuid())
+                    .or(Err(ResponseStatus::InvalidEncoding))?,
+                description: format!(
+                    "Provider \"{}\" (available, inactive)",
+                    self.provider_identity.name()
+                ),
+                vendor: String::new(),
+                version_maj: 0,
+                version_min: 0,
+                version_rev: 0,
+                id: self.provider_id,
+            },
+            HashSet::new(),
+        ))
+    }
+
+    fn is_active(&self) -> bool {
+        self.provider.get().is_some()
+    }
+
+    fn activate(&self) -> Result<()> {
+        let _ = self.active()?;
+        Ok(())
+    }
+
+    fn list_keys(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: list_keys::Operation,
+    ) -> Result<list_keys::Result> {
+        trace!("list_keys ingress");
+        match self.provider.get() {
+            Some(provider) => provider.list_keys(application_identity, op),
+            None => Ok(list_keys::Result {
+                keys: self.key_info_store.list_keys(application_identity)?,
+            }),
+        }
+    }
+
+    fn list_clients(&self, op: list_clients::Operation) -> Result<list_clients::Result> {
+        trace!("list_clients ingress");
+        match self.provider.get() {
+            Some(provider) => provider.list_clients(op),
+            None => Ok(list_clients::Result {
+                clients: self
+                    .key_info_store
+                    .list_clients()?
+                    .into_iter()
+                    .map(|application_identity| application_identity.name().clone())
+                    .collect(),
+            }),
+        }
+    }
+
+    fn delete_client(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: delete_client::Operation,
+    ) -> Result<delete_client::Result> {
+        self.active()?.delete_client(application_identity, op)
+    }
+
+    fn psa_generate_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_generate_key::Operation,
+    ) -> Result<psa_generate_key::Result> {
+        self.active()?.psa_generate_key(application_identity, op)
+    }
+
+    fn psa_import_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_import_key::Operation,
+    ) -> Result<psa_import_key::Result> {
+        self.active()?.psa_import_key(application_identity, op)
+    }
+
+    fn psa_export_public_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_public_key::Operation,
+    ) -> Result<psa_export_public_key::Result> {
+        self.active()?
+            .psa_export_public_key(application_identity, op)
+    }
+
+    fn psa_export_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_key::Operation,
+    ) -> Result<psa_export_key::Result> {
+        self.active()?.psa_export_key(application_identity, op)
+    }
+
+    fn psa_destroy_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_destroy_key::Operation,
+    ) -> Result<psa_destroy_key::Result> {
+        self.active()?.psa_destroy_key(application_identity, op)
+    }
+
+    fn psa_sign_hash(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_hash::Operation,
+    ) -> Result<psa_sign_hash::Result> {
+        self.active()?.psa_sign_hash(application_identity, op)
+    }
+
+    fn psa_verify_hash(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_verify_hash::Operation,
+    ) -> Result<psa_verify_hash::Result> {
+        self.active()?.psa_verify_hash(application_identity, op)
+    }
+
+    fn psa_asymmetric_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_asymmetric_encrypt::Operation,
+    ) -> Result<psa_asymmetric_encrypt::Result> {
+        self.active()?
+            .psa_asymmetric_encrypt(application_identity, op)
+    }
+
+    fn psa_asymmetric_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_asymmetric_decrypt::Operation,
+    ) -> Result<psa_asymmetric_decrypt::Result> {
+        self.active()?
+            .psa_asymmetric_decrypt(application_identity, op)
+    }
+
+    fn psa_aead_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_encrypt::Operation,
+    ) -> Result<psa_aead_encrypt::Result> {
+        self.active()?.psa_aead_encrypt(application_identity, op)
+    }
+
+    fn psa_aead_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_decrypt::Operation,
+    ) -> Result<psa_aead_decrypt::Result> {
+        self.active()?.psa_aead_decrypt(application_identity, op)
+    }
+
+    fn psa_hash_compute(
+        &self,
+        op: psa_hash_compute::Operation,
+    ) -> Result<psa_hash_compute::Result> {
+        self.active()?.psa_hash_compute(op)
+    }
+
+    fn psa_hash_compare(
+        &self,
+        op: psa_hash_compare::Operation,
+    ) -> Result<psa_hash_compare::Result> {
+        self.active()?.psa_hash_compare(op)
+    }
+
+    fn psa_raw_key_agreement(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_raw_key_agreement::Operation,
+    ) -> Result<psa_raw_key_agreement::Result> {
+        self.active()?
+            .psa_raw_key_agreement(application_identity, op)
+    }
+
+    fn psa_generate_random(
+        &self,
+        op: psa_generate_random::Operation,
+    ) -> Result<psa_generate_random::Result> {
+        self.active()?.psa_generate_random(op)
+    }
+
+    fn psa_cipher_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_cipher_encrypt::Operation,
+    ) -> Result<psa_cipher_encrypt::Result> {
+        self.active()?.psa_cipher_encrypt(application_identity, op)
+    }
+
+    fn psa_cipher_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_cipher_decrypt::Operation,
+    ) -> Result<psa_cipher_decrypt::Result> {
+        self.active()?.psa_cipher_decrypt(application_identity, op)
+    }
+
+    fn psa_sign_message(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_message::Operation,
+    ) -> Result<psa_sign_message::Result> {
+        self.active()?.psa_sign_message(application_identity, op)
+    }
+
+    fn psa_verify_message(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_verify_message::Operation,
+    ) -> Result<psa_verify_message::Result> {
+        self.active()?.psa_verify_message(application_identity, op)
+    }
+
+    fn can_do_crypto(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: can_do_crypto::Operation,
+    ) -> Result<can_do_crypto::Result> {
+        self.active()?.can_do_crypto(application_identity, op)
+    }
+
+    fn prepare_key_attestation(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: prepare_key_attestation::Operation,
+    ) -> Result<prepare_key_attestation::Result> {
+        self.active()?
+            .prepare_key_attestation(application_identity, op)
+    }
+
+    fn attest_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: attest_key::Operation,
+    ) -> Result<attest_key::Result> {
+        self.active()?.attest_key(application_identity, op)
+    }
+
+    fn extend_measurement(&self, register: u8, event: &[u8]) -> Result<()> {
+        self.active()?.extend_measurement(register, event)
+    }
+
+    fn wrapping_public_key(&self) -> Result<Vec<u8>> {
+        self.active()?.wrapping_public_key()
+    }
+
+    fn aes_key_wrap(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        key_name: &str,
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Vec<u8>> {
+        self.active()?
+            .aes_key_wrap(application_identity, wrapping_key_name, key_name, alg)
+    }
+
+    fn aes_key_unwrap(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        wrapped_key: &[u8],
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        self.active()?
+            .aes_key_unwrap(application_identity, wrapping_key_name, wrapped_key, alg)
+    }
+
+    fn xchacha20_poly1305_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_encrypt::Operation,
+    ) -> Result<psa_aead_encrypt::Result> {
+        self.active()?
+            .xchacha20_poly1305_encrypt(application_identity, op)
+    }
+
+    fn xchacha20_poly1305_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_decrypt::Operation,
+    ) -> Result<psa_aead_decrypt::Result> {
+        self.active()?
+            .xchacha20_poly1305_decrypt(application_identity, op)
+    }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        match self.provider.get() {
+            Some(provider) => provider.key_info_store(),
+            None => Some(&self.key_info_store),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-provider", feature = "sqlite-kim"))]
+mod test {
+    use super::*;
+    use crate::key_info_managers::KeyInfoManagerFactory;
+    use crate::providers::core::Provider as CoreProvider;
+    use crate::providers::test_provider::{Provider as TestProvider, ProviderBuilder};
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::operations::psa_algorithm::Algorithm;
+    use parsec_interface::operations::psa_generate_random;
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::AuthType;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// What the activation of the provider does
+    #[derive(Copy, Clone)]
+    enum Outcome {
+        Built,
+        Skipped,
+        FailedOnce,
+    }
+
+    /// Identity of the lazy provider
+    ///
+    /// Keys are only listed for the providers the Key Info Managers know of, the Core provider is
+    /// the one always built.
+    fn identity() -> ProviderIdentity {
+        ProviderIdentity::new(
+            CoreProvider::PROVIDER_UUID.to_string(),
+            CoreProvider::DEFAULT_PROVIDER_NAME.to_string(),
+        )
+    }
+
+    fn kim_client(dir: &Path) -> KeyInfoManagerClient {
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(dir.join("lazy.sqlite3").display().to_string()),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap();
+        factory.build_client(identity())
+    }
+
+    /// A lazy test provider and the count of its activations
+    fn provider(dir: &Path, outcome: Outcome) -> (LazyProvider, Arc<AtomicUsize>) {
+        let kim_client = kim_client(dir);
+        let activations = Arc::new(AtomicUsize::new(0));
+        let activation: Activation = {
+            let (kim_client, activations) = (kim_client.clone(), activations.clone());
+            Box::new(move || {
+                let previous = activations.fetch_add(1, Ordering::SeqCst);
+                match outcome {
+                    Outcome::Skipped => return Ok(None),
+                    Outcome::FailedOnce if previous == 0 => {
+                        return Err(anyhow::anyhow!("device not ready"))
+                    }
+                    _ => (),
+                }
+                Ok(Some(Arc::new(
+                    ProviderBuilder::new()
+                        .with_provider_name(TestProvider::DEFAULT_PROVIDER_NAME.to_string())
+                        .with_key_info_store(kim_client.clone())
+                        .with_seed(Some(1))
+                        .build()?,
+                )))
+            })
+        };
+        let provider = LazyProvider::new(ProviderId::Core, identity(), kim_client, activation);
+        (provider, activations)
+    }
+
+    fn app() -> ApplicationIdentity {
+        ApplicationIdentity::new(String::from("app"), AuthType::Direct)
+    }
+
+    fn generate_random(provider: &LazyProvider) -> Result<psa_generate_random::Result> {
+        provider.psa_generate_random(psa_generate_random::Operation { size: 8 })
+    }
+
+    #[test]
+    fn inactive_provider_supports_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, activations) = provider(dir.path(), Outcome::Built);
+        let (info, opcodes) = provider.describe().unwrap();
+        assert_eq!(info.id, ProviderId::Core);
+        assert_eq!(info.uuid.to_string(), CoreProvider::PROVIDER_UUID);
+        assert!(info.description.contains("inactive"));
+        assert!(opcodes.is_empty());
+        assert!(!provider.is_active());
+        assert_eq!(activations.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn keys_and_clients_are_listed_while_inactive() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, activations) = provider(dir.path(), Outcome::Built);
+        let kim_client = provider.key_info_store().unwrap();
+        kim_client
+            .insert_key_info(
+                kim_client.get_key_identity(app(), String::from("stored-key")),
+                &1u32,
+                Attributes {
+                    lifetime: Lifetime::Persistent,
+                    key_type: Type::RsaKeyPair,
+                    bits: 1024,
+                    policy: Policy {
+                        usage_flags: UsageFlags::default(),
+                        permitted_algorithms: Algorithm::None,
+                    },
+                },
+            )
+            .unwrap();
+
+        let keys = provider
+            .list_keys(&app(), list_keys::Operation {})
+            .unwrap()
+            .keys;
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].name, "stored-key");
+        assert_eq!(keys[0].provider_id, ProviderId::Core);
+        let clients = provider
+            .list_clients(list_clients::Operation {})
+            .unwrap()
+            .clients;
+        assert_eq!(clients, vec![String::from("app")]);
+        assert!(!provider.is_active());
+        assert_eq!(activations.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn provider_is_built_once_at_first_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, activations) = provider(dir.path(), Outcome::Built);
+        for _ in 0..2 {
+            assert_eq!(generate_random(&provider).unwrap().random_bytes.len(), 8);
+        }
+        assert!(provider.is_active());
+        assert_eq!(activations.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn active_provider_describes_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, _) = provider(dir.path(), Outcome::Built);
+        provider.activate().unwrap();
+        let (info, opcodes) = provider.describe().unwrap();
+        assert!(!info.description.contains("inactive"));
+        assert!(opcodes.contains(&Opcode::PsaGenerateRandom));
+    }
+
+    #[test]
+    fn activation_builds_the_provider_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, activations) = provider(dir.path(), Outcome::Built);
+        provider.activate().unwrap();
+        provider.activate().unwrap();
+        assert!(provider.is_active());
+        assert_eq!(activations.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn concurrent_first_requests_build_the_provider_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, activations) = provider(dir.path(), Outcome::Built);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                let _ = scope.spawn(|| generate_random(&provider).unwrap());
+            }
+        });
+        assert_eq!(activations.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn skipped_provider_cannot_be_activated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, _) = provider(dir.path(), Outcome::Skipped);
+        assert_eq!(
+            generate_random(&provider).unwrap_err(),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+        assert_eq!(
+            provider.activate().unwrap_err(),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+        assert!(!provider.is_active());
+        assert!(provider
+            .list_keys(&app(), list_keys::Operation {})
+            .unwrap()
+            .keys
+            .is_empty());
+    }
+
+    #[test]
+    fn failed_activation_is_retried_by_the_next_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let (provider, activations) = provider(dir.path(), Outcome::FailedOnce);
+        assert_eq!(
+            generate_random(&provider).unwrap_err(),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+        assert!(!provider.is_active());
+        assert_eq!(generate_random(&provider).unwrap().random_bytes.len(), 8);
+        assert!(provider.is_active());
+        assert_eq!(activations.load(Ordering::SeqCst), 2);
+    }
+}
@@ -3,16 +3,130 @@
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
+#[cfg(feature = "xchacha20-poly1305")]
+use crate::providers::utils::xchacha20;
+use log::error;
+use parsec_interface::operations::psa_algorithm::Aead;
+#[cfg(feature = "xchacha20-poly1305")]
+use parsec_interface::operations::psa_algorithm::{AeadWithDefaultLengthTag, Algorithm};
 use parsec_interface::operations::{psa_aead_decrypt, psa_aead_encrypt};
 use parsec_interface::requests::{ResponseStatus, Result};
-use psa_crypto::operations::aead;
+use psa_crypto::operations::{aead, key_management};
 use psa_crypto::types::key;
+#[cfg(feature = "xchacha20-poly1305")]
+use std::convert::TryInto;
+#[cfg(feature = "xchacha20-poly1305")]
+use zeroize::Zeroizing;
+
+/// Key and nonce of an AEAD operation
+struct AeadKey {
+    id: key::Id,
+    nonce: Vec<u8>,
+    /// Whether the key is a volatile subkey, destroyed after the operation
+    volatile: bool,
+}
+
+impl AeadKey {
+    /// Key and nonce to use for the algorithm with the given key and nonce. XChaCha20-Poly1305 is
+    /// done with a volatile ChaCha20-Poly1305 subkey: the key is exported to derive it, so it needs
+    /// to be exportable.
+    fn new(
+        id: key::Id,
+        key_attributes: key::Attributes,
+        alg: Aead,
+        nonce: &[u8],
+        xchacha: bool,
+    ) -> Result<Self> {
+        if !xchacha {
+            return Ok(AeadKey {
+                id,
+                nonce: nonce.to_vec(),
+                volatile: false,
+            });
+        }
+        Self::xchacha20_subkey(id, key_attributes, alg, nonce)
+    }
+
+    /// Volatile ChaCha20-Poly1305 subkey of the XChaCha20-Poly1305 key and nonce.
+    #[cfg(feature = "xchacha20-poly1305")]
+    fn xchacha20_subkey(
+        id: key::Id,
+        key_attributes: key::Attributes,
+        alg: Aead,
+        nonce: &[u8],
+    ) -> Result<Self> {
+        if alg != Aead::AeadWithDefaultLengthTag(AeadWithDefaultLengthTag::Chacha20Poly1305) {
+            error!("XChaCha20-Poly1305 is only done for the Chacha20Poly1305 algorithm.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+        let nonce: &[u8; xchacha20::EXTENDED_NONCE_LEN] = nonce.try_into().map_err(|_| {
+            error!(
+                "XChaCha20-Poly1305 nonces are {} bytes long.",
+                xchacha20::EXTENDED_NONCE_LEN
+            );
+            ResponseStatus::PsaErrorInvalidArgument
+        })?;
+        if !key_attributes.policy.usage_flags.export() {
+            error!("XChaCha20-Poly1305 can only be done with keys permitted to be exported.");
+            return Err(ResponseStatus::PsaErrorNotPermitted);
+        }
+        let mut key = Zeroizing::new([0; xchacha20::KEY_LEN]);
+        if key_management::export(id, &mut key[..])? != xchacha20::KEY_LEN {
+            error!("XChaCha20-Poly1305 needs a 256-bit ChaCha20 key.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+        let mut subkey = Zeroizing::new([0; xchacha20::KEY_LEN]);
+        let nonce = xchacha20::subkey_and_nonce(&key, nonce, &mut subkey);
+
+        let mut usage_flags: key::UsageFlags = Default::default();
+        let _ = usage_flags.set_encrypt().set_decrypt();
+        let attributes = key::Attributes {
+            lifetime: key::Lifetime::Volatile,
+            policy: key::Policy {
+                usage_flags,
+                permitted_algorithms: Algorithm::Aead(alg),
+            },
+            ..key_attributes
+        };
+        Ok(AeadKey {
+            id: key_management::import(attributes, None, &subkey[..])?,
+            nonce: nonce.to_vec(),
+            volatile: true,
+        })
+    }
+
+    #[cfg(not(feature = "xchacha20-poly1305"))]
+    fn xchacha20_subkey(
+        _id: key::Id,
+        _key_attributes: key::Attributes,
+        _alg: Aead,
+        _nonce: &[u8],
+    ) -> Result<Self> {
+        error!("XChaCha20-Poly1305 was not compiled in the Parsec binary.");
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+}
+
+impl Drop for AeadKey {
+    fn drop(&mut self) {
+        if self.volatile {
+            // Safety: the subkey is only used by this operation.
+            if let Err(error) = unsafe { key_management::destroy(self.id) } {
+                error!(
+                    "Failed to destroy the XChaCha20-Poly1305 subkey: {:?}",
+                    error
+                );
+            }
+        }
+    }
+}
 
 impl Provider {
     pub(super) fn psa_aead_encrypt_internal(
         &self,
         application_identity: &ApplicationIdentity,
         op: psa_aead_encrypt::Operation,
+        xchacha: bool,
     ) -> Result<psa_aead_encrypt::Result> {
         let key_name = op.key_name.clone();
 
@@ -33,11 +147,12 @@ impl Provider {
         let alg = op.alg;
         let buffer_size = key_attributes.aead_encrypt_output_size(alg, op.plaintext.len())?;
         let mut ciphertext = vec![0u8; buffer_size];
+        let aead_key = AeadKey::new(id, key_attributes, alg, &op.nonce, xchacha)?;
 
         match aead::encrypt(
-            id,
+            aead_key.id,
             alg,
-            &op.nonce,
+            &aead_key.nonce,
             &op.additional_data,
             &op.plaintext,
             &mut ciphertext,
@@ -60,6 +175,7 @@ impl Provider {
         &self,
         application_identity: &ApplicationIdentity,
         op: psa_aead_decrypt::Operation,
+        xchacha: bool,
     ) -> Result<psa_aead_decrypt::Result> {
         let key_identity = KeyIdentity::new(
             application_identity.clone(),
@@ -78,11 +194,12 @@ impl Provider {
         op.validate(key_attributes)?;
         let buffer_size = key_attributes.aead_decrypt_output_size(op.alg, op.ciphertext.len())?;
         let mut plaintext = vec![0u8; buffer_size];
+        let aead_key = AeadKey::new(id, key_attributes, op.alg, &op.nonce, xchacha)?;
 
         match aead::decrypt(
-            id,
+            aead_key.id,
             op.alg,
-            &op.nonce,
+            &aead_key.nonce,
             &op.additional_data,
             &op.ciphertext,
             &mut plaintext,
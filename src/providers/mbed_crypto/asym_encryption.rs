@@ -3,6 +3,7 @@
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
+use parsec_interface::operations::psa_algorithm::Algorithm;
 use parsec_interface::operations::{psa_asymmetric_decrypt, psa_asymmetric_encrypt};
 use parsec_interface::requests::{ResponseStatus, Result};
 use psa_crypto::operations::asym_encryption;
@@ -70,6 +71,7 @@ impl Provider {
         let id = key::Id::from_persistent_key_id(key_id)?;
         let key_attributes = key::Attributes::from_key_id(id)?;
         op.validate(key_attributes)?;
+        self.check_side_channel_resistance(Algorithm::AsymmetricEncryption(op.alg))?;
         let salt_buff = op.salt.as_ref().map(|salt| salt.as_slice());
         let buffer_size = key_attributes.asymmetric_decrypt_output_size(op.alg)?;
         let mut plaintext = vec![0u8; buffer_size];
@@ -35,6 +35,7 @@ impl CanDoCrypto for Provider {
             info!("Unsupported key size {}", attributes.bits);
             PsaErrorNotSupported
         })?;
+        self.check_side_channel_resistance(attributes.policy.permitted_algorithms)?;
 
         Ok(can_do_crypto::Result)
     }
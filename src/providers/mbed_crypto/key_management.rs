@@ -3,6 +3,9 @@
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
+use crate::providers::utils::aes_kw::{self, KeyWrapAlgorithm};
+use crate::providers::utils::key_destruction::confirm_destruction;
+use crate::utils::logging::Redacted;
 use log::error;
 use parsec_interface::operations::psa_key_attributes::{Attributes, Type};
 use parsec_interface::operations::utils_deprecated_primitives::CheckDeprecated;
@@ -11,9 +14,15 @@ use parsec_interface::operations::{
 };
 use parsec_interface::requests::{ResponseStatus, Result};
 use parsec_interface::secrecy::{ExposeSecret, Secret};
+use psa_crypto::operations::cipher;
 use psa_crypto::operations::key_management as psa_crypto_key_management;
+use psa_crypto::types::algorithm::Cipher;
 use psa_crypto::types::key;
 use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use zeroize::Zeroizing;
+
+/// Length of the AES blocks
+const AES_BLOCK_LEN: usize = 16;
 
 /// Creates a new PSA Key ID
 pub fn create_key_id(max_current_id: &AtomicU32) -> Result<key::psa_key_id_t> {
@@ -218,6 +227,103 @@ impl Provider {
         })
     }
 
+    /// Get the ID of the AES wrapping key of the application.
+    ///
+    /// The PSA cipher operations of Mbed Crypto do not take an IV for ECB: wrapping keys are AES
+    /// keys permitting CBC without padding instead, used with a zero IV on single blocks.
+    fn aes_wrapping_key_id(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+    ) -> Result<key::Id> {
+        let key_identity = KeyIdentity::new(
+            application_identity.clone(),
+            self.provider_identity.clone(),
+            wrapping_key_name.to_string(),
+        );
+        let key_id = self.key_info_store.get_key_id(&key_identity)?;
+        let id = key::Id::from_persistent_key_id(key_id)?;
+        let key_attributes = key::Attributes::from_key_id(id)?;
+        if key_attributes.key_type != Type::Aes {
+            error!(
+                "The wrapping key \"{}\" is not an AES key.",
+                Redacted(wrapping_key_name)
+            );
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+        Ok(id)
+    }
+
+    /// Encrypt or decrypt an AES block with the wrapping key.
+    fn aes_wrapping_block(
+        encrypt: bool,
+        id: key::Id,
+        block: &mut [u8; AES_BLOCK_LEN],
+    ) -> Result<()> {
+        let mut output = Zeroizing::new([0; AES_BLOCK_LEN]);
+        let iv = [0; AES_BLOCK_LEN];
+        let output_length = if encrypt {
+            cipher::encrypt(id, Cipher::CbcNoPadding, &block[..], &iv, &mut output[..])?
+        } else {
+            cipher::decrypt(id, Cipher::CbcNoPadding, &block[..], &iv, &mut output[..])?
+        };
+        if output_length != AES_BLOCK_LEN {
+            error!("The AES block function returned {} bytes.", output_length);
+            return Err(ResponseStatus::PsaErrorGenericError);
+        }
+        block.copy_from_slice(&output[..]);
+        Ok(())
+    }
+
+    pub(super) fn aes_key_wrap_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        key_name: &str,
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Vec<u8>> {
+        let key_identity = KeyIdentity::new(
+            application_identity.clone(),
+            self.provider_identity.clone(),
+            key_name.to_string(),
+        );
+        let key_id = self.key_info_store.get_key_id(&key_identity)?;
+
+        let _guard = self
+            .key_handle_mutex
+            .lock()
+            .expect("Grabbing key handle mutex failed");
+
+        let wrapping_key_id = self.aes_wrapping_key_id(application_identity, wrapping_key_name)?;
+        let id = key::Id::from_persistent_key_id(key_id)?;
+        let key_attributes = key::Attributes::from_key_id(id)?;
+        let mut buffer = Zeroizing::new(vec![0u8; key_attributes.export_key_output_size()?]);
+        let export_length = psa_crypto_key_management::export(id, &mut buffer)?;
+        buffer.truncate(export_length);
+
+        aes_kw::wrap(alg, &buffer, |block| {
+            Provider::aes_wrapping_block(true, wrapping_key_id, block)
+        })
+    }
+
+    pub(super) fn aes_key_unwrap_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        wrapped_key: &[u8],
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let _guard = self
+            .key_handle_mutex
+            .lock()
+            .expect("Grabbing key handle mutex failed");
+
+        let wrapping_key_id = self.aes_wrapping_key_id(application_identity, wrapping_key_name)?;
+        aes_kw::unwrap(alg, wrapped_key, |block| {
+            Provider::aes_wrapping_block(false, wrapping_key_id, block)
+        })
+    }
+
     pub(super) fn psa_destroy_key_internal(
         &self,
         application_identity: &ApplicationIdentity,
@@ -227,7 +333,7 @@ impl Provider {
         let key_identity = KeyIdentity::new(
             application_identity.clone(),
             self.provider_identity.clone(),
-            key_name,
+            key_name.clone(),
         );
 
         let key_id = self.key_info_store.get_key_id(&key_identity)?;
@@ -249,7 +355,14 @@ impl Provider {
         }
 
         match destroy_key_status {
-            Ok(()) => Ok(psa_destroy_key::Result {}),
+            Ok(()) => {
+                // The persistent key must not be readable any more.
+                confirm_destruction(
+                    &key_name,
+                    key::Attributes::from_key_id(id).map_err(ResponseStatus::from),
+                )?;
+                Ok(psa_destroy_key::Result {})
+            }
             Err(error) => {
                 // In that case we would have a zombie key in the Mbed Crypto backend. The key is
                 // maybe still there but can not be accessible from Parsec anymore.
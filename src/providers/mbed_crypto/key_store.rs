@@ -0,0 +1,273 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Location of the keys stored by Mbed Crypto
+//!
+//! Mbed Crypto persists keys through its PSA Internal Trusted Storage (ITS) file backend, which
+//! writes one file per key, named after the key ID, relative to the working directory of the
+//! process. The key store directory of the provider configuration is created accessible only to
+//! the service and made the working directory before Mbed Crypto is initialised, so that the keys
+//! do not land wherever the service happens to be started from.
+//!
+//! Mbed Crypto does not encrypt those files. When encrypted storage is required, the directory
+//! must be on a dm-crypt volume or an eCryptfs mount, which is checked at startup.
+use log::{info, warn};
+use std::fs::{self, DirBuilder};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+const MOUNT_INFO: &str = "/proc/self/mountinfo";
+
+/// Prepare the directory where Mbed Crypto stores its keys and make it the working directory of
+/// the service. This must be done before Mbed Crypto is initialised, and changes the directory
+/// against which all relative paths of the process are resolved.
+pub fn open(dir: &Path, require_encryption: bool) -> Result<()> {
+    std::env::set_current_dir(prepare(dir, require_encryption)?)
+}
+
+/// Create and check the key store directory, returning its canonical path.
+fn prepare(dir: &Path, require_encryption: bool) -> Result<PathBuf> {
+    if !dir.exists() {
+        info!("Creating the Mbed Crypto key store {}.", dir.display());
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    let metadata = fs::metadata(dir)?;
+    if !metadata.is_dir() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "the Mbed Crypto key store {} is not a directory",
+                dir.display()
+            ),
+        ));
+    }
+    // Safety: geteuid has no preconditions and cannot fail.
+    if metadata.uid() != unsafe { libc::geteuid() } || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "the Mbed Crypto key store {} must be owned by the service and only accessible by it",
+                dir.display()
+            ),
+        ));
+    }
+    let dir = dir.canonicalize()?;
+    match is_encrypted(&dir) {
+        Ok(true) => (),
+        Ok(false) if require_encryption => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "the Mbed Crypto key store {} is not on an encrypted filesystem",
+                    dir.display()
+                ),
+            ))
+        }
+        Ok(false) => warn!(
+            "The Mbed Crypto key store {} is not on an encrypted filesystem, keys are stored in clear.",
+            dir.display()
+        ),
+        Err(error) if require_encryption => return Err(error),
+        Err(error) => warn!(
+            "Could not check whether the Mbed Crypto key store is encrypted ({}).",
+            error
+        ),
+    }
+    Ok(dir)
+}
+
+/// Whether a directory is on a dm-crypt volume or an eCryptfs mount.
+fn is_encrypted(dir: &Path) -> Result<bool> {
+    let mount_info = fs::read_to_string(MOUNT_INFO)?;
+    let (fs_type, source) = mount_of(&mount_info, dir).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("no mount found for {}", dir.display()),
+        )
+    })?;
+    if fs_type == "ecryptfs" {
+        return Ok(true);
+    }
+    // Device mapper volumes are named /dev/mapper/<name>, linking to /dev/dm-<N>.
+    let device = match Path::new(&source).canonicalize() {
+        Ok(device) => device,
+        Err(_) => return Ok(false),
+    };
+    match device.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.starts_with("dm-") => {
+            let uuid = fs::read_to_string(format!("/sys/class/block/{}/dm/uuid", name))?;
+            Ok(uuid.starts_with("CRYPT-"))
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Find the filesystem type and source of the mount holding a path, from the content of a
+/// mountinfo file.
+fn mount_of(mount_info: &str, path: &Path) -> Option<(String, String)> {
+    let mut found: Option<(PathBuf, String, String)> = None;
+    for line in mount_info.lines() {
+        // The optional fields end with a single hyphen, followed by the filesystem type and the
+        // mount source.
+        let (mount, filesystem) = match line.split_once(" - ") {
+            Some(fields) => fields,
+            None => continue,
+        };
+        let mount_point = match mount.split(' ').nth(4) {
+            Some(mount_point) => PathBuf::from(unescape(mount_point)),
+            None => continue,
+        };
+        let mut filesystem = filesystem.split(' ');
+        let (fs_type, source) = match (filesystem.next(), filesystem.next()) {
+            (Some(fs_type), Some(source)) => (fs_type, unescape(source)),
+            _ => continue,
+        };
+        if !path.starts_with(&mount_point) {
+            continue;
+        }
+        // The innermost mount holds the path, later mounts hiding the earlier ones on the same
+        // mount point.
+        if let Some((longest, _, _)) = &found {
+            if mount_point.components().count() < longest.components().count() {
+                continue;
+            }
+        }
+        found = Some((mount_point, fs_type.to_string(), source));
+    }
+    found.map(|(_, fs_type, source)| (fs_type, source))
+}
+
+/// Undo the octal escaping of spaces, tabs, newlines and backslashes of mountinfo fields.
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        match rest
+            .get(index + 1..index + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok())
+        {
+            Some(byte) => {
+                unescaped.push(char::from(byte));
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MOUNTS: &str = "\
+22 1 253:0 / / rw,relatime shared:1 - ext4 /dev/mapper/root rw
+35 22 253:2 / /var/lib/parsec\\040keys rw,relatime shared:2 - ext4 /dev/mapper/parsec rw
+36 22 0:30 / /var/lib rw,nosuid shared:3 - tmpfs tmpfs rw
+37 22 0:31 / /var rw,nosuid - overlay overlay rw
+";
+
+    fn mount(path: &str) -> Option<(String, String)> {
+        mount_of(MOUNTS, Path::new(path))
+    }
+
+    fn mount_info(fs_type: &str, source: &str) -> Option<(String, String)> {
+        Some((fs_type.to_string(), source.to_string()))
+    }
+
+    #[test]
+    fn innermost_mount_holds_the_path() {
+        assert_eq!(
+            mount("/var/lib/parsec keys/mbed"),
+            mount_info("ext4", "/dev/mapper/parsec")
+        );
+        assert_eq!(mount("/var/lib/parsec"), mount_info("tmpfs", "tmpfs"));
+        assert_eq!(
+            mount("/home/parsec"),
+            mount_info("ext4", "/dev/mapper/root")
+        );
+    }
+
+    #[test]
+    fn mount_points_are_matched_by_component() {
+        assert_eq!(mount("/var/lib/parsec keys2"), mount_info("tmpfs", "tmpfs"));
+        assert_eq!(mount("/variable"), mount_info("ext4", "/dev/mapper/root"));
+    }
+
+    #[test]
+    fn later_mounts_hide_earlier_ones() {
+        let mount_info = "\
+22 1 253:0 / / rw - ext4 /dev/mapper/root rw
+40 22 0:40 / /srv rw - ext4 /dev/sdb1 rw
+41 22 0:41 / /srv rw - ecryptfs /srv rw
+";
+        assert_eq!(
+            mount_of(mount_info, Path::new("/srv/parsec")),
+            self::mount_info("ecryptfs", "/srv")
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let mount_info = "\
+garbage
+35 22 253:2 / /var rw no separator ext4 /dev/sda1 rw
+36 22 0:30 - tmpfs tmpfs rw
+37 22 0:31 / /var rw - ext4
+";
+        assert_eq!(mount_of(mount_info, Path::new("/var/lib/parsec")), None);
+    }
+
+    #[test]
+    fn mount_fields_are_unescaped() {
+        assert_eq!(unescape("/mnt/no\\040space"), "/mnt/no space");
+        assert_eq!(unescape("\\011tab\\012line"), "\ttab\nline");
+        assert_eq!(unescape("back\\134slash"), "back\\slash");
+        assert_eq!(unescape("plain"), "plain");
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(unescape("a\\9bc"), "a\\9bc");
+        assert_eq!(unescape("end\\04"), "end\\04");
+        assert_eq!(unescape("end\\"), "end\\");
+    }
+
+    #[test]
+    fn key_store_is_created_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_store = dir.path().join("mbed").join("keys");
+        let prepared = prepare(&key_store, false).unwrap();
+        assert_eq!(prepared, key_store.canonicalize().unwrap());
+        let mode = fs::metadata(&key_store).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[test]
+    fn key_store_must_be_a_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_store = dir.path().join("keys");
+        fs::write(&key_store, b"").unwrap();
+        assert_eq!(
+            prepare(&key_store, false).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn key_store_accessible_by_others_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        for mode in &[0o750, 0o705, 0o755] {
+            fs::set_permissions(dir.path(), fs::Permissions::from_mode(*mode)).unwrap();
+            assert_eq!(
+                prepare(dir.path(), false).unwrap_err().kind(),
+                ErrorKind::PermissionDenied
+            );
+        }
+    }
+}
@@ -7,10 +7,13 @@ use super::Provide;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::{KeyIdentity, KeyInfoManagerClient};
 use crate::providers::crypto_capability::CanDoCrypto;
+use crate::providers::utils::aes_kw::KeyWrapAlgorithm;
 use crate::providers::ProviderIdentity;
+use crate::utils::config::SideChannelHardening;
 use derivative::Derivative;
 use log::{error, trace};
 use parsec_interface::operations::list_providers::Uuid;
+use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricEncryption};
 use parsec_interface::operations::{
     can_do_crypto, psa_aead_decrypt, psa_aead_encrypt, psa_asymmetric_decrypt,
     psa_asymmetric_encrypt, psa_destroy_key, psa_export_key, psa_export_public_key,
@@ -22,10 +25,12 @@ use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
 use psa_crypto::types::{key, status};
 use std::collections::HashSet;
 use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicU32, Ordering::Relaxed},
     Mutex,
 };
+use zeroize::Zeroizing;
 
 mod aead;
 mod asym_encryption;
@@ -35,6 +40,7 @@ mod generate_random;
 mod hash;
 mod key_agreement;
 pub(super) mod key_management;
+mod key_store;
 
 const SUPPORTED_OPCODES: [Opcode; 16] = [
     Opcode::PsaGenerateKey,
@@ -73,11 +79,14 @@ pub struct Provider {
     // `key_handle_mutex` is use as a way of securing access to said operations among the threads.
     // This issue tracks progress on fixing the original problem in Mbed Crypto:
     // https://github.com/ARMmbed/mbed-crypto/issues/266
-    key_handle_mutex: Mutex<()>,
+    // It is shared with the software verifier.
+    key_handle_mutex: &'static Mutex<()>,
 
     // Holds the highest ID of all keys (including destroyed keys). New keys will receive an ID of
     // id_counter + 1. Once id_counter reaches the highest allowed ID, no more keys can be created.
     id_counter: AtomicU32,
+
+    side_channel_hardening: SideChannelHardening,
 }
 
 impl Provider {
@@ -91,7 +100,11 @@ impl Provider {
     /// Checks if there are not more keys stored in the Key Info Manager than in the MbedCryptoProvider and
     /// if there, delete them. Adds Key IDs currently in use in the local IDs store.
     /// Returns `None` if the initialisation failed.
-    fn new(provider_name: String, key_info_store: KeyInfoManagerClient) -> Option<Provider> {
+    fn new(
+        provider_name: String,
+        key_info_store: KeyInfoManagerClient,
+        side_channel_hardening: SideChannelHardening,
+    ) -> Option<Provider> {
         // Safety: this function should be called before any of the other Mbed Crypto functions
         // are.
         if let Err(error) = psa_crypto::init() {
@@ -104,8 +117,9 @@ impl Provider {
                 uuid: String::from(Self::PROVIDER_UUID),
             },
             key_info_store,
-            key_handle_mutex: Mutex::new(()),
+            key_handle_mutex: &super::MBED_CRYPTO_KEY_SLOTS,
             id_counter: AtomicU32::new(key::PSA_KEY_ID_USER_MIN),
+            side_channel_hardening,
         };
         let mut max_key_id: key::psa_key_id_t = key::PSA_KEY_ID_USER_MIN;
         {
@@ -158,21 +172,44 @@ impl Provider {
         mbed_crypto_provider.id_counter.store(max_key_id, Relaxed);
         Some(mbed_crypto_provider)
     }
+
+    /// Under strict side-channel hardening, refuse the algorithms whose Mbed Crypto
+    /// implementation is not side-channel resistant. RSA PKCS#1 v1.5 decryption leaks whether
+    /// the padding of a ciphertext is valid, even with RSA blinding.
+    fn check_side_channel_resistance(&self, alg: Algorithm) -> Result<()> {
+        if self.side_channel_hardening == SideChannelHardening::Strict
+            && alg == Algorithm::AsymmetricEncryption(AsymmetricEncryption::RsaPkcs1v15Crypt)
+        {
+            error!(
+                "RSA PKCS#1 v1.5 decryption is not allowed under strict side-channel hardening."
+            );
+            return Err(ResponseStatus::PsaErrorNotSupported);
+        }
+        Ok(())
+    }
 }
 
 impl Provide for Provider {
     fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
         trace!("describe ingress");
-        Ok((ProviderInfo {
-            // Assigned UUID for this provider: 1c1139dc-ad7c-47dc-ad6b-db6fdb466552
-            uuid: Uuid::parse_str(Provider::PROVIDER_UUID).or(Err(ResponseStatus::InvalidEncoding))?,
-            description: String::from("User space software provider, based on Mbed Crypto - the reference implementation of the PSA crypto API"),
-            vendor: String::from("Arm"),
-            version_maj: 0,
-            version_min: 1,
-            version_rev: 0,
-            id: ProviderId::MbedCrypto,
-        }, SUPPORTED_OPCODES.iter().copied().collect()))
+        let mut description = String::from("User space software provider, based on Mbed Crypto - the reference implementation of the PSA crypto API");
+        if self.side_channel_hardening == SideChannelHardening::Strict {
+            description.push_str(" (strict side-channel hardening)");
+        }
+        Ok((
+            ProviderInfo {
+                // Assigned UUID for this provider: 1c1139dc-ad7c-47dc-ad6b-db6fdb466552
+                uuid: Uuid::parse_str(Provider::PROVIDER_UUID)
+                    .or(Err(ResponseStatus::InvalidEncoding))?,
+                description,
+                vendor: String::from("Arm"),
+                version_maj: 0,
+                version_min: 1,
+                version_rev: 0,
+                id: ProviderId::MbedCrypto,
+            },
+            SUPPORTED_OPCODES.iter().copied().collect(),
+        ))
     }
 
     fn list_keys(
@@ -285,7 +322,7 @@ impl Provide for Provider {
         op: psa_aead_encrypt::Operation,
     ) -> Result<psa_aead_encrypt::Result> {
         trace!("psa_aead_encrypt ingress");
-        self.psa_aead_encrypt_internal(application_identity, op)
+        self.psa_aead_encrypt_internal(application_identity, op, false)
     }
 
     fn psa_aead_decrypt(
@@ -294,7 +331,7 @@ impl Provide for Provider {
         op: psa_aead_decrypt::Operation,
     ) -> Result<psa_aead_decrypt::Result> {
         trace!("psa_aead_decrypt ingress");
-        self.psa_aead_decrypt_internal(application_identity, op)
+        self.psa_aead_decrypt_internal(application_identity, op, false)
     }
 
     fn psa_hash_compute(
@@ -338,6 +375,50 @@ impl Provide for Provider {
         trace!("can_do_crypto ingress");
         self.can_do_crypto_main(application_identity, op)
     }
+
+    fn aes_key_wrap(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        key_name: &str,
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Vec<u8>> {
+        trace!("aes_key_wrap ingress");
+        self.aes_key_wrap_internal(application_identity, wrapping_key_name, key_name, alg)
+    }
+
+    fn xchacha20_poly1305_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_encrypt::Operation,
+    ) -> Result<psa_aead_encrypt::Result> {
+        trace!("xchacha20_poly1305_encrypt ingress");
+        self.psa_aead_encrypt_internal(application_identity, op, true)
+    }
+
+    fn xchacha20_poly1305_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_decrypt::Operation,
+    ) -> Result<psa_aead_decrypt::Result> {
+        trace!("xchacha20_poly1305_decrypt ingress");
+        self.psa_aead_decrypt_internal(application_identity, op, true)
+    }
+
+    fn aes_key_unwrap(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        wrapped_key: &[u8],
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        trace!("aes_key_unwrap ingress");
+        self.aes_key_unwrap_internal(application_identity, wrapping_key_name, wrapped_key, alg)
+    }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        Some(&self.key_info_store)
+    }
 }
 
 /// Mbed Crypto provider builder
@@ -347,6 +428,9 @@ pub struct ProviderBuilder {
     provider_name: Option<String>,
     #[derivative(Debug = "ignore")]
     key_info_store: Option<KeyInfoManagerClient>,
+    key_store_dir: Option<PathBuf>,
+    require_encrypted_key_store: bool,
+    side_channel_hardening: SideChannelHardening,
 }
 
 impl ProviderBuilder {
@@ -355,6 +439,9 @@ impl ProviderBuilder {
         ProviderBuilder {
             provider_name: None,
             key_info_store: None,
+            key_store_dir: None,
+            require_encrypted_key_store: false,
+            side_channel_hardening: SideChannelHardening::Default,
         }
     }
 
@@ -372,14 +459,54 @@ impl ProviderBuilder {
         self
     }
 
+    /// Add the directory where Mbed Crypto stores the keys
+    pub fn with_key_store_dir(mut self, key_store_dir: PathBuf) -> ProviderBuilder {
+        self.key_store_dir = Some(key_store_dir);
+
+        self
+    }
+
+    /// Require the key store directory to be on an encrypted filesystem
+    pub fn with_encrypted_key_store(
+        mut self,
+        require_encrypted_key_store: bool,
+    ) -> ProviderBuilder {
+        self.require_encrypted_key_store = require_encrypted_key_store;
+
+        self
+    }
+
+    /// Set the side-channel hardening level
+    pub fn with_side_channel_hardening(
+        mut self,
+        side_channel_hardening: SideChannelHardening,
+    ) -> ProviderBuilder {
+        self.side_channel_hardening = side_channel_hardening;
+
+        self
+    }
+
     /// Build into a MbedProvider
     pub fn build(self) -> std::io::Result<Provider> {
+        match self.key_store_dir {
+            Some(ref key_store_dir) => {
+                key_store::open(key_store_dir, self.require_encrypted_key_store)?
+            }
+            None if self.require_encrypted_key_store => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "an encrypted key store requires a key store directory",
+                ))
+            }
+            None => (),
+        }
         Provider::new(
             self.provider_name.ok_or_else(|| {
                 std::io::Error::new(std::io::ErrorKind::InvalidData, "missing provider name")
             })?,
             self.key_info_store
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing key info store"))?,
+            self.side_channel_hardening,
         )
         .ok_or_else(|| {
             Error::new(
@@ -12,10 +12,18 @@ use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::fmt;
 
+pub mod capability_matrix;
+
 pub mod core;
 
 pub mod crypto_capability;
 
+pub mod error_detail;
+
+pub mod lazy;
+
+pub mod utils;
+
 #[cfg(feature = "pkcs11-provider")]
 //TODO: To remove when #301 is merged
 #[allow(clippy::all)]
@@ -30,10 +38,32 @@ pub mod tpm;
 #[cfg(feature = "cryptoauthlib-provider")]
 pub mod cryptoauthlib;
 
+#[cfg(feature = "piv-provider")]
+pub mod piv;
+
+#[cfg(feature = "forwarding-provider")]
+pub mod forwarding;
+
+#[cfg(feature = "external-provider")]
+pub mod external;
+
 #[cfg(feature = "trusted-service-provider")]
 pub mod trusted_service;
 
+#[cfg(feature = "software-verifier")]
+pub mod software_verifier;
+
+/// Serializes the allocation of key slots in Mbed Crypto, which is not thread safe, between the
+/// Mbed Crypto provider and the software verifier that share the library.
+#[cfg(any(feature = "mbed-crypto-provider", feature = "software-verifier"))]
+static MBED_CRYPTO_KEY_SLOTS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(feature = "test-provider")]
+pub mod test_provider;
+
 use crate::authenticators::ApplicationIdentity;
+use crate::key_info_managers::KeyInfoManagerClient;
+use crate::providers::utils::aes_kw::KeyWrapAlgorithm;
 use parsec_interface::operations::{
     attest_key, can_do_crypto, delete_client, list_authenticators, list_clients, list_keys,
     list_opcodes, list_providers, ping, prepare_key_attestation, psa_aead_decrypt,
@@ -45,6 +75,7 @@ use parsec_interface::operations::{
 use parsec_interface::requests::{ResponseStatus, Result};
 
 use parsec_interface::requests::ProviderId;
+use zeroize::Zeroizing;
 
 /// The ProviderIdentity struct specifies a unique uuid-name
 /// combination to form a unique provider identity.
@@ -96,8 +127,14 @@ impl TryFrom<ProviderIdentity> for ProviderId {
             crate::providers::pkcs11::Provider::PROVIDER_UUID => Ok(ProviderId::Pkcs11),
             #[cfg(feature = "tpm-provider")]
             crate::providers::tpm::Provider::PROVIDER_UUID => Ok(ProviderId::Tpm),
+            // Refused alongside a PKCS 11 provider, see `ProviderConfig::provider_id`.
+            #[cfg(feature = "piv-provider")]
+            piv::Provider::PROVIDER_UUID => Ok(ProviderId::Pkcs11),
             #[cfg(feature = "trusted-service-provider")]
             crate::providers::trusted_service::Provider::PROVIDER_UUID => Ok(ProviderId::TrustedService),
+            // Refused alongside an Mbed Crypto provider, see `ProviderConfig::provider_id`.
+            #[cfg(feature = "test-provider")]
+            test_provider::Provider::PROVIDER_UUID => Ok(ProviderId::MbedCrypto),
             _ => Err(format!("Cannot convert from ProviderIdentity to ProviderId.\nProvider \"{}\" is not recognised.\nCould be it does not exist, or Parsec was not compiled with the required provider feature flags.", provider_identity.uuid)),
         }?;
 
@@ -416,4 +453,101 @@ pub trait Provide {
         trace!("attest_key ingress");
         Err(ResponseStatus::PsaErrorNotSupported)
     }
+
+    /// Extend a measurement register of the provider's hardware, a TPM PCR, with the digest of an
+    /// event.
+    ///
+    /// This is not a wire operation: the service measures its configuration when it starts
+    /// through `measure_configuration` of the Core provider.
+    fn extend_measurement(&self, _register: u8, _event: &[u8]) -> Result<()> {
+        trace!("extend_measurement ingress");
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+
+    /// Get the public key under which secrets are wrapped offline, for their later import into the
+    /// provider, as a DER encoded SubjectPublicKeyInfo.
+    ///
+    /// This is not a wire operation: provisioning tools get it with a PsaExportPublicKey request
+    /// on the reserved key name of the `wrapping-key-export` feature.
+    fn wrapping_public_key(&self) -> Result<Vec<u8>> {
+        trace!("wrapping_public_key ingress");
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+
+    /// Export a key wrapped by one of the application's AES keys, with AES-KW or AES-KWP.
+    ///
+    /// This is not a wire operation: the wrapped keys are returned by PsaExportKey requests on a
+    /// key name suffixed with the wrapping key, with the `aes-key-wrap` feature, as described in
+    /// `utils::aes_kw`.
+    fn aes_key_wrap(
+        &self,
+        _application_identity: &ApplicationIdentity,
+        _wrapping_key_name: &str,
+        _key_name: &str,
+        _alg: KeyWrapAlgorithm,
+    ) -> Result<Vec<u8>> {
+        trace!("aes_key_wrap ingress");
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+
+    /// Unwrap a key wrapped by one of the application's AES keys, with AES-KW or AES-KWP. The back
+    /// end then imports the unwrapped key like the others, with its conversion and checks.
+    ///
+    /// This is not a wire operation: wrapped keys are imported by PsaImportKey requests on a key
+    /// name suffixed with the wrapping key, with the `aes-key-wrap` feature, as described in
+    /// `utils::aes_kw`.
+    fn aes_key_unwrap(
+        &self,
+        _application_identity: &ApplicationIdentity,
+        _wrapping_key_name: &str,
+        _wrapped_key: &[u8],
+        _alg: KeyWrapAlgorithm,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        trace!("aes_key_unwrap ingress");
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+
+    /// Execute an AeadEncrypt operation with XChaCha20-Poly1305, its nonce being 24 bytes long.
+    ///
+    /// This is not a wire operation: applications ask for it by suffixing the key name of
+    /// PsaAeadEncrypt requests on the `Chacha20Poly1305` algorithm, with the
+    /// `xchacha20-poly1305` feature, as described in `utils::xchacha20`.
+    fn xchacha20_poly1305_encrypt(
+        &self,
+        _application_identity: &ApplicationIdentity,
+        _op: psa_aead_encrypt::Operation,
+    ) -> Result<psa_aead_encrypt::Result> {
+        trace!("xchacha20_poly1305_encrypt ingress");
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+
+    /// Execute an AeadDecrypt operation with XChaCha20-Poly1305, like
+    /// `xchacha20_poly1305_encrypt`.
+    fn xchacha20_poly1305_decrypt(
+        &self,
+        _application_identity: &ApplicationIdentity,
+        _op: psa_aead_decrypt::Operation,
+    ) -> Result<psa_aead_decrypt::Result> {
+        trace!("xchacha20_poly1305_decrypt ingress");
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+
+    /// Whether the provider is built. Providers activated on demand are not until their first
+    /// request.
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    /// Build the provider if it is activated on demand and not built yet.
+    fn activate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Key Info Manager client holding the keys of the provider, if it stores any.
+    ///
+    /// This is not an operation: it gives the back end handlers access to the stored key
+    /// attributes.
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        None
+    }
 }
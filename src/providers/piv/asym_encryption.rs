@@ -0,0 +1,222 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+use super::key_management::piv_algorithm;
+use super::{card, Provider};
+use crate::authenticators::ApplicationIdentity;
+use log::error;
+use parsec_interface::operations::psa_algorithm::AsymmetricEncryption;
+use parsec_interface::operations::psa_asymmetric_decrypt;
+use parsec_interface::requests::{ResponseStatus, Result};
+use zeroize::Zeroizing;
+
+/// Minimum length of the padding string of EME-PKCS1-v1_5
+const MIN_PADDING_LEN: usize = 8;
+
+/// Message of an EME-PKCS1-v1_5 encoded block, or `None` if its padding is not valid.
+fn eme_pkcs1_v1_5_message(block: &[u8]) -> Option<&[u8]> {
+    if block.len() < 2 + MIN_PADDING_LEN + 1 || block[0] != 0x00 || block[1] != 0x02 {
+        return None;
+    }
+    let separator = block[2..].iter().position(|byte| *byte == 0x00)? + 2;
+    if separator < 2 + MIN_PADDING_LEN {
+        return None;
+    }
+    Some(&block[separator + 1..])
+}
+
+impl Provider {
+    pub(super) fn psa_asymmetric_decrypt_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_asymmetric_decrypt::Operation,
+    ) -> Result<psa_asymmetric_decrypt::Result> {
+        let (_, key_reference, attributes) =
+            self.get_key(application_identity, op.key_name.clone())?;
+        op.validate(attributes)?;
+        if op.alg != AsymmetricEncryption::RsaPkcs1v15Crypt {
+            error!("Only RSA PKCS#1 v1.5 decryption is supported by PIV cards.");
+            return Err(ResponseStatus::PsaErrorNotSupported);
+        }
+        let algorithm = piv_algorithm(attributes)?;
+        if op.ciphertext.len() != (attributes.bits + 7) / 8 {
+            error!("The ciphertext is not as long as the modulus of the key.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+
+        // The card only applies the raw RSA operation, the padding is removed here.
+        let block = self.with_card(|card| {
+            self.verify_pin(card)?;
+            card::general_authenticate(card, algorithm, key_reference, &op.ciphertext)
+        })?;
+        match eme_pkcs1_v1_5_message(&block) {
+            Some(message) => Ok(psa_asymmetric_decrypt::Result {
+                plaintext: Zeroizing::new(message.to_vec()),
+            }),
+            None => {
+                // The same error for any defect of the padding, not to make a padding oracle.
+                error!("Wrong plaintext padding");
+                Err(ResponseStatus::PsaErrorInvalidPadding)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::super::pcsc::fake::state;
+    use super::super::test_card::{
+        application, authentication_challenge, authentication_response, provider_with_key, respond,
+        KEY_NAME, RSA_2048_CERTIFICATE,
+    };
+    use super::*;
+    use crate::utils::config::PivKeyUsage;
+    use parsec_interface::operations::psa_algorithm::Hash;
+
+    /// EME-PKCS1-v1_5 block of the message for a modulus of the given length
+    fn block(message: &[u8], modulus_len: usize) -> Vec<u8> {
+        let mut block = vec![0x00, 0x02];
+        block.resize(modulus_len - message.len() - 1, 0x5A);
+        block.push(0x00);
+        block.extend_from_slice(message);
+        block
+    }
+
+    fn decrypt(
+        provider: &Provider,
+        alg: AsymmetricEncryption,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        provider
+            .psa_asymmetric_decrypt_internal(
+                &application(),
+                psa_asymmetric_decrypt::Operation {
+                    key_name: String::from(KEY_NAME),
+                    alg,
+                    ciphertext: Zeroizing::new(ciphertext.to_vec()),
+                    salt: None,
+                },
+            )
+            .map(|result| result.plaintext.to_vec())
+    }
+
+    #[test]
+    fn padding_is_removed() {
+        assert_eq!(
+            eme_pkcs1_v1_5_message(&block(b"secret", 256)),
+            Some(&b"secret"[..])
+        );
+        assert_eq!(eme_pkcs1_v1_5_message(&block(b"", 64)), Some(&b""[..]));
+    }
+
+    #[test]
+    fn padding_must_be_long_enough() {
+        let mut short_padding = vec![0x00, 0x02, 0x5A, 0x00];
+        short_padding.extend_from_slice(&[0x11; 16]);
+        assert_eq!(eme_pkcs1_v1_5_message(&short_padding), None);
+        // The shortest padding
+        assert_eq!(
+            eme_pkcs1_v1_5_message(&block(&[0x11; 16], 2 + 8 + 1 + 16)),
+            Some(&[0x11; 16][..])
+        );
+    }
+
+    #[test]
+    fn block_type_must_be_encryption() {
+        let mut block = block(b"secret", 256);
+        block[1] = 0x01;
+        assert_eq!(eme_pkcs1_v1_5_message(&block), None);
+    }
+
+    #[test]
+    fn block_must_start_with_zero() {
+        let mut block = block(b"secret", 256);
+        block[0] = 0x01;
+        assert_eq!(eme_pkcs1_v1_5_message(&block), None);
+    }
+
+    #[test]
+    fn message_must_be_separated() {
+        let mut block = vec![0x00, 0x02];
+        block.resize(256, 0x5A);
+        assert_eq!(eme_pkcs1_v1_5_message(&block), None);
+        assert_eq!(eme_pkcs1_v1_5_message(&[0x00, 0x02, 0x00]), None);
+    }
+
+    #[test]
+    fn ciphertexts_are_decrypted_by_the_card() {
+        let (_dir, provider) = provider_with_key(RSA_2048_CERTIFICATE, PivKeyUsage::Decrypt);
+        respond(2, &authentication_response(&block(b"secret", 256)));
+        assert_eq!(
+            decrypt(
+                &provider,
+                AsymmetricEncryption::RsaPkcs1v15Crypt,
+                &[0xC5; 256]
+            )
+            .unwrap(),
+            b"secret"
+        );
+        // GENERAL AUTHENTICATE with the RSA 2048 key of slot 9c, the ciphertext as challenge
+        assert_eq!(
+            state(|state| state.sent[1][..4].to_vec()),
+            [0x10, 0x87, 0x07, 0x9C]
+        );
+        let challenge = authentication_challenge();
+        assert_eq!(challenge[challenge.len() - 256..], [0xC5; 256]);
+    }
+
+    #[test]
+    fn ciphertexts_must_be_as_long_as_the_modulus() {
+        let (_dir, provider) = provider_with_key(RSA_2048_CERTIFICATE, PivKeyUsage::Decrypt);
+        assert_eq!(
+            decrypt(
+                &provider,
+                AsymmetricEncryption::RsaPkcs1v15Crypt,
+                &[0xC5; 255]
+            )
+            .unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+        assert!(state(|state| state.sent.is_empty()));
+    }
+
+    #[test]
+    fn invalid_padding_is_reported() {
+        let (_dir, provider) = provider_with_key(RSA_2048_CERTIFICATE, PivKeyUsage::Decrypt);
+        let mut invalid = block(b"secret", 256);
+        invalid[1] = 0x01;
+        respond(2, &authentication_response(&invalid));
+        assert_eq!(
+            decrypt(
+                &provider,
+                AsymmetricEncryption::RsaPkcs1v15Crypt,
+                &[0xC5; 256]
+            )
+            .unwrap_err(),
+            ResponseStatus::PsaErrorInvalidPadding
+        );
+    }
+
+    #[test]
+    fn only_pkcs1_v1_5_is_supported() {
+        let (_dir, provider) = provider_with_key(RSA_2048_CERTIFICATE, PivKeyUsage::Decrypt);
+        let oaep = AsymmetricEncryption::RsaOaep {
+            hash_alg: Hash::Sha256,
+        };
+        assert!(decrypt(&provider, oaep, &[0xC5; 256]).is_err());
+        assert!(state(|state| state.sent.is_empty()));
+    }
+
+    #[test]
+    fn signing_keys_do_not_decrypt() {
+        let (_dir, provider) = provider_with_key(RSA_2048_CERTIFICATE, PivKeyUsage::Sign);
+        assert_eq!(
+            decrypt(
+                &provider,
+                AsymmetricEncryption::RsaPkcs1v15Crypt,
+                &[0xC5; 256]
+            )
+            .unwrap_err(),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+    }
+}
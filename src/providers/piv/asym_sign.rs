@@ -0,0 +1,370 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+use super::key_management::piv_algorithm;
+use super::{card, Provider};
+use crate::authenticators::ApplicationIdentity;
+use crate::providers::utils::ecdsa_signature;
+use log::error;
+use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_key_attributes::{Attributes, Type};
+use parsec_interface::operations::{psa_sign_hash, psa_sign_message};
+use parsec_interface::requests::{ResponseStatus, Result};
+use ring::digest;
+
+/// DER encoding of the DigestInfo of EMSA-PKCS1-v1_5, up to the digest.
+fn digest_info_prefix(hash: Hash) -> Result<&'static [u8]> {
+    match hash {
+        Hash::Sha256 => Ok(&[
+            0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x01, 0x05, 0x00, 0x04, 0x20,
+        ]),
+        Hash::Sha384 => Ok(&[
+            0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x02, 0x05, 0x00, 0x04, 0x30,
+        ]),
+        Hash::Sha512 => Ok(&[
+            0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x03, 0x05, 0x00, 0x04, 0x40,
+        ]),
+        _ => {
+            error!("RSA signatures with {:?} are not supported.", hash);
+            Err(ResponseStatus::PsaErrorNotSupported)
+        }
+    }
+}
+
+/// Encoded message signed with the raw RSA key, as in EMSA-PKCS1-v1_5.
+fn emsa_pkcs1_v1_5(hash: Hash, digest: &[u8], modulus_len: usize) -> Result<Vec<u8>> {
+    let prefix = digest_info_prefix(hash)?;
+    if digest.len() != hash.hash_length() {
+        error!("The hash is not as long as a {:?} digest.", hash);
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    let padding_len = modulus_len
+        .checked_sub(prefix.len() + digest.len() + 3)
+        .ok_or(ResponseStatus::PsaErrorInvalidArgument)?;
+    let mut message = vec![0x00, 0x01];
+    message.resize(2 + padding_len, 0xFF);
+    message.push(0x00);
+    message.extend_from_slice(prefix);
+    message.extend_from_slice(digest);
+    Ok(message)
+}
+
+/// Digest signed with ECDSA: truncated to the length of the curve order, or padded to it.
+fn ecdsa_input(digest: &[u8], field_len: usize) -> Vec<u8> {
+    if digest.len() >= field_len {
+        digest[..field_len].to_vec()
+    } else {
+        let mut input = vec![0; field_len - digest.len()];
+        input.extend_from_slice(digest);
+        input
+    }
+}
+
+fn hash_message(hash: Hash, message: &[u8]) -> Result<Vec<u8>> {
+    let algorithm = match hash {
+        Hash::Sha256 => &digest::SHA256,
+        Hash::Sha384 => &digest::SHA384,
+        Hash::Sha512 => &digest::SHA512,
+        _ => {
+            error!("Signing messages hashed with {:?} is not supported.", hash);
+            return Err(ResponseStatus::PsaErrorNotSupported);
+        }
+    };
+    Ok(digest::digest(algorithm, message).as_ref().to_vec())
+}
+
+impl Provider {
+    /// Sign a digest with the key of a slot.
+    fn sign(
+        &self,
+        key_reference: u8,
+        attributes: Attributes,
+        alg: AsymmetricSignature,
+        digest: &[u8],
+    ) -> Result<Vec<u8>> {
+        let algorithm = piv_algorithm(attributes)?;
+        let field_len = (attributes.bits + 7) / 8;
+        let challenge = match (attributes.key_type, alg) {
+            (Type::EccKeyPair { .. }, AsymmetricSignature::Ecdsa { .. }) => {
+                ecdsa_input(digest, field_len)
+            }
+            (
+                Type::RsaKeyPair,
+                AsymmetricSignature::RsaPkcs1v15Sign {
+                    hash_alg: SignHash::Specific(hash),
+                },
+            ) => emsa_pkcs1_v1_5(hash, digest, field_len)?,
+            _ => {
+                error!("The signature algorithm {:?} is not supported.", alg);
+                return Err(ResponseStatus::PsaErrorNotSupported);
+            }
+        };
+        let signature = self.with_card(|card| {
+            self.verify_pin(card)?;
+            card::general_authenticate(card, algorithm, key_reference, &challenge)
+        })?;
+        match attributes.key_type {
+            // The card returns the DER encoding of ECDSA signatures.
+            Type::EccKeyPair { .. } => ecdsa_signature::der_to_raw(&signature, field_len)
+                .ok_or_else(|| {
+                    error!("The card returned an invalid ECDSA signature.");
+                    ResponseStatus::PsaErrorHardwareFailure
+                }),
+            _ => Ok(signature.to_vec()),
+        }
+    }
+
+    pub(super) fn psa_sign_hash_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_hash::Operation,
+    ) -> Result<psa_sign_hash::Result> {
+        let (_, key_reference, attributes) =
+            self.get_key(application_identity, op.key_name.clone())?;
+        op.validate(attributes)?;
+        let signature = self.sign(key_reference, attributes, op.alg, &op.hash)?;
+        Ok(psa_sign_hash::Result {
+            signature: signature.into(),
+        })
+    }
+
+    pub(super) fn psa_sign_message_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_message::Operation,
+    ) -> Result<psa_sign_message::Result> {
+        let (_, key_reference, attributes) =
+            self.get_key(application_identity, op.key_name.clone())?;
+        op.validate(attributes)?;
+        let hash = match op.alg.hash() {
+            Some(SignHash::Specific(hash)) => hash,
+            _ => {
+                error!("Signing a message needs a specific hash algorithm.");
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+        };
+        let digest = hash_message(hash, &op.message)?;
+        let signature = self.sign(key_reference, attributes, op.alg, &digest)?;
+        Ok(psa_sign_message::Result {
+            signature: signature.into(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::super::pcsc::fake::state;
+    use super::super::test_card::{
+        application, authentication_challenge, authentication_response, provider_with_key, respond,
+        KEY_NAME, P256_CERTIFICATE, RSA_2048_CERTIFICATE,
+    };
+    use super::*;
+    use crate::utils::config::PivKeyUsage;
+    use zeroize::Zeroizing;
+
+    /// DigestInfo of SHA-256 up to the digest, from RFC 8017
+    const SHA256_DIGEST_INFO: [u8; 19] = [
+        0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01,
+        0x05, 0x00, 0x04, 0x20,
+    ];
+    /// SHA-256 digest of "abc", from FIPS 180-2
+    const SHA256_ABC: [u8; 32] = [
+        0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22,
+        0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00,
+        0x15, 0xad,
+    ];
+
+    fn ecdsa_sha256() -> AsymmetricSignature {
+        AsymmetricSignature::Ecdsa {
+            hash_alg: Hash::Sha256.into(),
+        }
+    }
+
+    fn sign_hash(provider: &Provider, alg: AsymmetricSignature, hash: &[u8]) -> Result<Vec<u8>> {
+        provider
+            .psa_sign_hash_internal(
+                &application(),
+                psa_sign_hash::Operation {
+                    key_name: String::from(KEY_NAME),
+                    alg,
+                    hash: Zeroizing::new(hash.to_vec()),
+                },
+            )
+            .map(|result| result.signature.to_vec())
+    }
+
+    /// DER encoding of the ECDSA signature of r = 11..11 and s = 22..22, as returned by the card
+    fn der_signature() -> Vec<u8> {
+        let mut der = vec![0x30, 0x44, 0x02, 0x20];
+        der.extend_from_slice(&[0x11; 32]);
+        der.extend_from_slice(&[0x02, 0x20]);
+        der.extend_from_slice(&[0x22; 32]);
+        der
+    }
+
+    #[test]
+    fn rsa_digests_are_encoded_in_digest_info() {
+        let message = emsa_pkcs1_v1_5(Hash::Sha256, &SHA256_ABC, 256).unwrap();
+        let mut expected = vec![0x00, 0x01];
+        expected.resize(256 - 19 - 32 - 1, 0xFF);
+        expected.push(0x00);
+        expected.extend_from_slice(&SHA256_DIGEST_INFO);
+        expected.extend_from_slice(&SHA256_ABC);
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn digest_info_of_each_hash() {
+        for (hash, len) in &[(Hash::Sha384, 48), (Hash::Sha512, 64)] {
+            let message = emsa_pkcs1_v1_5(*hash, &vec![0xAA; *len], 256).unwrap();
+            assert_eq!(message.len(), 256);
+            // Length of the DigestInfo, then of the digest
+            assert_eq!(message[256 - len - 19 + 1], (len + 17) as u8);
+            assert_eq!(message[256 - len - 1], *len as u8);
+        }
+    }
+
+    #[test]
+    fn rsa_digests_must_have_their_length() {
+        assert_eq!(
+            emsa_pkcs1_v1_5(Hash::Sha256, &[0xAA; 20], 256).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn rsa_modulus_must_hold_the_digest_info() {
+        assert_eq!(
+            emsa_pkcs1_v1_5(Hash::Sha512, &[0xAA; 64], 64).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn rsa_signatures_with_other_hashes_are_not_supported() {
+        assert_eq!(
+            emsa_pkcs1_v1_5(Hash::Sha224, &[0xAA; 28], 256).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn ecdsa_digests_are_truncated_or_padded() {
+        assert_eq!(ecdsa_input(&[0x01; 48], 32), vec![0x01; 32]);
+        let padded = ecdsa_input(&[0x01; 32], 48);
+        assert_eq!(padded[..16], [0x00; 16]);
+        assert_eq!(padded[16..], [0x01; 32]);
+    }
+
+    #[test]
+    fn messages_are_hashed() {
+        assert_eq!(hash_message(Hash::Sha256, b"abc").unwrap(), SHA256_ABC);
+        assert_eq!(
+            hash_message(Hash::Sha224, b"abc").unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn ecdsa_signatures_are_returned_raw() {
+        let (_dir, provider) = provider_with_key(P256_CERTIFICATE, PivKeyUsage::Sign);
+        respond(1, &authentication_response(&der_signature()));
+        let signature = sign_hash(&provider, ecdsa_sha256(), &SHA256_ABC).unwrap();
+        assert_eq!(signature[..32], [0x11; 32]);
+        assert_eq!(signature[32..], [0x22; 32]);
+
+        // GENERAL AUTHENTICATE with the P-256 key of slot 9c, the digest as challenge
+        let apdu = state(|state| state.sent[1].clone());
+        assert_eq!(apdu[..4], [0x00, 0x87, 0x11, 0x9C]);
+        let mut challenge = vec![0x7C, 0x24, 0x82, 0x00, 0x81, 0x20];
+        challenge.extend_from_slice(&SHA256_ABC);
+        assert_eq!(authentication_challenge(), challenge);
+    }
+
+    #[test]
+    fn invalid_ecdsa_signatures_of_the_card_are_refused() {
+        let (_dir, provider) = provider_with_key(P256_CERTIFICATE, PivKeyUsage::Sign);
+        respond(1, &authentication_response(&[0x30, 0x00]));
+        assert_eq!(
+            sign_hash(&provider, ecdsa_sha256(), &SHA256_ABC).unwrap_err(),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+    }
+
+    #[test]
+    fn rsa_signatures_are_computed_on_the_digest_info() {
+        let (_dir, provider) = provider_with_key(RSA_2048_CERTIFICATE, PivKeyUsage::Sign);
+        // The challenge is chained over two APDUs, the response fetched in two parts.
+        respond(2, &authentication_response(&[0x5A; 256]));
+        let alg = AsymmetricSignature::RsaPkcs1v15Sign {
+            hash_alg: Hash::Sha256.into(),
+        };
+        assert_eq!(
+            sign_hash(&provider, alg, &SHA256_ABC).unwrap(),
+            vec![0x5A; 256]
+        );
+        let challenge = authentication_challenge();
+        assert_eq!(
+            challenge[challenge.len() - 256..],
+            emsa_pkcs1_v1_5(Hash::Sha256, &SHA256_ABC, 256).unwrap()[..]
+        );
+        let apdu = state(|state| state.sent[1].clone());
+        assert_eq!(apdu[..4], [0x10, 0x87, 0x07, 0x9C]);
+    }
+
+    #[test]
+    fn other_algorithms_are_not_supported() {
+        let (_dir, provider) = provider_with_key(RSA_2048_CERTIFICATE, PivKeyUsage::Sign);
+        let alg = AsymmetricSignature::RsaPkcs1v15SignRaw;
+        assert!(sign_hash(&provider, alg, &SHA256_ABC).is_err());
+        // Nothing is sent to the card.
+        assert!(state(|state| state.sent.is_empty()));
+    }
+
+    #[test]
+    fn messages_are_hashed_before_signing() {
+        let (_dir, provider) = provider_with_key(P256_CERTIFICATE, PivKeyUsage::Sign);
+        respond(1, &authentication_response(&der_signature()));
+        let result = provider
+            .psa_sign_message_internal(
+                &application(),
+                psa_sign_message::Operation {
+                    key_name: String::from(KEY_NAME),
+                    alg: ecdsa_sha256(),
+                    message: Zeroizing::new(b"abc".to_vec()),
+                },
+            )
+            .unwrap();
+        assert_eq!(result.signature.len(), 64);
+        assert_eq!(authentication_challenge()[6..], SHA256_ABC);
+    }
+
+    #[test]
+    fn pin_is_verified_before_signing() {
+        let (_dir, mut provider) = provider_with_key(P256_CERTIFICATE, PivKeyUsage::Sign);
+        provider.pin = Some(Zeroizing::new(b"123456".to_vec()));
+        respond(1, &[]);
+        respond(1, &authentication_response(&der_signature()));
+        assert!(sign_hash(&provider, ecdsa_sha256(), &SHA256_ABC).is_ok());
+        // VERIFY of the PIV PIN, padded with 0xFF
+        assert_eq!(
+            state(|state| state.sent[1].clone()),
+            [0x00, 0x20, 0x00, 0x80, 0x08, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn wrong_pin_is_returned() {
+        let (_dir, mut provider) = provider_with_key(P256_CERTIFICATE, PivKeyUsage::Sign);
+        provider.pin = Some(Zeroizing::new(b"123456".to_vec()));
+        state(|state| state.responses.push_back(vec![0x63, 0xC2]));
+        assert_eq!(
+            sign_hash(&provider, ecdsa_sha256(), &SHA256_ABC).unwrap_err(),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        // The key is not used.
+        assert_eq!(state(|state| state.sent.len()), 2);
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Commands of the PIV application
+//!
+//! The commands of NIST SP 800-73-4 used by the provider: selecting the PIV application, verifying
+//! the PIN, reading the certificate of a key and computing with a key with GENERAL AUTHENTICATE.
+//! Commands with more data than a short APDU holds are chained, and long responses are fetched
+//! with GET RESPONSE. The data objects are BER-TLV encoded, with tags of one byte.
+use super::pcsc::{Card, PcscError};
+use log::error;
+use parsec_interface::requests::ResponseStatus;
+use zeroize::Zeroizing;
+
+/// Key reference of the PIV Authentication key
+pub(super) const KEY_AUTHENTICATION: u8 = 0x9A;
+/// Key reference of the Digital Signature key
+pub(super) const KEY_SIGNATURE: u8 = 0x9C;
+
+// Algorithm identifiers of the keys
+pub(super) const ALGORITHM_RSA_2048: u8 = 0x07;
+pub(super) const ALGORITHM_RSA_3072: u8 = 0x05;
+pub(super) const ALGORITHM_RSA_4096: u8 = 0x16;
+pub(super) const ALGORITHM_ECC_P256: u8 = 0x11;
+pub(super) const ALGORITHM_ECC_P384: u8 = 0x14;
+
+const PIV_AID: [u8; 5] = [0xA0, 0x00, 0x00, 0x03, 0x08];
+
+const CLA: u8 = 0x00;
+const CLA_CHAINING: u8 = 0x10;
+const INS_SELECT: u8 = 0xA4;
+const INS_VERIFY: u8 = 0x20;
+const INS_GET_DATA: u8 = 0xCB;
+const INS_GENERAL_AUTHENTICATE: u8 = 0x87;
+const INS_GET_RESPONSE: u8 = 0xC0;
+
+/// Key reference of the PIV Card Application PIN
+const PIN_REFERENCE: u8 = 0x80;
+/// The PIN is padded to this length with 0xFF
+const PIN_LEN: usize = 8;
+
+const MAX_COMMAND_DATA: usize = 255;
+
+const TAG_OBJECT_ID: u8 = 0x5C;
+const TAG_DATA: u8 = 0x53;
+const TAG_CERTIFICATE: u8 = 0x70;
+const TAG_CERTIFICATE_INFO: u8 = 0x71;
+const TAG_DYNAMIC_AUTHENTICATION: u8 = 0x7C;
+const TAG_CHALLENGE: u8 = 0x81;
+const TAG_RESPONSE: u8 = 0x82;
+
+/// Error of a command sent to the card
+#[derive(Debug)]
+pub(super) enum CardError {
+    /// The PC/SC call failed
+    Pcsc(PcscError),
+    /// The card refused the command, or its response is not the expected one
+    Status(ResponseStatus),
+}
+
+impl From<PcscError> for CardError {
+    fn from(error: PcscError) -> Self {
+        CardError::Pcsc(error)
+    }
+}
+
+impl From<ResponseStatus> for CardError {
+    fn from(status: ResponseStatus) -> Self {
+        CardError::Status(status)
+    }
+}
+
+impl From<CardError> for ResponseStatus {
+    fn from(error: CardError) -> Self {
+        match error {
+            CardError::Pcsc(error) => error.into(),
+            CardError::Status(status) => status,
+        }
+    }
+}
+
+pub(super) type CardResult<T> = Result<T, CardError>;
+
+fn invalid_response() -> CardError {
+    error!("The response of the card is not valid.");
+    CardError::Status(ResponseStatus::PsaErrorCommunicationFailure)
+}
+
+/// Status returned for a status word other than success.
+fn status_error(sw1: u8, sw2: u8) -> ResponseStatus {
+    match (sw1, sw2) {
+        (0x63, tries) if tries & 0xF0 == 0xC0 => {
+            error!("The PIN is incorrect, {} tries left.", tries & 0x0F);
+            ResponseStatus::PsaErrorNotPermitted
+        }
+        (0x69, 0x83) => {
+            error!("The PIN is blocked: it has to be reset with the PUK.");
+            ResponseStatus::PsaErrorHardwareFailure
+        }
+        (0x69, 0x82) => {
+            error!("The card refused to use the key without the PIN verified.");
+            ResponseStatus::PsaErrorNotPermitted
+        }
+        (0x6A, 0x82) | (0x6A, 0x88) => ResponseStatus::PsaErrorDoesNotExist,
+        (0x67, 0x00) | (0x6A, 0x80) | (0x6A, 0x86) => ResponseStatus::PsaErrorInvalidArgument,
+        (0x6D, 0x00) | (0x6E, 0x00) => ResponseStatus::PsaErrorNotSupported,
+        _ => {
+            error!("The card returned the status {:02X}{:02X}.", sw1, sw2);
+            ResponseStatus::PsaErrorCommunicationFailure
+        }
+    }
+}
+
+fn push_length(len: usize, out: &mut Vec<u8>) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else if len <= 0xFF {
+        out.extend_from_slice(&[0x81, len as u8]);
+    } else {
+        out.push(0x82);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+}
+
+pub(super) fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut object = vec![tag];
+    push_length(value.len(), &mut object);
+    object.extend_from_slice(value);
+    object
+}
+
+/// Data objects of a BER-TLV encoded sequence, up to the first one not valid.
+fn objects(mut data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (tag, rest) = data.split_first()?;
+        let (len, rest) = match rest.split_first()? {
+            (0x81, rest) => (*rest.first()? as usize, rest.get(1..)?),
+            (0x82, rest) => (
+                u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize,
+                rest.get(2..)?,
+            ),
+            (len, rest) if *len < 0x80 => (*len as usize, rest),
+            _ => return None,
+        };
+        let value = rest.get(..len)?;
+        data = &rest[len..];
+        Some((*tag, value))
+    })
+}
+
+fn find(data: &[u8], tag: u8) -> Option<&[u8]> {
+    objects(data)
+        .find(|(found, _)| *found == tag)
+        .map(|(_, value)| value)
+}
+
+/// APDUs sending a command, chained if its data does not fit in one.
+fn apdus(ins: u8, p1: u8, p2: u8, data: &[u8], expects_data: bool) -> Zeroizing<Vec<Vec<u8>>> {
+    let mut chunks: Vec<&[u8]> = data.chunks(MAX_COMMAND_DATA).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let last = chunks.len() - 1;
+    Zeroizing::new(
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let cla = if index == last {
+                    CLA
+                } else {
+                    CLA | CLA_CHAINING
+                };
+                let mut apdu = vec![cla, ins, p1, p2];
+                if !chunk.is_empty() {
+                    apdu.push(chunk.len() as u8);
+                    apdu.extend_from_slice(chunk);
+                }
+                if index == last && expects_data {
+                    apdu.push(0x00);
+                }
+                apdu
+            })
+            .collect(),
+    )
+}
+
+/// Send a command and return the data of its response.
+fn send(
+    card: &Card,
+    ins: u8,
+    p1: u8,
+    p2: u8,
+    data: &[u8],
+    expects_data: bool,
+) -> CardResult<Zeroizing<Vec<u8>>> {
+    let mut response = Zeroizing::new(Vec::new());
+    for apdu in apdus(ins, p1, p2, data, expects_data).iter() {
+        let mut reply = card.transmit(apdu)?;
+        loop {
+            if reply.len() < 2 {
+                return Err(invalid_response());
+            }
+            let (sw1, sw2) = (reply[reply.len() - 2], reply[reply.len() - 1]);
+            response.extend_from_slice(&reply[..reply.len() - 2]);
+            match (sw1, sw2) {
+                (0x90, 0x00) => break,
+                (0x61, remaining) => {
+                    reply = card.transmit(&[CLA, INS_GET_RESPONSE, 0x00, 0x00, remaining])?
+                }
+                _ => return Err(status_error(sw1, sw2).into()),
+            }
+        }
+    }
+    Ok(response)
+}
+
+/// Select the PIV application, which the other commands are sent to.
+pub(super) fn select(card: &Card) -> CardResult<()> {
+    let _ = send(card, INS_SELECT, 0x04, 0x00, &PIV_AID, true)?;
+    Ok(())
+}
+
+/// Verify the PIN, for the keys to be used afterwards.
+pub(super) fn verify_pin(card: &Card, pin: &[u8]) -> CardResult<()> {
+    if pin.len() > PIN_LEN {
+        error!("The PIV PIN is longer than {} bytes.", PIN_LEN);
+        return Err(ResponseStatus::PsaErrorInvalidArgument.into());
+    }
+    let mut padded = Zeroizing::new([0xFF; PIN_LEN]);
+    padded[..pin.len()].copy_from_slice(pin);
+    let _ = send(card, INS_VERIFY, 0x00, PIN_REFERENCE, &*padded, false)?;
+    Ok(())
+}
+
+fn certificate_object(key_reference: u8) -> Option<[u8; 3]> {
+    match key_reference {
+        KEY_AUTHENTICATION => Some([0x5F, 0xC1, 0x05]),
+        KEY_SIGNATURE => Some([0x5F, 0xC1, 0x0A]),
+        _ => None,
+    }
+}
+
+/// Read the DER-encoded certificate stored for a key.
+pub(super) fn read_certificate(card: &Card, key_reference: u8) -> CardResult<Vec<u8>> {
+    let object = certificate_object(key_reference)
+        .ok_or(CardError::Status(ResponseStatus::PsaErrorInvalidArgument))?;
+    let response = send(
+        card,
+        INS_GET_DATA,
+        0x3F,
+        0xFF,
+        &tlv(TAG_OBJECT_ID, &object),
+        true,
+    )?;
+    let data = find(&response, TAG_DATA).ok_or_else(invalid_response)?;
+    if let Some(info) = find(data, TAG_CERTIFICATE_INFO) {
+        if info.first().copied().unwrap_or(0) != 0 {
+            error!("Compressed certificates are not supported.");
+            return Err(ResponseStatus::PsaErrorNotSupported.into());
+        }
+    }
+    let certificate = find(data, TAG_CERTIFICATE).ok_or_else(invalid_response)?;
+    Ok(certificate.to_vec())
+}
+
+/// Compute with a key: sign the padded digest, or decrypt the ciphertext, given as challenge.
+pub(super) fn general_authenticate(
+    card: &Card,
+    algorithm: u8,
+    key_reference: u8,
+    challenge: &[u8],
+) -> CardResult<Zeroizing<Vec<u8>>> {
+    let mut template = vec![TAG_RESPONSE, 0x00];
+    template.extend_from_slice(&tlv(TAG_CHALLENGE, challenge));
+    let response = send(
+        card,
+        INS_GENERAL_AUTHENTICATE,
+        algorithm,
+        key_reference,
+        &tlv(TAG_DYNAMIC_AUTHENTICATION, &template),
+        true,
+    )?;
+    let result = find(&response, TAG_DYNAMIC_AUTHENTICATION)
+        .and_then(|template| find(template, TAG_RESPONSE))
+        .ok_or_else(invalid_response)?;
+    Ok(Zeroizing::new(result.to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn long_commands_are_chained() {
+        let data = vec![0xAB; 300];
+        let chained = apdus(INS_GENERAL_AUTHENTICATE, 0x07, KEY_SIGNATURE, &data, true);
+        assert_eq!(chained.len(), 2);
+        assert_eq!(chained[0][..5], [0x10, 0x87, 0x07, 0x9C, 0xFF]);
+        assert_eq!(chained[0].len(), 5 + 255);
+        assert_eq!(chained[1][..5], [0x00, 0x87, 0x07, 0x9C, 45]);
+        assert_eq!(chained[1].len(), 5 + 45 + 1);
+        assert_eq!(chained[1].last(), Some(&0x00));
+
+        let single = apdus(INS_VERIFY, 0x00, PIN_REFERENCE, &[0x31; 8], false);
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].len(), 5 + 8);
+    }
+
+    #[test]
+    fn data_objects_are_parsed() {
+        let certificate = vec![0x30; 300];
+        let mut data = tlv(TAG_CERTIFICATE, &certificate);
+        data.extend_from_slice(&tlv(TAG_CERTIFICATE_INFO, &[0x00]));
+        let object = tlv(TAG_DATA, &data);
+        assert_eq!(object[..4], [0x53, 0x82, 0x01, 0x33]);
+
+        let data = find(&object, TAG_DATA).unwrap();
+        assert_eq!(find(data, TAG_CERTIFICATE).unwrap(), &certificate[..]);
+        assert_eq!(find(data, TAG_CERTIFICATE_INFO).unwrap(), &[0x00]);
+        assert_eq!(find(data, TAG_CHALLENGE), None);
+        // Truncated objects are not returned.
+        assert_eq!(find(&object[..100], TAG_DATA), None);
+    }
+}
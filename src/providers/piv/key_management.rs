@@ -0,0 +1,393 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+use super::{card, Provider};
+use crate::authenticators::ApplicationIdentity;
+use crate::key_info_managers::KeyIdentity;
+use crate::utils::config::PivKeyUsage;
+use log::error;
+use parsec_interface::operations::psa_algorithm::{
+    Algorithm, AsymmetricEncryption, AsymmetricSignature, SignHash,
+};
+use parsec_interface::operations::psa_export_public_key;
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, EccFamily, Lifetime, Policy, Type, UsageFlags,
+};
+use parsec_interface::requests::{ResponseStatus, Result};
+use picky_asn1_x509::{Certificate, PublicKey};
+use zeroize::Zeroizing;
+
+/// Attributes of a key of a card and its public part, in the format of PsaExportPublicKey.
+fn key_from_certificate(der: &[u8], usage: PivKeyUsage) -> Result<(Attributes, Vec<u8>)> {
+    let certificate: Certificate = picky_asn1_der::from_bytes(der).map_err(|e| {
+        format_error!("Failed to parse the certificate of a PIV key", e);
+        ResponseStatus::PsaErrorInvalidArgument
+    })?;
+    let mut usage_flags = UsageFlags::default();
+    let (key_type, bits, permitted_algorithms, public_key) = match &certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+    {
+        PublicKey::Rsa(key) => {
+            let bits = key.0.modulus.as_unsigned_bytes_be().len() * 8;
+            let algorithm = match usage {
+                PivKeyUsage::Sign => {
+                    let _ = usage_flags.set_sign_hash().set_sign_message();
+                    Algorithm::AsymmetricSignature(AsymmetricSignature::RsaPkcs1v15Sign {
+                        hash_alg: SignHash::Any,
+                    })
+                }
+                PivKeyUsage::Decrypt => {
+                    let _ = usage_flags.set_decrypt();
+                    Algorithm::AsymmetricEncryption(AsymmetricEncryption::RsaPkcs1v15Crypt)
+                }
+            };
+            let public_key = picky_asn1_der::to_vec(&key.0).map_err(|e| {
+                format_error!("Failed to encode the public key of a PIV key", e);
+                ResponseStatus::PsaErrorInvalidArgument
+            })?;
+            (Type::RsaKeyPair, bits, algorithm, public_key)
+        }
+        PublicKey::Ec(point) => {
+            let point = point.0.payload_view();
+            // Uncompressed points on P-256 and P-384
+            let bits = match point.len() {
+                65 => 256,
+                97 => 384,
+                _ => {
+                    error!("The curve of a PIV key is not supported.");
+                    return Err(ResponseStatus::PsaErrorNotSupported);
+                }
+            };
+            if usage == PivKeyUsage::Decrypt {
+                error!("Only RSA keys of a PIV card can decrypt.");
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+            let _ = usage_flags.set_sign_hash().set_sign_message();
+            (
+                Type::EccKeyPair {
+                    curve_family: EccFamily::SecpR1,
+                },
+                bits,
+                Algorithm::AsymmetricSignature(AsymmetricSignature::Ecdsa {
+                    hash_alg: SignHash::Any,
+                }),
+                point.to_vec(),
+            )
+        }
+        PublicKey::Ed(_) => {
+            error!("The type of a PIV key is not supported.");
+            return Err(ResponseStatus::PsaErrorNotSupported);
+        }
+    };
+    Ok((
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type,
+            bits,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms,
+            },
+        },
+        public_key,
+    ))
+}
+
+/// Algorithm identifier of a key in the PIV commands.
+pub(super) fn piv_algorithm(attributes: Attributes) -> Result<u8> {
+    match (attributes.key_type, attributes.bits) {
+        (Type::RsaKeyPair, 2048) => Ok(card::ALGORITHM_RSA_2048),
+        (Type::RsaKeyPair, 3072) => Ok(card::ALGORITHM_RSA_3072),
+        (Type::RsaKeyPair, 4096) => Ok(card::ALGORITHM_RSA_4096),
+        (Type::EccKeyPair { .. }, 256) => Ok(card::ALGORITHM_ECC_P256),
+        (Type::EccKeyPair { .. }, 384) => Ok(card::ALGORITHM_ECC_P384),
+        _ => {
+            error!(
+                "PIV keys of type {:?} and {} bits are not supported.",
+                attributes.key_type, attributes.bits
+            );
+            Err(ResponseStatus::PsaErrorNotSupported)
+        }
+    }
+}
+
+impl Provider {
+    /// Read the attributes and the public part of the key of a slot, from its certificate.
+    pub(super) fn read_key(
+        &self,
+        key_reference: u8,
+        usage: PivKeyUsage,
+    ) -> Result<(Attributes, Vec<u8>)> {
+        let certificate = self.with_card(|card| card::read_certificate(card, key_reference))?;
+        key_from_certificate(&certificate, usage)
+    }
+
+    /// Key reference and attributes of the key of an application.
+    pub(super) fn get_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        key_name: String,
+    ) -> Result<(KeyIdentity, u8, Attributes)> {
+        let key_identity = self
+            .key_info_store
+            .get_key_identity(application_identity.clone(), key_name);
+        let key_reference = self.key_info_store.get_key_id::<u8>(&key_identity)?;
+        let attributes = self.key_info_store.get_key_attributes(&key_identity)?;
+        Ok((key_identity, key_reference, attributes))
+    }
+
+    pub(super) fn psa_export_public_key_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_public_key::Operation,
+    ) -> Result<psa_export_public_key::Result> {
+        let (key_identity, key_reference, attributes) =
+            self.get_key(application_identity, op.key_name)?;
+        if let Some(public_key) = self.key_info_store.get_public_key(&key_identity) {
+            return Ok(psa_export_public_key::Result {
+                data: Zeroizing::new(public_key),
+            });
+        }
+        // The card was absent when the service started.
+        let usage = if attributes.policy.usage_flags.decrypt() {
+            PivKeyUsage::Decrypt
+        } else {
+            PivKeyUsage::Sign
+        };
+        let (read_attributes, public_key) = self.read_key(key_reference, usage)?;
+        if read_attributes != attributes {
+            error!("The key in the card is not the one mapped when it was last read.");
+            return Err(ResponseStatus::PsaErrorCorruptionDetected);
+        }
+        self.key_info_store
+            .cache_public_key(&key_identity, public_key.clone());
+
+        Ok(psa_export_public_key::Result {
+            data: Zeroizing::new(public_key),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::super::pcsc::fake::state;
+    use super::super::test_card::{
+        application, certificate, certificate_object, provider, provider_with_key, respond,
+        ED25519_CERTIFICATE, KEY_NAME, P256_CERTIFICATE, P256_POINT, RSA_2048_CERTIFICATE,
+    };
+    use super::*;
+    use parsec_interface::requests::AuthType;
+    use picky_asn1_x509::RsaPublicKey;
+
+    #[test]
+    fn ecc_keys_sign() {
+        let (attributes, public_key) =
+            key_from_certificate(&certificate(P256_CERTIFICATE), PivKeyUsage::Sign).unwrap();
+        assert_eq!(
+            attributes.key_type,
+            Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1
+            }
+        );
+        assert_eq!(attributes.bits, 256);
+        assert!(attributes.policy.usage_flags.sign_hash());
+        assert!(attributes.policy.usage_flags.sign_message());
+        assert!(!attributes.policy.usage_flags.decrypt());
+        assert_eq!(
+            attributes.policy.permitted_algorithms,
+            AsymmetricSignature::Ecdsa {
+                hash_alg: SignHash::Any
+            }
+            .into()
+        );
+        assert_eq!(public_key, P256_POINT);
+    }
+
+    #[test]
+    fn ecc_keys_do_not_decrypt() {
+        assert_eq!(
+            key_from_certificate(&certificate(P256_CERTIFICATE), PivKeyUsage::Decrypt).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn rsa_keys_sign() {
+        let (attributes, public_key) =
+            key_from_certificate(&certificate(RSA_2048_CERTIFICATE), PivKeyUsage::Sign).unwrap();
+        assert_eq!(attributes.key_type, Type::RsaKeyPair);
+        assert_eq!(attributes.bits, 2048);
+        assert!(attributes.policy.usage_flags.sign_hash());
+        assert_eq!(
+            attributes.policy.permitted_algorithms,
+            AsymmetricSignature::RsaPkcs1v15Sign {
+                hash_alg: SignHash::Any
+            }
+            .into()
+        );
+        let public_key: RsaPublicKey = picky_asn1_der::from_bytes(&public_key).unwrap();
+        assert_eq!(public_key.modulus.as_unsigned_bytes_be().len(), 256);
+        assert_eq!(
+            public_key.public_exponent.as_unsigned_bytes_be(),
+            [0x01, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn rsa_keys_decrypt() {
+        let (attributes, _) =
+            key_from_certificate(&certificate(RSA_2048_CERTIFICATE), PivKeyUsage::Decrypt).unwrap();
+        assert!(attributes.policy.usage_flags.decrypt());
+        assert!(!attributes.policy.usage_flags.sign_hash());
+        assert_eq!(
+            attributes.policy.permitted_algorithms,
+            AsymmetricEncryption::RsaPkcs1v15Crypt.into()
+        );
+    }
+
+    #[test]
+    fn other_key_types_are_not_supported() {
+        assert_eq!(
+            key_from_certificate(&certificate(ED25519_CERTIFICATE), PivKeyUsage::Sign).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn invalid_certificates_are_refused() {
+        let mut der = certificate(P256_CERTIFICATE);
+        der.truncate(100);
+        assert_eq!(
+            key_from_certificate(&der, PivKeyUsage::Sign).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn algorithms_of_the_supported_keys() {
+        let attributes = |key_type, bits| {
+            let (mut attributes, _) =
+                key_from_certificate(&certificate(P256_CERTIFICATE), PivKeyUsage::Sign).unwrap();
+            attributes.key_type = key_type;
+            attributes.bits = bits;
+            piv_algorithm(attributes)
+        };
+        let ecc = Type::EccKeyPair {
+            curve_family: EccFamily::SecpR1,
+        };
+        assert_eq!(attributes(Type::RsaKeyPair, 2048), Ok(0x07));
+        assert_eq!(attributes(Type::RsaKeyPair, 3072), Ok(0x05));
+        assert_eq!(attributes(Type::RsaKeyPair, 4096), Ok(0x16));
+        assert_eq!(attributes(ecc, 256), Ok(0x11));
+        assert_eq!(attributes(ecc, 384), Ok(0x14));
+        assert_eq!(
+            attributes(Type::RsaKeyPair, 1024),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+        assert_eq!(
+            attributes(ecc, 521),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+
+    #[test]
+    fn certificates_are_read_from_the_slot_objects() {
+        let (_dir, provider) = provider(&[("YubiKey", true)], None);
+        respond(1, &certificate_object(&certificate(P256_CERTIFICATE)));
+        let (_, public_key) = provider
+            .read_key(card::KEY_SIGNATURE, PivKeyUsage::Sign)
+            .unwrap();
+        assert_eq!(public_key, P256_POINT);
+        // After the SELECT of the connection and of the transaction, GET DATA of the object of
+        // the Digital Signature certificate
+        assert_eq!(
+            state(|state| state.sent[2].clone()),
+            [0x00, 0xCB, 0x3F, 0xFF, 0x05, 0x5C, 0x03, 0x5F, 0xC1, 0x0A, 0x00]
+        );
+    }
+
+    #[test]
+    fn mapped_public_keys_are_exported() {
+        let (_dir, provider) = provider_with_key(P256_CERTIFICATE, PivKeyUsage::Sign);
+        let result = provider
+            .psa_export_public_key_internal(
+                &application(),
+                psa_export_public_key::Operation {
+                    key_name: String::from(KEY_NAME),
+                },
+            )
+            .unwrap();
+        assert_eq!(*result.data, P256_POINT);
+        // Without reading the card again
+        assert!(state(|state| state.sent.is_empty()));
+    }
+
+    #[test]
+    fn public_keys_are_read_when_not_cached() {
+        let (_dir, provider) = provider(&[("YubiKey", true)], None);
+        let (attributes, _) =
+            key_from_certificate(&certificate(P256_CERTIFICATE), PivKeyUsage::Sign).unwrap();
+        let key_identity = provider
+            .key_info_store
+            .get_key_identity(application(), String::from(KEY_NAME));
+        provider
+            .key_info_store
+            .insert_key_info(key_identity.clone(), &card::KEY_SIGNATURE, attributes)
+            .unwrap();
+        respond(1, &certificate_object(&certificate(P256_CERTIFICATE)));
+        let result = provider
+            .psa_export_public_key_internal(
+                &application(),
+                psa_export_public_key::Operation {
+                    key_name: String::from(KEY_NAME),
+                },
+            )
+            .unwrap();
+        assert_eq!(*result.data, P256_POINT);
+        assert_eq!(
+            provider.key_info_store.get_public_key(&key_identity),
+            Some(P256_POINT.to_vec())
+        );
+    }
+
+    #[test]
+    fn replaced_keys_are_detected() {
+        let (_dir, provider) = provider(&[("YubiKey", true)], None);
+        let (attributes, _) =
+            key_from_certificate(&certificate(P256_CERTIFICATE), PivKeyUsage::Sign).unwrap();
+        let key_identity = provider
+            .key_info_store
+            .get_key_identity(application(), String::from(KEY_NAME));
+        provider
+            .key_info_store
+            .insert_key_info(key_identity, &card::KEY_SIGNATURE, attributes)
+            .unwrap();
+        respond(1, &certificate_object(&certificate(RSA_2048_CERTIFICATE)));
+        assert_eq!(
+            provider
+                .psa_export_public_key_internal(
+                    &application(),
+                    psa_export_public_key::Operation {
+                        key_name: String::from(KEY_NAME),
+                    },
+                )
+                .unwrap_err(),
+            ResponseStatus::PsaErrorCorruptionDetected
+        );
+    }
+
+    #[test]
+    fn keys_of_other_applications_are_not_found() {
+        let (_dir, provider) = provider_with_key(P256_CERTIFICATE, PivKeyUsage::Sign);
+        assert_eq!(
+            provider
+                .get_key(
+                    &ApplicationIdentity::new(String::from("other"), AuthType::Direct),
+                    String::from(KEY_NAME)
+                )
+                .unwrap_err(),
+            ResponseStatus::PsaErrorDoesNotExist
+        );
+    }
+}
@@ -0,0 +1,679 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! PIV smartcard provider
+//!
+//! This provider uses the keys of a PIV smartcard or USB token, such as a YubiKey, through the
+//! PC/SC library, without any PKCS 11 middleware. The keys are generated or imported in the card
+//! with the tools of its vendor, which hold its management key, and are made available to the
+//! applications by the configuration: each configured slot is mapped to a key name of an
+//! application. The key of slot 9a or 9c is described by the certificate stored next to it.
+//!
+//! The card can be removed and inserted again: the provider connects to it again when the next
+//! operation finds it gone. As PIV cards are also used through PKCS 11 middleware, the provider
+//! serves the PKCS 11 provider ID and can not be configured alongside a PKCS 11 provider.
+use super::Provide;
+use crate::authenticators::ApplicationIdentity;
+use crate::key_info_managers::KeyInfoManagerClient;
+use crate::providers::ProviderIdentity;
+use crate::utils::config::{PivKeyConfig, PivSlot};
+use crate::utils::secrets;
+use card::{CardError, CardResult};
+use derivative::Derivative;
+use log::{error, info, trace, warn};
+use parsec_interface::operations::list_providers::{ProviderInfo, Uuid};
+use parsec_interface::operations::{
+    list_clients, list_keys, psa_asymmetric_decrypt, psa_export_public_key, psa_sign_hash,
+    psa_sign_message,
+};
+use parsec_interface::requests::{AuthType, Opcode, ProviderId, ResponseStatus, Result};
+use pcsc::{Card, Context};
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+mod asym_encryption;
+mod asym_sign;
+mod card;
+mod key_management;
+mod pcsc;
+
+const SUPPORTED_OPCODES: [Opcode; 4] = [
+    Opcode::PsaSignHash,
+    Opcode::PsaSignMessage,
+    Opcode::PsaAsymmetricDecrypt,
+    Opcode::PsaExportPublicKey,
+];
+
+/// PIV provider structure
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Provider {
+    // The identity of the provider including uuid & name.
+    provider_identity: ProviderIdentity,
+    #[derivative(Debug = "ignore")]
+    key_info_store: KeyInfoManagerClient,
+    #[derivative(Debug = "ignore")]
+    context: Context,
+    // Part of the name of the reader to use, any reader with a PIV card otherwise
+    reader: Option<String>,
+    // Card connected on the first operation, and again after it was removed
+    #[derivative(Debug = "ignore")]
+    card: Mutex<Option<Card>>,
+    #[derivative(Debug = "ignore")]
+    pin: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Provider {
+    /// The default provider name for piv provider
+    pub const DEFAULT_PROVIDER_NAME: &'static str = "piv-provider";
+
+    /// The UUID for this provider
+    pub const PROVIDER_UUID: &'static str = "0b1d8a40-3b5f-4c1b-9a2e-7e4f6d2c51a9";
+
+    /// Library loaded when no path is configured
+    pub const DEFAULT_LIBRARY_PATH: &'static str = "libpcsclite.so.1";
+
+    /// Run commands on the PIV application of the card, connecting to it first if needed.
+    ///
+    /// The commands run again once if the card has to be connected again, after it was removed
+    /// or reset.
+    fn with_card<T>(&self, commands: impl Fn(&Card) -> CardResult<T>) -> Result<T> {
+        let mut connected = self.card.lock().expect("Card lock poisoned");
+        let mut reconnected = false;
+        loop {
+            let card = match connected.take() {
+                Some(card) => card,
+                None => {
+                    reconnected = true;
+                    self.connect()?
+                }
+            };
+            match card.transaction(|card| {
+                card::select(card)?;
+                commands(card)
+            }) {
+                Err(CardError::Pcsc(e)) if e.needs_reconnection() && !reconnected => {
+                    warn!("The PIV card was removed or reset, connecting to it again.");
+                }
+                result => {
+                    *connected = Some(card);
+                    return result.map_err(ResponseStatus::from);
+                }
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<Card> {
+        let readers = self.context.readers().map_err(|e| {
+            format_error!("Failed to list the smartcard readers", e);
+            ResponseStatus::PsaErrorHardwareFailure
+        })?;
+        for reader in readers {
+            let name = reader.to_string_lossy().to_string();
+            if let Some(wanted) = &self.reader {
+                if !name.contains(wanted.as_str()) {
+                    continue;
+                }
+            }
+            let card = match self.context.connect(&reader) {
+                Ok(card) => card,
+                Err(e) => {
+                    format_error!(format!("Failed to connect to the card in {}", name), e);
+                    continue;
+                }
+            };
+            match card.transaction(card::select) {
+                Ok(()) => {
+                    info!("Using the PIV card in {}.", name);
+                    return Ok(card);
+                }
+                Err(_) => warn!("The card in {} has no PIV application.", name),
+            }
+        }
+        error!("No PIV card found in the smartcard readers.");
+        Err(ResponseStatus::PsaErrorHardwareFailure)
+    }
+
+    /// Verify the PIN, if configured, before using a key.
+    fn verify_pin(&self, card: &Card) -> CardResult<()> {
+        match &self.pin {
+            Some(pin) => card::verify_pin(card, pin),
+            None => Ok(()),
+        }
+    }
+
+    /// Map the configured keys to their application, from the certificates of their slots.
+    ///
+    /// The mappings of the keys not configured anymore are removed. Those of configured keys
+    /// which can not be read are kept, the card being maybe absent.
+    fn load_keys(&self, keys: &[PivKeyConfig], auth_type: AuthType) -> std::io::Result<()> {
+        let mut configured = HashSet::new();
+        for key in keys {
+            let key_reference = match key.slot {
+                PivSlot::Authentication => card::KEY_AUTHENTICATION,
+                PivSlot::Signature => card::KEY_SIGNATURE,
+            };
+            let key_identity = self.key_info_store.get_key_identity(
+                ApplicationIdentity::new(key.application.clone(), auth_type),
+                key.key_name.clone(),
+            );
+            if !configured.insert(key_identity.clone()) {
+                error!("The key {} is configured twice.", key_identity);
+                return Err(Error::new(ErrorKind::InvalidData, "duplicate PIV key"));
+            }
+            match self.read_key(key_reference, key.usage.unwrap_or_default()) {
+                Ok((attributes, public_key)) => {
+                    self.key_info_store
+                        .insert_key_info(key_identity.clone(), &key_reference, attributes)
+                        .or_else(|e| match e {
+                            ResponseStatus::PsaErrorAlreadyExists => self
+                                .key_info_store
+                                .replace_key_info(key_identity.clone(), &key_reference, attributes),
+                            e => Err(e),
+                        })
+                        .map_err(|e| {
+                            format_error!("Failed to store the mapping of a PIV key", e);
+                            Error::new(ErrorKind::Other, "PIV key not mapped")
+                        })?;
+                    self.key_info_store
+                        .cache_public_key(&key_identity, public_key);
+                    info!("Slot {:02x} holds the key {}.", key_reference, key_identity);
+                }
+                Err(e) => warn!(
+                    "The key of slot {:02x} could not be read ({}), keeping its previous mapping.",
+                    key_reference, e
+                ),
+            }
+        }
+
+        let stored = self.key_info_store.get_all().map_err(|e| {
+            format_error!("Failed to list the mappings of the PIV keys", e);
+            Error::new(ErrorKind::Other, "PIV keys not listed")
+        })?;
+        for key_identity in stored {
+            if !configured.contains(&key_identity) {
+                warn!(
+                    "The key {} is not configured anymore, removing its mapping.",
+                    key_identity
+                );
+                if let Err(e) = self.key_info_store.remove_key_info(&key_identity) {
+                    format_error!("Failed to remove the mapping of a PIV key", e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Provide for Provider {
+    fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+        trace!("describe ingress");
+        Ok((
+            ProviderInfo {
+                // Assigned UUID for this provider: 0b1d8a40-3b5f-4c1b-9a2e-7e4f6d2c51a9
+                uuid: Uuid::parse_str(Provider::PROVIDER_UUID)
+                    .or(Err(ResponseStatus::InvalidEncoding))?,
+                description: String::from(
+                    "PIV provider, using the keys of a PIV smartcard through PC/SC.",
+                ),
+                vendor: String::from("NIST SP 800-73"),
+                version_maj: 0,
+                version_min: 1,
+                version_rev: 0,
+                id: ProviderId::Pkcs11,
+            },
+            SUPPORTED_OPCODES.iter().copied().collect(),
+        ))
+    }
+
+    fn list_keys(
+        &self,
+        application_identity: &ApplicationIdentity,
+        _op: list_keys::Operation,
+    ) -> Result<list_keys::Result> {
+        Ok(list_keys::Result {
+            keys: self.key_info_store.list_keys(application_identity)?,
+        })
+    }
+
+    fn list_clients(&self, _op: list_clients::Operation) -> Result<list_clients::Result> {
+        Ok(list_clients::Result {
+            clients: self
+                .key_info_store
+                .list_clients()?
+                .into_iter()
+                .map(|application_identity| application_identity.name().clone())
+                .collect(),
+        })
+    }
+
+    fn psa_sign_hash(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_hash::Operation,
+    ) -> Result<psa_sign_hash::Result> {
+        trace!("psa_sign_hash ingress");
+        self.psa_sign_hash_internal(application_identity, op)
+    }
+
+    fn psa_sign_message(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_message::Operation,
+    ) -> Result<psa_sign_message::Result> {
+        trace!("psa_sign_message ingress");
+        self.psa_sign_message_internal(application_identity, op)
+    }
+
+    fn psa_asymmetric_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_asymmetric_decrypt::Operation,
+    ) -> Result<psa_asymmetric_decrypt::Result> {
+        trace!("psa_asymmetric_decrypt ingress");
+        self.psa_asymmetric_decrypt_internal(application_identity, op)
+    }
+
+    fn psa_export_public_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_public_key::Operation,
+    ) -> Result<psa_export_public_key::Result> {
+        trace!("psa_export_public_key ingress");
+        self.psa_export_public_key_internal(application_identity, op)
+    }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        Some(&self.key_info_store)
+    }
+}
+
+/// PIV Provider builder
+#[derive(Default, Derivative)]
+#[derivative(Debug)]
+pub struct ProviderBuilder {
+    provider_name: Option<String>,
+    #[derivative(Debug = "ignore")]
+    key_info_store: Option<KeyInfoManagerClient>,
+    library_path: Option<String>,
+    reader: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pin: Option<Zeroizing<String>>,
+    keys: Vec<PivKeyConfig>,
+    auth_type: Option<AuthType>,
+}
+
+impl ProviderBuilder {
+    /// Create a new PIV provider builder
+    pub fn new() -> ProviderBuilder {
+        ProviderBuilder {
+            provider_name: None,
+            key_info_store: None,
+            library_path: None,
+            reader: None,
+            pin: None,
+            keys: Vec::new(),
+            auth_type: None,
+        }
+    }
+
+    /// Add a provider name
+    pub fn with_provider_name(mut self, provider_name: String) -> ProviderBuilder {
+        self.provider_name = Some(provider_name);
+
+        self
+    }
+
+    /// Add a KeyInfo manager
+    pub fn with_key_info_store(mut self, key_info_store: KeyInfoManagerClient) -> ProviderBuilder {
+        self.key_info_store = Some(key_info_store);
+
+        self
+    }
+
+    /// Specify the path of the PC/SC library
+    pub fn with_library_path(mut self, library_path: String) -> ProviderBuilder {
+        self.library_path = Some(library_path);
+
+        self
+    }
+
+    /// Specify part of the name of the reader holding the card
+    pub fn with_reader(mut self, reader: Option<String>) -> ProviderBuilder {
+        self.reader = reader;
+
+        self
+    }
+
+    /// Specify the PIN of the card, or a reference to the secret holding it
+    pub fn with_pin(mut self, pin: Option<String>) -> ProviderBuilder {
+        self.pin = pin.map(Zeroizing::new);
+
+        self
+    }
+
+    /// Specify the keys of the card slots made available to the applications
+    pub fn with_keys(mut self, keys: Vec<PivKeyConfig>) -> ProviderBuilder {
+        self.keys = keys;
+
+        self
+    }
+
+    /// Specify the authenticator type of the applications owning the keys
+    pub fn with_auth_type(mut self, auth_type: AuthType) -> ProviderBuilder {
+        self.auth_type = Some(auth_type);
+
+        self
+    }
+
+    /// Attempt to build PIV Provider
+    pub fn build(self) -> std::io::Result<Provider> {
+        let pin = match self.pin {
+            Some(pin) => Some(match secrets::resolve(&pin)? {
+                Some(secret) => secret,
+                None => Zeroizing::new(pin.as_bytes().to_vec()),
+            }),
+            None => {
+                warn!("No PIN is configured: the card has to allow using its keys without it.");
+                None
+            }
+        };
+        let library_path = self
+            .library_path
+            .unwrap_or_else(|| String::from(Provider::DEFAULT_LIBRARY_PATH));
+        let provider = Provider {
+            provider_identity: ProviderIdentity {
+                name: self
+                    .provider_name
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing provider name"))?,
+                uuid: String::from(Provider::PROVIDER_UUID),
+            },
+            key_info_store: self
+                .key_info_store
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing key info store"))?,
+            context: Context::new(&library_path)?,
+            reader: self.reader,
+            card: Mutex::new(None),
+            pin,
+        };
+        provider.load_keys(
+            &self.keys,
+            self.auth_type
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing auth type"))?,
+        )?;
+        Ok(provider)
+    }
+}
+
+/// Provider on the fake PC/SC library, and the data of the test cards
+#[cfg(all(test, feature = "sqlite-kim"))]
+pub(super) mod test_card {
+    use super::pcsc::fake::{self, state};
+    use super::{card, Provider};
+    use crate::authenticators::ApplicationIdentity;
+    use crate::key_info_managers::KeyInfoManagerFactory;
+    use crate::providers::ProviderIdentity;
+    use crate::utils::config::{
+        KeyInfoManagerConfig, KeyInfoManagerType, PivKeyConfig, PivKeyUsage, PivSlot,
+    };
+    use base64::Engine;
+    use parsec_interface::requests::AuthType;
+    use std::sync::Mutex;
+
+    /// Self-signed certificate of a P-256 key
+    pub(super) const P256_CERTIFICATE: &str = concat!(
+        "MIIBczCCARmgAwIBAgIUG4LwrGMX4qasWHLkUW9xM9Xuv44wCgYIKoZIzj0EAwIwDzENMAsGA1UEAwwEcDI1",
+        "NjAeFw0yNjEwMTQxNjM0MjVaFw0zNjEwMTExNjM0MjVaMA8xDTALBgNVBAMMBHAyNTYwWTATBgcqhkjOPQIB",
+        "BggqhkjOPQMBBwNCAARp3owrZY28UpjmKbimcnd9GIz6rJcaFWbD6SOkwCCJiqMwv252cvHsHdGLrPMTdbvG",
+        "QWL5MFk5NawpsF288TEXo1MwUTAdBgNVHQ4EFgQUIjR1IaG/HWf3UlPPT9UsCmDqKqEwHwYDVR0jBBgwFoAU",
+        "IjR1IaG/HWf3UlPPT9UsCmDqKqEwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiAdyGT2/63A",
+        "B69qzUS+1LXfWA6BTGAwq2utVHXp51sUMwIhALdSVKRhkfQ9ezNTEr7YUJ0Jk/5Wo/pMHz+lDd2yF/i0",
+    );
+    /// Uncompressed point of the key of `P256_CERTIFICATE`
+    pub(super) const P256_POINT: [u8; 65] = [
+        0x04, 0x69, 0xde, 0x8c, 0x2b, 0x65, 0x8d, 0xbc, 0x52, 0x98, 0xe6, 0x29, 0xb8, 0xa6, 0x72,
+        0x77, 0x7d, 0x18, 0x8c, 0xfa, 0xac, 0x97, 0x1a, 0x15, 0x66, 0xc3, 0xe9, 0x23, 0xa4, 0xc0,
+        0x20, 0x89, 0x8a, 0xa3, 0x30, 0xbf, 0x6e, 0x76, 0x72, 0xf1, 0xec, 0x1d, 0xd1, 0x8b, 0xac,
+        0xf3, 0x13, 0x75, 0xbb, 0xc6, 0x41, 0x62, 0xf9, 0x30, 0x59, 0x39, 0x35, 0xac, 0x29, 0xb0,
+        0x5d, 0xbc, 0xf1, 0x31, 0x17,
+    ];
+    /// Self-signed certificate of a 2048-bit RSA key, of public exponent 65537
+    pub(super) const RSA_2048_CERTIFICATE: &str = concat!(
+        "MIIC/TCCAeWgAwIBAgIUFGXQszHpkEtZu2bfbKqLmFK4jSIwDQYJKoZIhvcNAQELBQAwDjEMMAoGA1UEAwwD",
+        "cnNhMB4XDTI2MTAxNDE2MzQyNVoXDTM2MTAxMTE2MzQyNVowDjEMMAoGA1UEAwwDcnNhMIIBIjANBgkqhkiG",
+        "9w0BAQEFAAOCAQ8AMIIBCgKCAQEAzWw0yacS9oGpdRjdjjpXSEjD7UUr/lGLW/iHQH1+/jAP/5/WO8SuIKbN",
+        "EfGD8iTmLCAPX94lt0qp3JgOl/263VG0QWCpvFy455ITlzgTkkwDU3POeT3hZBRH3xxePN9IoGmGMjECpZYd",
+        "2DDzuB0JpdK8qKNfnHL/A2Xb2tksujGm41Uo6CqRZ0azKXcWXaPlIwz3IdRnvKaOgfirLCeeDoUphUX7q4EM",
+        "BpoLFFcg0PwO6UfDKB08mvKpXmUjqiYcAhu2aPqHNY7w104KcjrJO+8jaNlKb3Sr1PsafR/l+9ZmiOocHUF9",
+        "IrzTlygk0TdXAjPZFnow7Tn/eA2t6PLIQwIDAQABo1MwUTAdBgNVHQ4EFgQUY/TXE1stmI5sRJAgSGE2ZQXZ",
+        "0VswHwYDVR0jBBgwFoAUY/TXE1stmI5sRJAgSGE2ZQXZ0VswDwYDVR0TAQH/BAUwAwEB/zANBgkqhkiG9w0B",
+        "AQsFAAOCAQEAJyPAw23XkzgFl0SjN4+KN1jZUVXDDQpTX4cib5/lz+t8n3CgkMw6S6k8csBKEvOnPJ4D1WIt",
+        "vX/kNAAVxufoK0OZJERAF4nb2rG2TKIxilwWdP9l0m1u1NUFd5Tcm6gY4O09ArxBYU0nEsCpEe9FCvBmTLtJ",
+        "Zmtig0n6AIvJa+02CfZnC8yoR6Q1YgWq3RA1phKmjZT6ZCfFiO8fCuC8QjqjXJukoFAOp9ezUCYzVSrFnx+5",
+        "fBBE6M5xn9gvdIHZFaWNoCFjyj9z9yWPLqgLh4J0F/Hd/3KyFVZycjy15L/mRIXlmtcQsjrz52tVYv3JN2Zi",
+        "AaIzJs/cmfLv+HASHQ==",
+    );
+    /// Self-signed certificate of an Ed25519 key
+    pub(super) const ED25519_CERTIFICATE: &str = concat!(
+        "MIIBLjCB4aADAgECAhRLtSLv6hXs8focJxpU7PUu3yD3LDAFBgMrZXAwDTELMAkGA1UEAwwCZWQwHhcNMjYx",
+        "MDE0MTYzNDI1WhcNMzYxMDExMTYzNDI1WjANMQswCQYDVQQDDAJlZDAqMAUGAytlcAMhACj9bPh4Rm8OjkAQ",
+        "SS2UvWVPEuUH/wBiInsxmmLzYFuIo1MwUTAdBgNVHQ4EFgQUnOQhRd9rs+n4153xGSg9gOAU0REwHwYDVR0j",
+        "BBgwFoAUnOQhRd9rs+n4153xGSg9gOAU0REwDwYDVR0TAQH/BAUwAwEB/zAFBgMrZXADQQCBNPnwq9kv0m8x",
+        "Js+NAQ6XxFGafM6j8NSUKM8dO5+erH5txht163XSoO2mIZTupCIW6JyY7FGlNrYpWvTWEFIO",
+    );
+
+    /// Name of the key of the slot 9c, mapped by `provider_with_key`
+    pub(super) const KEY_NAME: &str = "card-key";
+
+    /// DER encoding of a certificate given in base64
+    pub(super) fn certificate(base64: &str) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD
+            .decode(base64)
+            .unwrap()
+    }
+
+    /// Application owning the key mapped by `provider_with_key`
+    pub(super) fn application() -> ApplicationIdentity {
+        ApplicationIdentity::new(String::from("app"), AuthType::Direct)
+    }
+
+    /// Provider on a fake PC/SC library with the given readers and whether they hold a PIV card.
+    pub(super) fn provider(
+        readers: &[(&'static str, bool)],
+        reader: Option<&str>,
+    ) -> (tempfile::TempDir, Provider) {
+        let dir = tempfile::tempdir().unwrap();
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(dir.path().join("piv.sqlite3").to_str().unwrap().into()),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap();
+        let provider_identity = ProviderIdentity::new(
+            Provider::PROVIDER_UUID.to_string(),
+            Provider::DEFAULT_PROVIDER_NAME.to_string(),
+        );
+        let provider = Provider {
+            key_info_store: factory.build_client(provider_identity.clone()),
+            provider_identity,
+            context: fake::context(readers),
+            reader: reader.map(str::to_string),
+            card: Mutex::new(None),
+            pin: None,
+        };
+        (dir, provider)
+    }
+
+    /// Provider on a single card whose slot 9c holds the key of the certificate, mapped to
+    /// `KEY_NAME` of `application()` for the given use.
+    pub(super) fn provider_with_key(
+        certificate_base64: &str,
+        usage: PivKeyUsage,
+    ) -> (tempfile::TempDir, Provider) {
+        let (dir, provider) = provider(&[("YubiKey", true)], None);
+        respond(1, &certificate_object(&certificate(certificate_base64)));
+        provider
+            .load_keys(
+                &[PivKeyConfig {
+                    slot: PivSlot::Signature,
+                    key_name: String::from(KEY_NAME),
+                    application: application().name().clone(),
+                    usage: Some(usage),
+                }],
+                AuthType::Direct,
+            )
+            .unwrap();
+        state(|state| state.sent.clear());
+        (dir, provider)
+    }
+
+    /// Queue the response of the card to a command sent in the given number of chained APDUs,
+    /// in parts fetched with GET RESPONSE when it is longer than a short response.
+    pub(super) fn respond(apdus: usize, data: &[u8]) {
+        state(|state| {
+            for _ in 1..apdus {
+                state.responses.push_back(vec![0x90, 0x00]);
+            }
+            let mut parts = data.chunks(256).peekable();
+            if parts.peek().is_none() {
+                state.responses.push_back(vec![0x90, 0x00]);
+            }
+            while let Some(part) = parts.next() {
+                let mut response = part.to_vec();
+                match parts.peek() {
+                    Some(next) => response.extend_from_slice(&[0x61, next.len() as u8]),
+                    None => response.extend_from_slice(&[0x90, 0x00]),
+                }
+                state.responses.push_back(response);
+            }
+        });
+    }
+
+    /// Data object of an uncompressed certificate, as stored in the card
+    pub(super) fn certificate_object(certificate: &[u8]) -> Vec<u8> {
+        let mut data = card::tlv(0x70, certificate);
+        data.extend_from_slice(&card::tlv(0x71, &[0x00]));
+        card::tlv(0x53, &data)
+    }
+
+    /// Response of GENERAL AUTHENTICATE with the result of the computation
+    pub(super) fn authentication_response(result: &[u8]) -> Vec<u8> {
+        card::tlv(0x7C, &card::tlv(0x82, result))
+    }
+
+    /// Data of the last GENERAL AUTHENTICATE sent, in its chained APDUs
+    pub(super) fn authentication_challenge() -> Vec<u8> {
+        state(|state| {
+            state
+                .sent
+                .iter()
+                .filter(|apdu| apdu[1] == 0x87)
+                .flat_map(|apdu| apdu[5..5 + usize::from(apdu[4])].to_vec())
+                .collect()
+        })
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::card;
+    use super::pcsc::fake::state;
+    use super::test_card::provider;
+    use parsec_interface::requests::ResponseStatus;
+    use std::sync::Mutex;
+
+    #[test]
+    fn card_is_connected_once() {
+        let (_dir, provider) = provider(&[("YubiKey", true)], None);
+        provider.with_card(|_| Ok(())).unwrap();
+        provider.with_card(|_| Ok(())).unwrap();
+        assert_eq!(state(|state| state.connected.clone()), ["YubiKey"]);
+        // The PIV application is selected in each transaction.
+        assert_eq!(state(|state| state.sent.len()), 3);
+    }
+
+    #[test]
+    fn readers_without_piv_card_are_skipped() {
+        let (_dir, provider) = provider(&[("Reader A", false), ("Reader B", true)], None);
+        provider.with_card(|_| Ok(())).unwrap();
+        assert_eq!(
+            state(|state| state.connected.clone()),
+            ["Reader A", "Reader B"]
+        );
+    }
+
+    #[test]
+    fn configured_reader_is_used() {
+        let (_dir, provider) = provider(
+            &[("Yubico YubiKey 00 00", true), ("Nitrokey 01 00", true)],
+            Some("Nitrokey"),
+        );
+        provider.with_card(|_| Ok(())).unwrap();
+        assert_eq!(state(|state| state.connected.clone()), ["Nitrokey 01 00"]);
+    }
+
+    #[test]
+    fn no_piv_card() {
+        let (_dir, provider) = provider(&[("Reader", false)], None);
+        assert_eq!(
+            provider.with_card(|_| Ok(())).unwrap_err(),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+    }
+
+    #[test]
+    fn removed_card_is_connected_again() {
+        let (_dir, provider) = provider(&[("YubiKey", true)], None);
+        provider.with_card(|_| Ok(())).unwrap();
+        state(|state| state.removals = 1);
+        let runs = Mutex::new(0);
+        provider
+            .with_card(|card| {
+                *runs.lock().unwrap() += 1;
+                let _ = card.transmit(&[0x00, 0xCB, 0x3F, 0xFF])?;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(*runs.lock().unwrap(), 1);
+        assert_eq!(
+            state(|state| state.connected.clone()),
+            ["YubiKey", "YubiKey"]
+        );
+    }
+
+    #[test]
+    fn card_is_connected_again_only_once() {
+        let (_dir, provider) = provider(&[("YubiKey", true)], None);
+        provider.with_card(|_| Ok(())).unwrap();
+        state(|state| state.removals = 2);
+        assert_eq!(
+            provider.with_card(|_| Ok(())).unwrap_err(),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+        assert_eq!(
+            state(|state| state.connected.clone()),
+            ["YubiKey", "YubiKey"]
+        );
+    }
+
+    #[test]
+    fn card_errors_are_returned() {
+        let (_dir, provider) = provider(&[("YubiKey", true)], None);
+        // Wrong PIN, two tries left
+        state(|state| state.responses.push_back(vec![0x63, 0xC2]));
+        assert_eq!(
+            provider
+                .with_card(|card| card::verify_pin(card, b"123456"))
+                .unwrap_err(),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        assert_eq!(state(|state| state.connected.len()), 1);
+    }
+}
@@ -0,0 +1,555 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Access to the smartcard readers through the PC/SC API
+//!
+//! The PC/SC library, pcsc-lite on Linux, is loaded when the provider is built rather than linked
+//! with the service, so that Parsec can be built without it. Its types are the ones of pcsc-lite,
+//! where `LONG` and `DWORD` are the C `long` and `unsigned long`.
+use libloading::Library;
+use parsec_interface::requests::ResponseStatus;
+use std::ffi::{c_void, CStr, CString};
+use std::mem::size_of;
+use std::os::raw::{c_char, c_long, c_ulong};
+use std::ptr;
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+type Long = c_long;
+type Dword = c_ulong;
+
+const SCARD_S_SUCCESS: Long = 0;
+const SCARD_SCOPE_SYSTEM: Dword = 2;
+const SCARD_SHARE_SHARED: Dword = 2;
+const SCARD_PROTOCOL_T0: Dword = 1;
+const SCARD_PROTOCOL_T1: Dword = 2;
+const SCARD_LEAVE_CARD: Dword = 0;
+
+// Errors after which the card has to be connected again
+const SCARD_E_INVALID_HANDLE: u32 = 0x8010_0003;
+const SCARD_E_NO_SMARTCARD: u32 = 0x8010_000C;
+const SCARD_W_REMOVED_CARD: u32 = 0x8010_0069;
+const SCARD_W_RESET_CARD: u32 = 0x8010_0068;
+const SCARD_E_READER_UNAVAILABLE: u32 = 0x8010_0017;
+
+/// Largest response to a short APDU: 256 bytes of data and the status word
+const MAX_RESPONSE_LEN: usize = 258;
+
+#[repr(C)]
+struct ScardIoRequest {
+    protocol: Dword,
+    pci_length: Dword,
+}
+
+type EstablishContext =
+    unsafe extern "C" fn(Dword, *const c_void, *const c_void, *mut Long) -> Long;
+type ReleaseContext = unsafe extern "C" fn(Long) -> Long;
+type ListReaders = unsafe extern "C" fn(Long, *const c_char, *mut c_char, *mut Dword) -> Long;
+type Connect =
+    unsafe extern "C" fn(Long, *const c_char, Dword, Dword, *mut Long, *mut Dword) -> Long;
+type Disconnect = unsafe extern "C" fn(Long, Dword) -> Long;
+type BeginTransaction = unsafe extern "C" fn(Long) -> Long;
+type EndTransaction = unsafe extern "C" fn(Long, Dword) -> Long;
+type Transmit = unsafe extern "C" fn(
+    Long,
+    *const ScardIoRequest,
+    *const u8,
+    Dword,
+    *mut ScardIoRequest,
+    *mut u8,
+    *mut Dword,
+) -> Long;
+
+/// Functions of the PC/SC library
+struct Functions {
+    // Kept loaded as long as the functions are used, absent for the fake library of the tests.
+    _library: Option<Library>,
+    establish_context: EstablishContext,
+    release_context: ReleaseContext,
+    list_readers: ListReaders,
+    connect: Connect,
+    disconnect: Disconnect,
+    begin_transaction: BeginTransaction,
+    end_transaction: EndTransaction,
+    transmit: Transmit,
+}
+
+/// Error returned by a PC/SC function
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct PcscError(u32);
+
+impl PcscError {
+    /// Whether the card was removed or reset, and has to be connected again
+    pub(super) fn needs_reconnection(self) -> bool {
+        matches!(
+            self.0,
+            SCARD_E_INVALID_HANDLE
+                | SCARD_E_NO_SMARTCARD
+                | SCARD_W_REMOVED_CARD
+                | SCARD_W_RESET_CARD
+                | SCARD_E_READER_UNAVAILABLE
+        )
+    }
+}
+
+impl std::fmt::Display for PcscError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PC/SC error 0x{:08X}", self.0)
+    }
+}
+
+impl From<PcscError> for ResponseStatus {
+    fn from(error: PcscError) -> Self {
+        format_error!("PC/SC call failed", error);
+        if error.needs_reconnection() {
+            ResponseStatus::PsaErrorHardwareFailure
+        } else {
+            ResponseStatus::PsaErrorCommunicationFailure
+        }
+    }
+}
+
+fn check(rv: Long) -> Result<(), PcscError> {
+    if rv == SCARD_S_SUCCESS {
+        Ok(())
+    } else {
+        // The codes are 32 bits values, sign-extended by some implementations.
+        Err(PcscError(rv as u32))
+    }
+}
+
+/// PC/SC context, from which the readers are listed and the cards connected
+pub(super) struct Context {
+    functions: Arc<Functions>,
+    handle: Long,
+}
+
+impl Context {
+    /// Load the PC/SC library and establish a context with the resource manager.
+    pub(super) fn new(library_path: &str) -> std::io::Result<Self> {
+        // Safety: loading the library runs its initialisation code, which is the one of the
+        // system PC/SC library configured by the administrator.
+        let functions = unsafe { load(library_path) }.map_err(|e| {
+            format_error!(
+                format!("Failed to load the PC/SC library {}", library_path),
+                e
+            );
+            std::io::Error::new(std::io::ErrorKind::NotFound, "PC/SC library not loaded")
+        })?;
+        Context::establish(functions)
+    }
+
+    fn establish(functions: Functions) -> std::io::Result<Self> {
+        let mut handle = 0;
+        // Safety: the output pointer is valid for the call.
+        check(unsafe {
+            (functions.establish_context)(SCARD_SCOPE_SYSTEM, ptr::null(), ptr::null(), &mut handle)
+        })
+        .map_err(|e| {
+            format_error!("Failed to establish the PC/SC context", e);
+            std::io::Error::new(std::io::ErrorKind::Other, "no PC/SC context")
+        })?;
+        Ok(Context {
+            functions: Arc::new(functions),
+            handle,
+        })
+    }
+
+    /// Names of the readers known to the resource manager.
+    pub(super) fn readers(&self) -> Result<Vec<CString>, PcscError> {
+        let mut len: Dword = 0;
+        // Safety: a null buffer asks for the length of the list.
+        check(unsafe {
+            (self.functions.list_readers)(self.handle, ptr::null(), ptr::null_mut(), &mut len)
+        })?;
+        let mut buffer = vec![0u8; len as usize];
+        // Safety: the buffer is as long as given.
+        check(unsafe {
+            (self.functions.list_readers)(
+                self.handle,
+                ptr::null(),
+                buffer.as_mut_ptr() as *mut c_char,
+                &mut len,
+            )
+        })?;
+        // The names are separated by NUL characters, the list ending with an empty one.
+        Ok(buffer
+            .split(|byte| *byte == 0)
+            .take_while(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect())
+    }
+
+    /// Connect to the card in a reader, shared with the other applications.
+    pub(super) fn connect(&self, reader: &CStr) -> Result<Card, PcscError> {
+        let mut handle = 0;
+        let mut protocol: Dword = 0;
+        // Safety: the reader name is NUL-terminated and the output pointers valid for the call.
+        check(unsafe {
+            (self.functions.connect)(
+                self.handle,
+                reader.as_ptr(),
+                SCARD_SHARE_SHARED,
+                SCARD_PROTOCOL_T0 | SCARD_PROTOCOL_T1,
+                &mut handle,
+                &mut protocol,
+            )
+        })?;
+        Ok(Card {
+            functions: self.functions.clone(),
+            handle,
+            protocol,
+        })
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // Safety: the context was established and is not used afterwards.
+        if let Err(e) = check(unsafe { (self.functions.release_context)(self.handle) }) {
+            format_error!("Failed to release the PC/SC context", e);
+        }
+    }
+}
+
+/// Card connected in a reader
+pub(super) struct Card {
+    functions: Arc<Functions>,
+    handle: Long,
+    protocol: Dword,
+}
+
+impl Card {
+    /// Run commands while no other application can use the card.
+    pub(super) fn transaction<T, E: From<PcscError>>(
+        &self,
+        commands: impl FnOnce(&Card) -> Result<T, E>,
+    ) -> Result<T, E> {
+        // Safety: the card handle is connected.
+        check(unsafe { (self.functions.begin_transaction)(self.handle) })?;
+        let result = commands(self);
+        // Safety: the transaction was begun on this handle.
+        if let Err(e) =
+            check(unsafe { (self.functions.end_transaction)(self.handle, SCARD_LEAVE_CARD) })
+        {
+            format_error!("Failed to end the PC/SC transaction", e);
+        }
+        result
+    }
+
+    /// Send an APDU to the card and return its response, status word included.
+    pub(super) fn transmit(&self, apdu: &[u8]) -> Result<Zeroizing<Vec<u8>>, PcscError> {
+        let send_pci = ScardIoRequest {
+            protocol: self.protocol,
+            pci_length: size_of::<ScardIoRequest>() as Dword,
+        };
+        let mut response = Zeroizing::new(vec![0u8; MAX_RESPONSE_LEN]);
+        let mut len = response.len() as Dword;
+        // Safety: the buffers are as long as given and the PCI structure valid for the call.
+        check(unsafe {
+            (self.functions.transmit)(
+                self.handle,
+                &send_pci,
+                apdu.as_ptr(),
+                apdu.len() as Dword,
+                ptr::null_mut(),
+                response.as_mut_ptr(),
+                &mut len,
+            )
+        })?;
+        response.truncate(len as usize);
+        Ok(response)
+    }
+}
+
+impl Drop for Card {
+    fn drop(&mut self) {
+        // Safety: the card was connected and is not used afterwards.
+        let _ = unsafe { (self.functions.disconnect)(self.handle, SCARD_LEAVE_CARD) };
+    }
+}
+
+// Safety: the PC/SC handles can be used from any thread, the provider serializes their use.
+unsafe impl Send for Context {}
+unsafe impl Sync for Context {}
+unsafe impl Send for Card {}
+
+unsafe fn load(library_path: &str) -> Result<Functions, libloading::Error> {
+    let library = Library::new(library_path)?;
+    let establish_context = *library.get::<EstablishContext>(b"SCardEstablishContext\0")?;
+    let release_context = *library.get::<ReleaseContext>(b"SCardReleaseContext\0")?;
+    let list_readers = *library.get::<ListReaders>(b"SCardListReaders\0")?;
+    let connect = *library.get::<Connect>(b"SCardConnect\0")?;
+    let disconnect = *library.get::<Disconnect>(b"SCardDisconnect\0")?;
+    let begin_transaction = *library.get::<BeginTransaction>(b"SCardBeginTransaction\0")?;
+    let end_transaction = *library.get::<EndTransaction>(b"SCardEndTransaction\0")?;
+    let transmit = *library.get::<Transmit>(b"SCardTransmit\0")?;
+    Ok(Functions {
+        _library: Some(library),
+        establish_context,
+        release_context,
+        list_readers,
+        connect,
+        disconnect,
+        begin_transaction,
+        end_transaction,
+        transmit,
+    })
+}
+
+/// PC/SC library of the tests, with the readers and cards of the calling thread
+#[cfg(test)]
+pub(super) mod fake {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Readers and cards seen by the PC/SC calls of the current thread
+    #[derive(Default)]
+    pub(in super::super) struct State {
+        /// Name of each reader and whether its card has the PIV application
+        pub readers: Vec<(&'static str, bool)>,
+        /// Responses to the commands after SELECT, `90 00` once they are all sent
+        pub responses: VecDeque<Vec<u8>>,
+        /// Number of the next transmissions which find the card removed
+        pub removals: usize,
+        /// Readers connected to, in order
+        pub connected: Vec<&'static str>,
+        /// Commands sent to the cards
+        pub sent: Vec<Vec<u8>>,
+        pub transactions: usize,
+        pub ended_transactions: usize,
+        pub released: bool,
+    }
+
+    thread_local! {
+        static STATE: RefCell<State> = RefCell::new(State::default());
+    }
+
+    /// Access the state of the current thread.
+    pub(in super::super) fn state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+        STATE.with(|state| f(&mut state.borrow_mut()))
+    }
+
+    /// Establish a context with the fake library, with the given readers.
+    pub(in super::super) fn context(readers: &[(&'static str, bool)]) -> Context {
+        state(|state| {
+            *state = State {
+                readers: readers.to_vec(),
+                ..Default::default()
+            }
+        });
+        Context::establish(Functions {
+            _library: None,
+            establish_context,
+            release_context,
+            list_readers,
+            connect,
+            disconnect,
+            begin_transaction,
+            end_transaction,
+            transmit,
+        })
+        .unwrap()
+    }
+
+    unsafe extern "C" fn establish_context(
+        _scope: Dword,
+        _reserved_1: *const c_void,
+        _reserved_2: *const c_void,
+        context: *mut Long,
+    ) -> Long {
+        *context = 1;
+        SCARD_S_SUCCESS
+    }
+
+    unsafe extern "C" fn release_context(_context: Long) -> Long {
+        state(|state| state.released = true);
+        SCARD_S_SUCCESS
+    }
+
+    unsafe extern "C" fn list_readers(
+        _context: Long,
+        _groups: *const c_char,
+        buffer: *mut c_char,
+        len: *mut Dword,
+    ) -> Long {
+        let mut list = Vec::new();
+        for (name, _) in state(|state| state.readers.clone()) {
+            list.extend_from_slice(name.as_bytes());
+            list.push(0);
+        }
+        list.push(0);
+        if !buffer.is_null() {
+            ptr::copy_nonoverlapping(list.as_ptr(), buffer as *mut u8, list.len());
+        }
+        *len = list.len() as Dword;
+        SCARD_S_SUCCESS
+    }
+
+    unsafe extern "C" fn connect(
+        _context: Long,
+        reader: *const c_char,
+        _share_mode: Dword,
+        _protocols: Dword,
+        card: *mut Long,
+        protocol: *mut Dword,
+    ) -> Long {
+        let reader = CStr::from_ptr(reader).to_str().unwrap();
+        state(|state| {
+            let index = state
+                .readers
+                .iter()
+                .position(|(name, _)| *name == reader)
+                .unwrap();
+            state.connected.push(state.readers[index].0);
+            *card = index as Long;
+        });
+        *protocol = SCARD_PROTOCOL_T1;
+        SCARD_S_SUCCESS
+    }
+
+    unsafe extern "C" fn disconnect(_card: Long, _disposition: Dword) -> Long {
+        SCARD_S_SUCCESS
+    }
+
+    unsafe extern "C" fn begin_transaction(_card: Long) -> Long {
+        state(|state| state.transactions += 1);
+        SCARD_S_SUCCESS
+    }
+
+    unsafe extern "C" fn end_transaction(_card: Long, _disposition: Dword) -> Long {
+        state(|state| state.ended_transactions += 1);
+        SCARD_S_SUCCESS
+    }
+
+    unsafe extern "C" fn transmit(
+        card: Long,
+        _send_pci: *const ScardIoRequest,
+        apdu: *const u8,
+        apdu_len: Dword,
+        _receive_pci: *mut ScardIoRequest,
+        response: *mut u8,
+        response_len: *mut Dword,
+    ) -> Long {
+        let apdu = std::slice::from_raw_parts(apdu, apdu_len as usize).to_vec();
+        let answer = state(|state| {
+            if state.removals > 0 {
+                state.removals -= 1;
+                return None;
+            }
+            let answer = if apdu[1] == 0xA4 {
+                if state.readers[card as usize].1 {
+                    vec![0x90, 0x00]
+                } else {
+                    vec![0x6A, 0x82]
+                }
+            } else {
+                state
+                    .responses
+                    .pop_front()
+                    .unwrap_or_else(|| vec![0x90, 0x00])
+            };
+            state.sent.push(apdu);
+            Some(answer)
+        });
+        match answer {
+            Some(answer) => {
+                assert!(answer.len() <= *response_len as usize);
+                ptr::copy_nonoverlapping(answer.as_ptr(), response, answer.len());
+                *response_len = answer.len() as Dword;
+                SCARD_S_SUCCESS
+            }
+            // Sign-extended, as by pcsc-lite
+            None => SCARD_W_REMOVED_CARD as i32 as Long,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::fake::{self, state};
+    use super::{Context, PcscError, SCARD_W_REMOVED_CARD};
+    use parsec_interface::requests::ResponseStatus;
+    use std::ffi::CString;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn missing_library() {
+        assert_eq!(
+            Context::new("/nonexistent/libpcsclite.so.1")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn readers_are_listed() {
+        let context =
+            fake::context(&[("Yubico YubiKey CCID 00 00", true), ("Reader 01 00", false)]);
+        assert_eq!(
+            context.readers().unwrap(),
+            [
+                CString::new("Yubico YubiKey CCID 00 00").unwrap(),
+                CString::new("Reader 01 00").unwrap()
+            ]
+        );
+        assert!(fake::context(&[]).readers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn context_is_released() {
+        drop(fake::context(&[]));
+        assert!(state(|state| state.released));
+    }
+
+    #[test]
+    fn responses_are_returned() {
+        let context = fake::context(&[("Reader", true)]);
+        let card = context.connect(&context.readers().unwrap()[0]).unwrap();
+        state(|state| state.responses.push_back(vec![0x01, 0x02, 0x90, 0x00]));
+        let apdu = [0x00, 0xCB, 0x3F, 0xFF];
+        assert_eq!(*card.transmit(&apdu).unwrap(), [0x01, 0x02, 0x90, 0x00]);
+        assert_eq!(state(|state| state.sent.clone()), [apdu]);
+    }
+
+    #[test]
+    fn transactions_are_always_ended() {
+        let context = fake::context(&[("Reader", true)]);
+        let card = context.connect(&context.readers().unwrap()[0]).unwrap();
+        assert_eq!(card.transaction(|_| Ok::<_, PcscError>(7)).unwrap(), 7);
+        assert!(card
+            .transaction(|_| Err::<(), _>(PcscError(SCARD_W_REMOVED_CARD)))
+            .is_err());
+        assert_eq!(state(|state| state.transactions), 2);
+        assert_eq!(state(|state| state.ended_transactions), 2);
+    }
+
+    #[test]
+    fn removed_card() {
+        let context = fake::context(&[("Reader", true)]);
+        let card = context.connect(&context.readers().unwrap()[0]).unwrap();
+        state(|state| state.removals = 1);
+        // The sign-extended code of the library is read back as 32 bits.
+        let error = card.transmit(&[0x00, 0xCB, 0x3F, 0xFF]).unwrap_err();
+        assert_eq!(error, PcscError(SCARD_W_REMOVED_CARD));
+        assert!(error.needs_reconnection());
+        assert_eq!(
+            ResponseStatus::from(error),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+    }
+
+    #[test]
+    fn other_errors() {
+        // SCARD_E_CANCELLED
+        let error = PcscError(0x8010_0002);
+        assert!(!error.needs_reconnection());
+        assert_eq!(error.to_string(), "PC/SC error 0x80100002");
+        assert_eq!(
+            ResponseStatus::from(error),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+    }
+}
@@ -1,18 +1,36 @@
 // Copyright 2020 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
-use super::utils::{algorithm_to_mechanism, to_response_status};
+use super::utils::{asymmetric_encryption_mechanism, to_response_status};
 use super::KeyPairType;
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
 use cryptoki::error::Error;
 use cryptoki::error::RvError;
-use log::{info, trace};
+use cryptoki::mechanism::MechanismType;
+use log::{error, info, trace};
 use parsec_interface::operations::psa_algorithm::{Algorithm, AsymmetricEncryption};
 use parsec_interface::operations::{psa_asymmetric_decrypt, psa_asymmetric_encrypt};
 use parsec_interface::requests::{ResponseStatus, Result};
 
 impl Provider {
+    /// Check that the token supports the given mechanism, as not all of them implement every
+    /// OAEP parameterization.
+    fn check_mechanism_support(&self, mechanism_type: MechanismType) -> Result<()> {
+        let supported_mechanisms = self
+            .backend
+            .get_mechanism_list(self.slot())
+            .map_err(to_response_status)?;
+        if !supported_mechanisms.contains(&mechanism_type) {
+            error!(
+                "Mechanism {} is not supported by the token.",
+                mechanism_type
+            );
+            return Err(ResponseStatus::PsaErrorNotSupported);
+        }
+        Ok(())
+    }
+
     pub(super) fn psa_asymmetric_encrypt_internal(
         &self,
         application_identity: &ApplicationIdentity,
@@ -28,7 +46,9 @@ impl Provider {
 
         op.validate(key_attributes)?;
 
-        let mech = algorithm_to_mechanism(Algorithm::from(op.alg)).map_err(to_response_status)?;
+        let label = op.salt.as_ref().map(|salt| salt.as_slice());
+        let mech = asymmetric_encryption_mechanism(op.alg, label).map_err(to_response_status)?;
+        self.check_mechanism_support(mech.mechanism_type())?;
 
         let session = self.new_session()?;
 
@@ -59,7 +79,9 @@ impl Provider {
 
         op.validate(key_attributes)?;
 
-        let mech = algorithm_to_mechanism(Algorithm::from(op.alg)).map_err(to_response_status)?;
+        let label = op.salt.as_ref().map(|salt| salt.as_slice());
+        let mech = asymmetric_encryption_mechanism(op.alg, label).map_err(to_response_status)?;
+        self.check_mechanism_support(mech.mechanism_type())?;
 
         let session = self.new_session()?;
 
@@ -63,7 +63,7 @@ impl CanDoCrypto for Provider {
 
         let supported_mechanisms: Vec<MechanismType> = self
             .backend
-            .get_mechanism_list(self.slot_number)
+            .get_mechanism_list(self.slot())
             .map_err(to_response_status)?;
         let mechanism = algorithm_to_mechanism(attributes.policy.permitted_algorithms)
             .map_err(to_response_status)?;
@@ -74,7 +74,7 @@ impl CanDoCrypto for Provider {
 
         let mechanism_info: MechanismInfo = self
             .backend
-            .get_mechanism_info(self.slot_number, mechanism.mechanism_type())
+            .get_mechanism_info(self.slot(), mechanism.mechanism_type())
             .map_err(to_response_status)?;
         if std::any::type_name::<Ulong>() == std::any::type_name::<u64>() {
             if !(attributes.bits >= mechanism_info.min_key_size()
@@ -4,11 +4,17 @@ use super::utils::{algorithm_to_mechanism, to_response_status};
 use super::{utils, KeyPairType, Provider};
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
+use crate::providers::utils::aes_kw::KeyWrapAlgorithm;
+use crate::providers::utils::key_destruction::confirm_destruction;
+use crate::providers::utils::key_validation;
+use crate::utils::logging::Redacted;
 use cryptoki::mechanism::{Mechanism, MechanismType};
 use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle};
 use cryptoki::session::Session;
 use log::{error, info, trace};
-use parsec_interface::operations::psa_key_attributes::{EccFamily, Id, Lifetime, Type};
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, EccFamily, Id, Lifetime, Type, UsageFlags,
+};
 use parsec_interface::operations::utils_deprecated_primitives::CheckDeprecated;
 use parsec_interface::operations::{
     psa_destroy_key, psa_export_public_key, psa_generate_key, psa_import_key,
@@ -16,8 +22,9 @@ use parsec_interface::operations::{
 use parsec_interface::requests::{ResponseStatus, Result};
 use parsec_interface::secrecy::ExposeSecret;
 use picky_asn1::wrapper::{IntegerAsn1, OctetStringAsn1};
-use picky_asn1_x509::RsaPublicKey;
+use picky_asn1_x509::{RsaPublicKey, SubjectPublicKeyInfo};
 use std::convert::TryInto;
+use zeroize::Zeroizing;
 
 impl Provider {
     /// Find the PKCS 11 object handle corresponding to the key ID and the key type (public,
@@ -157,10 +164,11 @@ impl Provider {
 
         match session.generate_key_pair(&mech, &pub_template, &priv_template) {
             Ok((public, private)) => {
-                if let Err(e) =
-                    self.key_info_store
-                        .insert_key_info(key_identity, &key_id, key_attributes)
-                {
+                if let Err(e) = self.key_info_store.insert_key_info(
+                    key_identity.clone(),
+                    &key_id,
+                    key_attributes,
+                ) {
                     format_error!("Failed to insert the mappings, deleting the key", e);
                     if let Err(e) = session.destroy_object(public) {
                         format_error!("Failed to destroy public part of the key", e);
@@ -170,6 +178,7 @@ impl Provider {
                     }
                     Err(e)
                 } else {
+                    self.cache_public_key(&key_identity, public, key_attributes.key_type, &session);
                     Ok(psa_generate_key::Result {})
                 }
             }
@@ -206,6 +215,14 @@ impl Provider {
         );
         self.key_info_store.does_not_exist(&key_identity)?;
 
+        if key_attributes.key_type == Type::Aes {
+            return self.import_aes_wrapping_key(
+                key_identity,
+                key_attributes,
+                op.data.expose_secret(),
+            );
+        }
+
         let session = self.new_session()?;
 
         let key_id = self.create_key_id();
@@ -243,16 +260,18 @@ impl Provider {
         trace!("CreateObject command");
         match session.create_object(&template) {
             Ok(key) => {
-                if let Err(e) =
-                    self.key_info_store
-                        .insert_key_info(key_identity, &key_id, key_attributes)
-                {
+                if let Err(e) = self.key_info_store.insert_key_info(
+                    key_identity.clone(),
+                    &key_id,
+                    key_attributes,
+                ) {
                     format_error!("Failed to insert the mappings, deleting the key.", e);
                     if let Err(e) = session.destroy_object(key) {
                         format_error!("Failed to destroy public key: ", e);
                     }
                     Err(e)
                 } else {
+                    self.cache_public_key(&key_identity, key, key_attributes.key_type, &session);
                     Ok(psa_import_key::Result {})
                 }
             }
@@ -263,30 +282,256 @@ impl Provider {
         }
     }
 
+    /// Template of the AES keys, which can only wrap and unwrap other keys with this provider.
+    fn aes_wrapping_key_template(key_id: u32, usage_flags: UsageFlags) -> Vec<Attribute> {
+        vec![
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::KeyType(KeyType::AES),
+            Attribute::Token(true.into()),
+            Attribute::Id(key_id.to_be_bytes().to_vec()),
+            Attribute::Wrap(usage_flags.encrypt().into()),
+            Attribute::Unwrap(usage_flags.decrypt().into()),
+            // Wrapped keys are unwrapped by decrypting them with the key wrap mechanism.
+            Attribute::Decrypt(usage_flags.decrypt().into()),
+            Attribute::Extractable(usage_flags.export().into()),
+            Attribute::Sensitive((!usage_flags.export()).into()),
+        ]
+    }
+
+    /// Store the mappings of a new key, destroying it if that fails.
+    fn insert_new_key(
+        &self,
+        session: &Session,
+        key_identity: KeyIdentity,
+        key_id: u32,
+        key_attributes: Attributes,
+        key: ObjectHandle,
+    ) -> Result<()> {
+        if let Err(e) = self
+            .key_info_store
+            .insert_key_info(key_identity, &key_id, key_attributes)
+        {
+            format_error!("Failed to insert the mappings, deleting the key.", e);
+            if let Err(e) = session.destroy_object(key) {
+                format_error!("Failed to destroy the key: ", e);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn import_aes_wrapping_key(
+        &self,
+        key_identity: KeyIdentity,
+        key_attributes: Attributes,
+        key_data: &[u8],
+    ) -> Result<psa_import_key::Result> {
+        if ![16, 24, 32].contains(&key_data.len())
+            || (key_attributes.bits != 0 && key_attributes.bits != key_data.len() * 8)
+        {
+            error!("The AES key data does not match a 128, 192 or 256-bit key of the attributes.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+
+        let session = self.new_session()?;
+
+        let key_id = self.create_key_id();
+
+        let mut template =
+            Provider::aes_wrapping_key_template(key_id, key_attributes.policy.usage_flags);
+        template.push(Attribute::Value(key_data.to_vec()));
+
+        trace!("CreateObject command");
+        match session.create_object(&template) {
+            Ok(key) => {
+                self.insert_new_key(&session, key_identity, key_id, key_attributes, key)?;
+                Ok(psa_import_key::Result {})
+            }
+            Err(error) => {
+                format_error!("Import key status: ", error);
+                Err(to_response_status(error))
+            }
+        }
+    }
+
+    /// Get the ID of the AES wrapping key of the application.
+    fn aes_wrapping_key_id(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+    ) -> Result<u32> {
+        let key_identity = KeyIdentity::new(
+            application_identity.clone(),
+            self.provider_identity.clone(),
+            wrapping_key_name.to_string(),
+        );
+        if self
+            .key_info_store
+            .get_key_attributes(&key_identity)?
+            .key_type
+            != Type::Aes
+        {
+            error!(
+                "The wrapping key \"{}\" is not an AES key.",
+                Redacted(wrapping_key_name)
+            );
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+        self.key_info_store.get_key_id(&key_identity)
+    }
+
+    fn key_wrap_mechanism(alg: KeyWrapAlgorithm) -> Mechanism<'static> {
+        match alg {
+            KeyWrapAlgorithm::AesKw => Mechanism::AesKeyWrap,
+            KeyWrapAlgorithm::AesKwp => Mechanism::AesKeyWrapPad,
+        }
+    }
+
+    /// Wrap a key with C_WrapKey. Private keys are wrapped in the PKCS #8 format.
+    pub(super) fn aes_key_wrap_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        key_name: &str,
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Vec<u8>> {
+        let wrapping_key_id = self.aes_wrapping_key_id(application_identity, wrapping_key_name)?;
+        let key_identity = KeyIdentity::new(
+            application_identity.clone(),
+            self.provider_identity.clone(),
+            key_name.to_string(),
+        );
+        let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
+        if !key_attributes.policy.usage_flags.export() {
+            error!(
+                "The key \"{}\" can not be exported, even wrapped.",
+                Redacted(key_name)
+            );
+            return Err(ResponseStatus::PsaErrorNotPermitted);
+        }
+        let key_id = self.key_info_store.get_key_id(&key_identity)?;
+
+        let session = self.new_session()?;
+
+        let wrapping_key = self.find_key(&session, wrapping_key_id, KeyPairType::Any)?;
+        let key = match key_attributes.key_type {
+            Type::Aes => self.find_key(&session, key_id, KeyPairType::Any)?,
+            _ => self.find_key(&session, key_id, KeyPairType::PrivateKey)?,
+        };
+
+        trace!("WrapKey command");
+        session
+            .wrap_key(&Provider::key_wrap_mechanism(alg), wrapping_key, key)
+            .map_err(|error| {
+                format_error!("Wrap key status: ", error);
+                to_response_status(error)
+            })
+    }
+
+    /// Unwrap a key with C_Decrypt and the key wrap mechanism, the back end importing the
+    /// unwrapped key like the others.
+    pub(super) fn aes_key_unwrap_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        wrapped_key: &[u8],
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let wrapping_key_id = self.aes_wrapping_key_id(application_identity, wrapping_key_name)?;
+
+        let session = self.new_session()?;
+
+        let wrapping_key = self.find_key(&session, wrapping_key_id, KeyPairType::Any)?;
+
+        trace!("Decrypt command");
+        session
+            .decrypt(
+                &Provider::key_wrap_mechanism(alg),
+                wrapping_key,
+                wrapped_key,
+            )
+            .map(Zeroizing::new)
+            .map_err(|error| {
+                format_error!("Unwrap key status: ", error);
+                to_response_status(error)
+            })
+    }
+
+    /// Export the public key of the RSA wrapping key pair found on the token by the configured
+    /// label, under which secrets are wrapped offline with RSA-OAEP.
+    pub(super) fn wrapping_public_key_internal(&self) -> Result<Vec<u8>> {
+        let label = self.wrapping_key_label.as_ref().ok_or_else(|| {
+            error!("No wrapping key label is configured.");
+            ResponseStatus::PsaErrorNotSupported
+        })?;
+
+        let session = self.new_session()?;
+
+        trace!("FindObjects commands");
+        let objects = session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::PUBLIC_KEY),
+                Attribute::KeyType(KeyType::RSA),
+                Attribute::Label(label.as_bytes().to_vec()),
+            ])
+            .map_err(to_response_status)?;
+        let key = match objects.as_slice() {
+            [key] => *key,
+            [] => {
+                error!(
+                    "No RSA public key is labelled \"{}\" on the token.",
+                    Redacted(label)
+                );
+                return Err(ResponseStatus::PsaErrorDoesNotExist);
+            }
+            _ => {
+                error!(
+                    "Several RSA public keys are labelled \"{}\" on the token.",
+                    Redacted(label)
+                );
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+        };
+
+        let attributes = session
+            .get_attributes(
+                key,
+                &[AttributeType::Modulus, AttributeType::PublicExponent],
+            )
+            .map_err(to_response_status)?;
+        let (modulus, public_exponent) = match attributes.as_slice() {
+            [Attribute::Modulus(modulus), Attribute::PublicExponent(public_exponent)] => (
+                IntegerAsn1::from_bytes_be_unsigned(modulus.clone()),
+                IntegerAsn1::from_bytes_be_unsigned(public_exponent.clone()),
+            ),
+            _ => {
+                error!("Expected to find modulus and public exponent attributes in public key.");
+                return Err(ResponseStatus::PsaErrorCommunicationFailure);
+            }
+        };
+        picky_asn1_der::to_vec(&SubjectPublicKeyInfo::new_rsa_key(modulus, public_exponent))
+            .map_err(|err| {
+                format_error!("Could not serialise the wrapping public key", err);
+                ResponseStatus::PsaErrorCommunicationFailure
+            })
+    }
+
     pub(super) fn handle_rsa_public_import_attrib(
         &self,
         key_data: &[u8],
         bits: usize,
         template: &mut Vec<Attribute>,
     ) -> Result<()> {
-        let public_key: RsaPublicKey = picky_asn1_der::from_bytes(key_data).map_err(|e| {
-            format_error!("Failed to parse RsaPublicKey data", e);
-            ResponseStatus::PsaErrorInvalidArgument
-        })?;
+        let public_key = key_validation::parse_rsa_public_key(key_data)?;
 
-        if public_key.modulus.is_negative() || public_key.public_exponent.is_negative() {
-            error!("Only positive modulus and public exponent are supported.");
-            return Err(ResponseStatus::PsaErrorInvalidArgument);
-        }
-
-        let modulus_object = public_key.modulus.as_unsigned_bytes_be();
-        let exponent_object = public_key.public_exponent.as_unsigned_bytes_be();
-        if bits != 0 && modulus_object.len() * 8 != bits {
+        let modulus_object = public_key.modulus.to_vec();
+        let exponent_object = public_key.public_exponent.to_vec();
+        if bits != 0 && public_key.key_bits() != bits {
             if crate::utils::GlobalConfig::log_error_details() {
                 error!(
                     "`bits` field of key attributes (value: {}) must be either 0 or equal to the size of the key in `data` (value: {}).",
                     bits,
-                    modulus_object.len() * 8
+                    public_key.key_bits()
                 );
             } else {
                 error!("`bits` field of key attributes must be either 0 or equal to the size of the key in `data`.");
@@ -326,20 +571,7 @@ impl Provider {
 
         // For the format of ECC public keys, see:
         // https://parallaxsecond.github.io/parsec-book/parsec_client/operations/psa_export_public_key.html#description
-        let key_len = ((key_data.len() - 1) / 2) * 8;
-        let bits = if bits == 0 { key_len } else { bits };
-        if bits != key_len {
-            if crate::utils::GlobalConfig::log_error_details() {
-                error!(
-                        "`bits` field of key attributes (value: {}) must be either 0 or equal to half the size of the key in `data` (value: {}) for Weierstrass curves.",
-                        bits,
-                        key_len
-                    );
-            } else {
-                error!("`bits` field of key attributes must be either 0 or equal to half the size of the key in `data` for Weierstrass curves.");
-            }
-            return Err(ResponseStatus::PsaErrorInvalidArgument);
-        }
+        let bits = key_validation::validate_ecc_public_key(key_data, curve_family, bits)?;
 
         // The format expected by PKCS11 is an ASN.1 OctetString containing the
         // data that the PSA Crypto interface specifies.
@@ -377,24 +609,49 @@ impl Provider {
             key_name,
         );
         let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
+        if let Some(data) = self.key_info_store.get_public_key(&key_identity) {
+            return Ok(psa_export_public_key::Result { data: data.into() });
+        }
         let key_id = self.key_info_store.get_key_id(&key_identity)?;
         let session = self.new_session()?;
 
         let key = self.find_key(&session, key_id, KeyPairType::PublicKey)?;
         info!("Located key for export.");
-        let data = match key_attributes.key_type {
-            Type::RsaKeyPair | Type::RsaPublicKey => {
-                self.export_public_rsa_internal(key, &session)?
-            }
+        let data = self.export_public_internal(key, key_attributes.key_type, &session)?;
+        self.key_info_store
+            .cache_public_key(&key_identity, data.clone());
+
+        Ok(psa_export_public_key::Result { data: data.into() })
+    }
+
+    fn export_public_internal(
+        &self,
+        key: ObjectHandle,
+        key_type: Type,
+        session: &Session,
+    ) -> Result<Vec<u8>> {
+        match key_type {
+            Type::RsaKeyPair | Type::RsaPublicKey => self.export_public_rsa_internal(key, session),
             Type::EccKeyPair { .. } | Type::EccPublicKey { .. } => {
-                self.export_public_ec_internal(key, &session)?
+                self.export_public_ec_internal(key, session)
             }
-            _ => {
-                return Err(ResponseStatus::PsaErrorNotSupported);
-            }
-        };
+            _ => Err(ResponseStatus::PsaErrorNotSupported),
+        }
+    }
 
-        Ok(psa_export_public_key::Result { data: data.into() })
+    /// Store the public part of a new key with the Key Info Manager. Failing to do so is not an
+    /// error: it will then be read from the token when first exported.
+    fn cache_public_key(
+        &self,
+        key_identity: &KeyIdentity,
+        key: ObjectHandle,
+        key_type: Type,
+        session: &Session,
+    ) {
+        match self.export_public_internal(key, key_type, session) {
+            Ok(data) => self.key_info_store.cache_public_key(key_identity, data),
+            Err(e) => format_error!("Failed to read the public part of the new key", e),
+        }
     }
 
     fn export_public_rsa_internal(&self, key: ObjectHandle, session: &Session) -> Result<Vec<u8>> {
@@ -472,7 +729,7 @@ impl Provider {
         let key_identity = KeyIdentity::new(
             application_identity.clone(),
             self.provider_identity.clone(),
-            key_name,
+            key_name.clone(),
         );
         let key_id = self.key_info_store.get_key_id(&key_identity)?;
 
@@ -499,6 +756,9 @@ impl Provider {
             }
         }?;
 
+        // No object with the key ID must be left on the token.
+        confirm_destruction(&key_name, self.find_key(&session, key_id, KeyPairType::Any))?;
+
         Ok(psa_destroy_key::Result {})
     }
 }
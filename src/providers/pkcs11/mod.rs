@@ -8,12 +8,12 @@ use super::Provide;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::{KeyIdentity, KeyInfoManagerClient};
 use crate::providers::crypto_capability::CanDoCrypto;
+use crate::providers::utils::aes_kw::KeyWrapAlgorithm;
 use crate::providers::ProviderIdentity;
+use crate::utils::secrets;
 use cryptoki::context::{CInitializeArgs, Pkcs11};
-use cryptoki::error::{Error as Pkcs11Error, RvError};
-use cryptoki::session::{Session, UserType};
+use cryptoki::session::Session;
 use cryptoki::slot::Slot;
-use cryptoki::types::AuthPin;
 use derivative::Derivative;
 use log::{error, info, trace, warn};
 use parsec_interface::operations::list_providers::Uuid;
@@ -25,12 +25,14 @@ use parsec_interface::operations::{
 use parsec_interface::operations::{list_clients, list_keys, list_providers::ProviderInfo};
 use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
 use parsec_interface::secrecy::{ExposeSecret, SecretString};
+use slot_discovery::{SlotRediscovery, TokenSelector};
 use std::collections::HashSet;
 use std::convert::From;
 use std::convert::TryFrom;
 use std::io::{Error, ErrorKind};
 use std::str::FromStr;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use utils::{to_response_status, KeyPairType};
 use zeroize::{Zeroize, Zeroizing};
 
@@ -42,8 +44,13 @@ mod capability_discovery;
 mod generate_random;
 mod key_management;
 mod key_metadata;
+mod pin;
+mod slot_discovery;
 mod utils;
 
+// MAC operations (HMAC, CMAC) are not supported: parsec-interface does not define the
+// PsaMacCompute/PsaMacVerify operations yet and cryptoki 0.6 does not expose the HMAC, CMAC or
+// generic secret key generation mechanisms needed to implement them.
 const SUPPORTED_OPCODES: [Opcode; 10] = [
     Opcode::PsaGenerateKey,
     Opcode::PsaDestroyKey,
@@ -58,6 +65,8 @@ const SUPPORTED_OPCODES: [Opcode; 10] = [
 ];
 
 const PIN_STRING_PREFIX: &str = "str:";
+/// Seconds between two looks for the slot of a token selected by serial number or label
+const DEFAULT_TOKEN_REDISCOVERY_INTERVAL: u64 = 5;
 const PIN_HEX_PREFIX: &str = "hex:";
 
 /// Provider for Public Key Cryptography Standard #11
@@ -75,9 +84,12 @@ pub struct Provider {
     local_ids: RwLock<LocalIdStore>,
     #[derivative(Debug = "ignore")]
     backend: Pkcs11,
-    slot_number: Slot,
+    slot_number: Arc<RwLock<Slot>>,
+    slot_rediscovery: Option<SlotRediscovery>,
     software_public_operations: bool,
     allow_export: bool,
+    wrapping_key_label: Option<String>,
+    #[derivative(Debug = "ignore")]
     user_pin: Option<SecretString>,
 }
 
@@ -100,6 +112,7 @@ impl Provider {
         user_pin: Option<SecretString>,
         software_public_operations: bool,
         allow_export: bool,
+        wrapping_key_label: Option<String>,
     ) -> Option<Provider> {
         #[allow(clippy::mutex_atomic)]
         let pkcs11_provider = Provider {
@@ -110,10 +123,12 @@ impl Provider {
             key_info_store,
             local_ids: RwLock::new(HashSet::new()),
             backend,
-            slot_number,
+            slot_number: Arc::new(RwLock::new(slot_number)),
+            slot_rediscovery: None,
             software_public_operations,
             allow_export,
-            user_pin,
+            wrapping_key_label,
+            user_pin: user_pin.as_ref().map(pin::decode),
         };
         {
             let mut local_ids_handle = pkcs11_provider
@@ -205,6 +220,11 @@ impl Provider {
         Some(pkcs11_provider)
     }
 
+    // Slot of the token, which can change if it is selected by label or serial number.
+    fn slot(&self) -> Slot {
+        *self.slot_number.read().expect("Slot lock poisoned")
+    }
+
     // Create a new session with the following properties:
     // * without callback
     // * read/write session
@@ -214,31 +234,10 @@ impl Provider {
     fn new_session(&self) -> Result<Session> {
         let session = self
             .backend
-            .open_rw_session(self.slot_number)
+            .open_rw_session(self.slot())
             .map_err(to_response_status)?;
 
-        if self.user_pin.is_some() {
-            let mut pin = Zeroizing::new(self.user_pin.as_ref().unwrap().expose_secret().clone());
-            if pin.starts_with(PIN_HEX_PREFIX) {
-                if let Ok(mut raw_pin) = hex::decode(pin.split_off(PIN_HEX_PREFIX.len())) {
-                    pin = Zeroizing::new(String::from_utf8_lossy(&raw_pin.as_slice()).to_string());
-                    raw_pin.zeroize();
-                }
-            } else if pin.starts_with(PIN_STRING_PREFIX) {
-                pin = pin.split_off(PIN_STRING_PREFIX.len()).into();
-            }
-
-            session
-                .login(UserType::User, Some(&AuthPin::new(pin.to_string())))
-                .or_else(|e| {
-                    if let Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn) = e {
-                        Ok(())
-                    } else {
-                        Err(e)
-                    }
-                })
-                .map_err(to_response_status)?;
-        }
+        self.login(&session)?;
 
         Ok(session)
     }
@@ -388,6 +387,37 @@ impl Provide for Provider {
         trace!("can_do_crypto ingress");
         self.can_do_crypto_main(application_identity, op)
     }
+
+    fn wrapping_public_key(&self) -> Result<Vec<u8>> {
+        trace!("wrapping_public_key ingress");
+        self.wrapping_public_key_internal()
+    }
+
+    fn aes_key_wrap(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        key_name: &str,
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Vec<u8>> {
+        trace!("aes_key_wrap ingress");
+        self.aes_key_wrap_internal(application_identity, wrapping_key_name, key_name, alg)
+    }
+
+    fn aes_key_unwrap(
+        &self,
+        application_identity: &ApplicationIdentity,
+        wrapping_key_name: &str,
+        wrapped_key: &[u8],
+        alg: KeyWrapAlgorithm,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        trace!("aes_key_unwrap ingress");
+        self.aes_key_unwrap_internal(application_identity, wrapping_key_name, wrapped_key, alg)
+    }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        Some(&self.key_info_store)
+    }
 }
 
 /// Builder for Pkcs11Provider
@@ -404,9 +434,12 @@ pub struct ProviderBuilder {
     pkcs11_library_path: Option<String>,
     slot_number: Option<u64>,
     serial_number: Option<String>,
+    token_label: Option<String>,
+    token_rediscovery_interval: Option<u64>,
     user_pin: Option<SecretString>,
     software_public_operations: Option<bool>,
     allow_export: Option<bool>,
+    wrapping_key_label: Option<String>,
 }
 
 impl ProviderBuilder {
@@ -418,9 +451,12 @@ impl ProviderBuilder {
             pkcs11_library_path: None,
             slot_number: None,
             serial_number: None,
+            token_label: None,
+            token_rediscovery_interval: None,
             user_pin: None,
             software_public_operations: None,
             allow_export: None,
+            wrapping_key_label: None,
         }
     }
 
@@ -458,6 +494,22 @@ impl ProviderBuilder {
         self
     }
 
+    /// Specify the label of the token used
+    pub fn with_token_label(mut self, token_label: Option<String>) -> ProviderBuilder {
+        self.token_label = token_label;
+        self
+    }
+
+    /// Specify the interval in seconds at which the slot of a token selected by serial number or
+    /// label is looked for again, 0 to only look for it at start
+    pub fn with_token_rediscovery_interval(
+        mut self,
+        token_rediscovery_interval: Option<u64>,
+    ) -> ProviderBuilder {
+        self.token_rediscovery_interval = token_rediscovery_interval;
+        self
+    }
+
     /// Specify the user pin
     pub fn with_user_pin(mut self, mut user_pin: Option<String>) -> ProviderBuilder {
         self.user_pin = match user_pin {
@@ -487,6 +539,16 @@ impl ProviderBuilder {
         self
     }
 
+    /// Specify the label of the wrapping key pair on the token
+    pub fn with_wrapping_key_label(
+        mut self,
+        wrapping_key_label: Option<String>,
+    ) -> ProviderBuilder {
+        self.wrapping_key_label = wrapping_key_label;
+
+        self
+    }
+
     /// Attempt to build a PKCS11 provider
     pub fn build(self) -> std::io::Result<Provider> {
         let library_path = self
@@ -519,24 +581,23 @@ impl ProviderBuilder {
                 "Failed retrieving a valid slot with an initialized token",
             )
         })?;
-        let slot_number = match (self.serial_number, self.slot_number) {
-            (Some(serial_number), given_slot) => {
-                let mut slot = None;
-                for current_slot in slots {
-                    let current_token = backend.get_token_info(current_slot).map_err(|e| {
-                        format_error!("Failed parsing token info", e);
-                        Error::new(ErrorKind::InvalidData, "Failed parsing token info")
-                    })?;
-                    let sn = String::from_utf8(current_token.serial_number().as_bytes().to_vec())
-                        .map_err(|e| {
-                        format_error!("Failed parsing token serial number", e);
-                        Error::new(ErrorKind::InvalidData, "Failed parsing token serial number")
-                    })?;
-                    if sn.trim() == serial_number.trim() {
-                        slot = Some(current_slot);
-                        break;
-                    }
-                }
+        let selector = match (self.serial_number, self.token_label) {
+            (Some(_), Some(_)) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "only one of the token serial number and label can be given",
+                ))
+            }
+            (Some(serial_number), None) => Some(TokenSelector::SerialNumber(serial_number)),
+            (None, Some(label)) => Some(TokenSelector::Label(label)),
+            (None, None) => None,
+        };
+        let slot_number = match (&selector, self.slot_number) {
+            (Some(selector), given_slot) => {
+                let slot = slot_discovery::find_slot(&backend, selector).map_err(|e| {
+                    format_error!("Failed looking for the token", e);
+                    Error::new(ErrorKind::InvalidData, "Failed looking for the token")
+                })?;
                 match slot {
                     Some(slot) => {
                         if let Some(slot_number) = given_slot {
@@ -550,7 +611,7 @@ impl ProviderBuilder {
                     None => {
                         return Err(Error::new(
                             ErrorKind::InvalidData,
-                            "No token with the provided serial number",
+                            "No token with the provided serial number or label",
                         ))
                     }
                 }
@@ -569,7 +630,7 @@ impl ProviderBuilder {
                     ));
                 }
                 warn!(
-                    "Slot number {} will be used. However, It is preferred to use serial_number as the slot number might change during replug or OS reboot.",
+                    "Slot number {} will be used. However, It is preferred to use serial_number or token_label as the slot number might change during replug or OS reboot.",
                     slot_number
                 );
                 slot
@@ -586,7 +647,22 @@ impl ProviderBuilder {
             }
         };
 
-        Ok(Provider::new(
+        let user_pin = match self.user_pin {
+            Some(user_pin) => match secrets::resolve(user_pin.expose_secret())? {
+                Some(secret) => {
+                    let pin = std::str::from_utf8(&secret).map_err(|_| {
+                        Error::new(ErrorKind::InvalidData, "user pin is not valid UTF-8")
+                    })?;
+                    // The conversion from a &str is infallible.
+                    Some(SecretString::from_str(pin).unwrap())
+                }
+                None => Some(user_pin),
+            },
+            None => None,
+        };
+
+        let rediscovery_backend = backend.clone();
+        let mut provider = Provider::new(
             self.provider_name.ok_or_else(|| {
                 std::io::Error::new(std::io::ErrorKind::InvalidData, "missing provider name")
             })?,
@@ -594,10 +670,24 @@ impl ProviderBuilder {
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing key info store"))?,
             backend,
             slot_number,
-            self.user_pin,
+            user_pin,
             self.software_public_operations.unwrap_or(false),
             self.allow_export.unwrap_or(true),
+            self.wrapping_key_label,
         )
-        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "PKCS 11 initialization failed"))?)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "PKCS 11 initialization failed"))?;
+
+        let interval = self
+            .token_rediscovery_interval
+            .unwrap_or(DEFAULT_TOKEN_REDISCOVERY_INTERVAL);
+        if let (Some(selector), true) = (selector, interval > 0) {
+            provider.slot_rediscovery = Some(SlotRediscovery::start(
+                rediscovery_backend,
+                selector,
+                provider.slot_number.clone(),
+                Duration::from_secs(interval),
+            )?);
+        }
+        Ok(provider)
     }
 }
@@ -0,0 +1,116 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Management of the user PIN
+//!
+//! Tokens can make the user PIN expire, or lock it after too many failed attempts. The errors on
+//! the PIN are logged with what has to be done about them and returned as their own statuses.
+//!
+//! The sessions are logged in when opened. If the token reports the provider as logged in while
+//! the session is not, for example after the token was removed and inserted again, the provider
+//! logs out and logs in again.
+use super::utils::to_response_status;
+use super::{Provider, PIN_HEX_PREFIX, PIN_STRING_PREFIX};
+use cryptoki::error::{Error as Pkcs11Error, RvError};
+use cryptoki::session::{Session, SessionState, UserType};
+use cryptoki::types::AuthPin;
+use log::warn;
+use parsec_interface::requests::Result;
+use parsec_interface::secrecy::ExposeSecret;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Decode the user PIN as configured, with an optional prefix giving its format.
+pub(super) fn decode(configured: &AuthPin) -> AuthPin {
+    let mut pin = Zeroizing::new(configured.expose_secret().clone());
+    if pin.starts_with(PIN_HEX_PREFIX) {
+        if let Ok(mut raw_pin) = hex::decode(pin.split_off(PIN_HEX_PREFIX.len())) {
+            pin = Zeroizing::new(String::from_utf8_lossy(raw_pin.as_slice()).to_string());
+            raw_pin.zeroize();
+        }
+    } else if pin.starts_with(PIN_STRING_PREFIX) {
+        pin = pin.split_off(PIN_STRING_PREFIX.len()).into();
+    }
+    AuthPin::new(pin.to_string())
+}
+
+fn log_in(session: &Session, pin: &AuthPin) -> Result<()> {
+    session
+        .login(UserType::User, Some(pin))
+        .or_else(|e| {
+            if let Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn) = e {
+                Ok(())
+            } else {
+                Err(e)
+            }
+        })
+        .map_err(to_response_status)
+}
+
+impl Provider {
+    /// Log a session in with the user PIN, if there is one.
+    pub(super) fn login(&self, session: &Session) -> Result<()> {
+        let pin = match &self.user_pin {
+            Some(pin) => pin,
+            None => return Ok(()),
+        };
+        log_in(session, pin)?;
+
+        let state = session
+            .get_session_info()
+            .map_err(to_response_status)?
+            .session_state();
+        if state == SessionState::RwPublic || state == SessionState::RoPublic {
+            warn!("The token lost the login of the provider, logging in again.");
+            let _ = session.logout();
+            log_in(session, pin)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::requests::ResponseStatus;
+
+    fn decoded(configured: &str) -> String {
+        decode(&AuthPin::new(configured.to_string()))
+            .expose_secret()
+            .clone()
+    }
+
+    #[test]
+    fn plain_pins_are_kept() {
+        assert_eq!(decoded("123456"), "123456");
+        assert_eq!(decoded(""), "");
+    }
+
+    #[test]
+    fn string_prefix_is_removed() {
+        assert_eq!(decoded("str:123456"), "123456");
+        assert_eq!(decoded("str:hex:12"), "hex:12");
+        assert_eq!(decoded("str:"), "");
+    }
+
+    #[test]
+    fn hex_pins_are_decoded() {
+        assert_eq!(decoded("hex:313233"), "123");
+        assert_eq!(decoded("hex:4A4b"), "JK");
+    }
+
+    #[test]
+    fn pin_errors_have_their_own_status() {
+        let status = |rv| to_response_status(Pkcs11Error::Pkcs11(rv));
+        assert_eq!(
+            status(RvError::PinIncorrect),
+            ResponseStatus::PsaErrorNotPermitted
+        );
+        assert_eq!(
+            status(RvError::PinExpired),
+            ResponseStatus::PsaErrorBadState
+        );
+        assert_eq!(
+            status(RvError::PinLocked),
+            ResponseStatus::PsaErrorHardwareFailure
+        );
+    }
+}
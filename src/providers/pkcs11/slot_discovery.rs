@@ -0,0 +1,199 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Discovery of the slot of the token
+//!
+//! Slot numbers can change across boots, or when a token is removed and inserted again. The token
+//! can instead be selected by its label or its serial number, in which case its slot is looked up
+//! among the slots with a token present on start, and then periodically in the background. When
+//! the token shows up in another slot, the sessions opened afterwards use that slot, so that a
+//! smartcard inserted again is served without restarting the service.
+use super::utils::to_response_status;
+use cryptoki::context::Pkcs11;
+use cryptoki::slot::Slot;
+use log::{error, info, warn};
+use parsec_interface::requests::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Token to look for in the slots
+#[derive(Debug, Clone)]
+pub(super) enum TokenSelector {
+    /// Token with this label
+    Label(String),
+    /// Token with this serial number
+    SerialNumber(String),
+}
+
+impl TokenSelector {
+    // Both fields are padded with blanks in the token information.
+    fn matches(&self, label: &str, serial_number: &str) -> bool {
+        match self {
+            TokenSelector::Label(wanted) => label.trim() == wanted.trim(),
+            TokenSelector::SerialNumber(wanted) => serial_number.trim() == wanted.trim(),
+        }
+    }
+}
+
+/// Find the slot holding the selected token, among the slots with a token present.
+pub(super) fn find_slot(backend: &Pkcs11, selector: &TokenSelector) -> Result<Option<Slot>> {
+    let slots = backend.get_slots_with_token().map_err(to_response_status)?;
+    for slot in slots {
+        let token = match backend.get_token_info(slot) {
+            Ok(token) => token,
+            // The token can be removed while the slots are listed.
+            Err(e) => {
+                format_error!(format!("Failed to read the token in slot {}", slot), e);
+                continue;
+            }
+        };
+        if selector.matches(token.label(), token.serial_number()) {
+            return Ok(Some(slot));
+        }
+    }
+    Ok(None)
+}
+
+/// Update the slot of the token with the result of a look for it, given whether the token was
+/// present at the previous look. Returns whether the token is present.
+fn update_slot(slot: &RwLock<Slot>, found: Result<Option<Slot>>, present: bool) -> bool {
+    match found {
+        Ok(Some(found)) => {
+            let mut slot = slot.write().expect("Slot lock poisoned");
+            if *slot != found {
+                info!("The token moved from slot {} to slot {}.", *slot, found);
+                *slot = found;
+            } else if !present {
+                info!("The token is present again in slot {}.", found);
+            }
+            true
+        }
+        Ok(None) => {
+            if present {
+                warn!("The token is not present in any slot anymore.");
+            }
+            false
+        }
+        Err(e) => {
+            format_error!("Failed to list the slots", e);
+            present
+        }
+    }
+}
+
+/// Background thread looking for the token in the slots, stopped when dropped
+#[derive(Debug)]
+pub(super) struct SlotRediscovery {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SlotRediscovery {
+    /// Start updating the slot of the token, looking for it at the given interval.
+    pub(super) fn start(
+        backend: Pkcs11,
+        selector: TokenSelector,
+        slot: Arc<RwLock<Slot>>,
+        interval: Duration,
+    ) -> std::io::Result<Self> {
+        info!(
+            "Looking for the {:?} token every {} seconds.",
+            selector,
+            interval.as_secs()
+        );
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("pkcs11-slot-discovery".to_string())
+            .spawn(move || {
+                let mut present = true;
+                while !thread_stop.load(Ordering::Acquire) {
+                    thread::park_timeout(interval);
+                    present = update_slot(&slot, find_slot(&backend, &selector), present);
+                }
+            })?;
+
+        Ok(SlotRediscovery {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for SlotRediscovery {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                error!("The PKCS 11 slot discovery thread panicked.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::requests::ResponseStatus;
+    use std::convert::TryFrom;
+
+    fn slot(id: u64) -> Slot {
+        Slot::try_from(id).unwrap()
+    }
+
+    #[test]
+    fn labels_are_matched_without_padding() {
+        let label = TokenSelector::Label(String::from("Parsec Token"));
+        assert!(label.matches("Parsec Token                    ", "0123"));
+        assert!(!label.matches("Other Token", "Parsec Token"));
+    }
+
+    #[test]
+    fn serial_numbers_are_matched_without_padding() {
+        let serial_number = TokenSelector::SerialNumber(String::from("0123"));
+        assert!(serial_number.matches("Parsec Token", "0123            "));
+        assert!(!serial_number.matches("0123", "4567"));
+    }
+
+    #[test]
+    fn moved_tokens_change_the_slot() {
+        let current = RwLock::new(slot(1));
+        assert!(update_slot(&current, Ok(Some(slot(3))), true));
+        assert_eq!(*current.read().unwrap(), slot(3));
+    }
+
+    #[test]
+    fn missing_tokens_keep_their_slot() {
+        let current = RwLock::new(slot(1));
+        assert!(!update_slot(&current, Ok(None), true));
+        assert!(!update_slot(&current, Ok(None), false));
+        assert_eq!(*current.read().unwrap(), slot(1));
+    }
+
+    #[test]
+    fn tokens_inserted_again_are_present() {
+        let current = RwLock::new(slot(1));
+        assert!(update_slot(&current, Ok(Some(slot(1))), false));
+        assert_eq!(*current.read().unwrap(), slot(1));
+        assert!(update_slot(&current, Ok(Some(slot(2))), false));
+        assert_eq!(*current.read().unwrap(), slot(2));
+    }
+
+    #[test]
+    fn failed_looks_change_nothing() {
+        let current = RwLock::new(slot(1));
+        assert!(update_slot(
+            &current,
+            Err(ResponseStatus::PsaErrorCommunicationFailure),
+            true
+        ));
+        assert!(!update_slot(
+            &current,
+            Err(ResponseStatus::PsaErrorCommunicationFailure),
+            false
+        ));
+        assert_eq!(*current.read().unwrap(), slot(1));
+    }
+}
@@ -1,11 +1,14 @@
 // Copyright 2020 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
+use crate::providers::error_detail::{self, ErrorDetail};
 use cryptoki::error::{Error, RvError};
 use cryptoki::mechanism::rsa;
 use cryptoki::mechanism::Mechanism;
 use cryptoki::object::Attribute;
 use log::error;
-use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_algorithm::{
+    Algorithm, AsymmetricEncryption, AsymmetricSignature, Hash, SignHash,
+};
 
 use parsec_interface::operations::psa_key_attributes::*;
 use parsec_interface::requests::ResponseStatus;
@@ -26,7 +29,8 @@ pub const PUBLIC_EXPONENT: [u8; 3] = [0x01, 0x00, 0x01];
 /// If an error happens in the PKCS11 library, it means that it was badly used by the provider or
 /// that it failed in an unexpected way and hence the PsaErrorCommunicationFailure error.
 /// The errors translated to response status are related with signature verification failure, lack
-/// of memory, hardware failure, corruption detection, lack of entropy and unsupported operations.
+/// of memory, hardware failure, corruption detection, lack of entropy, unsupported operations and
+/// the user PIN.
 pub fn to_response_status(error: Error) -> ResponseStatus {
     match error {
         Error::LibraryLoading(e) => {
@@ -46,6 +50,11 @@ pub fn to_response_status(error: Error) -> ResponseStatus {
 }
 
 pub fn rv_to_response_status(rv: RvError) -> ResponseStatus {
+    error_detail::set(ErrorDetail::new(
+        "PKCS#11",
+        format!("{:?}", rv),
+        rv.to_string(),
+    ));
     match rv {
         RvError::HostMemory => ResponseStatus::PsaErrorInsufficientMemory,
         RvError::DeviceError => ResponseStatus::PsaErrorHardwareFailure,
@@ -57,6 +66,18 @@ pub fn rv_to_response_status(rv: RvError) -> ResponseStatus {
         RvError::TokenNotRecognized => ResponseStatus::PsaErrorHardwareFailure,
         RvError::RandomNoRng => ResponseStatus::PsaErrorInsufficientEntropy,
         RvError::StateUnsaveable => ResponseStatus::PsaErrorHardwareFailure,
+        RvError::PinIncorrect => {
+            error!("The user PIN is incorrect, the token might lock it after more attempts.");
+            ResponseStatus::PsaErrorNotPermitted
+        }
+        RvError::PinExpired => {
+            error!("The user PIN has expired, it must be changed on the token.");
+            ResponseStatus::PsaErrorBadState
+        }
+        RvError::PinLocked => {
+            error!("The user PIN is locked, the security officer of the token must unlock it.");
+            ResponseStatus::PsaErrorHardwareFailure
+        }
         s @ RvError::CurveNotSupported
         | s @ RvError::DomainParamsInvalid
         | s @ RvError::FunctionNotSupported => {
@@ -167,8 +188,6 @@ pub fn pkcsmgftype_from_psa_crypto_hash(alg: Hash) -> Result<rsa::PkcsMgfType, E
 pub fn algorithm_to_mechanism<'a>(
     alg: psa_crypto::types::algorithm::Algorithm,
 ) -> Result<Mechanism<'a>, Error> {
-    use psa_crypto::types::algorithm::{Algorithm, AsymmetricEncryption};
-
     match alg {
         Algorithm::Hash(Hash::Sha1) => Ok(Mechanism::Sha1),
         Algorithm::Hash(Hash::Sha256) => Ok(Mechanism::Sha256),
@@ -186,12 +205,8 @@ pub fn algorithm_to_mechanism<'a>(
             s_len: hash_alg.hash_length().try_into()?,
         })),
         Algorithm::AsymmetricSignature(AsymmetricSignature::Ecdsa { .. }) => Ok(Mechanism::Ecdsa),
-        Algorithm::AsymmetricEncryption(AsymmetricEncryption::RsaOaep { hash_alg }) => {
-            Ok(Mechanism::from(rsa::PkcsOaepParams::new(
-                algorithm_to_mechanism(Algorithm::from(hash_alg))?.mechanism_type(),
-                pkcsmgftype_from_psa_crypto_hash(hash_alg)?,
-                rsa::PkcsOaepSource::empty(),
-            )))
+        Algorithm::AsymmetricEncryption(alg @ AsymmetricEncryption::RsaOaep { .. }) => {
+            asymmetric_encryption_mechanism(alg, None)
         }
         alg => {
             error!("{:?} is not a supported algorithm", alg);
@@ -199,3 +214,25 @@ pub fn algorithm_to_mechanism<'a>(
         }
     }
 }
+
+/// Convert a PSA Crypto asymmetric encryption algorithm to a mechanism, using the given label
+/// (the PSA salt) as the OAEP source data. An empty label is the same as no label.
+pub fn asymmetric_encryption_mechanism(
+    alg: AsymmetricEncryption,
+    label: Option<&[u8]>,
+) -> Result<Mechanism<'_>, Error> {
+    match alg {
+        AsymmetricEncryption::RsaPkcs1v15Crypt => Ok(Mechanism::RsaPkcs),
+        AsymmetricEncryption::RsaOaep { hash_alg } => {
+            let source = match label {
+                Some(label) if !label.is_empty() => rsa::PkcsOaepSource::data_specified(label),
+                _ => rsa::PkcsOaepSource::empty(),
+            };
+            Ok(Mechanism::from(rsa::PkcsOaepParams::new(
+                algorithm_to_mechanism(Algorithm::from(hash_alg))?.mechanism_type(),
+                pkcsmgftype_from_psa_crypto_hash(hash_alg)?,
+                source,
+            )))
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Verification of signatures in software
+//!
+//! Verifying a signature only needs the public part of a key. Providers that know it without
+//! asking their backend can verify signatures with Mbed Crypto instead, leaving the hardware to
+//! the operations that need the private part. The public key is imported as a volatile key for
+//! the time of the verification.
+use super::MBED_CRYPTO_KEY_SLOTS;
+use log::error;
+use parsec_interface::operations::psa_algorithm::AsymmetricSignature;
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, Lifetime, Policy, Type, UsageFlags,
+};
+use parsec_interface::requests::{ResponseStatus, Result};
+use psa_crypto::operations::{asym_signature, key_management};
+
+/// Verify a signature of the given hash, made with the private key matching `public_key`, in the
+/// format returned by PsaExportPublicKey. The attributes are those of the key pair.
+pub fn verify_hash(
+    attributes: Attributes,
+    public_key: &[u8],
+    alg: AsymmetricSignature,
+    hash: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let key_type = match attributes.key_type {
+        Type::RsaKeyPair | Type::RsaPublicKey => Type::RsaPublicKey,
+        Type::EccKeyPair { curve_family } | Type::EccPublicKey { curve_family } => {
+            Type::EccPublicKey { curve_family }
+        }
+        _ => return Err(ResponseStatus::PsaErrorNotSupported),
+    };
+    let mut usage_flags = UsageFlags::default();
+    let _ = usage_flags.set_verify_hash();
+    let public_attributes = Attributes {
+        lifetime: Lifetime::Volatile,
+        key_type,
+        bits: attributes.bits,
+        policy: Policy {
+            usage_flags,
+            permitted_algorithms: attributes.policy.permitted_algorithms,
+        },
+    };
+
+    psa_crypto::init().map_err(|error| {
+        let error = ResponseStatus::from(error);
+        format_error!("Error initializing Mbed Crypto", error);
+        error
+    })?;
+    let _guard = MBED_CRYPTO_KEY_SLOTS
+        .lock()
+        .expect("Grabbing key slots mutex failed");
+    let id = key_management::import(public_attributes, None, public_key).map_err(|error| {
+        let error = ResponseStatus::from(error);
+        format_error!("Import of the public key for verification status: ", error);
+        error
+    })?;
+    let result =
+        asym_signature::verify_hash(id, alg, hash, signature).map_err(ResponseStatus::from);
+    // Safe as the volatile key was only created for this verification.
+    if unsafe { key_management::destroy(id) }.is_err() {
+        error!("Failed to destroy the public key imported for verification.");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify_hash;
+    use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, EccFamily, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::ResponseStatus;
+
+    // Key, hash and signature of the "sample" message from RFC 6979, A.2.5
+    const PUBLIC_KEY: [u8; 65] = [
+        0x04, 0x60, 0xfe, 0xd4, 0xba, 0x25, 0x5a, 0x9d, 0x31, 0xc9, 0x61, 0xeb, 0x74, 0xc6, 0x35,
+        0x6d, 0x68, 0xc0, 0x49, 0xb8, 0x92, 0x3b, 0x61, 0xfa, 0x6c, 0xe6, 0x69, 0x62, 0x2e, 0x60,
+        0xf2, 0x9f, 0xb6, 0x79, 0x03, 0xfe, 0x10, 0x08, 0xb8, 0xbc, 0x99, 0xa4, 0x1a, 0xe9, 0xe9,
+        0x56, 0x28, 0xbc, 0x64, 0xf2, 0xf1, 0xb2, 0x0c, 0x2d, 0x7e, 0x9f, 0x51, 0x77, 0xa3, 0xc2,
+        0x94, 0xd4, 0x46, 0x22, 0x99,
+    ];
+    const HASH: [u8; 32] = [
+        0xaf, 0x2b, 0xdb, 0xe1, 0xaa, 0x9b, 0x6e, 0xc1, 0xe2, 0xad, 0xe1, 0xd6, 0x94, 0xf4, 0x1f,
+        0xc7, 0x1a, 0x83, 0x1d, 0x02, 0x68, 0xe9, 0x89, 0x15, 0x62, 0x11, 0x3d, 0x8a, 0x62, 0xad,
+        0xd1, 0xbf,
+    ];
+    const SIGNATURE: [u8; 64] = [
+        0xef, 0xd4, 0x8b, 0x2a, 0xac, 0xb6, 0xa8, 0xfd, 0x11, 0x40, 0xdd, 0x9c, 0xd4, 0x5e, 0x81,
+        0xd6, 0x9d, 0x2c, 0x87, 0x7b, 0x56, 0xaa, 0xf9, 0x91, 0xc3, 0x4d, 0x0e, 0xa8, 0x4e, 0xaf,
+        0x37, 0x16, 0xf7, 0xcb, 0x1c, 0x94, 0x2d, 0x65, 0x7c, 0x41, 0xd4, 0x36, 0xc7, 0xa1, 0xb6,
+        0xe2, 0x9f, 0x65, 0xf3, 0xe9, 0x00, 0xdb, 0xb9, 0xaf, 0xf4, 0x06, 0x4d, 0xc4, 0xab, 0x2f,
+        0x84, 0x3a, 0xcd, 0xa8,
+    ];
+    const ECDSA_SHA256: AsymmetricSignature = AsymmetricSignature::Ecdsa {
+        hash_alg: SignHash::Specific(Hash::Sha256),
+    };
+
+    fn attributes(key_type: Type) -> Attributes {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags.set_sign_hash().set_verify_hash();
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type,
+            bits: 256,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms: ECDSA_SHA256.into(),
+            },
+        }
+    }
+
+    fn key_pair() -> Attributes {
+        attributes(Type::EccKeyPair {
+            curve_family: EccFamily::SecpR1,
+        })
+    }
+
+    #[test]
+    fn known_answer() {
+        verify_hash(key_pair(), &PUBLIC_KEY, ECDSA_SHA256, &HASH, &SIGNATURE).unwrap();
+    }
+
+    #[test]
+    fn public_key_attributes() {
+        let attributes = attributes(Type::EccPublicKey {
+            curve_family: EccFamily::SecpR1,
+        });
+        verify_hash(attributes, &PUBLIC_KEY, ECDSA_SHA256, &HASH, &SIGNATURE).unwrap();
+    }
+
+    #[test]
+    fn modified_signature() {
+        let mut signature = SIGNATURE;
+        signature[63] ^= 1;
+        assert_eq!(
+            verify_hash(key_pair(), &PUBLIC_KEY, ECDSA_SHA256, &HASH, &signature),
+            Err(ResponseStatus::PsaErrorInvalidSignature)
+        );
+    }
+
+    #[test]
+    fn other_hash() {
+        let mut hash = HASH;
+        hash[0] ^= 1;
+        assert_eq!(
+            verify_hash(key_pair(), &PUBLIC_KEY, ECDSA_SHA256, &hash, &SIGNATURE),
+            Err(ResponseStatus::PsaErrorInvalidSignature)
+        );
+    }
+
+    #[test]
+    fn algorithm_not_permitted_by_key() {
+        let alg = AsymmetricSignature::Ecdsa {
+            hash_alg: SignHash::Specific(Hash::Sha384),
+        };
+        assert_eq!(
+            verify_hash(key_pair(), &PUBLIC_KEY, alg, &HASH, &SIGNATURE),
+            Err(ResponseStatus::PsaErrorNotPermitted)
+        );
+    }
+
+    #[test]
+    fn symmetric_keys_are_not_supported() {
+        assert_eq!(
+            verify_hash(
+                attributes(Type::Aes),
+                &PUBLIC_KEY,
+                ECDSA_SHA256,
+                &HASH,
+                &SIGNATURE
+            ),
+            Err(ResponseStatus::PsaErrorNotSupported)
+        );
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+use super::{derive_bytes, Provider};
+use crate::authenticators::ApplicationIdentity;
+use log::error;
+use parsec_interface::operations::psa_key_attributes::Attributes;
+use parsec_interface::operations::{psa_sign_hash, psa_verify_hash};
+use parsec_interface::requests::{ResponseStatus, Result};
+
+/// Size of the signatures produced, in bytes, when it can not be deduced from the key size
+const DEFAULT_SIGNATURE_SIZE: usize = 64;
+
+impl Provider {
+    // Signatures only depend on the public part of the key and the hash: this provider offers no
+    // security whatsoever but imported public keys can verify the signatures of the key pairs.
+    fn signature(&self, public: &[u8], hash: &[u8], attributes: &Attributes) -> Vec<u8> {
+        let size = match attributes.bits {
+            0 => DEFAULT_SIGNATURE_SIZE,
+            bits if attributes.key_type.is_ecc_key_pair()
+                || attributes.key_type.is_ecc_public_key() =>
+            {
+                // r || s
+                2 * ((bits + 7) / 8)
+            }
+            bits => (bits + 7) / 8,
+        };
+        derive_bytes(self.seed, &[b"signature", public, hash], size)
+    }
+
+    pub(super) fn psa_sign_hash_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_hash::Operation,
+    ) -> Result<psa_sign_hash::Result> {
+        let key_identity = self.key_identity(application_identity, op.key_name.clone());
+        let (key, attributes) = self.get_key(&key_identity)?;
+        op.validate(attributes)?;
+
+        if key.material.is_empty() {
+            error!("Public keys can not be used for signing.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+
+        Ok(psa_sign_hash::Result {
+            signature: self.signature(&key.public, &op.hash, &attributes).into(),
+        })
+    }
+
+    pub(super) fn psa_verify_hash_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_verify_hash::Operation,
+    ) -> Result<psa_verify_hash::Result> {
+        let key_identity = self.key_identity(application_identity, op.key_name.clone());
+        let (key, attributes) = self.get_key(&key_identity)?;
+        op.validate(attributes)?;
+
+        if self.signature(&key.public, &op.hash, &attributes) != *op.signature {
+            return Err(ResponseStatus::PsaErrorInvalidSignature);
+        }
+
+        Ok(psa_verify_hash::Result {})
+    }
+}
@@ -0,0 +1,33 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+use super::{splitmix64, Provider};
+use parsec_interface::operations::psa_generate_random;
+use parsec_interface::requests::{ResponseStatus, Result};
+
+impl Provider {
+    pub(super) fn psa_generate_random_internal(
+        &self,
+        op: psa_generate_random::Operation,
+    ) -> Result<psa_generate_random::Result> {
+        let buffer_size = op.size;
+        if buffer_size > crate::utils::GlobalConfig::buffer_size_limit() {
+            let error = ResponseStatus::ResponseTooLarge;
+            format_error!("Generate random status", error);
+            return Err(error);
+        }
+
+        let mut state = self
+            .random_state
+            .lock()
+            .expect("Random state lock poisoned");
+        let mut buffer = Vec::with_capacity(buffer_size + 8);
+        while buffer.len() < buffer_size {
+            buffer.extend_from_slice(&splitmix64(&mut state).to_be_bytes());
+        }
+        buffer.truncate(buffer_size);
+
+        Ok(psa_generate_random::Result {
+            random_bytes: buffer.into(),
+        })
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+use super::{derive_bytes, Provider};
+use crate::authenticators::ApplicationIdentity;
+use crate::key_info_managers::KeyIdentity;
+use crate::providers::utils::key_destruction::confirm_destruction;
+use log::error;
+use parsec_interface::operations::psa_key_attributes::{Attributes, Type};
+use parsec_interface::operations::{
+    psa_destroy_key, psa_export_key, psa_export_public_key, psa_generate_key, psa_import_key,
+};
+use parsec_interface::requests::{ResponseStatus, Result};
+use parsec_interface::secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// Size of the key material when the `bits` attribute is not set
+const DEFAULT_KEY_SIZE: usize = 32;
+
+/// Key material as stored in the Key Info Manager
+///
+/// A test provider has no secure storage so the material itself is stored as the key ID.
+#[derive(Debug, Clone, Serialize, Deserialize, Zeroize)]
+#[zeroize(drop)]
+pub(super) struct TestKey {
+    /// Private (or symmetric) part of the key, empty for public keys
+    pub(super) material: Vec<u8>,
+    /// Public part of the key, empty for symmetric keys
+    pub(super) public: Vec<u8>,
+}
+
+fn key_size(attributes: &Attributes) -> usize {
+    if attributes.bits == 0 {
+        DEFAULT_KEY_SIZE
+    } else {
+        (attributes.bits + 7) / 8
+    }
+}
+
+impl Provider {
+    fn public_part(&self, material: &[u8], attributes: &Attributes) -> Vec<u8> {
+        if attributes.key_type.is_public_key() {
+            material.to_vec()
+        } else if matches!(
+            attributes.key_type,
+            Type::RsaKeyPair | Type::EccKeyPair { .. } | Type::DhKeyPair { .. }
+        ) {
+            derive_bytes(self.seed, &[b"public", material], key_size(attributes))
+        } else {
+            Vec::new()
+        }
+    }
+
+    pub(super) fn get_key(&self, key_identity: &KeyIdentity) -> Result<(TestKey, Attributes)> {
+        let key = self.key_info_store.get_key_id(key_identity)?;
+        let attributes = self.key_info_store.get_key_attributes(key_identity)?;
+        Ok((key, attributes))
+    }
+
+    pub(super) fn psa_generate_key_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_generate_key::Operation,
+    ) -> Result<psa_generate_key::Result> {
+        let key_identity = self.key_identity(application_identity, op.key_name);
+        self.key_info_store.does_not_exist(&key_identity)?;
+
+        if op.attributes.key_type.is_public_key() {
+            error!("A public key can not be generated.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+
+        // The material only depends on the seed and on the identity of the key, so that
+        // generating the same key twice gives the same result.
+        let material = derive_bytes(
+            self.seed,
+            &[
+                application_identity.name().as_bytes(),
+                key_identity.key_name().as_bytes(),
+            ],
+            key_size(&op.attributes),
+        );
+        let key = TestKey {
+            public: self.public_part(&material, &op.attributes),
+            material,
+        };
+        self.key_info_store
+            .insert_key_info(key_identity, &key, op.attributes)?;
+
+        Ok(psa_generate_key::Result {})
+    }
+
+    pub(super) fn psa_import_key_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_import_key::Operation,
+    ) -> Result<psa_import_key::Result> {
+        let key_identity = self.key_identity(application_identity, op.key_name);
+        self.key_info_store.does_not_exist(&key_identity)?;
+
+        let data = op.data.expose_secret();
+        if data.is_empty() {
+            error!("Key data is empty");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+
+        let key = if op.attributes.key_type.is_public_key() {
+            TestKey {
+                material: Vec::new(),
+                public: data.clone(),
+            }
+        } else {
+            TestKey {
+                public: self.public_part(data, &op.attributes),
+                material: data.clone(),
+            }
+        };
+        self.key_info_store
+            .insert_key_info(key_identity, &key, op.attributes)?;
+
+        Ok(psa_import_key::Result {})
+    }
+
+    pub(super) fn psa_export_public_key_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_public_key::Operation,
+    ) -> Result<psa_export_public_key::Result> {
+        let key_identity = self.key_identity(application_identity, op.key_name);
+        let (key, _) = self.get_key(&key_identity)?;
+        if key.public.is_empty() {
+            error!("The key does not have a public part.");
+            return Err(ResponseStatus::PsaErrorInvalidArgument);
+        }
+
+        Ok(psa_export_public_key::Result {
+            data: key.public.clone().into(),
+        })
+    }
+
+    pub(super) fn psa_export_key_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_key::Operation,
+    ) -> Result<psa_export_key::Result> {
+        let key_identity = self.key_identity(application_identity, op.key_name);
+        let (key, attributes) = self.get_key(&key_identity)?;
+        attributes.can_export()?;
+
+        let data = if attributes.key_type.is_public_key() {
+            key.public.clone()
+        } else {
+            key.material.clone()
+        };
+        Ok(psa_export_key::Result { data: data.into() })
+    }
+
+    pub(super) fn psa_destroy_key_internal(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_destroy_key::Operation,
+    ) -> Result<psa_destroy_key::Result> {
+        let key_identity = self.key_identity(application_identity, op.key_name.clone());
+        self.key_info_store.remove_key_info(&key_identity)?;
+        // The material is stored in the mapping, which must be gone.
+        confirm_destruction(&op.key_name, self.get_key(&key_identity))?;
+
+        Ok(psa_destroy_key::Result {})
+    }
+}
@@ -0,0 +1,748 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Deterministic test provider
+//!
+//! This provider does not perform any real cryptography: key material, signatures and random
+//! bytes are all derived deterministically from a configurable seed. It allows exercising the
+//! rest of the service (front end, dispatcher, authenticators, key info managers) without any
+//! hardware, simulator or software token.
+//!
+//! Faults can be injected to test how clients and the service react to failing or slow
+//! providers: the next N requests can be made to fail and a delay can be added to all requests.
+//!
+//! A conformance scenario can be configured, for the authors of client libraries to check that
+//! their library sends the expected requests and handles the scripted responses, see the
+//! `scenario` module.
+//!
+//! As there is no dedicated provider ID for it in the wire protocol, this provider answers to the
+//! Mbed Crypto provider ID, so that existing clients can use it without modification. It can
+//! hence not be used alongside an Mbed Crypto provider and MUST NOT be used in production.
+use super::Provide;
+use crate::authenticators::ApplicationIdentity;
+use crate::key_info_managers::{KeyIdentity, KeyInfoManagerClient};
+use crate::providers::ProviderIdentity;
+use derivative::Derivative;
+use log::{error, trace, warn};
+use parsec_interface::operations::list_providers::Uuid;
+use parsec_interface::operations::{list_clients, list_keys, list_providers::ProviderInfo};
+use parsec_interface::operations::{
+    psa_destroy_key, psa_export_key, psa_export_public_key, psa_generate_key, psa_generate_random,
+    psa_import_key, psa_sign_hash, psa_verify_hash,
+};
+use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
+use scenario::Scenario;
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+mod asym_sign;
+mod generate_random;
+mod key_management;
+mod scenario;
+
+const SUPPORTED_OPCODES: [Opcode; 8] = [
+    Opcode::PsaGenerateKey,
+    Opcode::PsaDestroyKey,
+    Opcode::PsaImportKey,
+    Opcode::PsaExportKey,
+    Opcode::PsaExportPublicKey,
+    Opcode::PsaSignHash,
+    Opcode::PsaVerifyHash,
+    Opcode::PsaGenerateRandom,
+];
+
+/// Default seed used when none is configured
+const DEFAULT_SEED: u64 = 0x5041_5253_4543_5445;
+
+/// Faults injected in the requests handled by the provider
+#[derive(Debug)]
+struct Faults {
+    // Number of the following requests which will fail
+    fail_next: AtomicU32,
+    // Delay applied to every request, in milliseconds
+    delay: AtomicU64,
+}
+
+impl Faults {
+    /// Applies the configured delay and returns an error if the request should fail.
+    fn inject(&self) -> Result<()> {
+        let delay = self.delay.load(Ordering::Relaxed);
+        if delay != 0 {
+            thread::sleep(Duration::from_millis(delay));
+        }
+
+        let previous = self
+            .fail_next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            });
+        if previous.is_ok() {
+            warn!("Injecting a failure in the test provider.");
+            return Err(ResponseStatus::PsaErrorCommunicationFailure);
+        }
+
+        Ok(())
+    }
+}
+
+/// Deterministic test provider structure
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Provider {
+    // The identity of the provider including uuid & name.
+    provider_identity: ProviderIdentity,
+    #[derivative(Debug = "ignore")]
+    key_info_store: KeyInfoManagerClient,
+    // Seed from which all the material is derived
+    seed: u64,
+    // State of the generator used for GenerateRandom
+    random_state: Mutex<u64>,
+    faults: Faults,
+    // Conformance scenario followed by the requests
+    scenario: Option<Scenario>,
+}
+
+impl Provider {
+    /// The default provider name for the test provider
+    pub const DEFAULT_PROVIDER_NAME: &'static str = "test-provider";
+
+    /// The UUID for this provider
+    pub const PROVIDER_UUID: &'static str = "4bb5e0b3-0f4a-4e0b-9e24-5d1c1d8f2c7a";
+
+    fn new(
+        provider_name: String,
+        key_info_store: KeyInfoManagerClient,
+        seed: u64,
+        fail_next: u32,
+        delay: u64,
+        scenario: Option<Scenario>,
+    ) -> Provider {
+        warn!("The test provider does not offer any security and must only be used for testing.");
+        Provider {
+            provider_identity: ProviderIdentity {
+                name: provider_name,
+                uuid: String::from(Self::PROVIDER_UUID),
+            },
+            key_info_store,
+            seed,
+            random_state: Mutex::new(seed),
+            faults: Faults {
+                fail_next: AtomicU32::new(fail_next),
+                delay: AtomicU64::new(delay),
+            },
+            scenario,
+        }
+    }
+
+    /// Injects the configured faults and checks the request against the conformance scenario.
+    fn before_request(&self, opcode: Opcode, key_name: Option<&str>) -> Result<()> {
+        self.faults.inject()?;
+        match &self.scenario {
+            Some(scenario) => scenario.check(opcode, key_name),
+            None => Ok(()),
+        }
+    }
+
+    fn key_identity(
+        &self,
+        application_identity: &ApplicationIdentity,
+        key_name: String,
+    ) -> KeyIdentity {
+        KeyIdentity::new(
+            application_identity.clone(),
+            self.provider_identity.clone(),
+            key_name,
+        )
+    }
+}
+
+/// Derives `len` bytes from the seed and the context given.
+///
+/// The bytes are produced by a SplitMix64 generator seeded with the FNV-1a hash of the context.
+/// This is NOT cryptographically secure.
+fn derive_bytes(seed: u64, context: &[&[u8]], len: usize) -> Vec<u8> {
+    let mut state = seed;
+    for part in context {
+        // Hash the length as well so that the boundaries between parts are unambiguous.
+        for byte in part.len().to_le_bytes().iter().chain(part.iter()) {
+            state ^= u64::from(*byte);
+            state = state.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        bytes.extend_from_slice(&splitmix64(&mut state).to_be_bytes());
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Provide for Provider {
+    fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+        trace!("describe ingress");
+        Ok((
+            ProviderInfo {
+                // Assigned UUID for this provider: 4bb5e0b3-0f4a-4e0b-9e24-5d1c1d8f2c7a
+                uuid: Uuid::from_str(Self::PROVIDER_UUID)
+                    .or(Err(ResponseStatus::InvalidEncoding))?,
+                description: String::from(
+                    "Deterministic provider for testing, offering no security",
+                ),
+                vendor: String::from("Parsec"),
+                version_maj: 0,
+                version_min: 1,
+                version_rev: 0,
+                id: ProviderId::MbedCrypto,
+            },
+            SUPPORTED_OPCODES.iter().copied().collect(),
+        ))
+    }
+
+    fn list_keys(
+        &self,
+        application_identity: &ApplicationIdentity,
+        _op: list_keys::Operation,
+    ) -> Result<list_keys::Result> {
+        Ok(list_keys::Result {
+            keys: self.key_info_store.list_keys(application_identity)?,
+        })
+    }
+
+    fn list_clients(&self, _op: list_clients::Operation) -> Result<list_clients::Result> {
+        Ok(list_clients::Result {
+            clients: self
+                .key_info_store
+                .list_clients()?
+                .into_iter()
+                .map(|application_identity| application_identity.name().clone())
+                .collect(),
+        })
+    }
+
+    fn psa_generate_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_generate_key::Operation,
+    ) -> Result<psa_generate_key::Result> {
+        trace!("psa_generate_key ingress");
+        self.before_request(Opcode::PsaGenerateKey, Some(&op.key_name))?;
+        self.psa_generate_key_internal(application_identity, op)
+    }
+
+    fn psa_import_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_import_key::Operation,
+    ) -> Result<psa_import_key::Result> {
+        trace!("psa_import_key ingress");
+        self.before_request(Opcode::PsaImportKey, Some(&op.key_name))?;
+        self.psa_import_key_internal(application_identity, op)
+    }
+
+    fn psa_export_public_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_public_key::Operation,
+    ) -> Result<psa_export_public_key::Result> {
+        trace!("psa_export_public_key ingress");
+        self.before_request(Opcode::PsaExportPublicKey, Some(&op.key_name))?;
+        self.psa_export_public_key_internal(application_identity, op)
+    }
+
+    fn psa_export_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_key::Operation,
+    ) -> Result<psa_export_key::Result> {
+        trace!("psa_export_key ingress");
+        self.before_request(Opcode::PsaExportKey, Some(&op.key_name))?;
+        self.psa_export_key_internal(application_identity, op)
+    }
+
+    fn psa_destroy_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_destroy_key::Operation,
+    ) -> Result<psa_destroy_key::Result> {
+        trace!("psa_destroy_key ingress");
+        self.before_request(Opcode::PsaDestroyKey, Some(&op.key_name))?;
+        self.psa_destroy_key_internal(application_identity, op)
+    }
+
+    fn psa_sign_hash(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_hash::Operation,
+    ) -> Result<psa_sign_hash::Result> {
+        trace!("psa_sign_hash ingress");
+        self.before_request(Opcode::PsaSignHash, Some(&op.key_name))?;
+        self.psa_sign_hash_internal(application_identity, op)
+    }
+
+    fn psa_verify_hash(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_verify_hash::Operation,
+    ) -> Result<psa_verify_hash::Result> {
+        trace!("psa_verify_hash ingress");
+        self.before_request(Opcode::PsaVerifyHash, Some(&op.key_name))?;
+        self.psa_verify_hash_internal(application_identity, op)
+    }
+
+    fn psa_generate_random(
+        &self,
+        op: psa_generate_random::Operation,
+    ) -> Result<psa_generate_random::Result> {
+        trace!("psa_generate_random ingress");
+        self.before_request(Opcode::PsaGenerateRandom, None)?;
+        self.psa_generate_random_internal(op)
+    }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        Some(&self.key_info_store)
+    }
+}
+
+/// Test provider builder
+#[derive(Default, Derivative)]
+#[derivative(Debug)]
+pub struct ProviderBuilder {
+    provider_name: Option<String>,
+    #[derivative(Debug = "ignore")]
+    key_info_store: Option<KeyInfoManagerClient>,
+    seed: Option<u64>,
+    fail_next: Option<u32>,
+    delay: Option<u64>,
+    scenario_path: Option<String>,
+    scenario_report_path: Option<String>,
+}
+
+impl ProviderBuilder {
+    /// Create a new provider builder
+    pub fn new() -> ProviderBuilder {
+        ProviderBuilder {
+            provider_name: None,
+            key_info_store: None,
+            seed: None,
+            fail_next: None,
+            delay: None,
+            scenario_path: None,
+            scenario_report_path: None,
+        }
+    }
+
+    /// Add a provider name
+    pub fn with_provider_name(mut self, provider_name: String) -> ProviderBuilder {
+        self.provider_name = Some(provider_name);
+
+        self
+    }
+
+    /// Add a KeyInfo manager
+    pub fn with_key_info_store(mut self, key_info_store: KeyInfoManagerClient) -> ProviderBuilder {
+        self.key_info_store = Some(key_info_store);
+
+        self
+    }
+
+    /// Specify the seed from which the key material and random bytes are derived
+    pub fn with_seed(mut self, seed: Option<u64>) -> ProviderBuilder {
+        self.seed = seed;
+
+        self
+    }
+
+    /// Specify the number of requests to fail after start-up
+    pub fn with_fail_next(mut self, fail_next: Option<u32>) -> ProviderBuilder {
+        self.fail_next = fail_next;
+
+        self
+    }
+
+    /// Specify the delay (in milliseconds) applied to all requests
+    pub fn with_delay(mut self, delay: Option<u64>) -> ProviderBuilder {
+        self.delay = delay;
+
+        self
+    }
+
+    /// Specify the path of the conformance scenario followed by the requests
+    pub fn with_scenario_path(mut self, scenario_path: Option<String>) -> ProviderBuilder {
+        self.scenario_path = scenario_path;
+
+        self
+    }
+
+    /// Specify the path of the file where the progress of the conformance scenario is written
+    pub fn with_scenario_report_path(
+        mut self,
+        scenario_report_path: Option<String>,
+    ) -> ProviderBuilder {
+        self.scenario_report_path = scenario_report_path;
+
+        self
+    }
+
+    /// Build into a test Provider
+    pub fn build(self) -> std::io::Result<Provider> {
+        let provider_name = self
+            .provider_name
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing provider name"))?;
+        let key_info_store = self.key_info_store.ok_or_else(|| {
+            error!("The key info store is missing.");
+            Error::new(ErrorKind::InvalidData, "missing key info store")
+        })?;
+        let scenario = match self.scenario_path {
+            Some(scenario_path) => Some(Scenario::parse(
+                &std::fs::read_to_string(scenario_path)?,
+                &SUPPORTED_OPCODES,
+                self.scenario_report_path,
+            )?),
+            None => None,
+        };
+
+        Ok(Provider::new(
+            provider_name,
+            key_info_store,
+            self.seed.unwrap_or(DEFAULT_SEED),
+            self.fail_next.unwrap_or(0),
+            self.delay.unwrap_or(0),
+            scenario,
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "sqlite-kim"))]
+mod test {
+    use super::*;
+    use crate::key_info_managers::KeyInfoManagerFactory;
+    use crate::utils::config::{KeyInfoManagerConfig, KeyInfoManagerType};
+    use parsec_interface::operations::psa_algorithm::{
+        Algorithm, AsymmetricSignature, Hash, SignHash,
+    };
+    use parsec_interface::operations::psa_key_attributes::{
+        Attributes, EccFamily, Lifetime, Policy, Type, UsageFlags,
+    };
+    use parsec_interface::requests::AuthType;
+    use parsec_interface::secrecy::{ExposeSecret, Secret};
+    use std::time::Instant;
+
+    fn build_provider(seed: u64) -> (tempfile::TempDir, Provider) {
+        build(ProviderBuilder::new().with_seed(Some(seed)))
+    }
+
+    fn build(builder: ProviderBuilder) -> (tempfile::TempDir, Provider) {
+        let dir = tempfile::tempdir().unwrap();
+        let factory = KeyInfoManagerFactory::new(
+            &KeyInfoManagerConfig {
+                name: String::from("sqlite-manager"),
+                manager_type: KeyInfoManagerType::SQLite,
+                store_path: None,
+                sqlite_db_path: Some(dir.path().join("test.sqlite3").to_str().unwrap().into()),
+                application_quota: None,
+                integrity_key_path: None,
+            },
+            AuthType::Direct,
+        )
+        .unwrap();
+        let provider_identity = ProviderIdentity::new(
+            Provider::PROVIDER_UUID.to_string(),
+            Provider::DEFAULT_PROVIDER_NAME.to_string(),
+        );
+
+        let provider = builder
+            .with_provider_name(Provider::DEFAULT_PROVIDER_NAME.to_string())
+            .with_key_info_store(factory.build_client(provider_identity))
+            .build()
+            .unwrap();
+        (dir, provider)
+    }
+
+    fn app() -> ApplicationIdentity {
+        ApplicationIdentity::new(String::from("app"), AuthType::Direct)
+    }
+
+    fn attributes(key_type: Type, bits: usize) -> Attributes {
+        let mut usage_flags = UsageFlags::default();
+        let _ = usage_flags.set_sign_hash().set_verify_hash().set_export();
+        Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type,
+            bits,
+            policy: Policy {
+                usage_flags,
+                permitted_algorithms: Algorithm::AsymmetricSignature(AsymmetricSignature::Ecdsa {
+                    hash_alg: SignHash::Specific(Hash::Sha256),
+                }),
+            },
+        }
+    }
+
+    fn ecc_key_pair() -> Attributes {
+        attributes(
+            Type::EccKeyPair {
+                curve_family: EccFamily::SecpR1,
+            },
+            256,
+        )
+    }
+
+    fn generate(provider: &Provider, key_name: &str, attributes: Attributes) -> Result<()> {
+        provider
+            .psa_generate_key(
+                &app(),
+                psa_generate_key::Operation {
+                    key_name: key_name.to_string(),
+                    attributes,
+                },
+            )
+            .map(|_| ())
+    }
+
+    fn import(provider: &Provider, key_name: &str, attributes: Attributes, data: &[u8]) {
+        let _ = provider
+            .psa_import_key(
+                &app(),
+                psa_import_key::Operation {
+                    key_name: key_name.to_string(),
+                    attributes,
+                    data: Secret::new(data.to_vec()),
+                },
+            )
+            .unwrap();
+    }
+
+    fn export_public(provider: &Provider, key_name: &str) -> Result<Vec<u8>> {
+        provider
+            .psa_export_public_key(
+                &app(),
+                psa_export_public_key::Operation {
+                    key_name: key_name.to_string(),
+                },
+            )
+            .map(|result| result.data.to_vec())
+    }
+
+    fn sign(provider: &Provider, key_name: &str) -> Vec<u8> {
+        provider
+            .psa_sign_hash(
+                &app(),
+                psa_sign_hash::Operation {
+                    key_name: key_name.to_string(),
+                    alg: AsymmetricSignature::Ecdsa {
+                        hash_alg: SignHash::Specific(Hash::Sha256),
+                    },
+                    hash: vec![0xa5; 32].into(),
+                },
+            )
+            .unwrap()
+            .signature
+            .to_vec()
+    }
+
+    fn verify(provider: &Provider, key_name: &str, signature: Vec<u8>) -> Result<()> {
+        provider
+            .psa_verify_hash(
+                &app(),
+                psa_verify_hash::Operation {
+                    key_name: key_name.to_string(),
+                    alg: AsymmetricSignature::Ecdsa {
+                        hash_alg: SignHash::Specific(Hash::Sha256),
+                    },
+                    hash: vec![0xa5; 32].into(),
+                    signature: signature.into(),
+                },
+            )
+            .map(|_| ())
+    }
+
+    fn random(provider: &Provider) -> Vec<u8> {
+        provider
+            .psa_generate_random(psa_generate_random::Operation { size: 10 })
+            .unwrap()
+            .random_bytes
+            .to_vec()
+    }
+
+    #[test]
+    fn splitmix64_known_answer() {
+        let mut state = 0;
+        assert_eq!(splitmix64(&mut state), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(splitmix64(&mut state), 0x6e78_9e6a_a1b9_65f4);
+    }
+
+    #[test]
+    fn derived_bytes_depend_on_context_boundaries() {
+        assert_eq!(
+            derive_bytes(42, &[b"ab", b"c"], 20),
+            derive_bytes(42, &[b"ab", b"c"], 20)
+        );
+        assert_ne!(
+            derive_bytes(42, &[b"ab", b"c"], 20),
+            derive_bytes(42, &[b"a", b"bc"], 20)
+        );
+        assert_ne!(
+            derive_bytes(42, &[b"ab", b"c"], 20),
+            derive_bytes(43, &[b"ab", b"c"], 20)
+        );
+        assert_eq!(derive_bytes(42, &[b"ab"], 13).len(), 13);
+    }
+
+    #[test]
+    fn same_seed_same_signatures() {
+        let (_dir_1, provider_1) = build_provider(42);
+        let (_dir_2, provider_2) = build_provider(42);
+        let (_dir_3, provider_3) = build_provider(43);
+        for provider in [&provider_1, &provider_2, &provider_3].iter() {
+            generate(provider, "key", ecc_key_pair()).unwrap();
+        }
+
+        let signature = sign(&provider_1, "key");
+        // r || s of a 256 bits curve
+        assert_eq!(signature.len(), 64);
+        assert_eq!(signature, sign(&provider_2, "key"));
+        assert_ne!(signature, sign(&provider_3, "key"));
+    }
+
+    #[test]
+    fn signatures_are_verified() {
+        let (_dir, provider) = build_provider(42);
+        generate(&provider, "key", ecc_key_pair()).unwrap();
+        let mut signature = sign(&provider, "key");
+        verify(&provider, "key", signature.clone()).unwrap();
+        signature[0] ^= 1;
+        assert_eq!(
+            verify(&provider, "key", signature).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidSignature
+        );
+    }
+
+    #[test]
+    fn imported_public_key_verifies() {
+        let (_dir, provider) = build_provider(42);
+        generate(&provider, "key", ecc_key_pair()).unwrap();
+        let public = export_public(&provider, "key").unwrap();
+        import(
+            &provider,
+            "public",
+            attributes(
+                Type::EccPublicKey {
+                    curve_family: EccFamily::SecpR1,
+                },
+                256,
+            ),
+            &public,
+        );
+        verify(&provider, "public", sign(&provider, "key")).unwrap();
+    }
+
+    #[test]
+    fn same_seed_same_random() {
+        let (_dir_1, provider_1) = build_provider(42);
+        let (_dir_2, provider_2) = build_provider(42);
+        let (_dir_3, provider_3) = build_provider(43);
+        let bytes = random(&provider_1);
+        assert_eq!(bytes.len(), 10);
+        assert_eq!(bytes, random(&provider_2));
+        assert_ne!(bytes, random(&provider_3));
+        // The generator moves on.
+        assert_ne!(bytes, random(&provider_1));
+    }
+
+    #[test]
+    fn imported_key_is_exported() {
+        let (_dir, provider) = build_provider(42);
+        import(&provider, "key", ecc_key_pair(), &[7; 32]);
+        let exported = provider
+            .psa_export_key(
+                &app(),
+                psa_export_key::Operation {
+                    key_name: String::from("key"),
+                },
+            )
+            .unwrap();
+        assert_eq!(exported.data.expose_secret(), &[7; 32]);
+        assert_eq!(export_public(&provider, "key").unwrap().len(), 32);
+    }
+
+    #[test]
+    fn public_keys_can_not_be_generated() {
+        let (_dir, provider) = build_provider(42);
+        let public = attributes(
+            Type::EccPublicKey {
+                curve_family: EccFamily::SecpR1,
+            },
+            256,
+        );
+        assert_eq!(
+            generate(&provider, "key", public).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn symmetric_keys_have_no_public_part() {
+        let (_dir, provider) = build_provider(42);
+        generate(&provider, "key", attributes(Type::Aes, 128)).unwrap();
+        assert_eq!(
+            export_public(&provider, "key").unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn destroyed_key_is_gone() {
+        let (_dir, provider) = build_provider(42);
+        generate(&provider, "key", ecc_key_pair()).unwrap();
+        assert_eq!(
+            generate(&provider, "key", ecc_key_pair()).unwrap_err(),
+            ResponseStatus::PsaErrorAlreadyExists
+        );
+        let _ = provider
+            .psa_destroy_key(
+                &app(),
+                psa_destroy_key::Operation {
+                    key_name: String::from("key"),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            export_public(&provider, "key").unwrap_err(),
+            ResponseStatus::PsaErrorDoesNotExist
+        );
+        generate(&provider, "key", ecc_key_pair()).unwrap();
+    }
+
+    #[test]
+    fn injected_failures() {
+        let (_dir, provider) = build(ProviderBuilder::new().with_fail_next(Some(2)));
+        for _ in 0..2 {
+            assert_eq!(
+                generate(&provider, "key", ecc_key_pair()).unwrap_err(),
+                ResponseStatus::PsaErrorCommunicationFailure
+            );
+        }
+        generate(&provider, "key", ecc_key_pair()).unwrap();
+    }
+
+    #[test]
+    fn injected_delay() {
+        let (_dir, provider) = build(ProviderBuilder::new().with_delay(Some(50)));
+        let start = Instant::now();
+        let _ = random(&provider);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
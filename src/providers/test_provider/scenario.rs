@@ -0,0 +1,267 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Conformance scenarios
+//!
+//! A scenario is a TOML file listing the requests a client library is expected to send, in order,
+//! so that its authors can check it against the service without any hardware. Each step gives the
+//! opcode of the request and, optionally, the name of the key it uses and the response status
+//! the provider answers with instead of handling the request:
+//!
+//! ```toml
+//! [[step]]
+//! opcode = "PsaGenerateKey"
+//! key_name = "conformance-key"
+//!
+//! [[step]]
+//! opcode = "PsaSignHash"
+//! key_name = "conformance-key"
+//! # PsaErrorInsufficientMemory
+//! status = 1141
+//! ```
+//!
+//! A request which does not match the next step, or comes after the last one, fails the scenario
+//! and is answered with `PsaErrorBadState`. The progress is logged and, if a report path is
+//! configured, written to that file after every request.
+use log::{error, info};
+use num_traits::FromPrimitive;
+use parsec_interface::requests::{Opcode, ResponseStatus, Result};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+
+#[derive(Deserialize, Debug)]
+struct ScenarioFile {
+    step: Vec<StepConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StepConfig {
+    opcode: String,
+    key_name: Option<String>,
+    status: Option<u16>,
+}
+
+#[derive(Debug)]
+struct Step {
+    opcode: Opcode,
+    key_name: Option<String>,
+    status: Option<ResponseStatus>,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    // Index of the next step
+    next: usize,
+    // Outcome of the requests received so far
+    outcomes: Vec<String>,
+    failed: bool,
+}
+
+/// Scenario followed by the requests sent to the provider
+#[derive(Debug)]
+pub(super) struct Scenario {
+    steps: Vec<Step>,
+    report_path: Option<String>,
+    progress: Mutex<Progress>,
+}
+
+impl Scenario {
+    /// Parse a scenario, for a provider handling the given opcodes.
+    pub(super) fn parse(
+        scenario: &str,
+        opcodes: &[Opcode],
+        report_path: Option<String>,
+    ) -> std::io::Result<Self> {
+        let scenario: ScenarioFile = toml::from_str(scenario).map_err(|e| {
+            format_error!("Failed to parse the conformance scenario", e);
+            Error::new(ErrorKind::InvalidData, "invalid conformance scenario")
+        })?;
+        let mut steps = Vec::with_capacity(scenario.step.len());
+        for (index, step) in scenario.step.into_iter().enumerate() {
+            let opcode = opcodes
+                .iter()
+                .copied()
+                .find(|opcode| format!("{:?}", opcode) == step.opcode)
+                .ok_or_else(|| {
+                    error!(
+                        "Step {} of the conformance scenario has an unsupported opcode: {}.",
+                        index + 1,
+                        step.opcode
+                    );
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        "invalid conformance scenario opcode",
+                    )
+                })?;
+            let status = match step.status {
+                None => None,
+                Some(code) => Some(ResponseStatus::from_u16(code).ok_or_else(|| {
+                    error!(
+                        "Step {} of the conformance scenario has an unknown status: {}.",
+                        index + 1,
+                        code
+                    );
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        "invalid conformance scenario status",
+                    )
+                })?),
+            };
+            steps.push(Step {
+                opcode,
+                key_name: step.key_name,
+                status,
+            });
+        }
+        info!("Conformance scenario of {} steps loaded.", steps.len());
+        Ok(Scenario {
+            steps,
+            report_path,
+            progress: Mutex::new(Progress::default()),
+        })
+    }
+
+    /// Check a request against the next step of the scenario. The request is handled by the
+    /// provider if `Ok` is returned.
+    pub(super) fn check(&self, opcode: Opcode, key_name: Option<&str>) -> Result<()> {
+        let mut progress = self
+            .progress
+            .lock()
+            .expect("Scenario progress lock poisoned");
+        let index = progress.next;
+        let received = match key_name {
+            Some(key_name) => format!("{:?} on key \"{}\"", opcode, key_name),
+            None => format!("{:?}", opcode),
+        };
+        let result = match self.steps.get(index) {
+            _ if progress.failed => Err(format!("{} received after the scenario failed", received)),
+            None => Err(format!(
+                "{} received after the end of the scenario",
+                received
+            )),
+            Some(step)
+                if step.opcode != opcode
+                    || (step.key_name.is_some() && step.key_name.as_deref() != key_name) =>
+            {
+                let expected = match &step.key_name {
+                    Some(key_name) => format!("{:?} on key \"{}\"", step.opcode, key_name),
+                    None => format!("{:?}", step.opcode),
+                };
+                Err(format!(
+                    "step {}: expected {}, received {}",
+                    index + 1,
+                    expected,
+                    received
+                ))
+            }
+            Some(step) => Ok(step.status),
+        };
+
+        let status = match result {
+            Ok(status) => {
+                progress.next += 1;
+                let outcome = format!("PASS step {}: {}", index + 1, received);
+                info!("Conformance scenario: {}.", outcome);
+                progress.outcomes.push(outcome);
+                status.map_or(Ok(()), Err)
+            }
+            Err(failure) => {
+                progress.failed = true;
+                let outcome = format!("FAIL {}", failure);
+                error!("Conformance scenario: {}.", outcome);
+                progress.outcomes.push(outcome);
+                Err(ResponseStatus::PsaErrorBadState)
+            }
+        };
+        self.write_report(&progress);
+        status
+    }
+
+    fn write_report(&self, progress: &Progress) {
+        let report_path = match &self.report_path {
+            Some(report_path) => report_path,
+            None => return,
+        };
+        let mut report = progress.outcomes.join("\n");
+        let _ = match (progress.failed, progress.next == self.steps.len()) {
+            (true, _) => write!(report, "\nRESULT: failed"),
+            (false, true) => write!(report, "\nRESULT: passed"),
+            (false, false) => write!(
+                report,
+                "\nRESULT: {} of {} steps done",
+                progress.next,
+                self.steps.len()
+            ),
+        };
+        report.push('\n');
+        if let Err(e) = std::fs::write(report_path, report) {
+            format_error!("Failed to write the conformance report", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCENARIO: &str = r#"
+        [[step]]
+        opcode = "PsaGenerateKey"
+        key_name = "key"
+
+        [[step]]
+        opcode = "PsaSignHash"
+        status = 1141
+
+        [[step]]
+        opcode = "PsaGenerateRandom"
+    "#;
+    const OPCODES: [Opcode; 3] = [
+        Opcode::PsaGenerateKey,
+        Opcode::PsaSignHash,
+        Opcode::PsaGenerateRandom,
+    ];
+
+    #[test]
+    fn requests_follow_the_steps() {
+        let report_path = format!("{}/conformance_report.txt", env!("OUT_DIR"));
+        let scenario = Scenario::parse(SCENARIO, &OPCODES, Some(report_path.clone())).unwrap();
+        scenario.check(Opcode::PsaGenerateKey, Some("key")).unwrap();
+        assert_eq!(
+            scenario.check(Opcode::PsaSignHash, Some("key")),
+            Err(ResponseStatus::PsaErrorInsufficientMemory)
+        );
+        scenario.check(Opcode::PsaGenerateRandom, None).unwrap();
+        let report = std::fs::read_to_string(&report_path).unwrap();
+        assert!(report.ends_with("RESULT: passed\n"));
+
+        assert_eq!(
+            scenario.check(Opcode::PsaGenerateRandom, None),
+            Err(ResponseStatus::PsaErrorBadState)
+        );
+        let report = std::fs::read_to_string(&report_path).unwrap();
+        assert!(report.contains("FAIL PsaGenerateRandom received after the end of the scenario"));
+        assert!(report.ends_with("RESULT: failed\n"));
+    }
+
+    #[test]
+    fn mismatches_fail_the_scenario() {
+        let scenario = Scenario::parse(SCENARIO, &OPCODES, None).unwrap();
+        assert_eq!(
+            scenario.check(Opcode::PsaGenerateKey, Some("other")),
+            Err(ResponseStatus::PsaErrorBadState)
+        );
+        assert_eq!(
+            scenario.check(Opcode::PsaGenerateKey, Some("key")),
+            Err(ResponseStatus::PsaErrorBadState)
+        );
+        assert!(Scenario::parse(SCENARIO, &OPCODES[..2], None).is_err());
+        assert!(Scenario::parse(
+            "[[step]]\nopcode = \"PsaSignHash\"\nstatus = 999",
+            &OPCODES,
+            None
+        )
+        .is_err());
+    }
+}
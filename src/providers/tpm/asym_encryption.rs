@@ -25,10 +25,7 @@ impl Provider {
         let password_context = self.get_key_ctx(&key_identity)?;
         let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
 
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
+        let mut esapi_context = self.esapi_context.acquire();
 
         op.validate(key_attributes)?;
 
@@ -46,13 +43,8 @@ impl Provider {
                 .clone()
                 .try_into()
                 .map_err(utils::to_response_status)?,
-            match op.salt {
-                Some(salt) => Some(
-                    salt.deref()
-                        .to_vec()
-                        .try_into()
-                        .map_err(utils::to_response_status)?,
-                ),
+            match utils::oaep_label(op.salt.as_deref().map(Vec::as_slice))? {
+                Some(label) => Some(label.try_into().map_err(utils::to_response_status)?),
                 None => None,
             },
         ) {
@@ -81,10 +73,7 @@ impl Provider {
         let password_context = self.get_key_ctx(&key_identity)?;
         let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
 
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
+        let mut esapi_context = self.esapi_context.acquire();
 
         op.validate(key_attributes)?;
 
@@ -102,13 +91,8 @@ impl Provider {
                 .clone()
                 .try_into()
                 .map_err(utils::to_response_status)?,
-            match op.salt {
-                Some(salt) => Some(
-                    salt.deref()
-                        .to_vec()
-                        .try_into()
-                        .map_err(utils::to_response_status)?,
-                ),
+            match utils::oaep_label(op.salt.as_deref().map(Vec::as_slice))? {
+                Some(label) => Some(label.try_into().map_err(utils::to_response_status)?),
                 None => None,
             },
         ) {
@@ -1,14 +1,17 @@
 // Copyright 2020 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
+use super::utils::PasswordContext;
 use super::{utils, Provider};
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
 use log::error;
 use parsec_interface::operations::psa_algorithm::*;
+use parsec_interface::operations::psa_key_attributes::Attributes;
 use parsec_interface::operations::{psa_sign_hash, psa_verify_hash};
 use parsec_interface::requests::{ResponseStatus, Result};
 use std::convert::TryFrom;
 use tss_esapi::structures::{Auth, Digest};
+use tss_esapi::TransientKeyContext;
 
 impl Provider {
     pub(super) fn psa_sign_hash_internal(
@@ -22,52 +25,26 @@ impl Provider {
             op.key_name.clone(),
         );
 
-        let password_context = self.get_key_ctx(&key_identity)?;
-        let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
-
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
-
-        match op.alg {
-            AsymmetricSignature::RsaPkcs1v15Sign { .. } => (),
-            AsymmetricSignature::Ecdsa { .. } => (),
-            _ => {
-                if crate::utils::GlobalConfig::log_error_details() {
-                    error!(
-                        "Requested algorithm is not supported by the TPM provider: {:?}",
-                        op.alg
-                    );
-                } else {
-                    error!("Requested algorithm is not supported by the TPM provider");
-                }
-                return Err(ResponseStatus::PsaErrorNotSupported);
-            }
-        }
-
-        op.validate(key_attributes)?;
-
-        let signature = esapi_context
+        self.sign_batches
             .sign(
-                password_context.key_material().clone(),
-                utils::parsec_to_tpm_params(key_attributes)?,
-                Some(
-                    Auth::try_from(password_context.auth_value())
-                        .map_err(utils::to_response_status)?,
-                ),
-                Digest::try_from((*op.hash).clone()).map_err(utils::to_response_status)?,
+                &key_identity,
+                op,
+                || {
+                    let password_context = self.get_key_ctx(&key_identity)?;
+                    let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
+                    Ok((
+                        password_context,
+                        key_attributes,
+                        self.esapi_context.acquire(),
+                    ))
+                },
+                |(password_context, key_attributes, esapi_context), op| {
+                    sign(esapi_context, password_context, *key_attributes, op)
+                },
             )
-            .map_err(|e| {
-                if crate::utils::GlobalConfig::log_error_details() {
-                    error!("Error signing: {}.", e);
-                }
-                utils::to_response_status(e)
-            })?;
-
-        Ok(psa_sign_hash::Result {
-            signature: utils::signature_data_to_bytes(signature, key_attributes)?.into(),
-        })
+            .map(|signature| psa_sign_hash::Result {
+                signature: signature.into(),
+            })
     }
 
     pub(super) fn psa_verify_hash_internal(
@@ -84,11 +61,6 @@ impl Provider {
         let password_context = self.get_key_ctx(&key_identity)?;
         let key_attributes = self.key_info_store.get_key_attributes(&key_identity)?;
 
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
-
         match op.alg {
             AsymmetricSignature::RsaPkcs1v15Sign { .. } => (),
             AsymmetricSignature::Ecdsa { .. } => (),
@@ -107,6 +79,24 @@ impl Provider {
 
         op.validate(key_attributes)?;
 
+        #[cfg(feature = "software-verifier")]
+        if self.software_verification {
+            let public_key = utils::pub_key_to_bytes(
+                password_context.key_material().public().clone(),
+                key_attributes,
+            )?;
+            return crate::providers::software_verifier::verify_hash(
+                key_attributes,
+                &public_key,
+                op.alg,
+                &op.hash,
+                &op.signature,
+            )
+            .map(|_| psa_verify_hash::Result {});
+        }
+
+        let mut esapi_context = self.esapi_context.acquire();
+
         let signature = utils::parsec_to_tpm_signature(op.signature, key_attributes, op.alg)?;
 
         let _ = esapi_context
@@ -121,3 +111,45 @@ impl Provider {
         Ok(psa_verify_hash::Result {})
     }
 }
+
+/// Sign a hash with a key already looked up.
+fn sign(
+    esapi_context: &mut TransientKeyContext,
+    password_context: &PasswordContext,
+    key_attributes: Attributes,
+    op: psa_sign_hash::Operation,
+) -> Result<Vec<u8>> {
+    match op.alg {
+        AsymmetricSignature::RsaPkcs1v15Sign { .. } => (),
+        AsymmetricSignature::Ecdsa { .. } => (),
+        _ => {
+            if crate::utils::GlobalConfig::log_error_details() {
+                error!(
+                    "Requested algorithm is not supported by the TPM provider: {:?}",
+                    op.alg
+                );
+            } else {
+                error!("Requested algorithm is not supported by the TPM provider");
+            }
+            return Err(ResponseStatus::PsaErrorNotSupported);
+        }
+    }
+
+    op.validate(key_attributes)?;
+
+    let signature = esapi_context
+        .sign(
+            password_context.key_material().clone(),
+            utils::parsec_to_tpm_params(key_attributes)?,
+            Some(Auth::try_from(password_context.auth_value()).map_err(utils::to_response_status)?),
+            Digest::try_from((*op.hash).clone()).map_err(utils::to_response_status)?,
+        )
+        .map_err(|e| {
+            if crate::utils::GlobalConfig::log_error_details() {
+                error!("Error signing: {}.", e);
+            }
+            utils::to_response_status(e)
+        })?;
+
+    utils::signature_data_to_bytes(signature, key_attributes)
+}
@@ -0,0 +1,299 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Generation and storage of the authValues of the keys
+//!
+//! Each key created in the TPM is given a random authValue of the configured length, which must be
+//! presented to use the key. By default the authValue is stored as is with the key in the Key Info
+//! Manager, so anyone reading the mappings and able to talk to the TPM can use the keys.
+//!
+//! When a secret is configured, the stored authValue is masked with a value derived from the
+//! secret and the private part of the key (HMAC-SHA256 over the TPM-encrypted private blob, which
+//! is unique to each key): a leak of the mappings alone does not give the authValues any more.
+//! Masked authValues are always stored on 33 bytes, the length of the authValue followed by the
+//! masked value padded to 32 bytes, which never clashes with a plain authValue of at most 32
+//! bytes. Keys stored before the secret was configured keep working. The secret file must be owned
+//! by the service and not accessible by anyone else.
+use super::utils::PasswordContext;
+use log::{error, warn};
+use parsec_interface::requests::{ResponseStatus, Result};
+use ring::hmac;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use tss_esapi::abstraction::transient::KeyMaterial;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Maximum length of an authValue accepted by the TPM software stack
+const MAX_AUTH_VALUE_LEN: usize = 32;
+/// Length of a masked authValue as stored
+const MASKED_LEN: usize = MAX_AUTH_VALUE_LEN + 1;
+const MIN_SECRET_LEN: usize = 32;
+/// Context of the derivation of the masks, changed with the format
+const MASK_CONTEXT: &[u8] = b"parsec tpm authValue v1";
+
+/// How the authValues of new keys are generated and stored
+#[derive(Debug)]
+pub(super) struct AuthValuePolicy {
+    len: usize,
+    secret: Option<hmac::Key>,
+}
+
+impl AuthValuePolicy {
+    /// Create the policy for authValues of the given length, masked by the secret read from the
+    /// given file if there is one.
+    pub(super) fn new(len: usize, secret_path: Option<&str>) -> std::io::Result<Self> {
+        if len > MAX_AUTH_VALUE_LEN {
+            error!(
+                "The authValues of the keys can be at most {} bytes long.",
+                MAX_AUTH_VALUE_LEN
+            );
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "invalid authValue length",
+            ));
+        }
+        if len == 0 {
+            warn!("The keys created in the TPM will not be protected by an authValue.");
+        }
+        let secret = match secret_path {
+            Some(secret_path) => {
+                let metadata = fs::metadata(secret_path)?;
+                // Safety: geteuid has no preconditions and cannot fail.
+                if metadata.uid() != unsafe { libc::geteuid() }
+                    || metadata.permissions().mode() & 0o077 != 0
+                {
+                    error!(
+                        "The authValue secret {} must be only accessible by the service.",
+                        secret_path
+                    );
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        "authValue secret accessible by others",
+                    ));
+                }
+                let secret = Zeroizing::new(fs::read(secret_path)?);
+                if secret.len() < MIN_SECRET_LEN {
+                    error!(
+                        "The authValue secret must be at least {} bytes long.",
+                        MIN_SECRET_LEN
+                    );
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "authValue secret too short",
+                    ));
+                }
+                Some(hmac::Key::new(hmac::HMAC_SHA256, &secret))
+            }
+            None => None,
+        };
+        Ok(AuthValuePolicy { len, secret })
+    }
+
+    /// Length of the authValues of new keys
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    fn mask(secret: &hmac::Key, key_material: &KeyMaterial) -> hmac::Tag {
+        let mut context = hmac::Context::with_key(secret);
+        context.update(MASK_CONTEXT);
+        context.update(key_material.private());
+        context.sign()
+    }
+
+    /// Replace the authValue of a key about to be stored by its masked form, if a secret is
+    /// configured.
+    pub(super) fn protect(&self, password_context: &mut PasswordContext) {
+        let secret = match &self.secret {
+            Some(secret) if !password_context.auth_value().is_empty() => secret,
+            _ => return,
+        };
+        let mask = AuthValuePolicy::mask(secret, password_context.key_material());
+        let auth_value = password_context.auth_value();
+        let mut masked = Vec::with_capacity(MASKED_LEN);
+        masked.push(auth_value.len() as u8);
+        masked.extend(
+            mask.as_ref()
+                .iter()
+                .zip(auth_value.iter().chain(std::iter::repeat(&0)))
+                .map(|(mask, byte)| mask ^ byte),
+        );
+        password_context.set_auth_value(masked);
+    }
+
+    /// Replace the authValue of a stored key by its plain form, if it was masked.
+    pub(super) fn recover(&self, password_context: &mut PasswordContext) -> Result<()> {
+        if password_context.auth_value().len() != MASKED_LEN {
+            return Ok(());
+        }
+        let secret = self.secret.as_ref().ok_or_else(|| {
+            error!("The authValue of the key is masked but no authValue secret is configured.");
+            ResponseStatus::PsaErrorGenericError
+        })?;
+        let mask = AuthValuePolicy::mask(secret, password_context.key_material());
+        let masked = password_context.auth_value();
+        let len = usize::from(masked[0]);
+        if len > MAX_AUTH_VALUE_LEN {
+            error!("The masked authValue of the key is invalid.");
+            return Err(ResponseStatus::PsaErrorDataCorrupt);
+        }
+        let mut auth_value: Vec<u8> = mask
+            .as_ref()
+            .iter()
+            .zip(masked[1..].iter())
+            .map(|(mask, byte)| mask ^ byte)
+            .collect();
+        auth_value[len..].zeroize();
+        auth_value.truncate(len);
+        password_context.set_auth_value(auth_value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AuthValuePolicy, MASKED_LEN};
+    use crate::providers::tpm::utils::PasswordContext;
+    use parsec_interface::requests::ResponseStatus;
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use tss_esapi::abstraction::transient::KeyMaterial;
+
+    fn write_secret(path: &Path, len: usize, mode: u32) {
+        fs::write(path, vec![0x5a; len]).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn policy(len: usize) -> (tempfile::TempDir, AuthValuePolicy) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        write_secret(&path, 32, 0o600);
+        let policy = AuthValuePolicy::new(len, Some(path.to_str().unwrap())).unwrap();
+        (dir, policy)
+    }
+
+    fn password_context(private: &[u8], auth_value: &[u8]) -> PasswordContext {
+        let key_material: KeyMaterial = toml::from_str(&format!(
+            "private = {:?}\n[public]\nRsa = [1, 0, 1]\n",
+            private
+        ))
+        .unwrap();
+        PasswordContext::new(key_material, auth_value.to_vec())
+    }
+
+    #[test]
+    fn protect_and_recover() {
+        let (_dir, policy) = policy(16);
+        let mut context = password_context(&[1, 2, 3], &[7; 16]);
+        policy.protect(&mut context);
+        assert_eq!(context.auth_value().len(), MASKED_LEN);
+        assert_eq!(context.auth_value()[0], 16);
+        policy.recover(&mut context).unwrap();
+        assert_eq!(context.auth_value(), &[7; 16]);
+    }
+
+    #[test]
+    fn mask_depends_on_private_part() {
+        let (_dir, policy) = policy(16);
+        let mut first = password_context(&[1, 2, 3], &[7; 16]);
+        let mut second = password_context(&[1, 2, 4], &[7; 16]);
+        let mut again = password_context(&[1, 2, 3], &[7; 16]);
+        policy.protect(&mut first);
+        policy.protect(&mut second);
+        policy.protect(&mut again);
+        assert_ne!(first.auth_value(), second.auth_value());
+        assert_eq!(first.auth_value(), again.auth_value());
+        assert_ne!(&first.auth_value()[1..17], &[7; 16]);
+    }
+
+    #[test]
+    fn plain_auth_values_are_kept() {
+        let (_dir, policy) = policy(16);
+        let mut context = password_context(&[1, 2, 3], &[7; 16]);
+        policy.recover(&mut context).unwrap();
+        assert_eq!(context.auth_value(), &[7; 16]);
+
+        let policy = AuthValuePolicy::new(16, None).unwrap();
+        policy.protect(&mut context);
+        assert_eq!(context.auth_value(), &[7; 16]);
+    }
+
+    #[test]
+    fn masked_auth_value_needs_secret() {
+        let (_dir, with_secret) = policy(16);
+        let mut context = password_context(&[1, 2, 3], &[7; 16]);
+        with_secret.protect(&mut context);
+        let without_secret = AuthValuePolicy::new(16, None).unwrap();
+        assert_eq!(
+            without_secret.recover(&mut context).unwrap_err(),
+            ResponseStatus::PsaErrorGenericError
+        );
+    }
+
+    #[test]
+    fn corrupt_masked_auth_value() {
+        let (_dir, policy) = policy(16);
+        let mut context = password_context(&[1, 2, 3], &[0xff; MASKED_LEN]);
+        assert_eq!(
+            policy.recover(&mut context).unwrap_err(),
+            ResponseStatus::PsaErrorDataCorrupt
+        );
+    }
+
+    #[test]
+    fn reject_accessible_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        for mode in [0o640, 0o604, 0o660] {
+            write_secret(&path, 32, mode);
+            assert_eq!(
+                AuthValuePolicy::new(16, Some(path.to_str().unwrap()))
+                    .unwrap_err()
+                    .kind(),
+                ErrorKind::PermissionDenied
+            );
+        }
+    }
+
+    #[test]
+    fn reject_foreign_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        write_secret(&path, 32, 0o600);
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        // Safety: the path is a valid NUL-terminated string.
+        if unsafe { libc::chown(c_path.as_ptr(), 65534, 65534) } != 0 {
+            // Only privileged users can give files away.
+            return;
+        }
+        assert_eq!(
+            AuthValuePolicy::new(16, Some(path.to_str().unwrap()))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn reject_short_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        write_secret(&path, 31, 0o600);
+        assert_eq!(
+            AuthValuePolicy::new(16, Some(path.to_str().unwrap()))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn reject_long_auth_value() {
+        assert_eq!(
+            AuthValuePolicy::new(33, None).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
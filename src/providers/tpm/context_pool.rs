@@ -0,0 +1,202 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Pool of ESAPI contexts
+//!
+//! When the TPM is accessed through a resource manager, several ESAPI contexts can be used
+//! concurrently and independent operations do not have to wait for each other. Each context is
+//! used by one thread at a time: a thread takes one out of the pool for the duration of an
+//! operation, waiting if they are all in use, and puts it back when done.
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tss_esapi::TransientKeyContext;
+
+/// Pool of ESAPI contexts shared between the threads of the service
+#[derive(Debug)]
+pub(super) struct ContextPool<C = TransientKeyContext> {
+    contexts: Mutex<Vec<C>>,
+    available: Condvar,
+    size: usize,
+    last_release: Mutex<Instant>,
+    // Name of the TCTI the contexts were opened with
+    tcti_name: String,
+}
+
+impl<C> ContextPool<C> {
+    /// Create a pool out of the given contexts, opened with the TCTI of the given name.
+    pub(super) fn new(contexts: Vec<C>, tcti_name: String) -> Self {
+        ContextPool {
+            size: contexts.len(),
+            contexts: Mutex::new(contexts),
+            available: Condvar::new(),
+            last_release: Mutex::new(Instant::now()),
+            tcti_name,
+        }
+    }
+
+    /// Name of the TCTI the contexts were opened with, such as "device" or "tabrmd"
+    pub(super) fn tcti_name(&self) -> &str {
+        &self.tcti_name
+    }
+
+    /// Take a context out of the pool, waiting for one to be available.
+    pub(super) fn acquire(&self) -> PooledContext<'_, C> {
+        let mut contexts = self
+            .contexts
+            .lock()
+            .expect("ESAPI Context pool lock poisoned");
+        loop {
+            if let Some(context) = contexts.pop() {
+                return PooledContext {
+                    pool: self,
+                    context: Some(context),
+                };
+            }
+            contexts = self
+                .available
+                .wait(contexts)
+                .expect("ESAPI Context pool lock poisoned");
+        }
+    }
+
+    /// Take a context out of the pool only if none is in use and none was used during the last
+    /// `idle` period, for background work that should not delay requests.
+    pub(super) fn try_acquire_idle(&self, idle: Duration) -> Option<PooledContext<'_, C>> {
+        let mut contexts = self
+            .contexts
+            .lock()
+            .expect("ESAPI Context pool lock poisoned");
+        let last_release = *self
+            .last_release
+            .lock()
+            .expect("ESAPI Context pool lock poisoned");
+        if contexts.len() < self.size || last_release.elapsed() < idle {
+            return None;
+        }
+        contexts.pop().map(|context| PooledContext {
+            pool: self,
+            context: Some(context),
+        })
+    }
+}
+
+/// ESAPI context taken out of the pool, put back when dropped
+#[derive(Debug)]
+pub(super) struct PooledContext<'a, C = TransientKeyContext> {
+    pool: &'a ContextPool<C>,
+    // Only None after being put back in drop.
+    context: Option<C>,
+}
+
+impl<C> Deref for PooledContext<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        self.context
+            .as_ref()
+            .expect("ESAPI Context already released")
+    }
+}
+
+impl<C> DerefMut for PooledContext<'_, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.context
+            .as_mut()
+            .expect("ESAPI Context already released")
+    }
+}
+
+impl<C> Drop for PooledContext<'_, C> {
+    fn drop(&mut self) {
+        if let Some(context) = self.context.take() {
+            *self
+                .pool
+                .last_release
+                .lock()
+                .expect("ESAPI Context pool lock poisoned") = Instant::now();
+            self.pool
+                .contexts
+                .lock()
+                .expect("ESAPI Context pool lock poisoned")
+                .push(context);
+            self.pool.available.notify_one();
+        }
+    }
+}
+
+/// Whether several contexts can be opened concurrently with the given TCTI: only resource
+/// managers, the in-kernel one (`/dev/tpmrm*`) or the userspace daemon, support that.
+pub(super) fn supports_concurrent_contexts(tcti: &str) -> bool {
+    match tcti.split_once(':') {
+        Some(("device", path)) => path.contains("tpmrm"),
+        None if tcti == "tabrmd" => true,
+        Some(("tabrmd", _)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{supports_concurrent_contexts, ContextPool};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    // The pool does not depend on the contexts it holds, integers stand for them.
+    fn pool(size: u32) -> ContextPool<u32> {
+        ContextPool::new((0..size).collect(), String::from("tabrmd"))
+    }
+
+    #[test]
+    fn contexts_are_put_back() {
+        let pool = pool(2);
+        let first = pool.acquire();
+        let second = pool.acquire();
+        assert_ne!(*first, *second);
+        drop(first);
+        drop(second);
+        assert_eq!(pool.contexts.lock().unwrap().len(), 2);
+        assert_eq!(pool.tcti_name(), "tabrmd");
+    }
+
+    #[test]
+    fn acquire_waits_for_a_context() {
+        let pool = &pool(1);
+        let (acquired, acquisition) = mpsc::channel();
+        thread::scope(|scope| {
+            let context = pool.acquire();
+            let _ = scope.spawn(move || {
+                let context = pool.acquire();
+                acquired.send(*context).unwrap();
+            });
+            assert!(acquisition.recv_timeout(Duration::from_millis(50)).is_err());
+            drop(context);
+            assert_eq!(acquisition.recv_timeout(Duration::from_secs(10)), Ok(0));
+        });
+    }
+
+    #[test]
+    fn background_work_waits_for_idle_pool() {
+        let pool = pool(2);
+        let context = pool.acquire();
+        assert!(pool.try_acquire_idle(Duration::ZERO).is_none());
+        drop(context);
+        assert!(pool.try_acquire_idle(Duration::from_secs(60)).is_none());
+        thread::sleep(Duration::from_millis(20));
+        assert!(pool.try_acquire_idle(Duration::from_millis(10)).is_some());
+    }
+
+    #[test]
+    fn resource_managers_support_concurrent_contexts() {
+        assert!(supports_concurrent_contexts("device:/dev/tpmrm0"));
+        assert!(supports_concurrent_contexts("tabrmd"));
+        assert!(supports_concurrent_contexts("tabrmd:bus_type=session"));
+    }
+
+    #[test]
+    fn direct_access_does_not() {
+        assert!(!supports_concurrent_contexts("device"));
+        assert!(!supports_concurrent_contexts("device:/dev/tpm0"));
+        assert!(!supports_concurrent_contexts("mssim:port=2321"));
+    }
+}
@@ -0,0 +1,344 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Provisioning of the device identity keys
+//!
+//! On first boot, the provider creates an Initial Attestation Key (IAK) and an Initial Device
+//! Identity key (IDevID) in the Endorsement Hierarchy, following the templates of the TCG "TPM 2.0
+//! Keys for Device Identity and Attestation" specification, and makes them persistent at the
+//! configured handles. The IAK then certifies the IDevID with TPM2_Certify. The public areas of
+//! both keys and the certification are written in marshalled TPM format to the artifacts
+//! directory, from where they are sent to the manufacturer CA which issues the certificates.
+//!
+//! Keys already present at their handle are kept, so the provisioning is only done once. The
+//! certification is produced again if its artifacts are missing.
+use crate::utils::config::{DeviceIdentityAlgorithm, DeviceIdentityConfig};
+use log::info;
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use tss_esapi::attributes::ObjectAttributesBuilder;
+use tss_esapi::handles::{KeyHandle, ObjectHandle, PersistentTpmHandle, TpmHandle};
+use tss_esapi::interface_types::algorithm::{
+    EccSchemeAlgorithm, HashingAlgorithm, PublicAlgorithm, RsaSchemeAlgorithm,
+};
+use tss_esapi::interface_types::dynamic_handles::Persistent;
+use tss_esapi::interface_types::ecc::EccCurve;
+use tss_esapi::interface_types::key_bits::RsaKeyBits;
+use tss_esapi::interface_types::resource_handles::{Hierarchy, Provision};
+use tss_esapi::interface_types::session_handles::AuthSession;
+use tss_esapi::structures::{
+    Data, EccParameter, EccPoint, EccScheme, KeyDerivationFunctionScheme, Public, PublicBuilder,
+    PublicEccParametersBuilder, PublicKeyRsa, PublicRsaParametersBuilder, RsaExponent, RsaScheme,
+    SignatureScheme, SymmetricDefinitionObject,
+};
+use tss_esapi::traits::Marshall;
+use tss_esapi::Context;
+
+/// Public area of the IAK
+const IAK_PUBLIC_FILE: &str = "iak.pub";
+/// Public area of the IDevID
+const IDEVID_PUBLIC_FILE: &str = "idevid.pub";
+/// Attestation structure of the certification of the IDevID by the IAK
+const IDEVID_CERTIFY_FILE: &str = "idevid.certify";
+/// Signature of the certification by the IAK
+const IDEVID_CERTIFY_SIGNATURE_FILE: &str = "idevid.certify.sig";
+
+/// Public template of a device identity key. The IAK is a restricted signing key, so that it only
+/// signs structures produced by the TPM; the IDevID signs any digest.
+fn template(algorithm: DeviceIdentityAlgorithm, restricted: bool) -> tss_esapi::Result<Public> {
+    let attributes = ObjectAttributesBuilder::new()
+        .with_fixed_tpm(true)
+        .with_fixed_parent(true)
+        .with_sensitive_data_origin(true)
+        .with_user_with_auth(true)
+        .with_restricted(restricted)
+        .with_sign_encrypt(true)
+        .with_decrypt(false)
+        .build()?;
+    match algorithm {
+        DeviceIdentityAlgorithm::Rsa2048 => {
+            let scheme = if restricted {
+                RsaScheme::create(RsaSchemeAlgorithm::RsaSsa, Some(HashingAlgorithm::Sha256))?
+            } else {
+                RsaScheme::Null
+            };
+            PublicBuilder::new()
+                .with_public_algorithm(PublicAlgorithm::Rsa)
+                .with_name_hashing_algorithm(HashingAlgorithm::Sha256)
+                .with_object_attributes(attributes)
+                .with_rsa_parameters(
+                    PublicRsaParametersBuilder::new()
+                        .with_symmetric(SymmetricDefinitionObject::Null)
+                        .with_scheme(scheme)
+                        .with_key_bits(RsaKeyBits::Rsa2048)
+                        .with_exponent(RsaExponent::default())
+                        .with_is_signing_key(true)
+                        .with_restricted(restricted)
+                        .build()?,
+                )
+                .with_rsa_unique_identifier(PublicKeyRsa::new_empty_with_size(RsaKeyBits::Rsa2048))
+                .build()
+        }
+        DeviceIdentityAlgorithm::EccP256 => {
+            let scheme = if restricted {
+                EccScheme::create(
+                    EccSchemeAlgorithm::EcDsa,
+                    Some(HashingAlgorithm::Sha256),
+                    None,
+                )?
+            } else {
+                EccScheme::Null
+            };
+            PublicBuilder::new()
+                .with_public_algorithm(PublicAlgorithm::Ecc)
+                .with_name_hashing_algorithm(HashingAlgorithm::Sha256)
+                .with_object_attributes(attributes)
+                .with_ecc_parameters(
+                    PublicEccParametersBuilder::new()
+                        .with_symmetric(SymmetricDefinitionObject::Null)
+                        .with_ecc_scheme(scheme)
+                        .with_curve(EccCurve::NistP256)
+                        .with_key_derivation_function_scheme(KeyDerivationFunctionScheme::Null)
+                        .with_is_signing_key(true)
+                        .with_restricted(restricted)
+                        .build()?,
+                )
+                .with_ecc_unique_identifier(EccPoint::new(
+                    EccParameter::try_from(vec![0u8; 32])?,
+                    EccParameter::try_from(vec![0u8; 32])?,
+                ))
+                .build()
+        }
+    }
+}
+
+/// Get the key persisted at the given handle, creating it from the template if there is none.
+fn provision_key(
+    context: &mut Context,
+    name: &str,
+    handle: u32,
+    public: Public,
+) -> std::io::Result<(KeyHandle, bool)> {
+    let persistent = PersistentTpmHandle::new(handle).map_err(|e| {
+        format_error!(format!("Invalid persistent handle for the {}", name), e);
+        Error::new(ErrorKind::InvalidData, "invalid device identity handle")
+    })?;
+    if let Ok(object) = context
+        .execute_without_session(|ctx| ctx.tr_from_tpm_public(TpmHandle::Persistent(persistent)))
+    {
+        info!(
+            "The {} is already persisted at handle {:#010x}.",
+            name, handle
+        );
+        return Ok((object.into(), false));
+    }
+
+    info!("Creating the {} at handle {:#010x}.", name, handle);
+    let transient = context
+        .create_primary(Hierarchy::Endorsement, public, None, None, None, None)
+        .map_err(|e| {
+            format_error!(format!("Failed to create the {}", name), e);
+            Error::new(ErrorKind::Other, "failed to create device identity key")
+        })?
+        .key_handle;
+    let persisted = context.evict_control(
+        Provision::Owner,
+        transient.into(),
+        Persistent::Persistent(persistent),
+    );
+    // The transient copy is not needed anymore, whether it was persisted or not.
+    let _ = context.flush_context(transient.into());
+    let object = persisted.map_err(|e| {
+        format_error!(format!("Failed to persist the {}", name), e);
+        Error::new(ErrorKind::Other, "failed to persist device identity key")
+    })?;
+    Ok((object.into(), true))
+}
+
+fn write_artifact(dir: &Path, file: &str, data: tss_esapi::Result<Vec<u8>>) -> std::io::Result<()> {
+    let data = data.map_err(|e| {
+        format_error!(format!("Failed to marshall {}", file), e);
+        Error::new(
+            ErrorKind::Other,
+            "failed to marshall device identity artifact",
+        )
+    })?;
+    std::fs::write(dir.join(file), data)
+}
+
+/// Provision the device identity keys, if needed, and write the artifacts for the CA.
+pub(super) fn provision(
+    context: &mut Context,
+    config: &DeviceIdentityConfig,
+) -> std::io::Result<()> {
+    let template = |restricted| {
+        template(config.algorithm, restricted).map_err(|e| {
+            format_error!("Invalid device identity template", e);
+            Error::new(ErrorKind::Other, "invalid device identity template")
+        })
+    };
+    let (iak, iak_created) = provision_key(context, "IAK", config.iak_handle, template(true)?)?;
+    let (idevid, idevid_created) =
+        provision_key(context, "IDevID", config.idevid_handle, template(false)?)?;
+
+    let dir = Path::new(&config.artifacts_dir);
+    if !iak_created
+        && !idevid_created
+        && dir.join(IDEVID_CERTIFY_FILE).exists()
+        && dir.join(IDEVID_CERTIFY_SIGNATURE_FILE).exists()
+    {
+        info!("The device identity keys are already provisioned.");
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let (iak_public, _, _) = context.read_public(iak).map_err(|e| {
+        format_error!("Failed to read the public area of the IAK", e);
+        Error::new(ErrorKind::Other, "failed to read device identity key")
+    })?;
+    let (idevid_public, _, _) = context.read_public(idevid).map_err(|e| {
+        format_error!("Failed to read the public area of the IDevID", e);
+        Error::new(ErrorKind::Other, "failed to read device identity key")
+    })?;
+    // Both keys have an empty authValue.
+    let (attest, signature) = context
+        .execute_with_sessions(
+            (
+                Some(AuthSession::Password),
+                Some(AuthSession::Password),
+                None,
+            ),
+            |ctx| {
+                ctx.certify(
+                    ObjectHandle::from(idevid),
+                    iak,
+                    Data::default(),
+                    SignatureScheme::Null,
+                )
+            },
+        )
+        .map_err(|e| {
+            format_error!("Failed to certify the IDevID with the IAK", e);
+            Error::new(ErrorKind::Other, "failed to certify device identity key")
+        })?;
+
+    write_artifact(dir, IAK_PUBLIC_FILE, iak_public.marshall())?;
+    write_artifact(dir, IDEVID_PUBLIC_FILE, idevid_public.marshall())?;
+    write_artifact(dir, IDEVID_CERTIFY_FILE, attest.marshall())?;
+    write_artifact(dir, IDEVID_CERTIFY_SIGNATURE_FILE, signature.marshall())?;
+    info!(
+        "The device identity artifacts were written to {}.",
+        config.artifacts_dir
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{template, write_artifact, IAK_PUBLIC_FILE};
+    use crate::utils::config::DeviceIdentityAlgorithm;
+    use tss_esapi::attributes::ObjectAttributes;
+    use tss_esapi::interface_types::algorithm::{
+        EccSchemeAlgorithm, HashingAlgorithm, RsaSchemeAlgorithm,
+    };
+    use tss_esapi::interface_types::ecc::EccCurve;
+    use tss_esapi::interface_types::key_bits::RsaKeyBits;
+    use tss_esapi::structures::{EccScheme, Public, RsaScheme};
+    use tss_esapi::WrapperErrorKind;
+
+    fn object_attributes(public: &Public) -> ObjectAttributes {
+        match public {
+            Public::Rsa {
+                object_attributes, ..
+            }
+            | Public::Ecc {
+                object_attributes, ..
+            } => *object_attributes,
+            _ => panic!("Unexpected key type"),
+        }
+    }
+
+    #[test]
+    fn keys_are_bound_to_the_tpm() {
+        for algorithm in &[
+            DeviceIdentityAlgorithm::Rsa2048,
+            DeviceIdentityAlgorithm::EccP256,
+        ] {
+            for restricted in &[true, false] {
+                let attributes = object_attributes(&template(*algorithm, *restricted).unwrap());
+                assert!(attributes.fixed_tpm());
+                assert!(attributes.fixed_parent());
+                assert!(attributes.sensitive_data_origin());
+                assert!(attributes.user_with_auth());
+                assert!(attributes.sign_encrypt());
+                assert!(!attributes.decrypt());
+                assert_eq!(attributes.restricted(), *restricted);
+            }
+        }
+    }
+
+    #[test]
+    fn rsa_templates() {
+        match template(DeviceIdentityAlgorithm::Rsa2048, true).unwrap() {
+            Public::Rsa { parameters, .. } => {
+                assert_eq!(parameters.key_bits(), RsaKeyBits::Rsa2048);
+                assert_eq!(
+                    parameters.rsa_scheme(),
+                    RsaScheme::create(RsaSchemeAlgorithm::RsaSsa, Some(HashingAlgorithm::Sha256))
+                        .unwrap()
+                );
+            }
+            _ => panic!("The IAK should be an RSA key"),
+        }
+        // The IDevID is not bound to a scheme, so that it can sign with any.
+        match template(DeviceIdentityAlgorithm::Rsa2048, false).unwrap() {
+            Public::Rsa { parameters, .. } => {
+                assert_eq!(parameters.key_bits(), RsaKeyBits::Rsa2048);
+                assert_eq!(parameters.rsa_scheme(), RsaScheme::Null);
+            }
+            _ => panic!("The IDevID should be an RSA key"),
+        }
+    }
+
+    #[test]
+    fn ecc_templates() {
+        match template(DeviceIdentityAlgorithm::EccP256, true).unwrap() {
+            Public::Ecc { parameters, .. } => {
+                assert_eq!(parameters.ecc_curve(), EccCurve::NistP256);
+                assert_eq!(
+                    parameters.ecc_scheme(),
+                    EccScheme::create(
+                        EccSchemeAlgorithm::EcDsa,
+                        Some(HashingAlgorithm::Sha256),
+                        None
+                    )
+                    .unwrap()
+                );
+            }
+            _ => panic!("The IAK should be an ECC key"),
+        }
+        match template(DeviceIdentityAlgorithm::EccP256, false).unwrap() {
+            Public::Ecc { parameters, .. } => {
+                assert_eq!(parameters.ecc_curve(), EccCurve::NistP256);
+                assert_eq!(parameters.ecc_scheme(), EccScheme::Null);
+            }
+            _ => panic!("The IDevID should be an ECC key"),
+        }
+    }
+
+    #[test]
+    fn artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        write_artifact(dir.path(), IAK_PUBLIC_FILE, Ok(vec![1, 2, 3])).unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join(IAK_PUBLIC_FILE)).unwrap(),
+            [1, 2, 3]
+        );
+
+        let failed = Err(tss_esapi::Error::WrapperError(
+            WrapperErrorKind::InvalidParam,
+        ));
+        assert!(write_artifact(dir.path(), "failed.pub", failed).is_err());
+        assert!(!dir.path().join("failed.pub").exists());
+    }
+}
@@ -12,10 +12,7 @@ impl Provider {
     ) -> Result<psa_generate_random::Result> {
         let size = op.size;
 
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
+        let mut esapi_context = self.esapi_context.acquire();
 
         let random_bytes = esapi_context
             .as_mut()
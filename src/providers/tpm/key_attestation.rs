@@ -67,10 +67,7 @@ impl Provider {
             params,
         };
 
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
+        let mut esapi_context = self.esapi_context.acquire();
 
         let params = esapi_context
             .get_make_cred_params(attested_key, None)
@@ -142,10 +139,7 @@ impl Provider {
             params,
         };
 
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
+        let mut esapi_context = self.esapi_context.acquire();
 
         let credential = esapi_context
             .activate_credential(
@@ -7,6 +7,7 @@ use super::utils::PasswordContext;
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
+use crate::providers::utils::key_destruction::confirm_destruction;
 use log::error;
 use parsec_interface::operations::psa_key_attributes::*;
 use parsec_interface::operations::utils_deprecated_primitives::CheckDeprecated;
@@ -17,11 +18,16 @@ use parsec_interface::requests::{ResponseStatus, Result};
 use parsec_interface::secrecy::ExposeSecret;
 use std::convert::TryInto;
 
-const AUTH_VAL_LEN: usize = 32;
-
 impl Provider {
-    #[allow(deprecated)]
+    /// Get the context of a key, with its plain authValue.
     pub(super) fn get_key_ctx(&self, key_identity: &KeyIdentity) -> Result<PasswordContext> {
+        let mut password_context = self.get_stored_key_ctx(key_identity)?;
+        self.auth_values.recover(&mut password_context)?;
+        Ok(password_context)
+    }
+
+    #[allow(deprecated)]
+    fn get_stored_key_ctx(&self, key_identity: &KeyIdentity) -> Result<PasswordContext> {
         // Try to deserialize into the new format
         self.key_info_store
             .get_key_id::<PasswordContext>(key_identity)
@@ -34,11 +40,8 @@ impl Provider {
                         .get_key_id::<LegacyPasswordContext>(key_identity)?;
 
                     // Try to migrate the key context to the new format
-                    let mut esapi_context = self
-                        .esapi_context
-                        .lock()
-                        .expect("ESAPI Context lock poisoned");
-                    let password_ctx = PasswordContext::new(
+                    let mut esapi_context = self.esapi_context.acquire();
+                    let mut password_ctx = PasswordContext::new(
                         esapi_context
                             .migrate_key_from_ctx(
                                 legacy_ctx.context,
@@ -55,6 +58,7 @@ impl Provider {
                     );
 
                     // Grab key attributes and replace legacy entry with new one
+                    self.auth_values.protect(&mut password_ctx);
                     let attributes = self.key_info_store.get_key_attributes(key_identity)?;
                     self.key_info_store.replace_key_info(
                         key_identity.clone(),
@@ -90,25 +94,33 @@ impl Provider {
             return Err(ResponseStatus::PsaErrorInvalidArgument);
         }
 
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
-
-        let (key_material, auth_value) = esapi_context
-            .create_key(utils::parsec_to_tpm_params(attributes)?, AUTH_VAL_LEN)
-            .map_err(|e| {
-                format_error!("Error creating a RSA signing key", e);
-                utils::to_response_status(e)
-            })?;
-        // We hardcode the AUTH_VAL_LEN, so we can assume there is an auth_value
-        let auth_value = auth_value.unwrap();
+        let pooled_key = self
+            .key_pool
+            .as_ref()
+            .and_then(|key_pool| key_pool.take(&attributes));
+        let mut password_context = match pooled_key {
+            Some(password_context) => password_context,
+            None => {
+                let mut esapi_context = self.esapi_context.acquire();
+
+                let (key_material, auth_value) = esapi_context
+                    .create_key(
+                        utils::parsec_to_tpm_params(attributes)?,
+                        self.auth_values.len(),
+                    )
+                    .map_err(|e| {
+                        format_error!("Error creating a RSA signing key", e);
+                        utils::to_response_status(e)
+                    })?;
+                // There is no authValue if its length is configured to 0.
+                let auth_value = auth_value.map_or_else(Vec::new, |auth| auth.value().to_vec());
+                PasswordContext::new(key_material, auth_value)
+            }
+        };
+        self.auth_values.protect(&mut password_context);
 
-        self.key_info_store.insert_key_info(
-            key_identity,
-            &PasswordContext::new(key_material, auth_value.value().to_vec()),
-            attributes,
-        )?;
+        self.key_info_store
+            .insert_key_info(key_identity, &password_context, attributes)?;
 
         Ok(psa_generate_key::Result {})
     }
@@ -140,10 +152,7 @@ impl Provider {
         );
         let key_data = op.data;
         self.key_info_store.does_not_exist(&key_identity)?;
-        let mut esapi_context = self
-            .esapi_context
-            .lock()
-            .expect("ESAPI Context lock poisoned");
+        let mut esapi_context = self.esapi_context.acquire();
 
         let attributes = utils::adjust_attributes_key_bits(attributes, key_data.expose_secret())?;
         let key_params = utils::parsec_to_tpm_params(attributes)?;
@@ -197,10 +206,15 @@ impl Provider {
         let key_identity = KeyIdentity::new(
             application_identity.clone(),
             self.provider_identity.clone(),
-            key_name,
+            key_name.clone(),
         );
 
         self.key_info_store.remove_key_info(&key_identity)?;
+        // The key only exists wrapped by the TPM in its mapping, which must be gone.
+        confirm_destruction(
+            &key_name,
+            self.key_info_store.get_key_attributes(&key_identity),
+        )?;
 
         Ok(psa_destroy_key::Result {})
     }
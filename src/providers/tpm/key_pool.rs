@@ -0,0 +1,320 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Pool of keys generated in advance
+//!
+//! Creating a key pair in a TPM can take seconds for RSA keys. For applications that need new keys
+//! with a low latency, keys matching a few configured templates are generated by a background
+//! thread while the TPM is otherwise idle, and handed out to the generation requests matching one
+//! of the templates. Pooled keys are only held in memory: they are stored with the Key Info
+//! Manager once claimed by a client, and generated again if the service restarts.
+use super::auth_value::AuthValuePolicy;
+use super::context_pool::ContextPool;
+use super::utils::{self, PasswordContext};
+use crate::utils::config::KeyPoolConfig;
+use derivative::Derivative;
+use log::{error, info};
+use parsec_interface::operations::psa_algorithm::Algorithm;
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, Lifetime, Policy, Type, UsageFlags,
+};
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tss_esapi::abstraction::transient::KeyParams;
+
+/// Interval at which the pool is checked for missing keys
+const REFILL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the TPM must not have been used by requests before a pooled key is generated
+const IDLE_PERIOD: Duration = Duration::from_millis(250);
+
+/// Kind of keys kept in the pool
+#[derive(Debug)]
+struct KeyTemplate {
+    key_type: Type,
+    bits: usize,
+    algorithm: Algorithm,
+    size: usize,
+    params: KeyParams,
+}
+
+impl KeyTemplate {
+    fn matches(&self, attributes: &Attributes) -> bool {
+        // Those are the only attributes used to create the key in the TPM.
+        self.key_type == attributes.key_type
+            && self.bits == attributes.bits
+            && self.algorithm == attributes.policy.permitted_algorithms
+    }
+}
+
+/// Keys generated in advance, by template
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub(super) struct KeyPool {
+    templates: Vec<KeyTemplate>,
+    auth_values: Arc<AuthValuePolicy>,
+    #[derivative(Debug = "ignore")]
+    keys: Mutex<Vec<Vec<PasswordContext>>>,
+}
+
+impl KeyPool {
+    /// Create an empty pool for the given templates, checking that the TPM provider can generate
+    /// such keys.
+    pub(super) fn new(
+        config: &[KeyPoolConfig],
+        auth_values: Arc<AuthValuePolicy>,
+    ) -> std::io::Result<Self> {
+        let mut templates = Vec::with_capacity(config.len());
+        for template in config {
+            let attributes = Attributes {
+                lifetime: Lifetime::Persistent,
+                key_type: template.key_type,
+                bits: template.bits,
+                policy: Policy {
+                    usage_flags: UsageFlags::default(),
+                    permitted_algorithms: template.algorithm,
+                },
+            };
+            if template.key_type.is_public_key() {
+                error!("Public keys can not be pooled: {:?}.", template.key_type);
+                return Err(Error::new(ErrorKind::InvalidData, "invalid key pool"));
+            }
+            let params = utils::parsec_to_tpm_params(attributes).map_err(|e| {
+                format_error!("Invalid key pool template", e);
+                Error::new(ErrorKind::InvalidData, "invalid key pool")
+            })?;
+            templates.push(KeyTemplate {
+                key_type: template.key_type,
+                bits: template.bits,
+                algorithm: template.algorithm,
+                size: template.size,
+                params,
+            });
+        }
+        let keys = templates.iter().map(|_| Vec::new()).collect();
+
+        Ok(KeyPool {
+            templates,
+            auth_values,
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// Take a pooled key created for the given attributes, if there is one.
+    pub(super) fn take(&self, attributes: &Attributes) -> Option<PasswordContext> {
+        let index = self.templates.iter().position(|t| t.matches(attributes))?;
+        self.keys.lock().expect("Key pool lock poisoned")[index].pop()
+    }
+
+    /// Index of a template with fewer keys than configured
+    fn missing(&self) -> Option<usize> {
+        let keys = self.keys.lock().expect("Key pool lock poisoned");
+        self.templates
+            .iter()
+            .zip(keys.iter())
+            .position(|(template, keys)| keys.len() < template.size)
+    }
+
+    /// Generate one missing key, if the TPM is idle.
+    fn refill(&self, contexts: &ContextPool) {
+        let index = match self.missing() {
+            Some(index) => index,
+            None => return,
+        };
+        let mut esapi_context = match contexts.try_acquire_idle(IDLE_PERIOD) {
+            Some(esapi_context) => esapi_context,
+            None => return,
+        };
+        match esapi_context.create_key(self.templates[index].params, self.auth_values.len()) {
+            Ok((key_material, auth_value)) => {
+                // There is no authValue if its length is configured to 0.
+                let auth_value = auth_value.map_or_else(Vec::new, |auth| auth.value().to_vec());
+                self.keys.lock().expect("Key pool lock poisoned")[index]
+                    .push(PasswordContext::new(key_material, auth_value));
+            }
+            Err(e) => format_error!("Error creating a pooled key", e),
+        }
+    }
+}
+
+/// Background thread keeping the key pool full, stopped when dropped
+#[derive(Debug)]
+pub(super) struct KeyPoolRefill {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl KeyPoolRefill {
+    /// Start refilling the pool with the given contexts.
+    pub(super) fn start(
+        key_pool: Arc<KeyPool>,
+        contexts: Arc<ContextPool>,
+    ) -> std::io::Result<Self> {
+        info!("Filling {} TPM key pool(s).", key_pool.templates.len());
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("tpm-key-pool".to_string())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Acquire) {
+                    key_pool.refill(&contexts);
+                    thread::park_timeout(REFILL_INTERVAL);
+                }
+            })?;
+
+        Ok(KeyPoolRefill {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for KeyPoolRefill {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                error!("The TPM key pool thread panicked.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
+    use tss_esapi::abstraction::transient::KeyMaterial;
+
+    fn rsa_template(key_type: Type, bits: usize) -> KeyPoolConfig {
+        KeyPoolConfig {
+            key_type,
+            bits,
+            algorithm: Algorithm::AsymmetricSignature(AsymmetricSignature::RsaPkcs1v15Sign {
+                hash_alg: SignHash::Specific(Hash::Sha256),
+            }),
+            size: 2,
+        }
+    }
+
+    fn key_pool(config: &[KeyPoolConfig]) -> std::io::Result<KeyPool> {
+        KeyPool::new(config, Arc::new(AuthValuePolicy::new(32, None).unwrap()))
+    }
+
+    fn key_attributes(template: &KeyPoolConfig) -> Attributes {
+        let mut attributes = Attributes {
+            lifetime: Lifetime::Persistent,
+            key_type: template.key_type,
+            bits: template.bits,
+            policy: Policy {
+                usage_flags: UsageFlags::default(),
+                permitted_algorithms: template.algorithm,
+            },
+        };
+        let _ = attributes.policy.usage_flags.set_sign_hash();
+        attributes
+    }
+
+    // Stands for a key created by the refill thread, told apart by its private part.
+    fn pooled_key(private: u8) -> PasswordContext {
+        let key_material: KeyMaterial = toml::from_str(&format!(
+            "private = [{}]\n[public]\nRsa = [1, 0, 1]\n",
+            private
+        ))
+        .unwrap();
+        PasswordContext::new(key_material, vec![private; 32])
+    }
+
+    #[test]
+    fn public_keys_are_not_pooled() {
+        let error = key_pool(&[rsa_template(Type::RsaPublicKey, 2048)]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unsupported_templates_are_rejected() {
+        let error = key_pool(&[rsa_template(Type::RsaKeyPair, 1000)]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let mut template = rsa_template(Type::RsaKeyPair, 2048);
+        template.algorithm = Algorithm::AsymmetricSignature(AsymmetricSignature::RsaPkcs1v15Sign {
+            hash_alg: SignHash::Any,
+        });
+        let error = key_pool(&[template]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn templates_are_matched_on_creation_attributes() {
+        let template = rsa_template(Type::RsaKeyPair, 2048);
+        let key_pool = key_pool(&[template]).unwrap();
+        let mut attributes = key_attributes(&template);
+        assert!(key_pool.templates[0].matches(&attributes));
+
+        // The usage flags are not part of the key created in the TPM.
+        attributes.policy.usage_flags = UsageFlags::default();
+        let _ = attributes.policy.usage_flags.set_export();
+        assert!(key_pool.templates[0].matches(&attributes));
+
+        attributes.bits = 4096;
+        assert!(!key_pool.templates[0].matches(&attributes));
+
+        let mut attributes = key_attributes(&template);
+        attributes.policy.permitted_algorithms =
+            Algorithm::AsymmetricSignature(AsymmetricSignature::RsaPss {
+                hash_alg: SignHash::Specific(Hash::Sha256),
+            });
+        assert!(!key_pool.templates[0].matches(&attributes));
+    }
+
+    #[test]
+    fn empty_pool_has_no_keys() {
+        let template = rsa_template(Type::RsaKeyPair, 2048);
+        let key_pool = key_pool(&[template]).unwrap();
+        assert_eq!(key_pool.missing(), Some(0));
+        assert!(key_pool.take(&key_attributes(&template)).is_none());
+    }
+
+    #[test]
+    fn missing_keys_are_found_by_template() {
+        let key_pool = key_pool(&[
+            rsa_template(Type::RsaKeyPair, 2048),
+            rsa_template(Type::RsaKeyPair, 3072),
+        ])
+        .unwrap();
+        {
+            let mut keys = key_pool.keys.lock().unwrap();
+            keys[0].push(pooled_key(1));
+            keys[0].push(pooled_key(2));
+        }
+        assert_eq!(key_pool.missing(), Some(1));
+
+        {
+            let mut keys = key_pool.keys.lock().unwrap();
+            keys[1].push(pooled_key(3));
+            keys[1].push(pooled_key(4));
+        }
+        assert_eq!(key_pool.missing(), None);
+    }
+
+    #[test]
+    fn keys_are_taken_from_their_template() {
+        let key_pool = key_pool(&[
+            rsa_template(Type::RsaKeyPair, 2048),
+            rsa_template(Type::RsaKeyPair, 3072),
+        ])
+        .unwrap();
+        key_pool.keys.lock().unwrap()[1].push(pooled_key(3));
+
+        let small = key_attributes(&rsa_template(Type::RsaKeyPair, 2048));
+        let large = key_attributes(&rsa_template(Type::RsaKeyPair, 3072));
+        assert!(key_pool.take(&small).is_none());
+        let key = key_pool.take(&large).unwrap();
+        assert_eq!(key.key_material().private(), &[3]);
+        assert_eq!(key.auth_value(), &[3; 32]);
+        assert!(key_pool.take(&large).is_none());
+        assert_eq!(key_pool.missing(), Some(0));
+    }
+}
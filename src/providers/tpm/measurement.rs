@@ -0,0 +1,97 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Measurement of events into the PCRs
+//!
+//! The SHA-256 digest of an event is extended into the SHA-256 bank of a PCR, the other banks
+//! being left unchanged: the PCR then holds `SHA-256(previous value || SHA-256(event))`. Only the
+//! PCRs 0 to 23 of the PC Client platforms can be extended. Verifiers find the event in the quotes
+//! of that PCR the TPM signs during the remote attestation of the device: they replay the event
+//! log of the PCR, in which the digest of the event is the one logged by the service when it
+//! extends the PCR.
+//!
+//! The service measures a single event, its configuration, as described in
+//! `measure_configuration` of the Core provider.
+use super::utils::to_response_status;
+use super::Provider;
+use log::{error, info};
+use num_traits::FromPrimitive;
+use parsec_interface::requests::{ResponseStatus, Result};
+use ring::digest;
+use std::convert::TryFrom;
+use tss_esapi::handles::PcrHandle;
+use tss_esapi::interface_types::algorithm::HashingAlgorithm;
+use tss_esapi::interface_types::session_handles::AuthSession;
+use tss_esapi::structures::{Digest, DigestValues};
+
+/// Highest PCR of the PC Client platforms, the others being reserved
+const LAST_PCR: u8 = 23;
+
+/// Handle of an extendable PCR
+fn pcr_handle(pcr: u8) -> Result<PcrHandle> {
+    if pcr > LAST_PCR {
+        error!("PCR {} does not exist, the last one is {}.", pcr, LAST_PCR);
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    // The ESAPI handles of the PCRs are their indexes.
+    PcrHandle::from_u8(pcr).ok_or(ResponseStatus::PsaErrorInvalidArgument)
+}
+
+/// SHA-256 digest of the event, extended into the PCR
+fn event_digest(event: &[u8]) -> Result<Digest> {
+    Digest::try_from(digest::digest(&digest::SHA256, event).as_ref()).map_err(|e| {
+        format_error!("Invalid event digest", e);
+        to_response_status(e)
+    })
+}
+
+impl Provider {
+    pub(super) fn extend_measurement_internal(&self, pcr: u8, event: &[u8]) -> Result<()> {
+        let pcr_handle = pcr_handle(pcr)?;
+        let event_digest = event_digest(event)?;
+        let mut digests = DigestValues::new();
+        digests.set(HashingAlgorithm::Sha256, event_digest.clone());
+
+        let mut esapi_context = self.esapi_context.acquire();
+        esapi_context
+            .as_mut()
+            .execute_without_session(|context| {
+                context.execute_with_session(Some(AuthSession::Password), |context| {
+                    context.pcr_extend(pcr_handle, digests)
+                })
+            })
+            .map_err(|e| {
+                format_error!("Failed to extend the PCR", e);
+                to_response_status(e)
+            })?;
+        info!(
+            "Extended PCR {} with the event digest {}.",
+            pcr,
+            hex::encode(event_digest.value())
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_platform_pcrs_are_extended() {
+        assert_eq!(pcr_handle(0).unwrap(), PcrHandle::Pcr0);
+        assert_eq!(pcr_handle(LAST_PCR).unwrap(), PcrHandle::Pcr23);
+        assert_eq!(
+            pcr_handle(LAST_PCR + 1).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+    }
+
+    #[test]
+    fn event_digest_is_sha256() {
+        // Section B.1 of FIPS 180-2
+        assert_eq!(
+            hex::encode(event_digest(b"abc").unwrap().value()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -9,8 +9,13 @@ use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyInfoManagerClient;
 use crate::providers::crypto_capability::CanDoCrypto;
 use crate::providers::ProviderIdentity;
+use crate::utils::config::{DeviceIdentityConfig, KeyPoolConfig};
+use crate::utils::secrets;
+use auth_value::AuthValuePolicy;
+use context_pool::ContextPool;
 use derivative::Derivative;
-use log::{info, trace};
+use key_pool::{KeyPool, KeyPoolRefill};
+use log::{error, info, trace};
 use parsec_interface::operations::list_providers::Uuid;
 use parsec_interface::operations::{
     attest_key, can_do_crypto, prepare_key_attestation, psa_asymmetric_decrypt,
@@ -19,24 +24,42 @@ use parsec_interface::operations::{
 };
 use parsec_interface::operations::{list_clients, list_keys, list_providers::ProviderInfo};
 use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
+use sign_batches::SignBatches;
 use std::collections::HashSet;
 use std::io::ErrorKind;
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::Arc;
+use tss_esapi::constants::response_code::Tss2ResponseCodeKind;
 use tss_esapi::interface_types::algorithm::HashingAlgorithm;
 use tss_esapi::interface_types::resource_handles::Hierarchy;
-use tss_esapi::structures::{SymmetricCipherParameters, SymmetricDefinitionObject};
+use tss_esapi::structures::SymmetricDefinitionObject;
 use tss_esapi::Tcti;
 use zeroize::Zeroize;
 
 mod asym_encryption;
 mod asym_sign;
+mod auth_value;
 mod capability_discovery;
+mod context_pool;
+mod device_identity;
 mod generate_random;
 mod key_attestation;
 mod key_management;
+mod key_pool;
+mod measurement;
+mod sign_batches;
+mod storage_root;
 mod utils;
 
+/// Conversion functions between Parsec and TSS types, exposed for the fuzzing harnesses
+#[cfg(feature = "fuzz")]
+pub mod fuzz {
+    pub use super::utils::{
+        adjust_attributes_key_bits, bytes_to_pub_key, parsec_to_tpm_params,
+        parsec_to_tpm_signature, pub_key_to_bytes, signature_data_to_bytes,
+    };
+}
+
 const SUPPORTED_OPCODES: [Opcode; 12] = [
     Opcode::PsaGenerateKey,
     Opcode::PsaGenerateRandom,
@@ -54,8 +77,14 @@ const SUPPORTED_OPCODES: [Opcode; 12] = [
 
 const ROOT_KEY_SIZE: u16 = 2048;
 const ROOT_KEY_AUTH_SIZE: usize = 32;
+/// Ciphers of the sessions of the ESAPI contexts, the first one supported by the TPM being used
+const CONTEXT_CIPHERS: [SymmetricDefinitionObject; 2] = [
+    SymmetricDefinitionObject::AES_256_CFB,
+    SymmetricDefinitionObject::AES_128_CFB,
+];
 const AUTH_STRING_PREFIX: &str = "str:";
 const AUTH_HEX_PREFIX: &str = "hex:";
+const DEFAULT_AUTH_VALUE_LEN: usize = 32;
 
 /// Provider for Trusted Platform Modules
 ///
@@ -69,10 +98,28 @@ pub struct Provider {
     // The identity of the provider including uuid & name.
     provider_identity: ProviderIdentity,
 
-    // The Mutex is needed both because interior mutability is needed to the ESAPI Context
-    // structure that is shared between threads and because two threads are not allowed the same
-    // ESAPI context simultaneously.
-    esapi_context: Mutex<tss_esapi::TransientKeyContext>,
+    // The pool is needed both because interior mutability is needed to the ESAPI Context
+    // structures that are shared between threads and because two threads are not allowed the same
+    // ESAPI context simultaneously. It holds a single context unless the TCTI goes through a
+    // resource manager.
+    // Every operation using a key loads its context in the TPM and flushes it afterwards: the
+    // TransientKeyContext abstraction does not let loaded objects outlive a call, and the handle
+    // of the primary key they are loaded under is not exposed. Caching loaded objects across
+    // operations would require that support in tss-esapi first.
+    esapi_context: Arc<ContextPool>,
+    // Keys generated in advance and the thread generating them, if configured.
+    key_pool: Option<Arc<KeyPool>>,
+    key_pool_refill: Option<KeyPoolRefill>,
+    // Signature requests for the same key handled together.
+    sign_batches: SignBatches,
+    // Length of the authValues of new keys and how they are stored.
+    auth_values: Arc<AuthValuePolicy>,
+    // Symmetric algorithm of the storage root key, the cipher of the sessions.
+    root_key_cipher: SymmetricDefinitionObject,
+    // Signatures are verified with the public part of the keys stored in the Key Info Manager,
+    // without using the TPM.
+    #[cfg(feature = "software-verifier")]
+    software_verification: bool,
     // The Key Info Manager stores the key context and its associated authValue (a PasswordContext
     // structure).
     #[derivative(Debug = "ignore")]
@@ -90,16 +137,38 @@ impl Provider {
     fn new(
         provider_name: String,
         key_info_store: KeyInfoManagerClient,
-        esapi_context: tss_esapi::TransientKeyContext,
-    ) -> Provider {
-        Provider {
+        esapi_context: ContextPool,
+        key_pool: Option<KeyPool>,
+        auth_values: Arc<AuthValuePolicy>,
+        software_verification: bool,
+        root_key_cipher: SymmetricDefinitionObject,
+    ) -> std::io::Result<Provider> {
+        #[cfg(not(feature = "software-verifier"))]
+        let _ = software_verification;
+        let esapi_context = Arc::new(esapi_context);
+        let key_pool = key_pool.map(Arc::new);
+        let key_pool_refill = match &key_pool {
+            Some(key_pool) => Some(KeyPoolRefill::start(
+                key_pool.clone(),
+                esapi_context.clone(),
+            )?),
+            None => None,
+        };
+        Ok(Provider {
             provider_identity: ProviderIdentity {
                 name: provider_name,
                 uuid: String::from(Self::PROVIDER_UUID),
             },
-            esapi_context: Mutex::new(esapi_context),
+            esapi_context,
+            key_pool,
+            key_pool_refill,
+            sign_batches: SignBatches::default(),
+            auth_values,
+            root_key_cipher,
+            #[cfg(feature = "software-verifier")]
+            software_verification,
             key_info_store,
-        }
+        })
     }
 }
 
@@ -109,7 +178,7 @@ impl Provide for Provider {
         Ok((ProviderInfo {
             // Assigned UUID for this provider: 1e4954a4-ff21-46d3-ab0c-661eeb667e1d
             uuid: Uuid::parse_str(Provider::PROVIDER_UUID).or(Err(ResponseStatus::InvalidEncoding))?,
-            description: String::from("TPM provider, interfacing with a library implementing the TCG TSS 2.0 Enhanced System API specification."),
+            description: format!("TPM provider, interfacing with a library implementing the TCG TSS 2.0 Enhanced System API specification, through the {} TCTI.", self.esapi_context.tcti_name()),
             vendor: String::from("Trusted Computing Group (TCG)"),
             version_maj: 0,
             version_min: 1,
@@ -249,6 +318,20 @@ impl Provide for Provider {
         trace!("attest_key ingress");
         self.attest_key_internal(application_identity, op)
     }
+
+    fn wrapping_public_key(&self) -> Result<Vec<u8>> {
+        trace!("wrapping_public_key ingress");
+        self.wrapping_public_key_internal()
+    }
+
+    fn extend_measurement(&self, register: u8, event: &[u8]) -> Result<()> {
+        trace!("extend_measurement ingress");
+        self.extend_measurement_internal(register, event)
+    }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        Some(&self.key_info_store)
+    }
 }
 
 impl Drop for Provider {
@@ -271,6 +354,12 @@ pub struct ProviderBuilder {
     tcti: Option<String>,
     owner_hierarchy_auth: Option<String>,
     endorsement_hierarchy_auth: Option<String>,
+    context_pool_size: Option<usize>,
+    key_pool: Option<Vec<KeyPoolConfig>>,
+    software_verification: Option<bool>,
+    auth_value_len: Option<usize>,
+    auth_value_secret_path: Option<String>,
+    device_identity: Option<DeviceIdentityConfig>,
 }
 
 impl ProviderBuilder {
@@ -282,6 +371,12 @@ impl ProviderBuilder {
             tcti: None,
             owner_hierarchy_auth: None,
             endorsement_hierarchy_auth: None,
+            context_pool_size: None,
+            key_pool: None,
+            software_verification: None,
+            auth_value_len: None,
+            auth_value_secret_path: None,
+            device_identity: None,
         }
     }
 
@@ -323,6 +418,54 @@ impl ProviderBuilder {
         self
     }
 
+    /// Specify the number of ESAPI contexts used concurrently
+    pub fn with_context_pool_size(mut self, context_pool_size: usize) -> ProviderBuilder {
+        self.context_pool_size = Some(context_pool_size);
+
+        self
+    }
+
+    /// Specify the templates of keys to generate in advance
+    pub fn with_key_pool(mut self, key_pool: Vec<KeyPoolConfig>) -> ProviderBuilder {
+        self.key_pool = Some(key_pool);
+
+        self
+    }
+
+    /// Verify signatures in software, with Mbed Crypto, instead of with the TPM
+    pub fn with_software_verification(mut self, software_verification: bool) -> ProviderBuilder {
+        self.software_verification = Some(software_verification);
+
+        self
+    }
+
+    /// Specify the length of the authValues of new keys
+    pub fn with_auth_value_len(mut self, auth_value_len: usize) -> ProviderBuilder {
+        self.auth_value_len = Some(auth_value_len);
+
+        self
+    }
+
+    /// Specify the file holding the secret with which the stored authValues are masked
+    pub fn with_auth_value_secret_path(
+        mut self,
+        auth_value_secret_path: String,
+    ) -> ProviderBuilder {
+        self.auth_value_secret_path = Some(auth_value_secret_path);
+
+        self
+    }
+
+    /// Provision the device identity keys on first boot
+    pub fn with_device_identity(
+        mut self,
+        device_identity: DeviceIdentityConfig,
+    ) -> ProviderBuilder {
+        self.device_identity = Some(device_identity);
+
+        self
+    }
+
     fn get_hierarchy_auth(&mut self, mut auth: Option<String>) -> std::io::Result<Vec<u8>> {
         match auth.take() {
             None => Err(std::io::Error::new(
@@ -338,50 +481,11 @@ impl ProviderBuilder {
             .map_err(|_| {
                 std::io::Error::new(ErrorKind::InvalidData, "invalid hex owner hierarchy auth")
             })?),
-            Some(auth) => Ok(auth.into()),
-        }
-    }
-
-    /// Identify the best cipher for our needs supported by the TPM.
-    ///
-    /// The algorithms sought are the following, in the given order:
-    /// * AES-256 in CFB mode
-    /// * AES-128 in CFB mode
-    ///
-    /// The method is unsafe because it relies on creating a TSS Context which could cause
-    /// undefined behaviour if multiple such contexts are opened concurrently.
-    unsafe fn find_default_context_cipher(&self) -> std::io::Result<SymmetricDefinitionObject> {
-        info!("Checking for ciphers supported by the TPM.");
-        let ciphers = [
-            SymmetricDefinitionObject::AES_256_CFB,
-            SymmetricDefinitionObject::AES_128_CFB,
-        ];
-        let mut ctx = tss_esapi::Context::new(
-            Tcti::from_str(self.tcti.as_ref().ok_or_else(|| {
-                std::io::Error::new(ErrorKind::InvalidData, "TCTI configuration missing")
-            })?)
-            .map_err(|_| {
-                std::io::Error::new(ErrorKind::InvalidData, "Invalid TCTI configuration string")
-            })?,
-        )
-        .map_err(|e| {
-            format_error!("Error when creating TSS Context", e);
-            std::io::Error::new(ErrorKind::InvalidData, "failed initializing TSS context")
-        })?;
-        for cipher in ciphers.iter() {
-            if ctx
-                .test_parms(tss_esapi::structures::PublicParameters::SymCipher(
-                    SymmetricCipherParameters::new(*cipher),
-                ))
-                .is_ok()
-            {
-                return Ok(*cipher);
-            }
+            Some(auth) => match secrets::resolve(&auth)? {
+                Some(secret) => Ok(secret.to_vec()),
+                None => Ok(auth.into()),
+            },
         }
-        Err(std::io::Error::new(
-            ErrorKind::Other,
-            "desired ciphers not supported",
-        ))
     }
 
     /// Create an instance of TpmProvider
@@ -392,41 +496,132 @@ impl ProviderBuilder {
     /// using a same TCTI that does not handle multiple applications concurrently.
     pub unsafe fn build(mut self) -> std::io::Result<Provider> {
         let owner_auth_unparsed = self.owner_hierarchy_auth.take();
-        let owner_auth = self.get_hierarchy_auth(owner_auth_unparsed)?;
-        let default_cipher = self.find_default_context_cipher()?;
-        let tcti = Tcti::from_str(self.tcti.as_ref().ok_or_else(|| {
+        let mut owner_auth = self.get_hierarchy_auth(owner_auth_unparsed)?;
+        let mut endorsement_auth = match self.endorsement_hierarchy_auth.take() {
+            Some(auth) => Some(self.get_hierarchy_auth(Some(auth))?),
+            None => None,
+        };
+        let tcti_string = self.tcti.as_ref().ok_or_else(|| {
             std::io::Error::new(ErrorKind::InvalidData, "TCTI configuration missing")
-        })?)
-        .map_err(|_| {
+        })?;
+        let tcti = Tcti::from_str(tcti_string).map_err(|_| {
             std::io::Error::new(ErrorKind::InvalidData, "Invalid TCTI configuration string")
         })?;
+        let context_pool_size = self.context_pool_size.unwrap_or(1);
+        if context_pool_size == 0 {
+            error!("The ESAPI context pool needs at least one context.");
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid context pool size",
+            ));
+        }
+        if context_pool_size > 1 && !context_pool::supports_concurrent_contexts(tcti_string) {
+            error!("Several ESAPI contexts can only be used with a TCTI going through a resource manager (\"device:/dev/tpmrm0\" or \"tabrmd\").");
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "TCTI does not support concurrent contexts",
+            ));
+        }
+        let software_verification = self.software_verification.unwrap_or(false);
+        if software_verification && !cfg!(feature = "software-verifier") {
+            error!("Software verification needs Parsec to be compiled with the \"software-verifier\" feature.");
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "software verifier not compiled",
+            ));
+        }
+        let auth_values = Arc::new(AuthValuePolicy::new(
+            self.auth_value_len.unwrap_or(DEFAULT_AUTH_VALUE_LEN),
+            self.auth_value_secret_path.as_deref(),
+        )?);
+        let key_pool = match self.key_pool.take() {
+            Some(templates) if !templates.is_empty() => {
+                Some(KeyPool::new(&templates, auth_values.clone())?)
+            }
+            _ => None,
+        };
+        // Only the name is kept, the configuration of the TCTI is zeroized with the auths.
+        let tcti_name = tcti_string.split(':').next().unwrap_or_default().to_owned();
         self.tcti.zeroize();
         self.owner_hierarchy_auth.zeroize();
-        let mut builder = tss_esapi::abstraction::transient::TransientKeyContextBuilder::new()
-            .with_tcti(tcti)
-            .with_root_key_size(ROOT_KEY_SIZE)
-            .with_root_key_auth_size(ROOT_KEY_AUTH_SIZE)
-            .with_hierarchy_auth(Hierarchy::Owner, owner_auth)
-            .with_root_hierarchy(Hierarchy::Owner)
-            .with_session_hash_alg(HashingAlgorithm::Sha256)
-            .with_default_context_cipher(default_cipher);
-        if self.endorsement_hierarchy_auth.is_some() {
-            let endorsement_auth_unparsed = self.endorsement_hierarchy_auth.take();
-            let endorsement_auth = self.get_hierarchy_auth(endorsement_auth_unparsed)?;
-            builder = builder.with_hierarchy_auth(Hierarchy::Endorsement, endorsement_auth);
-            self.endorsement_hierarchy_auth.zeroize();
+        self.endorsement_hierarchy_auth.zeroize();
+
+        let context_builder = |cipher| {
+            let mut builder = tss_esapi::abstraction::transient::TransientKeyContextBuilder::new()
+                .with_tcti(tcti.clone())
+                .with_root_key_size(ROOT_KEY_SIZE)
+                .with_root_key_auth_size(ROOT_KEY_AUTH_SIZE)
+                .with_hierarchy_auth(Hierarchy::Owner, owner_auth.clone())
+                .with_root_hierarchy(Hierarchy::Owner)
+                .with_session_hash_alg(HashingAlgorithm::Sha256)
+                .with_default_context_cipher(cipher);
+            if let Some(endorsement_auth) = &endorsement_auth {
+                builder =
+                    builder.with_hierarchy_auth(Hierarchy::Endorsement, endorsement_auth.clone());
+            }
+            builder
+        };
+        let context_error = |e| {
+            format_error!("Error creating TSS Transient Object Context", e);
+            std::io::Error::new(ErrorKind::InvalidData, "failed initializing TSS context")
+        };
+
+        // The session cipher is found by building the first context with each cipher in turn
+        // rather than probing the TPM with a context of its own: the session is started before the
+        // root key is created, so an unsupported cipher fails early.
+        let mut ciphers = CONTEXT_CIPHERS.iter().peekable();
+        let (first_context, default_cipher) = loop {
+            let cipher = *ciphers.next().expect("at least one context cipher");
+            match context_builder(cipher).build() {
+                Ok(context) => break (context, cipher),
+                Err(tss_esapi::Error::Tss2Error(rc))
+                    if ciphers.peek().is_some()
+                        && matches!(
+                            rc.kind(),
+                            Some(Tss2ResponseCodeKind::Symmetric)
+                                | Some(Tss2ResponseCodeKind::KeySize)
+                                | Some(Tss2ResponseCodeKind::Mode)
+                        ) =>
+                {
+                    info!(
+                        "The TPM does not support {:?} for sessions, trying the next cipher.",
+                        cipher
+                    );
+                }
+                Err(e) => return Err(context_error(e)),
+            }
+        };
+        let mut esapi_contexts = Vec::with_capacity(context_pool_size);
+        esapi_contexts.push(first_context);
+        // The root key is derived from the Owner Hierarchy seed, so keys created with one
+        // context can be loaded with any other.
+        for _ in 1..context_pool_size {
+            esapi_contexts.push(
+                context_builder(default_cipher)
+                    .build()
+                    .map_err(context_error)?,
+            );
+        }
+        owner_auth.zeroize();
+        endorsement_auth.zeroize();
+
+        if let Some(device_identity) = &self.device_identity {
+            device_identity::provision(esapi_contexts[0].as_mut(), device_identity)?;
         }
-        Ok(Provider::new(
+        let esapi_context = ContextPool::new(esapi_contexts, tcti_name);
+
+        Provider::new(
             self.provider_name.ok_or_else(|| {
                 std::io::Error::new(std::io::ErrorKind::InvalidData, "missing provider name")
             })?,
             self.key_info_store.ok_or_else(|| {
                 std::io::Error::new(ErrorKind::InvalidData, "missing key info store")
             })?,
-            builder.build().map_err(|e| {
-                format_error!("Error creating TSS Transient Object Context", e);
-                std::io::Error::new(ErrorKind::InvalidData, "failed initializing TSS context")
-            })?,
-        ))
+            esapi_context,
+            key_pool,
+            auth_values,
+            software_verification,
+            default_cipher,
+        )
     }
 }
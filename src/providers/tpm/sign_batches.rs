@@ -0,0 +1,445 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Batches of sign requests waiting for the same key
+//!
+//! The first request for a key opens a batch, looks its key up and waits for an ESAPI context.
+//! The requests for the same key arriving in the meantime join the batch instead of waiting for a
+//! context themselves: they are all signed with the context and the key context obtained once,
+//! before the context is released. A batch is closed as soon as its context is acquired, the
+//! requests arriving later open the next one.
+//!
+//! The thread signing a batch is not the one handling the other requests of the batch, so the
+//! error detail set while a request is signed is handed back with its result, to be set again in
+//! the thread handling it.
+use crate::key_info_managers::KeyIdentity;
+use crate::providers::error_detail::{self, ErrorDetail};
+use parsec_interface::operations::psa_sign_hash;
+use parsec_interface::requests::{ResponseStatus, Result};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+/// Result of a request, with the error detail set while it was signed
+type Answer = (Result<Vec<u8>>, Option<ErrorDetail>);
+
+type AnswerSlot = Arc<(Mutex<Option<Answer>>, Condvar)>;
+
+type Batch<T> = Arc<Mutex<Vec<PendingSign<T>>>>;
+
+/// Sign requests of a batch, by key
+#[derive(Debug)]
+pub(super) struct SignBatches<K = KeyIdentity, T = psa_sign_hash::Operation> {
+    open: Mutex<HashMap<K, Batch<T>>>,
+}
+
+impl<K, T> Default for SignBatches<K, T> {
+    fn default() -> Self {
+        SignBatches {
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// Sign request in a batch, answered when dropped
+#[derive(Debug)]
+struct PendingSign<T> {
+    request: Option<T>,
+    slot: AnswerSlot,
+    answer: Answer,
+}
+
+impl<T> Drop for PendingSign<T> {
+    fn drop(&mut self) {
+        let (answer, answered) = &*self.slot;
+        let pending_answer = std::mem::replace(
+            &mut self.answer,
+            (Err(ResponseStatus::PsaErrorGenericError), None),
+        );
+        // Answering even if the thread signing the batch panicked.
+        *answer.lock().unwrap_or_else(PoisonError::into_inner) = Some(pending_answer);
+        answered.notify_one();
+    }
+}
+
+impl<T> PendingSign<T> {
+    fn new(request: T) -> (Self, AnswerSlot) {
+        let slot: AnswerSlot = Arc::new((Mutex::new(None), Condvar::new()));
+        (
+            PendingSign {
+                request: Some(request),
+                slot: slot.clone(),
+                // Answer of a request that could not be processed.
+                answer: (Err(ResponseStatus::PsaErrorGenericError), None),
+            },
+            slot,
+        )
+    }
+
+    fn answer(mut self, answer: Answer) {
+        self.answer = answer;
+    }
+}
+
+impl<K: Clone + Eq + Hash, T> SignBatches<K, T> {
+    /// Sign a request with the key, in the batch open for the key or in a new one.
+    ///
+    /// If the calling thread opens the batch, it gets the state shared by the requests with
+    /// `prepare`, such as the key context and an ESAPI context, and signs every request of the
+    /// batch with `sign`. Otherwise it waits for the thread which opened the batch to sign its
+    /// request. The error detail of the request is set in the calling thread either way.
+    pub(super) fn sign<S>(
+        &self,
+        key: &K,
+        request: T,
+        prepare: impl FnOnce() -> Result<S>,
+        mut sign: impl FnMut(&mut S, T) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let (pending, slot) = PendingSign::new(request);
+        let batch = {
+            let mut open = self.open.lock().expect("Sign batch lock poisoned");
+            match open.get(key) {
+                Some(batch) => {
+                    batch
+                        .lock()
+                        .expect("Sign batch lock poisoned")
+                        .push(pending);
+                    None
+                }
+                None => {
+                    let batch = Arc::new(Mutex::new(vec![pending]));
+                    let _ = open.insert(key.clone(), batch.clone());
+                    Some(batch)
+                }
+            }
+        };
+
+        if let Some(requests) = batch {
+            let mut batch = OpenBatch {
+                batches: self,
+                key,
+                requests,
+                closed: false,
+            };
+            let _ = error_detail::take();
+            let state = prepare();
+            let detail = error_detail::take();
+
+            match state {
+                Ok(mut state) => {
+                    for mut pending in batch.close() {
+                        if let Some(request) = pending.request.take() {
+                            let result = sign(&mut state, request);
+                            pending.answer((result, error_detail::take()));
+                        }
+                    }
+                }
+                Err(e) => {
+                    for pending in batch.close() {
+                        pending.answer((Err(e), detail.clone()));
+                    }
+                }
+            }
+        }
+
+        let (answer, answered) = &*slot;
+        let mut answer = answer.lock().expect("Sign batch lock poisoned");
+        loop {
+            if let Some((result, detail)) = answer.take() {
+                if let Some(detail) = detail {
+                    error_detail::set(detail);
+                }
+                return result;
+            }
+            answer = answered.wait(answer).expect("Sign batch lock poisoned");
+        }
+    }
+}
+
+/// Batch opened by the calling thread
+///
+/// If the thread panics before closing it, the batch is closed when dropped, its requests being
+/// answered with an error.
+struct OpenBatch<'a, K: Clone + Eq + Hash, T> {
+    batches: &'a SignBatches<K, T>,
+    key: &'a K,
+    requests: Batch<T>,
+    closed: bool,
+}
+
+impl<K: Clone + Eq + Hash, T> OpenBatch<'_, K, T> {
+    /// Close the batch, the requests arriving later opening the next one, and take its requests.
+    fn close(&mut self) -> Vec<PendingSign<T>> {
+        self.closed = true;
+        let _ = self
+            .batches
+            .open
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(self.key);
+        std::mem::take(&mut *self.requests.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl<K: Clone + Eq + Hash, T> Drop for OpenBatch<'_, K, T> {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Answer, SignBatches};
+    use crate::providers::error_detail::{self, ErrorDetail};
+    use parsec_interface::requests::{ResponseStatus, Result};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    // The batches do not depend on the keys and requests, strings and integers stand for them.
+    type Batches = SignBatches<&'static str, u8>;
+
+    fn detail(request: u8) -> ErrorDetail {
+        ErrorDetail::new(
+            "TSS",
+            format!("{}", request),
+            String::from("signing failed"),
+        )
+    }
+
+    // Sign the request, failing with a detail for the even requests.
+    fn sign(_: &mut (), request: u8) -> Result<Vec<u8>> {
+        if request % 2 == 0 {
+            error_detail::set(detail(request));
+            Err(ResponseStatus::PsaErrorHardwareFailure)
+        } else {
+            Ok(vec![request])
+        }
+    }
+
+    // Number of requests waiting in the batch open for the key.
+    fn waiting(batches: &Batches, key: &'static str) -> usize {
+        batches
+            .open
+            .lock()
+            .unwrap()
+            .get(key)
+            .map_or(0, |batch| batch.lock().unwrap().len())
+    }
+
+    // Open a batch for the key whose preparation waits for the given number of requests to join,
+    // and return the results of the requests, the leader's first, with their error details.
+    fn batch_of(
+        batches: &Batches,
+        requests: &[u8],
+        prepare: impl FnOnce() -> Result<()> + Send,
+        sign_request: impl FnMut(&mut (), u8) -> Result<Vec<u8>> + Send,
+    ) -> Vec<Answer> {
+        let (joined, join) = mpsc::channel();
+        let (leader_request, followers) = requests.split_first().unwrap();
+        thread::scope(|scope| {
+            let leader = scope.spawn(move || {
+                let result = batches.sign(
+                    &"key",
+                    *leader_request,
+                    || {
+                        join.recv().unwrap();
+                        prepare()
+                    },
+                    sign_request,
+                );
+                (result, error_detail::take())
+            });
+            while waiting(batches, "key") == 0 {
+                thread::yield_now();
+            }
+            let followers: Vec<_> = followers
+                .iter()
+                .map(|request| {
+                    scope.spawn(move || {
+                        let result = batches.sign(
+                            &"key",
+                            *request,
+                            || panic!("Only the leader prepares the batch"),
+                            |_: &mut (), _| panic!("Only the leader signs the batch"),
+                        );
+                        (result, error_detail::take())
+                    })
+                })
+                .collect();
+            while waiting(batches, "key") < requests.len() {
+                thread::yield_now();
+            }
+            joined.send(()).unwrap();
+            std::iter::once(leader)
+                .chain(followers)
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn single_request_is_signed() {
+        let batches = Batches::default();
+        assert_eq!(batches.sign(&"key", 1, || Ok(()), sign), Ok(vec![1]));
+        assert_eq!(error_detail::take(), None);
+        assert!(batches.open.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn requests_for_the_same_key_are_signed_in_one_batch() {
+        let batches = Batches::default();
+        let prepared = AtomicUsize::new(0);
+        let results = batch_of(
+            &batches,
+            &[1, 3, 5],
+            || {
+                let _ = prepared.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            },
+            sign,
+        );
+        assert_eq!(prepared.load(Ordering::SeqCst), 1);
+        for (result, request) in results.into_iter().zip([1, 3, 5]) {
+            assert_eq!(result, (Ok(vec![request]), None));
+        }
+        assert!(batches.open.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_details_reach_the_thread_of_their_request() {
+        let batches = Batches::default();
+        let results = batch_of(&batches, &[1, 2, 3, 4], || Ok(()), sign);
+        assert_eq!(results[0], (Ok(vec![1]), None));
+        assert_eq!(
+            results[1],
+            (
+                Err(ResponseStatus::PsaErrorHardwareFailure),
+                Some(detail(2))
+            )
+        );
+        assert_eq!(results[2], (Ok(vec![3]), None));
+        assert_eq!(
+            results[3],
+            (
+                Err(ResponseStatus::PsaErrorHardwareFailure),
+                Some(detail(4))
+            )
+        );
+    }
+
+    #[test]
+    fn failed_preparation_fails_the_whole_batch() {
+        let batches = Batches::default();
+        let results = batch_of(
+            &batches,
+            &[1, 3],
+            || {
+                error_detail::set(detail(0));
+                Err(ResponseStatus::PsaErrorDoesNotExist)
+            },
+            sign,
+        );
+        for result in results {
+            assert_eq!(
+                result,
+                (Err(ResponseStatus::PsaErrorDoesNotExist), Some(detail(0)))
+            );
+        }
+    }
+
+    #[test]
+    fn requests_are_answered_if_the_leader_panics() {
+        let batches = &Batches::default();
+        let (joined, join) = mpsc::channel();
+        thread::scope(|scope| {
+            let leader = scope.spawn(move || {
+                batches.sign(
+                    &"key",
+                    1,
+                    || {
+                        join.recv().unwrap();
+                        Ok(())
+                    },
+                    |_, _| panic!("The TPM went away"),
+                )
+            });
+            while waiting(batches, "key") == 0 {
+                thread::yield_now();
+            }
+            let follower = scope.spawn(move || batches.sign(&"key", 3, || Ok(()), sign));
+            while waiting(batches, "key") < 2 {
+                thread::yield_now();
+            }
+            joined.send(()).unwrap();
+            assert!(leader.join().is_err());
+            assert_eq!(
+                follower.join().unwrap(),
+                Err(ResponseStatus::PsaErrorGenericError)
+            );
+        });
+    }
+
+    #[test]
+    fn batches_are_closed_if_the_preparation_panics() {
+        let batches = &Batches::default();
+        let (joined, join) = mpsc::channel();
+        thread::scope(|scope| {
+            let leader = scope.spawn(move || {
+                batches.sign(
+                    &"key",
+                    1,
+                    || -> Result<()> {
+                        join.recv().unwrap();
+                        panic!("The key context could not be loaded")
+                    },
+                    sign,
+                )
+            });
+            while waiting(batches, "key") == 0 {
+                thread::yield_now();
+            }
+            let follower = scope.spawn(move || batches.sign(&"key", 3, || Ok(()), sign));
+            while waiting(batches, "key") < 2 {
+                thread::yield_now();
+            }
+            joined.send(()).unwrap();
+            assert!(leader.join().is_err());
+            assert_eq!(
+                follower.join().unwrap(),
+                Err(ResponseStatus::PsaErrorGenericError)
+            );
+        });
+        assert!(batches.open.lock().unwrap().is_empty());
+        assert_eq!(batches.sign(&"key", 5, || Ok(()), sign), Ok(vec![5]));
+    }
+
+    #[test]
+    fn later_requests_open_the_next_batch() {
+        let batches = &Batches::default();
+        let (prepared, preparation) = mpsc::channel();
+        let (resume, resumed) = mpsc::channel();
+        thread::scope(|scope| {
+            let first = scope.spawn(move || {
+                batches.sign(
+                    &"key",
+                    1,
+                    || Ok(()),
+                    |state, request| {
+                        prepared.send(()).unwrap();
+                        resumed.recv().unwrap();
+                        sign(state, request)
+                    },
+                )
+            });
+            // The first batch is closed once its requests are being signed.
+            preparation.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(waiting(batches, "key"), 0);
+            assert_eq!(batches.sign(&"key", 3, || Ok(()), sign), Ok(vec![3]));
+            resume.send(()).unwrap();
+            assert_eq!(first.join().unwrap(), Ok(vec![1]));
+        });
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Public key of the storage root key
+//!
+//! The keys of the provider are created under a primary key of the Owner Hierarchy, the storage
+//! root key: a restricted RSA decryption key, with the cipher of the sessions as its symmetric
+//! algorithm. Primary keys being derived from the seed of their hierarchy and their template, it is
+//! created again from the same template to read its public key, which provisioning tools wrap
+//! secrets under offline, as `TPM2_Duplicate` does, for their later import into the TPM.
+use super::utils::to_response_status;
+use super::{Provider, ROOT_KEY_SIZE};
+use log::error;
+use parsec_interface::requests::{ResponseStatus, Result};
+use picky_asn1_x509::SubjectPublicKeyInfo;
+use std::convert::TryFrom;
+use tss_esapi::interface_types::key_bits::RsaKeyBits;
+use tss_esapi::interface_types::resource_handles::Hierarchy;
+use tss_esapi::structures::{Public, RsaExponent, SymmetricDefinitionObject};
+use tss_esapi::utils::create_restricted_decryption_rsa_public;
+
+/// Template of the storage root key, the same as the one the keys of the provider are created
+/// under
+fn template(cipher: SymmetricDefinitionObject) -> Result<Public> {
+    let rsa_key_bits = RsaKeyBits::try_from(ROOT_KEY_SIZE).map_err(|e| {
+        format_error!("Invalid storage root key size", e);
+        to_response_status(e)
+    })?;
+    create_restricted_decryption_rsa_public(cipher, rsa_key_bits, RsaExponent::ZERO_EXPONENT)
+        .map_err(|e| {
+            format_error!("Failed to build the storage root key template", e);
+            to_response_status(e)
+        })
+}
+
+/// DER encoding of the public key of the storage root key, as a SubjectPublicKeyInfo
+fn encode_public_key(out_public: Public) -> Result<Vec<u8>> {
+    let public_key = SubjectPublicKeyInfo::try_from(out_public).map_err(|e| {
+        format_error!("Failed to convert the storage root public key", e);
+        to_response_status(e)
+    })?;
+    picky_asn1_der::to_vec(&public_key).map_err(|e| {
+        error!("Failed to encode the storage root public key: {}", e);
+        ResponseStatus::PsaErrorGenericError
+    })
+}
+
+impl Provider {
+    pub(super) fn wrapping_public_key_internal(&self) -> Result<Vec<u8>> {
+        let public = template(self.root_key_cipher)?;
+
+        let out_public = {
+            let mut esapi_context = self.esapi_context.acquire();
+            let context = esapi_context.as_mut();
+            let result = context
+                .create_primary(Hierarchy::Owner, public, None, None, None, None)
+                .map_err(|e| {
+                    format_error!("Failed to create the storage root key", e);
+                    to_response_status(e)
+                })?;
+            if let Err(e) = context.flush_context(result.key_handle.into()) {
+                format_error!("Failed to flush the storage root key", e);
+            }
+            result.out_public
+        };
+
+        encode_public_key(out_public)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_public_key, template};
+    use std::convert::TryFrom;
+    use tss_esapi::interface_types::key_bits::RsaKeyBits;
+    use tss_esapi::structures::{Public, PublicKeyRsa, RsaExponent, SymmetricDefinitionObject};
+
+    #[test]
+    fn restricted_decryption_key() {
+        let public = template(SymmetricDefinitionObject::AES_256_CFB).unwrap();
+        assert!(public.object_attributes().restricted());
+        assert!(public.object_attributes().decrypt());
+        assert!(!public.object_attributes().sign_encrypt());
+        match public {
+            Public::Rsa { parameters, .. } => {
+                assert_eq!(parameters.key_bits(), RsaKeyBits::Rsa2048);
+                assert_eq!(parameters.exponent(), RsaExponent::ZERO_EXPONENT);
+                assert_eq!(
+                    parameters.symmetric_definition_object(),
+                    SymmetricDefinitionObject::AES_256_CFB
+                );
+            }
+            _ => panic!("The storage root key should be an RSA key"),
+        }
+    }
+
+    #[test]
+    fn subject_public_key_info() {
+        let modulus = vec![0xc5; 256];
+        let out_public = match template(SymmetricDefinitionObject::AES_128_CFB).unwrap() {
+            Public::Rsa {
+                object_attributes,
+                name_hashing_algorithm,
+                auth_policy,
+                parameters,
+                ..
+            } => Public::Rsa {
+                object_attributes,
+                name_hashing_algorithm,
+                auth_policy,
+                parameters,
+                unique: PublicKeyRsa::try_from(modulus.clone()).unwrap(),
+            },
+            _ => panic!("The storage root key should be an RSA key"),
+        };
+        let mut expected = vec![
+            0x30, 0x82, 0x01, 0x22, // SubjectPublicKeyInfo
+            0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05,
+            0x00, // rsaEncryption, NULL parameters
+            0x03, 0x82, 0x01, 0x0f, 0x00, // BIT STRING
+            0x30, 0x82, 0x01, 0x0a, // RSAPublicKey
+            0x02, 0x82, 0x01, 0x01, 0x00, // positive modulus
+        ];
+        expected.extend(&modulus);
+        // The zero exponent of the TPM stands for 65537.
+        expected.extend(&[0x02, 0x03, 0x01, 0x00, 0x01]);
+        assert_eq!(encode_public_key(out_public).unwrap(), expected);
+    }
+}
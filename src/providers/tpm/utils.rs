@@ -3,6 +3,8 @@
 
 #![allow(deprecated)]
 
+use crate::providers::error_detail::{self, ErrorDetail};
+use crate::providers::utils::{ecdsa_signature, key_validation};
 use log::error;
 use parsec_interface::operations::psa_algorithm::*;
 use parsec_interface::operations::psa_key_attributes::*;
@@ -41,6 +43,12 @@ pub fn to_response_status(error: Error) -> ResponseStatus {
             ResponseStatus::PsaErrorCommunicationFailure
         }
         Error::Tss2Error(e) => {
+            error_detail::set(ErrorDetail::new(
+                "TSS",
+                e.kind()
+                    .map_or_else(|| String::from("Unknown"), |kind| format!("{:?}", kind)),
+                e.to_string(),
+            ));
             if let Some(kind) = e.kind() {
                 match kind {
                     Tss2ResponseCodeKind::Success => ResponseStatus::Success,
@@ -120,6 +128,12 @@ impl PasswordContext {
         &self.auth_value
     }
 
+    /// Replace the authentication value of the key, zeroizing the previous one
+    pub fn set_auth_value(&mut self, auth_value: Vec<u8>) {
+        self.auth_value.zeroize();
+        self.auth_value = auth_value;
+    }
+
     /// Get reference to the [KeyMaterial] of the key
     pub fn key_material(&self) -> &KeyMaterial {
         &self.key_material
@@ -135,6 +149,7 @@ pub struct LegacyPasswordContext {
     pub auth_value: Vec<u8>,
 }
 
+/// Converts the Parsec key attributes to the parameters used by the TSS to create the key.
 pub fn parsec_to_tpm_params(attributes: Attributes) -> Result<KeyParams> {
     match attributes.key_type {
         Type::RsaKeyPair | Type::RsaPublicKey => {
@@ -212,25 +227,35 @@ pub fn adjust_attributes_key_bits(
     // * private keys currently not supported
     match attributes.key_type {
         Type::RsaPublicKey => {
-            let public_key: RsaPublicKey = picky_asn1_der::from_bytes(key_data).map_err(|err| {
-                format_error!("Could not deserialise key elements", err);
-                ResponseStatus::PsaErrorInvalidArgument
-            })?;
-            attributes.bits = public_key.modulus.as_unsigned_bytes_be().len() * 8;
+            attributes.bits = key_validation::parse_rsa_public_key(key_data)?.key_bits();
             Ok(attributes)
         }
-        Type::EccPublicKey { .. } => {
-            if key_data.is_empty() || key_data.len() % 2 == 0 {
-                return Err(ResponseStatus::PsaErrorInvalidArgument);
-            }
-
-            attributes.bits = ((key_data.len() - 1) / 2) * 8;
+        Type::EccPublicKey { curve_family } => {
+            attributes.bits = key_validation::validate_ecc_public_key(key_data, curve_family, 0)?;
             Ok(attributes)
         }
         _ => Ok(attributes),
     }
 }
 
+/// Converts the salt of an asymmetric encryption operation, used by PSA as the OAEP label, to
+/// the label given to the TPM. An empty label is the same as no label.
+///
+/// The TPM always uses a zero-terminated string as the label: it appends a zero octet if the
+/// label does not end with one and rejects labels with a zero octet anywhere else. To stay
+/// compatible with keys wrapped by other systems, only labels that the TPM uses as they are (ending
+/// with their only zero octet) are accepted.
+pub fn oaep_label(salt: Option<&[u8]>) -> Result<Option<Vec<u8>>> {
+    match salt {
+        None | Some([]) => Ok(None),
+        Some([label @ .., 0]) if !label.contains(&0) => Ok(salt.map(|salt| salt.to_vec())),
+        Some(_) => {
+            error!("The TPM only supports OAEP labels that are zero-terminated strings.");
+            Err(ResponseStatus::PsaErrorNotSupported)
+        }
+    }
+}
+
 #[allow(deprecated)]
 fn convert_hash_to_tpm(hash: Hash) -> Result<HashingAlgorithm> {
     match hash {
@@ -290,6 +315,7 @@ pub fn convert_curve_to_tpm(key_attributes: Attributes) -> Result<EccCurve> {
     }
 }
 
+/// Converts a public key returned by the TPM to the format defined for `PsaExportPublicKey`.
 pub fn pub_key_to_bytes(pub_key: PublicKey, key_attributes: Attributes) -> Result<Vec<u8>> {
     match pub_key {
         PublicKey::Rsa(key) => picky_asn1_der::to_vec(&RsaPublicKey {
@@ -298,7 +324,7 @@ pub fn pub_key_to_bytes(pub_key: PublicKey, key_attributes: Attributes) -> Resul
         })
         .or(Err(ResponseStatus::PsaErrorGenericError)),
         PublicKey::Ecc { x, y } => {
-            let p_byte_size = key_attributes.bits / 8; // should not fail for valid keys
+            let p_byte_size = (key_attributes.bits + 7) / 8; // should not fail for valid keys
             if x.len() != p_byte_size || y.len() != p_byte_size {
                 if crate::utils::GlobalConfig::log_error_details() {
                     error!(
@@ -325,23 +351,23 @@ fn elliptic_curve_point_to_octet_string(mut x: Vec<u8>, mut y: Vec<u8>) -> Vec<u
     octet_string
 }
 
+/// Parses and validates public key data received from a client, in the format defined for
+/// `PsaImportKey`, into its TSS representation.
 pub fn bytes_to_pub_key(key_data: Vec<u8>, key_attributes: &Attributes) -> Result<PublicKey> {
     match key_attributes.key_type {
         Type::RsaPublicKey => {
-            let public_key: RsaPublicKey =
-                picky_asn1_der::from_bytes(&key_data).map_err(|err| {
-                    format_error!("Could not deserialise key elements", err);
-                    ResponseStatus::PsaErrorInvalidArgument
-                })?;
+            let public_key = key_validation::parse_rsa_public_key(&key_data)?;
 
             validate_rsa_public_key(&public_key, key_attributes)?;
 
-            Ok(PublicKey::Rsa(
-                public_key.modulus.as_unsigned_bytes_be().to_vec(),
-            ))
+            Ok(PublicKey::Rsa(public_key.modulus))
         }
-        Type::EccPublicKey { .. } => {
-            validate_ecc_public_key(&key_data, key_attributes)?;
+        Type::EccPublicKey { curve_family } => {
+            let _ = key_validation::validate_ecc_public_key(
+                &key_data,
+                curve_family,
+                key_attributes.bits,
+            )?;
 
             let (x, y) = octet_string_to_elliptic_curve_point(key_data);
             Ok(PublicKey::Ecc { x, y })
@@ -360,6 +386,7 @@ fn octet_string_to_elliptic_curve_point(mut data: Vec<u8>) -> (Vec<u8>, Vec<u8>)
     (x, y)
 }
 
+/// Converts a signature produced by the TPM to the format defined for `PsaSignHash`.
 pub fn signature_data_to_bytes(data: Signature, key_attributes: Attributes) -> Result<Vec<u8>> {
     match data {
         Signature::RsaSsa(rsa_signature) | Signature::RsaPss(rsa_signature) => {
@@ -369,10 +396,14 @@ pub fn signature_data_to_bytes(data: Signature, key_attributes: Attributes) -> R
             // ECDSA signature data is represented the concatenation of the two result values, r and s,
             // in big endian format, as described here:
             // https://parallaxsecond.github.io/parsec-book/parsec_client/operations/psa_algorithm.html#asymmetricsignature-algorithm
-            let p_byte_size = key_attributes.bits / 8; // should not fail for valid keys
-            if ecc_signature.signature_r().value().len() != p_byte_size
-                || ecc_signature.signature_s().value().len() != p_byte_size
-            {
+            // Some TPMs return r and s without their leading zero bytes: they are put back.
+            let field_len = ecdsa_signature::field_len(key_attributes.bits);
+            ecdsa_signature::raw_from_integers(
+                ecc_signature.signature_r().value(),
+                ecc_signature.signature_s().value(),
+                field_len,
+            )
+            .ok_or_else(|| {
                 if crate::utils::GlobalConfig::log_error_details() {
                     error!(
                         "Received ECC signature with invalid size: r - {} bytes; s - {} bytes",
@@ -382,13 +413,8 @@ pub fn signature_data_to_bytes(data: Signature, key_attributes: Attributes) -> R
                 } else {
                     error!("Received ECC signature with invalid size.");
                 }
-                return Err(ResponseStatus::PsaErrorGenericError);
-            }
-
-            let mut signature = vec![];
-            signature.append(&mut ecc_signature.signature_r().value().to_vec());
-            signature.append(&mut ecc_signature.signature_s().value().to_vec());
-            Ok(signature)
+                ResponseStatus::PsaErrorGenericError
+            })
         }
         _ => {
             error!("Unsupported signature type received from TPM");
@@ -397,6 +423,8 @@ pub fn signature_data_to_bytes(data: Signature, key_attributes: Attributes) -> R
     }
 }
 
+/// Converts a signature received from a client, in the format defined for `PsaVerifyHash`, to
+/// its TSS representation.
 pub fn parsec_to_tpm_signature(
     data: Zeroizing<Vec<u8>>,
     key_attributes: Attributes,
@@ -431,7 +459,7 @@ pub fn parsec_to_tpm_signature(
             // ECDSA signature data is represented as the concatenation of the two result values, r and s,
             // in big endian format, as described here:
             // https://parallaxsecond.github.io/parsec-book/parsec_client/operations/psa_algorithm.html#asymmetricsignature-algorithm
-            let p_size = key_attributes.bits / 8;
+            let p_size = (key_attributes.bits + 7) / 8;
             if data.len() != p_size * 2 {
                 if crate::utils::GlobalConfig::log_error_details() {
                     error!(
@@ -463,24 +491,23 @@ pub fn parsec_to_tpm_signature(
     })
 }
 
-/// Validates an RsaPublicKey against the attributes we expect. Returns ok on success, otherwise
-/// returns an error.
-fn validate_rsa_public_key(public_key: &RsaPublicKey, attributes: &Attributes) -> Result<()> {
-    if public_key.modulus.is_negative() || public_key.public_exponent.is_negative() {
-        error!("Only positive modulus and public exponent are supported.");
-        return Err(ResponseStatus::PsaErrorInvalidArgument);
-    }
-
-    if public_key.public_exponent.as_unsigned_bytes_be() != PUBLIC_EXPONENT_BYTES {
+/// Validates an RSA public key against the attributes we expect and the capabilities of the TPM.
+/// Returns ok on success, otherwise returns an error.
+///
+/// The encoding of the key is expected to have been checked already, when parsing it.
+fn validate_rsa_public_key(
+    public_key: &key_validation::RsaPublicKeyComponents,
+    attributes: &Attributes,
+) -> Result<()> {
+    if public_key.public_exponent != PUBLIC_EXPONENT_BYTES {
         if crate::utils::GlobalConfig::log_error_details() {
-            error!("The TPM Provider only supports 0x10001 as public exponent for RSA public keys, {:?} given.", public_key.public_exponent.as_unsigned_bytes_be());
+            error!("The TPM Provider only supports 0x10001 as public exponent for RSA public keys, {:?} given.", public_key.public_exponent);
         } else {
             error!("The TPM Provider only supports 0x10001 as public exponent for RSA public keys");
         }
         return Err(ResponseStatus::PsaErrorNotSupported);
     }
-    let key_data = public_key.modulus.as_unsigned_bytes_be();
-    let len = key_data.len();
+    let len = public_key.modulus.len();
 
     let key_bits = attributes.bits;
     if key_bits != 0 && len * 8 != key_bits {
@@ -513,36 +540,6 @@ fn validate_rsa_public_key(public_key: &RsaPublicKey, attributes: &Attributes) -
     Ok(())
 }
 
-fn validate_ecc_public_key(public_key: &[u8], attributes: &Attributes) -> Result<()> {
-    if public_key.is_empty() {
-        error!("Public key buffer is empty.");
-        return Err(ResponseStatus::PsaErrorInvalidArgument);
-    }
-
-    // For the format of ECC public keys, see:
-    // https://parallaxsecond.github.io/parsec-book/parsec_client/operations/psa_export_public_key.html#description
-    if public_key[0] != 0x04 {
-        error!("ECC public key buffer is incorrectly formatted.");
-        return Err(ResponseStatus::PsaErrorInvalidArgument);
-    }
-
-    let len = public_key.len() - 1; // discard the first byte
-    if attributes.bits != 0 && (len * 8) / 2 != attributes.bits {
-        if crate::utils::GlobalConfig::log_error_details() {
-            error!(
-                    "`bits` field of key attributes (value: {}) must be either 0 or equal to half the size of the key in `data` (value: {}) for Weierstrass curves.",
-                    attributes.bits,
-                    len * 8
-                );
-        } else {
-            error!("`bits` field of key attributes must be either 0 or equal to half the size of the key in `data` for Weierstrass curves.");
-        }
-        return Err(ResponseStatus::PsaErrorInvalidArgument);
-    }
-
-    Ok(())
-}
-
 pub(super) fn ek_pub_key_to_bytes(ek_public: PublicKey) -> Result<Vec<u8>> {
     pub_key_to_bytes(
         ek_public,
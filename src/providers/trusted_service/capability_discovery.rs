@@ -1,14 +1,86 @@
 // Copyright 2021 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
-
+//! Capabilities of the crypto Trusted Service
+//!
+//! The crypto service does not describe the algorithms and key types it was built with, which
+//! vary between secure world deployments. The provider finds out by generating, then destroying,
+//! a key with the attributes asked about: the service refuses the ones it does not support. The
+//! answers are kept for the lifetime of the provider.
+use super::key_management::create_key_id;
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::providers::crypto_capability::CanDoCrypto;
-use log::{info, trace};
+use log::{error, info, trace};
 use parsec_interface::operations::can_do_crypto;
-use parsec_interface::operations::psa_key_attributes::{Attributes, Type};
+use parsec_interface::operations::psa_key_attributes::{Attributes, Lifetime, Type};
 use parsec_interface::requests::ResponseStatus::PsaErrorNotSupported;
-use parsec_interface::requests::Result;
+use parsec_interface::requests::{ResponseStatus, Result};
+
+/// Maximum number of attributes whose support is remembered
+const MAX_PROBED_ATTRIBUTES: usize = 256;
+
+impl Provider {
+    /// Check whether the crypto service supports keys with the given attributes. Public keys are
+    /// checked through the corresponding key pairs.
+    fn probe_support(&self, attributes: Attributes) -> Result<can_do_crypto::Result> {
+        let mut probe = attributes;
+        probe.lifetime = Lifetime::Persistent;
+        probe.key_type = match attributes.key_type {
+            Type::RsaPublicKey => Type::RsaKeyPair,
+            Type::EccPublicKey { curve_family } => Type::EccKeyPair { curve_family },
+            Type::DhPublicKey { group_family } => Type::DhKeyPair { group_family },
+            key_type => key_type,
+        };
+
+        let known = self
+            .probed_attributes
+            .lock()
+            .expect("Probed attributes lock poisoned")
+            .iter()
+            .find(|(probed, _)| *probed == probe)
+            .map(|(_, supported)| *supported);
+        let supported = match known {
+            Some(supported) => supported,
+            None => {
+                let key_id = create_key_id(&self.id_counter)?;
+                let supported = match self.context.generate_key(probe, key_id) {
+                    Ok(_) => {
+                        if self.context.destroy_key(key_id).is_err() {
+                            error!(
+                                "Failed to destroy the key generated to probe the crypto service."
+                            );
+                        }
+                        true
+                    }
+                    Err(error) => match ResponseStatus::from(error) {
+                        ResponseStatus::PsaErrorNotSupported
+                        | ResponseStatus::PsaErrorInvalidArgument => false,
+                        // Transient failures say nothing about the support of the attributes.
+                        status => {
+                            format_error!("Failed to probe the crypto service", status);
+                            return Err(status);
+                        }
+                    },
+                };
+                let mut probed_attributes = self
+                    .probed_attributes
+                    .lock()
+                    .expect("Probed attributes lock poisoned");
+                if probed_attributes.len() < MAX_PROBED_ATTRIBUTES {
+                    probed_attributes.push((probe, supported));
+                }
+                supported
+            }
+        };
+
+        if supported {
+            Ok(can_do_crypto::Result)
+        } else {
+            info!("The crypto service does not support {:?}", attributes);
+            Err(PsaErrorNotSupported)
+        }
+    }
+}
 
 impl CanDoCrypto for Provider {
     fn can_do_crypto_internal(
@@ -36,7 +108,7 @@ impl CanDoCrypto for Provider {
             PsaErrorNotSupported
         })?;
 
-        Ok(can_do_crypto::Result)
+        self.probe_support(attributes)
     }
 
     fn generate_check_internal(&self, attributes: Attributes) -> Result<can_do_crypto::Result> {
@@ -48,17 +120,11 @@ impl CanDoCrypto for Provider {
         })?;
 
         match attributes.key_type {
-            Type::RsaKeyPair
-            | Type::EccKeyPair { .. }
-            | Type::DhKeyPair { .. }
-            | Type::RawData
-            | Type::Aes
-            | Type::Camellia
-            | Type::Chacha20 => Ok(can_do_crypto::Result),
-            _ => {
-                info!("Unsupported key type {:?}", attributes.key_type);
+            Type::RsaPublicKey | Type::EccPublicKey { .. } | Type::DhPublicKey { .. } => {
+                info!("Public keys cannot be generated");
                 Err(PsaErrorNotSupported)
             }
+            _ => self.probe_support(attributes),
         }
     }
 
@@ -70,22 +136,7 @@ impl CanDoCrypto for Provider {
             PsaErrorNotSupported
         })?;
 
-        // We can import public keys and all the types we can generate.
-        match attributes.key_type {
-            Type::RsaPublicKey | Type::EccPublicKey { .. } | Type::DhPublicKey { .. } => {
-                Ok(can_do_crypto::Result)
-            }
-            Type::RsaKeyPair
-            | Type::EccKeyPair { .. }
-            | Type::DhKeyPair { .. }
-            | Type::RawData
-            | Type::Aes
-            | Type::Camellia
-            | Type::Chacha20 => Ok(can_do_crypto::Result),
-            _ => {
-                info!("Unsupported key type {:?}", attributes.key_type);
-                Err(PsaErrorNotSupported)
-            }
-        }
+        // Public keys can be imported when the corresponding key pairs are supported.
+        self.probe_support(attributes)
     }
 }
@@ -1,10 +1,16 @@
 // Copyright 2020 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
 use super::context::error::{Error, RpcCallerError, WrapperError};
+use crate::providers::error_detail::{self, ErrorDetail};
 use parsec_interface::requests::ResponseStatus;
 
 impl From<RpcCallerError> for ResponseStatus {
     fn from(error: RpcCallerError) -> Self {
+        error_detail::set(ErrorDetail::new(
+            "Trusted Services RPC",
+            format!("{:?}", error),
+            error.to_string(),
+        ));
         match error {
             RpcCallerError::EndpointDoesNotExist
             | RpcCallerError::InvalidOpcode
@@ -3,6 +3,7 @@
 use super::Provider;
 use crate::authenticators::ApplicationIdentity;
 use crate::key_info_managers::KeyIdentity;
+use crate::providers::utils::key_destruction::confirm_destruction;
 use log::error;
 use parsec_interface::operations::psa_key_attributes::{Attributes, Type};
 use parsec_interface::operations::utils_deprecated_primitives::CheckDeprecated;
@@ -51,15 +52,17 @@ impl Provider {
 
         match self.context.generate_key(key_attributes, key_id) {
             Ok(_) => {
-                if let Err(e) =
-                    self.key_info_store
-                        .insert_key_info(key_identity, &key_id, key_attributes)
-                {
+                if let Err(e) = self.key_info_store.insert_key_info(
+                    key_identity.clone(),
+                    &key_id,
+                    key_attributes,
+                ) {
                     if self.context.destroy_key(key_id).is_err() {
                         error!("Failed to destroy the previously generated key.");
                     }
                     Err(e)
                 } else {
+                    self.cache_public_key(&key_identity, key_id, key_attributes.key_type);
                     Ok(psa_generate_key::Result {})
                 }
             }
@@ -95,15 +98,17 @@ impl Provider {
             .import_key(key_attributes, key_id, key_data.expose_secret())
         {
             Ok(_) => {
-                if let Err(e) =
-                    self.key_info_store
-                        .insert_key_info(key_identity, &key_id, key_attributes)
-                {
+                if let Err(e) = self.key_info_store.insert_key_info(
+                    key_identity.clone(),
+                    &key_id,
+                    key_attributes,
+                ) {
                     if self.context.destroy_key(key_id).is_err() {
                         error!("Failed to destroy the previously generated key.");
                     }
                     Err(e)
                 } else {
+                    self.cache_public_key(&key_identity, key_id, key_attributes.key_type);
                     Ok(psa_import_key::Result {})
                 }
             }
@@ -149,12 +154,21 @@ impl Provider {
             self.provider_identity.clone(),
             key_name,
         );
+        if let Some(pub_key) = self.key_info_store.get_public_key(&key_identity) {
+            return Ok(psa_export_public_key::Result {
+                data: pub_key.into(),
+            });
+        }
         let key_id = self.key_info_store.get_key_id(&key_identity)?;
 
         match self.context.export_public_key(key_id) {
-            Ok(pub_key) => Ok(psa_export_public_key::Result {
-                data: pub_key.into(),
-            }),
+            Ok(pub_key) => {
+                self.key_info_store
+                    .cache_public_key(&key_identity, pub_key.clone());
+                Ok(psa_export_public_key::Result {
+                    data: pub_key.into(),
+                })
+            }
             Err(error) => {
                 format_error!("Export public key status: ", error);
                 Err(error.into())
@@ -162,6 +176,24 @@ impl Provider {
         }
     }
 
+    /// Store the public part of a new asymmetric key with the Key Info Manager. Failing to do so
+    /// is not an error: it will then be read from the Trusted Service when first exported.
+    fn cache_public_key(&self, key_identity: &KeyIdentity, key_id: u32, key_type: Type) {
+        match key_type {
+            Type::RsaKeyPair
+            | Type::RsaPublicKey
+            | Type::EccKeyPair { .. }
+            | Type::EccPublicKey { .. }
+            | Type::DhKeyPair { .. }
+            | Type::DhPublicKey { .. } => (),
+            _ => return,
+        }
+        match self.context.export_public_key(key_id) {
+            Ok(pub_key) => self.key_info_store.cache_public_key(key_identity, pub_key),
+            Err(error) => format_error!("Failed to read the public part of the new key", error),
+        }
+    }
+
     pub(super) fn psa_export_key_internal(
         &self,
         application_identity: &ApplicationIdentity,
@@ -195,13 +227,22 @@ impl Provider {
         let key_identity = KeyIdentity::new(
             application_identity.clone(),
             self.provider_identity.clone(),
-            key_name,
+            key_name.clone(),
         );
         let key_id = self.key_info_store.get_key_id(&key_identity)?;
         self.key_info_store.remove_key_info(&key_identity)?;
 
         match self.context.destroy_key(key_id) {
-            Ok(()) => Ok(psa_destroy_key::Result {}),
+            Ok(()) => {
+                // Any answer but an invalid handle means that the key is still there.
+                confirm_destruction(
+                    &key_name,
+                    self.context
+                        .export_public_key(key_id)
+                        .map_err(ResponseStatus::from),
+                )?;
+                Ok(psa_destroy_key::Result {})
+            }
             Err(error) => {
                 format_error!("Destroy key status: ", error);
                 Err(error.into())
@@ -12,6 +12,7 @@ use derivative::Derivative;
 use log::{error, trace};
 use parsec_interface::operations::list_providers::ProviderInfo;
 use parsec_interface::operations::list_providers::Uuid;
+use parsec_interface::operations::psa_key_attributes::Attributes;
 use parsec_interface::operations::{
     can_do_crypto, list_clients, list_keys, psa_asymmetric_decrypt, psa_asymmetric_encrypt,
     psa_destroy_key, psa_export_key, psa_export_public_key, psa_generate_key, psa_generate_random,
@@ -21,6 +22,7 @@ use parsec_interface::requests::{Opcode, ProviderId, Result};
 use psa_crypto::types::key;
 use std::collections::HashSet;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
 
 mod asym_encryption;
 mod asym_sign;
@@ -64,6 +66,9 @@ pub struct Provider {
     // Holds the highest ID of all keys (including destroyed keys). New keys will receive an ID of
     // id_counter + 1. Once id_counter reaches the highest allowed ID, no more keys can be created.
     id_counter: AtomicU32,
+
+    // Key attributes whose support by the crypto service is known, see `capability_discovery`.
+    probed_attributes: Mutex<Vec<(Attributes, bool)>>,
 }
 
 impl Provider {
@@ -86,6 +91,7 @@ impl Provider {
             key_info_store,
             context: Context::connect()?,
             id_counter: AtomicU32::new(key::PSA_KEY_ID_USER_MIN),
+            probed_attributes: Mutex::new(Vec::new()),
         };
         let mut max_key_id: key::psa_key_id_t = key::PSA_KEY_ID_USER_MIN;
         {
@@ -261,6 +267,10 @@ impl Provide for Provider {
         trace!("psa_asymmetric_decrypt ingress");
         self.psa_asymmetric_decrypt_internal(application_identity, op)
     }
+
+    fn key_info_store(&self) -> Option<&KeyInfoManagerClient> {
+        Some(&self.key_info_store)
+    }
 }
 
 /// Trusted Service provider builder
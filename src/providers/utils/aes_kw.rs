@@ -0,0 +1,359 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! AES key wrap
+//!
+//! Keys are exchanged with external systems wrapped with AES-KW (RFC 3394) or, for keys whose
+//! length is not a multiple of 8 bytes, AES-KWP (RFC 5649), both specified by NIST SP 800-38F.
+//! With the `aes-key-wrap` feature, applications export keys wrapped, or import wrapped keys, by
+//! suffixing the key name of PsaExportKey or PsaImportKey with `#aes-kw=` or `#aes-kwp=` followed
+//! by the name of the AES wrapping key, one of their keys. The wrapping key must be permitted to
+//! encrypt, to wrap keys, or to decrypt, to unwrap them, and the wrapped key to be exported.
+//! Unwrapped keys are then imported like the others: converted from the key formats and checked
+//! by the service.
+//!
+//! Providers with their own key wrap mechanisms use them. The functions here implement the
+//! algorithms over the AES block encryption and decryption of the wrapping key, for providers that
+//! only offer those.
+use crate::utils::key_suffixes;
+use log::error;
+use parsec_interface::requests::{ResponseStatus, Result};
+use std::convert::TryFrom;
+use zeroize::Zeroizing;
+
+/// Length of the semiblocks the keys are wrapped by
+const SEMIBLOCK_LEN: usize = 8;
+/// Initial value of AES-KW
+const KW_IV: [u8; SEMIBLOCK_LEN] = [0xa6; SEMIBLOCK_LEN];
+/// First half of the alternative initial value of AES-KWP, followed by the length of the key
+const KWP_IV_PREFIX: [u8; 4] = [0xa6, 0x59, 0x59, 0xa6];
+
+/// Key wrap algorithm
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyWrapAlgorithm {
+    /// AES-KW, for keys whose length is a multiple of 8 bytes
+    AesKw,
+    /// AES-KWP, for keys of any length
+    AesKwp,
+}
+
+impl KeyWrapAlgorithm {
+    /// Remove the key wrap suffix from the key name, returning the algorithm and wrapping key
+    /// name it gives.
+    pub fn strip_from(key_name: &mut String) -> Option<(Self, String)> {
+        [
+            (KeyWrapAlgorithm::AesKw, key_suffixes::AES_KW),
+            (KeyWrapAlgorithm::AesKwp, key_suffixes::AES_KWP),
+        ]
+        .iter()
+        .filter_map(|(alg, suffix)| Some((*alg, key_name.rfind(suffix)?, suffix.len())))
+        .max_by_key(|(_, start, _)| *start)
+        .filter(|(_, start, length)| *start > 0 && start + length < key_name.len())
+        .map(|(alg, start, length)| {
+            let wrapping_key_name = key_name[start + length..].to_string();
+            key_name.truncate(start);
+            (alg, wrapping_key_name)
+        })
+    }
+}
+
+/// Wrap the key with the algorithm, given the AES block encryption of the wrapping key.
+pub fn wrap(
+    alg: KeyWrapAlgorithm,
+    key: &[u8],
+    mut encrypt_block: impl FnMut(&mut [u8; 16]) -> Result<()>,
+) -> Result<Vec<u8>> {
+    let (iv, padded_key) = match alg {
+        KeyWrapAlgorithm::AesKw => {
+            if key.len() < 2 * SEMIBLOCK_LEN || key.len() % SEMIBLOCK_LEN != 0 {
+                error!("AES-KW only wraps keys of a multiple of 8 bytes, of at least 16 bytes.");
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+            (KW_IV, Zeroizing::new(key.to_vec()))
+        }
+        KeyWrapAlgorithm::AesKwp => {
+            let length = u32::try_from(key.len())
+                .ok()
+                .filter(|length| *length > 0)
+                .ok_or_else(|| {
+                    error!("AES-KWP only wraps keys of 1 byte to 4 GiB.");
+                    ResponseStatus::PsaErrorInvalidArgument
+                })?;
+            let mut iv = [0; SEMIBLOCK_LEN];
+            iv[..4].copy_from_slice(&KWP_IV_PREFIX);
+            iv[4..].copy_from_slice(&length.to_be_bytes());
+            let mut padded_key = Zeroizing::new(key.to_vec());
+            padded_key.resize(
+                (key.len() + SEMIBLOCK_LEN - 1) / SEMIBLOCK_LEN * SEMIBLOCK_LEN,
+                0,
+            );
+            (iv, padded_key)
+        }
+    };
+
+    // Keys of a single semiblock are encrypted with the initial value, as a single block.
+    if padded_key.len() == SEMIBLOCK_LEN {
+        let mut block = Zeroizing::new([0; 16]);
+        block[..SEMIBLOCK_LEN].copy_from_slice(&iv);
+        block[SEMIBLOCK_LEN..].copy_from_slice(&padded_key);
+        encrypt_block(&mut block)?;
+        return Ok(block.to_vec());
+    }
+
+    let semiblocks = padded_key.len() / SEMIBLOCK_LEN;
+    let mut a = iv;
+    let mut r = padded_key;
+    let mut block = Zeroizing::new([0; 16]);
+    for j in 0..6 {
+        for i in 0..semiblocks {
+            block[..SEMIBLOCK_LEN].copy_from_slice(&a);
+            block[SEMIBLOCK_LEN..].copy_from_slice(&r[i * SEMIBLOCK_LEN..(i + 1) * SEMIBLOCK_LEN]);
+            encrypt_block(&mut block)?;
+            let t = (semiblocks * j + i + 1) as u64;
+            for (a, (byte, t)) in a
+                .iter_mut()
+                .zip(block[..SEMIBLOCK_LEN].iter().zip(t.to_be_bytes().iter()))
+            {
+                *a = byte ^ t;
+            }
+            r[i * SEMIBLOCK_LEN..(i + 1) * SEMIBLOCK_LEN].copy_from_slice(&block[SEMIBLOCK_LEN..]);
+        }
+    }
+    let mut wrapped = a.to_vec();
+    wrapped.extend_from_slice(&r);
+    Ok(wrapped)
+}
+
+/// Whether the slices are equal, comparing all their bytes whatever the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Unwrap the key with the algorithm, given the AES block decryption of the wrapping key. Keys
+/// which do not pass the integrity check are refused with `PsaErrorInvalidSignature`.
+pub fn unwrap(
+    alg: KeyWrapAlgorithm,
+    wrapped: &[u8],
+    mut decrypt_block: impl FnMut(&mut [u8; 16]) -> Result<()>,
+) -> Result<Zeroizing<Vec<u8>>> {
+    let minimum_len = match alg {
+        KeyWrapAlgorithm::AesKw => 3 * SEMIBLOCK_LEN,
+        KeyWrapAlgorithm::AesKwp => 2 * SEMIBLOCK_LEN,
+    };
+    if wrapped.len() < minimum_len || wrapped.len() % SEMIBLOCK_LEN != 0 {
+        error!("The wrapped key is not a valid {:?} output.", alg);
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+
+    let mut a = [0; SEMIBLOCK_LEN];
+    let mut r;
+    let mut block = Zeroizing::new([0; 16]);
+    if wrapped.len() == 2 * SEMIBLOCK_LEN {
+        block.copy_from_slice(wrapped);
+        decrypt_block(&mut block)?;
+        a.copy_from_slice(&block[..SEMIBLOCK_LEN]);
+        r = Zeroizing::new(block[SEMIBLOCK_LEN..].to_vec());
+    } else {
+        let semiblocks = wrapped.len() / SEMIBLOCK_LEN - 1;
+        a.copy_from_slice(&wrapped[..SEMIBLOCK_LEN]);
+        r = Zeroizing::new(wrapped[SEMIBLOCK_LEN..].to_vec());
+        for j in (0..6).rev() {
+            for i in (0..semiblocks).rev() {
+                let t = (semiblocks * j + i + 1) as u64;
+                for (block, (a, t)) in block[..SEMIBLOCK_LEN]
+                    .iter_mut()
+                    .zip(a.iter().zip(t.to_be_bytes().iter()))
+                {
+                    *block = a ^ t;
+                }
+                block[SEMIBLOCK_LEN..]
+                    .copy_from_slice(&r[i * SEMIBLOCK_LEN..(i + 1) * SEMIBLOCK_LEN]);
+                decrypt_block(&mut block)?;
+                a.copy_from_slice(&block[..SEMIBLOCK_LEN]);
+                r[i * SEMIBLOCK_LEN..(i + 1) * SEMIBLOCK_LEN]
+                    .copy_from_slice(&block[SEMIBLOCK_LEN..]);
+            }
+        }
+    }
+
+    // The integrity check is made in constant time, so that the time taken does not tell how much
+    // of it passed.
+    let valid = match alg {
+        KeyWrapAlgorithm::AesKw => constant_time_eq(&a, &KW_IV),
+        KeyWrapAlgorithm::AesKwp => {
+            let length = u32::from_be_bytes([a[4], a[5], a[6], a[7]]) as usize;
+            let length_valid = length > 0 && length <= r.len() && r.len() - length < SEMIBLOCK_LEN;
+            let padding = if length_valid { &r[length..] } else { &[][..] };
+            let padding_valid = padding.iter().fold(0, |acc, byte| acc | byte) == 0;
+            let valid = constant_time_eq(&a[..4], &KWP_IV_PREFIX) & length_valid & padding_valid;
+            if valid {
+                r.truncate(length);
+            }
+            valid
+        }
+    };
+    if !valid {
+        error!("The wrapped key failed the {:?} integrity check.", alg);
+        return Err(ResponseStatus::PsaErrorInvalidSignature);
+    }
+    Ok(r)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_helpers::hex;
+    use aes::cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit};
+
+    fn aes_block<C: BlockEncrypt + BlockDecrypt + KeyInit>(
+        kek: &[u8],
+        encrypt: bool,
+        block: &mut [u8; 16],
+    ) {
+        let cipher = C::new_from_slice(kek).unwrap();
+        let block = GenericArray::from_mut_slice(block);
+        if encrypt {
+            cipher.encrypt_block(block);
+        } else {
+            cipher.decrypt_block(block);
+        }
+    }
+
+    /// AES block encryption or decryption with the key encryption key
+    fn aes(kek: &[u8], encrypt: bool) -> impl FnMut(&mut [u8; 16]) -> Result<()> + '_ {
+        move |block| {
+            match kek.len() {
+                16 => aes_block::<aes::Aes128>(kek, encrypt, block),
+                24 => aes_block::<aes::Aes192>(kek, encrypt, block),
+                _ => aes_block::<aes::Aes256>(kek, encrypt, block),
+            }
+            Ok(())
+        }
+    }
+
+    fn check_vector(alg: KeyWrapAlgorithm, kek: &str, key: &str, wrapped: &str) {
+        let (kek, key, wrapped) = (hex(kek), hex(key), hex(wrapped));
+        assert_eq!(wrap(alg, &key, aes(&kek, true)).unwrap(), wrapped);
+        assert_eq!(*unwrap(alg, &wrapped, aes(&kek, false)).unwrap(), key);
+    }
+
+    #[test]
+    fn rfc3394_128_bit_key_with_128_bit_kek() {
+        // RFC 3394, section 4.1
+        check_vector(
+            KeyWrapAlgorithm::AesKw,
+            "000102030405060708090A0B0C0D0E0F",
+            "00112233445566778899AABBCCDDEEFF",
+            "1FA68B0A8112B447AEF34BD8FB5A7B829D3E862371D2CFE5",
+        );
+    }
+
+    #[test]
+    fn rfc3394_128_bit_key_with_256_bit_kek() {
+        // RFC 3394, section 4.3
+        check_vector(
+            KeyWrapAlgorithm::AesKw,
+            "000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F",
+            "00112233445566778899AABBCCDDEEFF",
+            "64E8C3F9CE0F5BA263E9777905818A2A93C8191E7D6E8AE7",
+        );
+    }
+
+    #[test]
+    fn rfc3394_256_bit_key_with_256_bit_kek() {
+        // RFC 3394, section 4.6
+        check_vector(
+            KeyWrapAlgorithm::AesKw,
+            "000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F",
+            "00112233445566778899AABBCCDDEEFF000102030405060708090A0B0C0D0E0F",
+            "28C9F404C4B810F4CBCCB35CFB87F8263F5786E2D80ED326CBC7F0E71A99F43BFB988B9B7A02DD21",
+        );
+    }
+
+    #[test]
+    fn rfc5649_20_byte_key() {
+        // RFC 5649, section 6
+        check_vector(
+            KeyWrapAlgorithm::AesKwp,
+            "5840df6e29b02af1ab493b705bf16ea1ae8338f4dcc176a8",
+            "c37b7e6492584340bed12207808941155068f738",
+            "138bdeaa9b8fa7fc61f97742e72248ee5ae6ae5360d1ae6a5f54f373fa543b6a",
+        );
+    }
+
+    #[test]
+    fn rfc5649_7_byte_key() {
+        // RFC 5649, section 6, wrapped as a single block
+        check_vector(
+            KeyWrapAlgorithm::AesKwp,
+            "5840df6e29b02af1ab493b705bf16ea1ae8338f4dcc176a8",
+            "466f7250617369",
+            "afbeb0f07dfbf5419200f2ccb50bb24f",
+        );
+    }
+
+    #[test]
+    fn tampered_keys_fail_the_integrity_check() {
+        let kek = hex("000102030405060708090A0B0C0D0E0F");
+        let key: Vec<u8> = (0..32).collect();
+        for alg in [KeyWrapAlgorithm::AesKw, KeyWrapAlgorithm::AesKwp].iter() {
+            let mut wrapped = wrap(*alg, &key, aes(&kek, true)).unwrap();
+            wrapped[20] ^= 1;
+            assert_eq!(
+                unwrap(*alg, &wrapped, aes(&kek, false)),
+                Err(ResponseStatus::PsaErrorInvalidSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn keys_are_unwrapped_with_their_algorithm_only() {
+        let kek = hex("000102030405060708090A0B0C0D0E0F");
+        let key: Vec<u8> = (0..24).collect();
+        let wrapped = wrap(KeyWrapAlgorithm::AesKwp, &key, aes(&kek, true)).unwrap();
+        assert_eq!(
+            unwrap(KeyWrapAlgorithm::AesKw, &wrapped, aes(&kek, false)),
+            Err(ResponseStatus::PsaErrorInvalidSignature)
+        );
+        let wrapped = wrap(KeyWrapAlgorithm::AesKw, &key, aes(&kek, true)).unwrap();
+        assert_eq!(
+            unwrap(KeyWrapAlgorithm::AesKwp, &wrapped, aes(&kek, false)),
+            Err(ResponseStatus::PsaErrorInvalidSignature)
+        );
+    }
+
+    #[test]
+    fn invalid_lengths_are_refused() {
+        let kek = hex("000102030405060708090A0B0C0D0E0F");
+        let key: Vec<u8> = (0..20).collect();
+        assert_eq!(
+            wrap(KeyWrapAlgorithm::AesKw, &key, aes(&kek, true)),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+        assert_eq!(
+            wrap(KeyWrapAlgorithm::AesKwp, &[], aes(&kek, true)),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+        assert_eq!(
+            unwrap(KeyWrapAlgorithm::AesKw, &[0; 16], aes(&kek, false)),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+        assert_eq!(
+            unwrap(KeyWrapAlgorithm::AesKwp, &[0; 20], aes(&kek, false)),
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        );
+    }
+
+    #[test]
+    fn key_wrap_suffixes() {
+        let mut key_name = String::from("imported#aes-kwp=transport#aes-kw=kek");
+        assert_eq!(
+            KeyWrapAlgorithm::strip_from(&mut key_name),
+            Some((KeyWrapAlgorithm::AesKw, String::from("kek")))
+        );
+        assert_eq!(key_name, "imported#aes-kwp=transport");
+        let mut key_name = String::from("#aes-kw=kek");
+        assert_eq!(KeyWrapAlgorithm::strip_from(&mut key_name), None);
+        let mut key_name = String::from("imported#aes-kw=");
+        assert_eq!(KeyWrapAlgorithm::strip_from(&mut key_name), None);
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Encodings of ECDSA signatures
+//!
+//! The PSA Crypto API defines an ECDSA signature as the concatenation of its r and s values, each
+//! in big endian and as long as the field of the curve. Backends do not all agree: some produce or
+//! expect the DER encoding of the `Ecdsa-Sig-Value` structure of RFC 3279, others return r and s
+//! without their leading zero bytes. The functions here convert between those encodings.
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// Length of r and s in the signatures made with a key of the given size
+pub fn field_len(bits: usize) -> usize {
+    (bits + 7) / 8
+}
+
+/// Concatenate r and s, given as big endian integers possibly shorter or with extra leading zero
+/// bytes, in the PSA format. Returns `None` if one of them is longer than the field.
+pub fn raw_from_integers(r: &[u8], s: &[u8], field_len: usize) -> Option<Vec<u8>> {
+    let mut raw = Vec::with_capacity(2 * field_len);
+    for integer in [r, s] {
+        let integer = strip_leading_zeros(integer);
+        if integer.len() > field_len {
+            return None;
+        }
+        raw.resize(raw.len() + field_len - integer.len(), 0);
+        raw.extend_from_slice(integer);
+    }
+    Some(raw)
+}
+
+/// Convert a DER-encoded signature to the PSA format. Returns `None` if it is not a valid
+/// encoding of a signature over a field of that length.
+pub fn der_to_raw(der: &[u8], field_len: usize) -> Option<Vec<u8>> {
+    let (sequence, rest) = der_element(der, TAG_SEQUENCE)?;
+    let (r, sequence) = der_element(sequence, TAG_INTEGER)?;
+    let (s, sequence) = der_element(sequence, TAG_INTEGER)?;
+    if !rest.is_empty() || !sequence.is_empty() {
+        return None;
+    }
+    raw_from_integers(r, s, field_len)
+}
+
+/// Convert a signature in the PSA format to its DER encoding. Returns `None` if it can not be
+/// split in r and s values of the same length.
+pub fn raw_to_der(raw: &[u8]) -> Option<Vec<u8>> {
+    if raw.is_empty() || raw.len() % 2 != 0 {
+        return None;
+    }
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let mut integers = Vec::with_capacity(raw.len() + 6);
+    for integer in [r, s] {
+        let integer = strip_leading_zeros(integer);
+        let mut content = Vec::with_capacity(integer.len() + 1);
+        // The integers are signed: a leading zero byte keeps them positive.
+        if integer.first().map_or(true, |byte| byte & 0x80 != 0) {
+            content.push(0);
+        }
+        content.extend_from_slice(integer);
+        der_tlv(TAG_INTEGER, &content, &mut integers);
+    }
+    let mut der = Vec::with_capacity(integers.len() + 4);
+    der_tlv(TAG_SEQUENCE, &integers, &mut der);
+    Some(der)
+}
+
+/// Convert a signature given in the PSA format or DER-encoded to the PSA format. Signatures as long
+/// as the PSA format are taken to be in it.
+pub fn normalize(signature: &[u8], field_len: usize) -> Option<Vec<u8>> {
+    if signature.len() == 2 * field_len {
+        Some(signature.to_vec())
+    } else {
+        der_to_raw(signature, field_len)
+    }
+}
+
+fn strip_leading_zeros(mut integer: &[u8]) -> &[u8] {
+    while let Some((0, rest)) = integer.split_first() {
+        integer = rest;
+    }
+    integer
+}
+
+// Content of the DER element with the given tag at the start of the data, and the data after it
+fn der_element(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&found, data) = data.split_first()?;
+    let (&first, data) = data.split_first()?;
+    if found != tag {
+        return None;
+    }
+    let (length, data) = if first < 0x80 {
+        (usize::from(first), data)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 2 || data.len() < count {
+            return None;
+        }
+        let (length, data) = data.split_at(count);
+        let length = length
+            .iter()
+            .fold(0, |length, byte| (length << 8) | usize::from(*byte));
+        (length, data)
+    };
+    if data.len() < length {
+        return None;
+    }
+    Some(data.split_at(length))
+}
+
+fn der_tlv(tag: u8, content: &[u8], out: &mut Vec<u8>) {
+    out.push(tag);
+    match content.len() {
+        length @ 0..=0x7f => out.push(length as u8),
+        length @ 0x80..=0xff => out.extend_from_slice(&[0x81, length as u8]),
+        length => out.extend_from_slice(&[0x82, (length >> 8) as u8, length as u8]),
+    }
+    out.extend_from_slice(content);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_helpers::hex;
+
+    // P-256 signatures made by `openssl dgst -sign`, in DER and in the PSA format: a 31-byte r
+    // with a 33-byte s, sign byte included, and two 32-byte integers without sign bytes
+    fn short_r_der() -> Vec<u8> {
+        hex(
+            "3044021f2bef74d5d2ef3047ba4ea06bb73ff56187d573cc920c206f462eeb34d38c43\
+             022100d7a8d7294fba583c811e51adb54c5d800bac5ef53550a8a94d40b560394238c4",
+        )
+    }
+
+    fn short_r_raw() -> Vec<u8> {
+        hex(
+            "002bef74d5d2ef3047ba4ea06bb73ff56187d573cc920c206f462eeb34d38c43\
+             d7a8d7294fba583c811e51adb54c5d800bac5ef53550a8a94d40b560394238c4",
+        )
+    }
+
+    fn unsigned_der() -> Vec<u8> {
+        hex(
+            "304402200cc0a04cb35261a12a2f33db421bd68f6ed65acf83e0279b668a9a3c1d1484ad\
+             02200eba9488a5716c30825cda842b17fa6e27e96d61894fac77d90139f91bb8914c",
+        )
+    }
+
+    fn unsigned_raw() -> Vec<u8> {
+        hex(
+            "0cc0a04cb35261a12a2f33db421bd68f6ed65acf83e0279b668a9a3c1d1484ad\
+             0eba9488a5716c30825cda842b17fa6e27e96d61894fac77d90139f91bb8914c",
+        )
+    }
+
+    #[test]
+    fn field_lengths_are_rounded_up() {
+        assert_eq!(field_len(256), 32);
+        assert_eq!(field_len(384), 48);
+        assert_eq!(field_len(521), 66);
+    }
+
+    #[test]
+    fn der_signatures_are_converted_to_raw() {
+        assert_eq!(der_to_raw(&short_r_der(), 32).unwrap(), short_r_raw());
+        assert_eq!(der_to_raw(&unsigned_der(), 32).unwrap(), unsigned_raw());
+    }
+
+    #[test]
+    fn raw_signatures_are_converted_to_der() {
+        assert_eq!(raw_to_der(&short_r_raw()).unwrap(), short_r_der());
+        assert_eq!(raw_to_der(&unsigned_raw()).unwrap(), unsigned_der());
+    }
+
+    #[test]
+    fn integers_longer_than_the_field_are_refused() {
+        assert_eq!(der_to_raw(&short_r_der(), 31), None);
+        assert_eq!(raw_from_integers(&[0x01; 33], &[0x01; 32], 32), None);
+    }
+
+    #[test]
+    fn malformed_der_is_refused() {
+        let der = short_r_der();
+        // Cut short
+        assert_eq!(der_to_raw(&der[..der.len() - 1], 32), None);
+        // Followed by other data
+        assert_eq!(der_to_raw(&[der.clone(), vec![0x00]].concat(), 32), None);
+        // Not a sequence
+        let mut wrong_tag = der.clone();
+        wrong_tag[0] = 0x31;
+        assert_eq!(der_to_raw(&wrong_tag, 32), None);
+        // r not an integer
+        let mut wrong_tag = der.clone();
+        wrong_tag[2] = 0x04;
+        assert_eq!(der_to_raw(&wrong_tag, 32), None);
+        // A third integer in the sequence
+        let mut third = der[2..].to_vec();
+        third.extend_from_slice(&[TAG_INTEGER, 0x01, 0x01]);
+        let mut sequence = vec![TAG_SEQUENCE, third.len() as u8];
+        sequence.extend_from_slice(&third);
+        assert_eq!(der_to_raw(&sequence, 32), None);
+        assert_eq!(der_to_raw(&[], 32), None);
+    }
+
+    #[test]
+    fn long_form_lengths_are_used_for_p521() {
+        let raw = vec![0xff; 2 * field_len(521)];
+        let der = raw_to_der(&raw).unwrap();
+        // Two integers of 67 bytes, sign byte included
+        assert_eq!(der[..3], [TAG_SEQUENCE, 0x81, 138]);
+        assert_eq!(der[3..5], [TAG_INTEGER, 67]);
+        assert_eq!(der_to_raw(&der, 66).unwrap(), raw);
+    }
+
+    #[test]
+    fn raw_signatures_must_split_in_two() {
+        assert_eq!(raw_to_der(&[]), None);
+        assert_eq!(raw_to_der(&[0x01; 63]), None);
+    }
+
+    #[test]
+    fn zero_integers_are_encoded() {
+        assert_eq!(
+            raw_to_der(&[0x00; 4]).unwrap(),
+            [
+                TAG_SEQUENCE,
+                0x06,
+                TAG_INTEGER,
+                0x01,
+                0x00,
+                TAG_INTEGER,
+                0x01,
+                0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn integers_are_padded_to_the_field() {
+        // Extra leading zeros, as returned by some backends, and a short s
+        let r = [vec![0x00, 0x00], vec![0x11; 32]].concat();
+        let raw = raw_from_integers(&r, &[0x22; 30], 32).unwrap();
+        assert_eq!(raw[..32], [0x11; 32]);
+        assert_eq!(raw[32..34], [0x00; 2]);
+        assert_eq!(raw[34..], [0x22; 30]);
+    }
+
+    #[test]
+    fn raw_signatures_are_normalized_unchanged() {
+        assert_eq!(normalize(&short_r_raw(), 32).unwrap(), short_r_raw());
+    }
+
+    #[test]
+    fn der_signatures_are_normalized() {
+        assert_eq!(normalize(&short_r_der(), 32).unwrap(), short_r_raw());
+        assert_eq!(normalize(&[0x01; 10], 32), None);
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Confirmation of key destruction
+//!
+//! A `PsaDestroyKey` request succeeding means that the key material is gone, not only its name:
+//! after destroying a key, providers look it up again in their backend (by finding its objects,
+//! reading its attributes, ...) and only report success if it cannot be found any more. The
+//! mappings removed from the Key Info Manager are scrubbed by the managers themselves.
+use crate::utils::logging::Redacted;
+use log::error;
+use parsec_interface::requests::{ResponseStatus, Result};
+
+/// Status of a `PsaDestroyKey` request whose key could not be confirmed as removed from the
+/// backend. The key name is released anyway, but the material might still be held.
+pub const DESTRUCTION_UNCONFIRMED: ResponseStatus = ResponseStatus::PsaErrorCorruptionDetected;
+
+/// Check the result of looking a destroyed key up in the backend: only a key which does not exist
+/// (or whose handle is invalid) any more confirms the destruction.
+pub fn confirm_destruction<T>(key_name: &str, lookup: Result<T>) -> Result<()> {
+    match lookup {
+        Err(ResponseStatus::PsaErrorDoesNotExist) | Err(ResponseStatus::PsaErrorInvalidHandle) => {
+            Ok(())
+        }
+        Ok(_) => {
+            error!(
+                "The key \"{}\" is still present in the backend after being destroyed.",
+                Redacted(key_name)
+            );
+            Err(DESTRUCTION_UNCONFIRMED)
+        }
+        Err(e) => {
+            format_error!(
+                &format!(
+                    "The destruction of the key \"{}\" could not be confirmed",
+                    Redacted(key_name)
+                ),
+                e
+            );
+            Err(DESTRUCTION_UNCONFIRMED)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_key_confirms_destruction() {
+        assert!(
+            confirm_destruction::<()>("key", Err(ResponseStatus::PsaErrorDoesNotExist)).is_ok()
+        );
+    }
+
+    #[test]
+    fn invalid_handle_confirms_destruction() {
+        assert!(
+            confirm_destruction::<()>("key", Err(ResponseStatus::PsaErrorInvalidHandle)).is_ok()
+        );
+    }
+
+    #[test]
+    fn key_still_present_is_unconfirmed() {
+        assert_eq!(
+            confirm_destruction("key", Ok(vec![0x11, 0x22])),
+            Err(DESTRUCTION_UNCONFIRMED)
+        );
+    }
+
+    #[test]
+    fn failed_lookup_is_unconfirmed() {
+        for status in &[
+            ResponseStatus::PsaErrorCommunicationFailure,
+            ResponseStatus::PsaErrorHardwareFailure,
+            ResponseStatus::PsaErrorNotPermitted,
+        ] {
+            assert_eq!(
+                confirm_destruction::<()>("key", Err(*status)),
+                Err(DESTRUCTION_UNCONFIRMED)
+            );
+        }
+    }
+}
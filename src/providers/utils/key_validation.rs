@@ -0,0 +1,474 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Validation of imported key material
+//!
+//! Key data received in `PsaImportKey` requests comes straight from clients and ends up being
+//! handed to backend libraries (TSS, PKCS#11 modules, ...) which might not handle malformed
+//! encodings gracefully. The parsers in this module are deliberately strict: the structures are
+//! decoded with picky-asn1-der and then encoded again, so that only DER (no BER leniency such as
+//! indefinite or non-minimal lengths) without trailing data is accepted. The basic sanity of the
+//! values parsed is checked as well, so that providers can reject bad key material before it
+//! reaches those libraries.
+//!
+//! For the formats expected for each key type, see the
+//! [Parsec Book](https://parallaxsecond.github.io/parsec-book/parsec_client/operations/psa_export_public_key.html#description).
+use derivative::Derivative;
+use log::error;
+use parsec_interface::operations::psa_key_attributes::EccFamily;
+use parsec_interface::requests::{ResponseStatus, Result};
+use picky_asn1_x509::{RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Largest RSA modulus accepted, in bits.
+pub const MAX_RSA_KEY_BITS: usize = 16384;
+
+/// Components of an RSA public key.
+///
+/// The integers are big-endian and stripped from their sign byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsaPublicKeyComponents {
+    /// Modulus (n)
+    pub modulus: Vec<u8>,
+    /// Public exponent (e)
+    pub public_exponent: Vec<u8>,
+}
+
+impl RsaPublicKeyComponents {
+    /// Size of the key in bits, as deduced from the length of the modulus.
+    pub fn key_bits(&self) -> usize {
+        self.modulus.len() * 8
+    }
+}
+
+/// Components of a two-prime RSA private key, zeroized on drop.
+///
+/// The integers are big-endian and stripped from their sign byte.
+#[derive(Derivative, Zeroize)]
+#[derivative(Debug)]
+#[zeroize(drop)]
+pub struct RsaPrivateKeyComponents {
+    /// Modulus (n)
+    pub modulus: Vec<u8>,
+    /// Public exponent (e)
+    pub public_exponent: Vec<u8>,
+    /// Private exponent (d)
+    #[derivative(Debug = "ignore")]
+    pub private_exponent: Vec<u8>,
+    /// First prime factor (p)
+    #[derivative(Debug = "ignore")]
+    pub prime_1: Vec<u8>,
+    /// Second prime factor (q)
+    #[derivative(Debug = "ignore")]
+    pub prime_2: Vec<u8>,
+    /// d mod (p - 1)
+    #[derivative(Debug = "ignore")]
+    pub exponent_1: Vec<u8>,
+    /// d mod (q - 1)
+    #[derivative(Debug = "ignore")]
+    pub exponent_2: Vec<u8>,
+    /// (inverse of q) mod p
+    #[derivative(Debug = "ignore")]
+    pub coefficient: Vec<u8>,
+}
+
+impl RsaPrivateKeyComponents {
+    /// Size of the key in bits, as deduced from the length of the modulus.
+    pub fn key_bits(&self) -> usize {
+        self.modulus.len() * 8
+    }
+
+    /// Public part of the key pair.
+    pub fn public_key(&self) -> RsaPublicKeyComponents {
+        RsaPublicKeyComponents {
+            modulus: self.modulus.clone(),
+            public_exponent: self.public_exponent.clone(),
+        }
+    }
+}
+
+fn decode<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T> {
+    picky_asn1_der::from_bytes(data).map_err(|e| {
+        format_error!("Failed to decode the key data", e);
+        ResponseStatus::PsaErrorInvalidArgument
+    })
+}
+
+/// Whether the data is exactly the DER encoding of the value decoded from it.
+///
+/// picky-asn1-der also decodes some BER encodings, such as non-minimal lengths, and ignores the
+/// data following the value.
+fn is_der<T: Serialize>(value: &T, data: &[u8]) -> bool {
+    match picky_asn1_der::to_vec(value) {
+        Ok(der) => Zeroizing::new(der).as_slice() == data,
+        Err(_) => false,
+    }
+}
+
+/// Checks that the contents of an INTEGER are those of a minimally encoded positive integer and
+/// strips them from their sign byte.
+fn strip_positive_integer(integer: &mut Vec<u8>) -> Result<()> {
+    match integer.as_slice() {
+        [] => {
+            error!("Empty DER integer.");
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        }
+        [first, ..] if first & 0x80 != 0 => {
+            error!("Only positive integers are supported.");
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        }
+        [0, second, ..] if second & 0x80 == 0 => {
+            error!("DER integer is not minimally encoded.");
+            Err(ResponseStatus::PsaErrorInvalidArgument)
+        }
+        [0, _, ..] => {
+            let _ = integer.remove(0);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_zero(data: &[u8]) -> bool {
+    data.iter().all(|byte| *byte == 0)
+}
+
+fn is_odd(data: &[u8]) -> bool {
+    data.last().map_or(false, |byte| byte & 1 == 1)
+}
+
+fn check_rsa_public_components(modulus: &[u8], public_exponent: &[u8]) -> Result<()> {
+    if is_zero(modulus) || !is_odd(modulus) {
+        error!("RSA modulus must be a non-zero odd integer.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    if modulus.len() * 8 > MAX_RSA_KEY_BITS {
+        if crate::utils::GlobalConfig::log_error_details() {
+            error!(
+                "RSA modulus of {} bits is larger than the maximum supported ({} bits).",
+                modulus.len() * 8,
+                MAX_RSA_KEY_BITS
+            );
+        } else {
+            error!("RSA modulus is larger than the maximum supported.");
+        }
+        return Err(ResponseStatus::PsaErrorNotSupported);
+    }
+    // The public exponent must be odd, greater than 1 and smaller than the modulus.
+    if !is_odd(public_exponent)
+        || public_exponent == [1]
+        || public_exponent.len() > modulus.len()
+        || (public_exponent.len() == modulus.len() && public_exponent >= modulus)
+    {
+        error!("RSA public exponent is invalid.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    Ok(())
+}
+
+/// Parses and validates a DER encoded `RSAPublicKey` structure, as defined in RFC 3279.
+pub fn parse_rsa_public_key(data: &[u8]) -> Result<RsaPublicKeyComponents> {
+    let key: RsaPublicKey = decode(data)?;
+    if !is_der(&key, data) {
+        error!("RSA public key is not DER encoded or is followed by other data.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    let mut key = RsaPublicKeyComponents {
+        modulus: key.modulus.0,
+        public_exponent: key.public_exponent.0,
+    };
+    strip_positive_integer(&mut key.modulus)?;
+    strip_positive_integer(&mut key.public_exponent)?;
+
+    check_rsa_public_components(&key.modulus, &key.public_exponent)?;
+
+    Ok(key)
+}
+
+/// Parses and validates a DER encoded `RSAPrivateKey` structure, as defined in RFC 8017.
+///
+/// Only two-prime keys (version 0) are accepted.
+pub fn parse_rsa_private_key(data: &[u8]) -> Result<RsaPrivateKeyComponents> {
+    let key: RsaPrivateKey = decode(data)?;
+    let der = is_der(&key, data);
+    let RsaPrivateKey {
+        version,
+        modulus,
+        public_exponent,
+        private_exponent,
+        prime_1,
+        prime_2,
+        exponent_1,
+        exponent_2,
+        coefficient,
+    } = key;
+    // The components are zeroized on drop, whatever is wrong with them.
+    let mut key = RsaPrivateKeyComponents {
+        modulus: modulus.0,
+        public_exponent: public_exponent.0,
+        private_exponent: private_exponent.0,
+        prime_1: prime_1.0,
+        prime_2: prime_2.0,
+        exponent_1: exponent_1.0,
+        exponent_2: exponent_2.0,
+        coefficient: coefficient.0,
+    };
+    if !der {
+        error!("RSA private key is not DER encoded or is followed by other data.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    if version.0 != [0] {
+        error!("Only two-prime RSA private keys (version 0) are supported.");
+        return Err(ResponseStatus::PsaErrorNotSupported);
+    }
+    for integer in [
+        &mut key.modulus,
+        &mut key.public_exponent,
+        &mut key.private_exponent,
+        &mut key.prime_1,
+        &mut key.prime_2,
+        &mut key.exponent_1,
+        &mut key.exponent_2,
+        &mut key.coefficient,
+    ]
+    .iter_mut()
+    {
+        strip_positive_integer(integer)?;
+    }
+
+    check_rsa_public_components(&key.modulus, &key.public_exponent)?;
+
+    let modulus_len = key.modulus.len();
+    let private_values = [
+        &key.private_exponent,
+        &key.prime_1,
+        &key.prime_2,
+        &key.exponent_1,
+        &key.exponent_2,
+        &key.coefficient,
+    ];
+    if private_values
+        .iter()
+        .any(|value| is_zero(value) || value.len() > modulus_len)
+    {
+        error!("RSA private key contains invalid values.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    // Both primes are needed to build a modulus of the size given.
+    if key.prime_1.len() + key.prime_2.len() < modulus_len
+        || !is_odd(&key.prime_1)
+        || !is_odd(&key.prime_2)
+    {
+        error!("RSA private key primes do not match the modulus.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+
+    Ok(key)
+}
+
+/// Validates the encoding of an elliptic curve public key for the given curve family and returns
+/// the size of the key in bits.
+///
+/// If `bits` is not 0, the key data must have the length expected for a key of that size.
+///
+/// * Weierstrass curves are expected as an uncompressed point `0x04 || x || y`, as defined in
+/// section 2.3.3 of [SEC1](https://www.secg.org/sec1-v2.pdf)
+/// * Montgomery curves are expected as the raw x coordinate, as defined in RFC 7748
+pub fn validate_ecc_public_key(data: &[u8], curve_family: EccFamily, bits: usize) -> Result<usize> {
+    let (coordinates, coordinate_count) = match curve_family {
+        EccFamily::Montgomery => (data, 1),
+        _ => match data.split_first() {
+            Some((0x04, coordinates)) => (coordinates, 2),
+            Some((0x02, _)) | Some((0x03, _)) => {
+                error!("Compressed elliptic curve points are not supported.");
+                return Err(ResponseStatus::PsaErrorNotSupported);
+            }
+            _ => {
+                error!("ECC public key buffer is incorrectly formatted.");
+                return Err(ResponseStatus::PsaErrorInvalidArgument);
+            }
+        },
+    };
+
+    if coordinates.is_empty() || coordinates.len() % coordinate_count != 0 {
+        error!("ECC public key buffer has an invalid length.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    let coordinate_len = coordinates.len() / coordinate_count;
+
+    if bits != 0 && (bits + 7) / 8 != coordinate_len {
+        if crate::utils::GlobalConfig::log_error_details() {
+            error!(
+                "`bits` field of key attributes (value: {}) must be either 0 or match the size of the coordinates in `data` (value: {} bytes).",
+                bits,
+                coordinate_len
+            );
+        } else {
+            error!("`bits` field of key attributes must be either 0 or match the size of the coordinates in `data`.");
+        }
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+
+    if coordinates.chunks(coordinate_len).all(is_zero) {
+        error!("ECC public key is the point at infinity or the zero point.");
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+
+    Ok(match (bits, curve_family) {
+        (0, EccFamily::Montgomery) if coordinate_len == 32 => 255,
+        (0, EccFamily::SecpR1) if coordinate_len == 66 => 521,
+        (0, _) => coordinate_len * 8,
+        (bits, _) => bits,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // RSAPublicKey { modulus: 0xc5000001 (with a sign byte), publicExponent: 0x010001 }
+    const SMALL_RSA_PUBLIC_KEY: [u8; 14] = [
+        0x30, 0x0c, 0x02, 0x05, 0x00, 0xc5, 0x00, 0x00, 0x01, 0x02, 0x03, 0x01, 0x00, 0x01,
+    ];
+
+    #[test]
+    fn parse_valid_rsa_public_key() {
+        let key = parse_rsa_public_key(&SMALL_RSA_PUBLIC_KEY).unwrap();
+        assert_eq!(key.modulus, [0xc5, 0x00, 0x00, 0x01]);
+        assert_eq!(key.public_exponent, [0x01, 0x00, 0x01]);
+        assert_eq!(key.key_bits(), 32);
+    }
+
+    #[test]
+    fn reject_malformed_rsa_public_keys() {
+        // Trailing data
+        let mut trailing = SMALL_RSA_PUBLIC_KEY.to_vec();
+        trailing.push(0x00);
+        assert!(parse_rsa_public_key(&trailing).is_err());
+
+        // Truncated
+        assert!(parse_rsa_public_key(&SMALL_RSA_PUBLIC_KEY[..13]).is_err());
+
+        // Indefinite length
+        let mut indefinite = SMALL_RSA_PUBLIC_KEY.to_vec();
+        indefinite[1] = 0x80;
+        assert!(parse_rsa_public_key(&indefinite).is_err());
+
+        // Non-minimal length
+        let mut non_minimal = vec![0x30, 0x81];
+        non_minimal.extend_from_slice(&SMALL_RSA_PUBLIC_KEY[1..]);
+        assert!(parse_rsa_public_key(&non_minimal).is_err());
+
+        // Negative modulus
+        let negative = [
+            0x30, 0x0b, 0x02, 0x04, 0xc5, 0x00, 0x00, 0x01, 0x02, 0x03, 0x01, 0x00, 0x01,
+        ];
+        assert!(parse_rsa_public_key(&negative).is_err());
+
+        // Non-minimal integer
+        let padded = [
+            0x30, 0x0d, 0x02, 0x06, 0x00, 0x00, 0xc5, 0x00, 0x00, 0x01, 0x02, 0x03, 0x01, 0x00,
+            0x01,
+        ];
+        assert!(parse_rsa_public_key(&padded).is_err());
+
+        // Even public exponent
+        let mut even_exponent = SMALL_RSA_PUBLIC_KEY;
+        even_exponent[13] = 0x02;
+        assert!(parse_rsa_public_key(&even_exponent).is_err());
+
+        assert!(parse_rsa_public_key(&[]).is_err());
+    }
+
+    // RSAPrivateKey { version: 0, n: 0x8f (143), e: 7, d: 103, p: 11, q: 13, dp: 3, dq: 7,
+    // qinv: 6 }
+    const SMALL_RSA_PRIVATE_KEY: [u8; 30] = [
+        0x30, 0x1c, 0x02, 0x01, 0x00, 0x02, 0x02, 0x00, 0x8f, 0x02, 0x01, 0x07, 0x02, 0x01, 0x67,
+        0x02, 0x01, 0x0b, 0x02, 0x01, 0x0d, 0x02, 0x01, 0x03, 0x02, 0x01, 0x07, 0x02, 0x01, 0x06,
+    ];
+
+    #[test]
+    fn reject_malformed_rsa_private_keys() {
+        let mut trailing = SMALL_RSA_PRIVATE_KEY.to_vec();
+        trailing.push(0x00);
+        assert!(parse_rsa_private_key(&trailing).is_err());
+
+        assert!(parse_rsa_private_key(&SMALL_RSA_PRIVATE_KEY[..29]).is_err());
+
+        // Even first prime
+        let mut even_prime = SMALL_RSA_PRIVATE_KEY;
+        even_prime[17] = 0x0c;
+        assert_eq!(
+            parse_rsa_private_key(&even_prime).unwrap_err(),
+            ResponseStatus::PsaErrorInvalidArgument
+        );
+
+        // Zero private exponent
+        let mut zero_exponent = SMALL_RSA_PRIVATE_KEY;
+        zero_exponent[14] = 0x00;
+        assert!(parse_rsa_private_key(&zero_exponent).is_err());
+    }
+
+    #[test]
+    fn parse_rsa_private_key_checks_version() {
+        let mut key = SMALL_RSA_PRIVATE_KEY;
+        let parsed = parse_rsa_private_key(&key).unwrap();
+        assert_eq!(parsed.public_key().modulus, [0x8f]);
+        assert_eq!(parsed.prime_2, [0x0d]);
+
+        // Multi-prime keys are not supported
+        key[4] = 0x01;
+        assert_eq!(
+            parse_rsa_private_key(&key).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+    }
+
+    #[test]
+    fn validate_ecc_points() {
+        let mut point = vec![0x04];
+        point.extend_from_slice(&[0x11; 64]);
+        assert_eq!(
+            validate_ecc_public_key(&point, EccFamily::SecpR1, 0).unwrap(),
+            256
+        );
+        assert_eq!(
+            validate_ecc_public_key(&point, EccFamily::SecpR1, 256).unwrap(),
+            256
+        );
+        assert!(validate_ecc_public_key(&point, EccFamily::SecpR1, 384).is_err());
+
+        let mut p521_point = vec![0x04];
+        p521_point.extend_from_slice(&[0x01; 132]);
+        assert_eq!(
+            validate_ecc_public_key(&p521_point, EccFamily::SecpR1, 0).unwrap(),
+            521
+        );
+
+        // Odd number of coordinate bytes
+        assert!(validate_ecc_public_key(&point[..64], EccFamily::SecpR1, 0).is_err());
+
+        // Compressed point
+        point[0] = 0x02;
+        assert_eq!(
+            validate_ecc_public_key(&point[..33], EccFamily::SecpR1, 0).unwrap_err(),
+            ResponseStatus::PsaErrorNotSupported
+        );
+
+        // Point at infinity
+        assert!(validate_ecc_public_key(&[0x00], EccFamily::SecpR1, 0).is_err());
+        let mut zero = vec![0x04];
+        zero.extend_from_slice(&[0x00; 64]);
+        assert!(validate_ecc_public_key(&zero, EccFamily::SecpR1, 0).is_err());
+
+        // Montgomery keys are a single coordinate
+        assert_eq!(
+            validate_ecc_public_key(&[0x09; 32], EccFamily::Montgomery, 0).unwrap(),
+            255
+        );
+        assert_eq!(
+            validate_ecc_public_key(&[0x09; 56], EccFamily::Montgomery, 448).unwrap(),
+            448
+        );
+    }
+}
@@ -0,0 +1,23 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Utilities shared between providers
+//!
+//! Contrary to the `utils` modules found inside each provider, the functions here do not depend
+//! on any particular backend library and can be used by all providers.
+pub mod aes_kw;
+pub mod ecdsa_signature;
+pub mod key_destruction;
+#[cfg(any(
+    feature = "pkcs11-provider",
+    feature = "tpm-provider",
+    feature = "import-checks",
+    feature = "key-import-formats",
+    feature = "key-export-formats"
+))]
+pub mod key_validation;
+#[cfg(any(feature = "forwarding-provider", feature = "external-provider"))]
+pub mod remote_client;
+#[cfg(feature = "import-checks")]
+pub mod weak_keys;
+#[cfg(feature = "xchacha20-poly1305")]
+pub mod xchacha20;
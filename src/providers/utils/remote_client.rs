@@ -0,0 +1,308 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Client of a remote service, or of a plugin, reached through the Parsec wire protocol
+use crate::front::domain_socket::{abstract_name, connect_abstract};
+use crate::utils::GlobalConfig;
+use log::error;
+use parsec_interface::operations::{Convert, NativeOperation, NativeResult};
+use parsec_interface::operations_protobuf::ProtobufConverter;
+use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+use parsec_interface::requests::{
+    AuthType, BodyType, Opcode, ProviderId, Request, Response, ResponseStatus, Result,
+};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Connection parameters to the remote service
+#[derive(Debug)]
+pub struct RemoteClient {
+    socket_path: String,
+    timeout: Option<Duration>,
+}
+
+impl RemoteClient {
+    /// Client of the service listening on the socket at `socket_path`
+    pub fn new(socket_path: String, timeout: Option<Duration>) -> Self {
+        RemoteClient {
+            socket_path,
+            timeout,
+        }
+    }
+
+    fn connect(&self) -> std::io::Result<UnixStream> {
+        let path = Path::new(&self.socket_path);
+        let stream = match abstract_name(path) {
+            Some(name) => connect_abstract(name)?,
+            None => UnixStream::connect(path)?,
+        };
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        Ok(stream)
+    }
+
+    /// Send an operation to a provider of the remote service, as the given remote application.
+    ///
+    /// See [`request`] for the authentication of the application, and [`result`] for the status
+    /// returned.
+    pub fn send(
+        &self,
+        provider: ProviderId,
+        application: Option<&str>,
+        operation: NativeOperation,
+    ) -> Result<NativeResult> {
+        let opcode = operation.opcode();
+        let request = request(provider, application, operation)?;
+
+        let mut stream = self.connect().map_err(|e| {
+            format_error!(
+                format!(
+                    "Failed to connect to the remote service at {}",
+                    self.socket_path
+                ),
+                e
+            );
+            ResponseStatus::PsaErrorCommunicationFailure
+        })?;
+        let response = request
+            .write_to_stream(&mut stream)
+            .and_then(|_| {
+                Response::read_from_stream(&mut stream, GlobalConfig::buffer_size_limit())
+            })
+            .map_err(|e| {
+                format_error!("The remote service did not answer", e);
+                ResponseStatus::PsaErrorCommunicationFailure
+            })?;
+        result(response, opcode)
+    }
+}
+
+/// Request of an operation to a provider, as the given remote application.
+///
+/// The application is authenticated with the direct authenticator, the operation is sent without
+/// authentication if there is none.
+pub fn request(
+    provider: ProviderId,
+    application: Option<&str>,
+    operation: NativeOperation,
+) -> Result<Request> {
+    let opcode = operation.opcode();
+    let (auth_type, auth) = match application {
+        Some(application) => (AuthType::Direct, application.as_bytes().to_vec()),
+        None => (AuthType::NoAuth, Vec::new()),
+    };
+    Ok(Request {
+        header: RequestHeader {
+            provider,
+            session: 0,
+            content_type: BodyType::Protobuf,
+            accept_type: BodyType::Protobuf,
+            auth_type,
+            opcode,
+        },
+        body: ProtobufConverter {}.operation_to_body(operation)?,
+        auth: RequestAuth::new(auth),
+    })
+}
+
+/// Result of the operation answered by a response. The status of a failed remote operation is
+/// returned as is.
+pub fn result(response: Response, opcode: Opcode) -> Result<NativeResult> {
+    if response.header.status != ResponseStatus::Success {
+        return Err(response.header.status);
+    }
+    if response.header.opcode != opcode {
+        error!("The remote service answered to another operation.");
+        return Err(ResponseStatus::PsaErrorCommunicationFailure);
+    }
+    ProtobufConverter {}.body_to_result(response.body, opcode)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parsec_interface::operations::{ping, psa_destroy_key, psa_generate_random};
+    use parsec_interface::secrecy::ExposeSecret;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::thread::{self, JoinHandle};
+
+    fn destroy_key() -> NativeOperation {
+        NativeOperation::PsaDestroyKey(psa_destroy_key::Operation {
+            key_name: String::from("key"),
+        })
+    }
+
+    fn ping_response(opcode: Opcode) -> Response {
+        let mut response = Response::from_request_header(
+            request(
+                ProviderId::Core,
+                None,
+                NativeOperation::Ping(ping::Operation {}),
+            )
+            .unwrap()
+            .header,
+            ResponseStatus::Success,
+        );
+        response.body = ProtobufConverter {}
+            .result_to_body(NativeResult::Ping(ping::Result {
+                wire_protocol_version_maj: 1,
+                wire_protocol_version_min: 0,
+            }))
+            .unwrap();
+        response.header.opcode = opcode;
+        response
+    }
+
+    // Service answering a single request, or closing the connection if there is no response
+    fn service(
+        listener: UnixListener,
+        answer: impl FnOnce(Request) -> Option<Response> + Send + 'static,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = Request::read_from_stream(&mut stream, 1024).unwrap();
+            if let Some(response) = answer(request) {
+                response.write_to_stream(&mut stream).unwrap();
+            }
+        })
+    }
+
+    fn socket(dir: &Path) -> (String, UnixListener) {
+        let socket_path = dir.join("service.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        (socket_path.display().to_string(), listener)
+    }
+
+    #[test]
+    fn applications_are_authenticated_directly() {
+        let request = request(ProviderId::Pkcs11, Some("app"), destroy_key()).unwrap();
+        assert_eq!(request.header.provider, ProviderId::Pkcs11);
+        assert_eq!(request.header.opcode, Opcode::PsaDestroyKey);
+        assert_eq!(request.header.auth_type, AuthType::Direct);
+        assert_eq!(request.header.content_type, BodyType::Protobuf);
+        assert_eq!(request.header.accept_type, BodyType::Protobuf);
+        assert_eq!(request.auth.buffer.expose_secret(), b"app");
+    }
+
+    #[test]
+    fn operations_without_application_are_not_authenticated() {
+        let operation =
+            NativeOperation::PsaGenerateRandom(psa_generate_random::Operation { size: 8 });
+        let request = request(ProviderId::Pkcs11, None, operation).unwrap();
+        assert_eq!(request.header.auth_type, AuthType::NoAuth);
+        assert!(request.auth.buffer.expose_secret().is_empty());
+    }
+
+    #[test]
+    fn results_are_decoded() {
+        assert!(matches!(
+            result(ping_response(Opcode::Ping), Opcode::Ping),
+            Ok(NativeResult::Ping(ping::Result {
+                wire_protocol_version_maj: 1,
+                wire_protocol_version_min: 0,
+            }))
+        ));
+    }
+
+    #[test]
+    fn failed_statuses_are_returned_as_is() {
+        let mut response = ping_response(Opcode::Ping);
+        response.header.status = ResponseStatus::PsaErrorDoesNotExist;
+        assert_eq!(
+            result(response, Opcode::Ping).unwrap_err(),
+            ResponseStatus::PsaErrorDoesNotExist
+        );
+    }
+
+    #[test]
+    fn responses_to_another_operation_are_refused() {
+        assert_eq!(
+            result(ping_response(Opcode::Ping), Opcode::PsaDestroyKey).unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+    }
+
+    #[test]
+    fn operations_are_sent_to_the_service() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, listener) = socket(dir.path());
+        let service = service(listener, |request| {
+            assert_eq!(request.header.opcode, Opcode::Ping);
+            assert_eq!(request.header.provider, ProviderId::Core);
+            Some(ping_response(Opcode::Ping))
+        });
+        let client = RemoteClient::new(socket_path, Some(Duration::from_secs(10)));
+        let result = client.send(
+            ProviderId::Core,
+            None,
+            NativeOperation::Ping(ping::Operation {}),
+        );
+        service.join().unwrap();
+        assert!(matches!(result, Ok(NativeResult::Ping(_))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn abstract_sockets_are_reached() {
+        let name = format!("parsec-remote-client-test-{}", std::process::id());
+        let listener = crate::front::domain_socket::listen_abstract(name.as_bytes()).unwrap();
+        let service = service(listener, |_| Some(ping_response(Opcode::Ping)));
+        let client = RemoteClient::new(format!("@{}", name), Some(Duration::from_secs(10)));
+        let result = client.send(
+            ProviderId::Core,
+            None,
+            NativeOperation::Ping(ping::Operation {}),
+        );
+        service.join().unwrap();
+        assert!(matches!(result, Ok(NativeResult::Ping(_))));
+    }
+
+    #[test]
+    fn unreachable_services_are_communication_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = RemoteClient::new(dir.path().join("missing.sock").display().to_string(), None);
+        assert_eq!(
+            client
+                .send(ProviderId::Pkcs11, Some("app"), destroy_key())
+                .unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+    }
+
+    #[test]
+    fn unanswered_requests_are_communication_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, listener) = socket(dir.path());
+        let service = service(listener, |_| None);
+        let client = RemoteClient::new(socket_path, Some(Duration::from_secs(10)));
+        assert_eq!(
+            client
+                .send(ProviderId::Pkcs11, Some("app"), destroy_key())
+                .unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn slow_services_time_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket_path, listener) = socket(dir.path());
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        // Keep the connection open without answering until the request fails.
+        let service = service(listener, move |_| {
+            let _ = wait.recv();
+            None
+        });
+        let client = RemoteClient::new(socket_path, Some(Duration::from_millis(100)));
+        assert_eq!(
+            client
+                .send(ProviderId::Pkcs11, Some("app"), destroy_key())
+                .unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+        drop(done);
+        service.join().unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Detection of weak key material
+//!
+//! The parsers of `key_validation` make sure that imported keys are well encoded; the checks in
+//! this module look at their values. They find RSA moduli with a small factor, with a factor
+//! shared with another modulus or with factors close enough for Fermat's method to find them,
+//! elliptic curve points which are not on their curve and symmetric keys whose bytes show little
+//! entropy.
+//!
+//! The arithmetic is done on the `BigUint` integers of the num-bigint crate.
+use num_bigint::BigUint;
+use num_integer::Integer;
+use num_traits::{One, Zero};
+use parsec_interface::operations::psa_key_attributes::EccFamily;
+
+/// Number of candidates tried by Fermat's method before giving up
+pub const FERMAT_ROUNDS: usize = 64;
+
+/// Bound below which the primes dividing a modulus are looked for
+const SMALL_PRIMES_BOUND: u32 = 1000;
+
+fn is_square(value: &BigUint) -> bool {
+    // Only twelve residues modulo 64 are squares: most integers are ruled out at once.
+    const SQUARES_MOD_64: u64 = 0x0202_0212_0203_0213;
+    let low = value.iter_u32_digits().next().unwrap_or(0) & 63;
+    if SQUARES_MOD_64 >> low & 1 == 0 {
+        return false;
+    }
+    let root = value.sqrt();
+    &root * &root == *value
+}
+
+fn from_hex(hex: &str) -> BigUint {
+    BigUint::parse_bytes(hex.as_bytes(), 16).expect("hexadecimal constant")
+}
+
+/// Smallest prime factor of the modulus below a thousand, if any
+pub fn small_factor(modulus: &BigUint) -> Option<u32> {
+    let mut sieve = vec![true; SMALL_PRIMES_BOUND as usize];
+    (2..SMALL_PRIMES_BOUND).find(|candidate| {
+        if !sieve[*candidate as usize] {
+            return false;
+        }
+        for multiple in (candidate * candidate..SMALL_PRIMES_BOUND).step_by(*candidate as usize) {
+            sieve[multiple as usize] = false;
+        }
+        BigUint::from(*candidate) < *modulus && (modulus % *candidate).is_zero()
+    })
+}
+
+/// Whether two moduli share one factor without being equal
+pub fn shares_factor(modulus: &BigUint, other: &BigUint) -> bool {
+    let gcd = modulus.gcd(other);
+    !gcd.is_one() && gcd != *modulus && gcd != *other
+}
+
+/// Whether the modulus is the product of two factors close enough to be found by Fermat's method
+/// in the given number of rounds
+pub fn has_close_factors(modulus: &BigUint, rounds: usize) -> bool {
+    // n = a² - b² = (a - b)(a + b), trying the values of a upwards from the square root of n
+    let mut a = modulus.sqrt();
+    if &a * &a == *modulus {
+        return true;
+    }
+    a += 1u32;
+    let mut b_square = &a * &a - modulus;
+    for _ in 0..rounds {
+        if is_square(&b_square) {
+            return true;
+        }
+        // (a + 1)² - n = a² - n + 2a + 1
+        b_square += (&a << 1) + 1u32;
+        a += 1u32;
+    }
+    false
+}
+
+/// Whether the primes of a key pair are closer than the FIPS 186-4 bound, `|p - q| ≤ 2^(nlen/2 -
+/// 100)`
+pub fn primes_too_close(prime_1: &BigUint, prime_2: &BigUint, modulus_bits: u64) -> bool {
+    let difference = if prime_1 < prime_2 {
+        prime_2 - prime_1
+    } else {
+        prime_1 - prime_2
+    };
+    difference.bits() <= (modulus_bits / 2).saturating_sub(100) + 1
+}
+
+/// Parameters of a short Weierstrass curve `y² = x³ + ax + b` over the integers modulo p
+struct Curve {
+    p: BigUint,
+    a: BigUint,
+    b: BigUint,
+}
+
+fn curve(curve_family: EccFamily, bits: usize) -> Option<Curve> {
+    // Curves with a = -3
+    let r1 = |p: &str, b: &str| {
+        let p = from_hex(p);
+        Some(Curve {
+            a: &p - 3u32,
+            p,
+            b: from_hex(b),
+        })
+    };
+    match (curve_family, bits) {
+        (EccFamily::SecpR1, 192) => r1(
+            "fffffffffffffffffffffffffffffffeffffffffffffffff",
+            "64210519e59c80e70fa7e9ab72243049feb8deecc146b9b1",
+        ),
+        (EccFamily::SecpR1, 224) => r1(
+            "ffffffffffffffffffffffffffffffff000000000000000000000001",
+            "b4050a850c04b3abf54132565044b0b7d7bfd8ba270b39432355ffb4",
+        ),
+        (EccFamily::SecpR1, 256) => r1(
+            "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff",
+            "5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b",
+        ),
+        (EccFamily::SecpR1, 384) => r1(
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffe\
+             ffffffff0000000000000000ffffffff",
+            "b3312fa7e23ee7e4988e056be3f82d19181d9c6efe8141120314088f5013875a\
+             c656398d8a2ed19d2a85c8edd3ec2aef",
+        ),
+        (EccFamily::SecpR1, 521) => r1(
+            "01ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff\
+             ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff\
+             ffff",
+            "0051953eb9618e1c9a1f929a21a0b68540eea2da725b99b315f3b8b489918ef1\
+             09e156193951ec7e937b1652c0bd3bb1bf073573df883d2c34f1ef451fd46b50\
+             3f00",
+        ),
+        (EccFamily::SecpK1, 256) => Some(Curve {
+            p: from_hex("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"),
+            a: BigUint::zero(),
+            b: BigUint::from(7u32),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether an uncompressed point, of a key already checked by `validate_ecc_public_key`, is on
+/// its curve
+///
+/// Returns `None` for the curves whose parameters are not known here.
+pub fn is_on_curve(data: &[u8], curve_family: EccFamily, bits: usize) -> Option<bool> {
+    let curve = curve(curve_family, bits)?;
+    let coordinates = data.get(1..)?;
+    let (x, y) = coordinates.split_at(coordinates.len() / 2);
+    let (x, y) = (BigUint::from_bytes_be(x), BigUint::from_bytes_be(y));
+    if x >= curve.p || y >= curve.p {
+        return Some(false);
+    }
+    let left = &y * &y % &curve.p;
+    let right = ((&x * &x + &curve.a) * &x + &curve.b) % &curve.p;
+    Some(left == right)
+}
+
+fn shannon_entropy(symbols: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for symbol in symbols {
+        counts[*symbol as usize] += 1;
+    }
+    let total = symbols.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count != 0)
+        .map(|count| {
+            let probability = *count as f64 / total;
+            -probability * probability.log2()
+        })
+        .sum::<f64>()
+        * total
+}
+
+/// Estimate, in bits, of the entropy of a symmetric key
+///
+/// The Shannon entropy of the distribution of its bytes, which a short key can only reach if they
+/// are all different, and of the differences between consecutive bytes, so that counting
+/// sequences are not mistaken for random ones. The lower of the two is returned.
+pub fn estimate_entropy(key: &[u8]) -> f64 {
+    let differences: Vec<u8> = key
+        .windows(2)
+        .map(|pair| pair[1].wrapping_sub(pair[0]))
+        .collect();
+    shannon_entropy(key).min(shannon_entropy(&differences))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex_bytes(hex: &str) -> Vec<u8> {
+        from_hex(hex).to_bytes_be()
+    }
+
+    fn p() -> BigUint {
+        from_hex("e0a1a443bfa4df2f1b0d3cd4bc3d9b7d")
+    }
+
+    fn q() -> BigUint {
+        from_hex("e0a1a443bfa4df2f1b0d3cd4bc3d9c23")
+    }
+
+    fn r() -> BigUint {
+        from_hex("c6e1f4f3a9e7a2b9d5e1c3f08b7a6d15")
+    }
+
+    fn generator() -> Vec<u8> {
+        hex_bytes(
+            "046b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c2\
+             964fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51\
+             f5",
+        )
+    }
+
+    #[test]
+    fn small_factor_is_found() {
+        assert_eq!(small_factor(&BigUint::from(997u64 * 65537)), Some(997));
+        assert_eq!(small_factor(&BigUint::from(65537u64 * 65539)), None);
+        // A small prime is not its own factor.
+        assert_eq!(small_factor(&BigUint::from(997u32)), None);
+    }
+
+    #[test]
+    fn shared_factor_is_found() {
+        let modulus = p() * q();
+        assert!(shares_factor(&modulus, &(p() * r())));
+        assert!(!shares_factor(&modulus, &modulus));
+        assert!(!shares_factor(&modulus, &(r() * r())));
+    }
+
+    #[test]
+    fn close_factors_are_found() {
+        assert!(has_close_factors(&(p() * q()), FERMAT_ROUNDS));
+        assert!(has_close_factors(&(p() * p()), FERMAT_ROUNDS));
+        assert!(!has_close_factors(&(p() * r()), FERMAT_ROUNDS));
+    }
+
+    #[test]
+    fn close_primes_are_found() {
+        let modulus_bits = (p() * q()).bits();
+        assert!(primes_too_close(&p(), &q(), modulus_bits));
+        assert!(primes_too_close(&q(), &p(), modulus_bits));
+        assert!(!primes_too_close(&p(), &r(), modulus_bits));
+    }
+
+    #[test]
+    fn points_are_on_curve() {
+        assert_eq!(
+            is_on_curve(&generator(), EccFamily::SecpR1, 256),
+            Some(true)
+        );
+        let mut off_curve = generator();
+        off_curve[64] ^= 1;
+        assert_eq!(is_on_curve(&off_curve, EccFamily::SecpR1, 256), Some(false));
+        assert_eq!(
+            is_on_curve(&generator(), EccFamily::BrainpoolPR1, 256),
+            None
+        );
+    }
+
+    #[test]
+    fn coordinates_above_prime_are_refused() {
+        let mut point = vec![0x04];
+        point.extend(vec![0xff; 64]);
+        assert_eq!(is_on_curve(&point, EccFamily::SecpR1, 256), Some(false));
+    }
+
+    #[test]
+    fn entropy_of_symmetric_keys() {
+        assert_eq!(estimate_entropy(&[0x42; 16]), 0.0);
+        assert!(estimate_entropy(&(0..16).collect::<Vec<u8>>()) < 1.0);
+        assert!(
+            estimate_entropy(b"\x8e\x1f\xa4\x07\x5b\xd2\x39\xc6\x71\x0a\xee\x94\x2d\x63\xb8\x50")
+                > 48.0
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! XChaCha20-Poly1305 on top of ChaCha20-Poly1305
+//!
+//! XChaCha20-Poly1305 (draft-irtf-cfrg-xchacha) extends the nonce of ChaCha20-Poly1305 to 24 bytes,
+//! so that nonces can be chosen at random. It has no PSA algorithm identifier, and a nonce of 24
+//! bytes does not select it: with the `xchacha20-poly1305` feature, applications ask for it by
+//! suffixing the key name of PsaAeadEncrypt and PsaAeadDecrypt requests on the `Chacha20Poly1305`
+//! algorithm with `#xchacha20`, before the `#detached-tag` suffix if the request has both.
+//!
+//! It is ChaCha20-Poly1305 with a subkey derived from the key and the first 16 bytes of the nonce by
+//! HChaCha20, and a nonce made of four zero bytes and the last 8 bytes of the nonce. Providers whose
+//! backend only has ChaCha20-Poly1305 derive the subkey from the key material, so the key must be
+//! permitted to be exported; requests on other keys are refused with `PsaErrorNotPermitted`.
+//! HChaCha20 is the one of the `chacha20` crate.
+//!
+//! Selecting the variant by a vendor algorithm ID would need the ID in the `psa-crypto` algorithm
+//! types, which the service does not own, and in the clients: the key name suffix is used instead.
+//! Keys which are not exportable are not supported, as ChaCha20-Poly1305 is the only algorithm of
+//! the backends.
+use crate::utils::key_suffixes;
+use chacha20::cipher::consts::U10;
+use chacha20::hchacha;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Length of the ChaCha20-Poly1305 nonces
+pub const NONCE_LEN: usize = 12;
+/// Length of the XChaCha20-Poly1305 nonces
+pub const EXTENDED_NONCE_LEN: usize = 24;
+/// Length of the ChaCha20 keys
+pub const KEY_LEN: usize = 32;
+
+/// Remove the XChaCha20-Poly1305 suffix from the key name, returning whether it had it.
+pub fn strip_from(key_name: &mut String) -> bool {
+    key_suffixes::strip_flag(key_name, key_suffixes::XCHACHA20)
+}
+
+/// Write the HChaCha20 subkey of the key and the 16-byte input. The subkey is written in place,
+/// for it not to be copied out of the caller's zeroized buffer.
+pub fn hchacha20(key: &[u8; KEY_LEN], input: &[u8; 16], subkey: &mut [u8; KEY_LEN]) {
+    let mut output = hchacha::<U10>(key.into(), input.into());
+    subkey.copy_from_slice(&output);
+    output.as_mut_slice().zeroize();
+}
+
+/// Write the ChaCha20-Poly1305 subkey of an XChaCha20-Poly1305 key and nonce, returning the
+/// ChaCha20-Poly1305 nonce.
+pub fn subkey_and_nonce(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; EXTENDED_NONCE_LEN],
+    subkey: &mut Zeroizing<[u8; KEY_LEN]>,
+) -> [u8; NONCE_LEN] {
+    let mut input = [0; 16];
+    input.copy_from_slice(&nonce[..16]);
+    hchacha20(key, &input, subkey);
+    let mut chacha_nonce = [0; NONCE_LEN];
+    chacha_nonce[4..].copy_from_slice(&nonce[16..]);
+    chacha_nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+    use chacha20poly1305::ChaCha20Poly1305;
+
+    fn sequence<const N: usize>(start: u8) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = start + index as u8;
+        }
+        bytes
+    }
+
+    #[test]
+    fn hchacha20_test_vector() {
+        // Section 2.2.1 of draft-irtf-cfrg-xchacha-03
+        let input = [
+            0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x4a, 0x00, 0x00, 0x00, 0x00, 0x31, 0x41,
+            0x59, 0x27,
+        ];
+        let mut subkey = [0; KEY_LEN];
+        hchacha20(&sequence(0), &input, &mut subkey);
+        assert_eq!(
+            subkey,
+            [
+                0x82, 0x41, 0x3b, 0x42, 0x27, 0xb2, 0x7b, 0xfe, 0xd3, 0x0e, 0x42, 0x50, 0x8a, 0x87,
+                0x7d, 0x73, 0xa0, 0xf9, 0xe4, 0xd5, 0x8a, 0x74, 0xa8, 0x53, 0xc1, 0x2e, 0xc4, 0x13,
+                0x26, 0xd3, 0xec, 0xdc,
+            ]
+        );
+    }
+
+    #[test]
+    fn chacha_nonce_is_the_end_of_the_nonce() {
+        let mut nonce = [0; EXTENDED_NONCE_LEN];
+        nonce[16..].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut subkey = Zeroizing::new([0; KEY_LEN]);
+        assert_eq!(
+            subkey_and_nonce(&sequence(0), &nonce, &mut subkey),
+            [0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn xchacha20_poly1305_test_vector() {
+        // Section A.3.1 of draft-irtf-cfrg-xchacha-03, through a ChaCha20-Poly1305 implementation
+        // like the backends of the providers
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one \
+tip for the future, sunscreen would be it.";
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let mut subkey = Zeroizing::new([0; KEY_LEN]);
+        let nonce = subkey_and_nonce(&sequence(0x80), &sequence(0x40), &mut subkey);
+        let ciphertext = ChaCha20Poly1305::new(subkey.as_slice().into())
+            .encrypt(
+                nonce.as_slice().into(),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            ciphertext,
+            [
+                0xbd, 0x6d, 0x17, 0x9d, 0x3e, 0x83, 0xd4, 0x3b, 0x95, 0x76, 0x57, 0x94, 0x93, 0xc0,
+                0xe9, 0x39, 0x57, 0x2a, 0x17, 0x00, 0x25, 0x2b, 0xfa, 0xcc, 0xbe, 0xd2, 0x90, 0x2c,
+                0x21, 0x39, 0x6c, 0xbb, 0x73, 0x1c, 0x7f, 0x1b, 0x0b, 0x4a, 0xa6, 0x44, 0x0b, 0xf3,
+                0xa8, 0x2f, 0x4e, 0xda, 0x7e, 0x39, 0xae, 0x64, 0xc6, 0x70, 0x8c, 0x54, 0xc2, 0x16,
+                0xcb, 0x96, 0xb7, 0x2e, 0x12, 0x13, 0xb4, 0x52, 0x2f, 0x8c, 0x9b, 0xa4, 0x0d, 0xb5,
+                0xd9, 0x45, 0xb1, 0x1b, 0x69, 0xb9, 0x82, 0xc1, 0xbb, 0x9e, 0x3f, 0x3f, 0xac, 0x2b,
+                0xc3, 0x69, 0x48, 0x8f, 0x76, 0xb2, 0x38, 0x35, 0x65, 0xd3, 0xff, 0xf9, 0x21, 0xf9,
+                0x66, 0x4c, 0x97, 0x63, 0x7d, 0xa9, 0x76, 0x88, 0x12, 0xf6, 0x15, 0xc6, 0x8b, 0x13,
+                0xb5, 0x2e, 0xc0, 0x87, 0x59, 0x24, 0xc1, 0xc7, 0x98, 0x79, 0x47, 0xde, 0xaf, 0xd8,
+                0x78, 0x0a, 0xcf, 0x49,
+            ]
+        );
+    }
+
+    #[test]
+    fn suffix_is_stripped() {
+        let mut key_name = "key#xchacha20".to_string();
+        assert!(strip_from(&mut key_name));
+        assert_eq!(key_name, "key");
+        assert!(!strip_from(&mut key_name));
+        assert_eq!(key_name, "key");
+
+        let mut key_name = key_suffixes::XCHACHA20.to_string();
+        assert!(!strip_from(&mut key_name));
+        assert_eq!(key_name, key_suffixes::XCHACHA20);
+    }
+}
@@ -0,0 +1,531 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Administration of a running service
+//!
+//! The `parsec admin` commands connect to the socket of the service started with the same
+//! configuration file and send it the Core operations reserved to administrators, so that basic
+//! maintenance does not need a separate client. The requests are authenticated with the
+//! configured authenticator, as the user running the command (Unix peer credentials) or as the
+//! application given on the command line (direct authentication); that application must be listed
+//! in the admins of the authenticator.
+//!
+//! Reloading is done by sending `SIGHUP` to the service, whose PID is found from the credentials
+//! of the socket peer.
+//!
+//! The sensitive operations waiting for approval are read from the database of the approvals,
+//! which the command must have read access to. Approving one sends the pending request again with
+//! its approval ID, which the service takes as an approval when it comes from another
+//! administrator, answering it with `approvals::APPROVED` instead of executing it.
+//!
+//! The signature counters of the keys are read from their database as well.
+#[cfg(feature = "approvals")]
+use crate::back::approvals;
+use crate::front::domain_socket::{
+    abstract_name, connect_abstract, peer_credentials, DEFAULT_SOCKET_PATH,
+};
+#[cfg(feature = "sqlite-kim")]
+use crate::key_info_managers::signature_counters;
+use crate::utils::cli::{AdminCommand, AdminOpts};
+#[cfg(feature = "approvals")]
+use crate::utils::config::{ApprovalsConfig, SensitiveOperation};
+use crate::utils::config::{AuthenticatorConfig, ServiceConfig};
+#[cfg(feature = "approvals")]
+use crate::utils::key_suffixes;
+use crate::utils::service_builder::DEFAULT_BUFFER_SIZE_LIMIT;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "approvals")]
+use parsec_interface::operations::psa_export_key;
+use parsec_interface::operations::{
+    delete_client, list_authenticators, list_clients, list_keys, list_providers, ping,
+    psa_destroy_key, Convert, NativeOperation, NativeResult,
+};
+use parsec_interface::operations_protobuf::ProtobufConverter;
+use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+use parsec_interface::requests::{
+    AuthType, BodyType, Opcode, ProviderId, Request, Response, ResponseStatus,
+};
+use std::convert::TryFrom;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+#[cfg(feature = "sqlite-kim")]
+use std::time::UNIX_EPOCH;
+
+/// Connection to the service
+struct AdminClient {
+    socket_path: String,
+    auth_type: AuthType,
+    // Only missing if it was not given for the direct authenticator.
+    auth: Option<Vec<u8>>,
+}
+
+impl AdminClient {
+    fn new(opts: &AdminOpts, config: &ServiceConfig) -> Result<Self> {
+        let socket_path = opts
+            .socket
+            .clone()
+            .or_else(|| config.listener.socket_path.clone())
+            .unwrap_or_else(|| DEFAULT_SOCKET_PATH.to_string());
+        let (auth_type, auth) = match &config.authenticator {
+            AuthenticatorConfig::UnixPeerCredentials { .. } => {
+                // Safe as getuid can not fail.
+                let uid = unsafe { libc::getuid() };
+                (
+                    AuthType::UnixPeerCredentials,
+                    Some(uid.to_le_bytes().to_vec()),
+                )
+            }
+            AuthenticatorConfig::Direct { .. } => (
+                AuthType::Direct,
+                opts.app_name
+                    .as_ref()
+                    .map(|app_name| app_name.as_bytes().to_vec()),
+            ),
+            other => {
+                return Err(anyhow!(
+                    "The {} authenticator can not be used by parsec admin",
+                    other.auth_type()
+                ))
+            }
+        };
+
+        Ok(AdminClient {
+            socket_path,
+            auth_type,
+            auth,
+        })
+    }
+
+    fn connect(&self) -> Result<UnixStream> {
+        let path = Path::new(&self.socket_path);
+        let stream = match abstract_name(path) {
+            Some(name) => connect_abstract(name)?,
+            None => UnixStream::connect(path)?,
+        };
+        Ok(stream)
+    }
+
+    fn send(&self, provider: ProviderId, operation: NativeOperation) -> Result<NativeResult> {
+        let opcode = operation.opcode();
+        let response = self.request(provider, operation)?;
+        if response.header.status != ResponseStatus::Success {
+            return Err(anyhow!("The service failed: {}", response.header.status));
+        }
+        Ok(ProtobufConverter {}.body_to_result(response.body, opcode)?)
+    }
+
+    /// Send the operation, returning the response whatever its status.
+    fn request(&self, provider: ProviderId, operation: NativeOperation) -> Result<Response> {
+        let converter = ProtobufConverter {};
+        let opcode = operation.opcode();
+        // Operations which do not need authentication are sent without it.
+        let auth_type = match opcode {
+            Opcode::Ping | Opcode::ListProviders | Opcode::ListAuthenticators => AuthType::NoAuth,
+            _ => self.auth_type,
+        };
+        let auth = match auth_type {
+            AuthType::NoAuth => Vec::new(),
+            _ => self.auth.clone().ok_or_else(|| {
+                anyhow!("The direct authenticator needs an application name (--app-name)")
+            })?,
+        };
+        let request = Request {
+            header: RequestHeader {
+                provider,
+                session: 0,
+                content_type: BodyType::Protobuf,
+                accept_type: BodyType::Protobuf,
+                auth_type,
+                opcode,
+            },
+            body: converter.operation_to_body(operation)?,
+            auth: RequestAuth::new(auth),
+        };
+
+        let mut stream = self.connect()?;
+        request.write_to_stream(&mut stream)?;
+        Ok(Response::read_from_stream(
+            &mut stream,
+            DEFAULT_BUFFER_SIZE_LIMIT,
+        )?)
+    }
+
+    /// Send SIGHUP to the process listening on the socket.
+    fn reload(&self) -> Result<()> {
+        let stream = self.connect()?;
+        let pid = peer_credentials::peer_cred(&stream)?
+            .pid
+            .ok_or_else(|| anyhow!("The PID of the service is not known"))?;
+        // Safe as kill does not access memory.
+        if unsafe { libc::kill(pid, libc::SIGHUP) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        println!("Sent SIGHUP to the service (PID {}).", pid);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "approvals")]
+fn approvals_config(config: &ServiceConfig) -> Result<&ApprovalsConfig> {
+    config
+        .approvals
+        .as_ref()
+        .ok_or_else(|| anyhow!("The approval of sensitive operations is not configured"))
+}
+
+/// Run an administration command against the service started with the given configuration.
+pub fn run(opts: &AdminOpts, config: &ServiceConfig) -> Result<()> {
+    let client = AdminClient::new(opts, config)?;
+    match &opts.command {
+        AdminCommand::ListClients => {
+            let operation = NativeOperation::ListClients(list_clients::Operation {});
+            if let NativeResult::ListClients(result) = client.send(ProviderId::Core, operation)? {
+                for client in result.clients {
+                    println!("{}", client);
+                }
+            }
+        }
+        AdminCommand::DeleteClient { client: name } => {
+            let operation = NativeOperation::DeleteClient(delete_client::Operation {
+                client: name.clone(),
+            });
+            let _ = client.send(ProviderId::Core, operation)?;
+            println!("Deleted the keys of {}.", name);
+        }
+        AdminCommand::ListKeys => {
+            let operation = NativeOperation::ListKeys(list_keys::Operation {});
+            if let NativeResult::ListKeys(result) = client.send(ProviderId::Core, operation)? {
+                for key in result.keys {
+                    println!(
+                        "{} ({}): {:?}, {} bits",
+                        key.name, key.provider_id, key.attributes.key_type, key.attributes.bits
+                    );
+                }
+            }
+        }
+        AdminCommand::DestroyKey { key_name, provider } => {
+            let provider = ProviderId::try_from(*provider)?;
+            let operation = NativeOperation::PsaDestroyKey(psa_destroy_key::Operation {
+                key_name: key_name.clone(),
+            });
+            let _ = client.send(provider, operation)?;
+            println!("Destroyed {}.", key_name);
+        }
+        #[cfg(feature = "approvals")]
+        AdminCommand::PendingApprovals => {
+            for pending in approvals::pending(approvals_config(config)?)? {
+                println!(
+                    "{}: {:?} of \"{}\" in provider {}, requested by {} ({})",
+                    pending.id,
+                    pending.operation,
+                    pending.target,
+                    pending.provider_id,
+                    pending.requester.name(),
+                    pending.requester.authenticator_id()
+                );
+            }
+        }
+        #[cfg(feature = "approvals")]
+        AdminCommand::Approve { id } => {
+            let pending = approvals::pending(approvals_config(config)?)?
+                .into_iter()
+                .find(|pending| pending.id == *id)
+                .ok_or_else(|| anyhow!("No operation is waiting for approval {}", id))?;
+            let name = format!("{}{}{}", pending.target, key_suffixes::APPROVAL, id);
+            let operation = match pending.operation {
+                SensitiveOperation::ExportKey => {
+                    NativeOperation::PsaExportKey(psa_export_key::Operation { key_name: name })
+                }
+                SensitiveOperation::ForceDestroy => {
+                    NativeOperation::DeleteClient(delete_client::Operation { client: name })
+                }
+            };
+            match client
+                .request(pending.provider_id, operation)?
+                .header
+                .status
+            {
+                approvals::APPROVED => println!("Approved {}.", id),
+                status => return Err(anyhow!("The service did not approve {}: {}", id, status)),
+            }
+        }
+        #[cfg(not(feature = "approvals"))]
+        AdminCommand::PendingApprovals | AdminCommand::Approve { .. } => {
+            return Err(anyhow!(
+                "The approvals need the \"approvals\" feature, not compiled in the Parsec binary"
+            ));
+        }
+        #[cfg(feature = "sqlite-kim")]
+        AdminCommand::SignatureCounters => {
+            let signature_counters_config = config
+                .signature_counters
+                .as_ref()
+                .ok_or_else(|| anyhow!("The signature counters are not configured"))?;
+            for key in signature_counters::read(signature_counters_config)? {
+                let limit = key.limit.map_or_else(
+                    || String::from("no limit"),
+                    |limit| format!("limit {}", limit),
+                );
+                let last_signature = key
+                    .last_signature
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map_or_else(
+                        || String::from("never"),
+                        |time| format!("{} seconds after the Unix epoch", time.as_secs()),
+                    );
+                println!(
+                    "\"{}\" of {} ({}) in {}: {} signatures ({}), the last one {}",
+                    key.key_name,
+                    key.owner.name(),
+                    key.owner.authenticator_id(),
+                    key.provider_name,
+                    key.count,
+                    limit,
+                    last_signature
+                );
+            }
+        }
+        #[cfg(not(feature = "sqlite-kim"))]
+        AdminCommand::SignatureCounters => {
+            return Err(anyhow!(
+                "The signature counters need the \"sqlite-kim\" feature, not compiled in the Parsec binary"
+            ));
+        }
+        AdminCommand::Status => {
+            if let NativeResult::Ping(result) =
+                client.send(ProviderId::Core, NativeOperation::Ping(ping::Operation {}))?
+            {
+                println!(
+                    "Wire protocol version: {}.{}",
+                    result.wire_protocol_version_maj, result.wire_protocol_version_min
+                );
+            }
+            let operation = NativeOperation::ListProviders(list_providers::Operation {});
+            if let NativeResult::ListProviders(result) = client.send(ProviderId::Core, operation)? {
+                println!("Providers:");
+                for provider in result.providers {
+                    println!(
+                        "  {} ({}), version {}.{}.{}",
+                        provider.id,
+                        provider.description,
+                        provider.version_maj,
+                        provider.version_min,
+                        provider.version_rev
+                    );
+                }
+            }
+            let operation = NativeOperation::ListAuthenticators(list_authenticators::Operation {});
+            if let NativeResult::ListAuthenticators(result) =
+                client.send(ProviderId::Core, operation)?
+            {
+                println!("Authenticators:");
+                for authenticator in result.authenticators {
+                    println!("  {} ({})", authenticator.id, authenticator.description);
+                }
+            }
+        }
+        AdminCommand::Reload => client.reload()?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, AdminClient};
+    use crate::front::domain_socket::DEFAULT_SOCKET_PATH;
+    use crate::utils::cli::{AdminCommand, AdminOpts};
+    use crate::utils::config::ServiceConfig;
+    use parsec_interface::operations::{
+        list_clients, ping, Convert, NativeOperation, NativeResult,
+    };
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::{AuthType, ProviderId, Request, Response, ResponseStatus};
+    use parsec_interface::secrecy::ExposeSecret;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::thread::{self, JoinHandle};
+
+    fn config(authenticator: &str, socket_path: Option<&Path>) -> ServiceConfig {
+        let socket_path = socket_path
+            .map(|path| format!("socket_path = {:?}\n", path.to_str().unwrap()))
+            .unwrap_or_default();
+        toml::from_str(&format!(
+            "[core_settings]\n\
+             [listener]\nlistener_type = \"DomainSocket\"\ntimeout = 200\n{}\
+             [authenticator]\nauth_type = \"{}\"\n",
+            socket_path, authenticator
+        ))
+        .unwrap()
+    }
+
+    fn opts(app_name: Option<&str>, command: AdminCommand) -> AdminOpts {
+        AdminOpts {
+            socket: None,
+            app_name: app_name.map(str::to_string),
+            command,
+        }
+    }
+
+    /// Answer one request with the given status and result, returning the request received.
+    fn serve(
+        listener: UnixListener,
+        result: Result<NativeResult, ResponseStatus>,
+    ) -> JoinHandle<Request> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = Request::read_from_stream(&mut stream, 1 << 16).unwrap();
+            let response = match result {
+                Ok(result) => {
+                    let mut response =
+                        Response::from_request_header(request.header, ResponseStatus::Success);
+                    response.body = ProtobufConverter {}.result_to_body(result).unwrap();
+                    response
+                }
+                Err(status) => Response::from_request_header(request.header, status),
+            };
+            response.write_to_stream(&mut stream).unwrap();
+            request
+        })
+    }
+
+    #[test]
+    fn socket_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let configured = dir.path().join("parsec.sock");
+        let config_with_socket = config("Direct", Some(&configured));
+        let mut opts = opts(None, AdminCommand::ListClients);
+        assert_eq!(
+            AdminClient::new(&opts, &config_with_socket)
+                .unwrap()
+                .socket_path,
+            configured.to_str().unwrap()
+        );
+        assert_eq!(
+            AdminClient::new(&opts, &config("Direct", None))
+                .unwrap()
+                .socket_path,
+            DEFAULT_SOCKET_PATH
+        );
+        opts.socket = Some("/tmp/other.sock".to_string());
+        assert_eq!(
+            AdminClient::new(&opts, &config_with_socket)
+                .unwrap()
+                .socket_path,
+            "/tmp/other.sock"
+        );
+    }
+
+    #[test]
+    fn authentication() {
+        let opts = opts(Some("admin"), AdminCommand::ListClients);
+        let client = AdminClient::new(&opts, &config("UnixPeerCredentials", None)).unwrap();
+        assert_eq!(client.auth_type, AuthType::UnixPeerCredentials);
+        // Safe as getuid can not fail.
+        let uid = unsafe { libc::getuid() };
+        assert_eq!(client.auth, Some(uid.to_le_bytes().to_vec()));
+
+        let client = AdminClient::new(&opts, &config("Direct", None)).unwrap();
+        assert_eq!(client.auth_type, AuthType::Direct);
+        assert_eq!(client.auth, Some(b"admin".to_vec()));
+    }
+
+    #[test]
+    fn unsupported_authenticator() {
+        let config: ServiceConfig = toml::from_str(
+            "[core_settings]\n\
+             [listener]\nlistener_type = \"DomainSocket\"\ntimeout = 200\n\
+             [authenticator]\nauth_type = \"JwtSvid\"\nworkload_endpoint = \"unix:/tmp/agent\"\n",
+        )
+        .unwrap();
+        assert!(AdminClient::new(&opts(None, AdminCommand::Status), &config).is_err());
+    }
+
+    #[test]
+    fn authenticated_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("parsec.sock");
+        let server = serve(
+            UnixListener::bind(&socket_path).unwrap(),
+            Ok(NativeResult::ListClients(list_clients::Result {
+                clients: vec!["app".to_string()],
+            })),
+        );
+        let client = AdminClient::new(
+            &opts(Some("admin"), AdminCommand::ListClients),
+            &config("Direct", Some(&socket_path)),
+        )
+        .unwrap();
+        let operation = NativeOperation::ListClients(list_clients::Operation {});
+        match client.send(ProviderId::Core, operation).unwrap() {
+            NativeResult::ListClients(result) => assert_eq!(result.clients, ["app"]),
+            _ => panic!("Unexpected result"),
+        }
+        let request = server.join().unwrap();
+        assert_eq!(request.header.auth_type, AuthType::Direct);
+        assert_eq!(request.auth.buffer.expose_secret(), b"admin");
+    }
+
+    #[test]
+    fn unauthenticated_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("parsec.sock");
+        let server = serve(
+            UnixListener::bind(&socket_path).unwrap(),
+            Ok(NativeResult::Ping(ping::Result {
+                wire_protocol_version_maj: 1,
+                wire_protocol_version_min: 0,
+            })),
+        );
+        // No application name is needed for the operations without authentication.
+        let client = AdminClient::new(
+            &opts(None, AdminCommand::Status),
+            &config("Direct", Some(&socket_path)),
+        )
+        .unwrap();
+        let operation = NativeOperation::Ping(ping::Operation {});
+        assert!(client.send(ProviderId::Core, operation).is_ok());
+        let request = server.join().unwrap();
+        assert_eq!(request.header.auth_type, AuthType::NoAuth);
+        assert!(request.auth.buffer.expose_secret().is_empty());
+    }
+
+    #[test]
+    fn direct_authentication_needs_app_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("parsec.sock");
+        let client = AdminClient::new(
+            &opts(None, AdminCommand::ListClients),
+            &config("Direct", Some(&socket_path)),
+        )
+        .unwrap();
+        let operation = NativeOperation::ListClients(list_clients::Operation {});
+        // Refused before connecting: nothing listens on the socket.
+        assert!(client
+            .send(ProviderId::Core, operation)
+            .unwrap_err()
+            .to_string()
+            .contains("--app-name"));
+    }
+
+    #[test]
+    fn service_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("parsec.sock");
+        let server = serve(
+            UnixListener::bind(&socket_path).unwrap(),
+            Err(ResponseStatus::AdminOperation),
+        );
+        let config = config("Direct", Some(&socket_path));
+        let error = run(&opts(Some("app"), AdminCommand::ListClients), &config).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains(&ResponseStatus::AdminOperation.to_string()));
+        let _ = server.join().unwrap();
+    }
+
+    #[test]
+    fn approvals_need_configuration() {
+        let config = config("Direct", None);
+        assert!(run(&opts(None, AdminCommand::PendingApprovals), &config).is_err());
+    }
+}
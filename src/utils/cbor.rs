@@ -0,0 +1,456 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Minimal CBOR codec
+//!
+//! Values of the subset of CBOR (RFC 8949) needed by the COSE structures and the CBOR bodies:
+//! integers, byte and text strings, arrays, maps, booleans and null, and tags when encoding. The
+//! data items are written and read by `ciborium-ll`, which encodes them in the preferred
+//! serialization, with the shortest heads. Decoding is strict: indefinite lengths, tags, floating
+//! point numbers and trailing data are rejected, and nesting is limited.
+use ciborium_io::{Read, Write};
+use ciborium_ll::{simple, Decoder, Encoder, Header};
+use log::error;
+use parsec_interface::requests::{ResponseStatus, Result};
+use std::convert::TryFrom;
+use zeroize::Zeroizing;
+
+/// Deepest nesting of arrays and maps accepted when decoding
+const MAX_DEPTH: usize = 8;
+
+/// CBOR data item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// Unsigned or negative integer
+    Integer(i64),
+    /// Byte string, zeroized when dropped as it can hold key material
+    Bytes(Zeroizing<Vec<u8>>),
+    /// Text string
+    Text(String),
+    /// Array
+    Array(Vec<Value>),
+    /// Map, with its entries in the encoding order
+    Map(Vec<(Value, Value)>),
+    /// Boolean
+    Bool(bool),
+    /// Null
+    Null,
+}
+
+impl Value {
+    /// Byte string of the given bytes
+    pub fn bytes(bytes: &[u8]) -> Self {
+        Value::Bytes(Zeroizing::new(bytes.to_vec()))
+    }
+
+    /// Map of integer labels, as used by COSE
+    pub fn labelled(entries: Vec<(i64, Value)>) -> Self {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(label, value)| (Value::Integer(label), value))
+                .collect(),
+        )
+    }
+
+    /// Value of the given integer label, if this is a map which has it
+    pub fn get(&self, label: i64) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(key, _)| *key == Value::Integer(label))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// Encode the value
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut output = Vec::new();
+    encode_into(value, &mut Encoder::from(&mut output)).expect("Writing to a vector failed");
+    output
+}
+
+/// Encode the value with the given tag
+pub fn encode_tagged(tag: u64, value: &Value) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut encoder = Encoder::from(&mut output);
+    encoder
+        .push(Header::Tag(tag))
+        .and_then(|_| encode_into(value, &mut encoder))
+        .expect("Writing to a vector failed");
+    output
+}
+
+fn encode_into<W: Write>(
+    value: &Value,
+    encoder: &mut Encoder<W>,
+) -> std::result::Result<(), W::Error> {
+    match value {
+        Value::Integer(integer) if *integer >= 0 => encoder.push(Header::Positive(*integer as u64)),
+        // -1 - n, computed without overflow
+        Value::Integer(integer) => encoder.push(Header::Negative(!(*integer) as u64)),
+        Value::Bytes(bytes) => encoder.bytes(bytes, None),
+        Value::Text(text) => encoder.text(text, None),
+        Value::Array(items) => {
+            encoder.push(Header::Array(Some(items.len())))?;
+            for item in items {
+                encode_into(item, encoder)?;
+            }
+            Ok(())
+        }
+        Value::Map(entries) => {
+            encoder.push(Header::Map(Some(entries.len())))?;
+            for (key, value) in entries {
+                encode_into(key, encoder)?;
+                encode_into(value, encoder)?;
+            }
+            Ok(())
+        }
+        Value::Bool(false) => encoder.push(Header::Simple(simple::FALSE)),
+        Value::Bool(true) => encoder.push(Header::Simple(simple::TRUE)),
+        Value::Null => encoder.push(Header::Simple(simple::NULL)),
+    }
+}
+
+/// Decode the single value the data holds
+pub fn decode(data: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder::from(data);
+    let value = value(&mut decoder, data.len(), 0)?;
+    if decoder.offset() != data.len() {
+        error!("Unexpected trailing data after the CBOR value.");
+        return Err(ResponseStatus::DeserializingBodyFailed);
+    }
+    Ok(value)
+}
+
+/// Check that the data left after the decoder can hold the given number of items, of at least one
+/// byte each, before allocating for them.
+fn count(decoder: &mut Decoder<&[u8]>, length: usize, items: usize) -> Result<usize> {
+    if items > length - decoder.offset() {
+        error!("Unexpected end of the CBOR data.");
+        return Err(ResponseStatus::DeserializingBodyFailed);
+    }
+    Ok(items)
+}
+
+/// Decode the next value of the data of the given length.
+fn value(decoder: &mut Decoder<&[u8]>, length: usize, depth: usize) -> Result<Value> {
+    let header = decoder.pull().map_err(|_| {
+        error!("Unexpected end of the CBOR data, or reserved CBOR encoding.");
+        ResponseStatus::DeserializingBodyFailed
+    })?;
+    if matches!(header, Header::Array(_) | Header::Map(_)) && depth == MAX_DEPTH {
+        error!("CBOR data nested too deeply.");
+        return Err(ResponseStatus::DeserializingBodyFailed);
+    }
+    let integer = |argument| {
+        i64::try_from(argument).map_err(|_| {
+            error!("CBOR integer out of range.");
+            ResponseStatus::DeserializingBodyFailed
+        })
+    };
+    Ok(match header {
+        Header::Positive(argument) => Value::Integer(integer(argument)?),
+        Header::Negative(argument) => Value::Integer(-1 - integer(argument)?),
+        Header::Bytes(Some(bytes)) => {
+            let mut data = Zeroizing::new(vec![0; count(decoder, length, bytes)?]);
+            read(decoder, &mut data)?;
+            Value::Bytes(data)
+        }
+        Header::Text(Some(bytes)) => {
+            let mut data = vec![0; count(decoder, length, bytes)?];
+            read(decoder, &mut data)?;
+            Value::Text(String::from_utf8(data).map_err(|_| {
+                error!("CBOR text string which is not UTF-8.");
+                ResponseStatus::DeserializingBodyFailed
+            })?)
+        }
+        Header::Array(Some(items)) => {
+            let mut values = Vec::with_capacity(count(decoder, length, items)?);
+            for _ in 0..items {
+                values.push(value(decoder, length, depth + 1)?);
+            }
+            Value::Array(values)
+        }
+        Header::Map(Some(entries)) => {
+            let mut values =
+                Vec::with_capacity(count(decoder, length, entries.saturating_mul(2))? / 2);
+            for _ in 0..entries {
+                values.push((
+                    value(decoder, length, depth + 1)?,
+                    value(decoder, length, depth + 1)?,
+                ));
+            }
+            Value::Map(values)
+        }
+        Header::Simple(simple::FALSE) => Value::Bool(false),
+        Header::Simple(simple::TRUE) => Value::Bool(true),
+        Header::Simple(simple::NULL) => Value::Null,
+        Header::Bytes(None) | Header::Text(None) | Header::Array(None) | Header::Map(None) => {
+            error!("Indefinite lengths are not supported.");
+            return Err(ResponseStatus::DeserializingBodyFailed);
+        }
+        Header::Tag(_) | Header::Float(_) | Header::Simple(_) | Header::Break => {
+            error!("CBOR tags, floating point numbers and simple values are not supported.");
+            return Err(ResponseStatus::DeserializingBodyFailed);
+        }
+    })
+}
+
+fn read(decoder: &mut Decoder<&[u8]>, data: &mut [u8]) -> Result<()> {
+    decoder.read_exact(data).map_err(|_| {
+        error!("Unexpected end of the CBOR data.");
+        ResponseStatus::DeserializingBodyFailed
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(text: &str) -> Value {
+        Value::Text(String::from(text))
+    }
+
+    fn integers(integers: std::ops::RangeInclusive<i64>) -> Value {
+        Value::Array(integers.map(Value::Integer).collect())
+    }
+
+    fn refused(data: &[u8]) -> bool {
+        decode(data) == Err(ResponseStatus::DeserializingBodyFailed)
+    }
+
+    /// Examples of RFC 8949, appendix A, of the supported types
+    fn examples() -> Vec<(Value, &'static [u8])> {
+        vec![
+            (Value::Integer(0), &[0x00]),
+            (Value::Integer(1), &[0x01]),
+            (Value::Integer(10), &[0x0a]),
+            (Value::Integer(23), &[0x17]),
+            (Value::Integer(24), &[0x18, 0x18]),
+            (Value::Integer(25), &[0x18, 0x19]),
+            (Value::Integer(100), &[0x18, 0x64]),
+            (Value::Integer(1000), &[0x19, 0x03, 0xe8]),
+            (Value::Integer(1_000_000), &[0x1a, 0x00, 0x0f, 0x42, 0x40]),
+            (
+                Value::Integer(1_000_000_000_000),
+                &[0x1b, 0x00, 0x00, 0x00, 0xe8, 0xd4, 0xa5, 0x10, 0x00],
+            ),
+            (Value::Integer(-1), &[0x20]),
+            (Value::Integer(-10), &[0x29]),
+            (Value::Integer(-100), &[0x38, 0x63]),
+            (Value::Integer(-1000), &[0x39, 0x03, 0xe7]),
+            (Value::Bool(false), &[0xf4]),
+            (Value::Bool(true), &[0xf5]),
+            (Value::Null, &[0xf6]),
+            (Value::bytes(&[]), &[0x40]),
+            (Value::bytes(&[1, 2, 3, 4]), &[0x44, 0x01, 0x02, 0x03, 0x04]),
+            (text(""), &[0x60]),
+            (text("a"), &[0x61, 0x61]),
+            (text("IETF"), &[0x64, 0x49, 0x45, 0x54, 0x46]),
+            (text("\"\\"), &[0x62, 0x22, 0x5c]),
+            (text("\u{fc}"), &[0x62, 0xc3, 0xbc]),
+            (text("\u{6c34}"), &[0x63, 0xe6, 0xb0, 0xb4]),
+            (Value::Array(Vec::new()), &[0x80]),
+            (integers(1..=3), &[0x83, 0x01, 0x02, 0x03]),
+            (
+                Value::Array(vec![Value::Integer(1), integers(2..=3), integers(4..=5)]),
+                &[0x83, 0x01, 0x82, 0x02, 0x03, 0x82, 0x04, 0x05],
+            ),
+            (
+                integers(1..=25),
+                &[
+                    0x98, 0x19, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b,
+                    0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18,
+                    0x18, 0x18, 0x19,
+                ],
+            ),
+            (Value::Map(Vec::new()), &[0xa0]),
+            (
+                Value::labelled(vec![(1, Value::Integer(2)), (3, Value::Integer(4))]),
+                &[0xa2, 0x01, 0x02, 0x03, 0x04],
+            ),
+            (
+                Value::Map(vec![
+                    (text("a"), Value::Integer(1)),
+                    (text("b"), integers(2..=3)),
+                ]),
+                &[0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03],
+            ),
+            (
+                Value::Array(vec![text("a"), Value::Map(vec![(text("b"), text("c"))])]),
+                &[0x82, 0x61, 0x61, 0xa1, 0x61, 0x62, 0x61, 0x63],
+            ),
+        ]
+    }
+
+    #[test]
+    fn encode_examples() {
+        for (value, encoded) in examples() {
+            assert_eq!(encode(&value), encoded, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn decode_examples() {
+        for (value, encoded) in examples() {
+            assert_eq!(decode(encoded).unwrap(), value, "{:?}", encoded);
+        }
+    }
+
+    #[test]
+    fn integer_limits() {
+        let limits: [(i64, &[u8]); 2] = [
+            (
+                i64::MAX,
+                &[0x1b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+            (
+                i64::MIN,
+                &[0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+        ];
+        for (integer, encoded) in limits {
+            assert_eq!(encode(&Value::Integer(integer)), encoded);
+            assert_eq!(decode(encoded).unwrap(), Value::Integer(integer));
+        }
+        // 2^64 - 1 and -2^64, from RFC 8949, and 2^63 and -2^63 - 1 do not fit.
+        assert!(refused(&[
+            0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
+        ]));
+        assert!(refused(&[
+            0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
+        ]));
+        assert!(refused(&[
+            0x1b, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+        ]));
+        assert!(refused(&[
+            0x3b, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+        ]));
+    }
+
+    #[test]
+    fn encode_tagged_examples() {
+        // 1(1363896240) and 24(h'6449455446') of RFC 8949
+        assert_eq!(
+            encode_tagged(1, &Value::Integer(1_363_896_240)),
+            [0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]
+        );
+        assert_eq!(
+            encode_tagged(24, &Value::bytes(&[0x64, 0x49, 0x45, 0x54, 0x46])),
+            [0xd8, 0x18, 0x45, 0x64, 0x49, 0x45, 0x54, 0x46]
+        );
+        // COSE_Sign1
+        assert_eq!(encode_tagged(18, &Value::Array(Vec::new())), [0xd2, 0x80]);
+    }
+
+    #[test]
+    fn get_finds_integer_labels() {
+        let map = Value::Map(vec![
+            (Value::Integer(1), text("one")),
+            (text("2"), text("two")),
+            (Value::Integer(-1), text("minus one")),
+        ]);
+        assert_eq!(map.get(1), Some(&text("one")));
+        assert_eq!(map.get(-1), Some(&text("minus one")));
+        assert_eq!(map.get(2), None);
+        assert_eq!(integers(1..=3).get(1), None);
+        assert_eq!(Value::Integer(1).get(1), None);
+    }
+
+    #[test]
+    fn labelled_keeps_the_order() {
+        assert_eq!(
+            encode(&Value::labelled(vec![
+                (3, Value::Null),
+                (-1, Value::Null),
+                (1, Value::Null)
+            ])),
+            [0xa3, 0x03, 0xf6, 0x20, 0xf6, 0x01, 0xf6]
+        );
+    }
+
+    #[test]
+    fn refuse_trailing_data() {
+        assert!(refused(&[0x01, 0x01]));
+        assert!(refused(&[0x80, 0x00]));
+    }
+
+    #[test]
+    fn refuse_truncated_data() {
+        for (value, encoded) in examples() {
+            for length in 0..encoded.len() {
+                assert!(refused(&encoded[..length]), "{:?} at {}", value, length);
+            }
+        }
+    }
+
+    #[test]
+    fn refuse_indefinite_lengths() {
+        // (_ h'0102', h'030405'), (_ "strea", "ming"), [_ ], [_ 1, 2] and {_ "a": 1}
+        assert!(refused(&[
+            0x5f, 0x42, 0x01, 0x02, 0x43, 0x03, 0x04, 0x05, 0xff
+        ]));
+        assert!(refused(&[
+            0x7f, 0x65, 0x73, 0x74, 0x72, 0x65, 0x61, 0x64, 0x6d, 0x69, 0x6e, 0x67, 0xff
+        ]));
+        assert!(refused(&[0x9f, 0xff]));
+        assert!(refused(&[0x9f, 0x01, 0x02, 0xff]));
+        assert!(refused(&[0xbf, 0x61, 0x61, 0x01, 0xff]));
+        // Reserved additional information
+        for reserved in [0x1c, 0x1d, 0x1e] {
+            assert!(refused(&[reserved]));
+        }
+    }
+
+    #[test]
+    fn refuse_unsupported_types() {
+        // 1(1363896240), 0.0 as a half, single and double float, undefined and simple(16)
+        assert!(refused(&[0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]));
+        assert!(refused(&[0xf9, 0x00, 0x00]));
+        assert!(refused(&[0xfa, 0x00, 0x00, 0x00, 0x00]));
+        assert!(refused(&[
+            0xfb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+        ]));
+        assert!(refused(&[0xf7]));
+        assert!(refused(&[0xf0]));
+    }
+
+    #[test]
+    fn refuse_invalid_text() {
+        assert!(refused(&[0x62, 0xc3, 0x28]));
+        assert!(refused(&[0x61, 0xff]));
+    }
+
+    #[test]
+    fn refuse_lengths_beyond_the_data() {
+        // Byte string, array and maps of more items than there are bytes left
+        assert!(refused(&[0x5a, 0xff, 0xff, 0xff, 0xff, 0x00]));
+        assert!(refused(&[0x9a, 0xff, 0xff, 0xff, 0xff, 0x00]));
+        assert!(refused(&[
+            0xbb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
+        ]));
+        // A map of one entry needs two items.
+        assert!(refused(&[0xa1, 0x01]));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| {
+            let mut data = vec![0x81; depth];
+            data.push(0x00);
+            data
+        };
+        assert!(decode(&nested(MAX_DEPTH)).is_ok());
+        assert!(refused(&nested(MAX_DEPTH + 1)));
+
+        let maps = |depth: usize| {
+            let mut data = [0xa1, 0x00].repeat(depth);
+            data.push(0x00);
+            data
+        };
+        assert!(decode(&maps(MAX_DEPTH)).is_ok());
+        assert!(refused(&maps(MAX_DEPTH + 1)));
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Containers of the processes, from their cgroups
+//!
+//! The container runtimes (Docker, containerd, CRI-O, Podman) put the ID of a container in the
+//! cgroup path of its processes. Only the cgroups created by the runtimes are trusted: the first
+//! container cgroup below the root of a runtime (`docker`, `kubepods`, or the `system.slice`,
+//! `kubepods.slice` and `machine.slice` systemd slices), reached through systemd slices only.
+//! Cgroups which processes can create themselves, in a subtree delegated to a user or a service,
+//! or nested in a container, are not taken as containers.
+use std::fs;
+use std::io::Result;
+
+/// Length of the hexadecimal container IDs of the runtimes
+const CONTAINER_ID_LENGTH: usize = 64;
+
+/// Roots of the cgroup hierarchy under which the runtimes create the cgroups of the containers
+const RUNTIME_ROOTS: [&str; 5] = [
+    "docker",
+    "kubepods",
+    "system.slice",
+    "kubepods.slice",
+    "machine.slice",
+];
+
+/// Prefixes of the scopes the runtimes create for the containers with the systemd cgroup driver
+const RUNTIME_SCOPE_PREFIXES: [&str; 4] = ["docker-", "cri-containerd-", "crio-", "libpod-"];
+
+/// ID of the container of the process, `None` if it is not in a container
+pub fn container_id(pid: i32) -> Result<Option<String>> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+    Ok(cgroup_container_id(&cgroup))
+}
+
+/// Container ID in the cgroup paths of a process, as listed in `/proc/<pid>/cgroup`
+fn cgroup_container_id(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .find_map(path_container_id)
+}
+
+/// Container ID of a cgroup path, the first container cgroup below the root of a runtime
+///
+/// The runtimes name the cgroup of a container after its ID, alone with the cgroupfs driver
+/// (`/docker/<id>`, `/kubepods/besteffort/pod<uid>/<id>`) or as a scope with the systemd driver
+/// (`/system.slice/docker-<id>.scope`, `/kubepods.slice/.../cri-containerd-<id>.scope`,
+/// `/machine.slice/libpod-<id>.scope`). The cgroups on the way can only be systemd slices, or the
+/// QoS and pod cgroups of Kubernetes.
+fn path_container_id(path: &str) -> Option<String> {
+    let mut components = path.split('/').filter(|component| !component.is_empty());
+    let root = components.next()?;
+    if !RUNTIME_ROOTS.contains(&root) {
+        return None;
+    }
+    let cgroupfs = root == "docker" || root == "kubepods";
+    for component in components {
+        let id = match component.strip_suffix(".scope") {
+            Some(scope) => RUNTIME_SCOPE_PREFIXES
+                .iter()
+                .find_map(|prefix| scope.strip_prefix(prefix)),
+            None if cgroupfs => Some(component),
+            None => None,
+        };
+        if let Some(id) = id.filter(|id| is_container_id(id)) {
+            return Some(String::from(id));
+        }
+        let kubernetes = root == "kubepods"
+            && (component == "besteffort"
+                || component == "burstable"
+                || component.starts_with("pod"));
+        if !component.ends_with(".slice") && !kubernetes {
+            return None;
+        }
+    }
+    None
+}
+
+fn is_container_id(id: &str) -> bool {
+    id.len() == CONTAINER_ID_LENGTH && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ID: &str = "3f4e0a8b5c7d9e1f2a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d6e7f8091";
+    const OTHER_ID: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn container_ids_of_the_runtimes() {
+        let cgroups = [
+            format!("0::/system.slice/docker-{}.scope", ID),
+            format!("12:memory:/docker/{}\n11:cpu:/docker/{}", ID, ID),
+            format!(
+                "0::/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod1.slice/cri-containerd-{}.scope",
+                ID
+            ),
+            format!("0::/kubepods.slice/kubepods-pod1.slice/crio-{}.scope", ID),
+            format!("0::/kubepods/burstable/pod5e3c7a1b-0f4d/{}", ID),
+            format!("0::/machine.slice/libpod-{}.scope/container", ID),
+        ];
+        for cgroup in cgroups.iter() {
+            assert_eq!(
+                cgroup_container_id(cgroup).as_deref(),
+                Some(ID),
+                "{}",
+                cgroup
+            );
+        }
+    }
+
+    #[test]
+    fn processes_outside_of_containers_have_no_id() {
+        assert_eq!(cgroup_container_id("0::/user.slice/user-1000.slice"), None);
+        assert_eq!(cgroup_container_id("0::/system.slice/sshd.service"), None);
+        assert_eq!(cgroup_container_id("0::/"), None);
+    }
+
+    #[test]
+    fn cgroups_created_outside_of_the_runtimes_are_not_trusted() {
+        let cgroups = [
+            format!("0::/{}", ID),
+            format!(
+                "0::/user.slice/user-1000.slice/user@1000.service/docker-{}.scope",
+                ID
+            ),
+            format!("0::/system.slice/build.service/docker-{}.scope", ID),
+            format!("0::/system.slice/{}", ID),
+            format!("0::/kubepods.slice/{}", ID),
+            format!("0::/machine.slice/libpod-{}.scope.bak", ID),
+            format!("0::/docker/{}0", ID),
+        ];
+        for cgroup in cgroups.iter() {
+            assert_eq!(cgroup_container_id(cgroup), None, "{}", cgroup);
+        }
+    }
+
+    #[test]
+    fn cgroups_nested_in_a_container_keep_its_id() {
+        let cgroup = format!(
+            "0::/system.slice/docker-{}.scope/docker-{}.scope",
+            ID, OTHER_ID
+        );
+        assert_eq!(cgroup_container_id(&cgroup).as_deref(), Some(ID));
+    }
+
+    #[test]
+    fn container_of_a_process() {
+        assert!(container_id(std::process::id() as i32).is_ok());
+        assert!(container_id(-1).is_err());
+    }
+}
@@ -22,4 +22,88 @@ pub struct Opts {
     /// Sets the configuration file path
     #[structopt(short, long, default_value = "config.toml")]
     pub config: String,
+    /// Checks the configuration and the availability of the provider backends, prints a report
+    /// and exits, without starting the service
+    #[structopt(long)]
+    pub check_config: bool,
+    /// Serves a single request read from the standard input, writing the response to the standard
+    /// output, instead of listening on a socket (inetd-style activation)
+    #[structopt(long)]
+    pub stdio: bool,
+    /// Command to run instead of starting the service
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Commands of the Parsec binary
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Administers the service running with the configuration file, through its socket
+    Admin(AdminOpts),
+    /// Copies the key mappings of the providers from one Key Info Manager of the configuration to
+    /// another, verifying the copies. The service must be stopped.
+    MigrateKeyInfo(MigrateKeyInfoOpts),
+}
+
+/// Options of the Key Info Manager migration
+#[derive(StructOpt, Debug)]
+pub struct MigrateKeyInfoOpts {
+    /// Name of the Key Info Manager to copy the mappings from
+    #[structopt(long)]
+    pub from: String,
+    /// Name of the Key Info Manager to copy the mappings to
+    #[structopt(long)]
+    pub to: String,
+    /// Removes the mappings from the source, once all the copies are verified
+    #[structopt(long)]
+    pub remove_source: bool,
+}
+
+/// Options of the administration commands
+#[derive(StructOpt, Debug)]
+pub struct AdminOpts {
+    /// Socket to connect to, instead of the main socket of the configuration
+    #[structopt(long)]
+    pub socket: Option<String>,
+    /// Application name to authenticate as, with the direct authenticator
+    #[structopt(long)]
+    pub app_name: Option<String>,
+    /// Administration command
+    #[structopt(subcommand)]
+    pub command: AdminCommand,
+}
+
+/// Administration commands
+#[derive(StructOpt, Debug)]
+pub enum AdminCommand {
+    /// Lists the applications having keys in the service
+    ListClients,
+    /// Deletes all the keys of an application
+    DeleteClient {
+        /// Name of the application
+        client: String,
+    },
+    /// Lists the keys of the administrator application
+    ListKeys,
+    /// Destroys a key of the administrator application
+    DestroyKey {
+        /// Name of the key
+        key_name: String,
+        /// ID of the provider storing the key
+        #[structopt(long)]
+        provider: u8,
+    },
+    /// Lists the sensitive operations waiting for the approval of a second administrator
+    PendingApprovals,
+    /// Approves a sensitive operation requested by another administrator
+    Approve {
+        /// ID of the approval
+        id: u64,
+    },
+    /// Lists the signatures made with each key, with the time of the last one and the limit
+    SignatureCounters,
+    /// Prints the state of the service: wire protocol version, providers and authenticators
+    Status,
+    /// Makes the service reload its configuration file
+    Reload,
 }
@@ -4,32 +4,52 @@
 
 #[cfg(feature = "cryptoauthlib-provider")]
 use crate::providers::cryptoauthlib::Provider as CryptoAuthLibProvider;
+#[cfg(feature = "external-provider")]
+use crate::providers::external::Provider as ExternalProvider;
+#[cfg(feature = "forwarding-provider")]
+use crate::providers::forwarding::Provider as ForwardingProvider;
 #[cfg(feature = "mbed-crypto-provider")]
 use crate::providers::mbed_crypto::Provider as MbedCryptoProvider;
+#[cfg(feature = "piv-provider")]
+use crate::providers::piv::Provider as PivProvider;
 #[cfg(feature = "pkcs11-provider")]
 use crate::providers::pkcs11::Provider as Pkcs11Provider;
+#[cfg(feature = "test-provider")]
+use crate::providers::test_provider::Provider as TestProvider;
 #[cfg(feature = "tpm-provider")]
 use crate::providers::tpm::Provider as TpmProvider;
 #[cfg(feature = "trusted-service-provider")]
 use crate::providers::trusted_service::Provider as TrustedServiceProvider;
+use crate::providers::ProviderIdentity;
 #[cfg(not(all(
     feature = "mbed-crypto-provider",
     feature = "pkcs11-provider",
     feature = "tpm-provider",
     feature = "cryptoauthlib-provider",
-    feature = "trusted-service-provider"
+    feature = "piv-provider",
+    feature = "forwarding-provider",
+    feature = "external-provider",
+    feature = "trusted-service-provider",
+    feature = "test-provider"
 )))]
 use log::error;
 use log::LevelFilter;
-use parsec_interface::requests::ProviderId;
+use parsec_interface::operations::psa_algorithm::{Algorithm, Hash};
+use parsec_interface::operations::psa_key_attributes::{Lifetime, Type};
+use parsec_interface::requests::{AuthType, ProviderId};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::io::Error;
 #[cfg(not(all(
     feature = "mbed-crypto-provider",
     feature = "pkcs11-provider",
     feature = "tpm-provider",
     feature = "cryptoauthlib-provider",
-    feature = "trusted-service-provider"
+    feature = "piv-provider",
+    feature = "forwarding-provider",
+    feature = "external-provider",
+    feature = "trusted-service-provider",
+    feature = "test-provider"
 )))]
 use std::io::ErrorKind;
 use zeroize::Zeroize;
@@ -41,14 +61,505 @@ use zeroize::Zeroize;
 #[allow(missing_docs)]
 pub struct CoreSettings {
     pub thread_pool_size: Option<usize>,
+    pub thread_pool_min_size: Option<usize>,
+    pub thread_pool_max_size: Option<usize>,
+    pub max_connections: Option<usize>,
     pub idle_listener_sleep_duration: Option<u64>,
     pub log_level: Option<LevelFilter>,
     pub log_timestamp: Option<bool>,
+    pub log_sink: Option<LogSink>,
+    pub log_format: Option<LogFormat>,
     pub body_len_limit: Option<usize>,
+    pub in_flight_memory_limit: Option<usize>,
+    pub response_compression_threshold: Option<usize>,
     pub log_error_details: Option<bool>,
     pub allow_root: Option<bool>,
     pub buffer_size_limit: Option<usize>,
     pub allow_deprecated: Option<bool>,
+    pub result_cache_ttl: Option<u64>,
+    pub strict_providers: Option<bool>,
+    pub configuration_pcr: Option<u8>,
+}
+
+/// Destination of the logs
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum LogSink {
+    /// Standard error of the service
+    Stderr,
+    /// Local syslog daemon, through the `/dev/log` socket
+    Syslog,
+    /// systemd journal, through its native protocol. Needs the `journald` feature.
+    Journald,
+}
+
+/// Format of the log entries
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable text
+    Text,
+    /// One JSON object per entry
+    Json,
+}
+
+/// Fault injection settings
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct FaultInjectionConfig {
+    pub provider_error_percentage: Option<u8>,
+    pub kim_write_failure_percentage: Option<u8>,
+    pub slow_response_percentage: Option<u8>,
+    pub slow_response_delay: Option<u64>,
+}
+
+/// Requirements on the keys created by clients
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyRequirementsConfig {
+    pub hardware_backed: Option<bool>,
+    pub attestable: Option<bool>,
+    pub non_exportable: Option<bool>,
+}
+
+/// Checks on the key material of imported keys
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct ImportChecksConfig {
+    pub min_rsa_public_exponent: Option<u64>,
+    pub rsa_fermat_check: Option<bool>,
+    pub rsa_shared_factor_history: Option<usize>,
+    pub min_symmetric_key_entropy: Option<u32>,
+}
+
+/// Nonces used by ECDSA signatures
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum EcdsaNonceMode {
+    /// Nonces derived from the key and the hash signed, as defined in RFC 6979
+    Deterministic,
+    /// Nonces drawn from a random number generator
+    Randomized,
+}
+
+/// Selection of the nonces of the ECDSA keys created
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct EcdsaNoncesConfig {
+    pub mode: EcdsaNonceMode,
+    pub strict: Option<bool>,
+}
+
+/// Use permitted by the usage flags of a key
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum KeyUsage {
+    Export,
+    Copy,
+    Cache,
+    Encrypt,
+    Decrypt,
+    SignMessage,
+    VerifyMessage,
+    SignHash,
+    VerifyHash,
+    Derive,
+}
+
+/// Named template of the keys that can be created
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyTemplateConfig {
+    pub name: String,
+    pub provider: String,
+    pub key_type: Type,
+    pub bits: usize,
+    pub algorithm: Algorithm,
+    pub usage: Vec<KeyUsage>,
+    pub lifetime: Option<Lifetime>,
+}
+
+/// Key templates defined by the operators
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyTemplatesConfig {
+    pub strict: Option<bool>,
+    pub template: Vec<KeyTemplateConfig>,
+}
+
+/// Attributes given to the keys of a type when their creation request omits them
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyDefaultConfig {
+    pub key_type: Type,
+    pub bits: Option<usize>,
+    pub algorithm: Option<Algorithm>,
+}
+
+/// Attributes filled in by the service in key creation requests
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyDefaultsConfig {
+    pub strict: Option<bool>,
+    pub signature_hash: Option<Hash>,
+    #[serde(default)]
+    pub default: Vec<KeyDefaultConfig>,
+}
+
+/// Configuration of the priority between interactive and batch requests
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct RequestPriorityConfig {
+    pub batch_applications: Option<Vec<String>>,
+    pub batch_concurrency: Option<usize>,
+}
+
+/// Configuration of the throttling of authentication failures
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct AuthThrottleConfig {
+    pub free_attempts: Option<u32>,
+    pub backoff: Option<u64>,
+    pub max_backoff: Option<u64>,
+}
+
+/// Configuration of the leases of keys to other applications
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyLeasesConfig {
+    pub lease: Option<Vec<KeyLeaseConfig>>,
+}
+
+/// Lease of a key declared in the configuration
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyLeaseConfig {
+    pub owner: String,
+    pub key_name: String,
+    pub grantee: String,
+    pub expires: u64,
+}
+
+/// Configuration of the signing policies restricting keys to the digests they may sign
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct SigningPoliciesConfig {
+    pub policy: Option<Vec<SigningPolicyConfig>>,
+}
+
+/// Signing policy of a key
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct SigningPolicyConfig {
+    pub owner: String,
+    pub key_name: String,
+    pub digests: Option<Vec<String>>,
+    pub digests_file: Option<String>,
+}
+
+/// Operation which can be made to wait for the approval of a second administrator
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum SensitiveOperation {
+    /// Export of a key, with PsaExportKey
+    ExportKey,
+    /// Deletion of the keys of an application by an administrator, with DeleteClient
+    ForceDestroy,
+}
+
+/// Configuration of the dual-control approval of sensitive operations
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct ApprovalsConfig {
+    pub operations: Vec<SensitiveOperation>,
+    pub store_path: Option<String>,
+    pub ttl: Option<u64>,
+}
+
+/// Tenant of the service, isolating the applications of an orchestrator
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct TenantConfig {
+    pub applications: Vec<String>,
+    pub authenticators: Option<Vec<String>>,
+    pub providers: Option<Vec<String>>,
+    pub key_storage_quota: Option<usize>,
+}
+
+/// Event reported to the event hooks
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum HookEvent {
+    /// A key was generated or imported
+    KeyCreated,
+    /// A key was destroyed
+    KeyDestroyed,
+    /// A key was attested
+    KeyAttested,
+    /// A client failed to authenticate
+    AuthenticationFailed,
+}
+
+/// Program or webhook called on events
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(tag = "hook_type")]
+#[allow(missing_docs)]
+pub enum EventHookConfig {
+    /// Program executed with the event in its environment
+    Exec {
+        program: String,
+        args: Option<Vec<String>>,
+        events: Option<Vec<HookEvent>>,
+    },
+    /// HTTP endpoint, on the loopback interface or a Unix socket, receiving the event as JSON
+    Webhook {
+        url: String,
+        events: Option<Vec<HookEvent>>,
+    },
+}
+
+/// Hooks called on the events of the service
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Default, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct EventHooksConfig {
+    pub queue_size: Option<usize>,
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub hook: Vec<EventHookConfig>,
+}
+
+/// Tracking of the lifecycle of the keys
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeyLifecycleConfig {
+    pub store_path: Option<String>,
+}
+
+/// Counting of the signatures made with each key
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct SignatureCountersConfig {
+    pub store_path: Option<String>,
+    pub write_batch: Option<u64>,
+    pub max_signatures: Option<u64>,
+    pub key_limit: Option<Vec<KeySignatureLimitConfig>>,
+}
+
+/// Number of signatures a single key can make
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct KeySignatureLimitConfig {
+    pub owner: String,
+    pub key_name: String,
+    pub max_signatures: u64,
+}
+
+/// Write-behind of the metadata of the keys
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct WriteBehindConfig {
+    pub flush_interval: Option<u64>,
+    pub max_pending: Option<usize>,
+    pub journal: Option<bool>,
+    pub sync_journal: Option<bool>,
+}
+
+/// Activation of the providers
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct ProviderActivationConfig {
+    pub on_demand: Vec<String>,
+}
+
+/// Mirroring of read-only operations to a second provider
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct MirroringConfig {
+    pub provider: String,
+    pub mirror_provider: String,
+}
+
+/// Limits on the generation of random bytes
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct RandomLimitsConfig {
+    pub max_size: Option<usize>,
+    pub bytes_per_second: Option<u64>,
+    pub burst_size: Option<usize>,
+}
+
+/// Configuration of the limits of the AEAD requests
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct AeadLimitsConfig {
+    pub max_additional_data_size: Option<usize>,
+    pub max_plaintext_size: Option<usize>,
+}
+
+/// Role of the instance in the replication of the key mappings
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum ReplicationRole {
+    /// Sends its mappings to the standby instance
+    Active,
+    /// Receives the mappings of the active instance
+    Standby,
+}
+
+/// Configuration of the replication of the key mappings to a standby instance
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct ReplicationConfig {
+    pub role: ReplicationRole,
+    pub socket_path: String,
+    pub active_uid: Option<u32>,
+}
+
+/// Template of keys generated in advance by a provider
+///
+/// See the config.toml file for a description of each field.
+#[derive(Copy, Clone, Deserialize, Debug, Zeroize)]
+#[allow(missing_docs)]
+pub struct KeyPoolConfig {
+    pub key_type: Type,
+    pub bits: usize,
+    pub algorithm: Algorithm,
+    pub size: usize,
+}
+
+/// Algorithm of the device identity keys of the TPM provider
+#[derive(Copy, Clone, Deserialize, Debug, Zeroize)]
+pub enum DeviceIdentityAlgorithm {
+    /// RSA 2048 keys signing with SHA-256
+    Rsa2048,
+    /// ECC keys on the NIST P-256 curve signing with SHA-256
+    EccP256,
+}
+
+/// Device identity keys provisioned by the TPM provider
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug, Zeroize)]
+#[allow(missing_docs)]
+pub struct DeviceIdentityConfig {
+    pub algorithm: DeviceIdentityAlgorithm,
+    pub iak_handle: u32,
+    pub idevid_handle: u32,
+    pub artifacts_dir: String,
+}
+
+/// Key slot of a PIV card
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq, Zeroize)]
+pub enum PivSlot {
+    /// PIV Authentication key, in slot 9a
+    #[serde(rename = "9a")]
+    Authentication,
+    /// Digital Signature key, in slot 9c
+    #[serde(rename = "9c")]
+    Signature,
+}
+
+/// Use made of the key of a PIV slot
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq, Default, Zeroize)]
+pub enum PivKeyUsage {
+    /// Signing hashes and messages
+    #[default]
+    Sign,
+    /// Decrypting with RSA PKCS#1 v1.5
+    Decrypt,
+}
+
+/// Key of a PIV card made available to an application
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug, Zeroize)]
+#[allow(missing_docs)]
+pub struct PivKeyConfig {
+    pub slot: PivSlot,
+    pub key_name: String,
+    pub application: String,
+    pub usage: Option<PivKeyUsage>,
+}
+
+/// Provider of a remote service to which the operations are forwarded
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq, Zeroize)]
+#[allow(missing_docs)]
+pub enum RemoteProviderId {
+    MbedCrypto,
+    Pkcs11,
+    Tpm,
+    TrustedService,
+    CryptoAuthLib,
+}
+
+impl From<RemoteProviderId> for ProviderId {
+    fn from(remote_provider: RemoteProviderId) -> Self {
+        match remote_provider {
+            RemoteProviderId::MbedCrypto => ProviderId::MbedCrypto,
+            RemoteProviderId::Pkcs11 => ProviderId::Pkcs11,
+            RemoteProviderId::Tpm => ProviderId::Tpm,
+            RemoteProviderId::TrustedService => ProviderId::TrustedService,
+            RemoteProviderId::CryptoAuthLib => ProviderId::CryptoAuthLib,
+        }
+    }
+}
+
+/// Application whose operations are forwarded to a remote service
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug, Zeroize)]
+#[allow(missing_docs)]
+pub struct ForwardedApplicationConfig {
+    pub local: String,
+    pub remote: String,
 }
 
 /// Type of the Listener used
@@ -59,14 +570,52 @@ pub enum ListenerType {
 }
 
 /// Configuration of the Listener
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Deserialize, Debug)]
 pub struct ListenerConfig {
     /// Type of the Listener
     pub listener_type: ListenerType,
     /// Timeout of the Listener before the connection errors out (in milliseconds)
     pub timeout: u64,
+    /// Time a client has to send a complete request (in milliseconds)
+    pub idle_timeout: Option<u64>,
     /// Path of the Unix Domain socket
     pub socket_path: Option<String>,
+    /// User owning the socket, by name or ID
+    pub socket_owner: Option<String>,
+    /// Group owning the socket, by name or ID
+    pub socket_group: Option<String>,
+    /// Permissions of the socket
+    pub socket_mode: Option<u32>,
+    /// Create the directories of the sockets if they do not exist
+    pub create_socket_dir: Option<bool>,
+    /// Other sockets to listen on
+    pub additional_socket: Option<Vec<SocketConfig>>,
+    /// D-Bus interface offered in addition to the sockets
+    pub dbus: Option<DbusConfig>,
+}
+
+/// Configuration of the D-Bus interface
+///
+/// See the config.toml file for a description of each field.
+#[derive(Clone, Deserialize, Debug)]
+#[allow(missing_docs)]
+pub struct DbusConfig {
+    pub bus_address: Option<String>,
+}
+
+/// Configuration of a socket listened on in addition to the main one
+#[derive(Deserialize, Debug)]
+pub struct SocketConfig {
+    /// Path of the Unix Domain socket
+    pub socket_path: String,
+    /// User owning the socket, by name or ID
+    pub socket_owner: Option<String>,
+    /// Group owning the socket, by name or ID
+    pub socket_group: Option<String>,
+    /// Permissions of the socket
+    pub socket_mode: Option<u32>,
+    /// Only authenticator accepted on this socket
+    pub authenticator: Option<AuthenticatorConfig>,
 }
 
 /// Authenticator configuration structure
@@ -83,6 +632,12 @@ pub enum AuthenticatorConfig {
     UnixPeerCredentials {
         /// List of service admins
         admins: Option<Vec<Admin>>,
+        /// Applications identified by the SELinux context or AppArmor label of their process
+        security_contexts: Option<Vec<SecurityContextConfig>>,
+        /// Only authenticate the processes with a listed security context
+        require_security_context: Option<bool>,
+        /// Identification of the processes running in containers by their container
+        container_identity: Option<ContainerIdentityConfig>,
     },
     /// JWT-SVID
     JwtSvid {
@@ -90,7 +645,71 @@ pub enum AuthenticatorConfig {
         workload_endpoint: String,
         /// List of service admins
         admins: Option<Vec<Admin>>,
+        /// Time during which a validated token is cached (in seconds), 0 disables the cache
+        cache_ttl: Option<u64>,
+        /// Maximum number of tokens in the cache
+        cache_size: Option<usize>,
+    },
+    /// Tokens signed by attested keys
+    AttestationToken {
+        /// Keys trusted to sign tokens
+        keys: Vec<AttestedKeyConfig>,
+        /// Maximum time until the expiration of a token (in seconds)
+        max_token_lifetime: Option<u64>,
+        /// List of service admins
+        admins: Option<Vec<Admin>>,
     },
+    /// Decisions of an external plugin
+    External {
+        /// Path of the Unix domain socket of the plugin
+        socket_path: String,
+        /// Authentication type of the requests authenticated by the plugin
+        #[zeroize(skip)]
+        client_auth_type: Option<ExternalAuthType>,
+        /// Time to wait for a decision of the plugin (in milliseconds)
+        timeout: Option<u64>,
+        /// List of service admins
+        admins: Option<Vec<Admin>>,
+    },
+}
+
+/// Authentication type under which clients send the payloads checked by an external plugin
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum ExternalAuthType {
+    /// Direct authentication
+    Direct,
+    /// JSON Web Tokens
+    Jwt,
+    /// Unix peer credentials
+    UnixPeerCredentials,
+    /// JWT SPIFFE Verifiable Identity Documents
+    JwtSvid,
+}
+
+impl From<ExternalAuthType> for AuthType {
+    fn from(auth_type: ExternalAuthType) -> Self {
+        match auth_type {
+            ExternalAuthType::Direct => AuthType::Direct,
+            ExternalAuthType::Jwt => AuthType::Jwt,
+            ExternalAuthType::UnixPeerCredentials => AuthType::UnixPeerCredentials,
+            ExternalAuthType::JwtSvid => AuthType::JwtSvid,
+        }
+    }
+}
+
+impl AuthenticatorConfig {
+    /// Authentication type of the authenticator
+    pub fn auth_type(&self) -> AuthType {
+        match self {
+            AuthenticatorConfig::Direct { .. } => AuthType::Direct,
+            AuthenticatorConfig::UnixPeerCredentials { .. } => AuthType::UnixPeerCredentials,
+            AuthenticatorConfig::JwtSvid { .. } => AuthType::JwtSvid,
+            AuthenticatorConfig::AttestationToken { .. } => AuthType::Jwt,
+            AuthenticatorConfig::External {
+                client_auth_type, ..
+            } => client_auth_type.map_or(AuthType::Jwt, AuthType::from),
+        }
+    }
 }
 
 /// Structure defining the properties of a service admin
@@ -107,6 +726,86 @@ impl Admin {
     }
 }
 
+/// Key trusted by the attestation token authenticator
+#[derive(Deserialize, Debug, Zeroize, Clone)]
+#[zeroize(drop)]
+pub struct AttestedKeyConfig {
+    key_id: String,
+    application: String,
+    public_key: String,
+    algorithm: Option<String>,
+}
+
+impl AttestedKeyConfig {
+    /// Identifier of the key in the tokens
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Name of the application authenticated by the key
+    pub fn application(&self) -> &str {
+        &self.application
+    }
+
+    /// Public part of the key, in the format of PsaExportPublicKey, encoded in base64
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// JWS algorithm of the tokens signed by the key, if not the default one for its type
+    pub fn algorithm(&self) -> Option<&str> {
+        self.algorithm.as_deref()
+    }
+}
+
+/// Application identified by a security context in the Unix peer credentials authenticator
+#[derive(Deserialize, Debug, Zeroize, Clone)]
+#[zeroize(drop)]
+pub struct SecurityContextConfig {
+    context: String,
+    application: String,
+}
+
+impl SecurityContextConfig {
+    /// SELinux context or AppArmor label, ending with `*` to match all contexts with that prefix
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    /// Name of the application of the processes in that context
+    pub fn application(&self) -> &str {
+        &self.application
+    }
+}
+
+/// Way the Unix peer credentials authenticator finds the container of a process
+#[derive(Deserialize, Debug, Zeroize, Clone)]
+#[zeroize(drop)]
+#[serde(tag = "resolver")]
+pub enum ContainerIdentityConfig {
+    /// Container ID found in the cgroup path of the process
+    Cgroup,
+    /// Program given the PID of the process, printing the identity of its container
+    Exec {
+        /// Program executed
+        program: String,
+        /// Arguments given before the PID
+        args: Option<Vec<String>>,
+        /// Time after which the program is killed, in milliseconds
+        timeout: Option<u64>,
+    },
+}
+
+/// Side-channel hardening level of a software provider
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq, Default, Zeroize)]
+pub enum SideChannelHardening {
+    /// Countermeasures built into the cryptographic library
+    #[default]
+    Default,
+    /// Algorithms without a side-channel resistant implementation are also refused
+    Strict,
+}
+
 /// Type of the KeyInfoManager
 #[derive(Copy, Clone, Deserialize, Debug)]
 pub enum KeyInfoManagerType {
@@ -127,6 +826,10 @@ pub struct KeyInfoManagerConfig {
     pub store_path: Option<String>,
     /// File path where the SQLite database should be stored when using SQLiteKeyInfoManager
     pub sqlite_db_path: Option<String>,
+    /// Maximum number of bytes of mappings stored for each application and provider
+    pub application_quota: Option<usize>,
+    /// File holding the secret key protecting the integrity of the mappings
+    pub integrity_key_path: Option<String>,
 }
 
 /// Provider configuration structure
@@ -134,7 +837,7 @@ pub struct KeyInfoManagerConfig {
 /// to the one described in the Internally Tagged Enum representation
 /// where "provider_type" is the tag field. For details see:
 /// https://serde.rs/enum-representations.html
-#[derive(Deserialize, Debug, Zeroize)]
+#[derive(Deserialize, Debug, Clone, Zeroize)]
 #[zeroize(drop)]
 #[serde(tag = "provider_type")]
 pub enum ProviderConfig {
@@ -144,6 +847,12 @@ pub enum ProviderConfig {
         name: Option<String>,
         /// Name of the Key Info Manager to use
         key_info_manager: String,
+        /// Directory where Mbed Crypto stores the keys
+        key_store_dir: Option<String>,
+        /// Whether the key store directory must be on an encrypted filesystem
+        require_encrypted_key_store: Option<bool>,
+        /// Side-channel hardening level
+        side_channel_hardening: Option<SideChannelHardening>,
     },
     /// PKCS 11 provider configuration
     Pkcs11 {
@@ -157,12 +866,18 @@ pub enum ProviderConfig {
         slot_number: Option<u64>,
         /// Token serial number to use
         serial_number: Option<String>,
+        /// Token label to use
+        token_label: Option<String>,
+        /// Seconds between two looks for the slot of the token selected by serial number or label
+        token_rediscovery_interval: Option<u64>,
         /// User Pin
         user_pin: Option<String>,
         /// Control whether public key operations are performed in software
         software_public_operations: Option<bool>,
         /// Control whether it is allowed for a key to be exportable
         allow_export: Option<bool>,
+        /// Label of the RSA key pair of the token secrets are wrapped under offline
+        wrapping_key_label: Option<String>,
     },
     /// TPM provider configuration
     Tpm {
@@ -172,6 +887,8 @@ pub enum ProviderConfig {
         key_info_manager: String,
         /// TCTI to use with the provider
         tcti: String,
+        /// TCTIs tried in order when the first one can not be used
+        tcti_fallbacks: Option<Vec<String>>,
         /// Owner Hierarchy Authentication
         owner_hierarchy_auth: String,
         /// Endorsement Hierarchy Authentication Value
@@ -179,6 +896,18 @@ pub enum ProviderConfig {
         /// Allows the service to still start without this provider if there is no TPM on the
         /// system. The priority list of providers will be as if this provider was commented out.
         skip_if_no_tpm: Option<bool>,
+        /// Number of ESAPI contexts used concurrently
+        context_pool_size: Option<usize>,
+        /// Keys generated in advance
+        key_pool: Option<Vec<KeyPoolConfig>>,
+        /// Verify signatures in software instead of with the TPM
+        software_verification: Option<bool>,
+        /// Length of the authValues of new keys
+        auth_value_len: Option<usize>,
+        /// File holding the secret with which the stored authValues are masked
+        auth_value_secret_path: Option<String>,
+        /// Device identity keys provisioned on first boot
+        device_identity: Option<DeviceIdentityConfig>,
     },
     /// Microchip CryptoAuthentication Library provider configuration
     CryptoAuthLib {
@@ -202,6 +931,59 @@ pub enum ProviderConfig {
         baud: Option<u32>,
         /// Access key configuration file name
         access_key_file_name: Option<String>,
+        /// File where the map of the slots is written at startup
+        slot_map_file_name: Option<String>,
+        /// Slot holding the IO protection key
+        io_protection_key_slot: Option<u8>,
+        /// Emulate ECDSA with other hashes than SHA-256
+        signature_emulation: Option<bool>,
+    },
+    /// PIV smartcard provider configuration
+    Piv {
+        /// The name of the provider
+        name: Option<String>,
+        /// Name of the Key Info Manager to use
+        key_info_manager: String,
+        /// Path of the PC/SC library
+        library_path: Option<String>,
+        /// Part of the name of the reader holding the card
+        reader: Option<String>,
+        /// PIN of the card
+        pin: Option<String>,
+        /// Keys of the card slots
+        key: Option<Vec<PivKeyConfig>>,
+    },
+    /// Configuration of the provider forwarding operations to a remote service
+    Forwarding {
+        /// The name of the provider
+        name: Option<String>,
+        /// Name of the Key Info Manager to use
+        key_info_manager: String,
+        /// Path of the socket of the remote service
+        socket_path: String,
+        /// Provider of the remote service to which the operations are sent
+        remote_provider: RemoteProviderId,
+        /// Local applications and their name in the remote service
+        application: Option<Vec<ForwardedApplicationConfig>>,
+        /// Remote application as which the admin operations are forwarded
+        admin_application: Option<String>,
+        /// Timeout of the requests to the remote service (in milliseconds)
+        timeout: Option<u64>,
+    },
+    /// Configuration of the provider whose operations are done by an external plugin
+    External {
+        /// The name of the provider
+        name: Option<String>,
+        /// Name of the Key Info Manager to use
+        key_info_manager: String,
+        /// Path of the socket of the plugin
+        socket_path: Option<String>,
+        /// Program started by the service as the plugin, followed by its arguments
+        command: Option<Vec<String>>,
+        /// Provider ID served by the plugin
+        provider_id: RemoteProviderId,
+        /// Timeout of the requests to the plugin (in milliseconds)
+        timeout: Option<u64>,
     },
     /// Trusted Service provider configuration
     TrustedService {
@@ -210,6 +992,23 @@ pub enum ProviderConfig {
         /// Name of Key Info Manager to use
         key_info_manager: String,
     },
+    /// Deterministic test provider configuration
+    Test {
+        /// The name of the provider
+        name: Option<String>,
+        /// Name of Key Info Manager to use
+        key_info_manager: String,
+        /// Seed from which the key material and random bytes are derived
+        seed: Option<u64>,
+        /// Number of requests to fail after start-up
+        fail_next: Option<u32>,
+        /// Delay applied to all requests (in milliseconds)
+        delay: Option<u64>,
+        /// Path of the conformance scenario followed by the requests
+        scenario_path: Option<String>,
+        /// Path of the file where the progress of the conformance scenario is written
+        scenario_report_path: Option<String>,
+    },
 }
 
 impl ProviderConfig {
@@ -232,10 +1031,26 @@ impl ProviderConfig {
                 ref key_info_manager,
                 ..
             } => key_info_manager,
+            ProviderConfig::Piv {
+                ref key_info_manager,
+                ..
+            } => key_info_manager,
+            ProviderConfig::Forwarding {
+                ref key_info_manager,
+                ..
+            } => key_info_manager,
+            ProviderConfig::External {
+                ref key_info_manager,
+                ..
+            } => key_info_manager,
             ProviderConfig::TrustedService {
                 ref key_info_manager,
                 ..
             } => key_info_manager,
+            ProviderConfig::Test {
+                ref key_info_manager,
+                ..
+            } => key_info_manager,
         }
     }
     /// Get the Provider ID of the provider
@@ -245,7 +1060,18 @@ impl ProviderConfig {
             ProviderConfig::Pkcs11 { .. } => ProviderId::Pkcs11,
             ProviderConfig::Tpm { .. } => ProviderId::Tpm,
             ProviderConfig::CryptoAuthLib { .. } => ProviderId::CryptoAuthLib,
+            // The PIV provider serves the PKCS 11 provider ID: a PIV card is also used through
+            // PKCS 11 middleware, and the interface has no ID of its own for it. The two providers
+            // are refused together as conflicting.
+            ProviderConfig::Piv { .. } => ProviderId::Pkcs11,
+            // Clients address the forwarded provider by the ID it has in the remote service.
+            ProviderConfig::Forwarding {
+                remote_provider, ..
+            } => remote_provider.into(),
+            ProviderConfig::External { provider_id, .. } => provider_id.into(),
             ProviderConfig::TrustedService { .. } => ProviderId::TrustedService,
+            // The test provider stands in for the Mbed Crypto provider and is refused alongside it.
+            ProviderConfig::Test { .. } => ProviderId::MbedCrypto,
         }
     }
     /// Get the name of the Provider
@@ -268,16 +1094,36 @@ impl ProviderConfig {
             ProviderConfig::CryptoAuthLib { ref name, .. } => Ok(name
                 .clone()
                 .unwrap_or_else(|| String::from(CryptoAuthLibProvider::DEFAULT_PROVIDER_NAME))),
+            #[cfg(feature = "piv-provider")]
+            ProviderConfig::Piv { ref name, .. } => Ok(name
+                .clone()
+                .unwrap_or_else(|| String::from(PivProvider::DEFAULT_PROVIDER_NAME))),
+            #[cfg(feature = "forwarding-provider")]
+            ProviderConfig::Forwarding { ref name, .. } => Ok(name
+                .clone()
+                .unwrap_or_else(|| String::from(ForwardingProvider::DEFAULT_PROVIDER_NAME))),
+            #[cfg(feature = "external-provider")]
+            ProviderConfig::External { ref name, .. } => Ok(name
+                .clone()
+                .unwrap_or_else(|| String::from(ExternalProvider::DEFAULT_PROVIDER_NAME))),
             #[cfg(feature = "trusted-service-provider")]
             ProviderConfig::TrustedService { ref name, .. } => Ok(name
                 .clone()
                 .unwrap_or_else(|| String::from(TrustedServiceProvider::DEFAULT_PROVIDER_NAME))),
+            #[cfg(feature = "test-provider")]
+            ProviderConfig::Test { ref name, .. } => Ok(name
+                .clone()
+                .unwrap_or_else(|| String::from(TestProvider::DEFAULT_PROVIDER_NAME))),
             #[cfg(not(all(
                 feature = "mbed-crypto-provider",
                 feature = "pkcs11-provider",
                 feature = "tpm-provider",
                 feature = "cryptoauthlib-provider",
-                feature = "trusted-service-provider"
+                feature = "piv-provider",
+                feature = "forwarding-provider",
+                feature = "external-provider",
+                feature = "trusted-service-provider",
+                feature = "test-provider"
             )))]
             _ => {
                 error!("Provider ({:?}) chosen in the configuration was not compiled in Parsec binary.", self);
@@ -285,6 +1131,48 @@ impl ProviderConfig {
             }
         }
     }
+    /// Get the identity of the Provider, under which its keys are stored in the Key Info Manager
+    pub fn provider_identity(&self) -> Result<ProviderIdentity, Error> {
+        let uuid: Result<&str, Error> = match *self {
+            #[cfg(feature = "mbed-crypto-provider")]
+            ProviderConfig::MbedCrypto { .. } => Ok(MbedCryptoProvider::PROVIDER_UUID),
+            #[cfg(feature = "pkcs11-provider")]
+            ProviderConfig::Pkcs11 { .. } => Ok(Pkcs11Provider::PROVIDER_UUID),
+            #[cfg(feature = "tpm-provider")]
+            ProviderConfig::Tpm { .. } => Ok(TpmProvider::PROVIDER_UUID),
+            #[cfg(feature = "cryptoauthlib-provider")]
+            ProviderConfig::CryptoAuthLib { .. } => Ok(CryptoAuthLibProvider::PROVIDER_UUID),
+            #[cfg(feature = "piv-provider")]
+            ProviderConfig::Piv { .. } => Ok(PivProvider::PROVIDER_UUID),
+            #[cfg(feature = "forwarding-provider")]
+            ProviderConfig::Forwarding { .. } => Ok(ForwardingProvider::PROVIDER_UUID),
+            #[cfg(feature = "external-provider")]
+            ProviderConfig::External { .. } => Ok(ExternalProvider::PROVIDER_UUID),
+            #[cfg(feature = "trusted-service-provider")]
+            ProviderConfig::TrustedService { .. } => Ok(TrustedServiceProvider::PROVIDER_UUID),
+            #[cfg(feature = "test-provider")]
+            ProviderConfig::Test { .. } => Ok(TestProvider::PROVIDER_UUID),
+            #[cfg(not(all(
+                feature = "mbed-crypto-provider",
+                feature = "pkcs11-provider",
+                feature = "tpm-provider",
+                feature = "cryptoauthlib-provider",
+                feature = "piv-provider",
+                feature = "forwarding-provider",
+                feature = "external-provider",
+                feature = "trusted-service-provider",
+                feature = "test-provider"
+            )))]
+            _ => {
+                error!("Provider ({:?}) chosen in the configuration was not compiled in Parsec binary.", self);
+                Err(Error::new(ErrorKind::InvalidData, "provider not compiled"))
+            }
+        };
+        Ok(ProviderIdentity::new(
+            uuid?.to_string(),
+            self.provider_name()?,
+        ))
+    }
 }
 
 /// Configuration of Parsec
@@ -298,4 +1186,25 @@ pub struct ServiceConfig {
     pub authenticator: AuthenticatorConfig,
     pub key_manager: Option<Vec<KeyInfoManagerConfig>>,
     pub provider: Option<Vec<ProviderConfig>>,
+    pub fault_injection: Option<FaultInjectionConfig>,
+    pub key_requirements: Option<KeyRequirementsConfig>,
+    pub import_checks: Option<ImportChecksConfig>,
+    pub ecdsa_nonces: Option<EcdsaNoncesConfig>,
+    pub key_templates: Option<KeyTemplatesConfig>,
+    pub key_defaults: Option<KeyDefaultsConfig>,
+    pub key_lifecycle: Option<KeyLifecycleConfig>,
+    pub signature_counters: Option<SignatureCountersConfig>,
+    pub kim_write_behind: Option<WriteBehindConfig>,
+    pub request_priority: Option<RequestPriorityConfig>,
+    pub authentication_throttle: Option<AuthThrottleConfig>,
+    pub replication: Option<ReplicationConfig>,
+    pub key_leases: Option<KeyLeasesConfig>,
+    pub signing_policies: Option<SigningPoliciesConfig>,
+    pub approvals: Option<ApprovalsConfig>,
+    pub random_limits: Option<RandomLimitsConfig>,
+    pub aead_limits: Option<AeadLimitsConfig>,
+    pub tenants: Option<BTreeMap<String, TenantConfig>>,
+    pub mirroring: Option<MirroringConfig>,
+    pub event_hooks: Option<EventHooksConfig>,
+    pub provider_activation: Option<ProviderActivationConfig>,
 }
@@ -0,0 +1,137 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Configuration check report
+//!
+//! Outcome of `ServiceBuilder::check_config`: one entry per component of the configuration, stating
+//! whether it could be set up. The report is printed by the `--check-config` mode of the service so
+//! that deployment pipelines can validate a configuration without starting the service.
+use std::fmt;
+
+/// Outcome of the check of one component
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComponentCheck {
+    component: String,
+    outcome: Result<String, String>,
+}
+
+impl ComponentCheck {
+    /// Name of the component checked
+    pub fn component(&self) -> &str {
+        &self.component
+    }
+
+    /// Whether the component could be set up
+    pub fn is_ok(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+/// Report of a configuration check
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigReport {
+    checks: Vec<ComponentCheck>,
+}
+
+impl ConfigReport {
+    /// Record that a component could be set up.
+    pub fn pass(&mut self, component: impl Into<String>, detail: impl Into<String>) {
+        self.checks.push(ComponentCheck {
+            component: component.into(),
+            outcome: Ok(detail.into()),
+        });
+    }
+
+    /// Record that a component could not be set up.
+    pub fn fail(&mut self, component: impl Into<String>, reason: impl Into<String>) {
+        self.checks.push(ComponentCheck {
+            component: component.into(),
+            outcome: Err(reason.into()),
+        });
+    }
+
+    /// Checks performed, in order
+    pub fn checks(&self) -> &[ComponentCheck] {
+        &self.checks
+    }
+
+    /// Whether all the components could be set up
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(ComponentCheck::is_ok)
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match &check.outcome {
+                Ok(detail) => writeln!(f, "[ OK ] {}: {}", check.component, detail)?,
+                Err(reason) => writeln!(f, "[FAIL] {}: {}", check.component, reason)?,
+            }
+        }
+        let failed = self.checks.iter().filter(|check| !check.is_ok()).count();
+        write!(
+            f,
+            "{} component(s) checked, {} failure(s)",
+            self.checks.len(),
+            failed
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConfigReport;
+
+    #[test]
+    fn empty_report_is_ok() {
+        let report = ConfigReport::default();
+        assert!(report.is_ok());
+        assert!(report.checks().is_empty());
+        assert_eq!(report.to_string(), "0 component(s) checked, 0 failure(s)");
+    }
+
+    #[test]
+    fn checks_are_kept_in_order() {
+        let mut report = ConfigReport::default();
+        report.pass("listener", "socket /run/parsec/parsec.sock");
+        report.fail("provider tpm", "TCTI not found");
+        report.pass("key info managers", "1 configured");
+        let checks: Vec<(&str, bool)> = report
+            .checks()
+            .iter()
+            .map(|check| (check.component(), check.is_ok()))
+            .collect();
+        assert_eq!(
+            checks,
+            [
+                ("listener", true),
+                ("provider tpm", false),
+                ("key info managers", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn one_failure_fails_the_report() {
+        let mut report = ConfigReport::default();
+        report.pass("listener", "socket /run/parsec/parsec.sock");
+        assert!(report.is_ok());
+        report.fail("providers", "at least one provider is needed");
+        assert!(!report.is_ok());
+        report.pass("authenticator", "Direct");
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn display() {
+        let mut report = ConfigReport::default();
+        report.pass("listener", "socket /run/parsec/parsec.sock");
+        report.fail("providers", "at least one provider is needed");
+        assert_eq!(
+            report.to_string(),
+            "[ OK ] listener: socket /run/parsec/parsec.sock\n\
+             [FAIL] providers: at least one provider is needed\n\
+             2 component(s) checked, 1 failure(s)"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! COSE identifiers of keys and algorithms
+//!
+//! Labels and values of the COSE headers and COSE_Key structures (RFC 9052), of the RSA (RFC 8230)
+//! and elliptic curve (RFC 9053) keys, and the PSA algorithms of the COSE signature algorithms.
+use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
+use parsec_interface::operations::psa_key_attributes::EccFamily;
+
+/// Algorithm label of the headers
+pub const HEADER_ALGORITHM: i64 = 1;
+
+/// Key type label
+pub const KEY_TYPE: i64 = 1;
+/// Algorithm label
+pub const KEY_ALGORITHM: i64 = 3;
+/// Permitted operations label
+pub const KEY_OPS: i64 = 4;
+
+/// Elliptic curve key with x and y coordinates
+pub const KEY_TYPE_EC2: i64 = 2;
+/// RSA key
+pub const KEY_TYPE_RSA: i64 = 3;
+
+/// Curve of an EC2 key
+pub const EC2_CURVE: i64 = -1;
+/// x coordinate of an EC2 key
+pub const EC2_X: i64 = -2;
+/// y coordinate of an EC2 key
+pub const EC2_Y: i64 = -3;
+/// Modulus of an RSA key
+pub const RSA_N: i64 = -1;
+/// Public exponent of an RSA key
+pub const RSA_E: i64 = -2;
+
+/// Operation of keys computing signatures
+pub const KEY_OP_SIGN: i64 = 1;
+/// Operation of keys verifying signatures
+pub const KEY_OP_VERIFY: i64 = 2;
+
+/// COSE curves, with their PSA family and size
+const CURVES: [(i64, EccFamily, usize); 4] = [
+    (1, EccFamily::SecpR1, 256),
+    (2, EccFamily::SecpR1, 384),
+    (3, EccFamily::SecpR1, 521),
+    (8, EccFamily::SecpK1, 256),
+];
+
+/// COSE signature algorithms, with their PSA algorithm
+const SIGNATURE_ALGORITHMS: [(i64, AsymmetricSignature); 9] = [
+    (-7, ecdsa(Hash::Sha256)),
+    (-35, ecdsa(Hash::Sha384)),
+    (-36, ecdsa(Hash::Sha512)),
+    (-37, rsa_pss(Hash::Sha256)),
+    (-38, rsa_pss(Hash::Sha384)),
+    (-39, rsa_pss(Hash::Sha512)),
+    (-257, rsa_pkcs1v15(Hash::Sha256)),
+    (-258, rsa_pkcs1v15(Hash::Sha384)),
+    (-259, rsa_pkcs1v15(Hash::Sha512)),
+];
+
+const fn ecdsa(hash: Hash) -> AsymmetricSignature {
+    AsymmetricSignature::Ecdsa {
+        hash_alg: SignHash::Specific(hash),
+    }
+}
+
+const fn rsa_pss(hash: Hash) -> AsymmetricSignature {
+    AsymmetricSignature::RsaPss {
+        hash_alg: SignHash::Specific(hash),
+    }
+}
+
+const fn rsa_pkcs1v15(hash: Hash) -> AsymmetricSignature {
+    AsymmetricSignature::RsaPkcs1v15Sign {
+        hash_alg: SignHash::Specific(hash),
+    }
+}
+
+/// COSE curve of the PSA curve, if it has one
+pub fn curve_identifier(family: EccFamily, bits: usize) -> Option<i64> {
+    CURVES
+        .iter()
+        .find(|(_, curve_family, curve_bits)| *curve_family == family && *curve_bits == bits)
+        .map(|(curve, ..)| *curve)
+}
+
+/// PSA family and size of the COSE curve
+#[cfg(feature = "cbor-bodies")]
+pub fn curve(curve: i64) -> Option<(EccFamily, usize)> {
+    CURVES
+        .iter()
+        .find(|(identifier, ..)| *identifier == curve)
+        .map(|(_, family, bits)| (*family, *bits))
+}
+
+/// COSE algorithm of the PSA signature algorithm, if it has one
+pub fn signature_algorithm_identifier(alg: AsymmetricSignature) -> Option<i64> {
+    SIGNATURE_ALGORITHMS
+        .iter()
+        .find(|(_, signature_alg)| *signature_alg == alg)
+        .map(|(identifier, _)| *identifier)
+}
+
+/// PSA signature algorithm of the COSE algorithm
+#[cfg(feature = "cbor-bodies")]
+pub fn signature_algorithm(alg: i64) -> Option<AsymmetricSignature> {
+    SIGNATURE_ALGORITHMS
+        .iter()
+        .find(|(identifier, _)| *identifier == alg)
+        .map(|(_, signature_alg)| *signature_alg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn curve_identifiers() {
+        assert_eq!(curve_identifier(EccFamily::SecpR1, 384), Some(2));
+        assert_eq!(curve_identifier(EccFamily::SecpK1, 256), Some(8));
+        assert_eq!(curve_identifier(EccFamily::BrainpoolPR1, 256), None);
+        assert_eq!(curve_identifier(EccFamily::SecpR1, 192), None);
+    }
+
+    #[test]
+    fn signature_algorithm_identifiers() {
+        assert_eq!(
+            signature_algorithm_identifier(ecdsa(Hash::Sha256)),
+            Some(-7)
+        );
+        assert_eq!(
+            signature_algorithm_identifier(rsa_pkcs1v15(Hash::Sha512)),
+            Some(-259)
+        );
+        let any_hash = AsymmetricSignature::RsaPss {
+            hash_alg: SignHash::Any,
+        };
+        assert_eq!(signature_algorithm_identifier(any_hash), None);
+    }
+
+    #[cfg(feature = "cbor-bodies")]
+    #[test]
+    fn identifiers_round_trip() {
+        for (identifier, family, bits) in &CURVES {
+            assert_eq!(curve(*identifier), Some((*family, *bits)));
+        }
+        for (identifier, alg) in &SIGNATURE_ALGORITHMS {
+            assert_eq!(signature_algorithm(*identifier), Some(*alg));
+        }
+        assert_eq!(curve(4), None);
+        assert_eq!(signature_algorithm(-8), None);
+    }
+}
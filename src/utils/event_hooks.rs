@@ -0,0 +1,792 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Hooks called on the events of the service
+//!
+//! External inventory or SIEM systems can follow the keys created, destroyed and attested, and the
+//! failed authentications, without polling the service. Each hook is a program executed with the
+//! event in its environment, or an HTTP endpoint receiving it as a JSON object in a POST request.
+//!
+//! Events are put in a bounded queue and delivered in order by a worker thread, so that slow or
+//! unavailable hooks do not delay the requests. Events arriving while the queue is full are dropped
+//! and logged. Programs are passed the following environment variables, the ones which do not apply
+//! to the event being unset:
+//! * `PARSEC_EVENT`: the event, for example `KeyCreated`
+//! * `PARSEC_EVENT_TIME`: the time of the event, in seconds since the Unix epoch
+//! * `PARSEC_APPLICATION`: the name of the application
+//! * `PARSEC_KEY_NAME`: the name of the key
+//! * `PARSEC_PROVIDER`: the provider of the key, for example `Tpm`
+//! * `PARSEC_PEER`: the peer which failed to authenticate, for example `UID 1000`
+//!
+//! In-process components, like the D-Bus interface, can also subscribe to all the events.
+//!
+//! Webhooks receive the same values, in the `event`, `time`, `application`, `key_name`,
+//! `provider` and `peer` fields. Only plain HTTP is supported, so webhooks are limited to the
+//! loopback interface and to Unix sockets: names of applications and keys do not leave the host
+//! unencrypted. Endpoints on other hosts, or behind TLS, are reached through a local relay. The
+//! address of a webhook is resolved once, when the service starts, so that a slow name server
+//! does not stall the delivery of the events.
+use super::config::{EventHookConfig, EventHooksConfig, HookEvent};
+use super::logging::json_string;
+use log::{error, warn};
+use parsec_interface::requests::ProviderId;
+use std::fmt::Write as _;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default number of events waiting to be delivered
+const DEFAULT_QUEUE_SIZE: usize = 256;
+/// Queues of the subscribers to the events
+static SUBSCRIBERS: Mutex<Vec<SyncSender<Event>>> = Mutex::new(Vec::new());
+/// Default time given to a hook to handle an event (in seconds)
+const DEFAULT_TIMEOUT: u64 = 5;
+
+/// Event reported to the hooks
+#[derive(Debug, Clone)]
+pub struct Event {
+    kind: HookEvent,
+    time: u64,
+    application: Option<String>,
+    key_name: Option<String>,
+    provider: Option<ProviderId>,
+    peer: Option<String>,
+}
+
+impl Event {
+    /// Event on a key of an application
+    pub fn key(kind: HookEvent, application: &str, key_name: &str, provider: ProviderId) -> Self {
+        Event {
+            application: Some(application.to_string()),
+            key_name: Some(key_name.to_string()),
+            provider: Some(provider),
+            ..Event::new(kind)
+        }
+    }
+
+    /// Failed authentication of a peer
+    pub fn authentication_failed(peer: String) -> Self {
+        Event {
+            peer: Some(peer),
+            ..Event::new(HookEvent::AuthenticationFailed)
+        }
+    }
+
+    /// The event
+    pub fn kind(&self) -> HookEvent {
+        self.kind
+    }
+
+    /// Name of the application owning the key
+    pub fn application(&self) -> Option<&str> {
+        self.application.as_deref()
+    }
+
+    /// Name of the key
+    pub fn key_name(&self) -> Option<&str> {
+        self.key_name.as_deref()
+    }
+
+    /// Provider of the key
+    pub fn provider(&self) -> Option<ProviderId> {
+        self.provider
+    }
+
+    /// Peer which failed to authenticate
+    pub fn peer(&self) -> Option<&str> {
+        self.peer.as_deref()
+    }
+
+    fn new(kind: HookEvent) -> Self {
+        Event {
+            kind,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            application: None,
+            key_name: None,
+            provider: None,
+            peer: None,
+        }
+    }
+
+    // Names and values of the fields set
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("event", format!("{:?}", self.kind)),
+            ("time", self.time.to_string()),
+        ];
+        let optional = vec![
+            ("application", self.application.clone()),
+            ("key_name", self.key_name.clone()),
+            (
+                "provider",
+                self.provider.map(|provider| format!("{:?}", provider)),
+            ),
+            ("peer", self.peer.clone()),
+        ];
+        fields.extend(
+            optional
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|value| (name, value))),
+        );
+        fields
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        for (index, (name, value)) in self.fields().into_iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            if name == "time" {
+                let _ = write!(json, "\"{}\":{}", name, value);
+            } else {
+                let _ = write!(json, "\"{}\":{}", name, json_string(&value));
+            }
+        }
+        json.push('}');
+        json
+    }
+}
+
+#[derive(Debug)]
+enum Endpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+#[derive(Debug)]
+enum Target {
+    Exec {
+        program: String,
+        args: Vec<String>,
+    },
+    Webhook {
+        endpoint: Endpoint,
+        // Value of the Host header
+        host: String,
+        path: String,
+    },
+}
+
+#[derive(Debug)]
+struct Hook {
+    target: Target,
+    // All the events if not given
+    events: Option<Vec<HookEvent>>,
+}
+
+impl Hook {
+    // Webhooks outside of the host are ignored.
+    fn new(config: &EventHookConfig) -> std::io::Result<Option<Self>> {
+        let (target, events) = match config {
+            EventHookConfig::Exec {
+                program,
+                args,
+                events,
+            } => (
+                Target::Exec {
+                    program: program.clone(),
+                    args: args.clone().unwrap_or_default(),
+                },
+                events,
+            ),
+            EventHookConfig::Webhook { url, events } => {
+                if let Some(socket_path) = url.strip_prefix("unix:") {
+                    let target = Target::Webhook {
+                        endpoint: Endpoint::Unix(PathBuf::from(socket_path)),
+                        host: String::from("localhost"),
+                        path: String::from("/"),
+                    };
+                    return Ok(Some(Hook {
+                        target,
+                        events: events.clone(),
+                    }));
+                }
+                let address = url.strip_prefix("http://").ok_or_else(|| {
+                    error!("The webhook {} is not a plain HTTP URL.", url);
+                    Error::new(ErrorKind::InvalidData, "webhook URL not supported")
+                })?;
+                let (authority, path) = match address.find('/') {
+                    Some(index) => address.split_at(index),
+                    None => (address, "/"),
+                };
+                if authority.is_empty() {
+                    error!("The webhook {} has no host.", url);
+                    return Err(Error::new(ErrorKind::InvalidData, "invalid webhook URL"));
+                }
+                let address = resolve(authority).map_err(|e| {
+                    error!(
+                        "The host of the webhook {} could not be resolved: {}",
+                        url, e
+                    );
+                    e
+                })?;
+                if !address.ip().is_loopback() {
+                    warn!("The webhook {} is not on the loopback interface and is ignored, the events would leave the host unencrypted. Use a local relay instead.", url);
+                    return Ok(None);
+                }
+                (
+                    Target::Webhook {
+                        endpoint: Endpoint::Tcp(address),
+                        host: authority.to_string(),
+                        path: path.to_string(),
+                    },
+                    events,
+                )
+            }
+        };
+        Ok(Some(Hook {
+            target,
+            events: events.clone(),
+        }))
+    }
+
+    fn wants(&self, kind: HookEvent) -> bool {
+        self.events
+            .as_ref()
+            .map_or(true, |events| events.contains(&kind))
+    }
+
+    fn call(&self, event: &Event, timeout: Duration) -> std::io::Result<()> {
+        match &self.target {
+            Target::Exec { program, args } => exec(program, args, event, timeout),
+            Target::Webhook {
+                endpoint,
+                host,
+                path,
+            } => post(endpoint, host, path, event, timeout),
+        }
+    }
+}
+
+fn exec(program: &str, args: &[String], event: &Event, timeout: Duration) -> std::io::Result<()> {
+    let mut command = Command::new(program);
+    let _ = command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null());
+    for (name, value) in event.fields() {
+        let name = match name {
+            "time" => String::from("PARSEC_EVENT_TIME"),
+            name => format!("PARSEC_{}", name.to_uppercase()),
+        };
+        let _ = command.env(name, value);
+    }
+    let mut child = command.spawn()?;
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return if status.success() {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::Other,
+                    format!("exited with {}", status),
+                ))
+            };
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::new(ErrorKind::TimedOut, "killed after the timeout"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Address of the host and port of a URL, port 80 by default. IPv6 addresses are in brackets.
+fn resolve(authority: &str) -> std::io::Result<SocketAddr> {
+    if authority.rfind(':') > authority.rfind(']') {
+        authority.to_socket_addrs()?.next()
+    } else {
+        let host = authority.trim_start_matches('[').trim_end_matches(']');
+        (host, 80).to_socket_addrs()?.next()
+    }
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "host not found"))
+}
+
+fn post(
+    endpoint: &Endpoint,
+    host: &str,
+    path: &str,
+    event: &Event,
+    timeout: Duration,
+) -> std::io::Result<()> {
+    match endpoint {
+        Endpoint::Tcp(address) => {
+            let stream = TcpStream::connect_timeout(address, timeout)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            exchange(stream, host, path, event)
+        }
+        Endpoint::Unix(socket_path) => {
+            let stream = UnixStream::connect(socket_path)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            exchange(stream, host, path, event)
+        }
+    }
+}
+
+// Post the event on the connection and check the status of the response
+fn exchange(
+    mut stream: impl Read + Write,
+    host: &str,
+    path: &str,
+    event: &Event,
+) -> std::io::Result<()> {
+    let body = event.to_json();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+
+    // Only the status line is read: "HTTP/1.1 200 OK"
+    let mut response = Vec::new();
+    let _ = stream.take(64).read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.split(' ').nth(1).unwrap_or_default();
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Other,
+            format!("answered {:?}", response.lines().next().unwrap_or_default()),
+        ))
+    }
+}
+
+/// Queue of the events delivered to the hooks
+#[derive(Debug, Clone)]
+pub struct EventHooks {
+    sender: SyncSender<Event>,
+    events: Vec<HookEvent>,
+}
+
+impl EventHooks {
+    /// Create the hooks from their configuration and start the thread delivering the events.
+    pub fn new(config: &EventHooksConfig) -> std::io::Result<Self> {
+        let hooks = config
+            .hook
+            .iter()
+            .filter_map(|hook| Hook::new(hook).transpose())
+            .collect::<std::io::Result<Vec<Hook>>>()?;
+        let queue_size = config.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE);
+        if queue_size == 0 {
+            error!("The queue of the event hooks can not be empty.");
+            return Err(Error::new(ErrorKind::InvalidData, "empty event queue"));
+        }
+        let timeout = Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT));
+        let events = [
+            HookEvent::KeyCreated,
+            HookEvent::KeyDestroyed,
+            HookEvent::KeyAttested,
+            HookEvent::AuthenticationFailed,
+        ]
+        .iter()
+        .copied()
+        .filter(|kind| hooks.iter().any(|hook| hook.wants(*kind)))
+        .collect();
+
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let _ = thread::Builder::new()
+            .name(String::from("event-hooks"))
+            .spawn(move || deliver(hooks, receiver, timeout))?;
+        Ok(EventHooks { sender, events })
+    }
+
+    /// Queue an event for the hooks which want it. It is dropped if the queue is full.
+    pub fn notify(&self, event: Event) {
+        publish(&event);
+        if !self.events.contains(&event.kind) {
+            return;
+        }
+        match self.sender.try_send(event) {
+            Ok(()) => (),
+            Err(TrySendError::Full(event)) => {
+                warn!(
+                    "The queue of the event hooks is full, the {:?} event is dropped.",
+                    event.kind
+                )
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("The thread delivering the events to the hooks stopped.")
+            }
+        }
+    }
+}
+
+/// Receive all the events reported from now on. They are dropped while the queue of the subscriber
+/// is full.
+pub fn subscribe() -> Receiver<Event> {
+    let (sender, receiver) = mpsc::sync_channel(DEFAULT_QUEUE_SIZE);
+    SUBSCRIBERS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(sender);
+    receiver
+}
+
+// Subscribers which dropped their receiver are removed.
+fn publish(event: &Event) {
+    let mut subscribers = SUBSCRIBERS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    subscribers.retain(|subscriber| match subscriber.try_send(event.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            warn!(
+                "The queue of a subscriber to the events is full, the {:?} event is dropped.",
+                event.kind
+            );
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+}
+
+// Runs until every sender is dropped
+fn deliver(hooks: Vec<Hook>, receiver: Receiver<Event>, timeout: Duration) {
+    for event in receiver {
+        for hook in hooks.iter().filter(|hook| hook.wants(event.kind)) {
+            if let Err(e) = hook.call(&event, timeout) {
+                warn!(
+                    "The event hook {:?} failed on the {:?} event: {}",
+                    hook.target, event.kind, e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixListener;
+
+    fn key_created() -> Event {
+        Event::key(HookEvent::KeyCreated, "app \"1\"", "key", ProviderId::Tpm)
+    }
+
+    fn hooks(hook: EventHookConfig) -> EventHooks {
+        EventHooks::new(&EventHooksConfig {
+            queue_size: None,
+            timeout: None,
+            hook: vec![hook],
+        })
+        .unwrap()
+    }
+
+    fn webhook(url: &str) -> std::io::Result<Option<Hook>> {
+        Hook::new(&EventHookConfig::Webhook {
+            url: String::from(url),
+            events: None,
+        })
+    }
+
+    fn exec(script: &str, timeout: Duration) -> std::io::Result<()> {
+        let hook = Hook::new(&EventHookConfig::Exec {
+            program: String::from("sh"),
+            args: Some(vec![String::from("-c"), String::from(script)]),
+            events: None,
+        })
+        .unwrap()
+        .unwrap();
+        hook.call(&key_created(), timeout)
+    }
+
+    /// Read the request posted on the connection and answer it with the given status line
+    fn answer(mut stream: impl Read + Write, status: &str) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&request).ends_with('}') {
+            let length = stream.read(&mut buffer).unwrap();
+            request.extend_from_slice(&buffer[..length]);
+        }
+        write!(stream, "{}\r\n\r\n", status).unwrap();
+        String::from_utf8(request).unwrap()
+    }
+
+    #[test]
+    fn key_events_have_the_key_fields() {
+        let names: Vec<&str> = key_created()
+            .fields()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            ["event", "time", "application", "key_name", "provider"]
+        );
+    }
+
+    #[test]
+    fn authentication_failures_have_the_peer() {
+        let event = Event::authentication_failed(String::from("UID 1000"));
+        assert_eq!(event.kind(), HookEvent::AuthenticationFailed);
+        assert_eq!(event.peer(), Some("UID 1000"));
+        assert_eq!(event.application(), None);
+        let fields = event.fields();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[2], ("peer", String::from("UID 1000")));
+    }
+
+    #[test]
+    fn events_are_encoded_in_json() {
+        let mut event = key_created();
+        event.time = 1700000000;
+        assert_eq!(
+            event.to_json(),
+            "{\"event\":\"KeyCreated\",\"time\":1700000000,\"application\":\"app \\\"1\\\"\",\
+             \"key_name\":\"key\",\"provider\":\"Tpm\"}"
+        );
+    }
+
+    #[test]
+    fn webhook_urls_are_split() {
+        let hook = webhook("http://127.0.0.1:8080/parsec/events")
+            .unwrap()
+            .unwrap();
+        match hook.target {
+            Target::Webhook {
+                endpoint: Endpoint::Tcp(address),
+                host,
+                path,
+            } => {
+                assert_eq!(address, SocketAddr::from(([127, 0, 0, 1], 8080)));
+                assert_eq!(host, "127.0.0.1:8080");
+                assert_eq!(path, "/parsec/events");
+            }
+            target => panic!("unexpected target {:?}", target),
+        }
+        match webhook("http://[::1]").unwrap().unwrap().target {
+            Target::Webhook {
+                endpoint: Endpoint::Tcp(address),
+                path,
+                ..
+            } => {
+                assert_eq!(address.port(), 80);
+                assert_eq!(path, "/");
+            }
+            target => panic!("unexpected target {:?}", target),
+        }
+        match webhook("unix:/run/parsec/relay.sock")
+            .unwrap()
+            .unwrap()
+            .target
+        {
+            Target::Webhook {
+                endpoint: Endpoint::Unix(socket_path),
+                path,
+                ..
+            } => {
+                assert_eq!(socket_path, PathBuf::from("/run/parsec/relay.sock"));
+                assert_eq!(path, "/");
+            }
+            target => panic!("unexpected target {:?}", target),
+        }
+    }
+
+    #[test]
+    fn webhooks_outside_of_the_host_are_ignored() {
+        assert!(webhook("http://192.0.2.1:8080/events").unwrap().is_none());
+        let hooks = hooks(EventHookConfig::Webhook {
+            url: String::from("http://192.0.2.1/events"),
+            events: None,
+        });
+        assert!(hooks.events.is_empty());
+    }
+
+    #[test]
+    fn only_plain_http_webhooks_are_accepted() {
+        assert_eq!(
+            webhook("https://127.0.0.1/events").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            webhook("http:///events").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn hooks_want_the_events_configured() {
+        let hook = Hook::new(&EventHookConfig::Webhook {
+            url: String::from("http://127.0.0.1/events"),
+            events: Some(vec![HookEvent::KeyDestroyed]),
+        })
+        .unwrap()
+        .unwrap();
+        assert!(hook.wants(HookEvent::KeyDestroyed));
+        assert!(!hook.wants(HookEvent::KeyCreated));
+        let hook = webhook("http://127.0.0.1/events").unwrap().unwrap();
+        assert!(hook.wants(HookEvent::KeyCreated));
+        assert!(hook.wants(HookEvent::AuthenticationFailed));
+    }
+
+    #[test]
+    fn empty_queue_is_refused() {
+        assert_eq!(
+            EventHooks::new(&EventHooksConfig {
+                queue_size: Some(0),
+                timeout: None,
+                hook: Vec::new(),
+            })
+            .unwrap_err()
+            .kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn programs_get_the_event_in_their_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("env");
+        exec(
+            &format!("env > {}", output.display()),
+            Duration::from_secs(DEFAULT_TIMEOUT),
+        )
+        .unwrap();
+        let env = fs::read_to_string(output).unwrap();
+        let lines: Vec<&str> = env.lines().collect();
+        for variable in &[
+            "PARSEC_EVENT=KeyCreated",
+            "PARSEC_APPLICATION=app \"1\"",
+            "PARSEC_KEY_NAME=key",
+            "PARSEC_PROVIDER=Tpm",
+        ] {
+            assert!(lines.contains(variable), "{} missing", variable);
+        }
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("PARSEC_EVENT_TIME=")));
+        assert!(!env.contains("PARSEC_PEER="));
+    }
+
+    #[test]
+    fn failing_programs_are_reported() {
+        assert_eq!(
+            exec("exit 3", Duration::from_secs(DEFAULT_TIMEOUT))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn slow_programs_are_killed() {
+        let start = Instant::now();
+        assert_eq!(
+            exec("sleep 10", Duration::from_millis(100))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::TimedOut
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn events_are_posted_to_webhooks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hooks = hooks(EventHookConfig::Webhook {
+            url: format!("http://{}/events", listener.local_addr().unwrap()),
+            events: None,
+        });
+        hooks.notify(key_created());
+
+        let request = answer(listener.accept().unwrap().0, "HTTP/1.1 204 No Content");
+        assert!(request.starts_with("POST /events HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nContent-Type: application/json\r\n"));
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        assert!(body.starts_with("{\"event\":\"KeyCreated\",\"time\":"));
+        assert!(body.ends_with(
+            ",\"application\":\"app \\\"1\\\"\",\"key_name\":\"key\",\"provider\":\"Tpm\"}"
+        ));
+    }
+
+    #[test]
+    fn events_are_posted_to_unix_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("relay.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let hooks = hooks(EventHookConfig::Webhook {
+            url: format!("unix:{}", socket_path.display()),
+            events: None,
+        });
+        hooks.notify(key_created());
+
+        let request = answer(listener.accept().unwrap().0, "HTTP/1.1 200 OK");
+        assert!(request.starts_with("POST / HTTP/1.1\r\nHost: localhost\r\n"));
+        assert!(request.contains("{\"event\":\"KeyCreated\""));
+    }
+
+    #[test]
+    fn events_not_wanted_are_not_delivered() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hooks = hooks(EventHookConfig::Webhook {
+            url: format!("http://{}/events", listener.local_addr().unwrap()),
+            events: Some(vec![HookEvent::KeyCreated]),
+        });
+        hooks.notify(Event::authentication_failed(String::from("UID 1000")));
+        hooks.notify(key_created());
+
+        // The events are delivered in order: the first one was not sent.
+        let request = answer(listener.accept().unwrap().0, "HTTP/1.1 204 No Content");
+        assert!(request.contains("{\"event\":\"KeyCreated\""));
+    }
+
+    #[test]
+    fn webhook_errors_are_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            answer(listener.accept().unwrap().0, "HTTP/1.1 500 Internal Error")
+        });
+        let error = post(
+            &Endpoint::Tcp(address),
+            &address.to_string(),
+            "/events",
+            &key_created(),
+            Duration::from_secs(DEFAULT_TIMEOUT),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Other);
+        let _ = server.join().unwrap();
+    }
+
+    #[test]
+    fn subscribers_receive_all_the_events() {
+        let receiver = subscribe();
+        let hooks = hooks(EventHookConfig::Webhook {
+            url: String::from("http://127.0.0.1:9/events"),
+            events: Some(Vec::new()),
+        });
+        // Events of the other tests are published too.
+        let key_name = "subscribers_receive_all_the_events";
+        hooks.notify(Event::key(
+            HookEvent::KeyDestroyed,
+            "app",
+            key_name,
+            ProviderId::Tpm,
+        ));
+        let event = receiver
+            .iter()
+            .find(|event| event.key_name() == Some(key_name))
+            .unwrap();
+        assert_eq!(event.kind(), HookEvent::KeyDestroyed);
+        assert_eq!(event.provider(), Some(ProviderId::Tpm));
+    }
+}
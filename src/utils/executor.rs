@@ -0,0 +1,479 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Work-stealing executor handling the connections
+//!
+//! Each worker has its own queue, the connections accepted being spread over them in turn. A
+//! worker takes the oldest connection of its queue and, when it is empty, steals the newest one of
+//! the other queues, so that no connection waits behind a slow request while a worker is free.
+//!
+//! The number of workers follows the observed profile of the requests. A request mostly waiting
+//! on hardware (a TPM command, a PKCS#11 token) leaves its core free for other requests: with `w`
+//! the average time taken by a request and `c` the CPU time it uses, `cores × w / c` workers keep
+//! all the cores busy. A worker is added when a connection is queued while none is idle and there
+//! are fewer workers than that, within the configured bounds. A worker idle for some time leaves
+//! while there are more than the minimum, so that small devices do not keep threads they do not
+//! need.
+use derivative::Derivative;
+use log::error;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time after which an idle worker leaves, if there are more than the minimum
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+/// Weight of the last request in the averages of the profile
+const SMOOTHING: f64 = 0.1;
+
+type Task = Box<dyn FnOnce() + Send + 'static>;
+
+/// Averages of the time taken by the requests and of the CPU time they use, in seconds
+#[derive(Debug)]
+struct Profile {
+    wall_time: f64,
+    cpu_time: f64,
+}
+
+#[derive(Debug)]
+struct Counters {
+    // Queues which have a worker, by index
+    workers: Vec<bool>,
+    live: usize,
+    idle: usize,
+    active: usize,
+    queued: usize,
+    shutdown: bool,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct Shared {
+    min_threads: usize,
+    max_threads: usize,
+    cores: usize,
+    keep_alive: Duration,
+    // One queue per possible worker. The counters are locked before a queue when both are.
+    #[derivative(Debug = "ignore")]
+    queues: Vec<Mutex<VecDeque<Task>>>,
+    counters: Mutex<Counters>,
+    work: Condvar,
+    done: Condvar,
+    profile: Mutex<Profile>,
+}
+
+/// Pool of workers handling the connections
+#[derive(Debug)]
+pub struct Executor {
+    shared: Arc<Shared>,
+    next_queue: AtomicUsize,
+}
+
+fn thread_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: the pointer is to a valid timespec structure.
+    let _ = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+impl Shared {
+    fn lock_counters(&self) -> MutexGuard<'_, Counters> {
+        self.counters.lock().expect("Executor lock poisoned")
+    }
+
+    /// Number of workers the profile of the requests calls for.
+    fn target_threads(&self) -> usize {
+        let profile = self.profile.lock().expect("Executor profile lock poisoned");
+        let wait_ratio = profile.wall_time / profile.cpu_time.max(f64::EPSILON);
+        let target = (self.cores as f64 * wait_ratio.max(1.0)).ceil() as usize;
+        target.clamp(self.min_threads, self.max_threads)
+    }
+
+    fn record(&self, wall_time: Duration, cpu_time: Duration) {
+        let mut profile = self.profile.lock().expect("Executor profile lock poisoned");
+        profile.wall_time += SMOOTHING * (wall_time.as_secs_f64() - profile.wall_time);
+        profile.cpu_time += SMOOTHING * (cpu_time.as_secs_f64() - profile.cpu_time);
+    }
+
+    /// Oldest task of a worker's queue, or else the newest one of another queue.
+    fn find_task(&self, index: usize) -> Option<Task> {
+        if let Some(task) = self.queues[index]
+            .lock()
+            .expect("Executor queue lock poisoned")
+            .pop_front()
+        {
+            return Some(task);
+        }
+        (1..self.queues.len()).find_map(|offset| {
+            self.queues[(index + offset) % self.queues.len()]
+                .lock()
+                .expect("Executor queue lock poisoned")
+                .pop_back()
+        })
+    }
+}
+
+fn spawn_worker(shared: &Arc<Shared>, counters: &mut Counters) {
+    let index = match counters.workers.iter().position(|worker| !worker) {
+        Some(index) => index,
+        None => return,
+    };
+    let worker_shared = shared.clone();
+    match thread::Builder::new()
+        .name(String::from("parsec-worker"))
+        .spawn(move || work(worker_shared, index))
+    {
+        Ok(_) => {
+            counters.workers[index] = true;
+            counters.live += 1;
+        }
+        Err(e) => format_error!("Failed to start a worker", e),
+    }
+}
+
+fn work(shared: Arc<Shared>, index: usize) {
+    loop {
+        if let Some(task) = shared.find_task(index) {
+            {
+                let mut counters = shared.lock_counters();
+                counters.queued -= 1;
+                counters.active += 1;
+            }
+            let started = Instant::now();
+            let cpu_started = thread_cpu_time();
+            if panic::catch_unwind(AssertUnwindSafe(task)).is_err() {
+                error!("A worker panicked while handling a connection.");
+            }
+            shared.record(started.elapsed(), thread_cpu_time() - cpu_started);
+            let mut counters = shared.lock_counters();
+            counters.active -= 1;
+            if counters.active == 0 && counters.queued == 0 {
+                shared.done.notify_all();
+            }
+            continue;
+        }
+
+        let mut counters = shared.lock_counters();
+        // A task queued since the queues were looked at, or taken by another worker which did
+        // not count it yet.
+        if counters.queued > 0 {
+            drop(counters);
+            thread::yield_now();
+            continue;
+        }
+        if counters.shutdown {
+            counters.workers[index] = false;
+            counters.live -= 1;
+            return;
+        }
+        counters.idle += 1;
+        let (mut counters, timeout) = shared
+            .work
+            .wait_timeout(counters, shared.keep_alive)
+            .expect("Executor lock poisoned");
+        counters.idle -= 1;
+        // Counted out under the lock, so that idle workers do not all leave at once.
+        if timeout.timed_out() && counters.queued == 0 && counters.live > shared.min_threads {
+            counters.workers[index] = false;
+            counters.live -= 1;
+            return;
+        }
+    }
+}
+
+impl Executor {
+    /// Create an executor with between `min_threads` and `max_threads` workers, the minimum being
+    /// started at once.
+    pub fn new(min_threads: usize, max_threads: usize) -> std::io::Result<Self> {
+        Executor::with_keep_alive(min_threads, max_threads, KEEP_ALIVE)
+    }
+
+    fn with_keep_alive(
+        min_threads: usize,
+        max_threads: usize,
+        keep_alive: Duration,
+    ) -> std::io::Result<Self> {
+        if max_threads == 0 || min_threads > max_threads {
+            error!(
+                "Invalid thread pool bounds: at least {} and at most {} threads.",
+                min_threads, max_threads
+            );
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid thread pool size",
+            ));
+        }
+        let shared = Arc::new(Shared {
+            min_threads,
+            max_threads,
+            cores: thread::available_parallelism().map_or(1, |cores| cores.get()),
+            keep_alive,
+            queues: (0..max_threads)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            counters: Mutex::new(Counters {
+                workers: vec![false; max_threads],
+                live: 0,
+                idle: 0,
+                active: 0,
+                queued: 0,
+                shutdown: false,
+            }),
+            work: Condvar::new(),
+            done: Condvar::new(),
+            // No request seen yet: as many workers as cores.
+            profile: Mutex::new(Profile {
+                wall_time: 0.0,
+                cpu_time: 0.0,
+            }),
+        });
+        {
+            let mut counters = shared.lock_counters();
+            for _ in 0..min_threads {
+                spawn_worker(&shared, &mut counters);
+            }
+        }
+        Ok(Executor {
+            shared,
+            next_queue: AtomicUsize::new(0),
+        })
+    }
+
+    /// Queue a task, adding a worker for it if none is idle and the profile calls for more.
+    pub fn execute(&self, task: impl FnOnce() + Send + 'static) {
+        let index = self.next_queue.fetch_add(1, Ordering::Relaxed) % self.shared.queues.len();
+        let mut counters = self.shared.lock_counters();
+        counters.queued += 1;
+        self.shared.queues[index]
+            .lock()
+            .expect("Executor queue lock poisoned")
+            .push_back(Box::new(task));
+        // Idle workers count until they take a task: each one is woken up for a single task.
+        if counters.idle >= counters.queued {
+            self.shared.work.notify_one();
+        } else if counters.live < self.shared.target_threads() || counters.live == 0 {
+            spawn_worker(&self.shared, &mut counters);
+        }
+    }
+
+    /// Wait for all the queued tasks to be executed.
+    pub fn join(&self) {
+        let mut counters = self.shared.lock_counters();
+        while counters.active > 0 || counters.queued > 0 {
+            counters = self
+                .shared
+                .done
+                .wait(counters)
+                .expect("Executor lock poisoned");
+        }
+    }
+
+    /// Number of workers executing a task
+    pub fn active_count(&self) -> usize {
+        self.shared.lock_counters().active
+    }
+
+    /// Number of tasks waiting for a worker
+    pub fn queued_count(&self) -> usize {
+        self.shared.lock_counters().queued
+    }
+
+    /// Number of workers started
+    pub fn thread_count(&self) -> usize {
+        self.shared.lock_counters().live
+    }
+
+    /// Maximum number of workers
+    pub fn max_count(&self) -> usize {
+        self.shared.max_threads
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        // The workers leave once the queued tasks are executed.
+        self.shared.lock_counters().shutdown = true;
+        self.shared.work.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Time after which a test gives up waiting for the workers
+    const DEADLINE: Duration = Duration::from_secs(10);
+
+    fn set_profile(executor: &Executor, wall_time: f64, cpu_time: f64) {
+        *executor.shared.profile.lock().unwrap() = Profile {
+            wall_time,
+            cpu_time,
+        };
+    }
+
+    /// Queue tasks which only finish once all of them run at the same time, or after waiting for
+    /// the given time, returning whether they did.
+    fn run_together(executor: &Executor, tasks: usize, patience: Duration) -> bool {
+        let running = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..tasks {
+            let running = running.clone();
+            let sender = sender.clone();
+            executor.execute(move || {
+                let _ = running.fetch_add(1, Ordering::SeqCst);
+                let start = Instant::now();
+                while running.load(Ordering::SeqCst) < tasks && start.elapsed() < patience {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                sender
+                    .send(running.load(Ordering::SeqCst) == tasks)
+                    .unwrap();
+            });
+        }
+        executor.join();
+        let together: Vec<bool> = receiver.try_iter().collect();
+        together.len() == tasks && together.iter().all(|together| *together)
+    }
+
+    #[test]
+    fn invalid_bounds() {
+        assert!(Executor::new(2, 1).is_err());
+        assert!(Executor::new(0, 0).is_err());
+    }
+
+    #[test]
+    fn tasks_are_all_executed() {
+        let executor = Executor::new(1, 4).unwrap();
+        assert_eq!(executor.thread_count(), 1);
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..32 {
+            let sender = sender.clone();
+            executor.execute(move || sender.send(i).unwrap());
+        }
+        executor.join();
+        assert_eq!(executor.queued_count(), 0);
+        assert_eq!(executor.active_count(), 0);
+        let mut done: Vec<i32> = receiver.try_iter().collect();
+        done.sort_unstable();
+        assert_eq!(done, (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panicking_task_keeps_its_worker() {
+        let executor = Executor::new(1, 1).unwrap();
+        executor.execute(|| panic!("task failure"));
+        let (sender, receiver) = mpsc::channel();
+        executor.execute(move || sender.send(()).unwrap());
+        executor.join();
+        assert!(receiver.try_recv().is_ok());
+        assert_eq!(executor.thread_count(), 1);
+    }
+
+    #[test]
+    fn target_follows_profile() {
+        let executor = Executor::new(0, 64).unwrap();
+        let cores = executor.shared.cores;
+        // Requests using the CPU all the time they take: one worker per core.
+        set_profile(&executor, 0.002, 0.002);
+        assert_eq!(executor.shared.target_threads(), cores.min(64));
+        // Requests waiting nine tenths of the time: ten workers per core.
+        set_profile(&executor, 0.01, 0.001);
+        assert_eq!(executor.shared.target_threads(), (cores * 10).min(64));
+        // No CPU time measured
+        set_profile(&executor, 0.01, 0.0);
+        assert_eq!(executor.shared.target_threads(), 64);
+
+        let executor = Executor::new(3, 3).unwrap();
+        set_profile(&executor, 0.002, 0.002);
+        assert_eq!(executor.shared.target_threads(), 3);
+    }
+
+    #[test]
+    fn workers_are_added_for_waiting_requests() {
+        let executor = Executor::new(1, 8).unwrap();
+        set_profile(&executor, 1.0, 0.0);
+        assert!(run_together(&executor, 8, DEADLINE));
+        assert_eq!(executor.thread_count(), 8);
+    }
+
+    #[test]
+    fn idle_workers_take_one_task_each() {
+        let executor = Executor::new(1, 8).unwrap();
+        set_profile(&executor, 1.0, 0.0);
+        let start = Instant::now();
+        while executor.shared.lock_counters().idle == 0 && start.elapsed() < DEADLINE {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // All the tasks are queued before the idle worker wakes up.
+        assert!(run_together(&executor, 8, DEADLINE));
+    }
+
+    #[test]
+    fn workers_stay_within_maximum() {
+        let executor = Executor::new(1, 2).unwrap();
+        set_profile(&executor, 1.0, 0.0);
+        // Shorter than the time after which the idle workers leave
+        assert!(!run_together(&executor, 3, Duration::from_millis(200)));
+        assert_eq!(executor.thread_count(), 2);
+    }
+
+    #[test]
+    fn idle_workers_leave_down_to_minimum() {
+        let executor = Executor::with_keep_alive(2, 4, Duration::from_millis(20)).unwrap();
+        set_profile(&executor, 1.0, 0.0);
+        assert!(run_together(&executor, 4, DEADLINE));
+        assert_eq!(executor.thread_count(), 4);
+        let start = Instant::now();
+        while executor.thread_count() > 2 && start.elapsed() < DEADLINE {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(executor.thread_count(), 2);
+        // The minimum stays, however long it is idle.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(executor.thread_count(), 2);
+    }
+
+    #[test]
+    fn oldest_own_task_then_newest_other_task() {
+        let executor = Executor::new(0, 2).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        for (queue, task) in &[(0, "own 1"), (0, "own 2"), (1, "other 1"), (1, "other 2")] {
+            let sender = sender.clone();
+            let task = *task;
+            executor.shared.queues[*queue]
+                .lock()
+                .unwrap()
+                .push_back(Box::new(move || sender.send(task).unwrap()));
+        }
+        let mut order = Vec::new();
+        while let Some(task) = executor.shared.find_task(0) {
+            task();
+            order.push(receiver.try_recv().unwrap());
+        }
+        assert_eq!(order, ["own 1", "own 2", "other 2", "other 1"]);
+    }
+
+    #[test]
+    fn free_worker_steals_from_busy_one() {
+        let executor = Executor::new(2, 2).unwrap();
+        let (release, released) = mpsc::channel::<()>();
+        let (done, finished) = mpsc::channel();
+        // Queued in turn on both queues: the third task is behind the slow one, on the first
+        // queue, whichever worker took it.
+        executor.execute(move || {
+            let _ = released.recv_timeout(DEADLINE);
+        });
+        executor.execute(|| ());
+        executor.execute(move || done.send(()).unwrap());
+        assert!(finished.recv_timeout(DEADLINE).is_ok());
+        release.send(()).unwrap();
+        executor.join();
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Runtime fault injection
+//!
+//! When enabled, a configurable percentage of requests fail with a provider error, take longer to
+//! complete, or see their Key Info Manager writes rejected. This is meant to help validate the
+//! retry logic of clients and the way the service degrades; it must never be enabled in a
+//! production deployment.
+use log::warn;
+use parsec_interface::requests::{ResponseStatus, Result};
+use rand::Rng;
+use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8};
+use std::thread;
+use std::time::Duration;
+
+/// Status returned for injected provider errors
+const PROVIDER_ERROR_STATUS: ResponseStatus = ResponseStatus::PsaErrorCommunicationFailure;
+
+/// Fault injection settings shared by the whole service
+#[derive(Debug)]
+pub struct FaultInjection {
+    enabled: AtomicBool,
+    provider_error_percentage: AtomicU8,
+    kim_write_failure_percentage: AtomicU8,
+    slow_response_percentage: AtomicU8,
+    slow_response_delay: AtomicU64,
+}
+
+impl FaultInjection {
+    const fn new() -> Self {
+        FaultInjection {
+            enabled: AtomicBool::new(false),
+            provider_error_percentage: AtomicU8::new(0),
+            kim_write_failure_percentage: AtomicU8::new(0),
+            slow_response_percentage: AtomicU8::new(0),
+            slow_response_delay: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns `true` with the given percentage of probability, if fault injection is enabled.
+    fn roll(&self, percentage: &AtomicU8) -> bool {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return false;
+        }
+        let percentage = percentage.load(Ordering::Relaxed);
+        percentage > 0 && rand::thread_rng().gen_range(0..100) < percentage
+    }
+
+    /// Called before an operation is handed to a provider. Might delay the request and might
+    /// fail it with a provider error.
+    pub fn before_provider_operation() -> Result<()> {
+        FAULT_INJECTION.provider_operation()
+    }
+
+    /// Called before the Key Info Manager is modified. Might fail the write.
+    pub fn before_key_info_write() -> Result<()> {
+        FAULT_INJECTION.key_info_write()
+    }
+
+    fn provider_operation(&self) -> Result<()> {
+        if self.roll(&self.slow_response_percentage) {
+            let delay = self.slow_response_delay.load(Ordering::Relaxed);
+            warn!("Fault injection: delaying the request by {} ms.", delay);
+            thread::sleep(Duration::from_millis(delay));
+        }
+        if self.roll(&self.provider_error_percentage) {
+            warn!(
+                "Fault injection: failing the request with {}.",
+                PROVIDER_ERROR_STATUS
+            );
+            return Err(PROVIDER_ERROR_STATUS);
+        }
+        Ok(())
+    }
+
+    fn key_info_write(&self) -> Result<()> {
+        if self.roll(&self.kim_write_failure_percentage) {
+            warn!("Fault injection: failing the Key Info Manager write.");
+            return Err(ResponseStatus::KeyInfoManagerError);
+        }
+        Ok(())
+    }
+}
+
+static FAULT_INJECTION: FaultInjection = FaultInjection::new();
+
+pub(super) struct FaultInjectionBuilder {
+    provider_error_percentage: u8,
+    kim_write_failure_percentage: u8,
+    slow_response_percentage: u8,
+    slow_response_delay: u64,
+}
+
+impl FaultInjectionBuilder {
+    pub fn new() -> Self {
+        FaultInjectionBuilder {
+            provider_error_percentage: 0,
+            kim_write_failure_percentage: 0,
+            slow_response_percentage: 0,
+            slow_response_delay: 0,
+        }
+    }
+
+    pub fn with_provider_error_percentage(mut self, percentage: u8) -> Self {
+        self.provider_error_percentage = percentage;
+
+        self
+    }
+
+    pub fn with_kim_write_failure_percentage(mut self, percentage: u8) -> Self {
+        self.kim_write_failure_percentage = percentage;
+
+        self
+    }
+
+    pub fn with_slow_response_percentage(mut self, percentage: u8) -> Self {
+        self.slow_response_percentage = percentage;
+
+        self
+    }
+
+    pub fn with_slow_response_delay(mut self, delay: u64) -> Self {
+        self.slow_response_delay = delay;
+
+        self
+    }
+
+    pub fn build(self) {
+        self.apply(&FAULT_INJECTION)
+    }
+
+    fn apply(self, fault_injection: &FaultInjection) {
+        fault_injection
+            .provider_error_percentage
+            .store(self.provider_error_percentage, Ordering::Relaxed);
+        fault_injection
+            .kim_write_failure_percentage
+            .store(self.kim_write_failure_percentage, Ordering::Relaxed);
+        fault_injection
+            .slow_response_percentage
+            .store(self.slow_response_percentage, Ordering::Relaxed);
+        fault_injection
+            .slow_response_delay
+            .store(self.slow_response_delay, Ordering::Relaxed);
+        fault_injection.enabled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FaultInjection, FaultInjectionBuilder, PROVIDER_ERROR_STATUS};
+    use parsec_interface::requests::ResponseStatus;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    // Local settings, so that the faults do not reach the other tests of the process.
+    fn fault_injection(builder: FaultInjectionBuilder) -> FaultInjection {
+        let fault_injection = FaultInjection::new();
+        builder.apply(&fault_injection);
+        fault_injection
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let fault_injection = FaultInjection::new();
+        fault_injection
+            .provider_error_percentage
+            .store(100, Ordering::Relaxed);
+        fault_injection
+            .kim_write_failure_percentage
+            .store(100, Ordering::Relaxed);
+        assert!(fault_injection.provider_operation().is_ok());
+        assert!(fault_injection.key_info_write().is_ok());
+    }
+
+    #[test]
+    fn no_fault_at_zero_percent() {
+        let fault_injection = fault_injection(FaultInjectionBuilder::new());
+        for _ in 0..100 {
+            assert!(fault_injection.provider_operation().is_ok());
+            assert!(fault_injection.key_info_write().is_ok());
+        }
+    }
+
+    #[test]
+    fn provider_errors() {
+        let fault_injection =
+            fault_injection(FaultInjectionBuilder::new().with_provider_error_percentage(100));
+        assert_eq!(
+            fault_injection.provider_operation().unwrap_err(),
+            PROVIDER_ERROR_STATUS
+        );
+        assert!(fault_injection.key_info_write().is_ok());
+    }
+
+    #[test]
+    fn key_info_write_failures() {
+        let fault_injection =
+            fault_injection(FaultInjectionBuilder::new().with_kim_write_failure_percentage(100));
+        assert_eq!(
+            fault_injection.key_info_write().unwrap_err(),
+            ResponseStatus::KeyInfoManagerError
+        );
+        assert!(fault_injection.provider_operation().is_ok());
+    }
+
+    #[test]
+    fn slow_responses() {
+        let fault_injection = fault_injection(
+            FaultInjectionBuilder::new()
+                .with_slow_response_percentage(100)
+                .with_slow_response_delay(50),
+        );
+        let start = Instant::now();
+        assert!(fault_injection.provider_operation().is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn partial_percentage() {
+        let fault_injection =
+            fault_injection(FaultInjectionBuilder::new().with_provider_error_percentage(50));
+        let failures = (0..1000)
+            .filter(|_| fault_injection.provider_operation().is_err())
+            .count();
+        // Far enough from 500 to never fail in practice
+        assert!((300..700).contains(&failures));
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+#![doc = include_str!("../../doc/key-name-suffixes.md")]
+use crate::utils::logging::Redacted;
+use log::error;
+use parsec_interface::requests::{ResponseStatus, Result};
+
+/// Suffix of the names given with the ID of their approval
+pub const APPROVAL: &str = "#approval=";
+/// Suffix of the key names of the keys wrapped or unwrapped with AES-KW, before the wrapping key
+pub const AES_KW: &str = "#aes-kw=";
+/// Suffix of the key names of the keys wrapped or unwrapped with AES-KWP, before the wrapping key
+pub const AES_KWP: &str = "#aes-kwp=";
+/// Suffix of the key names of the keys generated from a template, before the template name
+pub const TEMPLATE: &str = "#template=";
+/// Suffix of the key names of the keys leased to the application, before the name of their owner
+pub const LEASE: &str = "#lease=";
+/// Suffix of the key names of the keys created in, or moved to, a state of their lifecycle, before
+/// the name of the state
+pub const STATE: &str = "#state=";
+/// Suffix of the key names of the keys disabled for some uses, before the name of the uses
+pub const DISABLE: &str = "#disable=";
+/// Suffix of the key names of the requests using the detached tag layout
+pub const DETACHED_TAG: &str = "#detached-tag";
+/// Suffix of the key names of the XChaCha20-Poly1305 requests
+pub const XCHACHA20: &str = "#xchacha20";
+/// Suffix of the key names of the requests signing JSON Web Signatures
+pub const JWS: &str = "#jws";
+/// Suffix of the key names of the requests signing COSE_Sign1 structures
+pub const COSE_SIGN1: &str = "#cose-sign1";
+/// Suffix of the key names of the requests signing server CertificateVerify messages
+pub const TLS13_SERVER: &str = "#tls13-server";
+/// Suffix of the key names of the requests signing client CertificateVerify messages
+pub const TLS13_CLIENT: &str = "#tls13-client";
+/// Suffix of the key names asking for a PEM encoded public key
+pub const PEM: &str = "#pem";
+/// Suffix of the key names asking for a JSON Web Key
+pub const JWK: &str = "#jwk";
+/// Suffix of the key names asking for an OpenSSH public key
+pub const SSH: &str = "#ssh";
+/// Suffix of the key names asking for a COSE_Key
+pub const COSE: &str = "#cose";
+/// Reserved key name of the PsaExportPublicKey requests getting the public key under which secrets
+/// are wrapped offline for the provider
+pub const WRAPPING_KEY: &str = "#wrapping-key";
+
+/// Suffixes followed by an argument
+const ARGUMENT_SUFFIXES: [&str; 7] = [APPROVAL, AES_KW, AES_KWP, TEMPLATE, LEASE, STATE, DISABLE];
+/// Suffixes standing on their own
+const FLAG_SUFFIXES: [&str; 11] = [
+    DETACHED_TAG,
+    XCHACHA20,
+    JWS,
+    COSE_SIGN1,
+    TLS13_SERVER,
+    TLS13_CLIENT,
+    PEM,
+    JWK,
+    SSH,
+    COSE,
+    WRAPPING_KEY,
+];
+
+/// Remove the suffix from the key name, returning whether it had it. Names made of the suffix
+/// alone are left as they are.
+pub fn strip_flag(key_name: &mut String, suffix: &str) -> bool {
+    if key_name.len() > suffix.len() && key_name.ends_with(suffix) {
+        key_name.truncate(key_name.len() - suffix.len());
+        true
+    } else {
+        false
+    }
+}
+
+/// Remove the suffix and its argument from the key name, returning the argument. Names are left as
+/// they are if the suffix is not preceded by a name and followed by an argument.
+pub fn strip_argument(key_name: &mut String, suffix: &str) -> Option<String> {
+    let start = key_name.rfind(suffix)?;
+    let argument = &key_name[start + suffix.len()..];
+    if start == 0 || argument.is_empty() {
+        return None;
+    }
+    let argument = argument.to_string();
+    key_name.truncate(start);
+    Some(argument)
+}
+
+/// Whether the key name could be read as having a suffix, by any operation.
+pub fn is_ambiguous(key_name: &str) -> bool {
+    key_name.match_indices('#').any(|(start, _)| {
+        let rest = &key_name[start..];
+        ARGUMENT_SUFFIXES
+            .iter()
+            .any(|suffix| rest.starts_with(suffix))
+            || FLAG_SUFFIXES.iter().any(|suffix| {
+                rest.strip_prefix(suffix)
+                    .map_or(false, |after| after.is_empty() || after.starts_with('#'))
+            })
+    })
+}
+
+/// Check that a key can be created with the name, left once the suffixes of the request are
+/// removed.
+pub fn check_new(key_name: &str) -> Result<()> {
+    if is_ambiguous(key_name) {
+        error!(
+            "Keys can not be created with the name \"{}\", which could be read as having a suffix.",
+            Redacted(key_name)
+        );
+        return Err(ResponseStatus::PsaErrorInvalidArgument);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_are_stripped() {
+        let mut key_name = String::from("key#jws");
+        assert!(strip_flag(&mut key_name, JWS));
+        assert_eq!(key_name, "key");
+        assert!(!strip_flag(&mut key_name, JWS));
+
+        let mut key_name = String::from(JWS);
+        assert!(!strip_flag(&mut key_name, JWS));
+        assert_eq!(key_name, JWS);
+    }
+
+    #[test]
+    fn arguments_are_stripped() {
+        let mut key_name = String::from("key#aes-kw=wrap#approval=3");
+        assert_eq!(
+            strip_argument(&mut key_name, APPROVAL),
+            Some(String::from("3"))
+        );
+        assert_eq!(
+            strip_argument(&mut key_name, AES_KW),
+            Some(String::from("wrap"))
+        );
+        assert_eq!(key_name, "key");
+
+        for name in ["key", "key#template=", "#template=web"] {
+            let mut key_name = String::from(name);
+            assert_eq!(strip_argument(&mut key_name, TEMPLATE), None);
+            assert_eq!(key_name, name);
+        }
+    }
+
+    #[test]
+    fn ambiguous_names_are_refused() {
+        for name in [
+            "key#jws",
+            "key#cose-sign1",
+            "key#pem#extra",
+            "key#xchacha20#detached-tag",
+            "key#template=web",
+            "key#approval=",
+            "key#aes-kwp=wrap.more",
+            "key#lease=alice",
+            "key#state=suspended",
+            "#wrapping-key",
+        ] {
+            assert!(is_ambiguous(name), "{}", name);
+            assert_eq!(
+                check_new(name).unwrap_err(),
+                ResponseStatus::PsaErrorInvalidArgument
+            );
+        }
+    }
+
+    #[test]
+    fn other_names_are_accepted() {
+        for name in [
+            "key",
+            "key#1",
+            "key#jwsx",
+            "key#cose-key",
+            "key#template",
+            "tls13-server",
+            "key#",
+        ] {
+            assert!(!is_ambiguous(name), "{}", name);
+            check_new(name).unwrap();
+        }
+    }
+}
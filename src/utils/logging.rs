@@ -0,0 +1,382 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Logging setup
+//!
+//! Logs are filtered as set by the `log_level` option or the `RUST_LOG` environment variable and
+//! sent to the sink chosen in the configuration: the standard error, the local syslog daemon or,
+//! with the `journald` feature, the systemd journal, written to with libsystemd.
+//!
+//! Unless `log_error_details` is set, the application and key names are redacted from the log
+//! messages: they are formatted through `Redacted`, which prints `***` in their place. The entries
+//! of the audit log, under the `parsec::audit` target, are written whatever the level and name the
+//! applications and keys involved, unredacted.
+//!
+//! Clients can tie the entries logged while handling a request to their own calls with a
+//! correlation ID, put in the session field of the request header. The front end enters a
+//! `CorrelationScope` for the request and the entries logged by that thread until the response is
+//! sent carry the ID: at the start of the message in text, as a `correlation_id` field in JSON and
+//! as a `PARSEC_CORRELATION_ID` field in the journal. Responses echo the session field, so clients
+//! find it there as well.
+use super::config::{CoreSettings, LogFormat, LogSink};
+use super::GlobalConfig;
+#[cfg(feature = "journald")]
+use libsystemd::logging::{self as journal, Priority};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::cell::Cell;
+use std::fmt;
+use std::fmt::Write as _;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::unix::net::UnixDatagram;
+
+/// Socket of the local syslog daemon
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Identifier of the service in syslog and in the journal
+const IDENTIFIER: &str = "parsec";
+/// Syslog facility of system daemons
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+/// Target of the audit log entries, written whatever the log level
+pub const AUDIT_TARGET: &str = "parsec::audit";
+/// Replaces redacted values
+const REDACTED: &str = "***";
+
+thread_local! {
+    /// Correlation ID of the request handled by the thread
+    static CORRELATION_ID: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Tags the entries logged by the current thread with a correlation ID until dropped
+#[derive(Debug)]
+pub struct CorrelationScope {
+    previous: Option<u64>,
+}
+
+impl CorrelationScope {
+    /// Tag the entries logged by the current thread with the given correlation ID, if any.
+    pub fn enter(correlation_id: Option<u64>) -> Self {
+        CorrelationScope {
+            previous: CORRELATION_ID.with(|id| id.replace(correlation_id)),
+        }
+    }
+}
+
+impl Drop for CorrelationScope {
+    fn drop(&mut self) {
+        CORRELATION_ID.with(|id| id.set(self.previous));
+    }
+}
+
+/// Formats an application or key name in a log message, replaced by `***` unless
+/// `log_error_details` is set
+pub struct Redacted<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if GlobalConfig::log_error_details() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(REDACTED)
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if GlobalConfig::log_error_details() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(REDACTED)
+        }
+    }
+}
+
+/// Correlation ID of the request handled by the current thread, if it has one
+pub fn correlation_id() -> Option<u64> {
+    CORRELATION_ID.with(Cell::get)
+}
+
+/// Set up the logger of the service from the core settings.
+///
+/// This can only be done once per process.
+pub fn init(settings: &CoreSettings) -> Result<()> {
+    let mut builder = env_logger::builder();
+    if let Some(level) = settings.log_level {
+        let _ = builder.filter_level(level);
+    }
+    let _ = builder.filter_module(AUDIT_TARGET, LevelFilter::Info);
+    let format = settings.log_format.unwrap_or(LogFormat::Text);
+    let timestamp = settings.log_timestamp.unwrap_or(false);
+
+    let (sink, socket_path) = match settings.log_sink.unwrap_or(LogSink::Stderr) {
+        LogSink::Stderr => {
+            // The default format of env_logger has no place for the correlation IDs.
+            let _ = builder.format(move |buf, record| {
+                let message = record.args().to_string();
+                let timestamp = if timestamp {
+                    Some(buf.timestamp_millis().to_string())
+                } else {
+                    None
+                };
+                match format {
+                    LogFormat::Text => {
+                        let message = text_message(message);
+                        match timestamp {
+                            Some(timestamp) => writeln!(
+                                buf,
+                                "[{} {:<5} {}] {}",
+                                timestamp,
+                                record.level(),
+                                record.target(),
+                                message
+                            ),
+                            None => writeln!(
+                                buf,
+                                "[{:<5} {}] {}",
+                                record.level(),
+                                record.target(),
+                                message
+                            ),
+                        }
+                    }
+                    LogFormat::Json => {
+                        writeln!(buf, "{}", json_entry(record, &message, timestamp))
+                    }
+                }
+            });
+            builder.init();
+            return Ok(());
+        }
+        LogSink::Syslog => (LogSink::Syslog, SYSLOG_SOCKET),
+        #[cfg(feature = "journald")]
+        LogSink::Journald => (LogSink::Journald, journal::SD_JOURNAL_SOCK_PATH),
+        #[cfg(not(feature = "journald"))]
+        LogSink::Journald => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The Journald log sink was not compiled in the Parsec binary",
+            ));
+        }
+    };
+
+    // The entries are sent to the journal by libsystemd. The socket is still connected to for the
+    // service to fail to start if the journal can not be reached.
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(socket_path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "Failed to connect to the log socket {} ({})",
+                socket_path, e
+            ),
+        )
+    })?;
+    let filter = builder.build();
+    let max_level = filter.filter();
+    log::set_boxed_logger(Box::new(SocketLogger {
+        filter,
+        socket,
+        sink,
+        format,
+    }))
+    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+/// Logger sending entries to the syslog daemon or to the journal
+#[derive(Debug)]
+struct SocketLogger {
+    filter: env_logger::Logger,
+    socket: UnixDatagram,
+    sink: LogSink,
+    format: LogFormat,
+}
+
+impl Log for SocketLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = record.args().to_string();
+        // There is nowhere left to report a failure to log.
+        if self.sink == LogSink::Journald {
+            #[cfg(feature = "journald")]
+            let _ = journal::journal_send(
+                priority(record.level()),
+                &message,
+                journald_fields(record).into_iter(),
+            );
+            return;
+        }
+        let body = match self.format {
+            LogFormat::Text => text_message(message),
+            LogFormat::Json => json_entry(record, &message, None),
+        };
+        let datagram = format!(
+            "<{}>{}[{}]: {}",
+            SYSLOG_FACILITY_DAEMON * 8 + severity(record.level()),
+            IDENTIFIER,
+            std::process::id(),
+            body
+        );
+        let _ = self.socket.send(datagram.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Prefix the message with the correlation ID, for the formats without separate fields.
+fn text_message(message: String) -> String {
+    match correlation_id() {
+        Some(id) => format!("[correlation_id={:016x}] {}", id, message),
+        None => message,
+    }
+}
+
+/// Syslog severity of a log level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Journal priority of a log level
+#[cfg(feature = "journald")]
+fn priority(level: Level) -> Priority {
+    match level {
+        Level::Error => Priority::Error,
+        Level::Warn => Priority::Warning,
+        Level::Info => Priority::Info,
+        Level::Debug | Level::Trace => Priority::Debug,
+    }
+}
+
+fn json_entry(record: &Record<'_>, message: &str, timestamp: Option<String>) -> String {
+    let mut entry = String::from("{");
+    if let Some(timestamp) = timestamp {
+        let _ = write!(entry, "\"timestamp\":{},", json_string(&timestamp));
+    }
+    if let Some(id) = correlation_id() {
+        let _ = write!(entry, "\"correlation_id\":\"{:016x}\",", id);
+    }
+    let _ = write!(
+        entry,
+        "\"level\":{},\"target\":{},\"message\":{}}}",
+        json_string(record.level().as_str()),
+        json_string(record.target()),
+        json_string(message)
+    );
+    entry
+}
+
+/// Quote and escape a string as a JSON value
+pub(crate) fn json_string(value: &str) -> String {
+    let mut string = String::with_capacity(value.len() + 2);
+    string.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\r' => string.push_str("\\r"),
+            '\t' => string.push_str("\\t"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(string, "\\u{:04x}", u32::from(c));
+            }
+            c => string.push(c),
+        }
+    }
+    string.push('"');
+    string
+}
+
+/// Fields of a journal entry, other than its message and priority
+#[cfg(feature = "journald")]
+fn journald_fields(record: &Record<'_>) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("SYSLOG_IDENTIFIER", String::from(IDENTIFIER)),
+        ("TARGET", String::from(record.target())),
+    ];
+    if let Some(id) = correlation_id() {
+        fields.push(("PARSEC_CORRELATION_ID", format!("{:016x}", id)));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_are_redacted() {
+        assert_eq!(
+            format!("Key \"{}\" not found.", Redacted("my key")),
+            "Key \"***\" not found."
+        );
+        assert_eq!(format!("{:?}", Redacted("my key")), "***");
+    }
+
+    #[test]
+    fn entries_are_encoded() {
+        let record = Record::builder()
+            .args(format_args!("unused"))
+            .level(Level::Warn)
+            .target("parsec")
+            .build();
+        assert_eq!(
+            json_entry(&record, "a \"quoted\"\nmessage", None),
+            "{\"level\":\"WARN\",\"target\":\"parsec\",\"message\":\"a \\\"quoted\\\"\\nmessage\"}"
+        );
+    }
+
+    #[cfg(feature = "journald")]
+    #[test]
+    fn journal_entries_have_fields() {
+        let record = Record::builder()
+            .args(format_args!("unused"))
+            .level(Level::Warn)
+            .target("parsec")
+            .build();
+        assert_eq!(
+            journald_fields(&record),
+            [
+                ("SYSLOG_IDENTIFIER", String::from("parsec")),
+                ("TARGET", String::from("parsec")),
+            ]
+        );
+        let _scope = CorrelationScope::enter(Some(0xcafe));
+        assert_eq!(
+            journald_fields(&record).pop(),
+            Some(("PARSEC_CORRELATION_ID", String::from("000000000000cafe")))
+        );
+    }
+
+    #[test]
+    fn entries_carry_the_correlation_id() {
+        let record = Record::builder()
+            .args(format_args!("unused"))
+            .level(Level::Info)
+            .target("parsec")
+            .build();
+        {
+            let _scope = CorrelationScope::enter(Some(0xcafe));
+            assert_eq!(
+                text_message("signed".to_string()),
+                "[correlation_id=000000000000cafe] signed"
+            );
+            assert_eq!(
+                json_entry(&record, "signed", None),
+                "{\"correlation_id\":\"000000000000cafe\",\"level\":\"INFO\",\"target\":\"parsec\",\"message\":\"signed\"}"
+            );
+        }
+        assert_eq!(correlation_id(), None);
+        assert_eq!(text_message("signed".to_string()), "signed");
+    }
+}
@@ -1,15 +1,34 @@
 // Copyright 2019 Contributors to the Parsec project.
 // SPDX-License-Identifier: Apache-2.0
 //! Service utilities
+pub mod admin;
+#[cfg(any(feature = "key-export-formats", feature = "cose-signing"))]
+pub mod cbor;
+pub mod cgroup;
 pub mod cli;
 pub mod config;
+pub mod config_check;
+#[cfg(any(feature = "key-export-formats", feature = "cose-signing"))]
+pub mod cose;
+pub mod event_hooks;
+pub mod executor;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
 mod global_config;
+pub mod key_suffixes;
+pub mod logging;
+pub mod secrets;
 mod service_builder;
+pub mod service_status;
+#[cfg(test)]
+pub mod test_helpers;
 #[cfg(all(
     feature = "mbed-crypto-provider",
     feature = "pkcs11-provider",
     feature = "tpm-provider",
     feature = "cryptoauthlib-provider",
+    feature = "piv-provider",
+    feature = "forwarding-provider",
     feature = "trusted-service-provider",
     feature = "direct-authenticator"
 ))]
@@ -0,0 +1,189 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Secrets kept out of the configuration file
+//!
+//! Options holding a secret, such as the TPM hierarchy auths or the PKCS 11 user PIN, can refer to
+//! the place holding it instead of giving it in plaintext:
+//! * `file:<path>`: the exact bytes of the file
+//! * `env:<variable>`: the value of the environment variable
+//! * `systemd-cred:<name>`: the credential of that name passed by systemd with `LoadCredential=`
+//!   or `LoadCredentialEncrypted=`, read from the `$CREDENTIALS_DIRECTORY` directory
+//!
+//! The references are resolved when the providers are built. The secrets are returned in buffers
+//! zeroized when dropped, which the providers copy into their own zeroized storage.
+use log::error;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+/// Prefix of the secrets read from a file
+pub const FILE_PREFIX: &str = "file:";
+/// Prefix of the secrets read from an environment variable
+pub const ENV_PREFIX: &str = "env:";
+/// Prefix of the secrets read from a systemd credential
+pub const SYSTEMD_CREDENTIAL_PREFIX: &str = "systemd-cred:";
+
+/// Directory of the systemd credentials, set by systemd in the environment of the service
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+/// Resolve a configuration value which can refer to a secret.
+///
+/// Returns `None` if the value is not a reference, in which case it is the secret itself.
+///
+/// # Errors
+///
+/// If the value is a reference to a secret which can not be read, an error is returned.
+pub fn resolve(value: &str) -> std::io::Result<Option<Zeroizing<Vec<u8>>>> {
+    if let Some(path) = value.strip_prefix(FILE_PREFIX) {
+        read_file(PathBuf::from(path)).map(Some)
+    } else if let Some(variable) = value.strip_prefix(ENV_PREFIX) {
+        match std::env::var_os(variable) {
+            Some(secret) => Ok(Some(Zeroizing::new(secret.into_vec()))),
+            None => {
+                error!(
+                    "The environment variable {} of a secret is not set.",
+                    variable
+                );
+                Err(Error::new(ErrorKind::NotFound, "secret variable not set"))
+            }
+        }
+    } else if let Some(name) = value.strip_prefix(SYSTEMD_CREDENTIAL_PREFIX) {
+        read_file(credential_path(
+            std::env::var_os(CREDENTIALS_DIRECTORY),
+            name,
+        )?)
+        .map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+fn credential_path(directory: Option<OsString>, name: &str) -> std::io::Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        error!("Invalid systemd credential name: \"{}\".", name);
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "invalid systemd credential name",
+        ));
+    }
+    match directory {
+        Some(directory) => Ok(PathBuf::from(directory).join(name)),
+        None => {
+            error!(
+                "The systemd credential {} is configured but ${} is not set: the service must be started by systemd with the credential loaded.",
+                name, CREDENTIALS_DIRECTORY
+            );
+            Err(Error::new(
+                ErrorKind::NotFound,
+                "systemd credentials directory not set",
+            ))
+        }
+    }
+}
+
+fn read_file(path: PathBuf) -> std::io::Result<Zeroizing<Vec<u8>>> {
+    fs::read(&path).map(Zeroizing::new).map_err(|e| {
+        format_error!(
+            format!("Failed to read the secret file {}", path.display()),
+            e
+        );
+        e
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_values_are_not_references() {
+        assert_eq!(resolve("password").unwrap(), None);
+        assert_eq!(resolve("hex:1a2b").unwrap(), None);
+        assert_eq!(resolve(" file:/secret").unwrap(), None);
+    }
+
+    #[test]
+    fn files_are_read_as_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        fs::write(&path, b"from file\n").unwrap();
+        let secret = resolve(&format!("file:{}", path.display())).unwrap();
+        assert_eq!(secret.unwrap().as_slice(), b"from file\n");
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        assert_eq!(
+            resolve(&format!("file:{}", path.display()))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn environment_variables_are_read() {
+        std::env::set_var("PARSEC_SECRETS_TEST", "from env");
+        let secret = resolve("env:PARSEC_SECRETS_TEST").unwrap();
+        assert_eq!(secret.unwrap().as_slice(), b"from env");
+    }
+
+    #[test]
+    fn unset_environment_variables_are_errors() {
+        assert_eq!(
+            resolve("env:PARSEC_SECRETS_TEST_UNSET").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn systemd_credentials_are_read_from_their_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("pin"), b"from systemd").unwrap();
+        // No other test uses the credentials directory.
+        std::env::set_var(CREDENTIALS_DIRECTORY, dir.path());
+        let secret = resolve("systemd-cred:pin").unwrap();
+        assert_eq!(secret.unwrap().as_slice(), b"from systemd");
+        assert_eq!(
+            resolve("systemd-cred:puk").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn credentials_are_in_their_directory() {
+        assert_eq!(
+            credential_path(
+                Some(OsString::from("/run/credentials/parsec.service")),
+                "pin"
+            )
+            .unwrap(),
+            PathBuf::from("/run/credentials/parsec.service/pin")
+        );
+    }
+
+    #[test]
+    fn credential_names_can_not_leave_their_directory() {
+        for name in &["", ".", "..", "../pin", "a/pin"] {
+            assert_eq!(
+                credential_path(Some(OsString::from("/run")), name)
+                    .unwrap_err()
+                    .kind(),
+                ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn credentials_need_systemd() {
+        assert_eq!(
+            credential_path(None, "pin").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
@@ -4,36 +4,71 @@
 //!
 //! The service builder is required to bootstrap all the components based on a
 //! provided configuration.
+use super::config_check::ConfigReport;
+#[cfg(feature = "fault-injection")]
+use super::fault_injection::FaultInjectionBuilder;
 use super::global_config::GlobalConfigBuilder;
+use crate::authenticators::tenants::Tenants;
 use crate::authenticators::Authenticate;
+#[cfg(feature = "approvals")]
+use crate::back::approvals::Approvals;
+#[cfg(feature = "import-checks")]
+use crate::back::import_checks::ImportChecks;
+#[cfg(feature = "signing-policies")]
+use crate::back::signing_policy::SigningPolicies;
 use crate::back::{
     backend_handler::{BackEndHandler, BackEndHandlerBuilder},
     dispatcher::DispatcherBuilder,
+    ecdsa_nonces::EcdsaNonces,
+    key_defaults::KeyDefaults,
+    key_requirements::KeyRequirements,
+    key_templates::KeyTemplates,
+    leases::KeyLeases,
+    mirroring::Mirroring,
+    priority::RequestPriority,
 };
+#[cfg(feature = "dbus-interface")]
+use crate::front::dbus::{DbusListener, WithDbusListener};
 use crate::front::{
-    domain_socket::DomainSocketListenerBuilder, front_end::FrontEndHandler,
-    front_end::FrontEndHandlerBuilder, listener::Listen,
+    auth_throttle::AuthThrottle,
+    domain_socket::{self, DomainSocketListenerBuilder, SocketEndpoint, DEFAULT_SOCKET_PATH},
+    front_end::FrontEndHandler,
+    front_end::FrontEndHandlerBuilder,
+    listener::Listen,
+    wire_protocol,
 };
-use crate::key_info_managers::KeyInfoManagerFactory;
+#[cfg(feature = "sqlite-kim")]
+use crate::key_info_managers::lifecycle::KeyLifecycle;
+use crate::key_info_managers::replication::Replication;
+#[cfg(feature = "sqlite-kim")]
+use crate::key_info_managers::signature_counters::SignatureCounters;
+use crate::key_info_managers::{KeyInfoManagerClient, KeyInfoManagerFactory};
+use crate::providers::lazy::{Activation, LazyProvider};
 use crate::providers::{core::ProviderBuilder as CoreProviderBuilder, Provide};
 use crate::utils::config::{
-    AuthenticatorConfig, KeyInfoManagerConfig, ListenerConfig, ListenerType, ProviderConfig,
-    ServiceConfig,
+    AuthenticatorConfig, CoreSettings, EventHooksConfig, FaultInjectionConfig,
+    KeyInfoManagerConfig, ListenerConfig, ListenerType, ProviderConfig, ServiceConfig,
 };
-use anyhow::Result;
-use log::{error, warn};
+use crate::utils::event_hooks::EventHooks;
+use crate::utils::executor::Executor;
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
 use parsec_interface::operations_protobuf::ProtobufConverter;
 use parsec_interface::requests::AuthType;
 use parsec_interface::requests::{BodyType, ProviderId};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::{Error, ErrorKind};
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
-use threadpool::{Builder as ThreadPoolBuilder, ThreadPool};
 
+#[cfg(feature = "attestation-token-authenticator")]
+use crate::authenticators::attestation_token_authenticator::AttestationTokenAuthenticator;
 #[cfg(feature = "direct-authenticator")]
 use crate::authenticators::direct_authenticator::DirectAuthenticator;
+#[cfg(feature = "external-authenticator")]
+use crate::authenticators::external_authenticator::ExternalAuthenticator;
 #[cfg(feature = "jwt-svid-authenticator")]
 use crate::authenticators::jwt_svid_authenticator::JwtSvidAuthenticator;
 #[cfg(feature = "unix-peer-credentials-authenticator")]
@@ -41,10 +76,18 @@ use crate::authenticators::unix_peer_credentials_authenticator::UnixPeerCredenti
 
 #[cfg(feature = "cryptoauthlib-provider")]
 use crate::providers::cryptoauthlib::ProviderBuilder as CryptoAuthLibProviderBuilder;
+#[cfg(feature = "external-provider")]
+use crate::providers::external::ProviderBuilder as ExternalProviderBuilder;
+#[cfg(feature = "forwarding-provider")]
+use crate::providers::forwarding::ProviderBuilder as ForwardingProviderBuilder;
 #[cfg(feature = "mbed-crypto-provider")]
 use crate::providers::mbed_crypto::ProviderBuilder as MbedCryptoProviderBuilder;
+#[cfg(feature = "piv-provider")]
+use crate::providers::piv::ProviderBuilder as PivProviderBuilder;
 #[cfg(feature = "pkcs11-provider")]
 use crate::providers::pkcs11::ProviderBuilder as Pkcs11ProviderBuilder;
+#[cfg(feature = "test-provider")]
+use crate::providers::test_provider::ProviderBuilder as TestProviderBuilder;
 #[cfg(feature = "tpm-provider")]
 use crate::providers::tpm::ProviderBuilder as TpmProviderBuilder;
 #[cfg(feature = "trusted-service-provider")]
@@ -54,8 +97,12 @@ use crate::providers::trusted_service::ProviderBuilder as TrustedServiceProvider
 use crate::providers::cryptoauthlib::Provider as CryptoAuthLibProvider;
 #[cfg(feature = "mbed-crypto-provider")]
 use crate::providers::mbed_crypto::Provider as MbedCryptoProvider;
+#[cfg(feature = "piv-provider")]
+use crate::providers::piv::Provider as PivProvider;
 #[cfg(feature = "pkcs11-provider")]
 use crate::providers::pkcs11::Provider as Pkcs11Provider;
+#[cfg(feature = "test-provider")]
+use crate::providers::test_provider::Provider as TestProvider;
 #[cfg(feature = "tpm-provider")]
 use crate::providers::tpm::Provider as TpmProvider;
 #[cfg(feature = "trusted-service-provider")]
@@ -66,28 +113,36 @@ use crate::providers::trusted_service::Provider as TrustedServiceProvider;
     feature = "pkcs11-provider",
     feature = "tpm-provider",
     feature = "cryptoauthlib-provider",
-    feature = "trusted-service-provider"
+    feature = "piv-provider",
+    feature = "trusted-service-provider",
+    feature = "test-provider"
 ))]
 use crate::providers::ProviderIdentity;
 
-#[cfg(any(
-    feature = "mbed-crypto-provider",
-    feature = "pkcs11-provider",
-    feature = "tpm-provider",
-    feature = "cryptoauthlib-provider",
-    feature = "trusted-service-provider"
-))]
-use log::info;
-
-const WIRE_PROTOCOL_VERSION_MINOR: u8 = 0;
-const WIRE_PROTOCOL_VERSION_MAJOR: u8 = 1;
-
 /// Default value for the limit on the request body size (in bytes) - equal to 1MB
 const DEFAULT_BODY_LEN_LIMIT: usize = 1 << 20;
 
 /// Default value for the limit on the buffer size for response (in bytes) - equal to 1MB
 pub const DEFAULT_BUFFER_SIZE_LIMIT: usize = 1 << 20;
 
+/// Default value for the minimum number of threads processing requests
+const DEFAULT_MIN_THREADS: usize = 1;
+
+/// Default value for the maximum number of threads processing requests, per processor
+const DEFAULT_MAX_THREADS_PER_CORE: usize = 8;
+
+/// Default value for the maximum number of entries in an authentication cache
+#[cfg(feature = "jwt-svid-authenticator")]
+const DEFAULT_AUTH_CACHE_SIZE: usize = 1024;
+
+/// Default value for the maximum time until the expiration of an attestation token (in seconds)
+#[cfg(feature = "attestation-token-authenticator")]
+const DEFAULT_MAX_TOKEN_LIFETIME: u64 = 300;
+
+/// Default value for the time to wait for a decision of an authentication plugin (in milliseconds)
+#[cfg(feature = "external-authenticator")]
+const DEFAULT_PLUGIN_TIMEOUT: u64 = 1000;
+
 type Provider = Arc<dyn Provide + Send + Sync>;
 type Authenticator = Box<dyn Authenticate + Send + Sync>;
 
@@ -108,7 +163,26 @@ impl ServiceBuilder {
     /// * if any of the fields specified in the configuration are inconsistent (e.g. key info manager with name 'X'
     /// requested for a certain provider does not exist) or if required fields are missing, an error of kind
     /// `InvalidData` is returned with a string describing the cause more accurately.
+    ///
+    /// The configuration is not measured, see `build_measured_service`.
     pub fn build_service(config: &ServiceConfig) -> Result<FrontEndHandler> {
+        ServiceBuilder::build(config, None)
+    }
+
+    /// Assemble a service like `build_service`, measuring the content of the configuration file
+    /// into the `configuration_pcr` of the TPM provider, if any, once the providers are created.
+    ///
+    /// The measurement is only done once, when the service starts, for the PCR to have a value
+    /// which verifiers can predict from the configuration file: configurations reloaded later on
+    /// are not measured.
+    pub fn build_measured_service(
+        config: &ServiceConfig,
+        config_file: &[u8],
+    ) -> Result<FrontEndHandler> {
+        ServiceBuilder::build(config, Some(config_file))
+    }
+
+    fn build(config: &ServiceConfig, config_file: Option<&[u8]>) -> Result<FrontEndHandler> {
         GlobalConfigBuilder::new()
             .with_log_error_details(config.core_settings.log_error_details.unwrap_or(false))
             .with_buffer_size_limit(
@@ -120,32 +194,112 @@ impl ServiceBuilder {
             .with_allow_deprecated(config.core_settings.allow_deprecated.unwrap_or(false))
             .build();
 
-        let authenticators = build_authenticators(&config.authenticator)?;
+        if let Some(fault_injection) = config.fault_injection {
+            setup_fault_injection(fault_injection)?;
+        }
+
+        check_compiled_features(config)?;
+
+        let authenticators = build_all_authenticators(config)?;
 
         if authenticators[0].0 == AuthType::Direct {
             warn!("Direct authenticator has been set as the default one. It is only secure under specific requirements. Please make sure to read the Recommendations on a Secure Parsec Deployment at https://parallaxsecond.github.io/parsec-book/parsec_security/secure_deployment.html");
         }
 
-        let key_info_manager_builders = get_key_info_manager_builders(
+        let mut key_info_manager_builders = get_key_info_manager_builders(
             config.key_manager.as_ref().unwrap_or(&Vec::new()),
             authenticators[0].0,
         )?;
+        let replication = config.replication.as_ref().map(Replication::new);
+        if let Some(replication) = &replication {
+            key_info_manager_builders = key_info_manager_builders
+                .into_iter()
+                .map(|(name, factory)| (name, factory.with_replication(replication.clone())))
+                .collect();
+        }
+        if let Some(tenants) = &config.tenants {
+            let quotas = Tenants::quotas(tenants);
+            key_info_manager_builders = key_info_manager_builders
+                .into_iter()
+                .map(|(name, factory)| (name, factory.with_tenant_quotas(quotas.clone())))
+                .collect();
+        }
+        #[cfg(feature = "sqlite-kim")]
+        if let Some(lifecycle) = &config.key_lifecycle {
+            let lifecycle = Arc::new(KeyLifecycle::new(lifecycle)?);
+            key_info_manager_builders = key_info_manager_builders
+                .into_iter()
+                .map(|(name, factory)| (name, factory.with_lifecycle(lifecycle.clone())))
+                .collect();
+        }
+        #[cfg(feature = "sqlite-kim")]
+        if let Some(signature_counters) = &config.signature_counters {
+            let signature_counters = Arc::new(SignatureCounters::new(
+                signature_counters,
+                config.kim_write_behind.as_ref(),
+            )?);
+            key_info_manager_builders = key_info_manager_builders
+                .into_iter()
+                .map(|(name, factory)| {
+                    (
+                        name,
+                        factory.with_signature_counters(signature_counters.clone()),
+                    )
+                })
+                .collect();
+        }
 
         let providers = build_providers(
             config.provider.as_ref().unwrap_or(&Vec::new()),
             key_info_manager_builders,
+            config.core_settings.strict_providers.unwrap_or(false),
+            config
+                .provider_activation
+                .as_ref()
+                .map_or(&[], |activation| activation.on_demand.as_slice()),
         )?;
 
+        // Started once all the providers are registered, so that both instances compare them all.
+        if let Some(replication) = replication {
+            replication.start()?;
+        }
+
         if providers.is_empty() {
             error!("Parsec needs at least one provider to start. No valid provider could be created from the configuration.");
             return Err(Error::new(ErrorKind::InvalidData, "need one provider").into());
         }
 
-        let backend_handlers = build_backend_handlers(providers, &authenticators)?;
+        let key_templates = match &config.key_templates {
+            Some(key_templates) => {
+                let provider_ids = provider_ids(config.provider.as_ref().unwrap_or(&Vec::new()))?;
+                Some(Arc::new(KeyTemplates::new(key_templates, &provider_ids)?))
+            }
+            None => None,
+        };
+
+        // The D-Bus interface emits the events as signals.
+        let event_hooks = match (&config.event_hooks, &config.listener.dbus) {
+            (Some(event_hooks), _) => Some(EventHooks::new(event_hooks)?),
+            (None, Some(_)) => Some(EventHooks::new(&EventHooksConfig::default())?),
+            (None, None) => None,
+        };
+
+        let backend_handlers = build_backend_handlers(
+            providers,
+            &authenticators,
+            config,
+            key_templates.as_ref(),
+            event_hooks.as_ref(),
+            config_file,
+        )?;
 
-        let dispatcher = DispatcherBuilder::new()
-            .with_backends(backend_handlers)
-            .build()?;
+        let mut dispatcher_builder = DispatcherBuilder::new().with_backends(backend_handlers);
+        if let Some(mirroring) = &config.mirroring {
+            let provider_ids = provider_ids(config.provider.as_ref().unwrap_or(&Vec::new()))?;
+            dispatcher_builder =
+                dispatcher_builder.with_mirroring(Mirroring::new(mirroring, &provider_ids)?);
+        }
+        let dispatcher = dispatcher_builder.build()?;
 
         let mut front_end_handler_builder = FrontEndHandlerBuilder::new();
         for (auth_type, authenticator) in authenticators {
@@ -160,40 +314,580 @@ impl ServiceBuilder {
                     .body_len_limit
                     .unwrap_or(DEFAULT_BODY_LEN_LIMIT),
             );
+        if let Some(in_flight_memory_limit) = config.core_settings.in_flight_memory_limit {
+            front_end_handler_builder =
+                front_end_handler_builder.with_in_flight_memory_limit(in_flight_memory_limit);
+        }
+        match config.core_settings.response_compression_threshold {
+            #[cfg(feature = "compression")]
+            Some(threshold) => {
+                front_end_handler_builder =
+                    front_end_handler_builder.with_response_compression_threshold(threshold);
+            }
+            #[cfg(not(feature = "compression"))]
+            Some(_) => {
+                error!("The response compression was not compiled in the Parsec binary.");
+                return Err(Error::new(ErrorKind::InvalidData, "compression not compiled").into());
+            }
+            None => (),
+        }
+        if let Some(auth_throttle) = &config.authentication_throttle {
+            front_end_handler_builder =
+                front_end_handler_builder.with_auth_throttle(AuthThrottle::from(auth_throttle));
+        }
+        if let Some(tenants) = &config.tenants {
+            let provider_ids = provider_ids(config.provider.as_ref().unwrap_or(&Vec::new()))?;
+            front_end_handler_builder =
+                front_end_handler_builder.with_tenants(Tenants::new(tenants, &provider_ids)?);
+        }
+        if let Some(event_hooks) = event_hooks {
+            front_end_handler_builder = front_end_handler_builder.with_event_hooks(event_hooks);
+        }
 
         Ok(front_end_handler_builder.build()?)
     }
 
+    /// Check the configuration without starting the service.
+    ///
+    /// Every component is set up as it would be by `build_service` and then dropped: the
+    /// authenticators, the Key Info Managers and the providers, which opens their backends (TCTI,
+    /// PKCS#11 library, ...) but does not create any key. No socket is opened; the listener is
+    /// only checked for the existence of the socket's directory. The outcome of each check is
+    /// recorded in the returned report.
+    pub fn check_config(config: &ServiceConfig) -> ConfigReport {
+        let mut report = ConfigReport::default();
+
+        let listener = &config.listener;
+        let create_socket_dir = listener.create_socket_dir.unwrap_or(false);
+        let mut sockets = vec![(
+            listener
+                .socket_path
+                .as_deref()
+                .unwrap_or(DEFAULT_SOCKET_PATH),
+            &listener.socket_owner,
+            &listener.socket_group,
+        )];
+        for socket in listener.additional_socket.iter().flatten() {
+            sockets.push((
+                &socket.socket_path,
+                &socket.socket_owner,
+                &socket.socket_group,
+            ));
+        }
+        for (socket_path, owner, group) in sockets {
+            let ownership = owner
+                .as_deref()
+                .map(domain_socket::user_id)
+                .transpose()
+                .and_then(|_| group.as_deref().map(domain_socket::group_id).transpose());
+            // Sockets in the abstract namespace have no directory.
+            let directory = Some(Path::new(socket_path))
+                .filter(|path| domain_socket::abstract_name(path).is_none())
+                .and_then(Path::parent);
+            if let Err(e) = ownership {
+                report.fail("listener", format!("socket {}: {}", socket_path, e));
+            } else if !create_socket_dir && directory.map_or(false, |dir| !dir.is_dir()) {
+                report.fail(
+                    "listener",
+                    format!("directory of socket {} does not exist", socket_path),
+                );
+            } else {
+                report.pass("listener", format!("socket {}", socket_path));
+            }
+        }
+
+        if let Some(fault_injection) = config.fault_injection {
+            match check_fault_injection(fault_injection) {
+                Ok(()) => report.pass("fault injection", "enabled"),
+                Err(e) => report.fail("fault injection", e.to_string()),
+            }
+        }
+
+        if let Err(e) = check_compiled_features(config) {
+            report.fail("features", e.to_string());
+        }
+
+        let authenticators = match build_all_authenticators(config) {
+            Ok(authenticators) => authenticators,
+            Err(e) => {
+                report.fail("authenticator", e.to_string());
+                return report;
+            }
+        };
+        for (auth_type, _) in &authenticators {
+            report.pass("authenticator", format!("{:?}", auth_type));
+        }
+
+        let kim_factories = match get_key_info_manager_builders(
+            config.key_manager.as_ref().unwrap_or(&Vec::new()),
+            authenticators[0].0,
+        ) {
+            Ok(kim_factories) => kim_factories,
+            Err(e) => {
+                report.fail("key info managers", e.to_string());
+                return report;
+            }
+        };
+        report.pass(
+            "key info managers",
+            format!("{} configured", kim_factories.len()),
+        );
+
+        let no_provider = Vec::new();
+        let provider_configs = config.provider.as_ref().unwrap_or(&no_provider);
+        if provider_configs.is_empty() {
+            report.fail("providers", "at least one provider is needed");
+        }
+        for conflict in provider_conflicts(provider_configs) {
+            report.fail("providers", conflict);
+        }
+        let strict_providers = config.core_settings.strict_providers.unwrap_or(false);
+        let mut provider_names = HashSet::new();
+        for provider_config in provider_configs {
+            let component = match provider_config.provider_name() {
+                Ok(name) => format!("provider {}", name),
+                Err(e) => {
+                    report.fail("provider", e.to_string());
+                    continue;
+                }
+            };
+            if !provider_names.insert(component.clone()) {
+                report.fail(component, "duplicate provider name");
+                continue;
+            }
+            let kim_factory = match kim_factories.get(provider_config.key_info_manager()) {
+                Some(kim_factory) => kim_factory,
+                None => {
+                    report.fail(
+                        component,
+                        format!(
+                            "key info manager {} not found",
+                            provider_config.key_info_manager()
+                        ),
+                    );
+                    continue;
+                }
+            };
+            // The safety is checked by the fact that only one instance per provider name is
+            // enforced, and that the provider is dropped before the next one is created.
+            match unsafe { get_provider(provider_config, kim_factory, None) } {
+                Ok(None) if strict_providers => report.fail(component, "skipped in strict mode"),
+                Ok(None) => report.pass(component, "skipped"),
+                Ok(Some(provider)) => match provider.describe() {
+                    Ok((info, opcodes)) => report.pass(
+                        component,
+                        format!(
+                            "{} {}.{}.{}, {} operations supported",
+                            info.description,
+                            info.version_maj,
+                            info.version_min,
+                            info.version_rev,
+                            opcodes.len()
+                        ),
+                    ),
+                    Err(status) => report.fail(component, status.to_string()),
+                },
+                Err(e) => report.fail(component, e.to_string()),
+            }
+        }
+
+        if let Some(pcr) = config.core_settings.configuration_pcr {
+            if provider_configs
+                .iter()
+                .any(|provider| matches!(provider, ProviderConfig::Tpm { .. }))
+            {
+                report.pass(
+                    "configuration measurement",
+                    format!("PCR {} of the TPM provider", pcr),
+                );
+            } else {
+                report.fail("configuration measurement", "no TPM provider configured");
+            }
+        }
+
+        if let Some(tenants) = &config.tenants {
+            match provider_ids(provider_configs).and_then(|ids| Tenants::new(tenants, &ids)) {
+                Ok(_) => report.pass("tenants", format!("{} configured", tenants.len())),
+                Err(e) => report.fail("tenants", e.to_string()),
+            }
+        }
+
+        if let Some(event_hooks) = &config.event_hooks {
+            match EventHooks::new(event_hooks) {
+                Ok(_) => report.pass("event hooks", format!("{} hooks", event_hooks.hook.len())),
+                Err(e) => report.fail("event hooks", e.to_string()),
+            }
+        }
+
+        if let Some(key_defaults) = &config.key_defaults {
+            match KeyDefaults::new(key_defaults) {
+                Ok(_) => report.pass(
+                    "key defaults",
+                    format!("{} key types", key_defaults.default.len()),
+                ),
+                Err(e) => report.fail("key defaults", e.to_string()),
+            }
+        }
+
+        if let Some(key_templates) = &config.key_templates {
+            match provider_ids(provider_configs)
+                .and_then(|ids| KeyTemplates::new(key_templates, &ids))
+            {
+                Ok(_) => report.pass(
+                    "key templates",
+                    format!("{} configured", key_templates.template.len()),
+                ),
+                Err(e) => report.fail("key templates", e.to_string()),
+            }
+        }
+
+        #[cfg(feature = "sqlite-kim")]
+        if let Some(lifecycle) = &config.key_lifecycle {
+            match KeyLifecycle::new(lifecycle) {
+                Ok(_) => report.pass("key lifecycle", "states of the keys tracked"),
+                Err(e) => report.fail("key lifecycle", e.to_string()),
+            }
+        }
+
+        #[cfg(feature = "approvals")]
+        if let Some(approvals) = &config.approvals {
+            match Approvals::new(approvals) {
+                Ok(_) => report.pass("approvals", "sensitive operations wait for approval"),
+                Err(e) => report.fail("approvals", e.to_string()),
+            }
+        }
+
+        #[cfg(feature = "sqlite-kim")]
+        if let Some(signature_counters) = &config.signature_counters {
+            match SignatureCounters::new(signature_counters, None) {
+                Ok(_) => report.pass("signature counters", "signatures of the keys counted"),
+                Err(e) => report.fail("signature counters", e.to_string()),
+            }
+        }
+
+        if let Some(activation) = &config.provider_activation {
+            match provider_ids(provider_configs) {
+                Ok(ids) => match activation
+                    .on_demand
+                    .iter()
+                    .find(|name| !ids.contains_key(*name))
+                {
+                    Some(name) => report.fail(
+                        "provider activation",
+                        format!("provider {} not found", name),
+                    ),
+                    None => report.pass(
+                        "provider activation",
+                        format!(
+                            "{} providers activated on demand",
+                            activation.on_demand.len()
+                        ),
+                    ),
+                },
+                Err(e) => report.fail("provider activation", e.to_string()),
+            }
+        }
+
+        if let Some(mirroring) = &config.mirroring {
+            match provider_ids(provider_configs).and_then(|ids| Mirroring::new(mirroring, &ids)) {
+                Ok(_) => report.pass(
+                    "mirroring",
+                    format!(
+                        "{} mirrored to {}",
+                        mirroring.provider, mirroring.mirror_provider
+                    ),
+                ),
+                Err(e) => report.fail("mirroring", e.to_string()),
+            }
+        }
+
+        report
+    }
+
+    /// Copy the mappings of all the configured providers from one configured Key Info Manager to
+    /// another, returning the number of mappings copied.
+    ///
+    /// The copies are verified before returning. With `remove_source`, the mappings are then
+    /// removed from the source. The service must not be running with this configuration.
+    pub fn migrate_key_info(
+        config: &ServiceConfig,
+        from: &str,
+        to: &str,
+        remove_source: bool,
+    ) -> Result<usize> {
+        if from == to {
+            return Err(anyhow!(
+                "the source and destination Key Info Managers are the same"
+            ));
+        }
+        let kim_configs = config.key_manager.as_ref().map_or(&[][..], Vec::as_slice);
+        let kim_config = |name: &str| {
+            kim_configs
+                .iter()
+                .find(|kim_config| kim_config.name == name)
+                .ok_or_else(|| anyhow!("no Key Info Manager is named {}", name))
+        };
+        let auth_type = config.authenticator.auth_type();
+        let source = KeyInfoManagerFactory::new(kim_config(from)?, auth_type)?;
+        let destination = KeyInfoManagerFactory::new(kim_config(to)?, auth_type)?;
+        let providers = config
+            .provider
+            .iter()
+            .flatten()
+            .filter(|provider_config| provider_config.key_info_manager() == from)
+            .map(ProviderConfig::provider_identity)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        info!(
+            "Migrating the mappings of {} providers from {} to {}.",
+            providers.len(),
+            from,
+            to
+        );
+        source.migrate_to(&destination, &providers, remove_source)
+    }
+
     /// Construct the service IPC front component and return ownership to it.
     pub fn start_listener(config: ListenerConfig) -> Result<Box<dyn Listen>> {
         let listener = match config.listener_type {
-            ListenerType::DomainSocket => DomainSocketListenerBuilder::new()
-                .with_timeout(Duration::from_millis(config.timeout))
-                .with_socket_path(config.socket_path.map(|s| s.into()))
-                .build(),
+            ListenerType::DomainSocket => {
+                let mut builder = DomainSocketListenerBuilder::new()
+                    .with_timeout(Duration::from_millis(config.timeout))
+                    .with_idle_timeout(config.idle_timeout.map(Duration::from_millis))
+                    .with_socket_path(config.socket_path.map(|s| s.into()))
+                    .with_socket_ownership(
+                        config
+                            .socket_owner
+                            .as_deref()
+                            .map(domain_socket::user_id)
+                            .transpose()?,
+                        config
+                            .socket_group
+                            .as_deref()
+                            .map(domain_socket::group_id)
+                            .transpose()?,
+                    )
+                    .with_socket_mode(config.socket_mode)
+                    .with_create_socket_dir(config.create_socket_dir.unwrap_or(false));
+                for socket in config.additional_socket.iter().flatten() {
+                    builder = builder.with_additional_socket(SocketEndpoint {
+                        path: socket.socket_path.clone().into(),
+                        owner: socket
+                            .socket_owner
+                            .as_deref()
+                            .map(domain_socket::user_id)
+                            .transpose()?,
+                        group: socket
+                            .socket_group
+                            .as_deref()
+                            .map(domain_socket::group_id)
+                            .transpose()?,
+                        mode: socket.socket_mode,
+                        auth_type: socket
+                            .authenticator
+                            .as_ref()
+                            .map(AuthenticatorConfig::auth_type),
+                    });
+                }
+                builder.build()
+            }
         }?;
 
-        Ok(Box::new(listener))
+        match config.dbus {
+            #[cfg(feature = "dbus-interface")]
+            Some(dbus) => Ok(Box::new(WithDbusListener::new(
+                Box::new(listener),
+                DbusListener::start(&dbus)?,
+            ))),
+            #[cfg(not(feature = "dbus-interface"))]
+            Some(_) => {
+                error!("The D-Bus interface was not compiled in the Parsec binary.");
+                Err(Error::new(ErrorKind::InvalidData, "D-Bus interface not compiled").into())
+            }
+            None => Ok(Box::new(listener)),
+        }
+    }
+
+    /// Construct the executor that will be used to process all service requests.
+    ///
+    /// A configured `thread_pool_size` fixes the number of threads. Otherwise it adapts to the
+    /// requests, between `thread_pool_min_size` and `thread_pool_max_size`.
+    pub fn build_executor(core_settings: &CoreSettings) -> Result<Executor> {
+        let (min_threads, max_threads) = match core_settings.thread_pool_size {
+            Some(size) => {
+                if core_settings.thread_pool_min_size.is_some()
+                    || core_settings.thread_pool_max_size.is_some()
+                {
+                    warn!("thread_pool_size is set, the thread pool bounds are ignored.");
+                }
+                (size, size)
+            }
+            None => {
+                let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+                (
+                    core_settings
+                        .thread_pool_min_size
+                        .unwrap_or(DEFAULT_MIN_THREADS),
+                    core_settings
+                        .thread_pool_max_size
+                        .unwrap_or(cores * DEFAULT_MAX_THREADS_PER_CORE),
+                )
+            }
+        };
+        Ok(Executor::new(min_threads, max_threads)?)
+    }
+}
+
+fn setup_fault_injection(config: FaultInjectionConfig) -> Result<()> {
+    check_fault_injection(config)?;
+
+    #[cfg(feature = "fault-injection")]
+    {
+        warn!("Fault injection is enabled. Some requests will fail or be delayed on purpose, this must never be used in production.");
+        FaultInjectionBuilder::new()
+            .with_provider_error_percentage(config.provider_error_percentage.unwrap_or(0))
+            .with_kim_write_failure_percentage(config.kim_write_failure_percentage.unwrap_or(0))
+            .with_slow_response_percentage(config.slow_response_percentage.unwrap_or(0))
+            .with_slow_response_delay(config.slow_response_delay.unwrap_or(0))
+            .build();
     }
 
-    /// Construct the thread pool that will be used to process all service requests.
-    pub fn build_threadpool(num_threads: Option<usize>) -> ThreadPool {
-        let mut threadpool_builder = ThreadPoolBuilder::new();
-        if let Some(num_threads) = num_threads {
-            threadpool_builder = threadpool_builder.num_threads(num_threads);
+    Ok(())
+}
+
+#[cfg(feature = "fault-injection")]
+fn check_fault_injection(config: FaultInjectionConfig) -> Result<()> {
+    let percentages = [
+        config.provider_error_percentage,
+        config.kim_write_failure_percentage,
+        config.slow_response_percentage,
+    ];
+    if percentages
+        .iter()
+        .flatten()
+        .any(|percentage| *percentage > 100)
+    {
+        error!("Fault injection percentages can not be greater than 100.");
+        return Err(
+            Error::new(ErrorKind::InvalidData, "invalid fault injection percentage").into(),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "fault-injection"))]
+fn check_fault_injection(_config: FaultInjectionConfig) -> Result<()> {
+    error!("Fault injection was configured but the \"fault-injection\" feature was not compiled in the Parsec binary.");
+    Err(Error::new(ErrorKind::InvalidData, "fault injection not compiled").into())
+}
+
+/// Refuse the configuration sections of the features not compiled in the Parsec binary, which
+/// would otherwise be ignored.
+fn check_compiled_features(config: &ServiceConfig) -> Result<()> {
+    let sections = [
+        (
+            "key_lifecycle",
+            config.key_lifecycle.is_some(),
+            "sqlite-kim",
+            cfg!(feature = "sqlite-kim"),
+        ),
+        (
+            "signature_counters",
+            config.signature_counters.is_some(),
+            "sqlite-kim",
+            cfg!(feature = "sqlite-kim"),
+        ),
+        (
+            "kim_write_behind",
+            config.kim_write_behind.is_some(),
+            "sqlite-kim",
+            cfg!(feature = "sqlite-kim"),
+        ),
+        (
+            "approvals",
+            config.approvals.is_some(),
+            "approvals",
+            cfg!(feature = "approvals"),
+        ),
+        (
+            "import_checks",
+            config.import_checks.is_some(),
+            "import-checks",
+            cfg!(feature = "import-checks"),
+        ),
+        (
+            "signing_policies",
+            config.signing_policies.is_some(),
+            "signing-policies",
+            cfg!(feature = "signing-policies"),
+        ),
+    ];
+    for (section, configured, feature, compiled) in sections.iter() {
+        if *configured && !*compiled {
+            error!(
+                "The {} section was configured but the \"{}\" feature was not compiled in the Parsec binary.",
+                section, feature
+            );
+            return Err(
+                Error::new(ErrorKind::InvalidData, format!("{} not compiled", feature)).into(),
+            );
         }
-        threadpool_builder.build()
     }
+
+    Ok(())
 }
 
 fn build_backend_handlers(
     mut providers: Vec<(ProviderId, Provider)>,
     authenticators: &[(AuthType, Authenticator)],
+    config: &ServiceConfig,
+    key_templates: Option<&Arc<KeyTemplates>>,
+    event_hooks: Option<&EventHooks>,
+    config_file: Option<&[u8]>,
 ) -> Result<HashMap<ProviderId, BackEndHandler>> {
+    let key_requirements = config
+        .key_requirements
+        .map(KeyRequirements::from)
+        .unwrap_or_default();
+    #[cfg(feature = "import-checks")]
+    let import_checks = config
+        .import_checks
+        .as_ref()
+        .map(|config| Arc::new(ImportChecks::new(config)));
+    let key_defaults = match &config.key_defaults {
+        Some(key_defaults) => Some(Arc::new(KeyDefaults::new(key_defaults)?)),
+        None => None,
+    };
+    let request_priority = config
+        .request_priority
+        .as_ref()
+        .map(|config| Arc::new(RequestPriority::from(config)));
+    let key_leases = config
+        .key_leases
+        .as_ref()
+        .map(|key_leases| Arc::new(KeyLeases::new(key_leases, authenticators[0].0)));
+    #[cfg(feature = "signing-policies")]
+    let signing_policies = match &config.signing_policies {
+        Some(signing_policies) => Some(Arc::new(SigningPolicies::new(
+            signing_policies,
+            authenticators[0].0,
+        )?)),
+        None => None,
+    };
+    #[cfg(feature = "approvals")]
+    let approvals = match &config.approvals {
+        Some(approvals) => Some(Arc::new(Approvals::new(approvals)?)),
+        None => None,
+    };
+    let result_cache_ttl = config
+        .core_settings
+        .result_cache_ttl
+        .filter(|ttl| *ttl > 0)
+        .map(Duration::from_secs);
     let mut map = HashMap::new();
 
     let mut core_provider_builder = CoreProviderBuilder::new()
-        .with_wire_protocol_version(WIRE_PROTOCOL_VERSION_MINOR, WIRE_PROTOCOL_VERSION_MAJOR);
+        .with_wire_protocol_version(wire_protocol::VERSION_MIN, wire_protocol::VERSION_MAJ);
 
     for (_auth_type, authenticator) in authenticators {
         let authenticator_info = authenticator
@@ -205,49 +899,133 @@ fn build_backend_handlers(
     for (provider_id, provider) in providers.drain(..) {
         core_provider_builder = core_provider_builder.with_provider(provider.clone());
 
-        let backend_handler = BackEndHandlerBuilder::new()
+        let mut backend_handler_builder = BackEndHandlerBuilder::new()
             .with_provider(provider)
             .with_converter(Box::from(ProtobufConverter {}))
             .with_provider_id(provider_id)
             .with_content_type(BodyType::Protobuf)
             .with_accept_type(BodyType::Protobuf)
-            .build()?;
+            .with_key_requirements(key_requirements);
+        if let Some(ecdsa_nonces) = config.ecdsa_nonces {
+            backend_handler_builder =
+                backend_handler_builder.with_ecdsa_nonces(EcdsaNonces::from(ecdsa_nonces));
+        }
+        #[cfg(feature = "import-checks")]
+        if let Some(import_checks) = &import_checks {
+            backend_handler_builder =
+                backend_handler_builder.with_import_checks(import_checks.clone());
+        }
+        if let Some(request_priority) = &request_priority {
+            backend_handler_builder =
+                backend_handler_builder.with_request_priority(request_priority.clone());
+        }
+        if let Some(key_leases) = &key_leases {
+            backend_handler_builder = backend_handler_builder.with_key_leases(key_leases.clone());
+        }
+        #[cfg(feature = "signing-policies")]
+        if let Some(signing_policies) = &signing_policies {
+            backend_handler_builder =
+                backend_handler_builder.with_signing_policies(signing_policies.clone());
+        }
+        #[cfg(feature = "approvals")]
+        if let Some(approvals) = &approvals {
+            backend_handler_builder = backend_handler_builder.with_approvals(approvals.clone());
+        }
+        if let Some(key_defaults) = &key_defaults {
+            backend_handler_builder =
+                backend_handler_builder.with_key_defaults(key_defaults.clone());
+        }
+        if let Some(key_templates) = key_templates {
+            backend_handler_builder =
+                backend_handler_builder.with_key_templates(key_templates.clone());
+        }
+        if let Some(random_limits) = config.random_limits {
+            backend_handler_builder = backend_handler_builder.with_random_limits(random_limits);
+        }
+        if let Some(aead_limits) = config.aead_limits {
+            backend_handler_builder = backend_handler_builder.with_aead_limits(aead_limits);
+        }
+        if let Some(result_cache_ttl) = result_cache_ttl {
+            backend_handler_builder =
+                backend_handler_builder.with_result_cache_ttl(result_cache_ttl);
+        }
+        if let Some(event_hooks) = event_hooks {
+            backend_handler_builder = backend_handler_builder.with_event_hooks(event_hooks.clone());
+        }
+        let backend_handler = backend_handler_builder.build()?;
         let _ = map.insert(provider_id, backend_handler);
     }
 
-    let core_provider_backend = BackEndHandlerBuilder::new()
-        .with_provider(Arc::new(core_provider_builder.build()?))
+    let core_provider = core_provider_builder.build()?;
+    if let (Some(pcr), Some(config_file)) = (config.core_settings.configuration_pcr, config_file) {
+        core_provider
+            .measure_configuration(ProviderId::Tpm, pcr, config_file)
+            .map_err(|status| {
+                error!(
+                    "Failed to measure the configuration into PCR {} of the TPM provider: {}.",
+                    pcr, status
+                );
+                Error::new(ErrorKind::Other, "configuration measurement failed")
+            })?;
+    }
+
+    let mut core_provider_backend = BackEndHandlerBuilder::new()
+        .with_provider(Arc::new(core_provider))
         .with_converter(Box::from(ProtobufConverter {}))
         .with_provider_id(ProviderId::Core)
         .with_content_type(BodyType::Protobuf)
-        .with_accept_type(BodyType::Protobuf)
-        .build()?;
+        .with_accept_type(BodyType::Protobuf);
+    if let Some(result_cache_ttl) = result_cache_ttl {
+        core_provider_backend = core_provider_backend.with_result_cache_ttl(result_cache_ttl);
+    }
+    #[cfg(feature = "approvals")]
+    if let Some(approvals) = &approvals {
+        core_provider_backend = core_provider_backend.with_approvals(approvals.clone());
+    }
+    let core_provider_backend = core_provider_backend.build()?;
 
     let _ = map.insert(ProviderId::Core, core_provider_backend);
 
     Ok(map)
 }
 
+/// IDs of the configured providers, by name
+fn provider_ids(configs: &[ProviderConfig]) -> std::io::Result<HashMap<String, ProviderId>> {
+    let mut provider_ids = HashMap::new();
+    for config in configs {
+        let _ = provider_ids.insert(config.provider_name()?, config.provider_id());
+    }
+    Ok(provider_ids)
+}
+
 fn build_providers(
     configs: &[ProviderConfig],
     kim_factorys: HashMap<String, KeyInfoManagerFactory>,
+    strict: bool,
+    on_demand: &[String],
 ) -> Result<Vec<(ProviderId, Provider)>> {
-    let mut providers = Vec::new();
-    let mut provider_names = HashSet::new();
+    let conflicts = provider_conflicts(configs);
+    if !conflicts.is_empty() {
+        error!(
+            "Conflicting providers found:\n{}\nThe \'[[provider]] name config option can be used to differentiate between providers of the same type.\nPlease check your config.toml file.",
+            conflicts.join("\n")
+        );
+        return Err(Error::new(ErrorKind::InvalidData, "conflicting providers found").into());
+    }
+
+    let provider_ids = provider_ids(configs)?;
+    if let Some(name) = on_demand
+        .iter()
+        .find(|name| !provider_ids.contains_key(*name))
+    {
+        format_error!("Provider to activate on demand was not found", name);
+        return Err(Error::new(ErrorKind::InvalidData, "provider to activate not found").into());
+    }
+
+    let mut providers: Vec<(ProviderId, Provider)> = Vec::new();
     for config in configs {
-        // Check for duplicate providers.
         let provider_id = config.provider_id();
 
-        // Check for duplicate provider names.
-        let provider_name = config.provider_name()?;
-        if provider_names.contains(&provider_name) {
-            error!("Duplicate provider names found.\n{} was found twice.\nThe \'[[provider]] name config option can be used to differentiate between providers of the same type.\nPlease check your config.toml file.", provider_name);
-            return Err(
-                Error::new(ErrorKind::InvalidData, "duplicate provider names found").into(),
-            );
-        }
-        let _ = provider_names.insert(provider_name.clone());
-
         let kim_factory = match kim_factorys.get(config.key_info_manager()) {
             Some(kim_factory) => kim_factory,
             None => {
@@ -262,8 +1040,42 @@ fn build_providers(
                 .into());
             }
         };
+        if on_demand.contains(&config.provider_name()?) {
+            let provider_identity = config.provider_identity()?;
+            // Built now so that the keys of the provider can be listed, and replicated, while it
+            // is inactive.
+            let kim_client = kim_factory.build_client(provider_identity.clone());
+            let activation: Activation = {
+                let (config, kim_factory, kim_client) =
+                    (config.clone(), kim_factory.clone(), kim_client.clone());
+                // The safety is checked by the fact that only one instance per provider type is
+                // enforced, and that the lazy provider builds it once.
+                Box::new(move || unsafe {
+                    get_provider(&config, &kim_factory, Some(kim_client.clone()))
+                })
+            };
+            info!("Provider {} is activated on demand.", provider_id);
+            providers.push((
+                provider_id,
+                Arc::new(LazyProvider::new(
+                    provider_id,
+                    provider_identity,
+                    kim_client,
+                    activation,
+                )),
+            ));
+            continue;
+        }
+
         // The safety is checked by the fact that only one instance per provider type is enforced.
-        let provider = match unsafe { get_provider(config, kim_factory) } {
+        let provider = match unsafe { get_provider(config, kim_factory, None) } {
+            Ok(None) if strict => {
+                error!(
+                    "Provider {} is skipped but all providers must be created in strict mode.",
+                    provider_id
+                );
+                return Err(Error::new(ErrorKind::Other, "provider skipped in strict mode").into());
+            }
             Ok(None) => {
                 warn!("Provider {} is skipped.", provider_id);
                 continue;
@@ -283,6 +1095,93 @@ fn build_providers(
     Ok(providers)
 }
 
+/// Describe the pairs of provider configurations which cannot be used together: providers with
+/// the same name, of the same type (and so the same UUID), serving the same provider ID, or using
+/// the same PKCS 11 token or the same TPM. Each conflict names the two `[[provider]]` sections
+/// involved, by position.
+///
+/// The PIV provider serves the PKCS 11 provider ID and the test provider the Mbed Crypto one, the
+/// interface having no ID of their own, so they can not be used alongside those providers.
+fn provider_conflicts(configs: &[ProviderConfig]) -> Vec<String> {
+    let section = |index: usize, config: &ProviderConfig| match config.provider_name() {
+        Ok(name) => format!(
+            "[[provider]] #{} ({}, \"{}\")",
+            index + 1,
+            config.provider_id(),
+            name
+        ),
+        Err(_) => format!("[[provider]] #{} ({})", index + 1, config.provider_id()),
+    };
+    let mut conflicts = Vec::new();
+    for (i, first) in configs.iter().enumerate() {
+        for (j, second) in configs.iter().enumerate().skip(i + 1) {
+            let mut reasons = Vec::new();
+            if let (Ok(first_name), Ok(second_name)) =
+                (first.provider_name(), second.provider_name())
+            {
+                if first_name == second_name {
+                    reasons.push("the same name");
+                }
+            }
+            if first.provider_id() == second.provider_id() {
+                reasons.push(match (first, second) {
+                    (ProviderConfig::Piv { .. }, ProviderConfig::Pkcs11 { .. })
+                    | (ProviderConfig::Pkcs11 { .. }, ProviderConfig::Piv { .. }) => {
+                        "the same provider ID (the PIV provider serves the PKCS 11 provider ID and can not be used with a PKCS 11 provider)"
+                    }
+                    (ProviderConfig::Test { .. }, ProviderConfig::MbedCrypto { .. })
+                    | (ProviderConfig::MbedCrypto { .. }, ProviderConfig::Test { .. }) => {
+                        "the same provider ID (the test provider serves the Mbed Crypto provider ID and can not be used with an Mbed Crypto provider)"
+                    }
+                    _ => "the same provider type",
+                });
+            }
+            match (first, second) {
+                (
+                    ProviderConfig::Pkcs11 {
+                        library_path: first_library,
+                        slot_number: first_slot,
+                        serial_number: first_serial,
+                        token_label: first_label,
+                        ..
+                    },
+                    ProviderConfig::Pkcs11 {
+                        library_path: second_library,
+                        slot_number: second_slot,
+                        serial_number: second_serial,
+                        token_label: second_label,
+                        ..
+                    },
+                ) if first_library == second_library
+                    && first_slot == second_slot
+                    && first_serial == second_serial
+                    && first_label == second_label =>
+                {
+                    reasons.push("the same PKCS 11 token")
+                }
+                (
+                    ProviderConfig::Tpm {
+                        tcti: first_tcti, ..
+                    },
+                    ProviderConfig::Tpm {
+                        tcti: second_tcti, ..
+                    },
+                ) if first_tcti == second_tcti => reasons.push("the same TCTI"),
+                _ => (),
+            }
+            if !reasons.is_empty() {
+                conflicts.push(format!(
+                    "{} and {} have {}.",
+                    section(i, first),
+                    section(j, second),
+                    reasons.join(", ")
+                ));
+            }
+        }
+    }
+    conflicts
+}
+
 // This cfg_attr is used to allow the fact that key_info_manager is not used when there is no
 // providers.
 #[cfg_attr(
@@ -291,39 +1190,60 @@ fn build_providers(
         feature = "pkcs11-provider",
         feature = "tpm-provider",
         feature = "cryptoauthlib-provider",
-        feature = "trusted-service-provider"
+        feature = "piv-provider",
+        feature = "forwarding-provider",
+        feature = "external-provider",
+        feature = "trusted-service-provider",
+        feature = "test-provider"
     )),
     allow(unused_variables),
     allow(clippy::match_single_binding)
 )]
-// Ok(None) is returned when the provider is skipped by configuration.
+// Ok(None) is returned when the provider is skipped by configuration. The client given is used by
+// providers activated on demand, whose client was built with the service.
 unsafe fn get_provider(
     config: &ProviderConfig,
     kim_factory: &KeyInfoManagerFactory,
+    kim_client: Option<KeyInfoManagerClient>,
 ) -> Result<Option<Provider>> {
+    let build_client = |provider_identity| match &kim_client {
+        Some(kim_client) => kim_client.clone(),
+        None => kim_factory.build_client(provider_identity),
+    };
     match config {
         #[cfg(feature = "mbed-crypto-provider")]
-        ProviderConfig::MbedCrypto { .. } => {
+        ProviderConfig::MbedCrypto {
+            key_store_dir,
+            require_encrypted_key_store,
+            side_channel_hardening,
+            ..
+        } => {
             info!("Creating a Mbed Crypto Provider.");
             let provider_identity = ProviderIdentity::new(
                 MbedCryptoProvider::PROVIDER_UUID.to_string(),
                 config.provider_name()?,
             );
-            Ok(Some(Arc::new(
-                MbedCryptoProviderBuilder::new()
-                    .with_key_info_store(kim_factory.build_client(provider_identity))
-                    .with_provider_name(config.provider_name()?)
-                    .build()?,
-            )))
+            let mut builder = MbedCryptoProviderBuilder::new()
+                .with_key_info_store(build_client(provider_identity))
+                .with_provider_name(config.provider_name()?)
+                .with_encrypted_key_store(require_encrypted_key_store.unwrap_or(false))
+                .with_side_channel_hardening(side_channel_hardening.unwrap_or_default());
+            if let Some(key_store_dir) = key_store_dir {
+                builder = builder.with_key_store_dir(Path::new(key_store_dir).to_path_buf());
+            }
+            Ok(Some(Arc::new(builder.build()?)))
         }
         #[cfg(feature = "pkcs11-provider")]
         ProviderConfig::Pkcs11 {
             library_path,
             slot_number,
             serial_number,
+            token_label,
+            token_rediscovery_interval,
             user_pin,
             software_public_operations,
             allow_export,
+            wrapping_key_label,
             ..
         } => {
             info!("Creating a PKCS 11 Provider.");
@@ -333,23 +1253,33 @@ unsafe fn get_provider(
             );
             Ok(Some(Arc::new(
                 Pkcs11ProviderBuilder::new()
-                    .with_key_info_store(kim_factory.build_client(provider_identity))
+                    .with_key_info_store(build_client(provider_identity))
                     .with_provider_name(config.provider_name()?)
                     .with_pkcs11_library_path(library_path.clone())
                     .with_slot_number(*slot_number)
                     .with_serial_number(serial_number.clone())
+                    .with_token_label(token_label.clone())
+                    .with_token_rediscovery_interval(*token_rediscovery_interval)
                     .with_user_pin(user_pin.clone())
                     .with_software_public_operations(*software_public_operations)
                     .with_allow_export(*allow_export)
+                    .with_wrapping_key_label(wrapping_key_label.clone())
                     .build()?,
             )))
         }
         #[cfg(feature = "tpm-provider")]
         ProviderConfig::Tpm {
             tcti,
+            tcti_fallbacks,
             owner_hierarchy_auth,
             endorsement_hierarchy_auth,
             skip_if_no_tpm,
+            context_pool_size,
+            key_pool,
+            software_verification,
+            auth_value_len,
+            auth_value_secret_path,
+            device_identity,
             ..
         } => {
             use std::str::FromStr;
@@ -361,26 +1291,51 @@ unsafe fn get_provider(
                 config.provider_name()?,
             );
 
-            let tcti_name_conf = TctiNameConf::from_str(tcti).map_err(|_| {
-                std::io::Error::new(ErrorKind::InvalidData, "Invalid TCTI configuration string")
-            })?;
-            if *skip_if_no_tpm == Some(true) {
+            let tctis: Vec<&str> = std::iter::once(tcti)
+                .chain(tcti_fallbacks.iter().flatten())
+                .map(String::as_str)
+                .collect();
+            let mut tcti_name_confs = Vec::with_capacity(tctis.len());
+            for tcti in &tctis {
+                tcti_name_confs.push(TctiNameConf::from_str(tcti).map_err(|_| {
+                    std::io::Error::new(ErrorKind::InvalidData, "Invalid TCTI configuration string")
+                })?);
+            }
+            // Without fallbacks, the TCTI is only tried beforehand to know if the provider is to
+            // be skipped.
+            let mut tcti = tctis[0];
+            if tctis.len() > 1 || *skip_if_no_tpm == Some(true) {
                 // TODO: When the TPM Provider uses the new TctiContext, pass it directly to the
                 // builder.
-                let _tcti_context = match TctiContext::initialize(tcti_name_conf) {
-                    Ok(tcti_context) => tcti_context,
-                    Err(e) => {
-                        format_error!("Error creating a TCTI context", e);
-                        // We make the assumption that the TCTI Name Configuration is correct
-                        // and that if we failed creating a TCTI Contecxt it means that there
-                        // is no TPM support on the platform.
-                        return Ok(None);
+                let available = tctis.iter().zip(tcti_name_confs).find(|(tcti, name_conf)| {
+                    match TctiContext::initialize(name_conf.clone()) {
+                        Ok(_tcti_context) => true,
+                        Err(e) => {
+                            format_error!(&format!("Error creating a {} TCTI context", tcti), e);
+                            false
+                        }
                     }
-                };
+                });
+                match available {
+                    Some((available, _)) => tcti = available,
+                    // We make the assumption that the TCTI Name Configurations are correct and
+                    // that if we failed creating all the TCTI Contexts it means that there is no
+                    // TPM support on the platform.
+                    None if *skip_if_no_tpm == Some(true) => return Ok(None),
+                    None => {
+                        error!("None of the configured TCTIs can be used.");
+                        return Err(
+                            std::io::Error::new(ErrorKind::Other, "no TCTI available").into()
+                        );
+                    }
+                }
+                if tctis.len() > 1 {
+                    info!("Using the {} TCTI.", tcti);
+                }
             }
 
             let mut builder = TpmProviderBuilder::new()
-                .with_key_info_store(kim_factory.build_client(provider_identity))
+                .with_key_info_store(build_client(provider_identity))
                 .with_tcti(tcti)
                 .with_provider_name(config.provider_name()?)
                 .with_owner_hierarchy_auth(owner_hierarchy_auth.clone());
@@ -389,6 +1344,24 @@ unsafe fn get_provider(
                     endorsement_hierarchy_auth.as_ref().unwrap().clone(),
                 );
             }
+            if let Some(context_pool_size) = context_pool_size {
+                builder = builder.with_context_pool_size(*context_pool_size);
+            }
+            if let Some(key_pool) = key_pool {
+                builder = builder.with_key_pool(key_pool.clone());
+            }
+            if let Some(software_verification) = software_verification {
+                builder = builder.with_software_verification(*software_verification);
+            }
+            if let Some(auth_value_len) = auth_value_len {
+                builder = builder.with_auth_value_len(*auth_value_len);
+            }
+            if let Some(auth_value_secret_path) = auth_value_secret_path {
+                builder = builder.with_auth_value_secret_path(auth_value_secret_path.clone());
+            }
+            if let Some(device_identity) = device_identity {
+                builder = builder.with_device_identity(device_identity.clone());
+            }
             Ok(Some(Arc::new(builder.build()?)))
         }
         #[cfg(feature = "cryptoauthlib-provider")]
@@ -401,6 +1374,9 @@ unsafe fn get_provider(
             bus,
             baud,
             access_key_file_name,
+            slot_map_file_name,
+            io_protection_key_slot,
+            signature_emulation,
             ..
         } => {
             info!("Creating a CryptoAuthentication Library Provider.");
@@ -410,7 +1386,7 @@ unsafe fn get_provider(
             );
             Ok(Some(Arc::new(
                 CryptoAuthLibProviderBuilder::new()
-                    .with_key_info_store(kim_factory.build_client(provider_identity))
+                    .with_key_info_store(build_client(provider_identity))
                     .with_provider_name(config.provider_name()?)
                     .with_device_type(device_type.to_string())
                     .with_iface_type(iface_type.to_string())
@@ -420,9 +1396,79 @@ unsafe fn get_provider(
                     .with_bus(*bus)
                     .with_baud(*baud)
                     .with_access_key_file(access_key_file_name.clone())
+                    .with_slot_map_file(slot_map_file_name.clone())
+                    .with_io_protection_key_slot(*io_protection_key_slot)
+                    .with_signature_emulation(*signature_emulation)
+                    .build()?,
+            )))
+        }
+        #[cfg(feature = "piv-provider")]
+        ProviderConfig::Piv {
+            library_path,
+            reader,
+            pin,
+            key,
+            ..
+        } => {
+            info!("Creating a PIV Provider.");
+            let provider_identity = ProviderIdentity::new(
+                PivProvider::PROVIDER_UUID.to_string(),
+                config.provider_name()?,
+            );
+            let mut builder = PivProviderBuilder::new()
+                .with_key_info_store(build_client(provider_identity))
+                .with_provider_name(config.provider_name()?)
+                .with_reader(reader.clone())
+                .with_pin(pin.clone())
+                .with_keys(key.clone().unwrap_or_default())
+                .with_auth_type(kim_factory.default_auth_type());
+            if let Some(library_path) = library_path {
+                builder = builder.with_library_path(library_path.clone());
+            }
+            Ok(Some(Arc::new(builder.build()?)))
+        }
+        #[cfg(feature = "forwarding-provider")]
+        ProviderConfig::Forwarding {
+            socket_path,
+            remote_provider,
+            application,
+            admin_application,
+            timeout,
+            ..
+        } => {
+            info!("Creating a Forwarding Provider.");
+            Ok(Some(Arc::new(
+                ForwardingProviderBuilder::new()
+                    .with_provider_name(config.provider_name()?)
+                    .with_provider_id((*remote_provider).into())
+                    .with_socket_path(socket_path.clone())
+                    .with_applications(application.clone().unwrap_or_default())
+                    .with_admin_application(admin_application.clone())
+                    .with_timeout(*timeout)
                     .build()?,
             )))
         }
+        #[cfg(feature = "external-provider")]
+        ProviderConfig::External {
+            socket_path,
+            command,
+            provider_id,
+            timeout,
+            ..
+        } => {
+            info!("Creating an External Provider.");
+            let mut builder = ExternalProviderBuilder::new()
+                .with_provider_name(config.provider_name()?)
+                .with_provider_id((*provider_id).into())
+                .with_timeout(*timeout);
+            if let Some(socket_path) = socket_path {
+                builder = builder.with_socket_path(socket_path.clone());
+            }
+            if let Some(command) = command {
+                builder = builder.with_command(command.clone());
+            }
+            Ok(Some(Arc::new(builder.build()?)))
+        }
         #[cfg(feature = "trusted-service-provider")]
         ProviderConfig::TrustedService { .. } => {
             info!("Creating a Trusted Service Provider.");
@@ -432,8 +1478,34 @@ unsafe fn get_provider(
             );
             Ok(Some(Arc::new(
                 TrustedServiceProviderBuilder::new()
-                    .with_key_info_store(kim_factory.build_client(provider_identity))
+                    .with_key_info_store(build_client(provider_identity))
+                    .with_provider_name(config.provider_name()?)
+                    .build()?,
+            )))
+        }
+        #[cfg(feature = "test-provider")]
+        ProviderConfig::Test {
+            seed,
+            fail_next,
+            delay,
+            scenario_path,
+            scenario_report_path,
+            ..
+        } => {
+            info!("Creating a test Provider.");
+            let provider_identity = ProviderIdentity::new(
+                TestProvider::PROVIDER_UUID.to_string(),
+                config.provider_name()?,
+            );
+            Ok(Some(Arc::new(
+                TestProviderBuilder::new()
+                    .with_key_info_store(build_client(provider_identity))
                     .with_provider_name(config.provider_name()?)
+                    .with_seed(*seed)
+                    .with_fail_next(*fail_next)
+                    .with_delay(*delay)
+                    .with_scenario_path(scenario_path.clone())
+                    .with_scenario_report_path(scenario_report_path.clone())
                     .build()?,
             )))
         }
@@ -442,7 +1514,11 @@ unsafe fn get_provider(
             feature = "pkcs11-provider",
             feature = "tpm-provider",
             feature = "cryptoauthlib-provider",
-            feature = "trusted-service-provider"
+            feature = "piv-provider",
+            feature = "forwarding-provider",
+            feature = "external-provider",
+            feature = "trusted-service-provider",
+            feature = "test-provider"
         )))]
         _ => {
             error!(
@@ -470,6 +1546,30 @@ fn get_key_info_manager_builders(
 }
 
 // Allowed to simplify the cfg blocks
+/// Build the main authenticator, first in the list, and those of the additional sockets.
+fn build_all_authenticators(config: &ServiceConfig) -> Result<Vec<(AuthType, Authenticator)>> {
+    let mut authenticators = build_authenticators(&config.authenticator)?;
+    let socket_authenticators = config
+        .listener
+        .additional_socket
+        .iter()
+        .flatten()
+        .filter_map(|socket| socket.authenticator.as_ref());
+    for authenticator_config in socket_authenticators {
+        // Sockets restricted to an authenticator already configured share it.
+        let auth_type = authenticator_config.auth_type();
+        if authenticators
+            .iter()
+            .any(|(existing, _)| *existing == auth_type)
+        {
+            continue;
+        }
+        authenticators.append(&mut build_authenticators(authenticator_config)?);
+    }
+
+    Ok(authenticators)
+}
+
 #[allow(clippy::unnecessary_wraps)]
 fn build_authenticators(config: &AuthenticatorConfig) -> Result<Vec<(AuthType, Authenticator)>> {
     // The authenticators supported by the Parsec service.
@@ -488,18 +1588,35 @@ fn build_authenticators(config: &AuthenticatorConfig) -> Result<Vec<(AuthType, A
             )),
         )),
         #[cfg(feature = "unix-peer-credentials-authenticator")]
-        AuthenticatorConfig::UnixPeerCredentials { admins } => authenticators.push((
-            AuthType::UnixPeerCredentials,
-            Box::from(UnixPeerCredentialsAuthenticator::new(
-                admins.as_ref().cloned().unwrap_or_default(),
-            )),
-        )),
+        AuthenticatorConfig::UnixPeerCredentials {
+            admins,
+            security_contexts,
+            require_security_context,
+            container_identity,
+        } => {
+            let mut unix_peer_credentials_authenticator =
+                UnixPeerCredentialsAuthenticator::new(admins.as_ref().cloned().unwrap_or_default())
+                    .with_security_contexts(
+                        security_contexts.as_deref().unwrap_or_default(),
+                        require_security_context.unwrap_or(false),
+                    )?;
+            if let Some(container_identity) = container_identity {
+                unix_peer_credentials_authenticator =
+                    unix_peer_credentials_authenticator.with_container_identity(container_identity);
+            }
+            authenticators.push((
+                AuthType::UnixPeerCredentials,
+                Box::from(unix_peer_credentials_authenticator),
+            ))
+        }
         #[cfg(feature = "jwt-svid-authenticator")]
         AuthenticatorConfig::JwtSvid {
             workload_endpoint,
             admins,
+            cache_ttl,
+            cache_size,
         } => {
-            let jwt_svid_authenticator = match JwtSvidAuthenticator::new(
+            let mut jwt_svid_authenticator = match JwtSvidAuthenticator::new(
                 workload_endpoint.to_string(),
                 admins.as_ref().cloned().unwrap_or_default(),
             ) {
@@ -512,12 +1629,48 @@ fn build_authenticators(config: &AuthenticatorConfig) -> Result<Vec<(AuthType, A
                     .into())
                 }
             };
+            if let Some(cache_ttl) = cache_ttl.filter(|ttl| *ttl > 0) {
+                jwt_svid_authenticator = jwt_svid_authenticator.with_cache(
+                    cache_size.unwrap_or(DEFAULT_AUTH_CACHE_SIZE),
+                    Duration::from_secs(cache_ttl),
+                );
+            }
             authenticators.push((AuthType::JwtSvid, Box::from(jwt_svid_authenticator)))
         }
+        #[cfg(feature = "attestation-token-authenticator")]
+        AuthenticatorConfig::AttestationToken {
+            keys,
+            max_token_lifetime,
+            admins,
+        } => authenticators.push((
+            AuthType::Jwt,
+            Box::from(AttestationTokenAuthenticator::new(
+                keys,
+                Duration::from_secs(max_token_lifetime.unwrap_or(DEFAULT_MAX_TOKEN_LIFETIME)),
+                admins.as_ref().cloned().unwrap_or_default(),
+            )?),
+        )),
+        #[cfg(feature = "external-authenticator")]
+        AuthenticatorConfig::External {
+            socket_path,
+            timeout,
+            admins,
+            ..
+        } => authenticators.push((
+            config.auth_type(),
+            Box::from(ExternalAuthenticator::new(
+                socket_path.into(),
+                config.auth_type(),
+                Duration::from_millis(timeout.unwrap_or(DEFAULT_PLUGIN_TIMEOUT)),
+                admins.as_ref().cloned().unwrap_or_default(),
+            )),
+        )),
         #[cfg(not(all(
             feature = "direct-authenticator",
             feature = "unix-peer-credentials-authenticator",
             feature = "jwt-svid-authenticator",
+            feature = "attestation-token-authenticator",
+            feature = "external-authenticator",
         )))]
         _ => {
             error!(
@@ -0,0 +1,426 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Service status
+//!
+//! Counters updated while requests are handled, from which a report of the health of the service
+//! can be produced: uptime, number of requests handled and failed, operations and failures per
+//! provider, Key Info Manager writes, random bytes generated and the applications refused more of
+//! them, thread pool usage and a fingerprint of the configuration in use. The service logs that
+//! report when it receives the `SIGUSR1` signal.
+use crate::utils::executor::Executor;
+use parsec_interface::requests::ProviderId;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Maximum number of applications counted in the refusals of random bytes
+const MAX_RANDOM_CONSUMERS: usize = 32;
+
+/// Number of values of `ProviderId`
+const PROVIDER_COUNT: usize = 6;
+
+const ALL_PROVIDERS: [ProviderId; PROVIDER_COUNT] = [
+    ProviderId::Core,
+    ProviderId::MbedCrypto,
+    ProviderId::Pkcs11,
+    ProviderId::Tpm,
+    ProviderId::TrustedService,
+    ProviderId::CryptoAuthLib,
+];
+
+#[derive(Debug)]
+struct ProviderCounters {
+    operations: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl ProviderCounters {
+    const fn new() -> Self {
+        ProviderCounters {
+            operations: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Counters shared by the whole service
+#[derive(Debug)]
+pub struct ServiceStatus {
+    started: Mutex<Option<Instant>>,
+    config_digest: AtomicU64,
+    requests: AtomicU64,
+    failed_requests: AtomicU64,
+    providers: [ProviderCounters; PROVIDER_COUNT],
+    key_info_inserts: AtomicU64,
+    key_info_removals: AtomicU64,
+    key_info_write_failures: AtomicU64,
+    random_bytes: AtomicU64,
+    random_refusals: Mutex<Vec<(String, u64)>>,
+}
+
+static SERVICE_STATUS: ServiceStatus = ServiceStatus::new();
+
+/// Kind of write made to a Key Info Manager
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyInfoWrite {
+    /// Key information inserted or replaced
+    Insert,
+    /// Key information removed
+    Remove,
+}
+
+impl ServiceStatus {
+    const fn new() -> Self {
+        ServiceStatus {
+            started: Mutex::new(None),
+            config_digest: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            failed_requests: AtomicU64::new(0),
+            providers: [
+                ProviderCounters::new(),
+                ProviderCounters::new(),
+                ProviderCounters::new(),
+                ProviderCounters::new(),
+                ProviderCounters::new(),
+                ProviderCounters::new(),
+            ],
+            key_info_inserts: AtomicU64::new(0),
+            key_info_removals: AtomicU64::new(0),
+            key_info_write_failures: AtomicU64::new(0),
+            random_bytes: AtomicU64::new(0),
+            random_refusals: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn start(&self) {
+        let mut started = self.started.lock().expect("Service status lock poisoned");
+        if started.is_none() {
+            *started = Some(Instant::now());
+        }
+    }
+
+    fn configure(&self, config_file: &str) {
+        self.config_digest
+            .store(fingerprint(config_file.as_bytes()), Ordering::Relaxed);
+    }
+
+    fn count_request(&self, success: bool) {
+        let _ = self.requests.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            let _ = self.failed_requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn count_provider_operation(&self, provider_id: ProviderId, success: bool) {
+        let counters = &self.providers[provider_id as usize];
+        let _ = counters.operations.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            let _ = counters.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn count_key_info_write(&self, write: KeyInfoWrite, success: bool) {
+        if !success {
+            let _ = self.key_info_write_failures.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let counter = match write {
+            KeyInfoWrite::Insert => &self.key_info_inserts,
+            KeyInfoWrite::Remove => &self.key_info_removals,
+        };
+        let _ = counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn count_random_bytes(&self, size: usize) {
+        let _ = self.random_bytes.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    fn count_random_refusal(&self, application: &str) {
+        let mut refusals = self
+            .random_refusals
+            .lock()
+            .expect("Service status lock poisoned");
+        if let Some((_, count)) = refusals.iter_mut().find(|(name, _)| name == application) {
+            *count += 1;
+        } else if refusals.len() < MAX_RANDOM_CONSUMERS {
+            refusals.push((application.to_string(), 1));
+        }
+    }
+
+    fn snapshot(&self, executor: &Executor) -> StatusReport {
+        let uptime = self
+            .started
+            .lock()
+            .expect("Service status lock poisoned")
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        let providers = ALL_PROVIDERS
+            .iter()
+            .map(|provider_id| {
+                let counters = &self.providers[*provider_id as usize];
+                (
+                    *provider_id,
+                    counters.operations.load(Ordering::Relaxed),
+                    counters.failures.load(Ordering::Relaxed),
+                )
+            })
+            .filter(|(_, operations, _)| *operations > 0)
+            .collect();
+
+        StatusReport {
+            uptime,
+            config_digest: self.config_digest.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            failed_requests: self.failed_requests.load(Ordering::Relaxed),
+            providers,
+            key_info_inserts: self.key_info_inserts.load(Ordering::Relaxed),
+            key_info_removals: self.key_info_removals.load(Ordering::Relaxed),
+            key_info_write_failures: self.key_info_write_failures.load(Ordering::Relaxed),
+            random_bytes: self.random_bytes.load(Ordering::Relaxed),
+            random_refusals: self
+                .random_refusals
+                .lock()
+                .expect("Service status lock poisoned")
+                .clone(),
+            active_threads: executor.active_count(),
+            queued_requests: executor.queued_count(),
+            threads: executor.thread_count(),
+            max_threads: executor.max_count(),
+        }
+    }
+
+    /// Record the start of the service, from which the uptime is computed. Only the first call
+    /// has an effect, reloading the configuration does not reset the uptime.
+    pub fn mark_started() {
+        SERVICE_STATUS.start()
+    }
+
+    /// Record the fingerprint of the configuration file in use.
+    pub fn set_config(config_file: &str) {
+        SERVICE_STATUS.configure(config_file)
+    }
+
+    /// Record a request handled, and whether it succeeded.
+    pub fn record_request(success: bool) {
+        SERVICE_STATUS.count_request(success)
+    }
+
+    /// Record an operation handed to a provider, and whether it succeeded.
+    pub fn record_provider_operation(provider_id: ProviderId, success: bool) {
+        SERVICE_STATUS.count_provider_operation(provider_id, success)
+    }
+
+    /// Record a write to a Key Info Manager, and whether it succeeded.
+    pub fn record_key_info_write(write: KeyInfoWrite, success: bool) {
+        SERVICE_STATUS.count_key_info_write(write, success)
+    }
+
+    /// Record random bytes generated for a client.
+    pub fn record_random_bytes(size: usize) {
+        SERVICE_STATUS.count_random_bytes(size)
+    }
+
+    /// Record a request for random bytes refused to an application because of the limits.
+    pub fn record_random_refusal(application: &str) {
+        SERVICE_STATUS.count_random_refusal(application)
+    }
+
+    /// Take a snapshot of the status of the service, given the executor handling requests.
+    pub fn report(executor: &Executor) -> StatusReport {
+        SERVICE_STATUS.snapshot(executor)
+    }
+}
+
+/// 64-bit FNV-1a hash, used to tell configurations apart. It is stable across builds, which
+/// makes it possible to compare nodes, but it is not meant to resist tampering.
+fn fingerprint(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Snapshot of the status of the service
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusReport {
+    /// Time since the service started
+    pub uptime: Duration,
+    /// Fingerprint of the configuration file in use
+    pub config_digest: u64,
+    /// Number of requests handled
+    pub requests: u64,
+    /// Number of requests for which an error was returned
+    pub failed_requests: u64,
+    /// Number of operations and of failures of each provider that handled operations
+    pub providers: Vec<(ProviderId, u64, u64)>,
+    /// Number of key information records inserted in the Key Info Managers
+    pub key_info_inserts: u64,
+    /// Number of key information records removed from the Key Info Managers
+    pub key_info_removals: u64,
+    /// Number of failed writes to the Key Info Managers
+    pub key_info_write_failures: u64,
+    /// Number of random bytes generated for clients
+    pub random_bytes: u64,
+    /// Number of requests for random bytes refused to each application because of the limits
+    pub random_refusals: Vec<(String, u64)>,
+    /// Number of threads currently handling a request
+    pub active_threads: usize,
+    /// Number of requests waiting for a thread
+    pub queued_requests: usize,
+    /// Number of threads started
+    pub threads: usize,
+    /// Maximum number of threads
+    pub max_threads: usize,
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "uptime: {} s", self.uptime.as_secs())?;
+        writeln!(f, "configuration fingerprint: {:016x}", self.config_digest)?;
+        writeln!(
+            f,
+            "requests: {} handled, {} failed",
+            self.requests, self.failed_requests
+        )?;
+        writeln!(
+            f,
+            "threads: {} active out of {} started (at most {}), {} requests queued",
+            self.active_threads, self.threads, self.max_threads, self.queued_requests
+        )?;
+        writeln!(
+            f,
+            "key info managers: {} inserts, {} removals, {} failed writes",
+            self.key_info_inserts, self.key_info_removals, self.key_info_write_failures
+        )?;
+        writeln!(f, "random bytes generated: {}", self.random_bytes)?;
+        for (application, refusals) in &self.random_refusals {
+            writeln!(
+                f,
+                "random bytes refused to \"{}\": {} requests",
+                application, refusals
+            )?;
+        }
+        for (provider_id, operations, failures) in &self.providers {
+            writeln!(
+                f,
+                "{}: {} operations, {} failed",
+                provider_id, operations, failures
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    // Local counters, as the other tests of the process update the ones of the service.
+    fn report(status: &ServiceStatus) -> StatusReport {
+        status.snapshot(&Executor::new(1, 2).unwrap())
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        assert_eq!(fingerprint(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fingerprint(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(fingerprint(b"[core_settings]"), fingerprint(b"[listener]"));
+    }
+
+    #[test]
+    fn uptime_is_kept_on_reload() {
+        let status = ServiceStatus::new();
+        assert_eq!(report(&status).uptime, Duration::default());
+        status.start();
+        thread::sleep(Duration::from_millis(20));
+        status.start();
+        assert!(report(&status).uptime >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn configuration_fingerprint() {
+        let status = ServiceStatus::new();
+        status.configure("[core_settings]\n");
+        assert_eq!(
+            report(&status).config_digest,
+            fingerprint(b"[core_settings]\n")
+        );
+    }
+
+    #[test]
+    fn requests_are_counted() {
+        let status = ServiceStatus::new();
+        status.count_request(true);
+        status.count_request(false);
+        status.count_request(true);
+        let report = report(&status);
+        assert_eq!(report.requests, 3);
+        assert_eq!(report.failed_requests, 1);
+    }
+
+    #[test]
+    fn providers_with_operations_are_reported() {
+        let status = ServiceStatus::new();
+        status.count_provider_operation(ProviderId::Tpm, true);
+        status.count_provider_operation(ProviderId::Tpm, false);
+        status.count_provider_operation(ProviderId::Core, true);
+        assert_eq!(
+            report(&status).providers,
+            [(ProviderId::Core, 1, 0), (ProviderId::Tpm, 2, 1)]
+        );
+    }
+
+    #[test]
+    fn key_info_writes_are_counted() {
+        let status = ServiceStatus::new();
+        status.count_key_info_write(KeyInfoWrite::Insert, true);
+        status.count_key_info_write(KeyInfoWrite::Insert, true);
+        status.count_key_info_write(KeyInfoWrite::Remove, true);
+        status.count_key_info_write(KeyInfoWrite::Remove, false);
+        let report = report(&status);
+        assert_eq!(report.key_info_inserts, 2);
+        assert_eq!(report.key_info_removals, 1);
+        assert_eq!(report.key_info_write_failures, 1);
+    }
+
+    #[test]
+    fn random_refusals_are_bounded() {
+        let status = ServiceStatus::new();
+        status.count_random_bytes(32);
+        status.count_random_bytes(16);
+        for i in 0..=MAX_RANDOM_CONSUMERS {
+            status.count_random_refusal(&format!("app-{}", i));
+        }
+        status.count_random_refusal("app-0");
+        let report = report(&status);
+        assert_eq!(report.random_bytes, 48);
+        assert_eq!(report.random_refusals.len(), MAX_RANDOM_CONSUMERS);
+        assert_eq!(report.random_refusals[0], (String::from("app-0"), 2));
+        assert!(!report
+            .random_refusals
+            .iter()
+            .any(|(name, _)| *name == format!("app-{}", MAX_RANDOM_CONSUMERS)));
+    }
+
+    #[test]
+    fn threads_of_the_executor() {
+        let report = report(&ServiceStatus::new());
+        assert_eq!(report.threads, 1);
+        assert_eq!(report.max_threads, 2);
+        assert_eq!(report.active_threads, 0);
+        assert_eq!(report.queued_requests, 0);
+    }
+
+    #[test]
+    fn display() {
+        let status = ServiceStatus::new();
+        status.count_request(false);
+        status.count_random_refusal("app");
+        status.count_provider_operation(ProviderId::Tpm, true);
+        let displayed = report(&status).to_string();
+        assert!(displayed.contains("requests: 1 handled, 1 failed\n"));
+        assert!(displayed.contains("random bytes refused to \"app\": 1 requests\n"));
+        assert!(displayed.ends_with(&format!("{}: 1 operations, 0 failed\n", ProviderId::Tpm)));
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Helpers shared by the unit tests
+#[cfg(any(
+    feature = "jws-signing",
+    feature = "cose-signing",
+    feature = "tls13-signing"
+))]
+use parsec_interface::operations::{
+    psa_algorithm::{Algorithm, AsymmetricSignature},
+    psa_key_attributes::{Attributes, Lifetime, Policy, Type, UsageFlags},
+};
+
+/// Bytes written in hexadecimal, whitespace being ignored
+pub fn hex(hex: &str) -> Vec<u8> {
+    let hex: String = hex.split_whitespace().collect();
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+        .collect()
+}
+
+/// Attributes of a persistent key permitted to sign with the algorithm
+#[cfg(any(
+    feature = "jws-signing",
+    feature = "cose-signing",
+    feature = "tls13-signing"
+))]
+pub fn attributes(key_type: Type, bits: usize, alg: AsymmetricSignature) -> Attributes {
+    Attributes {
+        lifetime: Lifetime::Persistent,
+        key_type,
+        bits,
+        policy: Policy {
+            usage_flags: UsageFlags::default(),
+            permitted_algorithms: Algorithm::AsymmetricSignature(alg),
+        },
+    }
+}
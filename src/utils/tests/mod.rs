@@ -48,20 +48,27 @@ fn providers_same_type_default_name() {
 
     let expected_error = anyhow!(Error::new(
         ErrorKind::InvalidData,
-        "duplicate provider names found"
+        "conflicting providers found"
     ));
 
     let err = ServiceBuilder::build_service(&config).unwrap_err();
     assert_eq!(format!("{:#?}", err), format!("{:#?}", expected_error));
 }
 
-/// Check that the service starts when two providers of the same type have different names.
+/// Check that the service throws an error when two providers of the same type have different
+/// names, as they would share a provider ID.
 #[test]
 fn providers_same_type_different_name() {
     let config_path: String = "providers_same_type_different_name.toml".to_string();
     let config = config_to_toml(config_path);
 
-    let _ = ServiceBuilder::build_service(&config).unwrap();
+    let expected_error = anyhow!(Error::new(
+        ErrorKind::InvalidData,
+        "conflicting providers found"
+    ));
+
+    let err = ServiceBuilder::build_service(&config).unwrap_err();
+    assert_eq!(format!("{:#?}", err), format!("{:#?}", expected_error));
 }
 
 /// Check that the service throws an error when two providers of the same type explicitly
@@ -73,7 +80,7 @@ fn providers_same_type_same_name() {
 
     let expected_error = anyhow!(Error::new(
         ErrorKind::InvalidData,
-        "duplicate provider names found"
+        "conflicting providers found"
     ));
 
     let err = ServiceBuilder::build_service(&config).unwrap_err();
@@ -89,7 +96,7 @@ fn providers_different_type_same_name() {
 
     let expected_error = anyhow!(Error::new(
         ErrorKind::InvalidData,
-        "duplicate provider names found"
+        "conflicting providers found"
     ));
 
     let err = ServiceBuilder::build_service(&config).unwrap_err();
@@ -105,3 +112,36 @@ fn providers_different_type() {
 
     let _ = ServiceBuilder::build_service(&config).unwrap();
 }
+
+/// Check that the service throws an error when a PIV provider is declared alongside a PKCS 11
+/// provider, whose provider ID it serves.
+#[test]
+fn providers_piv_and_pkcs11() {
+    let config_path: String = "providers_piv_and_pkcs11.toml".to_string();
+    let config = config_to_toml(config_path);
+
+    let expected_error = anyhow!(Error::new(
+        ErrorKind::InvalidData,
+        "conflicting providers found"
+    ));
+
+    let err = ServiceBuilder::build_service(&config).unwrap_err();
+    assert_eq!(format!("{:#?}", err), format!("{:#?}", expected_error));
+}
+
+/// Check that the service throws an error when a test provider is declared alongside an Mbed
+/// Crypto provider, whose provider ID it serves.
+#[cfg(feature = "test-provider")]
+#[test]
+fn providers_test_and_mbed_crypto() {
+    let config_path: String = "providers_test_and_mbed_crypto.toml".to_string();
+    let config = config_to_toml(config_path);
+
+    let expected_error = anyhow!(Error::new(
+        ErrorKind::InvalidData,
+        "conflicting providers found"
+    ));
+
+    let err = ServiceBuilder::build_service(&config).unwrap_err();
+    assert_eq!(format!("{:#?}", err), format!("{:#?}", expected_error));
+}